@@ -0,0 +1,1075 @@
+// Generated by `cargo run --example ts_bindings`. Do not edit.
+
+export type Duration = { secs: number, nanos: number };
+
+export type CancelReason = "User" | "Superseded" | "Timeout";
+
+export type Progress = { done: number, total: number, percent: number, };
+
+export type ProgressSetting = { 
+/**
+ * Minimum time between two updates
+ */
+interval_ms: number, 
+/**
+ * Minimum increase in percent between two updates
+ */
+step_percent: number, };
+
+export type OverflowPolicy = "DropOldest" | "Coalesce";
+
+export type BackpressureSetting = { 
+/**
+ * Events queued before progress events are dropped or coalesced
+ */
+capacity: number, policy: OverflowPolicy, };
+
+export type Grade = "Good" | "Ok" | "Poor";
+
+export type Thresholds = { good: number, ok: number, };
+
+export type GradeThresholds = { latency_ms: Thresholds, jitter_ms: Thresholds, loss_percent: Thresholds, throughput_mbps: Thresholds, };
+
+export type PingProtocol = "Icmp" | { "UdpEcho": { port: number, } };
+
+export type UdpPayload = "Auto" | "Echo" | { "Template": string } | { "Hex": string };
+
+export type PingSetting = { dst_ip: string, 
+/**
+ * Interface index for link-local IPv6 destinations, 0 otherwise
+ */
+scope_id: number, protocol: PingProtocol, count: number, timeout_ms: number, interval_ms: number, 
+/**
+ * ICMP identifier. Randomized when `None`.
+ */
+icmp_id: number | null, 
+/**
+ * First ICMP sequence number. Starts at 0 when `None`.
+ */
+icmp_seq: number | null, 
+/**
+ * Embed the send time in the ICMP payload and take the RTT from it
+ */
+timestamp_payload: boolean, 
+/**
+ * Include every sample in the done payload
+ */
+include_samples: boolean, 
+/**
+ * Most recent samples kept when `include_samples` is set
+ */
+max_samples: number, 
+/**
+ * Source port of UDP probes. Chosen by the OS when `None`.
+ */
+source_port: number | null, 
+/**
+ * Payload of UDP probes
+ */
+udp_payload: UdpPayload, 
+/**
+ * Queue samples for a slow consumer instead of blocking the probes.
+ * Samples are passed on directly when `None`.
+ */
+backpressure: BackpressureSetting | null, 
+/**
+ * Thresholds the done payload's `grade` is computed with
+ */
+grading: GradeThresholds, 
+/**
+ * Stop probing while the interface is down instead of failing every
+ * probe. Needs a link state followed by the interface monitor.
+ */
+pause_on_link_down: boolean, 
+/**
+ * Path of a pcap file every ICMP probe and reply is written to, for
+ * inspection in Wireshark. Headers the socket hides, e.g. of sent
+ * packets, are synthesized, inside Ethernet frames when the egress
+ * interface has a MAC address. UDP probes are not captured. Not
+ * captured when `None`.
+ */
+capture_to: string | null, };
+
+export type UnreachableReason = "Network" | "Host" | "Protocol" | "Port" | "FragmentationNeeded" | "AdminProhibited" | { "Other": { code: number, } };
+
+export type PingSample = { seq: number, 
+/**
+ * `None` if the probe timed out
+ */
+rtt: Duration | null, responder: string | null, 
+/**
+ * TTL or hop limit of the reply
+ */
+ttl: number | null, 
+/**
+ * Set when `ttl` differs from the previous reply, hinting at a path change
+ */
+ttl_changed: boolean, 
+/**
+ * Set when an ICMP error came back instead of a reply
+ */
+unreachable: UnreachableReason | null, 
+/**
+ * Further replies to this probe (`DUP!`) seen so far, 0 when the
+ * prober does not count them
+ */
+duplicates: number, };
+
+export type PingStat = { sent: number, received: number, min_ms: number | null, avg_ms: number | null, max_ms: number | null, 
+/**
+ * Mean absolute difference between consecutive RTTs
+ */
+jitter_ms: number | null, loss_percent: number, };
+
+export type PingDonePayload = { dst_ip: string, stat: PingStat, 
+/**
+ * Per-probe samples in send order, when requested
+ */
+samples: Array<PingSample>, 
+/**
+ * Set when older samples were dropped to stay within `max_samples`
+ */
+samples_truncated: boolean, cancelled: boolean, 
+/**
+ * Why the session was cancelled, when it was
+ */
+cancel_reason: CancelReason | null, 
+/**
+ * Samples dropped or coalesced because the consumer fell behind
+ */
+dropped_progress_events: number, 
+/**
+ * Duplicate replies received during the session (`DUP!`), when the
+ * prober counts them. Duplicates hint at loops or misconfiguration.
+ */
+duplicate_replies: number | null, 
+/**
+ * Replies that arrived after the reply to a later probe, when the
+ * prober tracks them. Common on load-balanced or buffering paths.
+ */
+reordered_replies: number | null, 
+/**
+ * Latency, jitter and loss graded with `setting.grading`
+ */
+grade: Grade | null, };
+
+export type PingInterfacePayload = { dst_ip: string, 
+/**
+ * Sequence number of the probe that failed or of the last one sent
+ * before the link came back
+ */
+seq: number, 
+/**
+ * Send error that revealed the link was down
+ */
+error: string | null, 
+/**
+ * Probing stopped until the interface recovers
+ */
+paused: boolean, };
+
+export type TcpConnectSetting = { dst: string, 
+/**
+ * Connects to make, clamped to [`MAX_TCP_CONNECTS`]
+ */
+count: number, timeout_ms: number, 
+/**
+ * Delay between the start of two connects
+ */
+interval_ms: number, 
+/**
+ * Local port to connect from. Chosen by the OS when `None`.
+ */
+source_port: number | null, 
+/**
+ * Close each connection with a reset instead of an orderly shutdown.
+ * Avoids piling up TIME_WAIT ports over long runs, but the service
+ * logs every connect as aborted.
+ */
+reset_on_close: boolean, };
+
+export type TcpConnectReport = { dst: string, 
+/**
+ * Connect time min/avg/max/jitter over the successful connects
+ */
+stat: PingStat, 
+/**
+ * Connects answered with a reset
+ */
+refused: number, 
+/**
+ * Connects that got no answer in time
+ */
+timeouts: number, 
+/**
+ * Local error of the last connect that could not be sent, e.g. a
+ * source port in use
+ */
+error: string | null, 
+/**
+ * Proxy the connects went through. Connect times then measure the
+ * proxy handshake plus the proxy-to-target path, not the direct path.
+ */
+via_proxy: string | null, 
+/**
+ * Set when the proxy itself failed, e.g. rejected the credentials
+ */
+proxy_error: string | null, cancelled: boolean, };
+
+export type QuicPingSetting = { dst: string, timeout_ms: number, 
+/**
+ * Probe over TCP when QUIC fails
+ */
+tcp_fallback: boolean, };
+
+export type PayloadSweepSetting = { dst_ip: string, 
+/**
+ * Smallest payload in bytes
+ */
+start: number, 
+/**
+ * Largest payload in bytes, inclusive
+ */
+end: number, step: number, 
+/**
+ * Probes per size. A size succeeds if any probe is answered.
+ */
+count: number, timeout_ms: number, };
+
+export type SizeResult = { size: number, success: boolean, 
+/**
+ * RTT of the first answered probe
+ */
+rtt: Duration | null, 
+/**
+ * Last error, e.g. message too long when the local MTU is exceeded
+ */
+error: string | null, };
+
+export type PayloadSweepReport = { dst_ip: string, results: Array<SizeResult>, 
+/**
+ * Largest size answered
+ */
+largest_ok: number | null, 
+/**
+ * Smallest failing size above `largest_ok`, where packets stop getting through
+ */
+cliff: number | null, cancelled: boolean, };
+
+export type BulkPingSetting = { 
+/**
+ * Identifies the run in events. The whole run shares one token.
+ */
+run_id: string, 
+/**
+ * Pings per target
+ */
+count: number, timeout_ms: number, interval_ms: number, 
+/**
+ * Targets pinged at the same time
+ */
+concurrency: number, };
+
+export type BulkPingRow = { dst_ip: string, stat: PingStat, 
+/**
+ * At least one ping answered
+ */
+reachable: boolean, };
+
+export type BulkPingTargetDone = { run_id: string, row: BulkPingRow, };
+
+export type BulkPingResult = { run_id: string, 
+/**
+ * Finished targets in input order
+ */
+rows: Array<BulkPingRow>, cancelled: boolean, };
+
+export type AlertSetting = { 
+/**
+ * Alert when an RTT exceeds this multiple of the rolling median
+ */
+spike_factor: number, 
+/**
+ * Number of recent RTTs the median is taken over
+ */
+window: number, 
+/**
+ * RTTs needed before spikes are checked
+ */
+min_samples: number, 
+/**
+ * Alert after this many consecutive losses
+ */
+loss_threshold: number, 
+/**
+ * Minimum time between two alerts of the same kind
+ */
+cooldown_ms: number, };
+
+export type PingAlertKind = { "LatencySpike": { rtt_ms: number, median_ms: number, } } | { "Loss": { consecutive: number, } };
+
+export type PingAlertPayload = { dst_ip: string, 
+/**
+ * Sequence number of the probe that raised the alert
+ */
+seq: number, kind: PingAlertKind, };
+
+export type HeatmapSetting = { 
+/**
+ * Probes sent to each target
+ */
+count: number, 
+/**
+ * Timeout per probe
+ */
+timeout_ms: number, 
+/**
+ * Maximum number of targets probed at once
+ */
+concurrency: number, };
+
+export type HeatmapEntry = { target: string, sent: number, received: number, median_rtt_ms: number | null, };
+
+export type ConnectivityStage = "Gateway" | "Dns" | "Http";
+
+export type WatchdogSetting = { 
+/**
+ * Time between checks
+ */
+interval_ms: number, 
+/**
+ * Failures shorter than this are blips, neither reported nor logged
+ */
+min_outage_ms: number, 
+/**
+ * Oldest outages are dropped beyond this many
+ */
+max_log: number, };
+
+export type GeoInfo = { 
+/**
+ * ISO 3166-1 alpha-2 code
+ */
+country_code: string | null, country: string | null, city: string | null, asn: number | null, as_name: string | null, };
+
+export type MulticastSetting = { group: string, port: number, 
+/**
+ * Local address of the interface to join an IPv4 group on. The routing
+ * table picks the interface when unset.
+ */
+interface_addr: string | null, 
+/**
+ * Index of the interface to join an IPv6 group on, 0 for the default
+ */
+interface_index: number, window_ms: number, };
+
+export type MulticastStatus = "JoinFailed" | "Silent" | "Receiving";
+
+export type MulticastResult = { group: string, port: number, status: MulticastStatus, 
+/**
+ * OS error of a failed bind or join
+ */
+join_error: string | null, packets: number, bytes: number, 
+/**
+ * Distinct sources of the received datagrams
+ */
+senders: Array<string>, 
+/**
+ * Time from the join to the first datagram
+ */
+first_packet_ms: number | null, 
+/**
+ * Set when the window was cut short
+ */
+cancelled: boolean, };
+
+export type Outage = { 
+/**
+ * Time of the first failed check
+ */
+started_ms: number, 
+/**
+ * Time of the first successful check after it, `None` while ongoing
+ */
+ended_ms: number | null, 
+/**
+ * Stage failing when the outage started
+ */
+stage: ConnectivityStage, };
+
+export type PresenceSetting = { 
+/**
+ * A device must be seen for this long before it joins
+ */
+join_after: Duration, 
+/**
+ * A device must go unconfirmed for this long before it leaves
+ */
+leave_after: Duration, };
+
+export type InfraSetting = { ping_count: number, ping_interval_ms: number, max_hop: number, timeout_ms: number, };
+
+export type SnapshotSetting = { 
+/**
+ * Drop MAC addresses and the public IP, and mask IPv6 addresses that
+ * identify the host, see [`redact_ip`]
+ */
+redact: boolean, 
+/**
+ * Plain-text echo service for the public IP. Skipped when `None`.
+ */
+public_ip_url: string | null, timeout_ms: number, };
+
+export type TcpInfoSetting = { dst: string, 
+/**
+ * Filler bytes sent before reading the statistics, so the congestion
+ * window and retransmissions reflect some actual transfer
+ */
+transfer_bytes: number, timeout_ms: number, };
+
+export type TcpStats = { 
+/**
+ * Smoothed RTT
+ */
+rtt_ms: number, 
+/**
+ * RTT variance
+ */
+rtt_var_ms: number, 
+/**
+ * Retransmission timeout
+ */
+rto_ms: number, 
+/**
+ * Segments retransmitted over the connection's lifetime
+ */
+total_retrans: number, 
+/**
+ * Segments currently considered lost
+ */
+lost: number, 
+/**
+ * Congestion window, in segments
+ */
+snd_cwnd: number, 
+/**
+ * Slow start threshold. `None` while still in initial slow start.
+ */
+snd_ssthresh: number | null, snd_mss: number, 
+/**
+ * Path MTU
+ */
+pmtu: number, 
+/**
+ * Segments sent. `None` on kernels that do not report it.
+ */
+segs_out: number | null, };
+
+export type TcpInfoReport = { dst: string, connect_ms: number | null, bytes_sent: number, 
+/**
+ * `None` when the connect failed or the platform lacks `TCP_INFO`
+ */
+stats: TcpStats | null, error: string | null, };
+
+export type DnsServerHealth = { server: string, 
+/**
+ * Interfaces the server is configured on
+ */
+interfaces: Array<string>, reachable: boolean, rtt_ms: number | null, 
+/**
+ * A records returned, sorted
+ */
+addrs: Array<string>, 
+/**
+ * Set when the answer differs from the one most servers gave
+ */
+disagrees: boolean, error: string | null, };
+
+export type DnsHealthReport = { name: string, servers: Array<DnsServerHealth>, };
+
+export type ResolverCacheReport = { host: string, 
+/**
+ * Lookup of a never-seen name under `host`, which no cache can answer
+ */
+cold_ms: number | null, 
+/**
+ * First lookup of `host`, possibly already cached
+ */
+first_ms: number | null, 
+/**
+ * Median of the repeated lookups of `host`
+ */
+warm_ms: number | null, 
+/**
+ * `None` when the timings were not available
+ */
+caching: boolean | null, error: string | null, };
+
+export type HostState = "Alive" | "Unreachable" | "Unavailable";
+
+export type Detection = "Icmp" | "Tcp" | "Arp";
+
+export type Host = { ip: string, state: HostState, 
+/**
+ * RTT of the first reply
+ */
+rtt: Duration | null, 
+/**
+ * Probes answered
+ */
+replies: number, 
+/**
+ * Ports of `quick_ports` that accepted a connection
+ */
+open_ports: Array<number>, 
+/**
+ * Hardware address, when found by ARP
+ */
+mac: string | null, 
+/**
+ * Method that detected the host, `None` unless alive
+ */
+detected_by: Detection | null, 
+/**
+ * Name from reverse DNS, when `reverse_dns` is set and the host has one
+ */
+hostname: string | null, };
+
+export type RetrySetting = { timeout_ms: number, concurrency: number, 
+/**
+ * Send only as many probes per host as `require_replies` needs, back to
+ * back and through the same discovery method, instead of repeating the
+ * full per-host scan. Quick ports are not checked.
+ */
+quick: boolean, };
+
+export type ScanIntensity = "Polite" | "Normal" | "Aggressive" | "Insane";
+
+export type DiscoveryMethod = "Icmp" | "Tcp" | "Both";
+
+export type DiscoveryOrder = "All" | "IcmpFirst" | "TcpFirst";
+
+export type HostScanSetting = { targets: Array<string>, 
+/**
+ * Interface index used for link-local IPv6 targets
+ */
+scope_id: number, 
+/**
+ * Probes sent to each host
+ */
+count: number, 
+/**
+ * Replies needed, out of `count`, to consider a host alive
+ */
+require_replies: number, timeout_ms: number, 
+/**
+ * Maximum number of hosts probed at once
+ */
+concurrency: number, 
+/**
+ * Probes sent per second across all workers. Unpaced when `None`.
+ */
+rate_limit_pps: number | null, 
+/**
+ * Time between the sends of successive probes to one host, like the
+ * interval of ping. Probes follow each other right away when 0.
+ */
+probe_interval_ms: number, 
+/**
+ * Cap on the total time spent on one host across its `count` probes,
+ * so unresponsive hosts give their slot back early. Unlimited when `None`.
+ */
+host_budget_ms: number | null, 
+/**
+ * ICMP identifier. Randomized when `None`.
+ */
+icmp_id: number | null, 
+/**
+ * First ICMP sequence number. Starts at 0 when `None`.
+ */
+icmp_seq: number | null, 
+/**
+ * `SO_RCVBUF` of the ICMP socket. Raise it for large, fast scans when
+ * `HostScanResult::dropped_packets` reports kernel drops. The OS
+ * default is kept when `None`.
+ */
+recv_buffer_size: number | null, 
+/**
+ * Throttling of progress updates
+ */
+progress: ProgressSetting, 
+/**
+ * TCP ports connected to on alive hosts as a quick service hint.
+ * Skipped when empty; see `DEFAULT_QUICK_PORTS`.
+ */
+quick_ports: Array<number>, 
+/**
+ * Second pass over unreachable hosts. Disabled when `None`.
+ */
+retry: RetrySetting | null, 
+/**
+ * Path of an NDJSON file each result is appended to as it is found
+ */
+stream_to: string | null, discovery: DiscoveryMethod, discovery_order: DiscoveryOrder, 
+/**
+ * Ports connected to by TCP discovery
+ */
+discovery_ports: Array<number>, 
+/**
+ * Look up the hostnames of alive hosts after the scan, see
+ * `resolve_hostnames`
+ */
+reverse_dns: boolean, };
+
+export type HostScanResult = { hosts: Array<Host>, 
+/**
+ * Set when the scan stopped before probing every target
+ */
+cancelled: boolean, 
+/**
+ * Why the scan was cancelled, when it was
+ */
+cancel_reason: CancelReason | null, 
+/**
+ * Replies the kernel dropped on receive, when the receiver can tell.
+ * Any non-zero value means `unreachable` may include live hosts.
+ */
+dropped_packets: number | null, 
+/**
+ * Hosts that only answered the retry pass
+ */
+recovered_on_retry: number, 
+/**
+ * Set when `stream_to` could not be written
+ */
+stream_error: string | null, 
+/**
+ * Address families that could not be probed, such as "IPv6
+ * unavailable on this host: ...". Their targets are `Unavailable`.
+ */
+family_errors: Array<string>, };
+
+export type GuardThresholds = { 
+/**
+ * Concurrency above this is flagged
+ */
+max_concurrency: number, 
+/**
+ * Timeouts below this count as no timeout at all
+ */
+min_timeout_ms: number, 
+/**
+ * Unpaced scans of more targets than this are flagged when each target
+ * gets more than `max_unpaced_count` probes
+ */
+large_target_count: number, max_unpaced_count: number, 
+/**
+ * Ping intervals below this are flagged for long sessions
+ */
+min_ping_interval_ms: number, 
+/**
+ * Ping sessions with more probes than this need `min_ping_interval_ms`
+ */
+max_fast_ping_count: number, 
+/**
+ * Expanding more targets than this needs confirmation
+ */
+confirm_target_count: number, 
+/**
+ * Expanding more targets than this is refused. Every target is held
+ * in memory, twice while duplicates are removed, so this stays within
+ * a small multiple of `confirm_target_count`.
+ */
+max_target_count: number, 
+/**
+ * IPv6 networks with a shorter prefix are never enumerated
+ */
+min_ipv6_prefix_len: number, };
+
+export type GuardIssue = { "ZeroTimeoutFlood": { concurrency: number, } } | { "ExcessiveConcurrency": { concurrency: number, } } | { "UnpacedLargeSweep": { targets: number, count: number, } } | { "PingFlood": { interval_ms: number, count: number, } };
+
+export type SettingWarning = { issue: GuardIssue, blocking: boolean, message: string, };
+
+export type NeighborScanOverrides = { concurrency: number | null, timeout_ms: number | null, count: number | null, };
+
+export type RouterScanSetting = { timeout_ms: number, concurrency: number, 
+/**
+ * Off-link address used to check forwarding. Skipped when `None`.
+ */
+forward_probe_dst: string | null, };
+
+export type FreeRange = { first: string, last: string, size: number, };
+
+export type SubnetUtilization = { cidr: string, 
+/**
+ * Alive hosts inside the subnet, in address order
+ */
+in_use: Array<string>, 
+/**
+ * Usable host addresses. `None` for IPv6, where only presence is
+ * meaningful.
+ */
+usable: number | null, free: number | null, 
+/**
+ * Share of usable addresses in use, in percent
+ */
+density_percent: number | null, 
+/**
+ * Largest unused runs, at most [`MAX_FREE_RANGES`]. Empty for IPv6.
+ */
+free_ranges: Array<FreeRange>, };
+
+export type PortState = "Open" | "Closed" | "Filtered" | "Error";
+
+export type PortProbe = { port: number, state: PortState, 
+/**
+ * Connect time for open ports
+ */
+connect_time: Duration | null, 
+/**
+ * Local error of a probe in [`PortState::Error`]
+ */
+error: string | null, };
+
+export type PortMatrix = { ip: string, 
+/**
+ * Probed ports in request order, duplicates dropped
+ */
+ports: Array<PortProbe>, 
+/**
+ * Cancelled before every port was probed
+ */
+cancelled: boolean, };
+
+export type FlowPolicy = "Fixed" | { "RetryOnSilence": { flows: number, } };
+
+export type TraceSetting = { 
+/**
+ * Destination IP address
+ */
+dst_ip: string, 
+/**
+ * Maximum number of hops
+ */
+max_hop: number, 
+/**
+ * Number of probes sent per hop
+ */
+tries_per_hop: number, 
+/**
+ * Timeout for the first hop. Grows with TTL.
+ */
+timeout_base_ms: number, 
+/**
+ * Upper bound of the per-hop timeout
+ */
+timeout_max_ms: number, 
+/**
+ * Source port of UDP probes. Picked by the OS when `None`.
+ */
+source_port: number | null, 
+/**
+ * Pause between the tries of one hop. Routers rate-limit time-exceeded
+ * messages, so back-to-back tries can all go unanswered.
+ */
+try_interval_ms: number, 
+/**
+ * When every try at a hop went unanswered, wait this long and send one
+ * more before declaring the hop silent. Disabled when `None`.
+ */
+silent_retry_ms: number | null, 
+/**
+ * Probes sent at the hop where the destination answered, topping up
+ * `tries_per_hop`. Uses `tries_per_hop` when `None`.
+ */
+final_hop_tries: number | null, 
+/**
+ * Extra probes sent to the destination once reached, reported
+ * separately as a destination RTT distribution
+ */
+confirm_probes: number, flow_policy: FlowPolicy, };
+
+export type AnomalyKind = "Loop" | "Stall";
+
+export type Anomaly = { kind: AnomalyKind, responder: string, 
+/**
+ * TTLs at which `responder` answered so far, ascending
+ */
+hops: Array<number>, };
+
+export type Hop = { ttl: number, 
+/**
+ * First router that answered at this TTL. `None` if every probe timed out.
+ */
+responder: string | null, 
+/**
+ * RTT of each probe, `None` for timeouts
+ */
+rtts: Array<Duration | null>, 
+/**
+ * Whether the destination answered
+ */
+reached: boolean, 
+/**
+ * Set when the responder was already seen at an earlier hop
+ */
+anomaly: AnomalyKind | null, 
+/**
+ * TTL of the first reply as received
+ */
+reply_ttl: number | null, 
+/**
+ * Flow that answered after the usual flow stayed silent, a sign of
+ * per-flow filtering
+ */
+answered_on_flow: number | null, };
+
+export type TraceResult = { dst_ip: string, hops: Array<Hop>, reached: boolean, anomalies: Array<Anomaly>, 
+/**
+ * RTTs of the `confirm_probes` sent after reaching the destination
+ */
+destination_rtts: Array<Duration | null>, 
+/**
+ * Summary of `destination_rtts`, `None` when none were sent
+ */
+destination: PingStat | null, cancelled: boolean, };
+
+export type AsHop = { 
+/**
+ * `None` for hops without a known origin, e.g. private addresses
+ */
+asn: number | null, 
+/**
+ * TTLs of the first and last hop answering from this AS
+ */
+first_ttl: number, last_ttl: number, 
+/**
+ * Routers where the path enters and leaves the AS
+ */
+entry: string, exit: string, 
+/**
+ * Lowest RTT at the entry and exit router
+ */
+entry_rtt_ms: number | null, exit_rtt_ms: number | null, };
+
+export type AsPathPayload = { dst_ip: string, hops: Array<AsHop>, reached: boolean, };
+
+export type Direction = "Download" | "Upload";
+
+export type SpeedtestOutcome = "Completed" | "Canceled" | "Failed";
+
+export type SpeedtestPhase = "Connecting" | "Warmup" | "Running" | "Finalizing";
+
+export type SpeedtestSetting = { 
+/**
+ * Maximum test duration
+ */
+duration_ms: number, 
+/**
+ * Stop after this many bytes. Unlimited when `None`.
+ */
+max_bytes: number | null, 
+/**
+ * Length of the warmup phase from the first transferred byte. The
+ * phase is reported only; its bytes still count.
+ */
+warmup_ms: number, 
+/**
+ * Throttling of progress updates. Progress is the share of the
+ * duration or of `max_bytes` used, whichever is further along.
+ */
+progress: ProgressSetting, 
+/**
+ * Thresholds the done payload's `grade` is computed with
+ */
+grading: GradeThresholds, };
+
+export type SeriesSetting = { 
+/**
+ * Number of runs
+ */
+runs: number, 
+/**
+ * Pause between runs so the link settles
+ */
+pause_ms: number, };
+
+export type SpeedtestUpdatePayload = { direction: Direction, phase: SpeedtestPhase, bytes: number, elapsed_ms: number, mbps: number, 
+/**
+ * How far the test is towards its duration or byte limit
+ */
+percent: number, };
+
+export type SpeedtestDonePayload = { direction: Direction, result: SpeedtestOutcome, bytes: number, elapsed_ms: number, 
+/**
+ * Throughput over the transfer, excluding the time to first byte
+ */
+mbps: number, 
+/**
+ * Time from the request to the first received chunk, covering
+ * connection setup and server think time. Downloads only.
+ */
+ttfb_ms: number | null, error: string | null, 
+/**
+ * Throughput graded with `setting.grading`. `None` if nothing was
+ * transferred.
+ */
+grade: Grade | null, };
+
+export type DuplexBaseline = { download_mbps: number, upload_mbps: number, };
+
+export type FullDuplexDonePayload = { download: SpeedtestDonePayload, upload: SpeedtestDonePayload, 
+/**
+ * Sum of both directions
+ */
+combined_mbps: number, 
+/**
+ * Solo throughput the directions were compared against
+ */
+baseline: DuplexBaseline | null, 
+/**
+ * Direction that kept less than `STARVED_SHARE` of its solo
+ * throughput. `None` without a baseline.
+ */
+starved: Direction | null, };
+
+export type SeriesResult = { 
+/**
+ * Every run started, in order
+ */
+runs: Array<SpeedtestDonePayload>, median_mbps: number | null, 
+/**
+ * Variance of the throughput, in Mbps squared
+ */
+variance: number | null, 
+/**
+ * Index into `runs` of the fastest completed run
+ */
+best_run: number | null, cancelled: boolean, };
+
+export type SpeedtestServer = { name: string, 
+/**
+ * Endpoint used for latency probes and downloads
+ */
+url: string, };
+
+export type ServerMeasurement = { server: SpeedtestServer, ping_ms: number | null, 
+/**
+ * Throughput of a completed test, when requested
+ */
+mbps: number | null, 
+/**
+ * 1 for the lowest latency. `None` for servers that did not answer.
+ */
+rank: number | null, error: string | null, };
+
+export type ServerComparison = { servers: Array<ServerMeasurement>, 
+/**
+ * Set when latency probes or throughput tests were cut short
+ */
+cancelled: boolean, };
+
+export type HttpPingSetting = { url: string, timeout_ms: number, 
+/**
+ * Additional attempts after a failed one
+ */
+retries: number, 
+/**
+ * Delay before the first retry, doubled for each further retry
+ */
+retry_backoff_ms: number, 
+/**
+ * Also retry on 5xx status codes. Connection errors are always retried.
+ */
+retry_on_server_error: boolean, 
+/**
+ * Substring the decoded body must contain for the ping to succeed
+ */
+body_match: string | null, 
+/**
+ * Also request a never-seen URL and look for signs of a transparent
+ * proxy or cache in the answers
+ */
+detect_middlebox: boolean, };
+
+export type HttpPingResult = { url: string, 
+/**
+ * Whether the final attempt got a non-error status
+ */
+success: boolean, 
+/**
+ * Number of requests made
+ */
+attempts: number, status: number | null, rtt: Duration | null, 
+/**
+ * Size of the body as transferred
+ */
+body_bytes: number | null, 
+/**
+ * Content encoding of the response, e.g. `gzip`
+ */
+encoding: string | null, compressed: boolean, 
+/**
+ * Whether the body contained `body_match`. `None` if not requested.
+ */
+matched: boolean | null, 
+/**
+ * Whether a proxy or cache was seen in the path. `None` if not
+ * requested or no response arrived.
+ */
+middlebox_detected: boolean | null, 
+/**
+ * Header lines and changes that gave the middlebox away
+ */
+middlebox_evidence: Array<string>, error: string | null, };
+
+export type LatencySetting = { samples: number, per_request_timeout_ms: number, 
+/**
+ * Pause between the end of one request and the start of the next
+ */
+inter_sample_ms: number, 
+/**
+ * Report when each sample was sent in `timestamps_ms`
+ */
+include_timestamps: boolean, };
+
+export type LatencyDonePayload = { url: string, 
+/**
+ * Request times of successful samples in milliseconds
+ */
+samples: Array<number>, 
+/**
+ * Whether each sample went over a reused connection
+ */
+reused: Array<boolean>, 
+/**
+ * Unix time in milliseconds each sample was sent at, parallel to
+ * `samples`. Empty unless `include_timestamps` is set.
+ */
+timestamps_ms: Array<number>, failed: number, avg_ms: number | null, 
+/**
+ * Mean absolute difference between consecutive samples
+ */
+jitter_ms: number | null, 
+/**
+ * Average of samples that opened a new connection
+ */
+cold_ms: number | null, 
+/**
+ * Average of samples over a kept-alive connection
+ */
+warm_ms: number | null, 
+/**
+ * Whether every sample was forced onto a new connection
+ */
+fresh_connection: boolean, error: string | null, };
+
+export type DownloadEvent = { "Started": { content_length: number | null, } } | { "Progress": { downloaded: number, content_length: number | null, 
+/**
+ * Average speed over the rolling window
+ */
+bytes_per_sec: number, 
+/**
+ * Estimated time remaining. `None` if the length or speed is unknown.
+ */
+eta_secs: number | null, } } | "Finished";
@@ -0,0 +1,9 @@
+//! Regenerate the TypeScript definitions used by the UI
+use std::path::Path;
+
+fn main() -> std::io::Result<()> {
+    let path = Path::new(env!("CARGO_MANIFEST_DIR")).join(netdia::schema::BINDINGS_PATH);
+    std::fs::write(&path, netdia::schema::typescript_definitions())?;
+    println!("Wrote {}", path.display());
+    Ok(())
+}
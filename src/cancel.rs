@@ -0,0 +1,138 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::atomic::{AtomicU8, Ordering};
+use std::sync::{Arc, Mutex};
+use ts_rs::TS;
+
+/// Why an operation was cancelled
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, TS)]
+pub enum CancelReason {
+    /// Cancelled on request
+    User,
+    /// Replaced by a new operation of the same kind
+    Superseded,
+    /// Ran past its deadline
+    Timeout,
+}
+
+impl CancelReason {
+    fn to_u8(self) -> u8 {
+        match self {
+            CancelReason::User => 1,
+            CancelReason::Superseded => 2,
+            CancelReason::Timeout => 3,
+        }
+    }
+    fn from_u8(v: u8) -> Option<CancelReason> {
+        match v {
+            1 => Some(CancelReason::User),
+            2 => Some(CancelReason::Superseded),
+            3 => Some(CancelReason::Timeout),
+            _ => None,
+        }
+    }
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            CancelReason::User => "user",
+            CancelReason::Superseded => "superseded",
+            CancelReason::Timeout => "timeout",
+        }
+    }
+}
+
+impl fmt::Display for CancelReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Cooperative cancellation flag shared between an operation and its caller
+#[derive(Clone, Debug, Default)]
+pub struct CancellationToken {
+    /// 0 while running, otherwise the encoded `CancelReason`
+    state: Arc<AtomicU8>,
+}
+
+impl CancellationToken {
+    pub fn new() -> CancellationToken {
+        CancellationToken::default()
+    }
+    /// Cancel on request of the user
+    pub fn cancel(&self) {
+        self.cancel_with(CancelReason::User);
+    }
+    /// Cancel with `reason`. The first reason recorded wins.
+    pub fn cancel_with(&self, reason: CancelReason) {
+        let _ = self
+            .state
+            .compare_exchange(0, reason.to_u8(), Ordering::SeqCst, Ordering::SeqCst);
+    }
+    pub fn is_cancelled(&self) -> bool {
+        self.state.load(Ordering::SeqCst) != 0
+    }
+    pub fn reason(&self) -> Option<CancelReason> {
+        CancelReason::from_u8(self.state.load(Ordering::SeqCst))
+    }
+}
+
+/// Tokens of the running operation of each kind. Starting an operation
+/// supersedes the previous one of the same kind.
+#[derive(Debug, Default)]
+pub struct OpRegistry {
+    ops: Mutex<HashMap<String, CancellationToken>>,
+}
+
+impl OpRegistry {
+    pub fn new() -> OpRegistry {
+        OpRegistry::default()
+    }
+    /// Token for a new `kind` operation, cancelling the previous one
+    pub fn start(&self, kind: &str) -> CancellationToken {
+        let token = CancellationToken::new();
+        let prev = self
+            .ops
+            .lock()
+            .unwrap()
+            .insert(kind.to_string(), token.clone());
+        if let Some(prev) = prev {
+            prev.cancel_with(CancelReason::Superseded);
+        }
+        token
+    }
+    /// Cancel the running `kind` operation. Returns false if none was running.
+    pub fn cancel_op(&self, kind: &str, reason: CancelReason) -> bool {
+        match self.ops.lock().unwrap().remove(kind) {
+            Some(token) => {
+                let running = !token.is_cancelled();
+                token.cancel_with(reason);
+                running
+            }
+            None => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn first_reason_wins() {
+        let token = CancellationToken::new();
+        assert_eq!(token.reason(), None);
+        token.cancel_with(CancelReason::Timeout);
+        token.cancel();
+        assert!(token.is_cancelled());
+        assert_eq!(token.reason(), Some(CancelReason::Timeout));
+    }
+
+    #[test]
+    fn cancel_op_reports_running() {
+        let ops = OpRegistry::new();
+        assert!(!ops.cancel_op("scan", CancelReason::User));
+        let token = ops.start("scan");
+        assert!(ops.cancel_op("scan", CancelReason::User));
+        assert_eq!(token.reason(), Some(CancelReason::User));
+        assert!(!ops.cancel_op("scan", CancelReason::User));
+    }
+}
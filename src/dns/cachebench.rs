@@ -0,0 +1,160 @@
+//! Whether the resolver in use caches answers
+use super::{DnsError, Resolver};
+use crate::cancel::CancellationToken;
+use crate::stats::median;
+use std::net::IpAddr;
+use std::time::Instant;
+use ts_rs::TS;
+
+/// Warm lookups slower than this share of the cold one count as uncached
+pub const CACHE_HIT_RATIO: f64 = 0.5;
+/// Default number of warm lookups
+pub const DEFAULT_WARM_SAMPLES: u32 = 3;
+
+/// Resolves one hostname
+pub trait HostLookup: Sync {
+    fn lookup(&self, host: &str, token: &CancellationToken) -> Result<Vec<IpAddr>, DnsError>;
+}
+
+impl HostLookup for Resolver {
+    fn lookup(&self, host: &str, token: &CancellationToken) -> Result<Vec<IpAddr>, DnsError> {
+        self.resolve_cancellable(host, token)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, TS)]
+pub struct ResolverCacheReport {
+    pub host: String,
+    /// Lookup of a never-seen name under `host`, which no cache can answer
+    pub cold_ms: Option<f64>,
+    /// First lookup of `host`, possibly already cached
+    pub first_ms: Option<f64>,
+    /// Median of the repeated lookups of `host`
+    pub warm_ms: Option<f64>,
+    /// `None` when the timings were not available
+    pub caching: Option<bool>,
+    pub error: Option<String>,
+}
+
+fn timed<L: HostLookup>(
+    lookup: &L,
+    host: &str,
+    token: &CancellationToken,
+) -> (f64, Result<Vec<IpAddr>, DnsError>) {
+    let start = Instant::now();
+    let result = lookup.lookup(host, token);
+    (start.elapsed().as_secs_f64() * 1000.0, result)
+}
+
+/// Time a cold lookup against repeated lookups of `host`.
+///
+/// The cold lookup goes to a random label under `host`, so it bypasses
+/// every cache on the way; an NXDOMAIN answer still counts as answered.
+pub fn benchmark_resolver_cache<L: HostLookup>(
+    lookup: &L,
+    host: &str,
+    warm_samples: u32,
+    token: &CancellationToken,
+) -> ResolverCacheReport {
+    let mut report = ResolverCacheReport {
+        host: host.to_string(),
+        cold_ms: None,
+        first_ms: None,
+        warm_ms: None,
+        caching: None,
+        error: None,
+    };
+    let cold_name = format!(
+        "netdia-{:04x}{:04x}.{}",
+        crate::ping::icmp::random_id(),
+        crate::ping::icmp::random_id(),
+        host.trim_end_matches('.')
+    );
+    match timed(lookup, &cold_name, token) {
+        (ms, Ok(_) | Err(DnsError::NxDomain | DnsError::NoRecords)) => report.cold_ms = Some(ms),
+        (_, Err(e)) => {
+            report.error = Some(e.to_string());
+            return report;
+        }
+    }
+    match timed(lookup, host, token) {
+        (ms, Ok(_)) => report.first_ms = Some(ms),
+        (_, Err(e)) => {
+            report.error = Some(e.to_string());
+            return report;
+        }
+    }
+    let mut warm = Vec::new();
+    for _ in 0..warm_samples.max(1) {
+        match timed(lookup, host, token) {
+            (ms, Ok(_)) => warm.push(ms),
+            (_, Err(e)) => {
+                report.error = Some(e.to_string());
+                break;
+            }
+        }
+    }
+    report.warm_ms = median(&warm);
+    report.caching = match (report.cold_ms, report.warm_ms) {
+        (Some(cold), Some(warm)) => Some(warm < cold * CACHE_HIT_RATIO),
+        _ => None,
+    };
+    report
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+    use std::thread;
+    use std::time::Duration;
+
+    /// Takes 40ms per lookup, or next to nothing for names already seen
+    /// when caching
+    struct Stub {
+        caching: bool,
+        seen: Mutex<Vec<String>>,
+    }
+
+    impl Stub {
+        fn new(caching: bool) -> Stub {
+            Stub {
+                caching,
+                seen: Mutex::new(Vec::new()),
+            }
+        }
+    }
+
+    impl HostLookup for Stub {
+        fn lookup(&self, host: &str, _token: &CancellationToken) -> Result<Vec<IpAddr>, DnsError> {
+            let mut seen = self.seen.lock().unwrap();
+            if !(self.caching && seen.iter().any(|h| h == host)) {
+                thread::sleep(Duration::from_millis(40));
+            }
+            seen.push(host.to_string());
+            if host.starts_with("netdia-") {
+                return Err(DnsError::NxDomain);
+            }
+            Ok(vec!["192.0.2.1".parse().unwrap()])
+        }
+    }
+
+    #[test]
+    fn cold_and_warm_timings() {
+        let token = CancellationToken::new();
+        let stub = Stub::new(true);
+        let report = benchmark_resolver_cache(&stub, "example.com", 3, &token);
+        assert_eq!(report.error, None);
+        assert!(report.cold_ms.unwrap() >= 40.0);
+        assert!(report.first_ms.unwrap() >= 40.0);
+        assert!(report.warm_ms.unwrap() < 20.0);
+        assert_eq!(report.caching, Some(true));
+        let seen = stub.seen.lock().unwrap();
+        assert!(seen[0].starts_with("netdia-") && seen[0].ends_with(".example.com"));
+        assert_eq!(seen.len(), 5);
+
+        let report = benchmark_resolver_cache(&Stub::new(false), "example.com", 2, &token);
+        assert!(report.warm_ms.unwrap() >= 40.0);
+        assert_eq!(report.caching, Some(false));
+    }
+}
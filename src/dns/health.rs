@@ -0,0 +1,189 @@
+use super::message::{RData, TYPE_A};
+use super::query;
+use crate::cancel::CancellationToken;
+use crate::net::interface::Interface;
+use crate::pool::map_concurrent;
+use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr};
+use std::time::{Duration, Instant};
+use ts_rs::TS;
+
+/// Name queried when none is given
+pub const DEFAULT_CHECK_NAME: &str = "example.com";
+
+/// Health of one DNS server
+#[derive(Clone, Debug, PartialEq, TS)]
+pub struct DnsServerHealth {
+    pub server: SocketAddr,
+    /// Interfaces the server is configured on
+    pub interfaces: Vec<String>,
+    pub reachable: bool,
+    pub rtt_ms: Option<f64>,
+    /// A records returned, sorted
+    pub addrs: Vec<IpAddr>,
+    /// Set when the answer differs from the one most servers gave
+    pub disagrees: bool,
+    pub error: Option<String>,
+}
+
+#[derive(Clone, Debug, PartialEq, TS)]
+pub struct DnsHealthReport {
+    pub name: String,
+    pub servers: Vec<DnsServerHealth>,
+}
+
+impl DnsHealthReport {
+    /// Whether every server answered and they all agreed
+    pub fn healthy(&self) -> bool {
+        !self.servers.is_empty() && self.servers.iter().all(|s| s.reachable && !s.disagrees)
+    }
+}
+
+/// DNS servers of the up interfaces, each with the interfaces using it
+pub fn configured_servers(interfaces: &[Interface]) -> Vec<(SocketAddr, Vec<String>)> {
+    let mut servers: Vec<(SocketAddr, Vec<String>)> = Vec::new();
+    for iface in interfaces.iter().filter(|i| i.is_up) {
+        for ip in &iface.dns_servers {
+            let addr = SocketAddr::new(*ip, 53);
+            match servers.iter_mut().find(|(s, _)| *s == addr) {
+                Some((_, names)) => names.push(iface.name.clone()),
+                None => servers.push((addr, vec![iface.name.clone()])),
+            }
+        }
+    }
+    servers
+}
+
+/// Query every DNS server of `interfaces` for `name`. The report is empty
+/// when no interface has DNS configured.
+pub fn check_dns_servers(
+    interfaces: &[Interface],
+    name: &str,
+    timeout: Duration,
+    token: &CancellationToken,
+) -> DnsHealthReport {
+    check_servers(&configured_servers(interfaces), name, timeout, token)
+}
+
+/// Query each of `servers` for the A records of `name`
+pub fn check_servers(
+    servers: &[(SocketAddr, Vec<String>)],
+    name: &str,
+    timeout: Duration,
+    token: &CancellationToken,
+) -> DnsHealthReport {
+    let mut results: Vec<DnsServerHealth> =
+        map_concurrent(servers, servers.len(), token, |(server, interfaces)| {
+            let mut health = DnsServerHealth {
+                server: *server,
+                interfaces: interfaces.clone(),
+                reachable: false,
+                rtt_ms: None,
+                addrs: Vec::new(),
+                disagrees: false,
+                error: None,
+            };
+            let start = Instant::now();
+            match query(*server, name, TYPE_A, timeout) {
+                Ok(response) => {
+                    health.reachable = true;
+                    health.rtt_ms = Some(start.elapsed().as_secs_f64() * 1000.0);
+                    health.addrs = response
+                        .answers
+                        .iter()
+                        .filter_map(|r| match r.data {
+                            RData::A(ip) => Some(IpAddr::V4(ip)),
+                            _ => None,
+                        })
+                        .collect();
+                    health.addrs.sort();
+                }
+                Err(e) => health.error = Some(e.to_string()),
+            }
+            health
+        })
+        .into_iter()
+        .flatten()
+        .collect();
+    // CDNs hand out different addresses, so only flag answers when a
+    // majority of servers agree on another one
+    let mut counts: HashMap<&[IpAddr], usize> = HashMap::new();
+    for r in results.iter().filter(|r| r.reachable) {
+        *counts.entry(&r.addrs).or_default() += 1;
+    }
+    let answered: usize = counts.values().sum();
+    let majority = counts
+        .iter()
+        .find(|(_, n)| **n * 2 > answered)
+        .map(|(addrs, _)| addrs.to_vec());
+    if let Some(majority) = majority {
+        for r in results.iter_mut().filter(|r| r.reachable) {
+            r.disagrees = r.addrs != majority;
+        }
+    }
+    DnsHealthReport {
+        name: name.to_string(),
+        servers: results,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dns::tests::serve_a;
+    use std::net::{Ipv4Addr, UdpSocket};
+
+    #[test]
+    fn failing_server_is_reported() {
+        let good = serve_a("example.com", Ipv4Addr::new(192, 0, 2, 1), 1);
+        // Bound but never answers
+        let silent = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        let servers = vec![
+            (good, vec!["eth0".to_string()]),
+            (silent.local_addr().unwrap(), vec!["wlan0".to_string()]),
+        ];
+        let report = check_servers(
+            &servers,
+            "example.com",
+            Duration::from_millis(200),
+            &CancellationToken::new(),
+        );
+        assert!(!report.healthy());
+        let (ok, failed) = (&report.servers[0], &report.servers[1]);
+        assert!(ok.reachable && ok.rtt_ms.is_some());
+        assert_eq!(ok.addrs, vec![IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1))]);
+        assert!(!ok.disagrees);
+        assert!(!failed.reachable);
+        assert_eq!(failed.interfaces, vec!["wlan0".to_string()]);
+        assert!(failed.error.is_some());
+    }
+
+    #[test]
+    fn servers_collected_from_up_interfaces() {
+        let dns: IpAddr = "192.0.2.53".parse().unwrap();
+        let mut eth0 = Interface::new(2, "eth0");
+        eth0.is_up = true;
+        eth0.dns_servers = vec![dns];
+        let mut wlan0 = Interface::new(3, "wlan0");
+        wlan0.is_up = true;
+        wlan0.dns_servers = vec![dns];
+        let mut down = Interface::new(4, "eth1");
+        down.dns_servers = vec!["192.0.2.54".parse().unwrap()];
+        let servers = configured_servers(&[eth0, wlan0, down]);
+        assert_eq!(
+            servers,
+            vec![(
+                SocketAddr::new(dns, 53),
+                vec!["eth0".to_string(), "wlan0".to_string()]
+            )]
+        );
+        let report = check_dns_servers(
+            &[Interface::new(1, "lo")],
+            DEFAULT_CHECK_NAME,
+            Duration::from_millis(100),
+            &CancellationToken::new(),
+        );
+        assert!(report.servers.is_empty());
+        assert!(!report.healthy());
+    }
+}
@@ -0,0 +1,254 @@
+//! DNS wire format, limited to what the resolver needs
+use super::DnsError;
+use std::net::{Ipv4Addr, Ipv6Addr};
+
+pub const TYPE_A: u16 = 1;
+pub const TYPE_CNAME: u16 = 5;
+pub const TYPE_PTR: u16 = 12;
+pub const TYPE_AAAA: u16 = 28;
+pub const CLASS_IN: u16 = 1;
+
+pub const RCODE_NOERROR: u8 = 0;
+pub const RCODE_NXDOMAIN: u8 = 3;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Question {
+    pub name: String,
+    pub qtype: u16,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum RData {
+    A(Ipv4Addr),
+    Aaaa(Ipv6Addr),
+    Cname(String),
+    Ptr(String),
+    Other(Vec<u8>),
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Record {
+    pub name: String,
+    pub rtype: u16,
+    pub ttl: u32,
+    pub data: RData,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Message {
+    pub id: u16,
+    pub is_response: bool,
+    pub truncated: bool,
+    pub rcode: u8,
+    pub questions: Vec<Question>,
+    pub answers: Vec<Record>,
+}
+
+fn encode_name(name: &str, out: &mut Vec<u8>) -> Result<(), DnsError> {
+    let name = name.trim_end_matches('.');
+    if name.len() > 253 {
+        return Err(DnsError::InvalidName(name.to_string()));
+    }
+    for label in name
+        .split('.')
+        .filter(|l| !name.is_empty() || !l.is_empty())
+    {
+        if label.is_empty() || label.len() > 63 {
+            return Err(DnsError::InvalidName(name.to_string()));
+        }
+        out.push(label.len() as u8);
+        out.extend_from_slice(label.as_bytes());
+    }
+    out.push(0);
+    Ok(())
+}
+
+/// Recursive query for `name`
+pub fn build_query(id: u16, name: &str, qtype: u16) -> Result<Vec<u8>, DnsError> {
+    let mut out = Vec::with_capacity(32 + name.len());
+    out.extend_from_slice(&id.to_be_bytes());
+    // RD set
+    out.extend_from_slice(&[0x01, 0x00]);
+    out.extend_from_slice(&[0, 1, 0, 0, 0, 0, 0, 0]);
+    encode_name(name, &mut out)?;
+    out.extend_from_slice(&qtype.to_be_bytes());
+    out.extend_from_slice(&CLASS_IN.to_be_bytes());
+    Ok(out)
+}
+
+/// Response to `query` with `answers`, used by tests and local responders
+pub fn build_response(query: &Message, rcode: u8, answers: &[Record]) -> Result<Vec<u8>, DnsError> {
+    let mut out = Vec::new();
+    out.extend_from_slice(&query.id.to_be_bytes());
+    // QR, RD and RA set
+    out.extend_from_slice(&[0x81, 0x80 | (rcode & 0x0f)]);
+    out.extend_from_slice(&(query.questions.len() as u16).to_be_bytes());
+    out.extend_from_slice(&(answers.len() as u16).to_be_bytes());
+    out.extend_from_slice(&[0, 0, 0, 0]);
+    for q in &query.questions {
+        encode_name(&q.name, &mut out)?;
+        out.extend_from_slice(&q.qtype.to_be_bytes());
+        out.extend_from_slice(&CLASS_IN.to_be_bytes());
+    }
+    for r in answers {
+        encode_name(&r.name, &mut out)?;
+        out.extend_from_slice(&r.rtype.to_be_bytes());
+        out.extend_from_slice(&CLASS_IN.to_be_bytes());
+        out.extend_from_slice(&r.ttl.to_be_bytes());
+        let mut rdata = Vec::new();
+        match &r.data {
+            RData::A(ip) => rdata.extend_from_slice(&ip.octets()),
+            RData::Aaaa(ip) => rdata.extend_from_slice(&ip.octets()),
+            RData::Cname(name) | RData::Ptr(name) => encode_name(name, &mut rdata)?,
+            RData::Other(data) => rdata.extend_from_slice(data),
+        }
+        out.extend_from_slice(&(rdata.len() as u16).to_be_bytes());
+        out.extend_from_slice(&rdata);
+    }
+    Ok(out)
+}
+
+struct Reader<'a> {
+    buf: &'a [u8],
+    pos: usize,
+}
+
+impl Reader<'_> {
+    fn take(&mut self, n: usize) -> Result<&[u8], DnsError> {
+        let end = self.pos.checked_add(n).filter(|e| *e <= self.buf.len());
+        let end = end.ok_or(DnsError::Malformed)?;
+        let slice = &self.buf[self.pos..end];
+        self.pos = end;
+        Ok(slice)
+    }
+    fn u16(&mut self) -> Result<u16, DnsError> {
+        let b = self.take(2)?;
+        Ok(u16::from_be_bytes([b[0], b[1]]))
+    }
+    fn u32(&mut self) -> Result<u32, DnsError> {
+        let b = self.take(4)?;
+        Ok(u32::from_be_bytes([b[0], b[1], b[2], b[3]]))
+    }
+    /// Read a possibly compressed name
+    fn name(&mut self) -> Result<String, DnsError> {
+        let mut labels: Vec<String> = Vec::new();
+        let mut pos = self.pos;
+        let mut resume = None;
+        // Bounds pointer loops
+        for _ in 0..128 {
+            let len = *self.buf.get(pos).ok_or(DnsError::Malformed)? as usize;
+            if len == 0 {
+                self.pos = resume.unwrap_or(pos + 1);
+                return Ok(labels.join("."));
+            }
+            if len & 0xc0 == 0xc0 {
+                let low = *self.buf.get(pos + 1).ok_or(DnsError::Malformed)? as usize;
+                resume.get_or_insert(pos + 2);
+                pos = ((len & 0x3f) << 8) | low;
+                continue;
+            }
+            let label = self
+                .buf
+                .get(pos + 1..pos + 1 + len)
+                .ok_or(DnsError::Malformed)?;
+            labels.push(String::from_utf8_lossy(label).into_owned());
+            pos += 1 + len;
+        }
+        Err(DnsError::Malformed)
+    }
+}
+
+pub fn parse_message(buf: &[u8]) -> Result<Message, DnsError> {
+    let mut r = Reader { buf, pos: 0 };
+    let id = r.u16()?;
+    let flags = r.u16()?;
+    let qdcount = r.u16()?;
+    let ancount = r.u16()?;
+    r.take(4)?;
+    let mut questions = Vec::new();
+    for _ in 0..qdcount {
+        let name = r.name()?;
+        let qtype = r.u16()?;
+        r.u16()?;
+        questions.push(Question { name, qtype });
+    }
+    let mut answers = Vec::new();
+    for _ in 0..ancount {
+        let name = r.name()?;
+        let rtype = r.u16()?;
+        r.u16()?;
+        let ttl = r.u32()?;
+        let len = r.u16()? as usize;
+        let start = r.pos;
+        let data = match rtype {
+            TYPE_A if len == 4 => {
+                let b = r.take(4)?;
+                RData::A(Ipv4Addr::new(b[0], b[1], b[2], b[3]))
+            }
+            TYPE_AAAA if len == 16 => {
+                let b: [u8; 16] = r.take(16)?.try_into().map_err(|_| DnsError::Malformed)?;
+                RData::Aaaa(Ipv6Addr::from(b))
+            }
+            TYPE_CNAME => RData::Cname(r.name()?),
+            TYPE_PTR => RData::Ptr(r.name()?),
+            _ => RData::Other(r.take(len)?.to_vec()),
+        };
+        // Names may be shorter than the declared length with compression
+        r.pos = start;
+        r.take(len)?;
+        answers.push(Record {
+            name,
+            rtype,
+            ttl,
+            data,
+        });
+    }
+    Ok(Message {
+        id,
+        is_response: flags & 0x8000 != 0,
+        truncated: flags & 0x0200 != 0,
+        rcode: (flags & 0x000f) as u8,
+        questions,
+        answers,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn query_round_trip() {
+        let query =
+            parse_message(&build_query(0x1234, "www.example.com.", TYPE_AAAA).unwrap()).unwrap();
+        assert_eq!(query.id, 0x1234);
+        assert!(!query.is_response);
+        assert_eq!(
+            query.questions,
+            vec![Question {
+                name: "www.example.com".to_string(),
+                qtype: TYPE_AAAA
+            }]
+        );
+        assert!(build_query(1, "a..b", TYPE_A).is_err());
+        assert!(build_query(1, &"x".repeat(64), TYPE_A).is_err());
+    }
+
+    #[test]
+    fn compressed_answer() {
+        // example.com A 93.184.216.34, answer name is a pointer to the question
+        let mut buf = vec![0xab, 0xcd, 0x81, 0x80, 0, 1, 0, 1, 0, 0, 0, 0];
+        buf.extend_from_slice(b"\x07example\x03com\x00\x00\x01\x00\x01");
+        buf.extend_from_slice(&[0xc0, 0x0c, 0, 1, 0, 1, 0, 0, 0x0e, 0x10, 0, 4]);
+        buf.extend_from_slice(&[93, 184, 216, 34]);
+        let message = parse_message(&buf).unwrap();
+        assert!(message.is_response);
+        assert_eq!(message.answers[0].name, "example.com");
+        assert_eq!(message.answers[0].ttl, 3600);
+        assert_eq!(
+            message.answers[0].data,
+            RData::A(Ipv4Addr::new(93, 184, 216, 34))
+        );
+        assert!(parse_message(&buf[..buf.len() - 1]).is_err());
+    }
+}
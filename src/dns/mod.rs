@@ -0,0 +1,579 @@
+//! Name resolution through the OS or a custom resolver
+pub mod cachebench;
+pub mod health;
+pub mod message;
+
+use crate::cancel::CancellationToken;
+use message::{RData, TYPE_A, TYPE_AAAA, TYPE_PTR};
+use std::fmt;
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, ToSocketAddrs, UdpSocket};
+use std::sync::{mpsc, RwLock};
+use std::time::{Duration, Instant};
+
+#[derive(Debug)]
+pub enum DnsError {
+    InvalidName(String),
+    /// Response could not be parsed
+    Malformed,
+    Timeout,
+    /// The name does not exist
+    NxDomain,
+    /// Server answered with an error rcode
+    ServerFailure(u8),
+    /// The name exists but has no records of the requested type
+    NoRecords,
+    /// The lookup was abandoned through its cancellation token
+    Cancelled,
+    Io(io::Error),
+}
+
+impl fmt::Display for DnsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DnsError::InvalidName(name) => write!(f, "Invalid name: {}", name),
+            DnsError::Malformed => write!(f, "Malformed DNS message"),
+            DnsError::Timeout => write!(f, "DNS query timed out"),
+            DnsError::NxDomain => write!(f, "Name does not exist"),
+            DnsError::ServerFailure(rcode) => write!(f, "DNS server error (rcode {})", rcode),
+            DnsError::NoRecords => write!(f, "No records found"),
+            DnsError::Cancelled => write!(f, "DNS lookup cancelled"),
+            DnsError::Io(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl std::error::Error for DnsError {}
+
+impl From<io::Error> for DnsError {
+    fn from(e: io::Error) -> Self {
+        match e.kind() {
+            io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut => DnsError::Timeout,
+            _ => DnsError::Io(e),
+        }
+    }
+}
+
+/// Which resolver hostname-taking commands use
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub enum ResolverStrategy {
+    /// The operating system's resolver
+    #[default]
+    System,
+    /// Query these nameservers directly, in order
+    Custom { nameservers: Vec<SocketAddr> },
+}
+
+/// How often blocking lookups check their cancellation token
+const CANCEL_POLL: Duration = Duration::from_millis(50);
+
+/// Resolver setting
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ResolverConfig {
+    pub strategy: ResolverStrategy,
+    /// Timeout per query with a custom resolver
+    pub timeout_ms: u64,
+}
+
+impl Default for ResolverConfig {
+    fn default() -> Self {
+        ResolverConfig {
+            strategy: ResolverStrategy::System,
+            timeout_ms: 2000,
+        }
+    }
+}
+
+/// Resolves hostnames according to a `ResolverConfig`
+#[derive(Clone, Debug, Default)]
+pub struct Resolver {
+    pub config: ResolverConfig,
+}
+
+impl Resolver {
+    pub fn new(config: ResolverConfig) -> Resolver {
+        Resolver { config }
+    }
+    /// Addresses of `host`. IP literals are returned as is.
+    pub fn resolve(&self, host: &str) -> Result<Vec<IpAddr>, DnsError> {
+        self.resolve_cancellable(host, &CancellationToken::new())
+    }
+    /// `resolve`, returning `DnsError::Cancelled` soon after `token` is
+    /// cancelled instead of waiting for the lookup to finish
+    pub fn resolve_cancellable(
+        &self,
+        host: &str,
+        token: &CancellationToken,
+    ) -> Result<Vec<IpAddr>, DnsError> {
+        if let Ok(ip) = host.trim_matches(['[', ']']).parse::<IpAddr>() {
+            return Ok(vec![ip]);
+        }
+        match &self.config.strategy {
+            ResolverStrategy::System => {
+                let addrs = system_lookup(host, token)?;
+                if addrs.is_empty() {
+                    return Err(DnsError::NoRecords);
+                }
+                Ok(addrs)
+            }
+            ResolverStrategy::Custom { nameservers } => {
+                self.resolve_custom(nameservers, host, token)
+            }
+        }
+    }
+    fn resolve_custom(
+        &self,
+        nameservers: &[SocketAddr],
+        host: &str,
+        token: &CancellationToken,
+    ) -> Result<Vec<IpAddr>, DnsError> {
+        let timeout = Duration::from_millis(self.config.timeout_ms);
+        let mut last_err = DnsError::NoRecords;
+        for server in nameservers {
+            let mut addrs = Vec::new();
+            for qtype in [TYPE_A, TYPE_AAAA] {
+                match query_cancellable(*server, host, qtype, timeout, token) {
+                    Ok(response) => {
+                        addrs.extend(response.answers.iter().filter_map(|r| match r.data {
+                            RData::A(ip) => Some(IpAddr::V4(ip)),
+                            RData::Aaaa(ip) => Some(IpAddr::V6(ip)),
+                            _ => None,
+                        }))
+                    }
+                    // Authoritative answer; other servers would say the same
+                    Err(DnsError::NxDomain) => return Err(DnsError::NxDomain),
+                    Err(DnsError::Cancelled) => return Err(DnsError::Cancelled),
+                    Err(e) => last_err = e,
+                }
+            }
+            if !addrs.is_empty() {
+                return Ok(addrs);
+            }
+        }
+        Err(last_err)
+    }
+}
+
+/// Finds the hostname of an address
+pub trait ReverseLookup: Sync {
+    /// Hostname from the PTR record of `ip`, `None` when it has none
+    fn reverse(&self, ip: IpAddr, token: &CancellationToken) -> Result<Option<String>, DnsError>;
+}
+
+impl ReverseLookup for Resolver {
+    fn reverse(&self, ip: IpAddr, token: &CancellationToken) -> Result<Option<String>, DnsError> {
+        match &self.config.strategy {
+            ResolverStrategy::System => system_reverse(
+                ip,
+                Duration::from_millis(self.config.timeout_ms),
+                token,
+                sys::name_info,
+            ),
+            ResolverStrategy::Custom { nameservers } => {
+                let timeout = Duration::from_millis(self.config.timeout_ms);
+                let mut last_err = DnsError::NoRecords;
+                for server in nameservers {
+                    match query_cancellable(*server, &ptr_name(ip), TYPE_PTR, timeout, token) {
+                        Ok(response) => {
+                            return Ok(response.answers.iter().find_map(|r| match &r.data {
+                                RData::Ptr(name) => Some(name.trim_end_matches('.').to_string()),
+                                _ => None,
+                            }))
+                        }
+                        Err(DnsError::NxDomain) => return Ok(None),
+                        Err(DnsError::Cancelled) => return Err(DnsError::Cancelled),
+                        Err(e) => last_err = e,
+                    }
+                }
+                Err(last_err)
+            }
+        }
+    }
+}
+
+/// Name queried for the PTR record of `ip`, e.g. `1.2.0.192.in-addr.arpa`
+pub fn ptr_name(ip: IpAddr) -> String {
+    match ip {
+        IpAddr::V4(v4) => {
+            let [a, b, c, d] = v4.octets();
+            format!("{}.{}.{}.{}.in-addr.arpa", d, c, b, a)
+        }
+        IpAddr::V6(v6) => {
+            let mut name = String::with_capacity(72);
+            for byte in v6.octets().iter().rev() {
+                name.push_str(&format!("{:x}.{:x}.", byte & 0x0f, byte >> 4));
+            }
+            name.push_str("ip6.arpa");
+            name
+        }
+    }
+}
+
+/// Reverse lookup through the OS resolver, `name_info`, on a helper
+/// thread. Like [`system_lookup`] on cancellation, the thread is abandoned
+/// once `timeout` passes, so a slow resolver does not hold up the caller
+/// for its own retry time.
+fn system_reverse(
+    ip: IpAddr,
+    timeout: Duration,
+    token: &CancellationToken,
+    name_info: fn(IpAddr) -> Result<Option<String>, DnsError>,
+) -> Result<Option<String>, DnsError> {
+    let deadline = Instant::now() + timeout;
+    let (tx, rx) = mpsc::channel();
+    std::thread::spawn(move || {
+        let _ = tx.send(name_info(ip));
+    });
+    loop {
+        if token.is_cancelled() {
+            return Err(DnsError::Cancelled);
+        }
+        let left = deadline.saturating_duration_since(Instant::now());
+        if left.is_zero() {
+            return Err(DnsError::Timeout);
+        }
+        match rx.recv_timeout(left.min(CANCEL_POLL)) {
+            Ok(result) => return result,
+            Err(mpsc::RecvTimeoutError::Timeout) => continue,
+            Err(mpsc::RecvTimeoutError::Disconnected) => return Ok(None),
+        }
+    }
+}
+
+#[cfg(unix)]
+mod sys {
+    use super::DnsError;
+    use std::ffi::CStr;
+    use std::net::IpAddr;
+
+    /// `getnameinfo` requiring a name, so addresses without one give `None`
+    pub fn name_info(ip: IpAddr) -> Result<Option<String>, DnsError> {
+        let mut host = [0 as libc::c_char; libc::NI_MAXHOST as usize];
+        let rc = match ip {
+            IpAddr::V4(v4) => {
+                let mut sa: libc::sockaddr_in = unsafe { std::mem::zeroed() };
+                sa.sin_family = libc::AF_INET as libc::sa_family_t;
+                sa.sin_addr.s_addr = u32::from_ne_bytes(v4.octets());
+                unsafe { lookup(&sa, &mut host) }
+            }
+            IpAddr::V6(v6) => {
+                let mut sa: libc::sockaddr_in6 = unsafe { std::mem::zeroed() };
+                sa.sin6_family = libc::AF_INET6 as libc::sa_family_t;
+                sa.sin6_addr.s6_addr = v6.octets();
+                unsafe { lookup(&sa, &mut host) }
+            }
+        };
+        match rc {
+            0 => {
+                let name = unsafe { CStr::from_ptr(host.as_ptr()) };
+                Ok(Some(name.to_string_lossy().into_owned()))
+            }
+            libc::EAI_NONAME => Ok(None),
+            rc => Err(eai_error(rc)),
+        }
+    }
+
+    /// Error for a failed `getnameinfo`. The `EAI_*` codes are negative on
+    /// some platforms, so they are mapped rather than passed on as rcodes.
+    pub fn eai_error(rc: libc::c_int) -> DnsError {
+        match rc {
+            libc::EAI_AGAIN => DnsError::Timeout,
+            // Non-recoverable failure, what SERVFAIL turns into
+            libc::EAI_FAIL => DnsError::ServerFailure(2),
+            libc::EAI_SYSTEM => DnsError::Io(std::io::Error::last_os_error()),
+            rc => {
+                let message = unsafe { CStr::from_ptr(libc::gai_strerror(rc)) };
+                DnsError::Io(std::io::Error::other(
+                    message.to_string_lossy().into_owned(),
+                ))
+            }
+        }
+    }
+
+    unsafe fn lookup<T>(sa: &T, host: &mut [libc::c_char]) -> libc::c_int {
+        libc::getnameinfo(
+            sa as *const T as *const libc::sockaddr,
+            std::mem::size_of::<T>() as libc::socklen_t,
+            host.as_mut_ptr(),
+            host.len() as libc::socklen_t,
+            std::ptr::null_mut(),
+            0,
+            libc::NI_NAMEREQD,
+        )
+    }
+}
+
+#[cfg(not(unix))]
+mod sys {
+    use super::DnsError;
+    use std::net::IpAddr;
+
+    pub fn name_info(_ip: IpAddr) -> Result<Option<String>, DnsError> {
+        Err(DnsError::Io(std::io::ErrorKind::Unsupported.into()))
+    }
+}
+
+/// Lookup through the OS resolver on a helper thread. `getaddrinfo` cannot
+/// be interrupted, so on cancellation the thread is left to finish alone.
+fn system_lookup(host: &str, token: &CancellationToken) -> Result<Vec<IpAddr>, DnsError> {
+    let (tx, rx) = mpsc::channel();
+    let name = host.to_string();
+    std::thread::spawn(move || {
+        let result = (name.as_str(), 0)
+            .to_socket_addrs()
+            .map(|addrs| addrs.map(|a| a.ip()).collect::<Vec<IpAddr>>());
+        let _ = tx.send(result);
+    });
+    loop {
+        if token.is_cancelled() {
+            return Err(DnsError::Cancelled);
+        }
+        match rx.recv_timeout(CANCEL_POLL) {
+            Ok(result) => return result.map_err(DnsError::from),
+            Err(mpsc::RecvTimeoutError::Timeout) => continue,
+            Err(mpsc::RecvTimeoutError::Disconnected) => return Err(DnsError::NoRecords),
+        }
+    }
+}
+
+/// Send one query to `server` over UDP and wait for the matching response
+pub fn query(
+    server: SocketAddr,
+    name: &str,
+    qtype: u16,
+    timeout: Duration,
+) -> Result<message::Message, DnsError> {
+    query_cancellable(server, name, qtype, timeout, &CancellationToken::new())
+}
+
+/// `query`, giving up with `DnsError::Cancelled` once `token` is cancelled
+pub fn query_cancellable(
+    server: SocketAddr,
+    name: &str,
+    qtype: u16,
+    timeout: Duration,
+    token: &CancellationToken,
+) -> Result<message::Message, DnsError> {
+    let id = crate::ping::icmp::random_id();
+    let request = message::build_query(id, name, qtype)?;
+    let bind: SocketAddr = match server {
+        SocketAddr::V4(_) => (Ipv4Addr::UNSPECIFIED, 0).into(),
+        SocketAddr::V6(_) => (Ipv6Addr::UNSPECIFIED, 0).into(),
+    };
+    let socket = UdpSocket::bind(bind)?;
+    socket.connect(server)?;
+    socket.send(&request)?;
+    let deadline = Instant::now() + timeout;
+    let mut buf = [0u8; 1500];
+    loop {
+        if token.is_cancelled() {
+            return Err(DnsError::Cancelled);
+        }
+        let left = deadline.saturating_duration_since(Instant::now());
+        if left.is_zero() {
+            return Err(DnsError::Timeout);
+        }
+        socket.set_read_timeout(Some(left.min(CANCEL_POLL)))?;
+        let n = match socket.recv(&mut buf) {
+            Ok(n) => n,
+            Err(e)
+                if matches!(
+                    e.kind(),
+                    io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+                ) =>
+            {
+                continue
+            }
+            Err(e) => return Err(e.into()),
+        };
+        // Ignore stray or spoofed datagrams
+        let Ok(response) = message::parse_message(&buf[..n]) else {
+            continue;
+        };
+        if response.id != id || !response.is_response {
+            continue;
+        }
+        return match response.rcode {
+            message::RCODE_NOERROR => Ok(response),
+            message::RCODE_NXDOMAIN => Err(DnsError::NxDomain),
+            rcode => Err(DnsError::ServerFailure(rcode)),
+        };
+    }
+}
+
+static GLOBAL_CONFIG: RwLock<Option<ResolverConfig>> = RwLock::new(None);
+
+/// Set the resolver used by every hostname-taking command
+pub fn set_resolver_config(config: ResolverConfig) {
+    *GLOBAL_CONFIG.write().unwrap() = Some(config);
+}
+
+/// Resolver setting currently in effect
+pub fn resolver_config() -> ResolverConfig {
+    GLOBAL_CONFIG.read().unwrap().clone().unwrap_or_default()
+}
+
+/// Resolve `host` with the global resolver setting
+pub fn resolve(host: &str) -> Result<Vec<IpAddr>, DnsError> {
+    Resolver::new(resolver_config()).resolve(host)
+}
+
+/// Resolve scan targets given as hostnames or IP literals, in order and
+/// without duplicates. Stops at the first failure or once `token` is
+/// cancelled, without waiting for lookups still in flight.
+pub fn resolve_targets(
+    resolver: &Resolver,
+    targets: &[String],
+    token: &CancellationToken,
+) -> Result<Vec<IpAddr>, DnsError> {
+    let mut ips = Vec::new();
+    for target in targets {
+        for ip in resolver.resolve_cancellable(target, token)? {
+            if !ips.contains(&ip) {
+                ips.push(ip);
+            }
+        }
+    }
+    Ok(ips)
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::message::*;
+    use super::*;
+    use std::thread;
+
+    /// Answer A queries for `name` with `ip` until `queries` were served.
+    /// Returns the server address.
+    pub(crate) fn serve_a(name: &'static str, ip: Ipv4Addr, queries: usize) -> SocketAddr {
+        let socket = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        let addr = socket.local_addr().unwrap();
+        thread::spawn(move || {
+            let mut buf = [0u8; 512];
+            for _ in 0..queries {
+                let Ok((n, peer)) = socket.recv_from(&mut buf) else {
+                    return;
+                };
+                let query = parse_message(&buf[..n]).unwrap();
+                let q = &query.questions[0];
+                let (rcode, answers) = if q.name != name {
+                    (RCODE_NXDOMAIN, Vec::new())
+                } else if q.qtype == TYPE_A {
+                    let record = Record {
+                        name: name.to_string(),
+                        rtype: TYPE_A,
+                        ttl: 60,
+                        data: RData::A(ip),
+                    };
+                    (RCODE_NOERROR, vec![record])
+                } else {
+                    (RCODE_NOERROR, Vec::new())
+                };
+                let response = build_response(&query, rcode, &answers).unwrap();
+                socket.send_to(&response, peer).unwrap();
+            }
+        });
+        addr
+    }
+
+    #[test]
+    fn custom_resolver_is_used() {
+        let server = serve_a("printer.home.test", Ipv4Addr::new(192, 0, 2, 55), 4);
+        let resolver = Resolver::new(ResolverConfig {
+            strategy: ResolverStrategy::Custom {
+                nameservers: vec![server],
+            },
+            timeout_ms: 1000,
+        });
+        assert_eq!(
+            resolver.resolve("printer.home.test").unwrap(),
+            vec![IpAddr::V4(Ipv4Addr::new(192, 0, 2, 55))]
+        );
+        assert!(matches!(
+            resolver.resolve("missing.home.test"),
+            Err(DnsError::NxDomain)
+        ));
+        // Literals never reach the resolver
+        assert_eq!(
+            resolver.resolve("[2001:db8::1]").unwrap(),
+            vec!["2001:db8::1".parse::<IpAddr>().unwrap()]
+        );
+    }
+
+    #[test]
+    fn reverse_names() {
+        assert_eq!(
+            ptr_name("192.0.2.1".parse().unwrap()),
+            "1.2.0.192.in-addr.arpa"
+        );
+        let v6 = ptr_name("2001:db8::567:89ab".parse().unwrap());
+        assert_eq!(
+            v6,
+            "b.a.9.8.7.6.5.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.8.b.d.0.1.0.0.2.ip6.arpa"
+        );
+    }
+
+    #[test]
+    fn unanswered_query_times_out() {
+        let silent = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        let started = Instant::now();
+        let result = query(
+            silent.local_addr().unwrap(),
+            "example.test",
+            TYPE_A,
+            Duration::from_millis(100),
+        );
+        assert!(matches!(result, Err(DnsError::Timeout)));
+        assert!(started.elapsed() < Duration::from_secs(1));
+    }
+
+    #[test]
+    fn cancelling_slow_resolution_returns_promptly() {
+        let silent = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        let resolver = Resolver::new(ResolverConfig {
+            strategy: ResolverStrategy::Custom {
+                nameservers: vec![silent.local_addr().unwrap()],
+            },
+            timeout_ms: 5000,
+        });
+        let token = CancellationToken::new();
+        let canceller = token.clone();
+        std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(50));
+            canceller.cancel();
+        });
+        let started = Instant::now();
+        let targets = vec!["192.0.2.1".to_string(), "slow.home.test".to_string()];
+        let result = resolve_targets(&resolver, &targets, &token);
+        assert!(matches!(result, Err(DnsError::Cancelled)));
+        assert!(started.elapsed() < Duration::from_millis(1000));
+    }
+
+    #[test]
+    fn slow_system_reverse_times_out() {
+        fn stuck(_ip: IpAddr) -> Result<Option<String>, DnsError> {
+            thread::sleep(Duration::from_secs(5));
+            Ok(None)
+        }
+        let started = Instant::now();
+        let ip = IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1));
+        let token = CancellationToken::new();
+        let result = system_reverse(ip, Duration::from_millis(100), &token, stuck);
+        assert!(matches!(result, Err(DnsError::Timeout)));
+        assert!(started.elapsed() < Duration::from_secs(2));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn eai_codes_mapped_to_errors() {
+        assert!(matches!(sys::eai_error(libc::EAI_AGAIN), DnsError::Timeout));
+        assert!(matches!(
+            sys::eai_error(libc::EAI_FAIL),
+            DnsError::ServerFailure(2)
+        ));
+        let DnsError::Io(e) = sys::eai_error(libc::EAI_MEMORY) else {
+            panic!("expected an I/O error");
+        };
+        assert!(!e.to_string().is_empty());
+    }
+}
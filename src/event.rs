@@ -0,0 +1,195 @@
+//! Bounded buffering between a running operation and a slow event consumer
+use std::collections::VecDeque;
+use std::sync::{Condvar, Mutex};
+use ts_rs::TS;
+
+/// What to do with a progress event when the queue is full
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, TS)]
+pub enum OverflowPolicy {
+    /// Drop the oldest queued progress event
+    #[default]
+    DropOldest,
+    /// Replace the newest queued progress event, so the consumer always
+    /// sees the latest state
+    Coalesce,
+}
+
+/// Bound on events waiting for a slow consumer
+#[derive(Clone, Copy, Debug, PartialEq, Eq, TS)]
+pub struct BackpressureSetting {
+    /// Events queued before progress events are dropped or coalesced
+    pub capacity: usize,
+    pub policy: OverflowPolicy,
+}
+
+impl Default for BackpressureSetting {
+    fn default() -> Self {
+        BackpressureSetting {
+            capacity: 64,
+            policy: OverflowPolicy::DropOldest,
+        }
+    }
+}
+
+/// Event passed from an operation to its consumer
+#[derive(Clone, Debug, PartialEq)]
+pub enum Event<P, T> {
+    /// May be dropped or coalesced under backpressure
+    Progress(P),
+    /// Always delivered, e.g. a found host or the done payload
+    Terminal(T),
+}
+
+#[derive(Debug)]
+struct State<P, T> {
+    queue: VecDeque<Event<P, T>>,
+    dropped: u64,
+    closed: bool,
+}
+
+/// Queue between a producer and a consumer thread. Progress events are
+/// bounded by the setting; terminal events are never dropped and may grow
+/// the queue past its capacity.
+#[derive(Debug)]
+pub struct EventQueue<P, T> {
+    setting: BackpressureSetting,
+    state: Mutex<State<P, T>>,
+    ready: Condvar,
+}
+
+impl<P, T> EventQueue<P, T> {
+    pub fn new(setting: BackpressureSetting) -> EventQueue<P, T> {
+        EventQueue {
+            setting,
+            state: Mutex::new(State {
+                queue: VecDeque::new(),
+                dropped: 0,
+                closed: false,
+            }),
+            ready: Condvar::new(),
+        }
+    }
+    /// Queue a progress event, dropping or coalescing one if full
+    pub fn progress(&self, event: P) {
+        let mut state = self.state.lock().unwrap();
+        if state.queue.len() >= self.setting.capacity.max(1) {
+            let victim = match self.setting.policy {
+                OverflowPolicy::DropOldest => state
+                    .queue
+                    .iter()
+                    .position(|e| matches!(e, Event::Progress(_))),
+                OverflowPolicy::Coalesce => state
+                    .queue
+                    .iter()
+                    .rposition(|e| matches!(e, Event::Progress(_))),
+            };
+            state.dropped += 1;
+            match victim {
+                Some(i) if self.setting.policy == OverflowPolicy::Coalesce => {
+                    state.queue[i] = Event::Progress(event);
+                    return;
+                }
+                Some(i) => {
+                    state.queue.remove(i);
+                }
+                // Only terminal events are queued; they take precedence
+                None => return,
+            }
+        }
+        state.queue.push_back(Event::Progress(event));
+        self.ready.notify_one();
+    }
+    /// Queue an event that must reach the consumer
+    pub fn terminal(&self, event: T) {
+        self.state
+            .lock()
+            .unwrap()
+            .queue
+            .push_back(Event::Terminal(event));
+        self.ready.notify_one();
+    }
+    /// No more events will be queued. The consumer drains what is left.
+    pub fn close(&self) {
+        self.state.lock().unwrap().closed = true;
+        self.ready.notify_all();
+    }
+    /// Progress events dropped or coalesced so far
+    pub fn dropped(&self) -> u64 {
+        self.state.lock().unwrap().dropped
+    }
+    /// Wait for the next event. `None` once closed and drained.
+    pub fn pop(&self) -> Option<Event<P, T>> {
+        let mut state = self.state.lock().unwrap();
+        loop {
+            if let Some(event) = state.queue.pop_front() {
+                return Some(event);
+            }
+            if state.closed {
+                return None;
+            }
+            state = self.ready.wait(state).unwrap();
+        }
+    }
+    /// Pass every event to `sink` until the queue is closed and drained
+    pub fn drain<F: FnMut(Event<P, T>)>(&self, mut sink: F) {
+        while let Some(event) = self.pop() {
+            sink(event);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn queue(policy: OverflowPolicy) -> EventQueue<u32, &'static str> {
+        EventQueue::new(BackpressureSetting {
+            capacity: 3,
+            policy,
+        })
+    }
+
+    fn drained(queue: &EventQueue<u32, &'static str>) -> Vec<Event<u32, &'static str>> {
+        queue.close();
+        let mut events = Vec::new();
+        queue.drain(|e| events.push(e));
+        events
+    }
+
+    #[test]
+    fn full_queue_drops_progress_but_keeps_terminal_events() {
+        use Event::*;
+        let q = queue(OverflowPolicy::DropOldest);
+        q.progress(1);
+        q.terminal("alive");
+        q.progress(2);
+        q.progress(3);
+        q.progress(4);
+        q.terminal("done");
+        assert_eq!(q.dropped(), 2);
+        assert_eq!(
+            drained(&q),
+            vec![
+                Terminal("alive"),
+                Progress(3),
+                Progress(4),
+                Terminal("done")
+            ]
+        );
+
+        let q = queue(OverflowPolicy::Coalesce);
+        (1..=6).for_each(|n| q.progress(n));
+        q.terminal("done");
+        assert_eq!(q.dropped(), 3);
+        assert_eq!(
+            drained(&q),
+            vec![Progress(1), Progress(2), Progress(6), Terminal("done")]
+        );
+
+        let q = queue(OverflowPolicy::Coalesce);
+        ["a", "b", "c"].into_iter().for_each(|t| q.terminal(t));
+        q.progress(1);
+        assert_eq!(q.dropped(), 1);
+        assert_eq!(drained(&q).len(), 3);
+    }
+}
@@ -0,0 +1,134 @@
+//! Good/ok/poor grading of measured metrics
+use crate::ping::result::PingStat;
+use ts_rs::TS;
+
+/// Verdict on a metric, ordered from best to worst
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, TS)]
+pub enum Grade {
+    Good,
+    Ok,
+    Poor,
+}
+
+/// Bounds of the good and ok grades of one metric. For metrics where lower
+/// is better, values up to `good` are good and up to `ok` are ok; for
+/// throughput, values of at least `good` are good and at least `ok` are ok.
+#[derive(Clone, Copy, Debug, PartialEq, TS)]
+pub struct Thresholds {
+    pub good: f64,
+    pub ok: f64,
+}
+
+impl Thresholds {
+    pub fn new(good: f64, ok: f64) -> Thresholds {
+        Thresholds { good, ok }
+    }
+    /// Grade of a metric where lower is better
+    pub fn grade_at_most(&self, value: f64) -> Grade {
+        if value <= self.good {
+            Grade::Good
+        } else if value <= self.ok {
+            Grade::Ok
+        } else {
+            Grade::Poor
+        }
+    }
+    /// Grade of a metric where higher is better
+    pub fn grade_at_least(&self, value: f64) -> Grade {
+        if value >= self.good {
+            Grade::Good
+        } else if value >= self.ok {
+            Grade::Ok
+        } else {
+            Grade::Poor
+        }
+    }
+}
+
+/// Threshold set applied to done payloads
+#[derive(Clone, Copy, Debug, PartialEq, TS)]
+pub struct GradeThresholds {
+    pub latency_ms: Thresholds,
+    pub jitter_ms: Thresholds,
+    pub loss_percent: Thresholds,
+    pub throughput_mbps: Thresholds,
+}
+
+impl Default for GradeThresholds {
+    /// Browsing, streaming and video calls
+    fn default() -> Self {
+        GradeThresholds {
+            latency_ms: Thresholds::new(50.0, 150.0),
+            jitter_ms: Thresholds::new(10.0, 30.0),
+            loss_percent: Thresholds::new(0.5, 2.0),
+            throughput_mbps: Thresholds::new(100.0, 25.0),
+        }
+    }
+}
+
+impl GradeThresholds {
+    /// Competitive online gaming, where latency and jitter matter most
+    pub fn gaming() -> GradeThresholds {
+        GradeThresholds {
+            latency_ms: Thresholds::new(20.0, 50.0),
+            jitter_ms: Thresholds::new(3.0, 10.0),
+            loss_percent: Thresholds::new(0.0, 0.5),
+            ..GradeThresholds::default()
+        }
+    }
+    /// Worst grade of latency, jitter and loss. `None` if nothing was sent.
+    pub fn grade_ping(&self, stat: &PingStat) -> Option<Grade> {
+        if stat.sent == 0 {
+            return None;
+        }
+        let grades = [
+            Some(self.loss_percent.grade_at_most(stat.loss_percent)),
+            stat.avg_ms.map(|ms| self.latency_ms.grade_at_most(ms)),
+            stat.jitter_ms.map(|ms| self.jitter_ms.grade_at_most(ms)),
+        ];
+        grades.into_iter().flatten().max()
+    }
+    pub fn grade_throughput(&self, mbps: f64) -> Grade {
+        self.throughput_mbps.grade_at_least(mbps)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn same_metrics_grade_differently_per_profile() {
+        let stat = PingStat {
+            sent: 100,
+            received: 100,
+            min_ms: Some(25.0),
+            avg_ms: Some(35.0),
+            max_ms: Some(50.0),
+            jitter_ms: Some(4.0),
+            loss_percent: 0.0,
+        };
+        assert_eq!(
+            GradeThresholds::default().grade_ping(&stat),
+            Some(Grade::Good)
+        );
+        assert_eq!(GradeThresholds::gaming().grade_ping(&stat), Some(Grade::Ok));
+        let strict = GradeThresholds {
+            jitter_ms: Thresholds::new(1.0, 2.0),
+            ..GradeThresholds::gaming()
+        };
+        assert_eq!(strict.grade_ping(&stat), Some(Grade::Poor));
+        assert_eq!(strict.grade_ping(&PingStat::default()), None);
+
+        let fiber = GradeThresholds {
+            throughput_mbps: Thresholds::new(500.0, 100.0),
+            ..Default::default()
+        };
+        assert_eq!(
+            GradeThresholds::default().grade_throughput(120.0),
+            Grade::Good
+        );
+        assert_eq!(fiber.grade_throughput(120.0), Grade::Ok);
+        assert_eq!(fiber.grade_throughput(20.0), Grade::Poor);
+    }
+}
@@ -0,0 +1,329 @@
+use super::{HttpTransport, KeepAliveTransport, Url};
+use crate::cancel::CancellationToken;
+use crate::ping::setting::DEFAULT_PING_COUNT;
+use crate::probe::cancellable_sleep;
+use std::fmt;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use ts_rs::TS;
+
+/// Per-request timeout of the latency command
+pub const DEFAULT_REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+pub const MAX_SAMPLES: u32 = 100;
+pub const MIN_REQUEST_TIMEOUT_MS: u64 = 100;
+pub const MAX_REQUEST_TIMEOUT_MS: u64 = 60_000;
+pub const MAX_INTER_SAMPLE_MS: u64 = 10_000;
+
+/// Sampling parameters of the latency command
+#[derive(Clone, Copy, Debug, PartialEq, Eq, TS)]
+pub struct LatencySetting {
+    pub samples: u32,
+    #[ts(type = "number")]
+    pub per_request_timeout_ms: u64,
+    /// Pause between the end of one request and the start of the next
+    #[ts(type = "number")]
+    pub inter_sample_ms: u64,
+    /// Report when each sample was sent in `timestamps_ms`
+    pub include_timestamps: bool,
+}
+
+impl Default for LatencySetting {
+    fn default() -> Self {
+        LatencySetting {
+            samples: DEFAULT_PING_COUNT,
+            per_request_timeout_ms: DEFAULT_REQUEST_TIMEOUT.as_millis() as u64,
+            inter_sample_ms: 0,
+            include_timestamps: false,
+        }
+    }
+}
+
+/// Out-of-range field of a [`LatencySetting`]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum LatencySettingError {
+    Samples(u32),
+    Timeout(u64),
+    Interval(u64),
+}
+
+impl fmt::Display for LatencySettingError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LatencySettingError::Samples(n) => {
+                write!(f, "Sample count must be 1 to {}, got {}", MAX_SAMPLES, n)
+            }
+            LatencySettingError::Timeout(ms) => write!(
+                f,
+                "Request timeout must be {} to {} ms, got {}",
+                MIN_REQUEST_TIMEOUT_MS, MAX_REQUEST_TIMEOUT_MS, ms
+            ),
+            LatencySettingError::Interval(ms) => write!(
+                f,
+                "Interval between samples must be at most {} ms, got {}",
+                MAX_INTER_SAMPLE_MS, ms
+            ),
+        }
+    }
+}
+
+impl std::error::Error for LatencySettingError {}
+
+impl LatencySetting {
+    pub fn validate(&self) -> Result<(), LatencySettingError> {
+        if !(1..=MAX_SAMPLES).contains(&self.samples) {
+            return Err(LatencySettingError::Samples(self.samples));
+        }
+        if !(MIN_REQUEST_TIMEOUT_MS..=MAX_REQUEST_TIMEOUT_MS).contains(&self.per_request_timeout_ms)
+        {
+            return Err(LatencySettingError::Timeout(self.per_request_timeout_ms));
+        }
+        if self.inter_sample_ms > MAX_INTER_SAMPLE_MS {
+            return Err(LatencySettingError::Interval(self.inter_sample_ms));
+        }
+        Ok(())
+    }
+}
+
+/// Result of an HTTP latency measurement
+#[derive(Clone, Debug, PartialEq, TS)]
+pub struct LatencyDonePayload {
+    pub url: String,
+    /// Request times of successful samples in milliseconds
+    pub samples: Vec<f64>,
+    /// Whether each sample went over a reused connection
+    pub reused: Vec<bool>,
+    /// Unix time in milliseconds each sample was sent at, parallel to
+    /// `samples`. Empty unless `include_timestamps` is set.
+    pub timestamps_ms: Vec<f64>,
+    pub failed: u32,
+    pub avg_ms: Option<f64>,
+    /// Mean absolute difference between consecutive samples
+    pub jitter_ms: Option<f64>,
+    /// Average of samples that opened a new connection
+    pub cold_ms: Option<f64>,
+    /// Average of samples over a kept-alive connection
+    pub warm_ms: Option<f64>,
+    /// Whether every sample was forced onto a new connection
+    pub fresh_connection: bool,
+    pub error: Option<String>,
+}
+
+/// Client for latency sampling. `fresh_connection` disables pooling so every
+/// sample includes connection setup.
+pub fn latency_transport(fresh_connection: bool) -> KeepAliveTransport {
+    KeepAliveTransport::new(!fresh_connection)
+}
+
+/// Sample the HEAD request time to `url`, reusing the connection between samples
+/// unless `fresh_connection` is set. Uses `LatencySetting::default()` when
+/// `setting` is `None`.
+pub fn measure_latency_jitter(
+    url: &str,
+    fresh_connection: bool,
+    setting: Option<LatencySetting>,
+    token: &CancellationToken,
+) -> LatencyDonePayload {
+    let transport = latency_transport(fresh_connection);
+    let setting = setting.unwrap_or_default();
+    measure_latency_with(&transport, url, fresh_connection, &setting, token)
+}
+
+/// `measure_latency_jitter` over `transport`
+pub fn measure_latency_with<T: HttpTransport>(
+    transport: &T,
+    url: &str,
+    fresh_connection: bool,
+    setting: &LatencySetting,
+    token: &CancellationToken,
+) -> LatencyDonePayload {
+    let mut payload = LatencyDonePayload {
+        url: url.to_string(),
+        samples: Vec::new(),
+        reused: Vec::new(),
+        timestamps_ms: Vec::new(),
+        failed: 0,
+        avg_ms: None,
+        jitter_ms: None,
+        cold_ms: None,
+        warm_ms: None,
+        fresh_connection,
+        error: None,
+    };
+    if let Err(e) = setting.validate() {
+        payload.error = Some(e.to_string());
+        return payload;
+    }
+    let parsed = match Url::parse(url) {
+        Ok(parsed) => parsed,
+        Err(e) => {
+            payload.error = Some(e.to_string());
+            return payload;
+        }
+    };
+    let timeout = Duration::from_millis(setting.per_request_timeout_ms);
+    // Offsets from one wall-clock reading keep timestamps monotonic even
+    // if the system clock is adjusted mid-run
+    let epoch_ms = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs_f64()
+        * 1000.0;
+    let started = Instant::now();
+    for n in 0..setting.samples {
+        if n > 0
+            && setting.inter_sample_ms > 0
+            && cancellable_sleep(token, Duration::from_millis(setting.inter_sample_ms))
+                .is_cancelled()
+        {
+            break;
+        }
+        if token.is_cancelled() {
+            break;
+        }
+        let sent_ms = epoch_ms + started.elapsed().as_secs_f64() * 1000.0;
+        // HEAD keeps the body transfer out of the sample
+        match transport.head(&parsed, timeout) {
+            Ok(response) => {
+                if setting.include_timestamps {
+                    payload.timestamps_ms.push(sent_ms);
+                }
+                payload
+                    .samples
+                    .push(response.elapsed.as_secs_f64() * 1000.0);
+                payload.reused.push(response.reused_connection);
+            }
+            Err(e) => {
+                payload.failed += 1;
+                payload.error = Some(e.to_string());
+            }
+        }
+    }
+    let samples = &payload.samples;
+    payload.avg_ms = mean(samples.iter().copied());
+    payload.jitter_ms = (samples.len() > 1)
+        .then(|| mean(samples.windows(2).map(|w| (w[1] - w[0]).abs())))
+        .flatten();
+    let split = |reused: bool| {
+        mean(
+            samples
+                .iter()
+                .zip(&payload.reused)
+                .filter(|(_, r)| **r == reused)
+                .map(|(ms, _)| *ms),
+        )
+    };
+    payload.cold_ms = split(false);
+    payload.warm_ms = split(true);
+    payload
+}
+
+fn mean(values: impl Iterator<Item = f64>) -> Option<f64> {
+    let (sum, n) = values.fold((0.0, 0), |(sum, n), v| (sum + v, n + 1));
+    (n > 0).then(|| sum / n as f64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::http::tests::serve_keep_alive;
+    use crate::http::{HttpError, HttpResponse};
+
+    /// Answer to a HEAD request: the length of the body, but no body
+    const OK: &[u8] = b"HTTP/1.1 200 OK\r\nContent-Length: 2\r\n\r\n";
+
+    #[test]
+    fn fresh_connection_disables_pooling() {
+        assert!(!latency_transport(true).pooling());
+        assert!(latency_transport(false).pooling());
+    }
+
+    #[test]
+    fn cold_and_warm_reported() {
+        let token = CancellationToken::new();
+        let url = serve_keep_alive(vec![DEFAULT_PING_COUNT as usize], OK);
+        let warm = measure_latency_jitter(&url, false, None, &token);
+        assert_eq!(warm.samples.len(), DEFAULT_PING_COUNT as usize);
+        assert_eq!(warm.reused, vec![false, true, true, true]);
+        assert!(warm.cold_ms.is_some() && warm.warm_ms.is_some());
+
+        let url = serve_keep_alive(vec![1; DEFAULT_PING_COUNT as usize], OK);
+        let cold = measure_latency_jitter(&url, true, None, &token);
+        assert_eq!(cold.failed, 0);
+        assert!(cold.reused.iter().all(|r| !r));
+        assert_eq!(cold.warm_ms, None);
+        assert_eq!(cold.cold_ms, cold.avg_ms);
+    }
+
+    /// Records the timeout of every HEAD request
+    struct Recorder(std::sync::Mutex<Vec<Duration>>);
+
+    impl HttpTransport for Recorder {
+        fn get(&self, _url: &Url, _timeout: Duration) -> Result<HttpResponse, HttpError> {
+            unreachable!("latency samples are HEAD requests")
+        }
+        fn head(&self, _url: &Url, timeout: Duration) -> Result<HttpResponse, HttpError> {
+            self.0.lock().unwrap().push(timeout);
+            Ok(HttpResponse {
+                status: 200,
+                headers: Vec::new(),
+                body: Vec::new(),
+                ttfb: Duration::from_millis(1),
+                elapsed: Duration::from_millis(1),
+                reused_connection: false,
+            })
+        }
+    }
+
+    #[test]
+    fn configured_samples_and_timeout_are_used() {
+        let token = CancellationToken::new();
+        let recorder = Recorder(Default::default());
+        let setting = LatencySetting {
+            samples: 7,
+            per_request_timeout_ms: 15_000,
+            inter_sample_ms: 5,
+            include_timestamps: false,
+        };
+        let started = std::time::Instant::now();
+        let done = measure_latency_with(&recorder, "http://example.com/", false, &setting, &token);
+        assert_eq!(done.samples.len(), 7);
+        assert!(done.timestamps_ms.is_empty());
+        assert!(started.elapsed() >= Duration::from_millis(30));
+        let timeouts = recorder.0.lock().unwrap().clone();
+        assert_eq!(timeouts, vec![Duration::from_secs(15); 7]);
+
+        let setting = LatencySetting {
+            samples: 0,
+            ..Default::default()
+        };
+        let done = measure_latency_with(&recorder, "http://example.com/", false, &setting, &token);
+        assert_eq!(
+            done.error.as_deref(),
+            Some("Sample count must be 1 to 100, got 0")
+        );
+        assert_eq!(recorder.0.lock().unwrap().len(), 7);
+        assert_eq!(LatencySetting::default().samples, DEFAULT_PING_COUNT);
+    }
+
+    #[test]
+    fn timestamps_parallel_samples_and_increase() {
+        let recorder = Recorder(Default::default());
+        let setting = LatencySetting {
+            samples: 5,
+            inter_sample_ms: 2,
+            include_timestamps: true,
+            ..Default::default()
+        };
+        let done = measure_latency_with(
+            &recorder,
+            "http://example.com/",
+            false,
+            &setting,
+            &CancellationToken::new(),
+        );
+        assert_eq!(done.timestamps_ms.len(), done.samples.len());
+        assert!(done.timestamps_ms.windows(2).all(|w| w[1] > w[0]));
+        // Within a day of now, so wall-clock based
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
+        assert!((now.as_secs_f64() * 1000.0 - done.timestamps_ms[0]).abs() < 86_400_000.0);
+    }
+}
@@ -0,0 +1,562 @@
+//! Minimal HTTP/1.1 client used by the HTTP probes
+pub mod latency;
+pub mod ping;
+
+use flate2::read::{DeflateDecoder, GzDecoder};
+use std::fmt;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{SocketAddr, TcpStream};
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Parsed `http://` URL
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Url {
+    pub host: String,
+    pub port: u16,
+    pub path: String,
+}
+
+impl Url {
+    pub fn parse(s: &str) -> Result<Url, HttpError> {
+        let rest = match s.split_once("://") {
+            Some(("http", rest)) => rest,
+            Some((scheme, _)) => return Err(HttpError::UnsupportedScheme(scheme.to_string())),
+            None => s,
+        };
+        let (authority, path) = match rest.find('/') {
+            Some(i) => (&rest[..i], &rest[i..]),
+            None => (rest, "/"),
+        };
+        if authority.is_empty() {
+            return Err(HttpError::InvalidUrl(s.to_string()));
+        }
+        let invalid = || HttpError::InvalidUrl(s.to_string());
+        let (host, port) = if let Some(bracketed) = authority.strip_prefix('[') {
+            let (host, rest) = bracketed.split_once(']').ok_or_else(invalid)?;
+            match rest.strip_prefix(':') {
+                Some(port) => (host, port.parse::<u16>().map_err(|_| invalid())?),
+                None if rest.is_empty() => (host, 80),
+                None => return Err(invalid()),
+            }
+        } else {
+            match authority.split_once(':') {
+                Some((host, port)) => (host, port.parse::<u16>().map_err(|_| invalid())?),
+                None => (authority, 80),
+            }
+        };
+        Ok(Url {
+            host: host.to_string(),
+            port,
+            path: path.to_string(),
+        })
+    }
+    fn host_header(&self) -> String {
+        let host = if self.host.contains(':') {
+            format!("[{}]", self.host)
+        } else {
+            self.host.clone()
+        };
+        if self.port == 80 {
+            host
+        } else {
+            format!("{}:{}", host, self.port)
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum HttpError {
+    InvalidUrl(String),
+    UnsupportedScheme(String),
+    /// Name resolution or TCP connect failed
+    Connect(io::Error),
+    Timeout,
+    /// Connection dropped or malformed response
+    Protocol(String),
+}
+
+impl HttpError {
+    /// Whether retrying the request may succeed
+    pub fn is_retryable(&self) -> bool {
+        matches!(
+            self,
+            HttpError::Connect(_) | HttpError::Timeout | HttpError::Protocol(_)
+        )
+    }
+}
+
+impl fmt::Display for HttpError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HttpError::InvalidUrl(s) => write!(f, "Invalid URL: {}", s),
+            HttpError::UnsupportedScheme(s) => write!(f, "Unsupported scheme: {}", s),
+            HttpError::Connect(e) => write!(f, "Connection failed: {}", e),
+            HttpError::Timeout => write!(f, "Request timed out"),
+            HttpError::Protocol(s) => write!(f, "Invalid response: {}", s),
+        }
+    }
+}
+
+impl std::error::Error for HttpError {}
+
+fn io_error(e: io::Error) -> HttpError {
+    match e.kind() {
+        io::ErrorKind::TimedOut | io::ErrorKind::WouldBlock => HttpError::Timeout,
+        _ => HttpError::Protocol(e.to_string()),
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct HttpResponse {
+    pub status: u16,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+    /// Time until the status line was received
+    pub ttfb: Duration,
+    /// Time until the body was fully read
+    pub elapsed: Duration,
+    /// Whether the request went over an already open connection
+    pub reused_connection: bool,
+}
+
+impl HttpResponse {
+    /// First header named `name`, compared case-insensitively
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(k, _)| k.eq_ignore_ascii_case(name))
+            .map(|(_, v)| v.as_str())
+    }
+    /// Value of `Content-Encoding` unless it is `identity`
+    pub fn content_encoding(&self) -> Option<&str> {
+        self.header("content-encoding")
+            .filter(|v| !v.eq_ignore_ascii_case("identity"))
+    }
+    /// Body with any gzip or deflate content encoding removed
+    pub fn decoded_body(&self) -> io::Result<Vec<u8>> {
+        let mut decoded = Vec::new();
+        match self.content_encoding().map(|v| v.to_ascii_lowercase()) {
+            None => return Ok(self.body.clone()),
+            Some(enc) if enc == "gzip" || enc == "x-gzip" => {
+                GzDecoder::new(&self.body[..]).read_to_end(&mut decoded)?;
+            }
+            Some(enc) if enc == "deflate" => {
+                DeflateDecoder::new(&self.body[..]).read_to_end(&mut decoded)?;
+            }
+            Some(enc) => {
+                return Err(io::Error::new(
+                    io::ErrorKind::Unsupported,
+                    format!("Unsupported content encoding: {}", enc),
+                ))
+            }
+        }
+        Ok(decoded)
+    }
+}
+
+/// Performs a single HTTP GET
+pub trait HttpTransport: Sync {
+    fn get(&self, url: &Url, timeout: Duration) -> Result<HttpResponse, HttpError>;
+    /// Same request as `get` with the HEAD method, so no body is sent.
+    /// Falls back to `get` for transports that only speak GET.
+    fn head(&self, url: &Url, timeout: Duration) -> Result<HttpResponse, HttpError> {
+        self.get(url, timeout)
+    }
+}
+
+/// Plain-text HTTP/1.1 over TCP, one connection per request
+#[derive(Clone, Copy, Debug, Default)]
+pub struct TcpTransport;
+
+fn connect(url: &Url, timeout: Duration) -> Result<TcpStream, HttpError> {
+    let ip = crate::dns::resolve(&url.host)
+        .map_err(|e| HttpError::Connect(io::Error::new(io::ErrorKind::NotFound, e)))?
+        .into_iter()
+        .next()
+        .ok_or_else(|| HttpError::Connect(io::Error::new(io::ErrorKind::NotFound, "No address")))?;
+    let addr = SocketAddr::new(ip, url.port);
+    let stream = TcpStream::connect_timeout(&addr, timeout).map_err(|e| match e.kind() {
+        io::ErrorKind::TimedOut => HttpError::Timeout,
+        _ => HttpError::Connect(e),
+    })?;
+    set_timeouts(&stream, timeout)?;
+    Ok(stream)
+}
+
+fn set_timeouts(stream: &TcpStream, timeout: Duration) -> Result<(), HttpError> {
+    stream
+        .set_read_timeout(Some(timeout))
+        .map_err(HttpError::Connect)?;
+    stream
+        .set_write_timeout(Some(timeout))
+        .map_err(HttpError::Connect)
+}
+
+fn request_bytes(method: &str, url: &Url, keep_alive: bool) -> Vec<u8> {
+    format!(
+        "{} {} HTTP/1.1\r\nHost: {}\r\nUser-Agent: netdia\r\nAccept: */*\r\nAccept-Encoding: gzip, deflate\r\nConnection: {}\r\n\r\n",
+        method,
+        url.path,
+        url.host_header(),
+        if keep_alive { "keep-alive" } else { "close" }
+    )
+    .into_bytes()
+}
+
+impl TcpTransport {
+    fn send(&self, method: &str, url: &Url, timeout: Duration) -> Result<HttpResponse, HttpError> {
+        let start = Instant::now();
+        let mut stream = connect(url, timeout)?;
+        stream
+            .write_all(&request_bytes(method, url, false))
+            .map_err(io_error)?;
+        read_response_to(method, BufReader::new(stream), start)
+    }
+}
+
+impl HttpTransport for TcpTransport {
+    fn get(&self, url: &Url, timeout: Duration) -> Result<HttpResponse, HttpError> {
+        self.send("GET", url, timeout)
+    }
+    fn head(&self, url: &Url, timeout: Duration) -> Result<HttpResponse, HttpError> {
+        self.send("HEAD", url, timeout)
+    }
+}
+
+/// Send one request with `method`, extra `headers` and `body` on a new
+/// connection. Used for SOAP and other non-GET calls.
+pub fn request(
+    method: &str,
+    url: &Url,
+    headers: &[(&str, &str)],
+    body: &[u8],
+    timeout: Duration,
+) -> Result<HttpResponse, HttpError> {
+    let start = Instant::now();
+    let mut stream = connect(url, timeout)?;
+    let mut head = format!(
+        "{} {} HTTP/1.1\r\nHost: {}\r\nUser-Agent: netdia\r\nContent-Length: {}\r\nConnection: close\r\n",
+        method,
+        url.path,
+        url.host_header(),
+        body.len()
+    );
+    for (name, value) in headers {
+        head.push_str(&format!("{}: {}\r\n", name, value));
+    }
+    head.push_str("\r\n");
+    let mut bytes = head.into_bytes();
+    bytes.extend_from_slice(body);
+    stream.write_all(&bytes).map_err(io_error)?;
+    read_response(BufReader::new(stream), start)
+}
+
+struct PooledConn {
+    host: String,
+    port: u16,
+    reader: BufReader<TcpStream>,
+}
+
+/// HTTP/1.1 over TCP that keeps the last connection open for the next
+/// request when `pooling` is on. With pooling off every request opens a new
+/// connection, so its time includes connection setup.
+#[derive(Default)]
+pub struct KeepAliveTransport {
+    pooling: bool,
+    conn: Mutex<Option<PooledConn>>,
+    connects: AtomicU32,
+}
+
+impl KeepAliveTransport {
+    pub fn new(pooling: bool) -> KeepAliveTransport {
+        KeepAliveTransport {
+            pooling,
+            ..Default::default()
+        }
+    }
+    pub fn pooling(&self) -> bool {
+        self.pooling
+    }
+    /// Connections opened so far
+    pub fn connects(&self) -> u32 {
+        self.connects.load(Ordering::Relaxed)
+    }
+
+    fn send(
+        &self,
+        method: &str,
+        conn: &mut BufReader<TcpStream>,
+        url: &Url,
+        start: Instant,
+    ) -> Result<HttpResponse, HttpError> {
+        conn.get_mut()
+            .write_all(&request_bytes(method, url, self.pooling))
+            .map_err(io_error)?;
+        read_response_to(method, conn, start)
+    }
+
+    fn request(
+        &self,
+        method: &str,
+        url: &Url,
+        timeout: Duration,
+    ) -> Result<HttpResponse, HttpError> {
+        let mut pooled = self.conn.lock().unwrap();
+        let idle = pooled
+            .take()
+            .filter(|c| self.pooling && c.host == url.host && c.port == url.port);
+        if let Some(mut conn) = idle {
+            let start = Instant::now();
+            set_timeouts(conn.reader.get_ref(), timeout)?;
+            // The server may have closed the idle connection; fall through
+            // to a new one in that case
+            if let Ok(mut response) = self.send(method, &mut conn.reader, url, start) {
+                response.reused_connection = true;
+                if keeps_alive(&response) {
+                    *pooled = Some(conn);
+                }
+                return Ok(response);
+            }
+        }
+        let start = Instant::now();
+        let stream = connect(url, timeout)?;
+        self.connects.fetch_add(1, Ordering::Relaxed);
+        let mut reader = BufReader::new(stream);
+        let response = self.send(method, &mut reader, url, start)?;
+        if self.pooling && keeps_alive(&response) {
+            *pooled = Some(PooledConn {
+                host: url.host.clone(),
+                port: url.port,
+                reader,
+            });
+        }
+        Ok(response)
+    }
+}
+
+impl HttpTransport for KeepAliveTransport {
+    fn get(&self, url: &Url, timeout: Duration) -> Result<HttpResponse, HttpError> {
+        self.request("GET", url, timeout)
+    }
+    fn head(&self, url: &Url, timeout: Duration) -> Result<HttpResponse, HttpError> {
+        self.request("HEAD", url, timeout)
+    }
+}
+
+/// Whether the connection can carry another request after `response`
+fn keeps_alive(response: &HttpResponse) -> bool {
+    let delimited = response.header("content-length").is_some()
+        || response.header("transfer-encoding").is_some()
+        || matches!(response.status, 204 | 304);
+    delimited
+        && !response
+            .header("connection")
+            .is_some_and(|v| v.eq_ignore_ascii_case("close"))
+}
+
+/// Read a response from `reader`. `start` is when the request began.
+pub fn read_response<R: BufRead>(reader: R, start: Instant) -> Result<HttpResponse, HttpError> {
+    read_response_to("GET", reader, start)
+}
+
+/// Read the response to a `method` request. A HEAD response carries no
+/// body whatever its headers say.
+fn read_response_to<R: BufRead>(
+    method: &str,
+    mut reader: R,
+    start: Instant,
+) -> Result<HttpResponse, HttpError> {
+    let mut line = String::new();
+    if reader.read_line(&mut line).map_err(io_error)? == 0 {
+        return Err(HttpError::Protocol("Connection closed".to_string()));
+    }
+    let ttfb = start.elapsed();
+    let status = line
+        .split_whitespace()
+        .nth(1)
+        .and_then(|s| s.parse::<u16>().ok())
+        .ok_or_else(|| HttpError::Protocol(format!("Bad status line: {}", line.trim_end())))?;
+    let mut headers = Vec::new();
+    loop {
+        line.clear();
+        if reader.read_line(&mut line).map_err(io_error)? == 0 {
+            return Err(HttpError::Protocol("Truncated headers".to_string()));
+        }
+        let trimmed = line.trim_end();
+        if trimmed.is_empty() {
+            break;
+        }
+        if let Some((k, v)) = trimmed.split_once(':') {
+            headers.push((k.trim().to_string(), v.trim().to_string()));
+        }
+    }
+    let mut response = HttpResponse {
+        status,
+        headers,
+        body: Vec::new(),
+        ttfb,
+        elapsed: Duration::ZERO,
+        reused_connection: false,
+    };
+    let chunked = response
+        .header("transfer-encoding")
+        .is_some_and(|v| v.eq_ignore_ascii_case("chunked"));
+    let content_length = response
+        .header("content-length")
+        .and_then(|v| v.parse::<usize>().ok());
+    if method == "HEAD" {
+        // No body follows, whatever the length headers say
+    } else if chunked {
+        response.body = read_chunked(&mut reader)?;
+    } else if let Some(len) = content_length {
+        response.body = vec![0; len];
+        reader.read_exact(&mut response.body).map_err(io_error)?;
+    } else if status >= 200 && status != 204 && status != 304 {
+        reader.read_to_end(&mut response.body).map_err(io_error)?;
+    }
+    response.elapsed = start.elapsed();
+    Ok(response)
+}
+
+fn read_chunked<R: BufRead>(reader: &mut R) -> Result<Vec<u8>, HttpError> {
+    let mut body = Vec::new();
+    let mut line = String::new();
+    loop {
+        line.clear();
+        reader.read_line(&mut line).map_err(io_error)?;
+        let size_str = line.trim().split(';').next().unwrap_or("");
+        let size = usize::from_str_radix(size_str, 16)
+            .map_err(|_| HttpError::Protocol(format!("Bad chunk size: {}", size_str)))?;
+        if size == 0 {
+            // Trailers end with an empty line
+            loop {
+                line.clear();
+                if reader.read_line(&mut line).map_err(io_error)? == 0 || line.trim().is_empty() {
+                    return Ok(body);
+                }
+            }
+        }
+        let start = body.len();
+        body.resize(start + size, 0);
+        reader.read_exact(&mut body[start..]).map_err(io_error)?;
+        line.clear();
+        reader.read_line(&mut line).map_err(io_error)?;
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use std::net::TcpListener;
+    use std::thread;
+
+    /// Serve each canned response to one connection, in order
+    pub(crate) fn serve(responses: Vec<Vec<u8>>) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        thread::spawn(move || {
+            for response in responses {
+                let (mut stream, _) = listener.accept().unwrap();
+                let mut reader = BufReader::new(stream.try_clone().unwrap());
+                let mut line = String::new();
+                while reader.read_line(&mut line).unwrap_or(0) > 0 && line != "\r\n" {
+                    line.clear();
+                }
+                let _ = stream.write_all(&response);
+            }
+        });
+        format!("http://{}/", addr)
+    }
+
+    #[test]
+    fn parse_urls() {
+        let url = Url::parse("http://example.com:8080/status?x=1").unwrap();
+        assert_eq!(url.host, "example.com");
+        assert_eq!(url.port, 8080);
+        assert_eq!(url.path, "/status?x=1");
+        let url = Url::parse("example.com").unwrap();
+        assert_eq!((url.port, url.path.as_str()), (80, "/"));
+        let url = Url::parse("http://[::1]:81/").unwrap();
+        assert_eq!((url.host.as_str(), url.port), ("::1", 81));
+        assert!(matches!(
+            Url::parse("https://example.com"),
+            Err(HttpError::UnsupportedScheme(_))
+        ));
+    }
+
+    #[test]
+    fn get_content_length_and_chunked() {
+        let url = serve(vec![
+            b"HTTP/1.1 200 OK\r\nContent-Length: 5\r\n\r\nhello".to_vec(),
+            b"HTTP/1.1 404 Not Found\r\nTransfer-Encoding: chunked\r\n\r\n3\r\nabc\r\n2\r\nde\r\n0\r\n\r\n".to_vec(),
+        ]);
+        let url = Url::parse(&url).unwrap();
+        let timeout = Duration::from_secs(2);
+        let response = TcpTransport.get(&url, timeout).unwrap();
+        assert_eq!(response.status, 200);
+        assert_eq!(response.body, b"hello");
+        let response = TcpTransport.get(&url, timeout).unwrap();
+        assert_eq!(response.status, 404);
+        assert_eq!(response.header("TRANSFER-ENCODING"), Some("chunked"));
+        assert_eq!(response.body, b"abcde");
+    }
+
+    /// Serve `responses` in order over one connection per item of `conns`,
+    /// each item giving the number of requests answered on that connection
+    pub(crate) fn serve_keep_alive(conns: Vec<usize>, response: &'static [u8]) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        thread::spawn(move || {
+            for requests in conns {
+                let (mut stream, _) = listener.accept().unwrap();
+                let mut reader = BufReader::new(stream.try_clone().unwrap());
+                for _ in 0..requests {
+                    let mut line = String::new();
+                    while reader.read_line(&mut line).unwrap_or(0) > 0 && line != "\r\n" {
+                        line.clear();
+                    }
+                    let _ = stream.write_all(response);
+                }
+            }
+        });
+        format!("http://{}/", addr)
+    }
+
+    #[test]
+    fn head_reads_no_body() {
+        // Announces a body it does not send, as HEAD responses do
+        let head = b"HTTP/1.1 200 OK\r\nContent-Length: 5000\r\n\r\n";
+        let url = Url::parse(&serve_keep_alive(vec![2], head)).unwrap();
+        let timeout = Duration::from_secs(2);
+        let transport = KeepAliveTransport::new(true);
+        for reused in [false, true] {
+            let response = transport.head(&url, timeout).unwrap();
+            assert_eq!((response.status, response.reused_connection), (200, reused));
+            assert!(response.body.is_empty());
+        }
+        let url = Url::parse(&serve(vec![head.to_vec()])).unwrap();
+        assert!(TcpTransport.head(&url, timeout).unwrap().body.is_empty());
+    }
+
+    #[test]
+    fn keep_alive_reuses_connection() {
+        let ok = b"HTTP/1.1 200 OK\r\nContent-Length: 2\r\n\r\nok";
+        let url = Url::parse(&serve_keep_alive(vec![3], ok)).unwrap();
+        let timeout = Duration::from_secs(2);
+        let transport = KeepAliveTransport::new(true);
+        let reused: Vec<bool> = (0..3)
+            .map(|_| transport.get(&url, timeout).unwrap().reused_connection)
+            .collect();
+        assert_eq!(reused, vec![false, true, true]);
+        assert_eq!(transport.connects(), 1);
+
+        let url = Url::parse(&serve_keep_alive(vec![1, 1], ok)).unwrap();
+        let transport = KeepAliveTransport::new(false);
+        for _ in 0..2 {
+            assert!(!transport.get(&url, timeout).unwrap().reused_connection);
+        }
+        assert_eq!(transport.connects(), 2);
+    }
+}
@@ -0,0 +1,387 @@
+use super::{HttpResponse, HttpTransport, Url};
+use crate::cancel::CancellationToken;
+use crate::probe::cancellable_sleep;
+use std::time::Duration;
+use ts_rs::TS;
+
+/// Settings for HTTP ping
+#[derive(Clone, Debug, PartialEq, Eq, TS)]
+pub struct HttpPingSetting {
+    pub url: String,
+    #[ts(type = "number")]
+    pub timeout_ms: u64,
+    /// Additional attempts after a failed one
+    pub retries: u32,
+    /// Delay before the first retry, doubled for each further retry
+    #[ts(type = "number")]
+    pub retry_backoff_ms: u64,
+    /// Also retry on 5xx status codes. Connection errors are always retried.
+    pub retry_on_server_error: bool,
+    /// Substring the decoded body must contain for the ping to succeed
+    pub body_match: Option<String>,
+    /// Also request a never-seen URL and look for signs of a transparent
+    /// proxy or cache in the answers
+    pub detect_middlebox: bool,
+}
+
+impl HttpPingSetting {
+    pub fn new(url: &str) -> HttpPingSetting {
+        HttpPingSetting {
+            url: url.to_string(),
+            timeout_ms: 5000,
+            retries: 0,
+            retry_backoff_ms: 500,
+            retry_on_server_error: false,
+            body_match: None,
+            detect_middlebox: false,
+        }
+    }
+    /// Backoff before retry number `retry` (1-based)
+    pub fn backoff(&self, retry: u32) -> Duration {
+        let factor = 1u64 << retry.saturating_sub(1).min(16);
+        Duration::from_millis(self.retry_backoff_ms.saturating_mul(factor))
+    }
+}
+
+/// Result of an HTTP ping
+#[derive(Clone, Debug, PartialEq, Eq, TS)]
+pub struct HttpPingResult {
+    pub url: String,
+    /// Whether the final attempt got a non-error status
+    pub success: bool,
+    /// Number of requests made
+    pub attempts: u32,
+    pub status: Option<u16>,
+    #[ts(type = "Duration | null")]
+    pub rtt: Option<Duration>,
+    /// Size of the body as transferred
+    pub body_bytes: Option<usize>,
+    /// Content encoding of the response, e.g. `gzip`
+    pub encoding: Option<String>,
+    pub compressed: bool,
+    /// Whether the body contained `body_match`. `None` if not requested.
+    pub matched: Option<bool>,
+    /// Whether a proxy or cache was seen in the path. `None` if not
+    /// requested or no response arrived.
+    pub middlebox_detected: Option<bool>,
+    /// Header lines and changes that gave the middlebox away
+    pub middlebox_evidence: Vec<String>,
+    pub error: Option<String>,
+}
+
+/// Request `setting.url`, retrying transient failures with backoff
+pub fn http_ping<T: HttpTransport>(
+    transport: &T,
+    setting: &HttpPingSetting,
+    token: &CancellationToken,
+) -> HttpPingResult {
+    let mut result = HttpPingResult {
+        url: setting.url.clone(),
+        success: false,
+        attempts: 0,
+        status: None,
+        rtt: None,
+        body_bytes: None,
+        encoding: None,
+        compressed: false,
+        matched: None,
+        middlebox_detected: None,
+        middlebox_evidence: Vec::new(),
+        error: None,
+    };
+    let url = match Url::parse(&setting.url) {
+        Ok(url) => url,
+        Err(e) => {
+            result.error = Some(e.to_string());
+            return result;
+        }
+    };
+    let timeout = Duration::from_millis(setting.timeout_ms);
+    let mut last_response: Option<HttpResponse>;
+    loop {
+        result.attempts += 1;
+        let retryable = match transport.get(&url, timeout) {
+            Ok(response) => {
+                result.status = Some(response.status);
+                result.rtt = Some(response.elapsed);
+                result.body_bytes = Some(response.body.len());
+                result.encoding = response.content_encoding().map(|e| e.to_string());
+                result.compressed = result.encoding.is_some();
+                result.success = response.status < 400;
+                result.error =
+                    (!result.success).then(|| format!("HTTP status {}", response.status));
+                if let Some(pattern) = &setting.body_match {
+                    let matched = match response.decoded_body() {
+                        Ok(body) => contains(&body, pattern.as_bytes()),
+                        Err(e) => {
+                            result.error = Some(e.to_string());
+                            false
+                        }
+                    };
+                    result.matched = Some(matched);
+                    if result.success && !matched {
+                        result.success = false;
+                        result.error = Some("Response body did not match".to_string());
+                    }
+                }
+                let retryable = response.status >= 500 && setting.retry_on_server_error;
+                last_response = Some(response);
+                retryable
+            }
+            Err(e) => {
+                last_response = None;
+                result.status = None;
+                result.rtt = None;
+                result.body_bytes = None;
+                result.encoding = None;
+                result.compressed = false;
+                result.matched = None;
+                result.error = Some(e.to_string());
+                e.is_retryable()
+            }
+        };
+        if result.success || !retryable || result.attempts > setting.retries {
+            if let Some(plain) = last_response.filter(|_| setting.detect_middlebox) {
+                let marked = transport.get(&marked_url(&url), timeout).ok();
+                result.middlebox_evidence = middlebox_evidence(&plain, marked.as_ref());
+                result.middlebox_detected = Some(!result.middlebox_evidence.is_empty());
+            }
+            return result;
+        }
+        if cancellable_sleep(token, setting.backoff(result.attempts)).is_cancelled() {
+            return result;
+        }
+    }
+}
+
+/// Query parameter carrying the unique marker
+const MARKER_PARAM: &str = "netdia-marker";
+
+/// `url` with a marker no cache can have seen before
+fn marked_url(url: &Url) -> Url {
+    let marker = format!(
+        "{:04x}{:04x}",
+        crate::ping::icmp::random_id(),
+        crate::ping::icmp::random_id()
+    );
+    let sep = if url.path.contains('?') { '&' } else { '?' };
+    Url {
+        path: format!("{}{}{}={}", url.path, sep, MARKER_PARAM, marker),
+        ..url.clone()
+    }
+}
+
+/// Evidence of a proxy or cache in `plain`, the ping response, and
+/// `marked`, the response for the marked URL. The TTL of HTTP replies is
+/// not visible through a connected TCP socket, so only headers are used.
+pub fn middlebox_evidence(plain: &HttpResponse, marked: Option<&HttpResponse>) -> Vec<String> {
+    let mut evidence = Vec::new();
+    for response in std::iter::once(plain).chain(marked) {
+        for name in ["Via", "X-Cache", "X-Cache-Lookup", "X-Squid-Error"] {
+            if let Some(value) = response.header(name) {
+                let line = format!("{}: {}", name, value);
+                if !evidence.contains(&line) {
+                    evidence.push(line);
+                }
+            }
+        }
+    }
+    if let Some(marked) = marked {
+        // A fresh URL can only be served aged from a cache in between
+        if let Some(age) = marked.header("Age").filter(|a| a.trim() != "0") {
+            evidence.push(format!("Age: {} on a never requested URL", age));
+        }
+        let (before, after) = (plain.header("Server"), marked.header("Server"));
+        if before != after {
+            evidence.push(format!(
+                "Server changed from {} to {}",
+                before.unwrap_or("(none)"),
+                after.unwrap_or("(none)")
+            ));
+        }
+    }
+    evidence
+}
+
+fn contains(haystack: &[u8], needle: &[u8]) -> bool {
+    needle.is_empty() || haystack.windows(needle.len()).any(|w| w == needle)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::http::tests::serve;
+    use crate::http::{HttpError, HttpResponse, TcpTransport};
+    use flate2::write::GzEncoder;
+    use flate2::Compression;
+    use std::io::{self, Write};
+    use std::sync::atomic::{AtomicU32, Ordering};
+
+    /// Fails the first `failures` requests with the given error kind
+    struct Flaky {
+        failures: u32,
+        status: u16,
+        calls: AtomicU32,
+    }
+
+    impl HttpTransport for Flaky {
+        fn get(&self, _url: &Url, _timeout: Duration) -> Result<HttpResponse, HttpError> {
+            let n = self.calls.fetch_add(1, Ordering::SeqCst);
+            if n < self.failures {
+                return Err(HttpError::Connect(io::Error::from(
+                    io::ErrorKind::ConnectionRefused,
+                )));
+            }
+            Ok(HttpResponse {
+                status: self.status,
+                headers: Vec::new(),
+                body: Vec::new(),
+                ttfb: Duration::from_millis(1),
+                elapsed: Duration::from_millis(2),
+                reused_connection: false,
+            })
+        }
+    }
+
+    fn setting(retries: u32) -> HttpPingSetting {
+        HttpPingSetting {
+            retries,
+            retry_backoff_ms: 1,
+            ..HttpPingSetting::new("http://example.com/")
+        }
+    }
+
+    #[test]
+    fn retry_after_transient_failure() {
+        let transport = Flaky {
+            failures: 1,
+            status: 200,
+            calls: AtomicU32::new(0),
+        };
+        let result = http_ping(&transport, &setting(2), &CancellationToken::new());
+        assert!(result.success);
+        assert_eq!(result.attempts, 2);
+        assert_eq!(result.status, Some(200));
+        assert_eq!(result.error, None);
+    }
+
+    #[test]
+    fn retries_exhausted() {
+        let transport = Flaky {
+            failures: 5,
+            status: 200,
+            calls: AtomicU32::new(0),
+        };
+        let result = http_ping(&transport, &setting(2), &CancellationToken::new());
+        assert!(!result.success);
+        assert_eq!(result.attempts, 3);
+    }
+
+    #[test]
+    fn error_status_is_not_retried_by_default() {
+        let transport = Flaky {
+            failures: 0,
+            status: 503,
+            calls: AtomicU32::new(0),
+        };
+        let result = http_ping(&transport, &setting(3), &CancellationToken::new());
+        assert_eq!((result.success, result.attempts), (false, 1));
+
+        let transport = Flaky {
+            failures: 0,
+            status: 503,
+            calls: AtomicU32::new(0),
+        };
+        let mut retry_5xx = setting(3);
+        retry_5xx.retry_on_server_error = true;
+        let result = http_ping(&transport, &retry_5xx, &CancellationToken::new());
+        assert_eq!((result.success, result.attempts), (false, 4));
+    }
+
+    #[test]
+    fn backoff_doubles() {
+        let s = HttpPingSetting::new("http://example.com/");
+        assert_eq!(s.backoff(1), Duration::from_millis(500));
+        assert_eq!(s.backoff(3), Duration::from_millis(2000));
+    }
+
+    fn gzip_response(body: &[u8]) -> Vec<u8> {
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(body).unwrap();
+        let gz = encoder.finish().unwrap();
+        let mut response = format!(
+            "HTTP/1.1 200 OK\r\nContent-Encoding: gzip\r\nContent-Length: {}\r\n\r\n",
+            gz.len()
+        )
+        .into_bytes();
+        response.extend_from_slice(&gz);
+        response
+    }
+
+    #[test]
+    fn reports_size_encoding_and_match() {
+        let body = b"{\"status\":\"ok\",\"padding\":\"aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa\"}";
+        let gz = gzip_response(body);
+        let mut setting = HttpPingSetting::new(&serve(vec![gz.clone()]));
+        setting.body_match = Some("\"status\":\"ok\"".to_string());
+        let result = http_ping(&TcpTransport, &setting, &CancellationToken::new());
+        assert!(result.success, "{:?}", result.error);
+        assert_eq!(result.encoding.as_deref(), Some("gzip"));
+        assert!(result.compressed);
+        assert!(result.body_bytes.unwrap() < body.len());
+        assert_eq!(result.matched, Some(true));
+    }
+
+    #[test]
+    fn body_mismatch_fails_the_ping() {
+        let plain = b"HTTP/1.1 200 OK\r\nContent-Length: 11\r\n\r\nmaintenance".to_vec();
+        let mut setting = HttpPingSetting::new(&serve(vec![plain]));
+        setting.body_match = Some("ok".to_string());
+        let result = http_ping(&TcpTransport, &setting, &CancellationToken::new());
+        assert!(!result.success);
+        assert_eq!(result.matched, Some(false));
+        assert_eq!(result.body_bytes, Some(11));
+        assert_eq!(result.encoding, None);
+        assert!(!result.compressed);
+    }
+
+    #[test]
+    fn transparent_proxy_detected() {
+        let origin = b"HTTP/1.1 200 OK\r\nServer: nginx\r\nContent-Length: 0\r\n\r\n".to_vec();
+        let proxied = b"HTTP/1.1 200 OK\r\nServer: squid/5.7\r\nVia: 1.1 proxy.isp.example (squid/5.7)\r\nX-Cache: MISS from proxy.isp.example\r\nContent-Length: 0\r\n\r\n".to_vec();
+        let mut setting = HttpPingSetting::new(&serve(vec![origin.clone(), proxied]));
+        setting.detect_middlebox = true;
+        let result = http_ping(&TcpTransport, &setting, &CancellationToken::new());
+        assert!(result.success);
+        assert_eq!(result.middlebox_detected, Some(true));
+        assert_eq!(
+            result.middlebox_evidence,
+            vec![
+                "Via: 1.1 proxy.isp.example (squid/5.7)",
+                "X-Cache: MISS from proxy.isp.example",
+                "Server changed from nginx to squid/5.7",
+            ]
+        );
+
+        let mut setting = HttpPingSetting::new(&serve(vec![origin.clone(); 2]));
+        setting.detect_middlebox = true;
+        let result = http_ping(&TcpTransport, &setting, &CancellationToken::new());
+        assert_eq!(result.middlebox_detected, Some(false));
+        assert!(result.middlebox_evidence.is_empty());
+
+        let result = http_ping(
+            &TcpTransport,
+            &HttpPingSetting::new(&serve(vec![origin])),
+            &CancellationToken::new(),
+        );
+        assert_eq!(result.middlebox_detected, None);
+    }
+
+    #[test]
+    fn marker_is_unique_per_request() {
+        let url = Url::parse("http://example.com/status?x=1").unwrap();
+        let (a, b) = (marked_url(&url), marked_url(&url));
+        assert!(a.path.starts_with("/status?x=1&netdia-marker="));
+        assert_ne!(a.path, b.path);
+    }
+}
@@ -1,3 +1,23 @@
+pub mod cancel;
+pub mod dns;
+pub mod event;
+pub mod grade;
+pub mod http;
+pub mod net;
+pub mod pcap;
+pub mod ping;
+pub mod pool;
+pub mod probe;
+pub mod progress;
+pub mod rate;
+pub mod scan;
+pub mod schema;
+pub mod socket;
+pub mod speedtest;
+pub mod stats;
+pub mod trace;
+pub mod update;
+
 pub fn add(left: usize, right: usize) -> usize {
     left + right
 }
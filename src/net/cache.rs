@@ -0,0 +1,102 @@
+//! Cached interface list with on-demand refresh
+use super::interface::{Interface, InterfaceSource};
+use std::io;
+use std::sync::RwLock;
+use std::time::{Duration, Instant};
+
+/// Event name emitted with the refreshed interface list
+pub const INTERFACES_REFRESHED_EVENT: &str = "interfaces:refreshed";
+/// Non-forced refreshes closer together than this are skipped
+pub const DEFAULT_MIN_REFRESH_INTERVAL: Duration = Duration::from_secs(2);
+
+#[derive(Debug, Default)]
+struct Cached {
+    interfaces: Vec<Interface>,
+    last_refresh: Option<Instant>,
+}
+
+/// Interface list shared between commands, refreshed periodically or on demand
+#[derive(Debug)]
+pub struct InterfaceCache<S> {
+    source: S,
+    min_interval: Duration,
+    state: RwLock<Cached>,
+}
+
+impl<S: InterfaceSource> InterfaceCache<S> {
+    pub fn new(source: S) -> InterfaceCache<S> {
+        InterfaceCache {
+            source,
+            min_interval: DEFAULT_MIN_REFRESH_INTERVAL,
+            state: RwLock::new(Cached::default()),
+        }
+    }
+    pub fn with_min_interval(mut self, min_interval: Duration) -> InterfaceCache<S> {
+        self.min_interval = min_interval;
+        self
+    }
+    /// Interfaces as of the last refresh
+    pub fn interfaces(&self) -> Vec<Interface> {
+        self.state.read().unwrap().interfaces.clone()
+    }
+    pub fn last_refresh(&self) -> Option<Instant> {
+        self.state.read().unwrap().last_refresh
+    }
+    /// Re-enumerate interfaces and replace the cache. Unless `force` is
+    /// set, a refresh within the minimum interval of the previous one is
+    /// skipped and returns `None`. Otherwise returns the new list to emit.
+    pub fn refresh(&self, force: bool) -> io::Result<Option<Vec<Interface>>> {
+        let now = Instant::now();
+        if !force {
+            let last = self.last_refresh();
+            if last.is_some_and(|t| now.saturating_duration_since(t) < self.min_interval) {
+                return Ok(None);
+            }
+        }
+        let interfaces = self.source.interfaces()?;
+        let mut state = self.state.write().unwrap();
+        state.interfaces = interfaces.clone();
+        state.last_refresh = Some(Instant::now());
+        Ok(Some(interfaces))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+
+    /// Returns whatever interfaces are currently set
+    struct Switchable(Mutex<Vec<Interface>>);
+
+    impl InterfaceSource for Switchable {
+        fn interfaces(&self) -> io::Result<Vec<Interface>> {
+            Ok(self.0.lock().unwrap().clone())
+        }
+    }
+
+    #[test]
+    fn force_refresh_picks_up_new_interfaces() {
+        let cache = InterfaceCache::new(Switchable(Mutex::new(vec![Interface::new(1, "eth0")])))
+            .with_min_interval(Duration::from_secs(60));
+        assert_eq!(cache.last_refresh(), None);
+        assert_eq!(cache.refresh(false).unwrap().unwrap().len(), 1);
+        let first = cache.last_refresh().unwrap();
+
+        // A VPN comes up
+        cache
+            .source
+            .0
+            .lock()
+            .unwrap()
+            .push(Interface::new(9, "tun0"));
+        assert_eq!(cache.refresh(false).unwrap(), None);
+        assert_eq!(cache.interfaces().len(), 1);
+
+        let refreshed = cache.refresh(true).unwrap().unwrap();
+        let names: Vec<&str> = refreshed.iter().map(|i| i.name.as_str()).collect();
+        assert_eq!(names, vec!["eth0", "tun0"]);
+        assert_eq!(cache.interfaces(), refreshed);
+        assert!(cache.last_refresh().unwrap() > first);
+    }
+}
@@ -0,0 +1,259 @@
+//! Per-connection byte counters and top talkers
+use super::interface::Interface;
+use crate::cancel::CancellationToken;
+use crate::probe::cancellable_sleep_until;
+use std::collections::HashMap;
+use std::io;
+use std::net::IpAddr;
+use std::time::{Duration, Instant};
+
+/// Byte counters of one tracked connection
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Flow {
+    pub protocol: String,
+    pub src: IpAddr,
+    pub dst: IpAddr,
+    pub src_port: Option<u16>,
+    pub dst_port: Option<u16>,
+    /// Bytes sent by the originator
+    pub orig_bytes: u64,
+    /// Bytes sent by the responder
+    pub reply_bytes: u64,
+}
+
+impl Flow {
+    fn key(&self) -> (String, IpAddr, IpAddr, Option<u16>, Option<u16>) {
+        (
+            self.protocol.clone(),
+            self.src,
+            self.dst,
+            self.src_port,
+            self.dst_port,
+        )
+    }
+    pub fn total_bytes(&self) -> u64 {
+        self.orig_bytes + self.reply_bytes
+    }
+}
+
+/// Source of connection tracking snapshots
+pub trait FlowSource {
+    fn flows(&self) -> io::Result<Vec<Flow>>;
+}
+
+/// Netfilter connection tracking table. Byte counters need
+/// `net.netfilter.nf_conntrack_acct=1`.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Conntrack;
+
+impl FlowSource for Conntrack {
+    #[cfg(target_os = "linux")]
+    fn flows(&self) -> io::Result<Vec<Flow>> {
+        let content = std::fs::read_to_string("/proc/net/nf_conntrack")?;
+        Ok(parse_conntrack(&content))
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    fn flows(&self) -> io::Result<Vec<Flow>> {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "Connection tracking is not supported on this platform",
+        ))
+    }
+}
+
+/// Parse `/proc/net/nf_conntrack` or `conntrack -L -o extended` output.
+/// Entries without byte counters are skipped.
+pub fn parse_conntrack(content: &str) -> Vec<Flow> {
+    content.lines().filter_map(parse_conntrack_line).collect()
+}
+
+fn parse_conntrack_line(line: &str) -> Option<Flow> {
+    let tokens: Vec<&str> = line.split_whitespace().collect();
+    // Protocol name follows the address family and its number
+    let protocol = tokens.get(2)?.to_string();
+    let mut src = None;
+    let mut dst = None;
+    let mut src_port = None;
+    let mut dst_port = None;
+    let mut bytes = Vec::new();
+    for (key, value) in tokens.iter().filter_map(|t| t.split_once('=')) {
+        // Keys repeat for the reply direction; the first occurrence is the
+        // original direction
+        match key {
+            "src" if src.is_none() => src = value.parse().ok(),
+            "dst" if dst.is_none() => dst = value.parse().ok(),
+            "sport" if src_port.is_none() => src_port = value.parse().ok(),
+            "dport" if dst_port.is_none() => dst_port = value.parse().ok(),
+            "bytes" => bytes.push(value.parse::<u64>().ok()?),
+            _ => {}
+        }
+    }
+    Some(Flow {
+        protocol,
+        src: src?,
+        dst: dst?,
+        src_port,
+        dst_port,
+        orig_bytes: *bytes.first()?,
+        reply_bytes: bytes.get(1).copied().unwrap_or(0),
+    })
+}
+
+/// Remote endpoint ranked by traffic
+#[derive(Clone, Debug, PartialEq)]
+pub struct Talker {
+    pub remote: IpAddr,
+    pub hostname: Option<String>,
+    /// Bytes in both directions during the window
+    pub bytes: u64,
+    pub bytes_per_sec: f64,
+    /// Connections that carried traffic during the window
+    pub flows: usize,
+}
+
+/// Rank remote endpoints of `local` addresses by bytes moved between two
+/// snapshots. Flows first seen in `after` count with their full counters.
+pub fn rank_talkers(
+    before: &[Flow],
+    after: &[Flow],
+    local: &[IpAddr],
+    elapsed: Duration,
+    limit: usize,
+) -> Vec<Talker> {
+    let previous: HashMap<_, u64> = before.iter().map(|f| (f.key(), f.total_bytes())).collect();
+    let mut by_remote: HashMap<IpAddr, (u64, usize)> = HashMap::new();
+    for flow in after {
+        let remote = if local.contains(&flow.src) {
+            flow.dst
+        } else if local.contains(&flow.dst) {
+            flow.src
+        } else {
+            continue;
+        };
+        let prev = previous.get(&flow.key()).copied().unwrap_or(0);
+        // Counters reset when an entry is recreated with the same tuple
+        let delta = flow
+            .total_bytes()
+            .checked_sub(prev)
+            .unwrap_or(flow.total_bytes());
+        if delta == 0 {
+            continue;
+        }
+        let entry = by_remote.entry(remote).or_default();
+        entry.0 += delta;
+        entry.1 += 1;
+    }
+    let secs = elapsed.as_secs_f64();
+    let mut talkers: Vec<Talker> = by_remote
+        .into_iter()
+        .map(|(remote, (bytes, flows))| Talker {
+            remote,
+            hostname: None,
+            bytes,
+            bytes_per_sec: if secs > 0.0 { bytes as f64 / secs } else { 0.0 },
+            flows,
+        })
+        .collect();
+    talkers.sort_by(|a, b| b.bytes.cmp(&a.bytes).then(a.remote.cmp(&b.remote)));
+    talkers.truncate(limit);
+    talkers
+}
+
+/// Sample `source` over `duration` and return the top `limit` remote
+/// endpoints talking to `iface`. Returns what was measured so far if
+/// cancelled.
+pub fn top_talkers<S: FlowSource>(
+    source: &S,
+    iface: &Interface,
+    duration: Duration,
+    limit: usize,
+    token: &CancellationToken,
+) -> io::Result<Vec<Talker>> {
+    let local: Vec<IpAddr> = iface.addrs.iter().map(|a| a.addr).collect();
+    let started = Instant::now();
+    let before = source.flows()?;
+    cancellable_sleep_until(token, started + duration);
+    let after = source.flows()?;
+    Ok(rank_talkers(
+        &before,
+        &after,
+        &local,
+        started.elapsed(),
+        limit,
+    ))
+}
+
+/// Fill in hostnames with `resolve`
+pub fn resolve_hostnames<F>(talkers: &mut [Talker], resolve: F)
+where
+    F: Fn(IpAddr) -> Option<String>,
+{
+    for talker in talkers {
+        talker.hostname = resolve(talker.remote);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BEFORE: &str = "\
+ipv4     2 tcp      6 431999 ESTABLISHED src=192.168.1.10 dst=93.184.216.34 sport=51234 dport=443 packets=10 bytes=1200 src=93.184.216.34 dst=192.168.1.10 sport=443 dport=51234 packets=12 bytes=15000 [ASSURED] mark=0 zone=0 use=2
+ipv4     2 udp      17 25 src=192.168.1.10 dst=8.8.8.8 sport=40000 dport=53 packets=1 bytes=60 src=8.8.8.8 dst=192.168.1.10 sport=53 dport=40000 packets=1 bytes=120 mark=0 zone=0 use=2
+ipv4     2 tcp      6 100 ESTABLISHED src=192.168.1.10 dst=1.1.1.1 sport=50000 dport=443 src=1.1.1.1 dst=192.168.1.10 sport=443 dport=50000 mark=0 use=1
+";
+
+    const AFTER: &str = "\
+ipv4     2 tcp      6 431999 ESTABLISHED src=192.168.1.10 dst=93.184.216.34 sport=51234 dport=443 packets=20 bytes=2200 src=93.184.216.34 dst=192.168.1.10 sport=443 dport=51234 packets=40 bytes=65000 [ASSURED] mark=0 zone=0 use=2
+ipv4     2 udp      17 25 src=192.168.1.10 dst=8.8.8.8 sport=40000 dport=53 packets=1 bytes=60 src=8.8.8.8 dst=192.168.1.10 sport=53 dport=40000 packets=1 bytes=120 mark=0 zone=0 use=2
+ipv4     2 udp      17 29 src=192.168.1.10 dst=8.8.8.8 sport=40001 dport=53 packets=1 bytes=60 src=8.8.8.8 dst=192.168.1.10 sport=53 dport=40001 packets=1 bytes=140 mark=0 zone=0 use=2
+ipv4     2 tcp      6 300 ESTABLISHED src=203.0.113.5 dst=192.168.1.10 sport=60000 dport=22 packets=5 bytes=4000 src=192.168.1.10 dst=203.0.113.5 sport=22 dport=60000 packets=5 bytes=6000 [ASSURED] mark=0 use=1
+ipv4     2 tcp      6 300 ESTABLISHED src=10.9.9.9 dst=10.9.9.8 sport=1 dport=2 packets=5 bytes=999999 src=10.9.9.8 dst=10.9.9.9 sport=2 dport=1 packets=5 bytes=1 mark=0 use=1
+";
+
+    #[test]
+    fn parse_accounted_entries() {
+        let flows = parse_conntrack(BEFORE);
+        // The entry without byte counters is skipped
+        assert_eq!(flows.len(), 2);
+        assert_eq!(flows[0].protocol, "tcp");
+        assert_eq!(flows[0].src_port, Some(51234));
+        assert_eq!(flows[0].dst_port, Some(443));
+        assert_eq!((flows[0].orig_bytes, flows[0].reply_bytes), (1200, 15000));
+    }
+
+    #[test]
+    fn talkers_ranked_by_window_bytes() {
+        let local: Vec<IpAddr> = vec!["192.168.1.10".parse().unwrap()];
+        let talkers = rank_talkers(
+            &parse_conntrack(BEFORE),
+            &parse_conntrack(AFTER),
+            &local,
+            Duration::from_secs(2),
+            10,
+        );
+        let ranked: Vec<(String, u64, usize)> = talkers
+            .iter()
+            .map(|t| (t.remote.to_string(), t.bytes, t.flows))
+            .collect();
+        assert_eq!(
+            ranked,
+            vec![
+                ("93.184.216.34".to_string(), 51000, 1),
+                ("203.0.113.5".to_string(), 10000, 1),
+                // Only the new lookup moved bytes during the window
+                ("8.8.8.8".to_string(), 200, 1),
+            ]
+        );
+        assert_eq!(talkers[0].bytes_per_sec, 25500.0);
+        let top = rank_talkers(
+            &parse_conntrack(BEFORE),
+            &parse_conntrack(AFTER),
+            &local,
+            Duration::from_secs(2),
+            1,
+        );
+        assert_eq!(top.len(), 1);
+    }
+}
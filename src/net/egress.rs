@@ -0,0 +1,568 @@
+//! Probing through a named interface, such as a VPN tunnel
+use super::interface::Interface;
+use super::route::{self, Route};
+use crate::ping::compare::source_ip_for;
+use crate::ping::{ProbeError, ProbeReply, Prober};
+use std::collections::HashMap;
+use std::fmt;
+use std::io;
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// Name prefixes of tunnel interfaces created by common VPN clients
+const TUNNEL_PREFIXES: [&str; 7] = ["tun", "tap", "wg", "utun", "ppp", "ipsec", "tailscale"];
+
+/// Reason probes cannot be sent through the requested interface
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum EgressError {
+    NoSuchInterface(String),
+    InterfaceDown(String),
+    /// The interface has no address of the destination's family
+    NoAddress {
+        iface: String,
+        dst: IpAddr,
+    },
+    NoRoute(IpAddr),
+    /// Traffic to `dst` would leave through `route_iface` instead
+    RouteMismatch {
+        iface: String,
+        dst: IpAddr,
+        route_iface: String,
+    },
+    /// The source address is not assigned to any interface
+    SourceNotLocal(IpAddr),
+    /// `src` belongs to `src_iface`, but traffic to `dst` leaves through
+    /// `route_iface`, so replies may never reach `src`
+    SourceMismatch {
+        src: IpAddr,
+        src_iface: String,
+        dst: IpAddr,
+        route_iface: String,
+    },
+}
+
+impl fmt::Display for EgressError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EgressError::NoSuchInterface(name) => write!(f, "No interface named {}", name),
+            EgressError::InterfaceDown(name) => write!(f, "Interface {} is down", name),
+            EgressError::NoAddress { iface, dst } => {
+                write!(f, "{} has no address usable for reaching {}", iface, dst)
+            }
+            EgressError::NoRoute(dst) => write!(f, "No route to {}", dst),
+            EgressError::RouteMismatch {
+                iface,
+                dst,
+                route_iface,
+            } => write!(
+                f,
+                "Traffic to {} is routed via {}, not {}",
+                dst, route_iface, iface
+            ),
+            EgressError::SourceNotLocal(src) => {
+                write!(f, "{} is not assigned to any interface", src)
+            }
+            EgressError::SourceMismatch {
+                src,
+                src_iface,
+                dst,
+                route_iface,
+            } => write!(
+                f,
+                "Source {} is on {}, but traffic to {} is routed via {}",
+                src, src_iface, dst, route_iface
+            ),
+        }
+    }
+}
+
+impl std::error::Error for EgressError {}
+
+/// Whether `iface` looks like a tunnel created by a VPN client
+pub fn is_tunnel(iface: &Interface) -> bool {
+    iface.mac.is_none()
+        && !iface.is_loopback
+        && TUNNEL_PREFIXES.iter().any(|p| iface.name.starts_with(p))
+}
+
+/// Interface traffic to `dst` leaves through. Addresses of this host are
+/// reached over loopback, which the main table has no route for.
+pub fn egress_interface<'a>(
+    interfaces: &'a [Interface],
+    routes: &[Route],
+    dst: IpAddr,
+) -> Option<&'a Interface> {
+    let local = dst.is_loopback()
+        || interfaces
+            .iter()
+            .any(|i| i.addrs.iter().any(|net| net.addr == dst));
+    if local {
+        return interfaces.iter().find(|i| i.is_loopback);
+    }
+    let route = route::lookup(routes, dst)?;
+    interfaces.iter().find(|i| i.name == route.iface)
+}
+
+/// MAC address of the interface traffic to `dst` leaves through, if it
+/// has a link layer
+pub fn system_egress_mac(dst: IpAddr) -> Option<[u8; 6]> {
+    let interfaces = super::interface::get_interfaces().ok()?;
+    let routes = route::get_routes().unwrap_or_default();
+    egress_interface(&interfaces, &routes, dst)?.mac
+}
+
+/// Source address to bind to so probes to `dst` leave through the
+/// interface `name`.
+///
+/// Binding the source address alone does not change the egress interface,
+/// so the routing table is checked as well. If `dst` would be routed
+/// elsewhere, e.g. outside a split tunnel, this fails instead of letting
+/// the probes silently take the default route.
+///
+/// `routes` holds the main table only. When it disagrees, the kernel is
+/// asked which source it picks for `dst`, so a tunnel set up with policy
+/// routing rules, as by wg-quick, is still accepted.
+pub fn egress_source(
+    interfaces: &[Interface],
+    routes: &[Route],
+    name: &str,
+    dst: IpAddr,
+) -> Result<IpAddr, EgressError> {
+    egress_source_with(interfaces, routes, name, dst, |dst| {
+        route::kernel_source(dst).ok()
+    })
+}
+
+/// [`egress_source`] with `kernel_source` standing in for the kernel's
+/// source selection
+fn egress_source_with(
+    interfaces: &[Interface],
+    routes: &[Route],
+    name: &str,
+    dst: IpAddr,
+    kernel_source: impl Fn(IpAddr) -> Option<IpAddr>,
+) -> Result<IpAddr, EgressError> {
+    let iface = interfaces
+        .iter()
+        .find(|i| i.name == name)
+        .ok_or_else(|| EgressError::NoSuchInterface(name.to_string()))?;
+    if !iface.is_up {
+        return Err(EgressError::InterfaceDown(name.to_string()));
+    }
+    let src = source_ip_for(iface, dst).ok_or_else(|| EgressError::NoAddress {
+        iface: name.to_string(),
+        dst,
+    })?;
+    let route = route::lookup(routes, dst);
+    if route.is_some_and(|r| r.iface == name) {
+        return Ok(src);
+    }
+    if let Some(picked) = kernel_source(dst).filter(|k| iface.addrs.iter().any(|n| n.addr == *k)) {
+        return Ok(picked);
+    }
+    match route {
+        Some(route) => Err(EgressError::RouteMismatch {
+            iface: name.to_string(),
+            dst,
+            route_iface: route.iface.clone(),
+        }),
+        None => Err(EgressError::NoRoute(dst)),
+    }
+}
+
+/// Source address checked by [`verify_source`]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct VerifiedSource {
+    /// Requested source
+    pub src: IpAddr,
+    /// Why `src` may not get replies back, when it may not
+    pub mismatch: Option<EgressError>,
+    /// Address of the routed interface to use instead, when there is a
+    /// mismatch and the interface has one
+    pub corrected: Option<IpAddr>,
+}
+
+/// Check that `src` is on the interface traffic to `dst` leaves through,
+/// as [`egress_source`] decides it.
+///
+/// A source taken from the default interface is wrong on a multi-homed
+/// host whose route to `dst` prefers another interface: the probes leave
+/// with an address replies will not come back to, and every probe times
+/// out. The mismatch is returned along with an address of the routed
+/// interface. An unspecified `src` is left for the kernel to choose, and
+/// without routes of the family, as on platforms the table cannot be read
+/// on, there is nothing to check against.
+pub fn verify_source(
+    interfaces: &[Interface],
+    routes: &[Route],
+    src: IpAddr,
+    dst: IpAddr,
+) -> Result<VerifiedSource, EgressError> {
+    verify_source_with(interfaces, routes, src, dst, |dst| {
+        route::kernel_source(dst).ok()
+    })
+}
+
+fn verify_source_with(
+    interfaces: &[Interface],
+    routes: &[Route],
+    src: IpAddr,
+    dst: IpAddr,
+    kernel_source: impl Fn(IpAddr) -> Option<IpAddr>,
+) -> Result<VerifiedSource, EgressError> {
+    let unchecked = VerifiedSource {
+        src,
+        mismatch: None,
+        corrected: None,
+    };
+    let has_routes = routes
+        .iter()
+        .any(|r| r.destination.addr.is_ipv6() == dst.is_ipv6());
+    if src.is_unspecified() || !has_routes {
+        return Ok(unchecked);
+    }
+    let owner = interfaces
+        .iter()
+        .find(|i| i.addrs.iter().any(|net| net.addr == src))
+        .ok_or(EgressError::SourceNotLocal(src))?;
+    let route_iface = match egress_source_with(interfaces, routes, &owner.name, dst, kernel_source)
+    {
+        Ok(_) => return Ok(unchecked),
+        Err(EgressError::RouteMismatch { route_iface, .. }) => route_iface,
+        Err(e) => return Err(e),
+    };
+    let corrected = interfaces
+        .iter()
+        .find(|i| i.name == route_iface && i.is_up)
+        .and_then(|i| source_ip_for(i, dst));
+    Ok(VerifiedSource {
+        src,
+        mismatch: Some(EgressError::SourceMismatch {
+            src,
+            src_iface: owner.name.clone(),
+            dst,
+            route_iface,
+        }),
+        corrected,
+    })
+}
+
+/// Prober that verifies its source against the route to each destination
+/// before every probe.
+///
+/// `make_prober` builds a prober bound to the given source address, as in
+/// [`crate::ping::compare::compare_interfaces`]. One prober is built per
+/// source and kept for the following probes. Each distinct mismatch is kept
+/// for [`RouteCheckedProber::mismatches`]; probes still go out from the
+/// requested source unless [`RouteCheckedProber::with_correction`] is set,
+/// since policy routing can make a mismatch in the main table harmless.
+pub struct RouteCheckedProber<P, F> {
+    interfaces: Vec<Interface>,
+    routes: Vec<Route>,
+    src: IpAddr,
+    make_prober: F,
+    correct: bool,
+    /// Source the kernel picks for a destination, see [`route::kernel_source`]
+    kernel_source: fn(IpAddr) -> Option<IpAddr>,
+    probers: Mutex<HashMap<IpAddr, Arc<P>>>,
+    mismatches: Mutex<Vec<EgressError>>,
+}
+
+impl<P, F> RouteCheckedProber<P, F>
+where
+    F: Fn(IpAddr) -> P,
+{
+    pub fn new(
+        interfaces: Vec<Interface>,
+        routes: Vec<Route>,
+        src: IpAddr,
+        make_prober: F,
+    ) -> RouteCheckedProber<P, F> {
+        RouteCheckedProber {
+            interfaces,
+            routes,
+            src,
+            make_prober,
+            correct: false,
+            kernel_source: |dst| route::kernel_source(dst).ok(),
+            probers: Mutex::new(HashMap::new()),
+            mismatches: Mutex::new(Vec::new()),
+        }
+    }
+    /// Send from an address of the routed interface when the requested
+    /// source is on another one. Fails the probe when the routed interface
+    /// has no usable address.
+    pub fn with_correction(mut self, correct: bool) -> RouteCheckedProber<P, F> {
+        self.correct = correct;
+        self
+    }
+    /// Mismatches found so far, one per destination route
+    pub fn mismatches(&self) -> Vec<EgressError> {
+        self.mismatches.lock().unwrap().clone()
+    }
+    /// Prober bound to `src`, built on first use
+    fn prober_for(&self, src: IpAddr) -> Arc<P> {
+        let mut probers = self.probers.lock().unwrap();
+        probers
+            .entry(src)
+            .or_insert_with(|| Arc::new((self.make_prober)(src)))
+            .clone()
+    }
+    /// Sum of `f` over the probers built so far that report it
+    fn sum_probers(&self, f: impl Fn(&P) -> Option<u64>) -> Option<u64> {
+        let probers = self.probers.lock().unwrap();
+        probers.values().filter_map(|p| f(p)).reduce(|a, b| a + b)
+    }
+}
+
+impl<P, F> Prober for RouteCheckedProber<P, F>
+where
+    P: Prober + Send,
+    F: Fn(IpAddr) -> P + Sync,
+{
+    fn probe(&self, dst: IpAddr, seq: u16, timeout: Duration) -> Result<ProbeReply, ProbeError> {
+        let unusable = |e: EgressError| {
+            ProbeError::Io(io::Error::new(
+                io::ErrorKind::AddrNotAvailable,
+                e.to_string(),
+            ))
+        };
+        let verified = verify_source_with(
+            &self.interfaces,
+            &self.routes,
+            self.src,
+            dst,
+            self.kernel_source,
+        )
+        .map_err(unusable)?;
+        let mut src = verified.src;
+        if let Some(mismatch) = verified.mismatch {
+            {
+                let mut mismatches = self.mismatches.lock().unwrap();
+                if !mismatches.contains(&mismatch) {
+                    mismatches.push(mismatch.clone());
+                }
+            }
+            if self.correct {
+                src = verified.corrected.ok_or_else(|| unusable(mismatch))?;
+            }
+        }
+        self.prober_for(src).probe(dst, seq, timeout)
+    }
+    fn dropped_packets(&self) -> Option<u64> {
+        self.sum_probers(P::dropped_packets)
+    }
+    fn duplicate_replies(&self) -> Option<u64> {
+        self.sum_probers(P::duplicate_replies)
+    }
+    fn duplicates_of(&self, dst: IpAddr, seq: u16) -> Option<u32> {
+        let probers = self.probers.lock().unwrap();
+        probers
+            .values()
+            .filter_map(|p| p.duplicates_of(dst, seq))
+            .reduce(|a, b| a + b)
+    }
+    fn reordered_replies(&self) -> Option<u64> {
+        self.sum_probers(P::reordered_replies)
+    }
+    fn check_family(&self, ipv6: bool) -> io::Result<()> {
+        if !self.src.is_unspecified() && self.src.is_ipv6() != ipv6 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "Source {} cannot reach {} targets",
+                    self.src,
+                    if ipv6 { "IPv6" } else { "IPv4" }
+                ),
+            ));
+        }
+        self.prober_for(self.src).check_family(ipv6)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::net::ipnet::IpNet;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    fn iface(index: u32, name: &str, addr: &str, prefix_len: u8) -> Interface {
+        let mut iface = Interface::new(index, name);
+        iface.is_up = true;
+        iface.addrs = vec![IpNet::new(addr.parse().unwrap(), prefix_len)];
+        iface
+    }
+
+    fn route(dst: &str, prefix_len: u8, iface: &str) -> Route {
+        Route {
+            destination: IpNet::new(dst.parse().unwrap(), prefix_len),
+            gateway: None,
+            iface: iface.to_string(),
+            metric: 0,
+        }
+    }
+
+    #[test]
+    fn split_tunnel_destination_outside_vpn_errors() {
+        let mut eth0 = iface(2, "eth0", "192.168.1.20", 24);
+        eth0.mac = Some([2, 0, 0, 0, 0, 1]);
+        let interfaces = vec![eth0, iface(5, "wg0", "10.8.0.2", 24)];
+        assert!(is_tunnel(&interfaces[1]));
+        assert!(!is_tunnel(&interfaces[0]));
+        // Only the corporate range goes through the tunnel
+        let routes = vec![route("0.0.0.0", 0, "eth0"), route("10.0.0.0", 8, "wg0")];
+
+        let inside = "10.20.0.5".parse().unwrap();
+        assert_eq!(
+            egress_source(&interfaces, &routes, "wg0", inside),
+            Ok("10.8.0.2".parse().unwrap())
+        );
+        let outside: IpAddr = "203.0.113.9".parse().unwrap();
+        let err = egress_source_with(&interfaces, &routes, "wg0", outside, |_| None).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Traffic to 203.0.113.9 is routed via eth0, not wg0"
+        );
+        // Policy routing sends everything through the tunnel, whatever the
+        // main table says
+        let policy = |_| Some("10.8.0.2".parse().unwrap());
+        assert_eq!(
+            egress_source_with(&interfaces, &routes, "wg0", outside, policy),
+            Ok("10.8.0.2".parse().unwrap())
+        );
+        assert_eq!(
+            egress_source(&interfaces, &routes, "tun9", inside),
+            Err(EgressError::NoSuchInterface("tun9".to_string()))
+        );
+        // IPv6 routes are checked the same way
+        let interfaces = vec![iface(6, "wg1", "fd00:8::2", 64)];
+        let routes = vec![route("fd00::", 8, "wg1")];
+        assert_eq!(
+            egress_source_with(
+                &interfaces,
+                &routes,
+                "wg1",
+                "fd00:1::5".parse().unwrap(),
+                |_| None
+            ),
+            Ok("fd00:8::2".parse().unwrap())
+        );
+        let v6: IpAddr = "2001:db8::9".parse().unwrap();
+        assert_eq!(
+            egress_source_with(&interfaces, &routes, "wg1", v6, |_| None),
+            Err(EgressError::NoRoute(v6))
+        );
+    }
+
+    #[test]
+    fn source_on_wrong_interface_is_corrected() {
+        let interfaces = vec![
+            iface(2, "eth0", "192.168.1.20", 24),
+            iface(3, "wlan0", "172.16.0.7", 16),
+        ];
+        // The default route prefers Wi-Fi, but the source came from eth0
+        let routes = vec![
+            route("0.0.0.0", 0, "wlan0"),
+            route("192.168.1.0", 24, "eth0"),
+        ];
+        let src: IpAddr = "192.168.1.20".parse().unwrap();
+        let dst: IpAddr = "198.51.100.4".parse().unwrap();
+
+        let no_policy = |_| None;
+        let verified = verify_source_with(&interfaces, &routes, src, dst, no_policy).unwrap();
+        assert_eq!(verified.src, src);
+        assert_eq!(verified.corrected, Some("172.16.0.7".parse().unwrap()));
+        let mismatch = verified.mismatch.unwrap();
+        assert_eq!(
+            mismatch.to_string(),
+            "Source 192.168.1.20 is on eth0, but traffic to 198.51.100.4 is routed via wlan0"
+        );
+        // Policy routing keeps traffic from eth0's address on eth0
+        let policy = |_| Some(src);
+        let verified = verify_source_with(&interfaces, &routes, src, dst, policy).unwrap();
+        assert_eq!(verified.mismatch, None);
+
+        let local: IpAddr = "192.168.1.1".parse().unwrap();
+        assert_eq!(
+            verify_source_with(&interfaces, &routes, src, local, no_policy),
+            Ok(VerifiedSource {
+                src,
+                mismatch: None,
+                corrected: None,
+            })
+        );
+        let foreign: IpAddr = "10.9.9.9".parse().unwrap();
+        assert_eq!(
+            verify_source_with(&interfaces, &routes, foreign, dst, no_policy),
+            Err(EgressError::SourceNotLocal(foreign))
+        );
+        // Nothing to check IPv6 against
+        let v6: IpAddr = "2001:db8::1".parse().unwrap();
+        let verified = verify_source_with(&interfaces, &routes, v6, v6, no_policy).unwrap();
+        assert_eq!(verified.mismatch, None);
+
+        // Without a usable address on the routed interface it cannot be fixed
+        let mut down = interfaces.clone();
+        down[1].is_up = false;
+        let verified = verify_source_with(&down, &routes, src, dst, no_policy).unwrap();
+        assert_eq!(verified.mismatch, Some(mismatch.clone()));
+        assert_eq!(verified.corrected, None);
+
+        // Warned about, but sent from the requested source
+        let bound = Mutex::new(Vec::new());
+        let built = AtomicUsize::new(0);
+        let make = |src| {
+            built.fetch_add(1, Ordering::Relaxed);
+            Bound { src, log: &bound }
+        };
+        let mut prober = RouteCheckedProber::new(interfaces.clone(), routes.clone(), src, make);
+        prober.kernel_source = no_policy;
+        for seq in 0..3 {
+            prober.probe(dst, seq, Duration::from_millis(10)).unwrap();
+        }
+        assert_eq!(prober.mismatches(), vec![mismatch.clone()]);
+        assert_eq!(built.load(Ordering::Relaxed), 1);
+        prober.check_family(false).unwrap();
+        assert!(prober.check_family(true).is_err());
+        assert_eq!(*bound.lock().unwrap(), [src; 3]);
+
+        // Corrected on request, one prober per source
+        bound.lock().unwrap().clear();
+        built.store(0, Ordering::Relaxed);
+        let mut prober =
+            RouteCheckedProber::new(interfaces, routes, src, make).with_correction(true);
+        prober.kernel_source = no_policy;
+        for seq in 0..3 {
+            prober.probe(dst, seq, Duration::from_millis(10)).unwrap();
+        }
+        prober.probe(local, 3, Duration::from_millis(10)).unwrap();
+        assert_eq!(prober.mismatches(), vec![mismatch]);
+        assert_eq!(built.load(Ordering::Relaxed), 2);
+        let bound = bound.into_inner().unwrap();
+        assert_eq!(&bound[..3], &["172.16.0.7".parse::<IpAddr>().unwrap(); 3]);
+        assert_eq!(bound[3], src);
+    }
+
+    /// Records the source address each probe was sent from
+    struct Bound<'a> {
+        src: IpAddr,
+        log: &'a Mutex<Vec<IpAddr>>,
+    }
+
+    impl Prober for Bound<'_> {
+        fn probe(
+            &self,
+            dst: IpAddr,
+            _seq: u16,
+            _timeout: Duration,
+        ) -> Result<ProbeReply, ProbeError> {
+            self.log.lock().unwrap().push(self.src);
+            Ok(ProbeReply {
+                responder: dst,
+                rtt: Duration::from_millis(1),
+                ttl: None,
+            })
+        }
+    }
+}
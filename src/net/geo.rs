@@ -0,0 +1,240 @@
+//! Geolocation and ASN lookups behind a shared cache
+use crate::trace::aspath::AsnLookup;
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::{Duration, Instant};
+use ts_rs::TS;
+
+/// Default number of cached addresses
+pub const DEFAULT_GEO_CACHE_CAPACITY: usize = 1024;
+/// Default time a cached entry stays valid
+pub const DEFAULT_GEO_CACHE_TTL: Duration = Duration::from_secs(6 * 60 * 60);
+
+/// Location and origin AS of an address
+#[derive(Clone, Debug, Default, PartialEq, Eq, TS)]
+pub struct GeoInfo {
+    /// ISO 3166-1 alpha-2 code
+    pub country_code: Option<String>,
+    pub country: Option<String>,
+    pub city: Option<String>,
+    pub asn: Option<u32>,
+    pub as_name: Option<String>,
+}
+
+/// Backend answering geolocation queries, e.g. a web API or a local database
+pub trait GeoLookup: Sync {
+    fn lookup(&self, ip: IpAddr) -> Result<GeoInfo, String>;
+}
+
+impl<L: GeoLookup + ?Sized> GeoLookup for &L {
+    fn lookup(&self, ip: IpAddr) -> Result<GeoInfo, String> {
+        (**self).lookup(ip)
+    }
+}
+
+#[derive(Debug)]
+struct Entry {
+    info: GeoInfo,
+    stored: Instant,
+    /// Value of `Entries::clock` at the last use
+    used: u64,
+}
+
+#[derive(Debug, Default)]
+struct Entries {
+    map: HashMap<IpAddr, Entry>,
+    clock: u64,
+}
+
+/// Entries behind every [`GeoCache::shared`]
+static SHARED_ENTRIES: OnceLock<Arc<Mutex<Entries>>> = OnceLock::new();
+
+fn shared_entries() -> &'static Arc<Mutex<Entries>> {
+    SHARED_ENTRIES.get_or_init(Default::default)
+}
+
+/// Drop every entry of the process-wide cache
+pub fn clear_geo_cache() {
+    shared_entries().lock().unwrap().map.clear();
+}
+
+/// Bounded LRU cache in front of a [`GeoLookup`]. Failed lookups are not
+/// cached.
+#[derive(Debug)]
+pub struct GeoCache<L> {
+    backend: L,
+    capacity: usize,
+    ttl: Duration,
+    entries: Arc<Mutex<Entries>>,
+}
+
+impl<L: GeoLookup> GeoCache<L> {
+    /// Cache of its own
+    pub fn new(backend: L) -> GeoCache<L> {
+        GeoCache {
+            backend,
+            capacity: DEFAULT_GEO_CACHE_CAPACITY,
+            ttl: DEFAULT_GEO_CACHE_TTL,
+            entries: Default::default(),
+        }
+    }
+    /// Cache over the process-wide entries, shared by traceroute annotation,
+    /// the public IP and speedtest server details
+    pub fn shared(backend: L) -> GeoCache<L> {
+        GeoCache {
+            entries: shared_entries().clone(),
+            ..GeoCache::new(backend)
+        }
+    }
+    pub fn with_capacity(mut self, capacity: usize) -> GeoCache<L> {
+        self.capacity = capacity.max(1);
+        self
+    }
+    pub fn with_ttl(mut self, ttl: Duration) -> GeoCache<L> {
+        self.ttl = ttl;
+        self
+    }
+    pub fn len(&self) -> usize {
+        self.entries.lock().unwrap().map.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+    /// Drop every cached entry
+    pub fn clear(&self) {
+        self.entries.lock().unwrap().map.clear();
+    }
+    /// Cached entry if still fresh, otherwise a backend lookup. The lock is
+    /// not held during the backend call, so concurrent misses on the same
+    /// address may both reach the backend.
+    pub fn lookup(&self, ip: IpAddr) -> Result<GeoInfo, String> {
+        if let Some(info) = self.cached(ip) {
+            return Ok(info);
+        }
+        let info = self.backend.lookup(ip)?;
+        self.insert(ip, info.clone());
+        Ok(info)
+    }
+    fn cached(&self, ip: IpAddr) -> Option<GeoInfo> {
+        let mut entries = self.entries.lock().unwrap();
+        entries.clock += 1;
+        let clock = entries.clock;
+        let entry = entries.map.get_mut(&ip)?;
+        if entry.stored.elapsed() >= self.ttl {
+            entries.map.remove(&ip);
+            return None;
+        }
+        entry.used = clock;
+        Some(entry.info.clone())
+    }
+    fn insert(&self, ip: IpAddr, info: GeoInfo) {
+        let mut entries = self.entries.lock().unwrap();
+        entries.clock += 1;
+        let used = entries.clock;
+        if !entries.map.contains_key(&ip) && entries.map.len() >= self.capacity {
+            let oldest = entries
+                .map
+                .iter()
+                .min_by_key(|(_, e)| e.used)
+                .map(|(ip, _)| *ip);
+            if let Some(oldest) = oldest {
+                entries.map.remove(&oldest);
+            }
+        }
+        entries.map.insert(
+            ip,
+            Entry {
+                info,
+                stored: Instant::now(),
+                used,
+            },
+        );
+    }
+}
+
+impl<L: GeoLookup> AsnLookup for GeoCache<L> {
+    fn asn(&self, ip: IpAddr) -> Option<u32> {
+        self.lookup(ip).ok()?.asn
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    /// Counts calls; every address is in AS 64500 + last octet
+    #[derive(Default)]
+    struct Counting(AtomicUsize);
+
+    impl GeoLookup for Counting {
+        fn lookup(&self, ip: IpAddr) -> Result<GeoInfo, String> {
+            self.0.fetch_add(1, Ordering::SeqCst);
+            let IpAddr::V4(v4) = ip else {
+                return Err("no data".to_string());
+            };
+            Ok(GeoInfo {
+                country_code: Some("JP".to_string()),
+                asn: Some(64500 + v4.octets()[3] as u32),
+                ..Default::default()
+            })
+        }
+    }
+
+    fn v4(n: u8) -> IpAddr {
+        IpAddr::from([198, 51, 100, n])
+    }
+
+    #[test]
+    fn second_lookup_is_served_from_cache() {
+        let cache = GeoCache::new(Counting::default());
+        let first = cache.lookup(v4(1)).unwrap();
+        assert_eq!(cache.lookup(v4(1)).unwrap(), first);
+        assert_eq!(cache.asn(v4(1)), Some(64501));
+        assert_eq!(cache.backend.0.load(Ordering::SeqCst), 1);
+
+        assert!(cache.lookup("2001:db8::1".parse().unwrap()).is_err());
+        assert_eq!(cache.len(), 1);
+        cache.clear();
+        cache.lookup(v4(1)).unwrap();
+        assert_eq!(cache.backend.0.load(Ordering::SeqCst), 3);
+    }
+
+    #[test]
+    fn evicts_least_recently_used_and_expired() {
+        let cache = GeoCache::new(Counting::default()).with_capacity(2);
+        cache.lookup(v4(1)).unwrap();
+        cache.lookup(v4(2)).unwrap();
+        cache.lookup(v4(1)).unwrap();
+        // Evicts 2, the least recently used
+        cache.lookup(v4(3)).unwrap();
+        assert_eq!(cache.backend.0.load(Ordering::SeqCst), 3);
+        cache.lookup(v4(1)).unwrap();
+        assert_eq!(cache.backend.0.load(Ordering::SeqCst), 3);
+        cache.lookup(v4(2)).unwrap();
+        assert_eq!(cache.backend.0.load(Ordering::SeqCst), 4);
+
+        let expiring = GeoCache::new(Counting::default()).with_ttl(Duration::ZERO);
+        expiring.lookup(v4(1)).unwrap();
+        expiring.lookup(v4(1)).unwrap();
+        assert_eq!(expiring.backend.0.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn shared_caches_see_each_others_lookups() {
+        let backend = Counting::default();
+        let ip = IpAddr::from([198, 51, 100, 201]);
+        GeoCache::shared(&backend).lookup(ip).unwrap();
+        let other = GeoCache::shared(&backend);
+        assert_eq!(other.asn(ip), Some(64701));
+        assert_eq!(backend.0.load(Ordering::SeqCst), 1);
+        // A private cache does not see the shared entries
+        GeoCache::new(&backend).lookup(ip).unwrap();
+        assert_eq!(backend.0.load(Ordering::SeqCst), 2);
+
+        clear_geo_cache();
+        other.lookup(ip).unwrap();
+        assert_eq!(backend.0.load(Ordering::SeqCst), 3);
+    }
+}
@@ -0,0 +1,242 @@
+//! One-shot health view of the default gateways and DNS servers
+use super::interface::Interface;
+use super::route::{default_gateways, Route};
+use super::scope::is_link_local_v6;
+use crate::cancel::CancellationToken;
+use crate::dns::health::configured_servers;
+use crate::ping::result::PingStat;
+use crate::ping::session::ping;
+use crate::ping::{PingSetting, Prober};
+use crate::pool::map_concurrent;
+use crate::trace::{traceroute, HopProber, TraceSetting};
+use std::net::IpAddr;
+use ts_rs::TS;
+
+/// What a dashboard target is to this host
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum InfraRole {
+    Gateway,
+    Dns,
+}
+
+/// Health of one target
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum InfraStatus {
+    /// Every ping answered
+    Up,
+    /// Some pings lost
+    Degraded,
+    /// No ping answered
+    Down,
+}
+
+/// Probing depth of the dashboard. Traces stay short since the targets
+/// are expected within a few hops.
+#[derive(Clone, Debug, PartialEq, Eq, TS)]
+pub struct InfraSetting {
+    pub ping_count: u32,
+    #[ts(type = "number")]
+    pub ping_interval_ms: u64,
+    pub max_hop: u8,
+    #[ts(type = "number")]
+    pub timeout_ms: u64,
+}
+
+impl Default for InfraSetting {
+    fn default() -> Self {
+        InfraSetting {
+            ping_count: 3,
+            ping_interval_ms: 200,
+            max_hop: 4,
+            timeout_ms: 1000,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct InfraEntry {
+    pub ip: IpAddr,
+    /// A DNS server that is also the gateway is listed for both roles
+    pub roles: Vec<InfraRole>,
+    pub status: InfraStatus,
+    pub ping: PingStat,
+    /// Hops to the target, `None` if the short trace did not reach it
+    pub hops: Option<u8>,
+    /// Routers answering on the way, in TTL order
+    pub path: Vec<Option<IpAddr>>,
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct InfraDashboard {
+    pub entries: Vec<InfraEntry>,
+    /// Set when no default route has a gateway
+    pub no_gateway: bool,
+    /// Set when no interface has a DNS server configured
+    pub no_dns: bool,
+    pub cancelled: bool,
+}
+
+impl InfraDashboard {
+    /// Whether there is a gateway and DNS and every target is up
+    pub fn healthy(&self) -> bool {
+        !self.no_gateway && !self.no_dns && self.entries.iter().all(|e| e.status == InfraStatus::Up)
+    }
+}
+
+/// Default gateways followed by DNS servers, each address once.
+/// Link-local IPv6 gateways are left out: the probers are shared by every
+/// target, while each such gateway needs the scope of its own interface.
+pub fn infra_targets(interfaces: &[Interface], routes: &[Route]) -> Vec<(IpAddr, Vec<InfraRole>)> {
+    let mut targets: Vec<(IpAddr, Vec<InfraRole>)> = default_gateways(routes)
+        .into_iter()
+        .filter(|ip| !matches!(ip, IpAddr::V6(v6) if is_link_local_v6(v6)))
+        .map(|ip| (ip, vec![InfraRole::Gateway]))
+        .collect();
+    for (server, _) in configured_servers(interfaces) {
+        match targets.iter_mut().find(|(ip, _)| *ip == server.ip()) {
+            Some((_, roles)) => roles.push(InfraRole::Dns),
+            None => targets.push((server.ip(), vec![InfraRole::Dns])),
+        }
+    }
+    targets
+}
+
+/// Ping and trace every gateway and DNS server concurrently
+pub fn infra_dashboard<P: Prober, H: HopProber>(
+    prober: &P,
+    hop_prober: &H,
+    interfaces: &[Interface],
+    routes: &[Route],
+    setting: &InfraSetting,
+    token: &CancellationToken,
+) -> InfraDashboard {
+    let targets = infra_targets(interfaces, routes);
+    let entries = map_concurrent(&targets, targets.len(), token, |(ip, roles)| {
+        let mut ping_setting = PingSetting::new(*ip);
+        ping_setting.count = setting.ping_count;
+        ping_setting.timeout_ms = setting.timeout_ms;
+        ping_setting.interval_ms = setting.ping_interval_ms;
+        let done = ping(prober, &ping_setting, token, |_| {});
+        let mut trace_setting = TraceSetting::new(*ip);
+        trace_setting.max_hop = setting.max_hop;
+        trace_setting.tries_per_hop = 1;
+        trace_setting.timeout_max_ms = setting.timeout_ms;
+        let trace = traceroute(hop_prober, &trace_setting, token, |_| {});
+        let status = match done.stat.received {
+            0 => InfraStatus::Down,
+            n if n < done.stat.sent => InfraStatus::Degraded,
+            _ => InfraStatus::Up,
+        };
+        InfraEntry {
+            ip: *ip,
+            roles: roles.clone(),
+            status,
+            ping: done.stat,
+            hops: trace.reached.then_some(trace.hops.len() as u8),
+            path: trace.hops.iter().map(|h| h.responder).collect(),
+        }
+    });
+    InfraDashboard {
+        entries: entries.into_iter().flatten().collect(),
+        no_gateway: !targets.iter().any(|(_, r)| r.contains(&InfraRole::Gateway)),
+        no_dns: !targets.iter().any(|(_, r)| r.contains(&InfraRole::Dns)),
+        cancelled: token.is_cancelled(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::net::ipnet::IpNet;
+    use crate::ping::{ProbeError, ProbeReply};
+    use crate::trace::HopReply;
+    use std::time::Duration;
+
+    /// Everything but 192.0.2.53 answers
+    struct Lan;
+
+    impl Prober for Lan {
+        fn probe(&self, dst: IpAddr, _seq: u16, _t: Duration) -> Result<ProbeReply, ProbeError> {
+            if dst == "192.0.2.53".parse::<IpAddr>().unwrap() {
+                return Err(ProbeError::Timeout);
+            }
+            Ok(ProbeReply {
+                responder: dst,
+                rtt: Duration::from_millis(2),
+                ttl: Some(64),
+            })
+        }
+    }
+
+    impl HopProber for Lan {
+        fn probe_hop(&self, dst: IpAddr, ttl: u8, _t: Duration) -> Result<HopReply, ProbeError> {
+            let gateway: IpAddr = "10.0.0.1".parse().unwrap();
+            let reached = dst == gateway || ttl == 2;
+            Ok(HopReply {
+                responder: if ttl == 1 { gateway } else { dst },
+                rtt: Duration::from_millis(ttl as u64),
+                reached,
+                reply_ttl: None,
+            })
+        }
+    }
+
+    fn eth0(dns: &[&str]) -> Interface {
+        let mut iface = Interface::new(2, "eth0");
+        iface.is_up = true;
+        iface.dns_servers = dns.iter().map(|s| s.parse().unwrap()).collect();
+        iface
+    }
+
+    fn default_route() -> Route {
+        Route {
+            destination: IpNet::new("0.0.0.0".parse().unwrap(), 0),
+            gateway: Some("10.0.0.1".parse().unwrap()),
+            iface: "eth0".to_string(),
+            metric: 0,
+        }
+    }
+
+    #[test]
+    fn entry_per_gateway_and_dns_server() {
+        let interfaces = [eth0(&["10.0.0.1", "1.1.1.1", "192.0.2.53"])];
+        let token = CancellationToken::new();
+        let setting = InfraSetting {
+            ping_count: 2,
+            ping_interval_ms: 0,
+            ..Default::default()
+        };
+        let dash = infra_dashboard(
+            &Lan,
+            &Lan,
+            &interfaces,
+            &[default_route()],
+            &setting,
+            &token,
+        );
+        let summary: Vec<(String, Vec<InfraRole>, InfraStatus, Option<u8>)> = dash
+            .entries
+            .iter()
+            .map(|e| (e.ip.to_string(), e.roles.clone(), e.status, e.hops))
+            .collect();
+        use InfraRole::*;
+        assert_eq!(
+            summary,
+            vec![
+                (
+                    "10.0.0.1".into(),
+                    vec![Gateway, Dns],
+                    InfraStatus::Up,
+                    Some(1)
+                ),
+                ("1.1.1.1".into(), vec![Dns], InfraStatus::Up, Some(2)),
+                ("192.0.2.53".into(), vec![Dns], InfraStatus::Down, Some(2)),
+            ]
+        );
+        assert!(!dash.healthy());
+
+        let dash = infra_dashboard(&Lan, &Lan, &[eth0(&[])], &[], &setting, &token);
+        assert!(dash.entries.is_empty());
+        assert!(dash.no_gateway && dash.no_dns);
+    }
+}
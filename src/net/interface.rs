@@ -0,0 +1,370 @@
+//! Network interfaces
+use super::ipnet::IpNet;
+use std::io;
+use std::net::{IpAddr, Ipv6Addr};
+
+/// Network interface and its addresses
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Interface {
+    pub index: u32,
+    pub name: String,
+    pub mac: Option<[u8; 6]>,
+    pub is_up: bool,
+    pub is_loopback: bool,
+    pub mtu: Option<u32>,
+    pub addrs: Vec<IpNet>,
+    /// DNS servers used for lookups through this interface
+    pub dns_servers: Vec<IpAddr>,
+    /// OS flags of IPv6 addresses in `addrs`, where the OS reports them
+    pub ipv6_flags: Vec<(Ipv6Addr, Ipv6AddrFlags)>,
+}
+
+/// Kind of an IPv6 address, from its bits and the OS flags
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Ipv6AddrKind {
+    /// fe80::/10
+    LinkLocal,
+    /// fc00::/7
+    UniqueLocal,
+    /// Global address kept across reconnects, e.g. EUI-64 or stable privacy
+    GlobalStable,
+    /// Rotating privacy address (RFC 8981)
+    GlobalTemporary,
+    /// Past its preferred lifetime; kept only for existing connections
+    Deprecated,
+    Other,
+}
+
+impl Ipv6AddrKind {
+    /// Rank as a probe source, lower is better
+    fn preference(self) -> u8 {
+        match self {
+            Ipv6AddrKind::GlobalStable => 0,
+            Ipv6AddrKind::UniqueLocal => 1,
+            Ipv6AddrKind::GlobalTemporary => 2,
+            Ipv6AddrKind::LinkLocal => 3,
+            Ipv6AddrKind::Deprecated => 4,
+            Ipv6AddrKind::Other => 5,
+        }
+    }
+}
+
+/// IPv6 address flags reported by the OS
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Ipv6AddrFlags {
+    pub temporary: bool,
+    pub deprecated: bool,
+}
+
+/// Classify `addr` by prefix, refined for global addresses with `flags`
+pub fn classify_ipv6(addr: &Ipv6Addr, flags: Ipv6AddrFlags) -> Ipv6AddrKind {
+    let first = addr.segments()[0];
+    if first & 0xffc0 == 0xfe80 {
+        Ipv6AddrKind::LinkLocal
+    } else if first & 0xfe00 == 0xfc00 {
+        Ipv6AddrKind::UniqueLocal
+    } else if first & 0xe000 == 0x2000 {
+        if flags.deprecated {
+            Ipv6AddrKind::Deprecated
+        } else if flags.temporary {
+            Ipv6AddrKind::GlobalTemporary
+        } else {
+            Ipv6AddrKind::GlobalStable
+        }
+    } else {
+        Ipv6AddrKind::Other
+    }
+}
+
+impl Interface {
+    pub fn new(index: u32, name: &str) -> Interface {
+        Interface {
+            index,
+            name: name.to_string(),
+            mac: None,
+            is_up: false,
+            is_loopback: false,
+            mtu: None,
+            addrs: Vec::new(),
+            dns_servers: Vec::new(),
+            ipv6_flags: Vec::new(),
+        }
+    }
+    /// Kind of `addr`, using the OS flags when known
+    pub fn ipv6_kind(&self, addr: &Ipv6Addr) -> Ipv6AddrKind {
+        let flags = self
+            .ipv6_flags
+            .iter()
+            .find(|(a, _)| a == addr)
+            .map_or(Ipv6AddrFlags::default(), |(_, f)| *f);
+        classify_ipv6(addr, flags)
+    }
+    /// IPv6 addresses with their kind, best probe source first: stable
+    /// global, unique local, temporary, link-local, deprecated
+    pub fn ipv6_addrs(&self) -> Vec<(Ipv6Addr, Ipv6AddrKind)> {
+        let mut addrs: Vec<(Ipv6Addr, Ipv6AddrKind)> = self
+            .addrs
+            .iter()
+            .filter_map(|net| match net.addr {
+                IpAddr::V6(v6) if !v6.is_loopback() => Some((v6, self.ipv6_kind(&v6))),
+                _ => None,
+            })
+            .collect();
+        addrs.sort_by_key(|(_, kind)| kind.preference());
+        addrs
+    }
+}
+
+/// Source of interface snapshots
+pub trait InterfaceSource: Sync {
+    fn interfaces(&self) -> io::Result<Vec<Interface>>;
+}
+
+/// Interfaces reported by the operating system
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SystemInterfaces;
+
+impl InterfaceSource for SystemInterfaces {
+    fn interfaces(&self) -> io::Result<Vec<Interface>> {
+        get_interfaces()
+    }
+}
+
+/// List network interfaces of this host
+#[cfg(unix)]
+pub fn get_interfaces() -> io::Result<Vec<Interface>> {
+    use super::ipnet::netmask_prefix_len;
+    use std::ffi::CStr;
+    use std::net::Ipv4Addr;
+
+    unsafe fn sockaddr_ip(sa: *const libc::sockaddr) -> Option<IpAddr> {
+        if sa.is_null() {
+            return None;
+        }
+        match (*sa).sa_family as i32 {
+            libc::AF_INET => {
+                let sin = &*(sa as *const libc::sockaddr_in);
+                Some(IpAddr::V4(Ipv4Addr::from(u32::from_be(
+                    sin.sin_addr.s_addr,
+                ))))
+            }
+            libc::AF_INET6 => {
+                let sin6 = &*(sa as *const libc::sockaddr_in6);
+                Some(IpAddr::V6(Ipv6Addr::from(sin6.sin6_addr.s6_addr)))
+            }
+            _ => None,
+        }
+    }
+
+    let mut ifap: *mut libc::ifaddrs = std::ptr::null_mut();
+    if unsafe { libc::getifaddrs(&mut ifap) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    let mut interfaces: Vec<Interface> = Vec::new();
+    let mut cur = ifap;
+    while !cur.is_null() {
+        let ifa = unsafe { &*cur };
+        cur = ifa.ifa_next;
+        let name = unsafe { CStr::from_ptr(ifa.ifa_name) }
+            .to_string_lossy()
+            .into_owned();
+        let pos = match interfaces.iter().position(|i| i.name == name) {
+            Some(pos) => pos,
+            None => {
+                let index = unsafe { libc::if_nametoindex(ifa.ifa_name) };
+                let mut iface = Interface::new(index, &name);
+                iface.is_up = ifa.ifa_flags & libc::IFF_UP as u32 != 0;
+                iface.is_loopback = ifa.ifa_flags & libc::IFF_LOOPBACK as u32 != 0;
+                iface.mtu = read_mtu(&name);
+                interfaces.push(iface);
+                interfaces.len() - 1
+            }
+        };
+        if let Some(mac) = unsafe { link_mac(ifa.ifa_addr) } {
+            interfaces[pos].mac = Some(mac);
+        }
+        if let Some(addr) = unsafe { sockaddr_ip(ifa.ifa_addr) } {
+            let prefix_len = unsafe { sockaddr_ip(ifa.ifa_netmask) }
+                .map(netmask_prefix_len)
+                .unwrap_or(match addr {
+                    IpAddr::V4(_) => 32,
+                    IpAddr::V6(_) => 128,
+                });
+            interfaces[pos].addrs.push(IpNet::new(addr, prefix_len));
+        }
+    }
+    unsafe { libc::freeifaddrs(ifap) };
+    interfaces.sort_by_key(|i| i.index);
+    // resolv.conf is system wide, so every interface that can carry
+    // lookups gets its servers
+    let dns_servers = std::fs::read_to_string(RESOLV_CONF)
+        .map(|conf| parse_resolv_conf(&conf))
+        .unwrap_or_default();
+    for iface in interfaces.iter_mut().filter(|i| i.is_up && !i.is_loopback) {
+        iface.dns_servers = dns_servers.clone();
+    }
+    if let Ok(text) = std::fs::read_to_string(IF_INET6) {
+        for (index, addr, flags) in parse_if_inet6(&text) {
+            if let Some(iface) = interfaces.iter_mut().find(|i| i.index == index) {
+                iface.ipv6_flags.push((addr, flags));
+            }
+        }
+    }
+    Ok(interfaces)
+}
+
+#[cfg(unix)]
+const RESOLV_CONF: &str = "/etc/resolv.conf";
+/// IPv6 addresses with their flags, Linux only
+#[cfg(unix)]
+const IF_INET6: &str = "/proc/net/if_inet6";
+const IFA_F_TEMPORARY: u32 = 0x01;
+const IFA_F_DEPRECATED: u32 = 0x20;
+
+/// Interface index, address and flags of each line of `/proc/net/if_inet6`
+pub fn parse_if_inet6(text: &str) -> Vec<(u32, Ipv6Addr, Ipv6AddrFlags)> {
+    text.lines()
+        .filter_map(|line| {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            let bits = u128::from_str_radix(fields.first()?, 16).ok()?;
+            let index = u32::from_str_radix(fields.get(1)?, 16).ok()?;
+            let flags = u32::from_str_radix(fields.get(4)?, 16).ok()?;
+            let flags = Ipv6AddrFlags {
+                temporary: flags & IFA_F_TEMPORARY != 0,
+                deprecated: flags & IFA_F_DEPRECATED != 0,
+            };
+            Some((index, Ipv6Addr::from(bits), flags))
+        })
+        .collect()
+}
+
+/// `nameserver` addresses of a resolv.conf file, in order
+pub fn parse_resolv_conf(conf: &str) -> Vec<IpAddr> {
+    conf.lines()
+        .filter_map(|line| {
+            let mut fields = line.split_whitespace();
+            match fields.next() {
+                // Drop any zone suffix, which IpAddr does not parse
+                Some("nameserver") => fields.next()?.split('%').next()?.parse().ok(),
+                _ => None,
+            }
+        })
+        .collect()
+}
+
+/// List network interfaces of this host
+#[cfg(not(unix))]
+pub fn get_interfaces() -> io::Result<Vec<Interface>> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "Interface enumeration is not supported on this platform",
+    ))
+}
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+unsafe fn link_mac(sa: *const libc::sockaddr) -> Option<[u8; 6]> {
+    if sa.is_null() || (*sa).sa_family as i32 != libc::AF_PACKET {
+        return None;
+    }
+    let sll = &*(sa as *const libc::sockaddr_ll);
+    if sll.sll_halen != 6 {
+        return None;
+    }
+    let mut mac = [0u8; 6];
+    mac.copy_from_slice(&sll.sll_addr[..6]);
+    Some(mac)
+}
+
+#[cfg(all(unix, not(any(target_os = "linux", target_os = "android"))))]
+unsafe fn link_mac(sa: *const libc::sockaddr) -> Option<[u8; 6]> {
+    if sa.is_null() || (*sa).sa_family as i32 != libc::AF_LINK {
+        return None;
+    }
+    let sdl = &*(sa as *const libc::sockaddr_dl);
+    if sdl.sdl_alen != 6 {
+        return None;
+    }
+    let data = sdl.sdl_data.as_ptr() as *const u8;
+    let mut mac = [0u8; 6];
+    std::ptr::copy_nonoverlapping(data.add(sdl.sdl_nlen as usize), mac.as_mut_ptr(), 6);
+    Some(mac)
+}
+
+#[cfg(target_os = "linux")]
+fn read_mtu(name: &str) -> Option<u32> {
+    std::fs::read_to_string(format!("/sys/class/net/{}/mtu", name))
+        .ok()?
+        .trim()
+        .parse()
+        .ok()
+}
+
+#[cfg(all(unix, not(target_os = "linux")))]
+fn read_mtu(_name: &str) -> Option<u32> {
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(unix)]
+    #[test]
+    fn loopback_is_listed() {
+        let interfaces = get_interfaces().unwrap();
+        let lo = interfaces
+            .iter()
+            .find(|i| i.is_loopback)
+            .expect("loopback interface");
+        assert!(lo.index > 0);
+        assert!(lo.addrs.iter().any(|a| a.addr.is_loopback()));
+    }
+
+    #[test]
+    fn ipv6_addresses_classified() {
+        let inet6 = "\
+fe80000000000000021122fffe334455 02 40 20 80     eth0
+20010db800000000021122fffe334455 02 40 00 00     eth0
+20010db800000000a1b2c3d4e5f60718 02 40 00 01     eth0
+20010db80000000091a2b3c4d5e6f708 02 40 00 21     eth0
+fd001234000000000000000000000001 02 40 00 80     eth0
+";
+        let mut eth0 = Interface::new(2, "eth0");
+        for (index, addr, flags) in parse_if_inet6(inet6) {
+            assert_eq!(index, 2);
+            eth0.addrs.push(IpNet::new(IpAddr::V6(addr), 64));
+            eth0.ipv6_flags.push((addr, flags));
+        }
+        let kinds: Vec<(String, Ipv6AddrKind)> = eth0
+            .ipv6_addrs()
+            .iter()
+            .map(|(a, k)| (a.to_string(), *k))
+            .collect();
+        use Ipv6AddrKind::*;
+        assert_eq!(
+            kinds,
+            vec![
+                ("2001:db8::211:22ff:fe33:4455".to_string(), GlobalStable),
+                ("fd00:1234::1".to_string(), UniqueLocal),
+                ("2001:db8::a1b2:c3d4:e5f6:718".to_string(), GlobalTemporary),
+                ("fe80::211:22ff:fe33:4455".to_string(), LinkLocal),
+                ("2001:db8::91a2:b3c4:d5e6:f708".to_string(), Deprecated),
+            ]
+        );
+        // Without OS flags a global address counts as stable
+        let addr: Ipv6Addr = "2001:db8::1".parse().unwrap();
+        assert_eq!(Interface::new(3, "wlan0").ipv6_kind(&addr), GlobalStable);
+        assert_eq!(
+            classify_ipv6(&"ff02::1".parse().unwrap(), Default::default()),
+            Other
+        );
+    }
+
+    #[test]
+    fn resolv_conf_nameservers() {
+        let conf = "# generated\nsearch home.test\nnameserver 192.0.2.53\n\
+                    nameserver fe80::1%eth0\nnameserver bogus\noptions edns0\n";
+        let servers: Vec<IpAddr> = vec!["192.0.2.53".parse().unwrap(), "fe80::1".parse().unwrap()];
+        assert_eq!(parse_resolv_conf(conf), servers);
+    }
+}
@@ -0,0 +1,317 @@
+use std::fmt;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::str::FromStr;
+
+/// IP address with prefix length
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct IpNet {
+    pub addr: IpAddr,
+    pub prefix_len: u8,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum IpNetError {
+    InvalidAddress(String),
+    InvalidPrefix(String),
+}
+
+impl fmt::Display for IpNetError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            IpNetError::InvalidAddress(s) => write!(f, "Invalid IP address: {}", s),
+            IpNetError::InvalidPrefix(s) => write!(f, "Invalid prefix length: {}", s),
+        }
+    }
+}
+
+impl std::error::Error for IpNetError {}
+
+impl IpNet {
+    pub fn new(addr: IpAddr, prefix_len: u8) -> IpNet {
+        IpNet { addr, prefix_len }
+    }
+    pub fn max_prefix_len(&self) -> u8 {
+        match self.addr {
+            IpAddr::V4(_) => 32,
+            IpAddr::V6(_) => 128,
+        }
+    }
+    fn all_ones(&self) -> u128 {
+        match self.addr {
+            IpAddr::V4(_) => u32::MAX as u128,
+            IpAddr::V6(_) => u128::MAX,
+        }
+    }
+    /// Bits after the prefix. A prefix past the address length, possible
+    /// through `new`, is taken as a host route.
+    fn host_bits(&self) -> u32 {
+        self.max_prefix_len().saturating_sub(self.prefix_len) as u32
+    }
+    fn mask_bits(&self) -> u128 {
+        self.all_ones().checked_shl(self.host_bits()).unwrap_or(0) & self.all_ones()
+    }
+    fn bits(&self) -> u128 {
+        match self.addr {
+            IpAddr::V4(ip) => u32::from(ip) as u128,
+            IpAddr::V6(ip) => u128::from(ip),
+        }
+    }
+    fn addr_from_bits(&self, bits: u128) -> IpAddr {
+        match self.addr {
+            IpAddr::V4(_) => IpAddr::V4(Ipv4Addr::from(bits as u32)),
+            IpAddr::V6(_) => IpAddr::V6(Ipv6Addr::from(bits)),
+        }
+    }
+    pub fn netmask(&self) -> IpAddr {
+        self.addr_from_bits(self.mask_bits())
+    }
+    /// Inverse of the netmask
+    pub fn hostmask(&self) -> IpAddr {
+        self.addr_from_bits(!self.mask_bits() & self.all_ones())
+    }
+    /// First address of the network
+    pub fn network(&self) -> IpAddr {
+        self.addr_from_bits(self.bits() & self.mask_bits())
+    }
+    /// Last address of the network
+    pub fn last(&self) -> IpAddr {
+        self.addr_from_bits(self.bits() | (!self.mask_bits() & self.all_ones()))
+    }
+    /// Number of addresses in the network, saturating for IPv6 /0
+    pub fn size(&self) -> u128 {
+        1u128.checked_shl(self.host_bits()).unwrap_or(u128::MAX)
+    }
+    /// Every address of the network in order, network and broadcast
+    /// address included
+    pub fn addrs(&self) -> impl Iterator<Item = IpAddr> + '_ {
+        let network = self.bits() & self.mask_bits();
+        (0..self.size()).map(move |i| self.addr_from_bits(network + i))
+    }
+    pub fn contains(&self, ip: &IpAddr) -> bool {
+        if ip.is_ipv4() != self.addr.is_ipv4() {
+            return false;
+        }
+        let other = IpNet::new(*ip, self.prefix_len);
+        other.bits() & self.mask_bits() == self.bits() & self.mask_bits()
+    }
+}
+
+impl FromStr for IpNet {
+    type Err = IpNetError;
+
+    /// Parse `addr/prefix`. A bare address is a host route.
+    fn from_str(s: &str) -> Result<IpNet, IpNetError> {
+        let (addr, prefix) = match s.trim().split_once('/') {
+            Some((addr, prefix)) => (addr, Some(prefix)),
+            None => (s.trim(), None),
+        };
+        let addr: IpAddr = addr
+            .parse()
+            .map_err(|_| IpNetError::InvalidAddress(addr.to_string()))?;
+        let mut net = IpNet::new(addr, 0);
+        net.prefix_len = match prefix {
+            Some(p) => p
+                .parse::<u8>()
+                .ok()
+                .filter(|p| *p <= net.max_prefix_len())
+                .ok_or_else(|| IpNetError::InvalidPrefix(p.to_string()))?,
+            None => net.max_prefix_len(),
+        };
+        Ok(net)
+    }
+}
+
+impl fmt::Display for IpNet {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.addr, self.prefix_len)
+    }
+}
+
+/// Prefix length of a contiguous netmask
+pub fn netmask_prefix_len(mask: IpAddr) -> u8 {
+    match mask {
+        IpAddr::V4(m) => u32::from(m).leading_ones() as u8,
+        IpAddr::V6(m) => u128::from(m).leading_ones() as u8,
+    }
+}
+
+/// Details of an IPv4 network
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Ipv4SubnetInfo {
+    pub network: Ipv4Addr,
+    /// `None` for /31 and /32, which have no broadcast address
+    pub broadcast: Option<Ipv4Addr>,
+    pub first_usable: Ipv4Addr,
+    pub last_usable: Ipv4Addr,
+    pub usable_hosts: u64,
+    pub netmask: Ipv4Addr,
+    pub wildcard: Ipv4Addr,
+    pub prefix_len: u8,
+}
+
+/// Details of an IPv6 network
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Ipv6SubnetInfo {
+    pub prefix: Ipv6Addr,
+    pub prefix_len: u8,
+    pub first: Ipv6Addr,
+    pub last: Ipv6Addr,
+    /// Saturates at `u128::MAX` for /0
+    pub address_count: u128,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum SubnetInfo {
+    V4(Ipv4SubnetInfo),
+    V6(Ipv6SubnetInfo),
+}
+
+/// Network, broadcast, usable range and masks of `cidr`
+pub fn subnet_info(cidr: &str) -> Result<SubnetInfo, IpNetError> {
+    let net: IpNet = cidr.parse()?;
+    match (net.network(), net.last()) {
+        (IpAddr::V4(network), IpAddr::V4(last)) => {
+            let (broadcast, first_usable, last_usable, usable_hosts) = match net.prefix_len {
+                32 => (None, network, network, 1),
+                // RFC 3021 point-to-point links use both addresses
+                31 => (None, network, last, 2),
+                _ => (
+                    Some(last),
+                    Ipv4Addr::from(u32::from(network) + 1),
+                    Ipv4Addr::from(u32::from(last) - 1),
+                    net.size() as u64 - 2,
+                ),
+            };
+            let (IpAddr::V4(netmask), IpAddr::V4(wildcard)) = (net.netmask(), net.hostmask())
+            else {
+                unreachable!()
+            };
+            Ok(SubnetInfo::V4(Ipv4SubnetInfo {
+                network,
+                broadcast,
+                first_usable,
+                last_usable,
+                usable_hosts,
+                netmask,
+                wildcard,
+                prefix_len: net.prefix_len,
+            }))
+        }
+        (IpAddr::V6(first), IpAddr::V6(last)) => Ok(SubnetInfo::V6(Ipv6SubnetInfo {
+            prefix: first,
+            prefix_len: net.prefix_len,
+            first,
+            last,
+            address_count: net.size(),
+        })),
+        _ => unreachable!(),
+    }
+}
+
+/// Whether `ip` is inside `cidr`
+pub fn ip_in_cidr(ip: &str, cidr: &str) -> Result<bool, IpNetError> {
+    let ip: IpAddr = ip
+        .trim()
+        .parse()
+        .map_err(|_| IpNetError::InvalidAddress(ip.to_string()))?;
+    let net: IpNet = cidr.parse()?;
+    Ok(net.contains(&ip))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn v4(cidr: &str) -> Ipv4SubnetInfo {
+        match subnet_info(cidr).unwrap() {
+            SubnetInfo::V4(info) => info,
+            SubnetInfo::V6(_) => panic!("expected IPv4"),
+        }
+    }
+
+    #[test]
+    fn ipv4_slash_24() {
+        let info = v4("192.168.1.77/24");
+        assert_eq!(info.network, Ipv4Addr::new(192, 168, 1, 0));
+        assert_eq!(info.broadcast, Some(Ipv4Addr::new(192, 168, 1, 255)));
+        assert_eq!(info.first_usable, Ipv4Addr::new(192, 168, 1, 1));
+        assert_eq!(info.last_usable, Ipv4Addr::new(192, 168, 1, 254));
+        assert_eq!(info.usable_hosts, 254);
+        assert_eq!(info.netmask, Ipv4Addr::new(255, 255, 255, 0));
+        assert_eq!(info.wildcard, Ipv4Addr::new(0, 0, 0, 255));
+    }
+
+    #[test]
+    fn ipv4_slash_31_and_32() {
+        let info = v4("10.0.0.1/31");
+        assert_eq!(info.network, Ipv4Addr::new(10, 0, 0, 0));
+        assert_eq!(info.broadcast, None);
+        assert_eq!(
+            (info.first_usable, info.last_usable),
+            (Ipv4Addr::new(10, 0, 0, 0), Ipv4Addr::new(10, 0, 0, 1))
+        );
+        assert_eq!(info.usable_hosts, 2);
+        let info = v4("10.0.0.9/32");
+        assert_eq!(
+            (info.first_usable, info.last_usable, info.usable_hosts),
+            (Ipv4Addr::new(10, 0, 0, 9), Ipv4Addr::new(10, 0, 0, 9), 1)
+        );
+        assert_eq!(info.wildcard, Ipv4Addr::new(0, 0, 0, 0));
+        assert_eq!(v4("0.0.0.0/0").usable_hosts, (1u64 << 32) - 2);
+    }
+
+    #[test]
+    fn ipv6_slash_64() {
+        let SubnetInfo::V6(info) = subnet_info("2001:db8:1:2::abcd/64").unwrap() else {
+            panic!("expected IPv6");
+        };
+        assert_eq!(info.prefix, "2001:db8:1:2::".parse::<Ipv6Addr>().unwrap());
+        assert_eq!(
+            info.last,
+            "2001:db8:1:2:ffff:ffff:ffff:ffff"
+                .parse::<Ipv6Addr>()
+                .unwrap()
+        );
+        assert_eq!(info.address_count, 1u128 << 64);
+        let SubnetInfo::V6(all) = subnet_info("::/0").unwrap() else {
+            panic!("expected IPv6");
+        };
+        assert_eq!(all.address_count, u128::MAX);
+    }
+
+    #[test]
+    fn membership_at_boundaries() {
+        assert_eq!(ip_in_cidr("192.168.1.0", "192.168.1.0/24"), Ok(true));
+        assert_eq!(ip_in_cidr("192.168.1.255", "192.168.1.0/24"), Ok(true));
+        assert_eq!(ip_in_cidr("192.168.2.0", "192.168.1.0/24"), Ok(false));
+        assert_eq!(ip_in_cidr("192.168.0.255", "192.168.1.0/24"), Ok(false));
+        assert_eq!(ip_in_cidr("2001:db8::1", "2001:db8::/32"), Ok(true));
+        assert_eq!(ip_in_cidr("192.168.1.1", "2001:db8::/32"), Ok(false));
+    }
+
+    #[test]
+    fn malformed_input() {
+        assert_eq!(
+            subnet_info("192.168.1.0/33"),
+            Err(IpNetError::InvalidPrefix("33".to_string()))
+        );
+        assert_eq!(
+            subnet_info("192.168.1/24"),
+            Err(IpNetError::InvalidAddress("192.168.1".to_string()))
+        );
+        assert!(ip_in_cidr("nope", "10.0.0.0/8").is_err());
+        assert!(subnet_info("10.0.0.0/x").is_err());
+    }
+
+    #[test]
+    fn edge_prefixes_do_not_overflow() {
+        let host = IpNet::new("10.0.0.9".parse().unwrap(), 40);
+        assert_eq!(host.size(), 1);
+        assert_eq!(host.addrs().collect::<Vec<_>>(), vec![host.addr]);
+        assert_eq!(host.netmask(), IpAddr::V4(Ipv4Addr::BROADCAST));
+        let host = IpNet::new("2001:db8::1".parse().unwrap(), 255);
+        assert_eq!((host.size(), host.network()), (1, host.addr));
+        assert_eq!(IpNet::new("10.0.0.0".parse().unwrap(), 0).size(), 1 << 32);
+        assert_eq!(IpNet::new("::".parse().unwrap(), 0).size(), u128::MAX);
+    }
+}
@@ -0,0 +1,221 @@
+//! MAC addresses and vendor lookup
+use std::collections::HashMap;
+use std::fmt;
+use std::str::FromStr;
+use std::sync::OnceLock;
+
+const BUNDLED_OUI: &str = include_str!("../../resources/oui.csv");
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct MacAddr(pub [u8; 6]);
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MacParseError(pub String);
+
+impl fmt::Display for MacParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Invalid MAC address: {}", self.0)
+    }
+}
+
+impl std::error::Error for MacParseError {}
+
+impl MacAddr {
+    /// Organizationally unique identifier (first three octets)
+    pub fn oui(&self) -> [u8; 3] {
+        [self.0[0], self.0[1], self.0[2]]
+    }
+    /// Set when the address was assigned locally rather than by the vendor
+    pub fn is_locally_administered(&self) -> bool {
+        self.0[0] & 0x02 != 0
+    }
+    pub fn is_multicast(&self) -> bool {
+        self.0[0] & 0x01 != 0
+    }
+}
+
+impl fmt::Display for MacAddr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let b = self.0;
+        write!(
+            f,
+            "{:02x}:{:02x}:{:02x}:{:02x}:{:02x}:{:02x}",
+            b[0], b[1], b[2], b[3], b[4], b[5]
+        )
+    }
+}
+
+impl FromStr for MacAddr {
+    type Err = MacParseError;
+
+    /// Accepts `aa:bb:cc:dd:ee:ff`, `aa-bb-cc-dd-ee-ff`, `aabb.ccdd.eeff` and
+    /// `aabbccddeeff`, in any case.
+    fn from_str(s: &str) -> Result<MacAddr, MacParseError> {
+        let err = || MacParseError(s.to_string());
+        let trimmed = s.trim();
+        let hex: String = if trimmed.contains([':', '-']) {
+            let parts: Vec<&str> = trimmed.split([':', '-']).collect();
+            if parts.len() != 6 || parts.iter().any(|p| p.is_empty() || p.len() > 2) {
+                return Err(err());
+            }
+            parts.iter().map(|p| format!("{:0>2}", p)).collect()
+        } else if trimmed.contains('.') {
+            let parts: Vec<&str> = trimmed.split('.').collect();
+            if parts.len() != 3 || parts.iter().any(|p| p.len() != 4) {
+                return Err(err());
+            }
+            parts.concat()
+        } else {
+            trimmed.to_string()
+        };
+        if hex.len() != 12 || !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+            return Err(err());
+        }
+        let mut octets = [0u8; 6];
+        for (i, octet) in octets.iter_mut().enumerate() {
+            *octet = u8::from_str_radix(&hex[i * 2..i * 2 + 2], 16).map_err(|_| err())?;
+        }
+        Ok(MacAddr(octets))
+    }
+}
+
+/// Vendor registered for an OUI
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct OuiEntry {
+    pub vendor: String,
+    pub detail: String,
+}
+
+/// OUI to vendor database
+#[derive(Clone, Debug, Default)]
+pub struct OuiDb {
+    entries: HashMap<[u8; 3], OuiEntry>,
+}
+
+impl OuiDb {
+    /// Parse lines of `PREFIX,vendor,detail`. The detail may contain commas.
+    /// Empty lines and lines starting with `#` are skipped.
+    pub fn from_csv(content: &str) -> OuiDb {
+        let mut entries = HashMap::new();
+        for line in content.lines().map(str::trim) {
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let mut cols = line.splitn(3, ',');
+            let (Some(prefix), Some(vendor)) = (cols.next(), cols.next()) else {
+                continue;
+            };
+            let Ok(n) = u32::from_str_radix(prefix.trim(), 16) else {
+                continue;
+            };
+            let b = n.to_be_bytes();
+            entries.insert(
+                [b[1], b[2], b[3]],
+                OuiEntry {
+                    vendor: vendor.trim().to_string(),
+                    detail: cols.next().unwrap_or("").trim().to_string(),
+                },
+            );
+        }
+        OuiDb { entries }
+    }
+    /// Database shipped with the crate.
+    ///
+    /// This is a small subset of common vendors; load a full IEEE export with
+    /// [`OuiDb::from_csv`] for complete coverage.
+    pub fn bundled() -> &'static OuiDb {
+        static DB: OnceLock<OuiDb> = OnceLock::new();
+        DB.get_or_init(|| OuiDb::from_csv(BUNDLED_OUI))
+    }
+    pub fn get(&self, mac: &MacAddr) -> Option<&OuiEntry> {
+        self.entries.get(&mac.oui())
+    }
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
+
+/// Result of [`lookup_vendor`]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct VendorLookup {
+    /// Normalized address
+    pub mac: String,
+    pub vendor: Option<String>,
+    pub detail: Option<String>,
+    /// Locally administered addresses (e.g. randomized Wi-Fi MACs) have no vendor
+    pub locally_administered: bool,
+}
+
+/// Look up the vendor of `mac` in `db`
+pub fn lookup_vendor(db: &OuiDb, mac: &str) -> Result<VendorLookup, MacParseError> {
+    let mac: MacAddr = mac.parse()?;
+    let locally_administered = mac.is_locally_administered();
+    let entry = if locally_administered {
+        None
+    } else {
+        db.get(&mac)
+    };
+    Ok(VendorLookup {
+        mac: mac.to_string(),
+        vendor: entry.map(|e| e.vendor.clone()),
+        detail: entry.map(|e| e.detail.clone()),
+        locally_administered,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_all_formats() {
+        let expected = MacAddr([0xb8, 0x27, 0xeb, 0x12, 0x34, 0x5f]);
+        for s in [
+            "b8:27:eb:12:34:5f",
+            "B8-27-EB-12-34-5F",
+            "b827.eb12.345f",
+            "B827EB12345F",
+            " b8:27:eb:12:34:5f ",
+        ] {
+            assert_eq!(s.parse::<MacAddr>(), Ok(expected), "{}", s);
+        }
+        assert_eq!(
+            "0:1:2:a:b:c".parse::<MacAddr>().unwrap().to_string(),
+            "00:01:02:0a:0b:0c"
+        );
+        for bad in [
+            "b8:27:eb:12:34",
+            "b827eb12345",
+            "zz:27:eb:12:34:5f",
+            "b82.7eb1.2345f",
+            "",
+        ] {
+            assert!(bad.parse::<MacAddr>().is_err(), "{}", bad);
+        }
+    }
+
+    #[test]
+    fn lookup_bundled_vendor() {
+        let db = OuiDb::bundled();
+        let found = lookup_vendor(db, "B8-27-EB-00-00-01").unwrap();
+        assert_eq!(found.mac, "b8:27:eb:00:00:01");
+        assert_eq!(found.vendor.as_deref(), Some("Raspberry Pi"));
+        assert_eq!(found.detail.as_deref(), Some("Raspberry Pi Foundation"));
+        let cisco = lookup_vendor(db, "0000.0c11.2233").unwrap();
+        assert_eq!(cisco.detail.as_deref(), Some("Cisco Systems, Inc"));
+    }
+
+    #[test]
+    fn unknown_and_locally_administered() {
+        let db = OuiDb::bundled();
+        let unknown = lookup_vendor(db, "a0b1c2d3e4f5").unwrap();
+        assert_eq!(unknown.vendor, None);
+        assert!(!unknown.locally_administered);
+        let local = lookup_vendor(db, "52:54:00:12:34:56").unwrap();
+        assert_eq!(local.vendor, None);
+        assert!(local.locally_administered);
+    }
+}
@@ -0,0 +1,22 @@
+//! Address and interface helpers
+pub mod cache;
+pub mod conntrack;
+pub mod egress;
+pub mod geo;
+pub mod infra;
+pub mod interface;
+pub mod ipnet;
+pub mod mac;
+pub mod monitor;
+pub mod mtu;
+pub mod multicast;
+pub mod nat;
+pub mod neigh;
+pub mod presence;
+pub mod route;
+pub mod scope;
+pub mod snapshot;
+pub mod socks;
+pub mod traffic;
+pub mod upnp;
+pub mod watchdog;
@@ -0,0 +1,247 @@
+//! Interface change detection
+use super::interface::{Interface, InterfaceSource};
+use crate::cancel::CancellationToken;
+use crate::probe::cancellable_sleep;
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::{Duration, Instant};
+
+/// Event name emitted when interfaces change
+pub const INTERFACES_CHANGED_EVENT: &str = "interfaces:changed";
+pub const DEFAULT_DEBOUNCE: Duration = Duration::from_millis(1500);
+pub const DEFAULT_POLL_INTERVAL: Duration = Duration::from_millis(500);
+/// How often a task waiting for a link checks its cancellation token
+const LINK_POLL: Duration = Duration::from_millis(100);
+
+/// Difference between two interface snapshots
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct InterfaceDelta {
+    pub added: Vec<Interface>,
+    pub removed: Vec<Interface>,
+    /// New state of interfaces whose state or addresses changed
+    pub changed: Vec<Interface>,
+}
+
+impl InterfaceDelta {
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.changed.is_empty()
+    }
+}
+
+/// Compare two snapshots by interface index
+pub fn diff(old: &[Interface], new: &[Interface]) -> InterfaceDelta {
+    let mut delta = InterfaceDelta::default();
+    for iface in new {
+        match old.iter().find(|o| o.index == iface.index) {
+            None => delta.added.push(iface.clone()),
+            Some(o) if o != iface => delta.changed.push(iface.clone()),
+            Some(_) => {}
+        }
+    }
+    for iface in old {
+        if !new.iter().any(|n| n.index == iface.index) {
+            delta.removed.push(iface.clone());
+        }
+    }
+    delta
+}
+
+/// Turns a stream of snapshots into debounced change events.
+///
+/// A change is reported once snapshots have been stable for the debounce
+/// period. Flapping that returns to the last reported state emits nothing.
+#[derive(Debug)]
+pub struct InterfaceMonitor {
+    debounce: Duration,
+    reported: Vec<Interface>,
+    latest: Vec<Interface>,
+    last_change: Option<Instant>,
+}
+
+impl InterfaceMonitor {
+    pub fn new(initial: Vec<Interface>, debounce: Duration) -> InterfaceMonitor {
+        InterfaceMonitor {
+            debounce,
+            latest: initial.clone(),
+            reported: initial,
+            last_change: None,
+        }
+    }
+    /// Last reported snapshot
+    pub fn interfaces(&self) -> &[Interface] {
+        &self.reported
+    }
+    /// Feed a snapshot taken at `now`. Returns a delta when a change has settled.
+    pub fn observe(&mut self, snapshot: Vec<Interface>, now: Instant) -> Option<InterfaceDelta> {
+        if snapshot != self.latest {
+            self.latest = snapshot;
+            self.last_change = Some(now);
+            return None;
+        }
+        let settled = match self.last_change {
+            Some(at) => now.saturating_duration_since(at) >= self.debounce,
+            None => false,
+        };
+        if !settled {
+            return None;
+        }
+        self.last_change = None;
+        let delta = diff(&self.reported, &self.latest);
+        self.reported = self.latest.clone();
+        (!delta.is_empty()).then_some(delta)
+    }
+}
+
+/// Poll `source` until cancelled, calling `on_change` with each settled delta.
+pub fn watch_interfaces<S, F>(
+    source: &S,
+    poll_interval: Duration,
+    debounce: Duration,
+    token: &CancellationToken,
+    mut on_change: F,
+) where
+    S: InterfaceSource,
+    F: FnMut(&InterfaceDelta, &[Interface]),
+{
+    let initial = source.interfaces().unwrap_or_default();
+    let mut monitor = InterfaceMonitor::new(initial, debounce);
+    while !cancellable_sleep(token, poll_interval).is_cancelled() {
+        let Ok(snapshot) = source.interfaces() else {
+            continue;
+        };
+        if let Some(delta) = monitor.observe(snapshot, Instant::now()) {
+            on_change(&delta, monitor.interfaces());
+        }
+    }
+}
+
+/// Whether the interface a probe runs over is up, shared between the probe,
+/// which marks it down on send failures, and the interface monitor, which
+/// marks it up again. Clones share state.
+#[derive(Clone, Debug)]
+pub struct LinkState {
+    iface: String,
+    inner: Arc<(Mutex<bool>, Condvar)>,
+}
+
+impl LinkState {
+    /// State of interface `iface`, initially up
+    pub fn new(iface: &str) -> LinkState {
+        LinkState {
+            iface: iface.to_string(),
+            inner: Arc::new((Mutex::new(true), Condvar::new())),
+        }
+    }
+    pub fn iface(&self) -> &str {
+        &self.iface
+    }
+    pub fn is_up(&self) -> bool {
+        *self.inner.0.lock().unwrap()
+    }
+    pub fn set_up(&self, up: bool) {
+        *self.inner.0.lock().unwrap() = up;
+        if up {
+            self.inner.1.notify_all();
+        }
+    }
+    /// Follow a settled delta from [`watch_interfaces`]
+    pub fn apply(&self, delta: &InterfaceDelta) {
+        if delta.removed.iter().any(|i| i.name == self.iface) {
+            self.set_up(false);
+        }
+        let mut current = delta.added.iter().chain(&delta.changed);
+        if let Some(iface) = current.find(|i| i.name == self.iface) {
+            self.set_up(iface.is_up);
+        }
+    }
+    /// Block while down or until `token` is cancelled
+    pub fn wait_until_up(&self, token: &CancellationToken) {
+        let (lock, cvar) = &*self.inner;
+        let mut up = lock.lock().unwrap();
+        while !*up && !token.is_cancelled() {
+            up = cvar.wait_timeout(up, LINK_POLL).unwrap().0;
+        }
+    }
+    /// Block while down for at most `timeout`. Returns whether the link is up.
+    pub fn wait_until_up_for(&self, token: &CancellationToken, timeout: Duration) -> bool {
+        let deadline = Instant::now() + timeout;
+        let (lock, cvar) = &*self.inner;
+        let mut up = lock.lock().unwrap();
+        while !*up && !token.is_cancelled() {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining.is_zero() {
+                break;
+            }
+            up = cvar.wait_timeout(up, remaining.min(LINK_POLL)).unwrap().0;
+        }
+        *up
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::net::ipnet::IpNet;
+
+    fn eth0(addr: &str) -> Interface {
+        let mut iface = Interface::new(2, "eth0");
+        iface.is_up = true;
+        iface.addrs.push(IpNet::new(addr.parse().unwrap(), 24));
+        iface
+    }
+
+    #[test]
+    fn link_state_follows_deltas() {
+        let link = LinkState::new("eth0");
+        let mut down = eth0("192.168.1.10");
+        down.is_up = false;
+        link.apply(&diff(&[eth0("192.168.1.10")], &[down.clone()]));
+        assert!(!link.is_up());
+        link.apply(&diff(&[down], &[eth0("192.168.1.10")]));
+        assert!(link.is_up());
+        link.apply(&diff(&[eth0("192.168.1.10")], &[]));
+        assert!(!link.is_up());
+        // Other interfaces do not matter
+        link.apply(&diff(&[], &[Interface::new(3, "wlan0")]));
+        assert!(!link.is_up());
+    }
+
+    #[test]
+    fn rapid_changes_emit_one_event() {
+        let t0 = Instant::now();
+        let ms = |n| t0 + Duration::from_millis(n);
+        let mut monitor =
+            InterfaceMonitor::new(vec![eth0("192.168.1.10")], Duration::from_millis(500));
+        let wifi = Interface::new(3, "wlan0");
+
+        let mut events = Vec::new();
+        let snapshots = [
+            (0, vec![eth0("192.168.1.10")]),
+            (100, vec![eth0("10.0.0.5")]),
+            (200, vec![eth0("10.0.0.5"), wifi.clone()]),
+            (300, vec![eth0("10.0.0.5"), wifi.clone()]),
+            (900, vec![eth0("10.0.0.5"), wifi.clone()]),
+            (1000, vec![eth0("10.0.0.5"), wifi.clone()]),
+        ];
+        for (at, snapshot) in snapshots {
+            events.extend(monitor.observe(snapshot, ms(at)));
+        }
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].added, vec![wifi]);
+        assert_eq!(events[0].changed, vec![eth0("10.0.0.5")]);
+        assert!(events[0].removed.is_empty());
+    }
+
+    #[test]
+    fn flap_back_to_reported_state_is_silent() {
+        let t0 = Instant::now();
+        let mut monitor =
+            InterfaceMonitor::new(vec![eth0("192.168.1.10")], Duration::from_millis(500));
+        assert!(monitor.observe(vec![], t0).is_none());
+        assert!(monitor
+            .observe(vec![eth0("192.168.1.10")], t0 + Duration::from_millis(100))
+            .is_none());
+        assert!(monitor
+            .observe(vec![eth0("192.168.1.10")], t0 + Duration::from_secs(2))
+            .is_none());
+    }
+}
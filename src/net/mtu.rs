@@ -0,0 +1,323 @@
+//! Verification of interface MTUs against what actually gets through
+use super::interface::Interface;
+use super::route::Route;
+use crate::cancel::CancellationToken;
+use crate::ping::sweep::SizedProber;
+use std::net::IpAddr;
+use std::time::Duration;
+
+/// IPv4 header plus the 8-byte ICMP or UDP header
+pub const IPV4_PROBE_OVERHEAD: usize = 28;
+/// IPv6 header plus the 8-byte ICMPv6 or UDP header
+pub const IPV6_PROBE_OVERHEAD: usize = 48;
+/// Smallest MTU every IPv4 host must accept
+const IPV4_MIN_MTU: usize = 576;
+const IPV6_MIN_MTU: usize = 1280;
+/// Probes sent per size before it counts as too large, so a single lost
+/// packet does not shrink the verified MTU
+pub const MTU_TRIES_PER_SIZE: u8 = 3;
+
+/// Reported and verified MTU of one interface
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MtuCheck {
+    pub iface: String,
+    /// MTU configured on the interface
+    pub reported_mtu: Option<u32>,
+    /// Largest packet that reached the gateway with Don't Fragment set
+    pub verified_mtu: Option<u32>,
+    pub gateway: Option<IpAddr>,
+    /// Set when full-size packets do not get through, as is common behind
+    /// PPPoE or a VPN
+    pub mismatch: bool,
+    pub error: Option<String>,
+    /// Cancelled before a verdict was reached
+    pub cancelled: bool,
+}
+
+fn probe_overhead(dst: IpAddr) -> usize {
+    match dst {
+        IpAddr::V4(_) => IPV4_PROBE_OVERHEAD,
+        IpAddr::V6(_) => IPV6_PROBE_OVERHEAD,
+    }
+}
+
+/// Send DF-set packets of the reported MTU to `gateway`, and search for the
+/// largest working size if they do not get through. Each size gets up to
+/// [`MTU_TRIES_PER_SIZE`] probes.
+pub fn verify_mtu<P: SizedProber>(
+    prober: &P,
+    iface: &Interface,
+    gateway: IpAddr,
+    timeout: Duration,
+    token: &CancellationToken,
+) -> MtuCheck {
+    let mut check = MtuCheck {
+        iface: iface.name.clone(),
+        reported_mtu: iface.mtu,
+        verified_mtu: None,
+        gateway: Some(gateway),
+        mismatch: false,
+        error: None,
+        cancelled: false,
+    };
+    let Some(reported) = iface.mtu else {
+        check.error = Some(format!("{} reports no MTU", iface.name));
+        return check;
+    };
+    let overhead = probe_overhead(gateway);
+    let mut seq: u16 = 0;
+    let mut fits = |mtu: usize| {
+        (0..MTU_TRIES_PER_SIZE).any(|_| {
+            seq = seq.wrapping_add(1);
+            !token.is_cancelled()
+                && prober
+                    .probe_sized(gateway, seq, mtu.saturating_sub(overhead), timeout)
+                    .is_ok()
+        })
+    };
+    let cancelled = |mut check: MtuCheck| {
+        check.cancelled = true;
+        check
+    };
+    if fits(reported as usize) {
+        check.verified_mtu = Some(reported);
+        return check;
+    }
+    // An interface may report less than the protocol minimum
+    let min = match gateway {
+        IpAddr::V4(_) => IPV4_MIN_MTU,
+        IpAddr::V6(_) => IPV6_MIN_MTU,
+    }
+    .min(reported as usize);
+    if !fits(min) {
+        if token.is_cancelled() {
+            return cancelled(check);
+        }
+        check.error = Some(format!("Gateway {} did not answer", gateway));
+        return check;
+    }
+    // Largest size known to fit and smallest known to fail
+    let (mut ok, mut failed) = (min, reported as usize);
+    while failed.saturating_sub(ok) > 1 {
+        let mid = ok + (failed - ok) / 2;
+        if fits(mid) {
+            ok = mid;
+        } else {
+            failed = mid;
+        }
+        if token.is_cancelled() {
+            return cancelled(check);
+        }
+    }
+    check.verified_mtu = Some(ok as u32);
+    check.mismatch = true;
+    check
+}
+
+/// Verify the MTU of every up, non-loopback interface with a default
+/// gateway
+pub fn check_interface_mtus<P: SizedProber>(
+    prober: &P,
+    interfaces: &[Interface],
+    routes: &[Route],
+    timeout: Duration,
+    token: &CancellationToken,
+) -> Vec<MtuCheck> {
+    interfaces
+        .iter()
+        .filter(|i| i.is_up && !i.is_loopback)
+        .take_while(|_| !token.is_cancelled())
+        .map(|iface| {
+            let gateway = routes
+                .iter()
+                .find(|r| r.is_default() && r.iface == iface.name)
+                .and_then(|r| r.gateway);
+            match gateway {
+                Some(gateway) => verify_mtu(prober, iface, gateway, timeout, token),
+                None => MtuCheck {
+                    iface: iface.name.clone(),
+                    reported_mtu: iface.mtu,
+                    verified_mtu: None,
+                    gateway: None,
+                    mismatch: false,
+                    error: Some(format!("No default gateway on {}", iface.name)),
+                    cancelled: false,
+                },
+            }
+        })
+        .collect()
+}
+
+/// MTU of the interface traffic to `dst` leaves through, see
+/// [`egress_interface`](super::egress::egress_interface)
+pub fn egress_mtu(interfaces: &[Interface], routes: &[Route], dst: IpAddr) -> Option<u32> {
+    super::egress::egress_interface(interfaces, routes, dst)?.mtu
+}
+
+/// Largest [`egress_mtu`] of `dsts` on this host, so a receive buffer
+/// sized by it fits replies from every target. `None` when the tables
+/// cannot be read or no target has a known egress interface.
+pub fn system_egress_mtu(dsts: &[IpAddr]) -> Option<u32> {
+    let interfaces = super::interface::get_interfaces().ok()?;
+    let routes = super::route::get_routes().unwrap_or_default();
+    dsts.iter()
+        .filter_map(|dst| egress_mtu(&interfaces, &routes, *dst))
+        .max()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::net::ipnet::IpNet;
+    use crate::ping::{ProbeError, ProbeReply};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    /// Answers packets whose total size fits `mtu`
+    struct Link {
+        mtu: usize,
+    }
+
+    impl SizedProber for Link {
+        fn probe_sized(
+            &self,
+            dst: IpAddr,
+            _seq: u16,
+            payload_len: usize,
+            _timeout: Duration,
+        ) -> Result<ProbeReply, ProbeError> {
+            if payload_len + IPV4_PROBE_OVERHEAD > self.mtu {
+                return Err(ProbeError::Timeout);
+            }
+            Ok(ProbeReply {
+                responder: dst,
+                rtt: Duration::from_millis(1),
+                ttl: None,
+            })
+        }
+    }
+
+    #[test]
+    fn pppoe_mismatch_is_flagged() {
+        let mut eth0 = Interface::new(2, "eth0");
+        eth0.is_up = true;
+        eth0.mtu = Some(1500);
+        let mut lo = Interface::new(1, "lo");
+        lo.is_up = true;
+        lo.is_loopback = true;
+        let routes = vec![Route {
+            destination: IpNet::new("0.0.0.0".parse().unwrap(), 0),
+            gateway: Some("192.168.1.1".parse().unwrap()),
+            iface: "eth0".to_string(),
+            metric: 100,
+        }];
+        let token = CancellationToken::new();
+        let timeout = Duration::from_millis(100);
+        let checks = check_interface_mtus(
+            &Link { mtu: 1492 },
+            &[lo, eth0.clone()],
+            &routes,
+            timeout,
+            &token,
+        );
+        assert_eq!(checks.len(), 1);
+        assert_eq!(checks[0].reported_mtu, Some(1500));
+        assert_eq!(checks[0].verified_mtu, Some(1492));
+        assert!(checks[0].mismatch);
+
+        let checks = check_interface_mtus(&Link { mtu: 1500 }, &[eth0], &routes, timeout, &token);
+        assert_eq!(checks[0].verified_mtu, Some(1500));
+        assert!(!checks[0].mismatch);
+    }
+
+    /// `Link` losing every probe but each `.1`th, and cancelling `.2` once
+    /// it has seen `.3` probes
+    struct Lossy(Link, usize, CancellationToken, usize, AtomicUsize);
+
+    impl SizedProber for Lossy {
+        fn probe_sized(
+            &self,
+            dst: IpAddr,
+            seq: u16,
+            payload_len: usize,
+            timeout: Duration,
+        ) -> Result<ProbeReply, ProbeError> {
+            let n = self.4.fetch_add(1, Ordering::SeqCst) + 1;
+            if n >= self.3 {
+                self.2.cancel();
+            }
+            if !n.is_multiple_of(self.1) {
+                return Err(ProbeError::Timeout);
+            }
+            self.0.probe_sized(dst, seq, payload_len, timeout)
+        }
+    }
+
+    #[test]
+    fn lost_probes_and_cancellation() {
+        let mut eth0 = Interface::new(2, "eth0");
+        eth0.mtu = Some(1500);
+        let gateway = "192.168.1.1".parse().unwrap();
+        let timeout = Duration::from_millis(100);
+        let link = |mtu, every, cancel_after| {
+            let token = CancellationToken::new();
+            let lossy = Lossy(
+                Link { mtu },
+                every,
+                token.clone(),
+                cancel_after,
+                AtomicUsize::new(0),
+            );
+            (lossy, token)
+        };
+
+        // Two of three probes lost: still the full MTU
+        let (lossy, token) = link(1500, 3, usize::MAX);
+        let check = verify_mtu(&lossy, &eth0, gateway, timeout, &token);
+        assert_eq!((check.verified_mtu, check.mismatch), (Some(1500), false));
+
+        // Cancelled midway through the search: no verdict
+        let (lossy, token) = link(1492, 1, 6);
+        let check = verify_mtu(&lossy, &eth0, gateway, timeout, &token);
+        assert!(check.cancelled);
+        assert_eq!((check.verified_mtu, check.mismatch), (None, false));
+
+        // Reported below the IPv4 minimum
+        eth0.mtu = Some(500);
+        let (lossy, token) = link(400, 1, usize::MAX);
+        let check = verify_mtu(&lossy, &eth0, gateway, timeout, &token);
+        assert_eq!(check.verified_mtu, None);
+        assert!(check.error.is_some());
+    }
+
+    #[test]
+    fn egress_mtu_follows_the_route() {
+        let iface = |index, name: &str, mtu, addr: &str| {
+            let mut iface = Interface::new(index, name);
+            iface.mtu = Some(mtu);
+            iface.addrs = vec![IpNet::new(addr.parse().unwrap(), 24)];
+            iface
+        };
+        let mut lo = iface(1, "lo", 65536, "127.0.0.1");
+        lo.is_loopback = true;
+        let eth0 = iface(2, "eth0", 1500, "192.168.1.2");
+        let jumbo = iface(3, "eth1", 9000, "10.0.0.2");
+        let route = |net: &str, prefix_len, name: &str| Route {
+            destination: IpNet::new(net.parse().unwrap(), prefix_len),
+            gateway: None,
+            iface: name.to_string(),
+            metric: 0,
+        };
+        let routes = [route("0.0.0.0", 0, "eth0"), route("10.0.0.0", 8, "eth1")];
+        let interfaces = [lo, eth0, jumbo];
+        let mtu = |dst: &str| egress_mtu(&interfaces, &routes, dst.parse().unwrap());
+        assert_eq!(mtu("10.1.2.3"), Some(9000));
+        assert_eq!(mtu("8.8.8.8"), Some(1500));
+        // Local addresses go over loopback
+        assert_eq!(mtu("127.0.0.1"), Some(65536));
+        assert_eq!(mtu("10.0.0.2"), Some(65536));
+        assert_eq!(
+            egress_mtu(&interfaces, &routes[1..], "8.8.8.8".parse().unwrap()),
+            None
+        );
+    }
+}
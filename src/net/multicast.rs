@@ -0,0 +1,300 @@
+//! Multicast group reachability, for debugging mDNS, SSDP and streaming
+use crate::cancel::CancellationToken;
+use crate::pool::map_concurrent;
+use crate::socket::bind_udp_shared;
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV6, UdpSocket};
+use std::time::{Duration, Instant};
+use ts_rs::TS;
+
+/// Default time to listen for traffic after joining
+pub const DEFAULT_MULTICAST_WINDOW_MS: u64 = 5000;
+/// Groups tested at once by [`test_multicast_groups`]
+pub const MULTICAST_CONCURRENCY: usize = 8;
+/// Longest a single receive blocks before checking for cancellation
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Group to join and how long to listen on it
+#[derive(Clone, Debug, PartialEq, Eq, TS)]
+pub struct MulticastSetting {
+    pub group: IpAddr,
+    pub port: u16,
+    /// Local address of the interface to join an IPv4 group on. The routing
+    /// table picks the interface when unset.
+    pub interface_addr: Option<Ipv4Addr>,
+    /// Index of the interface to join an IPv6 group on, 0 for the default
+    pub interface_index: u32,
+    #[ts(type = "number")]
+    pub window_ms: u64,
+}
+
+impl MulticastSetting {
+    /// mDNS group 224.0.0.251:5353
+    pub fn mdns() -> MulticastSetting {
+        MulticastSetting::new(IpAddr::V4(Ipv4Addr::new(224, 0, 0, 251)), 5353)
+    }
+    /// SSDP group 239.255.255.250:1900
+    pub fn ssdp() -> MulticastSetting {
+        MulticastSetting::new(IpAddr::V4(Ipv4Addr::new(239, 255, 255, 250)), 1900)
+    }
+    pub fn new(group: IpAddr, port: u16) -> MulticastSetting {
+        MulticastSetting {
+            group,
+            port,
+            interface_addr: None,
+            interface_index: 0,
+            window_ms: DEFAULT_MULTICAST_WINDOW_MS,
+        }
+    }
+}
+
+/// Outcome of a group test
+#[derive(Clone, Copy, Debug, PartialEq, Eq, TS)]
+pub enum MulticastStatus {
+    /// The IGMP/MLD join was refused, so nothing could be received
+    JoinFailed,
+    /// Joined, but no traffic arrived within the window
+    Silent,
+    /// Joined and traffic arrived
+    Receiving,
+}
+
+/// Traffic seen on one group
+#[derive(Clone, Debug, PartialEq, TS)]
+pub struct MulticastResult {
+    pub group: IpAddr,
+    pub port: u16,
+    pub status: MulticastStatus,
+    /// OS error of a failed bind or join
+    pub join_error: Option<String>,
+    pub packets: u32,
+    #[ts(type = "number")]
+    pub bytes: u64,
+    /// Distinct sources of the received datagrams
+    pub senders: Vec<IpAddr>,
+    /// Time from the join to the first datagram
+    pub first_packet_ms: Option<f64>,
+    /// Set when the window was cut short
+    pub cancelled: bool,
+}
+
+impl MulticastResult {
+    fn join_failed(setting: &MulticastSetting, e: io::Error) -> MulticastResult {
+        MulticastResult {
+            group: setting.group,
+            port: setting.port,
+            status: MulticastStatus::JoinFailed,
+            join_error: Some(e.to_string()),
+            packets: 0,
+            bytes: 0,
+            senders: Vec::new(),
+            first_packet_ms: None,
+            cancelled: false,
+        }
+    }
+}
+
+/// Address to bind the listening socket to. On Linux this is the group
+/// itself, so unicast datagrams to the port and other groups joined on the
+/// host are not counted. Elsewhere binding to a group is not portable and
+/// the wildcard address is used.
+fn bind_addr(setting: &MulticastSetting) -> SocketAddr {
+    let on_group = cfg!(target_os = "linux");
+    match setting.group {
+        IpAddr::V4(group) => {
+            let ip = if on_group {
+                group
+            } else {
+                Ipv4Addr::UNSPECIFIED
+            };
+            SocketAddr::new(IpAddr::V4(ip), setting.port)
+        }
+        IpAddr::V6(group) => {
+            let (ip, scope) = if on_group {
+                (group, setting.interface_index)
+            } else {
+                (Ipv6Addr::UNSPECIFIED, 0)
+            };
+            SocketAddr::V6(SocketAddrV6::new(ip, setting.port, 0, scope))
+        }
+    }
+}
+
+fn join(setting: &MulticastSetting) -> io::Result<UdpSocket> {
+    if !setting.group.is_multicast() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("{} is not a multicast address", setting.group),
+        ));
+    }
+    let socket = bind_udp_shared(bind_addr(setting))?;
+    match setting.group {
+        IpAddr::V4(group) => {
+            let iface = setting.interface_addr.unwrap_or(Ipv4Addr::UNSPECIFIED);
+            socket.join_multicast_v4(&group, &iface)?;
+            Ok(socket)
+        }
+        IpAddr::V6(group) => {
+            socket.join_multicast_v6(&group, setting.interface_index)?;
+            Ok(socket)
+        }
+    }
+}
+
+/// Join `setting.group` and count the datagrams that arrive within
+/// `window_ms`. A refused join is reported as [`MulticastStatus::JoinFailed`]
+/// rather than as a silent group.
+pub fn test_multicast_group(
+    setting: &MulticastSetting,
+    token: &CancellationToken,
+) -> MulticastResult {
+    let socket = match join(setting) {
+        Ok(socket) => socket,
+        Err(e) => return MulticastResult::join_failed(setting, e),
+    };
+    let start = Instant::now();
+    let deadline = start + Duration::from_millis(setting.window_ms);
+    let mut result = MulticastResult {
+        group: setting.group,
+        port: setting.port,
+        status: MulticastStatus::Silent,
+        join_error: None,
+        packets: 0,
+        bytes: 0,
+        senders: Vec::new(),
+        first_packet_ms: None,
+        cancelled: false,
+    };
+    let mut buf = [0u8; 65535];
+    loop {
+        if token.is_cancelled() {
+            result.cancelled = true;
+            break;
+        }
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        if remaining.is_zero() {
+            break;
+        }
+        if socket
+            .set_read_timeout(Some(remaining.min(POLL_INTERVAL)))
+            .is_err()
+        {
+            break;
+        }
+        match socket.recv_from(&mut buf) {
+            Ok((n, from)) => {
+                if result.first_packet_ms.is_none() {
+                    result.first_packet_ms = Some(start.elapsed().as_secs_f64() * 1000.0);
+                }
+                result.packets += 1;
+                result.bytes += n as u64;
+                if !result.senders.contains(&from.ip()) {
+                    result.senders.push(from.ip());
+                }
+            }
+            Err(e)
+                if matches!(
+                    e.kind(),
+                    io::ErrorKind::WouldBlock
+                        | io::ErrorKind::TimedOut
+                        | io::ErrorKind::Interrupted
+                ) => {}
+            Err(_) => break,
+        }
+    }
+    if result.packets > 0 {
+        result.status = MulticastStatus::Receiving;
+    }
+    result
+}
+
+/// Test several groups at once. Groups not started before cancellation are
+/// left out.
+pub fn test_multicast_groups(
+    settings: &[MulticastSetting],
+    token: &CancellationToken,
+) -> Vec<MulticastResult> {
+    map_concurrent(settings, MULTICAST_CONCURRENCY, token, |s| {
+        test_multicast_group(s, token)
+    })
+    .into_iter()
+    .flatten()
+    .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+
+    fn free_port() -> u16 {
+        UdpSocket::bind("0.0.0.0:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port()
+    }
+
+    #[test]
+    fn local_sender_is_received() {
+        let group = Ipv4Addr::new(239, 255, 77, 77);
+        let port = free_port();
+        let setting = MulticastSetting {
+            window_ms: 2000,
+            ..MulticastSetting::new(IpAddr::V4(group), port)
+        };
+        let token = CancellationToken::new();
+        let listener = thread::spawn(move || test_multicast_group(&setting, &token));
+        let sender = UdpSocket::bind("0.0.0.0:0").unwrap();
+        sender.set_multicast_loop_v4(true).unwrap();
+        for _ in 0..10 {
+            thread::sleep(Duration::from_millis(100));
+            let _ = sender.send_to(b"netdia", (group, port));
+        }
+        let result = listener.join().unwrap();
+        assert_eq!(result.status, MulticastStatus::Receiving, "{:?}", result);
+        assert!(result.packets > 0);
+        assert_eq!(result.bytes, result.packets as u64 * 6);
+        assert!(result.first_packet_ms.is_some());
+        assert!(result.join_error.is_none());
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn unicast_to_the_port_is_not_counted() {
+        let group = Ipv4Addr::new(239, 255, 77, 79);
+        let port = free_port();
+        let setting = MulticastSetting {
+            window_ms: 500,
+            ..MulticastSetting::new(IpAddr::V4(group), port)
+        };
+        let token = CancellationToken::new();
+        let listener = thread::spawn(move || test_multicast_group(&setting, &token));
+        let sender = UdpSocket::bind("0.0.0.0:0").unwrap();
+        for _ in 0..4 {
+            thread::sleep(Duration::from_millis(50));
+            let _ = sender.send_to(b"netdia", (Ipv4Addr::LOCALHOST, port));
+        }
+        let result = listener.join().unwrap();
+        assert_eq!(result.status, MulticastStatus::Silent, "{:?}", result);
+    }
+
+    #[test]
+    fn join_failure_is_not_silence() {
+        let setting = MulticastSetting {
+            interface_addr: Some(Ipv4Addr::new(192, 0, 2, 254)),
+            window_ms: 100,
+            ..MulticastSetting::new(IpAddr::V4(Ipv4Addr::new(239, 255, 77, 78)), free_port())
+        };
+        let result = test_multicast_group(&setting, &CancellationToken::new());
+        assert_eq!(result.status, MulticastStatus::JoinFailed);
+        assert!(result.join_error.is_some());
+    }
+
+    #[test]
+    fn unicast_group_is_rejected() {
+        let setting = MulticastSetting::new(IpAddr::V4(Ipv4Addr::LOCALHOST), free_port());
+        let result = test_multicast_group(&setting, &CancellationToken::new());
+        assert_eq!(result.status, MulticastStatus::JoinFailed);
+    }
+}
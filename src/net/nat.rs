@@ -0,0 +1,199 @@
+//! NAT assessment from the addresses seen at each layer
+use super::geo::{GeoCache, GeoInfo, GeoLookup};
+use crate::http::{HttpError, HttpTransport, Url};
+use std::net::IpAddr;
+use std::time::Duration;
+
+/// Whether `ip` is in the RFC 6598 shared address space, 100.64.0.0/10,
+/// used by carrier-grade NAT
+pub fn is_shared_address(ip: &IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(v4) => {
+            let o = v4.octets();
+            o[0] == 100 && (o[1] & 0xc0) == 64
+        }
+        IpAddr::V6(_) => false,
+    }
+}
+
+/// Whether `ip` cannot be reached from the internet: RFC 1918, shared,
+/// link-local or unique local
+fn is_internal(ip: &IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(v4) => v4.is_private() || v4.is_link_local() || is_shared_address(ip),
+        IpAddr::V6(v6) => {
+            let s = v6.segments()[0];
+            (s & 0xfe00) == 0xfc00 || (s & 0xffc0) == 0xfe80
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum NatVerdict {
+    /// The host has the public address itself
+    NoNat,
+    /// One NAT, normally the home router
+    Nat,
+    /// The router's WAN side is itself behind another private NAT
+    DoubleNat,
+    /// The provider translates addresses from the shared address space
+    Cgnat,
+    /// No public address to compare against
+    Unknown,
+}
+
+/// Addresses compared and the verdict drawn from them
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct NatAssessment {
+    pub local_ip: Option<IpAddr>,
+    /// WAN address the gateway reports, e.g. over UPnP
+    pub gateway_external_ip: Option<IpAddr>,
+    /// Address seen by an echo service on the internet
+    pub public_ip: Option<IpAddr>,
+    pub verdict: NatVerdict,
+    /// Why the verdict was reached
+    pub reason: String,
+}
+
+/// Classify the NAT between this host and the internet
+pub fn assess_nat(
+    local_ip: Option<IpAddr>,
+    gateway_external_ip: Option<IpAddr>,
+    public_ip: Option<IpAddr>,
+) -> NatAssessment {
+    let (verdict, reason) = match (local_ip, gateway_external_ip, public_ip) {
+        (_, _, None) => (NatVerdict::Unknown, "Public address unknown".to_string()),
+        (Some(local), _, Some(public)) if local == public => (
+            NatVerdict::NoNat,
+            "Local address is the public address".to_string(),
+        ),
+        (_, Some(gw), Some(_)) if is_shared_address(&gw) => (
+            NatVerdict::Cgnat,
+            format!("Gateway WAN address {} is in 100.64.0.0/10", gw),
+        ),
+        (_, Some(gw), Some(_)) if is_internal(&gw) => (
+            NatVerdict::DoubleNat,
+            format!("Gateway WAN address {} is private", gw),
+        ),
+        (_, Some(gw), Some(public)) if gw != public => (
+            NatVerdict::DoubleNat,
+            format!(
+                "Gateway WAN address {} differs from public address {}",
+                gw, public
+            ),
+        ),
+        (Some(local), None, Some(_)) if is_shared_address(&local) => (
+            NatVerdict::Cgnat,
+            format!("Local address {} is in 100.64.0.0/10", local),
+        ),
+        _ => (
+            NatVerdict::Nat,
+            "Public address differs from the local address".to_string(),
+        ),
+    };
+    NatAssessment {
+        local_ip,
+        gateway_external_ip,
+        public_ip,
+        verdict,
+        reason,
+    }
+}
+
+/// Location of the public address, through the process-wide geo cache
+pub fn public_ip_geo<L: GeoLookup>(backend: &L, public_ip: IpAddr) -> Result<GeoInfo, String> {
+    GeoCache::shared(backend).lookup(public_ip)
+}
+
+/// Public address as reported by an echo service returning it as plain text
+pub fn fetch_public_ip<T: HttpTransport>(
+    transport: &T,
+    url: &str,
+    timeout: Duration,
+) -> Result<IpAddr, HttpError> {
+    let response = transport.get(&Url::parse(url)?, timeout)?;
+    if response.status >= 400 {
+        return Err(HttpError::Protocol(format!(
+            "HTTP status {}",
+            response.status
+        )));
+    }
+    let body = String::from_utf8_lossy(&response.body);
+    body.trim()
+        .parse()
+        .map_err(|_| HttpError::Protocol(format!("Not an IP address: {}", body.trim())))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::http::tests::serve;
+
+    fn ip(s: &str) -> Option<IpAddr> {
+        Some(s.parse().unwrap())
+    }
+
+    #[test]
+    fn verdicts() {
+        let cases = [
+            (
+                ip("203.0.113.5"),
+                None,
+                ip("203.0.113.5"),
+                NatVerdict::NoNat,
+            ),
+            (
+                ip("192.168.1.10"),
+                ip("203.0.113.5"),
+                ip("203.0.113.5"),
+                NatVerdict::Nat,
+            ),
+            (ip("192.168.1.10"), None, ip("203.0.113.5"), NatVerdict::Nat),
+            (
+                ip("192.168.1.10"),
+                ip("100.72.3.4"),
+                ip("203.0.113.5"),
+                NatVerdict::Cgnat,
+            ),
+            (
+                ip("100.100.0.7"),
+                None,
+                ip("203.0.113.5"),
+                NatVerdict::Cgnat,
+            ),
+            (
+                ip("192.168.1.10"),
+                ip("10.0.0.2"),
+                ip("203.0.113.5"),
+                NatVerdict::DoubleNat,
+            ),
+            (
+                ip("192.168.1.10"),
+                ip("198.51.100.9"),
+                ip("203.0.113.5"),
+                NatVerdict::DoubleNat,
+            ),
+            (
+                ip("192.168.1.10"),
+                ip("10.0.0.2"),
+                None,
+                NatVerdict::Unknown,
+            ),
+        ];
+        for (local, gw, public, expected) in cases {
+            let assessment = assess_nat(local, gw, public);
+            assert_eq!(assessment.verdict, expected, "{:?}", assessment);
+        }
+        assert!(!is_shared_address(&"100.128.0.1".parse().unwrap()));
+        assert!(is_shared_address(&"100.127.255.255".parse().unwrap()));
+    }
+
+    #[test]
+    fn public_ip_from_echo_service() {
+        let url = serve(vec![
+            b"HTTP/1.1 200 OK\r\nContent-Length: 12\r\n\r\n203.0.113.5\n".to_vec(),
+        ]);
+        let ip = fetch_public_ip(&crate::http::TcpTransport, &url, Duration::from_secs(1));
+        assert_eq!(ip.unwrap(), "203.0.113.5".parse::<IpAddr>().unwrap());
+    }
+}
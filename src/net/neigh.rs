@@ -0,0 +1,199 @@
+//! Neighbor (ARP / NDP) table
+use super::mac::MacAddr;
+use std::io;
+use std::net::IpAddr;
+
+/// Neighbor Unreachability Detection state, common to all platforms
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum NudState {
+    Incomplete,
+    Reachable,
+    Stale,
+    Delay,
+    Probe,
+    Failed,
+    /// Static entry
+    Permanent,
+    /// Entry without NUD, e.g. on point-to-point links
+    Noarp,
+    Unknown,
+}
+
+impl NudState {
+    /// Map a Linux `ip neigh` state name
+    pub fn from_linux(state: &str) -> Option<NudState> {
+        match state {
+            "INCOMPLETE" => Some(NudState::Incomplete),
+            "REACHABLE" => Some(NudState::Reachable),
+            "STALE" => Some(NudState::Stale),
+            "DELAY" => Some(NudState::Delay),
+            "PROBE" => Some(NudState::Probe),
+            "FAILED" => Some(NudState::Failed),
+            "PERMANENT" => Some(NudState::Permanent),
+            "NOARP" => Some(NudState::Noarp),
+            "NONE" => Some(NudState::Unknown),
+            _ => None,
+        }
+    }
+    /// Map a BSD / macOS `ndp -an` state letter
+    pub fn from_ndp(state: &str) -> NudState {
+        match state {
+            "I" => NudState::Incomplete,
+            "R" => NudState::Reachable,
+            "S" => NudState::Stale,
+            "D" => NudState::Delay,
+            "P" => NudState::Probe,
+            _ => NudState::Unknown,
+        }
+    }
+    /// Whether the neighbor is believed to be reachable right now
+    pub fn is_usable(&self) -> bool {
+        matches!(
+            self,
+            NudState::Reachable
+                | NudState::Stale
+                | NudState::Delay
+                | NudState::Probe
+                | NudState::Permanent
+                | NudState::Noarp
+        )
+    }
+    /// Whether the entry proves the neighbor answered recently. Stale, Delay
+    /// and Probe entries linger long after the device is gone.
+    pub fn is_confirmed(&self) -> bool {
+        matches!(
+            self,
+            NudState::Reachable | NudState::Permanent | NudState::Noarp
+        )
+    }
+}
+
+/// Entry of the neighbor table
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct NeighborHost {
+    pub ip: IpAddr,
+    pub mac: Option<MacAddr>,
+    pub iface: String,
+    /// Advertised itself as a router (IPv6)
+    pub router: bool,
+    pub state: NudState,
+}
+
+/// Source of neighbor table snapshots
+pub trait NeighborSource: Sync {
+    fn neighbors(&self) -> io::Result<Vec<NeighborHost>>;
+}
+
+/// Neighbor table of the operating system
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SystemNeighbors;
+
+impl NeighborSource for SystemNeighbors {
+    fn neighbors(&self) -> io::Result<Vec<NeighborHost>> {
+        get_neighbors()
+    }
+}
+
+/// Neighbor table of this host
+#[cfg(target_os = "linux")]
+pub fn get_neighbors() -> io::Result<Vec<NeighborHost>> {
+    let output = std::process::Command::new("ip")
+        .args(["neigh", "show"])
+        .output()?;
+    if !output.status.success() {
+        return Err(io::Error::other(
+            String::from_utf8_lossy(&output.stderr).trim().to_string(),
+        ));
+    }
+    Ok(parse_ip_neigh(&String::from_utf8_lossy(&output.stdout)))
+}
+
+/// Neighbor table of this host
+#[cfg(not(target_os = "linux"))]
+pub fn get_neighbors() -> io::Result<Vec<NeighborHost>> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "Reading the neighbor table is not supported on this platform",
+    ))
+}
+
+/// Parse Linux `ip neigh show` output
+pub fn parse_ip_neigh(content: &str) -> Vec<NeighborHost> {
+    content
+        .lines()
+        .filter_map(|line| {
+            let tokens: Vec<&str> = line.split_whitespace().collect();
+            let ip: IpAddr = tokens.first()?.parse().ok()?;
+            let mut host = NeighborHost {
+                ip,
+                mac: None,
+                iface: String::new(),
+                router: false,
+                state: NudState::Unknown,
+            };
+            let mut i = 1;
+            while i < tokens.len() {
+                match tokens[i] {
+                    "dev" => {
+                        host.iface = tokens.get(i + 1)?.to_string();
+                        i += 1;
+                    }
+                    "lladdr" => {
+                        host.mac = tokens.get(i + 1).and_then(|m| m.parse().ok());
+                        i += 1;
+                    }
+                    "router" => host.router = true,
+                    // Transitional entries list several states; the last wins
+                    other => {
+                        if let Some(state) = NudState::from_linux(other) {
+                            host.state = state;
+                        }
+                    }
+                }
+                i += 1;
+            }
+            Some(host)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const IP_NEIGH: &str = "\
+192.168.1.1 dev eth0 lladdr 00:11:22:33:44:55 REACHABLE
+fe80::1 dev eth0 lladdr 00:11:22:33:44:55 router STALE
+2001:db8::5 dev eth0 lladdr aa:bb:cc:dd:ee:ff DELAY
+2001:db8::6 dev eth0 lladdr aa:bb:cc:dd:ee:01 PROBE
+2001:db8::9 dev eth0  FAILED
+2001:db8::a dev eth0  INCOMPLETE
+ff02::16 dev eth0 lladdr 33:33:00:00:00:16 NOARP
+10.0.0.1 dev wg0 lladdr 02:00:00:00:00:01 PERMANENT
+";
+
+    #[test]
+    fn parse_nud_states() {
+        let neighbors = parse_ip_neigh(IP_NEIGH);
+        let states: Vec<NudState> = neighbors.iter().map(|n| n.state).collect();
+        assert_eq!(
+            states,
+            vec![
+                NudState::Reachable,
+                NudState::Stale,
+                NudState::Delay,
+                NudState::Probe,
+                NudState::Failed,
+                NudState::Incomplete,
+                NudState::Noarp,
+                NudState::Permanent,
+            ]
+        );
+        assert!(neighbors[1].router);
+        assert_eq!(neighbors[1].iface, "eth0");
+        assert_eq!(neighbors[1].mac, "00:11:22:33:44:55".parse().ok());
+        assert_eq!(neighbors[4].mac, None);
+        assert!(!neighbors[4].state.is_usable());
+        assert_eq!(NudState::from_ndp("S"), NudState::Stale);
+    }
+}
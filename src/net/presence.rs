@@ -0,0 +1,232 @@
+//! Live table of LAN devices built from neighbor table snapshots
+use super::mac::{MacAddr, OuiDb};
+use super::neigh::{NeighborHost, NeighborSource};
+use crate::cancel::CancellationToken;
+use crate::probe::cancellable_sleep;
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::time::{Duration, Instant};
+use ts_rs::TS;
+
+/// Event name emitted when a device appears
+pub const NEIGHBOR_JOIN_EVENT: &str = "neighbor:join";
+/// Event name emitted when a device is gone
+pub const NEIGHBOR_LEAVE_EVENT: &str = "neighbor:leave";
+pub const DEFAULT_POLL_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Debouncing of presence changes
+#[derive(Clone, Copy, Debug, PartialEq, Eq, TS)]
+pub struct PresenceSetting {
+    /// A device must be seen for this long before it joins
+    #[ts(type = "Duration")]
+    pub join_after: Duration,
+    /// A device must go unconfirmed for this long before it leaves
+    #[ts(type = "Duration")]
+    pub leave_after: Duration,
+}
+
+impl Default for PresenceSetting {
+    fn default() -> Self {
+        PresenceSetting {
+            join_after: Duration::ZERO,
+            leave_after: Duration::from_secs(60),
+        }
+    }
+}
+
+/// Device on the LAN, as reported with [`NEIGHBOR_JOIN_EVENT`]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct NeighborDevice {
+    pub ip: IpAddr,
+    pub mac: Option<MacAddr>,
+    /// Vendor of `mac`, if registered
+    pub vendor: Option<String>,
+    pub iface: String,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum PresenceEvent {
+    Join(NeighborDevice),
+    Leave(NeighborDevice),
+}
+
+#[derive(Debug)]
+struct Tracked {
+    device: NeighborDevice,
+    first_seen: Instant,
+    last_seen: Instant,
+    joined: bool,
+}
+
+/// Turns neighbor table snapshots into debounced join and leave events
+#[derive(Debug)]
+pub struct PresenceMonitor<'a> {
+    setting: PresenceSetting,
+    oui: &'a OuiDb,
+    tracked: HashMap<IpAddr, Tracked>,
+}
+
+impl<'a> PresenceMonitor<'a> {
+    pub fn new(setting: PresenceSetting, oui: &'a OuiDb) -> PresenceMonitor<'a> {
+        PresenceMonitor {
+            setting,
+            oui,
+            tracked: HashMap::new(),
+        }
+    }
+    /// Devices currently present, by address
+    pub fn devices(&self) -> Vec<&NeighborDevice> {
+        let mut devices: Vec<&NeighborDevice> = self
+            .tracked
+            .values()
+            .filter(|t| t.joined)
+            .map(|t| &t.device)
+            .collect();
+        devices.sort_by_key(|d| d.ip);
+        devices
+    }
+    fn device(&self, host: &NeighborHost) -> NeighborDevice {
+        NeighborDevice {
+            ip: host.ip,
+            mac: host.mac,
+            vendor: host
+                .mac
+                .filter(|mac| !mac.is_locally_administered())
+                .and_then(|mac| self.oui.get(&mac))
+                .map(|e| e.vendor.clone()),
+            iface: host.iface.clone(),
+        }
+    }
+    /// Feed a neighbor table snapshot taken at `now`
+    pub fn observe(&mut self, neighbors: &[NeighborHost], now: Instant) -> Vec<PresenceEvent> {
+        let mut events = Vec::new();
+        for host in neighbors {
+            if !host.state.is_confirmed() || host.ip.is_multicast() {
+                continue;
+            }
+            let device = self.device(host);
+            let tracked = self.tracked.entry(host.ip).or_insert_with(|| Tracked {
+                device: device.clone(),
+                first_seen: now,
+                last_seen: now,
+                joined: false,
+            });
+            tracked.last_seen = now;
+            if !tracked.joined {
+                tracked.device = device;
+            }
+        }
+        let setting = self.setting;
+        self.tracked.retain(|_, t| {
+            let missing = now.saturating_duration_since(t.last_seen);
+            if missing >= setting.leave_after {
+                if t.joined {
+                    events.push(PresenceEvent::Leave(t.device.clone()));
+                }
+                return false;
+            }
+            // Not seen in this snapshot and not joined yet: start over
+            if !missing.is_zero() && !t.joined {
+                return false;
+            }
+            if !t.joined && now.saturating_duration_since(t.first_seen) >= setting.join_after {
+                t.joined = true;
+                events.push(PresenceEvent::Join(t.device.clone()));
+            }
+            true
+        });
+        events.sort_by_key(|e| match e {
+            PresenceEvent::Join(d) | PresenceEvent::Leave(d) => d.ip,
+        });
+        events
+    }
+}
+
+/// Poll `source` until cancelled, calling `on_event` for each join and leave
+pub fn watch_neighbors<S, F>(
+    source: &S,
+    poll_interval: Duration,
+    setting: PresenceSetting,
+    token: &CancellationToken,
+    mut on_event: F,
+) where
+    S: NeighborSource,
+    F: FnMut(&PresenceEvent),
+{
+    let mut monitor = PresenceMonitor::new(setting, OuiDb::bundled());
+    loop {
+        if let Ok(neighbors) = source.neighbors() {
+            monitor
+                .observe(&neighbors, Instant::now())
+                .iter()
+                .for_each(&mut on_event);
+        }
+        if cancellable_sleep(token, poll_interval).is_cancelled() {
+            break;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::net::neigh::NudState;
+
+    fn host(last: u8, state: NudState) -> NeighborHost {
+        NeighborHost {
+            ip: IpAddr::from([192, 168, 1, last]),
+            mac: Some(MacAddr([0xb8, 0x27, 0xeb, 0, 0, last])),
+            iface: "eth0".to_string(),
+            router: false,
+            state,
+        }
+    }
+
+    #[test]
+    fn device_joins_then_leaves_after_debounce() {
+        let oui = OuiDb::from_csv("B827EB,Raspberry Pi Foundation,");
+        let setting = PresenceSetting {
+            join_after: Duration::from_secs(5),
+            leave_after: Duration::from_secs(30),
+        };
+        let mut monitor = PresenceMonitor::new(setting, &oui);
+        let t0 = Instant::now();
+        let at = |s| t0 + Duration::from_secs(s);
+        let pi = || host(7, NudState::Reachable);
+
+        assert!(monitor.observe(&[pi()], at(0)).is_empty());
+        let events = monitor.observe(&[pi()], at(5));
+        let PresenceEvent::Join(joined) = &events[0] else {
+            panic!("{:?}", events);
+        };
+        assert_eq!(joined.vendor.as_deref(), Some("Raspberry Pi Foundation"));
+        assert_eq!(monitor.devices().len(), 1);
+
+        // A missed poll or a failed entry does not make it leave
+        assert!(monitor.observe(&[], at(10)).is_empty());
+        assert!(monitor
+            .observe(&[host(7, NudState::Failed)], at(20))
+            .is_empty());
+        assert!(monitor.observe(&[pi()], at(25)).is_empty());
+
+        let events = monitor.observe(&[], at(55));
+        assert_eq!(events, vec![PresenceEvent::Leave(joined.clone())]);
+        assert!(monitor.devices().is_empty());
+
+        // A stale entry is not a sighting, so the device still leaves
+        assert!(monitor.observe(&[pi()], at(60)).is_empty());
+        assert_eq!(monitor.observe(&[pi()], at(65)).len(), 1);
+        assert!(monitor
+            .observe(&[host(7, NudState::Stale)], at(80))
+            .is_empty());
+        let events = monitor.observe(&[host(7, NudState::Delay)], at(95));
+        assert!(matches!(&events[..], [PresenceEvent::Leave(d)] if d.ip == joined.ip));
+
+        // Seen only briefly: never joins, so never leaves
+        assert!(monitor
+            .observe(&[host(8, NudState::Reachable)], at(130))
+            .is_empty());
+        assert!(monitor.observe(&[], at(131)).is_empty());
+        assert!(monitor.observe(&[], at(170)).is_empty());
+    }
+}
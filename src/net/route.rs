@@ -0,0 +1,250 @@
+//! Routing table
+use super::ipnet::IpNet;
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, UdpSocket};
+
+/// Route flag of unreachable and prohibit routes (`RTF_REJECT`)
+const RTF_REJECT: u32 = 0x0200;
+
+/// Entry of the routing table
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Route {
+    pub destination: IpNet,
+    pub gateway: Option<IpAddr>,
+    pub iface: String,
+    pub metric: u32,
+}
+
+impl Route {
+    pub fn is_default(&self) -> bool {
+        self.destination.prefix_len == 0
+    }
+}
+
+/// Routing table of this host, IPv4 then IPv6. Only the main table is
+/// read; see [`kernel_source`] for what policy routing picks.
+#[cfg(target_os = "linux")]
+pub fn get_routes() -> io::Result<Vec<Route>> {
+    let content = std::fs::read_to_string("/proc/net/route")?;
+    let mut routes = parse_proc_net_route(&content);
+    // Missing when IPv6 is disabled
+    if let Ok(content) = std::fs::read_to_string("/proc/net/ipv6_route") {
+        routes.extend(parse_proc_net_ipv6_route(&content));
+    }
+    Ok(routes)
+}
+
+/// Routing table of this host
+#[cfg(not(target_os = "linux"))]
+pub fn get_routes() -> io::Result<Vec<Route>> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "Reading the routing table is not supported on this platform",
+    ))
+}
+
+/// Gateways of the default routes
+pub fn default_gateways(routes: &[Route]) -> Vec<IpAddr> {
+    let mut gateways: Vec<IpAddr> = routes
+        .iter()
+        .filter(|r| r.is_default())
+        .filter_map(|r| r.gateway)
+        .collect();
+    gateways.dedup();
+    gateways
+}
+
+/// Next hops of all routes, default gateways first, each once
+pub fn next_hops(routes: &[Route]) -> Vec<IpAddr> {
+    let mut hops = default_gateways(routes);
+    for gateway in routes.iter().filter_map(|r| r.gateway) {
+        if !hops.contains(&gateway) {
+            hops.push(gateway);
+        }
+    }
+    hops
+}
+
+/// Route the kernel would pick for `dst`: the longest matching prefix,
+/// then the lowest metric. Policy routing rules are not considered.
+pub fn lookup(routes: &[Route], dst: IpAddr) -> Option<&Route> {
+    routes
+        .iter()
+        .filter(|r| r.destination.contains(&dst))
+        .min_by_key(|r| (std::cmp::Reverse(r.destination.prefix_len), r.metric))
+}
+
+/// Source address the kernel picks for traffic to `dst`, with policy
+/// routing rules applied. Connecting a UDP socket sends nothing.
+pub fn kernel_source(dst: IpAddr) -> io::Result<IpAddr> {
+    let any: IpAddr = match dst {
+        IpAddr::V4(_) => Ipv4Addr::UNSPECIFIED.into(),
+        IpAddr::V6(_) => Ipv6Addr::UNSPECIFIED.into(),
+    };
+    let socket = UdpSocket::bind(SocketAddr::new(any, 0))?;
+    socket.connect(SocketAddr::new(dst, 9))?;
+    Ok(socket.local_addr()?.ip())
+}
+
+fn parse_hex_v4(s: &str) -> Option<Ipv4Addr> {
+    parse_hex_v4_as(s, u32::to_ne_bytes)
+}
+
+/// The address bytes are stored in network byte order and printed as an
+/// integer in host byte order, which `to_bytes` turns back into bytes
+fn parse_hex_v4_as(s: &str, to_bytes: fn(u32) -> [u8; 4]) -> Option<Ipv4Addr> {
+    let n = u32::from_str_radix(s, 16).ok()?;
+    Some(Ipv4Addr::from(to_bytes(n)))
+}
+
+/// Parse the Linux `/proc/net/route` format
+pub fn parse_proc_net_route(content: &str) -> Vec<Route> {
+    content
+        .lines()
+        .skip(1)
+        .filter_map(|line| {
+            let cols: Vec<&str> = line.split_whitespace().collect();
+            if cols.len() < 8 {
+                return None;
+            }
+            let destination = parse_hex_v4(cols[1])?;
+            let gateway = parse_hex_v4(cols[2])?;
+            let mask = parse_hex_v4(cols[7])?;
+            Some(Route {
+                destination: IpNet::new(
+                    IpAddr::V4(destination),
+                    u32::from(mask).leading_ones() as u8,
+                ),
+                gateway: (!gateway.is_unspecified()).then_some(IpAddr::V4(gateway)),
+                iface: cols[0].to_string(),
+                metric: cols[6].parse().ok()?,
+            })
+        })
+        .collect()
+}
+
+/// Parse the Linux `/proc/net/ipv6_route` format. Unreachable and
+/// prohibit routes are left out.
+pub fn parse_proc_net_ipv6_route(content: &str) -> Vec<Route> {
+    let hex_v6 = |s: &str| u128::from_str_radix(s, 16).ok().map(Ipv6Addr::from);
+    content
+        .lines()
+        .filter_map(|line| {
+            let cols: Vec<&str> = line.split_whitespace().collect();
+            if cols.len() < 10 {
+                return None;
+            }
+            let flags = u32::from_str_radix(cols[8], 16).ok()?;
+            if flags & RTF_REJECT != 0 {
+                return None;
+            }
+            let destination = hex_v6(cols[0])?;
+            let gateway = hex_v6(cols[4])?;
+            Some(Route {
+                destination: IpNet::new(
+                    IpAddr::V6(destination),
+                    u8::from_str_radix(cols[1], 16).ok()?,
+                ),
+                gateway: (!gateway.is_unspecified()).then_some(IpAddr::V6(gateway)),
+                iface: cols[9].to_string(),
+                metric: u32::from_str_radix(cols[5], 16).ok()?,
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SAMPLE: &str =
+        "Iface\tDestination\tGateway \tFlags\tRefCnt\tUse\tMetric\tMask\t\tMTU\tWindow\tIRTT
+eth0\t00000000\t0101A8C0\t0003\t0\t0\t100\t00000000\t0\t0\t0
+eth0\t0001A8C0\t00000000\t0001\t0\t0\t100\t00FFFFFF\t0\t0\t0
+";
+
+    #[test]
+    fn parse_default_route() {
+        let routes = parse_proc_net_route(SAMPLE);
+        assert_eq!(routes.len(), 2);
+        assert!(routes[0].is_default());
+        assert_eq!(
+            routes[1].destination,
+            IpNet::new("192.168.1.0".parse().unwrap(), 24)
+        );
+        assert_eq!(routes[1].gateway, None);
+        assert_eq!(
+            default_gateways(&routes),
+            vec!["192.168.1.1".parse::<IpAddr>().unwrap()]
+        );
+    }
+
+    const SAMPLE_V6: &str = "\
+20010db8000000010000000000000000 40 00000000000000000000000000000000 00 00000000000000000000000000000000 00000100 00000001 00000000 00000001     eth0
+00000000000000000000000000000000 00 00000000000000000000000000000000 00 20010db8000000010000000000000001 00000400 00000002 00000000 00000003     eth0
+00000000000000000000000000000000 00 00000000000000000000000000000000 00 00000000000000000000000000000000 ffffffff 00000001 00000000 00200200       lo
+";
+
+    #[test]
+    fn parse_ipv6_routes() {
+        let routes = parse_proc_net_ipv6_route(SAMPLE_V6);
+        // The unreachable catch-all on lo is left out
+        assert_eq!(routes.len(), 2);
+        assert_eq!(
+            routes[0].destination,
+            IpNet::new("2001:db8:0:1::".parse().unwrap(), 64)
+        );
+        assert_eq!(routes[0].metric, 256);
+        assert!(routes[1].is_default());
+        assert_eq!(routes[1].gateway, Some("2001:db8:0:1::1".parse().unwrap()));
+        let local = lookup(&routes, "2001:db8:0:1::20".parse().unwrap()).unwrap();
+        assert_eq!(local.gateway, None);
+        let remote = lookup(&routes, "2001:db8:ff::9".parse().unwrap()).unwrap();
+        assert!(remote.is_default());
+        assert_eq!(lookup(&routes, "192.0.2.1".parse().unwrap()), None);
+    }
+
+    #[test]
+    fn kernel_source_for_loopback() {
+        let src = kernel_source(IpAddr::V4(Ipv4Addr::LOCALHOST)).unwrap();
+        assert!(src.is_loopback());
+    }
+
+    #[test]
+    fn hex_addresses_in_host_byte_order() {
+        let gateway = Ipv4Addr::new(192, 168, 1, 1);
+        // As printed on a little-endian and on a big-endian host
+        assert_eq!(parse_hex_v4_as("0101A8C0", u32::to_le_bytes), Some(gateway));
+        assert_eq!(parse_hex_v4_as("C0A80101", u32::to_be_bytes), Some(gateway));
+        let printed = format!("{:08X}", u32::from_ne_bytes(gateway.octets()));
+        assert_eq!(parse_hex_v4(&printed), Some(gateway));
+    }
+
+    #[test]
+    fn next_hops_include_non_default_routes() {
+        let mut routes = parse_proc_net_route(SAMPLE);
+        routes.push(Route {
+            destination: IpNet::new("10.0.0.0".parse().unwrap(), 8),
+            gateway: Some("192.168.1.254".parse().unwrap()),
+            iface: "eth0".to_string(),
+            metric: 100,
+        });
+        assert_eq!(
+            next_hops(&routes),
+            vec![
+                "192.168.1.1".parse::<IpAddr>().unwrap(),
+                "192.168.1.254".parse().unwrap()
+            ]
+        );
+    }
+
+    #[test]
+    fn lookup_prefers_longest_prefix() {
+        let routes = parse_proc_net_route(SAMPLE);
+        let local = lookup(&routes, "192.168.1.20".parse().unwrap()).unwrap();
+        assert_eq!(local.gateway, None);
+        let remote = lookup(&routes, "203.0.113.9".parse().unwrap()).unwrap();
+        assert!(remote.is_default());
+        assert_eq!(lookup(&routes, "2001:db8::1".parse().unwrap()), None);
+    }
+}
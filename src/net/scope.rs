@@ -0,0 +1,170 @@
+//! IPv6 zone (scope id) handling for link-local targets
+use std::fmt;
+use std::net::{IpAddr, Ipv6Addr, SocketAddr, SocketAddrV4, SocketAddrV6};
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ScopeError {
+    /// The address part could not be parsed
+    InvalidAddress(String),
+    /// A link-local IPv6 address was given without `%zone`
+    MissingScope(Ipv6Addr),
+    /// The zone does not name a known interface
+    UnknownInterface(String),
+}
+
+impl fmt::Display for ScopeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ScopeError::InvalidAddress(s) => write!(f, "Invalid IP address: {}", s),
+            ScopeError::MissingScope(ip) => write!(
+                f,
+                "Link-local address {} requires a zone id (e.g. {}%eth0)",
+                ip, ip
+            ),
+            ScopeError::UnknownInterface(name) => write!(f, "Unknown interface: {}", name),
+        }
+    }
+}
+
+impl std::error::Error for ScopeError {}
+
+/// IP address with the interface index used to reach it
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ScopedIp {
+    pub ip: IpAddr,
+    /// Interface index for link-local IPv6 targets, 0 otherwise
+    pub scope_id: u32,
+}
+
+impl ScopedIp {
+    /// `ip` with `scope_id` if it is a link-local IPv6 address, the only
+    /// kind the scope applies to, so one setting can hold targets of both
+    /// kinds
+    pub fn for_target(ip: IpAddr, scope_id: u32) -> ScopedIp {
+        let on_link = matches!(ip, IpAddr::V6(v6) if is_link_local_v6(&v6));
+        ScopedIp {
+            ip,
+            scope_id: if on_link { scope_id } else { 0 },
+        }
+    }
+    /// Socket address for sending to this target
+    pub fn socket_addr(&self, port: u16) -> SocketAddr {
+        match self.ip {
+            IpAddr::V4(ip) => SocketAddr::V4(SocketAddrV4::new(ip, port)),
+            IpAddr::V6(ip) => SocketAddr::V6(SocketAddrV6::new(ip, port, 0, self.scope_id)),
+        }
+    }
+}
+
+/// Whether `ip` is in fe80::/10
+pub fn is_link_local_v6(ip: &Ipv6Addr) -> bool {
+    ip.segments()[0] & 0xffc0 == 0xfe80
+}
+
+/// Parse `addr` or `addr%zone`, resolving interface names with the system table.
+pub fn parse_scoped_ip(s: &str) -> Result<ScopedIp, ScopeError> {
+    parse_scoped_ip_with(s, interface_index)
+}
+
+/// Parse `addr` or `addr%zone`, resolving interface names with `lookup`.
+///
+/// Numeric zones are used as the interface index directly.
+pub fn parse_scoped_ip_with<F>(s: &str, lookup: F) -> Result<ScopedIp, ScopeError>
+where
+    F: Fn(&str) -> Option<u32>,
+{
+    let (addr, zone) = match s.split_once('%') {
+        Some((addr, zone)) => (addr, Some(zone)),
+        None => (s, None),
+    };
+    let ip: IpAddr = addr
+        .parse()
+        .map_err(|_| ScopeError::InvalidAddress(s.to_string()))?;
+    let scope_id = match (ip, zone) {
+        (IpAddr::V4(_), Some(_)) => return Err(ScopeError::InvalidAddress(s.to_string())),
+        (_, Some(zone)) => match zone.parse::<u32>() {
+            Ok(index) => index,
+            Err(_) => lookup(zone).ok_or_else(|| ScopeError::UnknownInterface(zone.to_string()))?,
+        },
+        (IpAddr::V6(v6), None) if is_link_local_v6(&v6) => {
+            return Err(ScopeError::MissingScope(v6))
+        }
+        _ => 0,
+    };
+    Ok(ScopedIp { ip, scope_id })
+}
+
+/// Interface index of `name`
+#[cfg(target_os = "linux")]
+pub fn interface_index(name: &str) -> Option<u32> {
+    if name.contains('/') {
+        return None;
+    }
+    std::fs::read_to_string(format!("/sys/class/net/{}/ifindex", name))
+        .ok()?
+        .trim()
+        .parse()
+        .ok()
+}
+
+/// Interface index of `name`. Only numeric zones are supported on this platform.
+#[cfg(not(target_os = "linux"))]
+pub fn interface_index(_name: &str) -> Option<u32> {
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lookup(name: &str) -> Option<u32> {
+        match name {
+            "eth0" => Some(2),
+            "wlan0" => Some(3),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn link_local_zone_binds_interface() {
+        let target = parse_scoped_ip_with("fe80::1%wlan0", lookup).unwrap();
+        assert_eq!(target.scope_id, 3);
+        match target.socket_addr(0) {
+            SocketAddr::V6(addr) => {
+                assert_eq!(addr.ip(), &"fe80::1".parse::<Ipv6Addr>().unwrap());
+                assert_eq!(addr.scope_id(), 3);
+            }
+            SocketAddr::V4(_) => panic!("expected IPv6"),
+        }
+        assert_eq!(
+            parse_scoped_ip_with("fe80::1%5", lookup).unwrap().scope_id,
+            5
+        );
+    }
+
+    #[test]
+    fn link_local_requires_zone() {
+        assert_eq!(
+            parse_scoped_ip_with("fe80::1", lookup),
+            Err(ScopeError::MissingScope("fe80::1".parse().unwrap()))
+        );
+        assert_eq!(
+            parse_scoped_ip_with("fe80::1%tun9", lookup),
+            Err(ScopeError::UnknownInterface("tun9".to_string()))
+        );
+        assert_eq!(
+            parse_scoped_ip_with("2001:db8::1", lookup)
+                .unwrap()
+                .scope_id,
+            0
+        );
+        assert!(parse_scoped_ip_with("192.0.2.1%eth0", lookup).is_err());
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn loopback_index_from_system() {
+        assert!(interface_index("lo").is_some());
+        assert_eq!(interface_index("../lo"), None);
+    }
+}
@@ -0,0 +1,290 @@
+//! Interfaces, routes and DNS gathered into one report for bug reports
+use super::interface::{Interface, InterfaceSource};
+use super::nat::fetch_public_ip;
+use super::route::{default_gateways, Route};
+use crate::http::HttpTransport;
+use std::fmt::Write as _;
+use std::io;
+use std::net::{IpAddr, Ipv6Addr};
+use std::time::Duration;
+use ts_rs::TS;
+
+/// What to gather and whether to hide identifying details
+#[derive(Clone, Debug, PartialEq, Eq, TS)]
+pub struct SnapshotSetting {
+    /// Drop MAC addresses and the public IP, and mask IPv6 addresses that
+    /// identify the host, see [`redact_ip`]
+    pub redact: bool,
+    /// Plain-text echo service for the public IP. Skipped when `None`.
+    pub public_ip_url: Option<String>,
+    #[ts(type = "number")]
+    pub timeout_ms: u64,
+}
+
+impl Default for SnapshotSetting {
+    fn default() -> Self {
+        SnapshotSetting {
+            redact: false,
+            public_ip_url: None,
+            timeout_ms: 3000,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct NetworkSnapshot {
+    pub interfaces: Vec<Interface>,
+    pub routes: Vec<Route>,
+    /// DNS servers of the up interfaces, each once
+    pub dns_servers: Vec<IpAddr>,
+    pub default_gateways: Vec<IpAddr>,
+    pub public_ip: Option<IpAddr>,
+    pub redacted: bool,
+    /// Sections that could not be gathered, as "section: reason"
+    pub errors: Vec<String>,
+}
+
+impl NetworkSnapshot {
+    /// Plain-text rendering suited to pasting into an issue
+    pub fn to_text(&self) -> String {
+        let mut out = String::new();
+        let _ = writeln!(out, "[interfaces]");
+        for iface in &self.interfaces {
+            let mut flags = vec![if iface.is_up { "up" } else { "down" }];
+            if iface.is_loopback {
+                flags.push("loopback");
+            }
+            let _ = write!(out, "{} (#{}) {}", iface.name, iface.index, flags.join(","));
+            if let Some(mtu) = iface.mtu {
+                let _ = write!(out, " mtu {}", mtu);
+            }
+            if let Some(mac) = iface.mac {
+                let _ = write!(out, " mac {}", super::mac::MacAddr(mac));
+            }
+            let _ = writeln!(out);
+            for addr in &iface.addrs {
+                let _ = writeln!(out, "  {}", addr);
+            }
+        }
+        let _ = writeln!(out, "[routes]");
+        for route in &self.routes {
+            let via = route.gateway.map_or("-".to_string(), |g| g.to_string());
+            let _ = writeln!(
+                out,
+                "{} via {} dev {} metric {}",
+                route.destination, via, route.iface, route.metric
+            );
+        }
+        let list = |ips: &[IpAddr]| {
+            ips.iter()
+                .map(|ip| ip.to_string())
+                .collect::<Vec<_>>()
+                .join(" ")
+        };
+        let _ = writeln!(out, "[dns]\n{}", list(&self.dns_servers));
+        let _ = writeln!(out, "[gateway]\n{}", list(&self.default_gateways));
+        let public_ip = match (self.public_ip, self.redacted) {
+            (_, true) => "(redacted)".to_string(),
+            (Some(ip), _) => ip.to_string(),
+            (None, _) => "-".to_string(),
+        };
+        let _ = writeln!(out, "[public_ip]\n{}", public_ip);
+        if !self.errors.is_empty() {
+            let _ = writeln!(out, "[errors]");
+            for e in &self.errors {
+                let _ = writeln!(out, "{}", e);
+            }
+        }
+        out
+    }
+}
+
+/// Mask the parts of `ip` that identify the host or its network. A global
+/// IPv6 address keeps only its first 16 bits, and an EUI-64 interface id,
+/// which embeds the MAC address, is zeroed whatever the scope. IPv4
+/// addresses are returned as is.
+pub fn redact_ip(ip: IpAddr) -> IpAddr {
+    match ip {
+        IpAddr::V4(_) => ip,
+        IpAddr::V6(v6) => IpAddr::V6(redact_v6(v6)),
+    }
+}
+
+fn redact_v6(v6: Ipv6Addr) -> Ipv6Addr {
+    let mut octets = v6.octets();
+    let eui64 = octets[11] == 0xff && octets[12] == 0xfe;
+    let global = octets[0] & 0xe0 == 0x20;
+    if global {
+        octets[2..].fill(0);
+    } else if eui64 {
+        octets[8..].fill(0);
+    }
+    Ipv6Addr::from(octets)
+}
+
+/// Assemble a snapshot from already gathered parts
+pub fn build_snapshot(
+    interfaces: io::Result<Vec<Interface>>,
+    routes: io::Result<Vec<Route>>,
+    public_ip: Option<Result<IpAddr, String>>,
+    redact: bool,
+) -> NetworkSnapshot {
+    let mut snapshot = NetworkSnapshot {
+        redacted: redact,
+        ..Default::default()
+    };
+    match interfaces {
+        Ok(interfaces) => snapshot.interfaces = interfaces,
+        Err(e) => snapshot.errors.push(format!("interfaces: {}", e)),
+    }
+    match routes {
+        Ok(routes) => snapshot.routes = routes,
+        Err(e) => snapshot.errors.push(format!("routes: {}", e)),
+    }
+    for iface in snapshot.interfaces.iter().filter(|i| i.is_up) {
+        for ip in &iface.dns_servers {
+            if !snapshot.dns_servers.contains(ip) {
+                snapshot.dns_servers.push(*ip);
+            }
+        }
+    }
+    snapshot.default_gateways = default_gateways(&snapshot.routes);
+    match public_ip {
+        Some(Ok(ip)) => snapshot.public_ip = Some(ip),
+        Some(Err(e)) => snapshot.errors.push(format!("public_ip: {}", e)),
+        None => {}
+    }
+    if redact {
+        for iface in &mut snapshot.interfaces {
+            iface.mac = None;
+            iface
+                .addrs
+                .iter_mut()
+                .for_each(|n| n.addr = redact_ip(n.addr));
+            iface
+                .dns_servers
+                .iter_mut()
+                .for_each(|ip| *ip = redact_ip(*ip));
+            iface
+                .ipv6_flags
+                .iter_mut()
+                .for_each(|(a, _)| *a = redact_v6(*a));
+        }
+        for route in &mut snapshot.routes {
+            route.destination.addr = redact_ip(route.destination.addr);
+            route.gateway = route.gateway.map(redact_ip);
+        }
+        snapshot
+            .dns_servers
+            .iter_mut()
+            .for_each(|ip| *ip = redact_ip(*ip));
+        snapshot
+            .default_gateways
+            .iter_mut()
+            .for_each(|ip| *ip = redact_ip(*ip));
+        snapshot.public_ip = None;
+    }
+    snapshot
+}
+
+/// Gather the snapshot from `source`, the system routing table and, if
+/// configured, the public IP service over `transport`
+pub fn network_snapshot<S: InterfaceSource, T: HttpTransport>(
+    source: &S,
+    transport: &T,
+    setting: &SnapshotSetting,
+) -> NetworkSnapshot {
+    let timeout = Duration::from_millis(setting.timeout_ms);
+    // Not looked up at all when it would be redacted anyway
+    let public_ip = setting
+        .public_ip_url
+        .as_deref()
+        .filter(|_| !setting.redact)
+        .map(|url| fetch_public_ip(transport, url, timeout).map_err(|e| e.to_string()));
+    build_snapshot(
+        source.interfaces(),
+        super::route::get_routes(),
+        public_ip,
+        setting.redact,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::net::ipnet::IpNet;
+
+    fn parts() -> (Vec<Interface>, Vec<Route>) {
+        let mut eth0 = Interface::new(2, "eth0");
+        eth0.is_up = true;
+        eth0.mtu = Some(1500);
+        eth0.mac = Some([0x02, 0, 0, 0, 0, 0x01]);
+        eth0.addrs = vec![IpNet::new("192.168.1.20".parse().unwrap(), 24)];
+        eth0.dns_servers = vec!["192.168.1.1".parse().unwrap()];
+        let route = Route {
+            destination: IpNet::new("0.0.0.0".parse().unwrap(), 0),
+            gateway: Some("192.168.1.1".parse().unwrap()),
+            iface: "eth0".to_string(),
+            metric: 100,
+        };
+        (vec![eth0], vec![route])
+    }
+
+    #[test]
+    fn snapshot_has_every_section() {
+        let (interfaces, routes) = parts();
+        let public = || Some(Ok("203.0.113.9".parse().unwrap()));
+        let snapshot = build_snapshot(Ok(interfaces.clone()), Ok(routes), public(), false);
+        assert_eq!(
+            snapshot.default_gateways,
+            vec!["192.168.1.1".parse::<IpAddr>().unwrap()]
+        );
+        assert_eq!(snapshot.dns_servers.len(), 1);
+        let text = snapshot.to_text();
+        for section in [
+            "[interfaces]",
+            "[routes]",
+            "[dns]",
+            "[gateway]",
+            "[public_ip]",
+        ] {
+            assert!(text.contains(section), "{} missing from\n{}", section, text);
+        }
+        assert!(text.contains("eth0 (#2) up mtu 1500 mac 02:00:00:00:00:01"));
+        assert!(text.contains("0.0.0.0/0 via 192.168.1.1 dev eth0 metric 100"));
+        assert!(text.contains("203.0.113.9"));
+
+        let unavailable = io::Error::new(io::ErrorKind::Unsupported, "not supported");
+        let redacted = build_snapshot(Ok(interfaces), Err(unavailable), public(), true);
+        let text = redacted.to_text();
+        assert!(!text.contains("02:00:00") && !text.contains("203.0.113.9"));
+        assert_eq!(redacted.errors, vec!["routes: not supported"]);
+        assert!(text.contains("[errors]"));
+    }
+
+    #[test]
+    fn identifying_ipv6_addresses_redacted() {
+        let (mut interfaces, mut routes) = parts();
+        interfaces[0].addrs.extend([
+            IpNet::new("2001:db8:1234:5678:abcd::20".parse().unwrap(), 64),
+            IpNet::new("fe80::200:ff:fe00:1".parse().unwrap(), 64),
+            IpNet::new("fd00::20".parse().unwrap(), 64),
+        ]);
+        routes.push(Route {
+            destination: IpNet::new("::".parse().unwrap(), 0),
+            gateway: Some("fe80::211:22ff:fe33:4455".parse().unwrap()),
+            iface: "eth0".to_string(),
+            metric: 100,
+        });
+        let snapshot = build_snapshot(Ok(interfaces), Ok(routes), None, true);
+        let text = snapshot.to_text();
+        for secret in ["db8:1234", "ff:fe00:1", "fe33:4455"] {
+            assert!(!text.contains(secret), "{} left in\n{}", secret, text);
+        }
+        assert!(text.contains("2001::/64"));
+        assert!(text.contains("fe80::/64"));
+        // Neither global nor derived from the MAC address
+        assert!(text.contains("fd00::20/64"));
+        assert!(text.contains("192.168.1.20/24"));
+    }
+}
@@ -0,0 +1,345 @@
+//! SOCKS5 client (RFC 1928, RFC 1929) for connect probes through a proxy
+use crate::scan::port::{PortProbe, PortProber, PortState};
+use std::fmt;
+use std::io::{self, Read, Write};
+use std::net::{IpAddr, SocketAddr, TcpStream};
+use std::time::{Duration, Instant};
+
+/// Username/password credentials
+#[derive(Clone, PartialEq, Eq)]
+pub struct Socks5Auth {
+    pub username: String,
+    pub password: String,
+}
+
+impl fmt::Debug for Socks5Auth {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Socks5Auth")
+            .field("username", &self.username)
+            .finish_non_exhaustive()
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Socks5Proxy {
+    pub addr: SocketAddr,
+    pub auth: Option<Socks5Auth>,
+}
+
+#[derive(Debug)]
+pub enum Socks5Error {
+    /// Could not reach the proxy itself
+    ProxyUnreachable(io::Error),
+    /// The proxy accepts none of the offered authentication methods
+    NoAcceptableAuth,
+    /// The proxy rejected the credentials
+    AuthFailed,
+    /// The proxy did not speak SOCKS5
+    Protocol(String),
+    /// The proxy could not connect to the target
+    Reply(u8),
+    Timeout,
+    Io(io::Error),
+}
+
+impl Socks5Error {
+    /// Whether the failure lies with the proxy rather than the target
+    pub fn is_proxy_error(&self) -> bool {
+        !matches!(
+            self,
+            Socks5Error::Reply(REPLY_NETWORK_UNREACHABLE..=REPLY_TTL_EXPIRED)
+        )
+    }
+}
+
+impl fmt::Display for Socks5Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Socks5Error::ProxyUnreachable(e) => write!(f, "Cannot reach SOCKS5 proxy: {}", e),
+            Socks5Error::NoAcceptableAuth => {
+                write!(f, "SOCKS5 proxy accepts no offered authentication method")
+            }
+            Socks5Error::AuthFailed => write!(f, "SOCKS5 proxy authentication failed"),
+            Socks5Error::Protocol(msg) => write!(f, "SOCKS5 protocol error: {}", msg),
+            Socks5Error::Reply(code) => write!(f, "SOCKS5 proxy reply: {}", reply_message(*code)),
+            Socks5Error::Timeout => write!(f, "SOCKS5 proxy timed out"),
+            Socks5Error::Io(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl std::error::Error for Socks5Error {}
+
+impl From<io::Error> for Socks5Error {
+    fn from(e: io::Error) -> Self {
+        match e.kind() {
+            io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut => Socks5Error::Timeout,
+            _ => Socks5Error::Io(e),
+        }
+    }
+}
+
+const VERSION: u8 = 5;
+const METHOD_NONE: u8 = 0;
+const METHOD_USERPASS: u8 = 2;
+const METHOD_UNACCEPTABLE: u8 = 0xff;
+const CMD_CONNECT: u8 = 1;
+const ATYP_V4: u8 = 1;
+const ATYP_DOMAIN: u8 = 3;
+const ATYP_V6: u8 = 4;
+
+pub const REPLY_SUCCEEDED: u8 = 0;
+pub const REPLY_NETWORK_UNREACHABLE: u8 = 3;
+pub const REPLY_HOST_UNREACHABLE: u8 = 4;
+pub const REPLY_CONNECTION_REFUSED: u8 = 5;
+pub const REPLY_TTL_EXPIRED: u8 = 6;
+
+fn reply_message(code: u8) -> &'static str {
+    match code {
+        1 => "general failure",
+        2 => "connection not allowed by ruleset",
+        REPLY_NETWORK_UNREACHABLE => "network unreachable",
+        REPLY_HOST_UNREACHABLE => "host unreachable",
+        REPLY_CONNECTION_REFUSED => "connection refused",
+        REPLY_TTL_EXPIRED => "TTL expired",
+        7 => "command not supported",
+        8 => "address type not supported",
+        _ => "unknown error",
+    }
+}
+
+impl Socks5Proxy {
+    pub fn new(addr: SocketAddr) -> Socks5Proxy {
+        Socks5Proxy { addr, auth: None }
+    }
+    fn open(&self, timeout: Duration) -> Result<TcpStream, Socks5Error> {
+        let stream = TcpStream::connect_timeout(&self.addr, timeout)
+            .map_err(Socks5Error::ProxyUnreachable)?;
+        stream.set_read_timeout(Some(timeout))?;
+        stream.set_write_timeout(Some(timeout))?;
+        stream.set_nodelay(true)?;
+        Ok(stream)
+    }
+    /// Greeting and authentication
+    fn handshake(&self, stream: &mut TcpStream) -> Result<(), Socks5Error> {
+        let methods: &[u8] = if self.auth.is_some() {
+            &[METHOD_NONE, METHOD_USERPASS]
+        } else {
+            &[METHOD_NONE]
+        };
+        let mut greeting = vec![VERSION, methods.len() as u8];
+        greeting.extend_from_slice(methods);
+        stream.write_all(&greeting)?;
+        let mut choice = [0u8; 2];
+        stream.read_exact(&mut choice)?;
+        if choice[0] != VERSION {
+            return Err(Socks5Error::Protocol(format!("version {}", choice[0])));
+        }
+        match (choice[1], &self.auth) {
+            (METHOD_NONE, _) => Ok(()),
+            (METHOD_USERPASS, Some(auth)) => {
+                let (user, pass) = (auth.username.as_bytes(), auth.password.as_bytes());
+                if user.len() > 255 || pass.len() > 255 {
+                    return Err(Socks5Error::AuthFailed);
+                }
+                let mut request = vec![1, user.len() as u8];
+                request.extend_from_slice(user);
+                request.push(pass.len() as u8);
+                request.extend_from_slice(pass);
+                stream.write_all(&request)?;
+                let mut status = [0u8; 2];
+                stream.read_exact(&mut status)?;
+                if status[1] != 0 {
+                    return Err(Socks5Error::AuthFailed);
+                }
+                Ok(())
+            }
+            (METHOD_UNACCEPTABLE, _) | (METHOD_USERPASS, None) => {
+                Err(Socks5Error::NoAcceptableAuth)
+            }
+            (other, _) => Err(Socks5Error::Protocol(format!(
+                "unexpected method {}",
+                other
+            ))),
+        }
+    }
+    /// Check that the proxy is reachable and accepts our credentials
+    pub fn validate(&self, timeout: Duration) -> Result<(), Socks5Error> {
+        let mut stream = self.open(timeout)?;
+        self.handshake(&mut stream)
+    }
+    /// Connect to `target` through the proxy
+    pub fn connect(&self, target: SocketAddr, timeout: Duration) -> Result<TcpStream, Socks5Error> {
+        let mut stream = self.open(timeout)?;
+        self.handshake(&mut stream)?;
+        let mut request = vec![VERSION, CMD_CONNECT, 0];
+        match target.ip() {
+            IpAddr::V4(ip) => {
+                request.push(ATYP_V4);
+                request.extend_from_slice(&ip.octets());
+            }
+            IpAddr::V6(ip) => {
+                request.push(ATYP_V6);
+                request.extend_from_slice(&ip.octets());
+            }
+        }
+        request.extend_from_slice(&target.port().to_be_bytes());
+        stream.write_all(&request)?;
+        let mut header = [0u8; 4];
+        stream.read_exact(&mut header)?;
+        if header[0] != VERSION {
+            return Err(Socks5Error::Protocol(format!("version {}", header[0])));
+        }
+        if header[1] != REPLY_SUCCEEDED {
+            return Err(Socks5Error::Reply(header[1]));
+        }
+        // Skip the bound address
+        let addr_len = match header[3] {
+            ATYP_V4 => 4,
+            ATYP_V6 => 16,
+            ATYP_DOMAIN => {
+                let mut len = [0u8; 1];
+                stream.read_exact(&mut len)?;
+                len[0] as usize
+            }
+            other => return Err(Socks5Error::Protocol(format!("address type {}", other))),
+        };
+        let mut bound = vec![0u8; addr_len + 2];
+        stream.read_exact(&mut bound)?;
+        Ok(stream)
+    }
+}
+
+/// Connect probes through a SOCKS5 proxy. Connect times include the
+/// proxy handshake and the proxy-to-target path, not the direct path.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Socks5Prober {
+    pub proxy: Socks5Proxy,
+}
+
+impl PortProber for Socks5Prober {
+    fn probe_port(&self, ip: IpAddr, port: u16, timeout: Duration) -> PortProbe {
+        let start = Instant::now();
+        let result = self.proxy.connect(SocketAddr::new(ip, port), timeout);
+        let elapsed = start.elapsed();
+        let state = match result {
+            Ok(_) => PortState::Open,
+            Err(Socks5Error::Reply(REPLY_CONNECTION_REFUSED)) => PortState::Closed,
+            Err(_) => PortState::Filtered,
+        };
+        PortProbe {
+            port,
+            state,
+            connect_time: (state == PortState::Open).then_some(elapsed),
+            error: None,
+        }
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use std::net::{Ipv4Addr, TcpListener};
+    use std::thread;
+
+    /// Minimal SOCKS5 server for tests. Requires `credentials` when set and
+    /// connects to the requested IPv4 target. Returns its address.
+    pub(crate) fn socks5_server(credentials: Option<(&'static str, &'static str)>) -> SocketAddr {
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        let addr = listener.local_addr().unwrap();
+        thread::spawn(move || {
+            for conn in listener.incoming() {
+                let Ok(mut conn) = conn else { return };
+                let _ = serve(&mut conn, credentials);
+            }
+        });
+        addr
+    }
+
+    fn serve(conn: &mut TcpStream, credentials: Option<(&str, &str)>) -> io::Result<()> {
+        let mut head = [0u8; 2];
+        conn.read_exact(&mut head)?;
+        let mut methods = vec![0u8; head[1] as usize];
+        conn.read_exact(&mut methods)?;
+        let wanted = if credentials.is_some() {
+            METHOD_USERPASS
+        } else {
+            METHOD_NONE
+        };
+        if !methods.contains(&wanted) {
+            return conn.write_all(&[VERSION, METHOD_UNACCEPTABLE]);
+        }
+        conn.write_all(&[VERSION, wanted])?;
+        if let Some((user, pass)) = credentials {
+            let mut b = [0u8; 2];
+            conn.read_exact(&mut b)?;
+            let mut u = vec![0u8; b[1] as usize];
+            conn.read_exact(&mut u)?;
+            let mut plen = [0u8; 1];
+            conn.read_exact(&mut plen)?;
+            let mut p = vec![0u8; plen[0] as usize];
+            conn.read_exact(&mut p)?;
+            let ok = u == user.as_bytes() && p == pass.as_bytes();
+            conn.write_all(&[1, if ok { 0 } else { 1 }])?;
+            if !ok {
+                return Ok(());
+            }
+        }
+        let mut req = [0u8; 10];
+        conn.read_exact(&mut req)?;
+        let target = SocketAddr::new(
+            IpAddr::V4(Ipv4Addr::new(req[4], req[5], req[6], req[7])),
+            u16::from_be_bytes([req[8], req[9]]),
+        );
+        let rep = match TcpStream::connect_timeout(&target, Duration::from_secs(1)) {
+            Ok(_) => REPLY_SUCCEEDED,
+            Err(e) if e.kind() == io::ErrorKind::ConnectionRefused => REPLY_CONNECTION_REFUSED,
+            Err(_) => REPLY_HOST_UNREACHABLE,
+        };
+        conn.write_all(&[VERSION, rep, 0, ATYP_V4, 0, 0, 0, 0, 0, 0])
+    }
+
+    fn closed_port() -> u16 {
+        let l = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        l.local_addr().unwrap().port()
+    }
+
+    #[test]
+    fn connect_through_proxy() {
+        let target = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        let prober = Socks5Prober {
+            proxy: Socks5Proxy::new(socks5_server(None)),
+        };
+        let localhost = IpAddr::V4(Ipv4Addr::LOCALHOST);
+        let timeout = Duration::from_secs(1);
+        let probe = prober.probe_port(localhost, target.local_addr().unwrap().port(), timeout);
+        assert_eq!(probe.state, PortState::Open);
+        assert!(probe.connect_time.is_some());
+        let probe = prober.probe_port(localhost, closed_port(), timeout);
+        assert_eq!(probe.state, PortState::Closed);
+    }
+
+    #[test]
+    fn auth_failures_are_distinct() {
+        let addr = socks5_server(Some(("user", "secret")));
+        let timeout = Duration::from_secs(1);
+        let mut proxy = Socks5Proxy::new(addr);
+        assert!(matches!(
+            proxy.validate(timeout),
+            Err(Socks5Error::NoAcceptableAuth)
+        ));
+        proxy.auth = Some(Socks5Auth {
+            username: "user".to_string(),
+            password: "wrong".to_string(),
+        });
+        let err = proxy.validate(timeout).unwrap_err();
+        assert!(matches!(err, Socks5Error::AuthFailed));
+        assert!(err.is_proxy_error());
+        proxy.auth.as_mut().unwrap().password = "secret".to_string();
+        proxy.validate(timeout).unwrap();
+        let target = SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), closed_port());
+        let err = proxy.connect(target, timeout).unwrap_err();
+        assert!(matches!(err, Socks5Error::Reply(REPLY_CONNECTION_REFUSED)));
+        assert!(!err.is_proxy_error());
+    }
+}
@@ -0,0 +1,288 @@
+//! Per-interface traffic rates for the background stats task
+use crate::cancel::CancellationToken;
+use crate::probe::cancellable_sleep;
+use std::io;
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::{Duration, Instant};
+
+/// Event name emitted with each set of rates
+pub const INTERFACE_STATS_EVENT: &str = "interfaces:stats";
+/// How often a suspended task checks its cancellation token
+const SUSPEND_POLL: Duration = Duration::from_millis(100);
+
+/// Cumulative byte counters of one interface
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct InterfaceCounters {
+    pub name: String,
+    pub rx_bytes: u64,
+    pub tx_bytes: u64,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct InterfaceRate {
+    pub name: String,
+    pub rx_bytes_per_sec: f64,
+    pub tx_bytes_per_sec: f64,
+}
+
+/// Source of interface counter snapshots
+pub trait CounterSource: Sync {
+    fn counters(&self) -> io::Result<Vec<InterfaceCounters>>;
+}
+
+/// Counters from `/proc/net/dev`
+#[derive(Clone, Copy, Debug, Default)]
+pub struct ProcNetDev;
+
+impl CounterSource for ProcNetDev {
+    fn counters(&self) -> io::Result<Vec<InterfaceCounters>> {
+        if cfg!(any(target_os = "linux", target_os = "android")) {
+            Ok(parse_proc_net_dev(&std::fs::read_to_string(
+                "/proc/net/dev",
+            )?))
+        } else {
+            Err(io::Error::from(io::ErrorKind::Unsupported))
+        }
+    }
+}
+
+/// Parse `/proc/net/dev`, skipping the two header lines
+pub fn parse_proc_net_dev(text: &str) -> Vec<InterfaceCounters> {
+    text.lines()
+        .skip(2)
+        .filter_map(|line| {
+            let (name, fields) = line.split_once(':')?;
+            let fields: Vec<u64> = fields
+                .split_whitespace()
+                .map(|f| f.parse().ok())
+                .collect::<Option<_>>()?;
+            Some(InterfaceCounters {
+                name: name.trim().to_string(),
+                rx_bytes: *fields.first()?,
+                tx_bytes: *fields.get(8)?,
+            })
+        })
+        .collect()
+}
+
+/// Turns counter snapshots into rates against the previous snapshot
+#[derive(Debug, Default)]
+pub struct RateTracker {
+    baseline: Option<(Instant, Vec<InterfaceCounters>)>,
+}
+
+impl RateTracker {
+    pub fn new() -> RateTracker {
+        RateTracker::default()
+    }
+    /// Forget the baseline, so the next snapshot only starts a new one
+    pub fn reset(&mut self) {
+        self.baseline = None;
+    }
+    /// Rates since the previous snapshot, `None` for the first one.
+    /// Interfaces new since then, or whose counters went back, are left out.
+    pub fn observe(
+        &mut self,
+        counters: Vec<InterfaceCounters>,
+        now: Instant,
+    ) -> Option<Vec<InterfaceRate>> {
+        let (then, previous) = self.baseline.replace((now, counters.clone()))?;
+        let secs = now.saturating_duration_since(then).as_secs_f64();
+        if secs <= 0.0 {
+            return None;
+        }
+        let rates = counters
+            .iter()
+            .filter_map(|c| {
+                let p = previous.iter().find(|p| p.name == c.name)?;
+                Some(InterfaceRate {
+                    name: c.name.clone(),
+                    rx_bytes_per_sec: c.rx_bytes.checked_sub(p.rx_bytes)? as f64 / secs,
+                    tx_bytes_per_sec: c.tx_bytes.checked_sub(p.tx_bytes)? as f64 / secs,
+                })
+            })
+            .collect();
+        Some(rates)
+    }
+}
+
+#[derive(Debug, Default)]
+struct ControlState {
+    suspended: bool,
+    /// Bumped on every resume so tasks know to drop their baselines
+    resumes: u64,
+    follow_focus: bool,
+}
+
+/// Suspends and resumes background monitoring, e.g. while the window is
+/// in the background. Clones share state.
+#[derive(Clone, Debug, Default)]
+pub struct MonitorControl {
+    inner: Arc<(Mutex<ControlState>, Condvar)>,
+}
+
+impl MonitorControl {
+    pub fn new() -> MonitorControl {
+        MonitorControl::default()
+    }
+    pub fn suspend(&self) {
+        self.inner.0.lock().unwrap().suspended = true;
+    }
+    pub fn resume(&self) {
+        let mut state = self.inner.0.lock().unwrap();
+        if state.suspended {
+            state.suspended = false;
+            state.resumes += 1;
+            self.inner.1.notify_all();
+        }
+    }
+    pub fn is_suspended(&self) -> bool {
+        self.inner.0.lock().unwrap().suspended
+    }
+    /// Suspend and resume with window focus from now on
+    pub fn set_follow_focus(&self, follow: bool) {
+        self.inner.0.lock().unwrap().follow_focus = follow;
+    }
+    /// Window focus changed. Ignored unless following focus.
+    pub fn focus_changed(&self, focused: bool) {
+        if !self.inner.0.lock().unwrap().follow_focus {
+            return;
+        }
+        if focused {
+            self.resume();
+        } else {
+            self.suspend();
+        }
+    }
+    /// Number of resumes so far
+    pub fn resumes(&self) -> u64 {
+        self.inner.0.lock().unwrap().resumes
+    }
+    /// Block while suspended or until `token` is cancelled
+    pub fn wait_while_suspended(&self, token: &CancellationToken) {
+        let (lock, cvar) = &*self.inner;
+        let mut state = lock.lock().unwrap();
+        while state.suspended && !token.is_cancelled() {
+            state = cvar.wait_timeout(state, SUSPEND_POLL).unwrap().0;
+        }
+    }
+}
+
+/// Poll `source` every `interval` until cancelled, calling `on_update` with
+/// the rates. Pauses while `control` is suspended and starts from a fresh
+/// baseline after each resume, so traffic during the pause does not show
+/// up as a spike.
+pub fn watch_traffic<S, F>(
+    source: &S,
+    interval: Duration,
+    control: &MonitorControl,
+    token: &CancellationToken,
+    mut on_update: F,
+) where
+    S: CounterSource,
+    F: FnMut(&[InterfaceRate]),
+{
+    let mut tracker = RateTracker::new();
+    let mut resumes = control.resumes();
+    while !token.is_cancelled() {
+        control.wait_while_suspended(token);
+        if control.resumes() != resumes {
+            resumes = control.resumes();
+            tracker.reset();
+        }
+        if let Ok(counters) = source.counters() {
+            if let Some(rates) = tracker.observe(counters, Instant::now()) {
+                on_update(&rates);
+            }
+        }
+        if cancellable_sleep(token, interval).is_cancelled() {
+            break;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicU64, Ordering};
+    use std::sync::mpsc;
+    use std::thread;
+
+    #[test]
+    fn parse_counters() {
+        let text = "\
+Inter-|   Receive                                                |  Transmit
+ face |bytes    packets errs drop fifo frame compressed multicast|bytes    packets errs drop fifo colls carrier compressed
+    lo:  104502     980    0    0    0     0          0         0   104502     980    0    0    0     0       0          0
+  eth0: 9812345   12000    0    0    0     0          0        10  1234567    9000    0    0    0     0       0          0
+";
+        let counters = parse_proc_net_dev(text);
+        assert_eq!(counters.len(), 2);
+        assert_eq!(
+            counters[1],
+            InterfaceCounters {
+                name: "eth0".to_string(),
+                rx_bytes: 9_812_345,
+                tx_bytes: 1_234_567,
+            }
+        );
+    }
+
+    /// eth0 receives 1000 bytes every time it is read
+    #[derive(Default)]
+    struct Busy(AtomicU64);
+
+    impl CounterSource for Busy {
+        fn counters(&self) -> io::Result<Vec<InterfaceCounters>> {
+            let rx = self.0.fetch_add(1000, Ordering::SeqCst) + 1000;
+            Ok(vec![InterfaceCounters {
+                name: "eth0".to_string(),
+                rx_bytes: rx,
+                tx_bytes: 0,
+            }])
+        }
+    }
+
+    #[test]
+    fn suspend_stops_updates_and_resume_resets_baseline() {
+        let source = Busy::default();
+        let control = MonitorControl::new();
+        let token = CancellationToken::new();
+        let (tx, rx) = mpsc::channel();
+        thread::scope(|s| {
+            s.spawn(|| {
+                watch_traffic(&source, Duration::from_millis(10), &control, &token, |r| {
+                    let _ = tx.send(r[0].rx_bytes_per_sec);
+                })
+            });
+            rx.recv_timeout(Duration::from_secs(2)).unwrap();
+
+            control.suspend();
+            // Let an update already under way land
+            thread::sleep(Duration::from_millis(50));
+            while rx.try_recv().is_ok() {}
+            thread::sleep(Duration::from_millis(100));
+            assert!(rx.try_recv().is_err(), "updates while suspended");
+
+            // Heavy traffic while suspended
+            source.0.fetch_add(10_000_000_000, Ordering::SeqCst);
+            control.resume();
+            let rate = rx.recv_timeout(Duration::from_secs(2)).unwrap();
+            assert!(rate < 1e9, "spike of {} B/s after resume", rate);
+            token.cancel();
+        });
+        assert_eq!(control.resumes(), 1);
+    }
+
+    #[test]
+    fn focus_drives_suspension_only_when_followed() {
+        let control = MonitorControl::new();
+        control.focus_changed(false);
+        assert!(!control.is_suspended());
+        control.set_follow_focus(true);
+        control.focus_changed(false);
+        assert!(control.is_suspended());
+        control.focus_changed(true);
+        assert!(!control.is_suspended());
+    }
+}
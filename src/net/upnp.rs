@@ -0,0 +1,447 @@
+//! Internet gateway discovery over UPnP (SSDP + SOAP) and NAT-PMP
+use crate::http::{self, HttpError, Url};
+use std::fmt;
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr, UdpSocket};
+use std::time::{Duration, Instant};
+
+pub const SSDP_ADDR: SocketAddr = SocketAddr::V4(std::net::SocketAddrV4::new(
+    Ipv4Addr::new(239, 255, 255, 250),
+    1900,
+));
+pub const NATPMP_PORT: u16 = 5351;
+const IGD_SEARCH_TARGET: &str = "urn:schemas-upnp-org:device:InternetGatewayDevice:1";
+const WAN_SERVICES: [&str; 2] = [
+    "urn:schemas-upnp-org:service:WANIPConnection:",
+    "urn:schemas-upnp-org:service:WANPPPConnection:",
+];
+/// Mappings listed at most, in case a gateway never reports the end
+const MAX_MAPPINGS: u32 = 256;
+
+#[derive(Debug)]
+pub enum UpnpError {
+    /// No gateway answered discovery
+    NotAvailable,
+    /// The gateway has no WAN connection service
+    NoWanService,
+    Http(HttpError),
+    /// SOAP fault or unexpected response
+    Protocol(String),
+    Io(io::Error),
+}
+
+impl fmt::Display for UpnpError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            UpnpError::NotAvailable => write!(f, "No UPnP or NAT-PMP gateway available"),
+            UpnpError::NoWanService => write!(f, "Gateway has no WAN connection service"),
+            UpnpError::Http(e) => write!(f, "{}", e),
+            UpnpError::Protocol(s) => write!(f, "Invalid gateway response: {}", s),
+            UpnpError::Io(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl std::error::Error for UpnpError {}
+
+impl From<HttpError> for UpnpError {
+    fn from(e: HttpError) -> Self {
+        UpnpError::Http(e)
+    }
+}
+
+impl From<io::Error> for UpnpError {
+    fn from(e: io::Error) -> Self {
+        UpnpError::Io(e)
+    }
+}
+
+/// Answer to an SSDP M-SEARCH
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SsdpResponse {
+    /// URL of the device description
+    pub location: String,
+    pub search_target: String,
+    pub server: Option<String>,
+    pub usn: Option<String>,
+}
+
+/// Parse an SSDP search response. Returns `None` without a `LOCATION`.
+pub fn parse_ssdp_response(text: &str) -> Option<SsdpResponse> {
+    if !text.lines().next()?.starts_with("HTTP/1.") {
+        return None;
+    }
+    let header = |name: &str| {
+        text.lines().skip(1).find_map(|line| {
+            let (k, v) = line.split_once(':')?;
+            k.trim()
+                .eq_ignore_ascii_case(name)
+                .then(|| v.trim().to_string())
+        })
+    };
+    Some(SsdpResponse {
+        location: header("location")?,
+        search_target: header("st").unwrap_or_default(),
+        server: header("server"),
+        usn: header("usn"),
+    })
+}
+
+/// Text of the first `<tag>` element in `xml`, ignoring namespace prefixes
+fn element<'a>(xml: &'a str, tag: &str) -> Option<&'a str> {
+    let mut rest = xml;
+    loop {
+        let open = rest.find('<')?;
+        rest = &rest[open + 1..];
+        let end = rest.find('>')?;
+        let name = rest[..end].split_whitespace().next().unwrap_or("");
+        let local = name.rsplit(':').next().unwrap_or(name);
+        if local == tag && !name.starts_with('/') {
+            let body = &rest[end + 1..];
+            let close = body.find("</")?;
+            return Some(body[..close].trim());
+        }
+        rest = &rest[end + 1..];
+    }
+}
+
+/// Device and WAN service of an internet gateway description
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct IgdDescription {
+    pub friendly_name: Option<String>,
+    pub model_name: Option<String>,
+    pub service_type: String,
+    /// Absolute control URL of the WAN service
+    pub control_url: String,
+}
+
+/// Find the WAN connection service in a device description fetched from
+/// `location`
+pub fn parse_igd_description(xml: &str, location: &str) -> Option<IgdDescription> {
+    let service = xml.split("<service>").skip(1).find(|s| {
+        element(s, "serviceType").is_some_and(|t| WAN_SERVICES.iter().any(|w| t.starts_with(w)))
+    })?;
+    let control = element(service, "controlURL")?;
+    let base = element(xml, "URLBase").unwrap_or(location);
+    Some(IgdDescription {
+        friendly_name: element(xml, "friendlyName").map(str::to_string),
+        model_name: element(xml, "modelName").map(str::to_string),
+        service_type: element(service, "serviceType")?.to_string(),
+        control_url: absolute_url(base, control),
+    })
+}
+
+fn absolute_url(base: &str, path: &str) -> String {
+    if path.starts_with("http://") {
+        return path.to_string();
+    }
+    let origin = match base
+        .find("://")
+        .and_then(|i| base[i + 3..].find('/').map(|j| i + 3 + j))
+    {
+        Some(end) => &base[..end],
+        None => base.trim_end_matches('/'),
+    };
+    format!("{}/{}", origin, path.trim_start_matches('/'))
+}
+
+/// Existing port forwarding on the gateway
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PortMapping {
+    pub external_port: u16,
+    pub protocol: String,
+    pub internal_client: String,
+    pub internal_port: u16,
+    pub description: String,
+    pub enabled: bool,
+    /// Remaining lease in seconds, 0 for permanent
+    pub lease_secs: u32,
+}
+
+/// Gateway state as far as it could be queried
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct GatewayInfo {
+    /// `upnp` or `nat-pmp`
+    pub protocol: String,
+    pub address: IpAddr,
+    pub friendly_name: Option<String>,
+    pub model_name: Option<String>,
+    pub external_ip: Option<IpAddr>,
+    /// Connection status such as `Connected`, UPnP only
+    pub connection_status: Option<String>,
+    pub mappings: Vec<PortMapping>,
+}
+
+fn soap_call(
+    igd: &IgdDescription,
+    action: &str,
+    args: &str,
+    timeout: Duration,
+) -> Result<String, UpnpError> {
+    let body = format!(
+        "<?xml version=\"1.0\"?>\r\n<s:Envelope xmlns:s=\"http://schemas.xmlsoap.org/soap/envelope/\" \
+         s:encodingStyle=\"http://schemas.xmlsoap.org/soap/encoding/\"><s:Body>\
+         <u:{action} xmlns:u=\"{service}\">{args}</u:{action}></s:Body></s:Envelope>",
+        action = action,
+        service = igd.service_type,
+        args = args
+    );
+    let soap_action = format!("\"{}#{}\"", igd.service_type, action);
+    let response = http::request(
+        "POST",
+        &Url::parse(&igd.control_url)?,
+        &[
+            ("Content-Type", "text/xml; charset=\"utf-8\""),
+            ("SOAPAction", &soap_action),
+        ],
+        body.as_bytes(),
+        timeout,
+    )?;
+    let text = String::from_utf8_lossy(&response.body).into_owned();
+    if response.status >= 400 {
+        let detail = element(&text, "errorDescription").unwrap_or("SOAP fault");
+        return Err(UpnpError::Protocol(detail.to_string()));
+    }
+    Ok(text)
+}
+
+/// Parse a `GetGenericPortMappingEntry` response
+pub fn parse_port_mapping(xml: &str) -> Option<PortMapping> {
+    Some(PortMapping {
+        external_port: element(xml, "NewExternalPort")?.parse().ok()?,
+        protocol: element(xml, "NewProtocol")?.to_string(),
+        internal_client: element(xml, "NewInternalClient")?.to_string(),
+        internal_port: element(xml, "NewInternalPort")?.parse().ok()?,
+        description: element(xml, "NewPortMappingDescription")
+            .unwrap_or("")
+            .to_string(),
+        enabled: element(xml, "NewEnabled").is_some_and(|v| v == "1"),
+        lease_secs: element(xml, "NewLeaseDuration")
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(0),
+    })
+}
+
+/// Search for an internet gateway with SSDP and wait up to `timeout`
+pub fn ssdp_search(timeout: Duration) -> Result<(SocketAddr, SsdpResponse), UpnpError> {
+    let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0))?;
+    let request = format!(
+        "M-SEARCH * HTTP/1.1\r\nHOST: {}\r\nMAN: \"ssdp:discover\"\r\nMX: {}\r\nST: {}\r\n\r\n",
+        SSDP_ADDR,
+        timeout.as_secs().clamp(1, 5),
+        IGD_SEARCH_TARGET
+    );
+    socket.send_to(request.as_bytes(), SSDP_ADDR)?;
+    let deadline = Instant::now() + timeout;
+    let mut buf = [0u8; 2048];
+    loop {
+        let left = deadline.saturating_duration_since(Instant::now());
+        if left.is_zero() {
+            return Err(UpnpError::NotAvailable);
+        }
+        socket.set_read_timeout(Some(left))?;
+        match socket.recv_from(&mut buf) {
+            Ok((n, from)) => {
+                if let Some(response) = parse_ssdp_response(&String::from_utf8_lossy(&buf[..n])) {
+                    return Ok((from, response));
+                }
+            }
+            Err(e)
+                if matches!(
+                    e.kind(),
+                    io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+                ) =>
+            {
+                return Err(UpnpError::NotAvailable)
+            }
+            Err(e) => return Err(e.into()),
+        }
+    }
+}
+
+/// Query a UPnP gateway found at `location`
+pub fn query_upnp_gateway(
+    address: IpAddr,
+    location: &str,
+    timeout: Duration,
+) -> Result<GatewayInfo, UpnpError> {
+    let description = http::request("GET", &Url::parse(location)?, &[], &[], timeout)?;
+    let igd = parse_igd_description(&String::from_utf8_lossy(&description.body), location)
+        .ok_or(UpnpError::NoWanService)?;
+    let external_ip = soap_call(&igd, "GetExternalIPAddress", "", timeout)
+        .ok()
+        .and_then(|xml| element(&xml, "NewExternalIPAddress")?.parse().ok());
+    let connection_status = soap_call(&igd, "GetStatusInfo", "", timeout)
+        .ok()
+        .and_then(|xml| element(&xml, "NewConnectionStatus").map(str::to_string));
+    let mut mappings = Vec::new();
+    for index in 0..MAX_MAPPINGS {
+        let args = format!("<NewPortMappingIndex>{}</NewPortMappingIndex>", index);
+        // The gateway answers the first index past the end with a fault
+        let Ok(xml) = soap_call(&igd, "GetGenericPortMappingEntry", &args, timeout) else {
+            break;
+        };
+        match parse_port_mapping(&xml) {
+            Some(mapping) => mappings.push(mapping),
+            None => break,
+        }
+    }
+    Ok(GatewayInfo {
+        protocol: "upnp".to_string(),
+        address,
+        friendly_name: igd.friendly_name,
+        model_name: igd.model_name,
+        external_ip,
+        connection_status,
+        mappings,
+    })
+}
+
+/// Parse a NAT-PMP external address response (opcode 128)
+pub fn parse_natpmp_external(buf: &[u8]) -> Option<Result<Ipv4Addr, u16>> {
+    if buf.len() < 12 || buf[0] != 0 || buf[1] != 128 {
+        return None;
+    }
+    let result = u16::from_be_bytes([buf[2], buf[3]]);
+    if result != 0 {
+        return Some(Err(result));
+    }
+    Some(Ok(Ipv4Addr::new(buf[8], buf[9], buf[10], buf[11])))
+}
+
+/// Ask `gateway` for its external address over NAT-PMP
+pub fn natpmp_external_ip(gateway: Ipv4Addr, timeout: Duration) -> Result<Ipv4Addr, UpnpError> {
+    let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0))?;
+    socket.connect((gateway, NATPMP_PORT))?;
+    socket.set_read_timeout(Some(timeout))?;
+    socket.send(&[0, 0])?;
+    let mut buf = [0u8; 16];
+    let n = match socket.recv(&mut buf) {
+        Ok(n) => n,
+        // Nothing listening, or the port is filtered
+        Err(e)
+            if matches!(
+                e.kind(),
+                io::ErrorKind::WouldBlock
+                    | io::ErrorKind::TimedOut
+                    | io::ErrorKind::ConnectionRefused
+            ) =>
+        {
+            return Err(UpnpError::NotAvailable)
+        }
+        Err(e) => return Err(e.into()),
+    };
+    match parse_natpmp_external(&buf[..n]) {
+        Some(Ok(ip)) => Ok(ip),
+        Some(Err(code)) => Err(UpnpError::Protocol(format!("NAT-PMP result code {}", code))),
+        None => Err(UpnpError::Protocol(
+            "Malformed NAT-PMP response".to_string(),
+        )),
+    }
+}
+
+/// Find the internet gateway with UPnP, falling back to NAT-PMP on
+/// `default_gateway`. Fails with `NotAvailable` when neither answers.
+pub fn discover_gateway(
+    default_gateway: Option<Ipv4Addr>,
+    timeout: Duration,
+) -> Result<GatewayInfo, UpnpError> {
+    match ssdp_search(timeout) {
+        Ok((from, response)) => return query_upnp_gateway(from.ip(), &response.location, timeout),
+        Err(UpnpError::NotAvailable) => {}
+        Err(e) => return Err(e),
+    }
+    let gateway = default_gateway.ok_or(UpnpError::NotAvailable)?;
+    let external = natpmp_external_ip(gateway, timeout)?;
+    Ok(GatewayInfo {
+        protocol: "nat-pmp".to_string(),
+        address: IpAddr::V4(gateway),
+        friendly_name: None,
+        model_name: None,
+        external_ip: Some(IpAddr::V4(external)),
+        connection_status: None,
+        mappings: Vec::new(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DESCRIPTION: &str = r#"<?xml version="1.0"?>
+<root xmlns="urn:schemas-upnp-org:device-1-0">
+  <device>
+    <deviceType>urn:schemas-upnp-org:device:InternetGatewayDevice:1</deviceType>
+    <friendlyName>Home Router</friendlyName>
+    <modelName>HR-1000</modelName>
+    <deviceList><device><serviceList>
+      <service>
+        <serviceType>urn:schemas-upnp-org:service:Layer3Forwarding:1</serviceType>
+        <controlURL>/ctl/L3F</controlURL>
+      </service>
+      <service>
+        <serviceType>urn:schemas-upnp-org:service:WANIPConnection:1</serviceType>
+        <controlURL>/ctl/IPConn</controlURL>
+      </service>
+    </serviceList></device></deviceList>
+  </device>
+</root>"#;
+
+    #[test]
+    fn parse_ssdp_and_description() {
+        let text = "HTTP/1.1 200 OK\r\nCACHE-CONTROL: max-age=120\r\n\
+                    ST: urn:schemas-upnp-org:device:InternetGatewayDevice:1\r\n\
+                    USN: uuid:1234::urn:schemas-upnp-org:device:InternetGatewayDevice:1\r\n\
+                    Location: http://192.168.1.1:5000/rootDesc.xml\r\n\
+                    SERVER: Linux UPnP/1.1 MiniUPnPd/2.2\r\n\r\n";
+        let response = parse_ssdp_response(text).unwrap();
+        assert_eq!(response.location, "http://192.168.1.1:5000/rootDesc.xml");
+        assert_eq!(response.search_target, IGD_SEARCH_TARGET);
+        assert_eq!(
+            response.server.as_deref(),
+            Some("Linux UPnP/1.1 MiniUPnPd/2.2")
+        );
+        assert_eq!(parse_ssdp_response("NOTIFY * HTTP/1.1\r\n\r\n"), None);
+
+        let igd = parse_igd_description(DESCRIPTION, &response.location).unwrap();
+        assert_eq!(igd.friendly_name.as_deref(), Some("Home Router"));
+        assert_eq!(igd.model_name.as_deref(), Some("HR-1000"));
+        assert_eq!(
+            igd.service_type,
+            "urn:schemas-upnp-org:service:WANIPConnection:1"
+        );
+        assert_eq!(igd.control_url, "http://192.168.1.1:5000/ctl/IPConn");
+    }
+
+    #[test]
+    fn parse_soap_responses() {
+        let xml = r#"<s:Envelope><s:Body><u:GetGenericPortMappingEntryResponse>
+            <NewRemoteHost></NewRemoteHost><NewExternalPort>8443</NewExternalPort>
+            <NewProtocol>TCP</NewProtocol><NewInternalPort>443</NewInternalPort>
+            <NewInternalClient>192.168.1.20</NewInternalClient><NewEnabled>1</NewEnabled>
+            <NewPortMappingDescription>nas</NewPortMappingDescription>
+            <NewLeaseDuration>0</NewLeaseDuration>
+            </u:GetGenericPortMappingEntryResponse></s:Body></s:Envelope>"#;
+        let mapping = parse_port_mapping(xml).unwrap();
+        assert_eq!((mapping.external_port, mapping.internal_port), (8443, 443));
+        assert_eq!(mapping.internal_client, "192.168.1.20");
+        assert!(mapping.enabled);
+        assert_eq!(mapping.description, "nas");
+
+        let xml = "<s:Body><u:GetExternalIPAddressResponse><NewExternalIPAddress>100.72.1.2\
+                   </NewExternalIPAddress></u:GetExternalIPAddressResponse></s:Body>";
+        assert_eq!(element(xml, "NewExternalIPAddress"), Some("100.72.1.2"));
+    }
+
+    #[test]
+    fn parse_natpmp() {
+        let ok = [0, 128, 0, 0, 0, 0, 0, 9, 203, 0, 113, 7];
+        assert_eq!(
+            parse_natpmp_external(&ok),
+            Some(Ok(Ipv4Addr::new(203, 0, 113, 7)))
+        );
+        let refused = [0, 128, 0, 3, 0, 0, 0, 9, 0, 0, 0, 0];
+        assert_eq!(parse_natpmp_external(&refused), Some(Err(3)));
+        assert_eq!(parse_natpmp_external(&[0, 0]), None);
+    }
+}
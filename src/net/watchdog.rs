@@ -0,0 +1,283 @@
+//! Background watchdog logging internet outages
+use crate::cancel::CancellationToken;
+use crate::dns::Resolver;
+use crate::http::{HttpTransport, Url};
+use crate::ping::Prober;
+use crate::probe::cancellable_sleep_until;
+use std::net::IpAddr;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use ts_rs::TS;
+
+/// Event name emitted when connectivity is lost
+pub const INTERNET_DOWN_EVENT: &str = "internet:down";
+/// Event name emitted when connectivity is back
+pub const INTERNET_UP_EVENT: &str = "internet:up";
+
+/// Stage of the connectivity check, in the order they run
+#[derive(Clone, Copy, Debug, PartialEq, Eq, TS)]
+pub enum ConnectivityStage {
+    /// Default gateway answers a ping
+    Gateway,
+    /// Check hostname resolves
+    Dns,
+    /// Check URL answers over HTTP
+    Http,
+}
+
+/// Runs one connectivity check, failing with the first stage that failed
+pub trait ConnectivityCheck: Sync {
+    fn check(&self, token: &CancellationToken) -> Result<(), ConnectivityStage>;
+}
+
+/// Gateway ping, then DNS resolution of the URL host, then an HTTP request
+pub struct StagedCheck<'a, P: Prober, T: HttpTransport> {
+    pub prober: &'a P,
+    pub transport: &'a T,
+    pub resolver: &'a Resolver,
+    /// Skipped when `None`
+    pub gateway: Option<IpAddr>,
+    pub url: Url,
+    pub timeout: Duration,
+}
+
+impl<P: Prober, T: HttpTransport> ConnectivityCheck for StagedCheck<'_, P, T> {
+    fn check(&self, token: &CancellationToken) -> Result<(), ConnectivityStage> {
+        if let Some(gateway) = self.gateway {
+            self.prober
+                .probe(gateway, 0, self.timeout)
+                .map_err(|_| ConnectivityStage::Gateway)?;
+        }
+        self.resolver
+            .resolve_cancellable(&self.url.host, token)
+            .map_err(|_| ConnectivityStage::Dns)?;
+        self.transport
+            .get(&self.url, self.timeout)
+            .map_err(|_| ConnectivityStage::Http)?;
+        Ok(())
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, TS)]
+pub struct WatchdogSetting {
+    /// Time between checks
+    #[ts(type = "number")]
+    pub interval_ms: u64,
+    /// Failures shorter than this are blips, neither reported nor logged
+    #[ts(type = "number")]
+    pub min_outage_ms: u64,
+    /// Oldest outages are dropped beyond this many
+    pub max_log: usize,
+}
+
+impl Default for WatchdogSetting {
+    fn default() -> Self {
+        WatchdogSetting {
+            interval_ms: 10_000,
+            min_outage_ms: 5_000,
+            max_log: 200,
+        }
+    }
+}
+
+/// Logged outage, times in Unix milliseconds
+#[derive(Clone, Debug, PartialEq, Eq, TS)]
+pub struct Outage {
+    /// Time of the first failed check
+    #[ts(type = "number")]
+    pub started_ms: u64,
+    /// Time of the first successful check after it, `None` while ongoing
+    #[ts(type = "number | null")]
+    pub ended_ms: Option<u64>,
+    /// Stage failing when the outage started
+    pub stage: ConnectivityStage,
+}
+
+impl Outage {
+    /// Length of an ended outage
+    pub fn duration_ms(&self) -> Option<u64> {
+        self.ended_ms.map(|end| end.saturating_sub(self.started_ms))
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum WatchdogEvent {
+    /// [`INTERNET_DOWN_EVENT`] with the outage just started
+    Down(Outage),
+    /// [`INTERNET_UP_EVENT`] with the outage just ended
+    Up(Outage),
+}
+
+/// Turns check results into debounced down and up transitions
+#[derive(Debug)]
+pub struct Watchdog {
+    setting: WatchdogSetting,
+    /// Start and stage of the current run of failures
+    failing: Option<(u64, ConnectivityStage)>,
+    down: bool,
+    log: Vec<Outage>,
+}
+
+impl Watchdog {
+    pub fn new(setting: WatchdogSetting) -> Watchdog {
+        Watchdog {
+            setting,
+            failing: None,
+            down: false,
+            log: Vec::new(),
+        }
+    }
+    /// Outages so far, oldest first
+    pub fn outages(&self) -> &[Outage] {
+        &self.log
+    }
+    pub fn is_down(&self) -> bool {
+        self.down
+    }
+    /// Feed the result of a check finished at `now_ms`
+    pub fn observe(
+        &mut self,
+        result: Result<(), ConnectivityStage>,
+        now_ms: u64,
+    ) -> Option<WatchdogEvent> {
+        match result {
+            Err(stage) => {
+                let (started_ms, stage) = *self.failing.get_or_insert((now_ms, stage));
+                if self.down || now_ms.saturating_sub(started_ms) < self.setting.min_outage_ms {
+                    return None;
+                }
+                self.down = true;
+                if self.log.len() >= self.setting.max_log.max(1) {
+                    self.log.remove(0);
+                }
+                let outage = Outage {
+                    started_ms,
+                    ended_ms: None,
+                    stage,
+                };
+                self.log.push(outage.clone());
+                Some(WatchdogEvent::Down(outage))
+            }
+            Ok(()) => {
+                self.failing = None;
+                if !std::mem::take(&mut self.down) {
+                    return None;
+                }
+                let outage = self.log.last_mut()?;
+                outage.ended_ms = Some(now_ms);
+                Some(WatchdogEvent::Up(outage.clone()))
+            }
+        }
+    }
+}
+
+fn unix_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_millis() as u64)
+}
+
+/// Run `check` every `setting.interval_ms` until cancelled, calling
+/// `on_event` on each transition. Returns the outage log.
+pub fn watch_connectivity<C, F>(
+    check: &C,
+    setting: &WatchdogSetting,
+    token: &CancellationToken,
+    mut on_event: F,
+) -> Vec<Outage>
+where
+    C: ConnectivityCheck,
+    F: FnMut(&WatchdogEvent),
+{
+    let interval = Duration::from_millis(setting.interval_ms);
+    let mut watchdog = Watchdog::new(setting.clone());
+    while !token.is_cancelled() {
+        let started = Instant::now();
+        let result = check.check(token);
+        if token.is_cancelled() {
+            break;
+        }
+        if let Some(event) = watchdog.observe(result, unix_ms()) {
+            on_event(&event);
+        }
+        if cancellable_sleep_until(token, started + interval).is_cancelled() {
+            break;
+        }
+    }
+    watchdog.log
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+
+    #[test]
+    fn down_then_up_logs_outage() {
+        let mut watchdog = Watchdog::new(WatchdogSetting {
+            min_outage_ms: 5_000,
+            ..Default::default()
+        });
+        let t0 = 1_700_000_000_000;
+        let at = |s: u64| t0 + s * 1000;
+        assert_eq!(watchdog.observe(Ok(()), at(0)), None);
+
+        // A short blip is neither reported nor logged
+        assert_eq!(watchdog.observe(Err(ConnectivityStage::Http), at(10)), None);
+        assert_eq!(watchdog.observe(Ok(()), at(12)), None);
+        assert!(watchdog.outages().is_empty());
+
+        assert_eq!(watchdog.observe(Err(ConnectivityStage::Dns), at(20)), None);
+        let down = watchdog.observe(Err(ConnectivityStage::Gateway), at(30));
+        let expected = Outage {
+            started_ms: at(20),
+            ended_ms: None,
+            stage: ConnectivityStage::Dns,
+        };
+        assert_eq!(down, Some(WatchdogEvent::Down(expected.clone())));
+        assert!(watchdog.is_down());
+        assert_eq!(watchdog.observe(Err(ConnectivityStage::Dns), at(40)), None);
+
+        let Some(WatchdogEvent::Up(up)) = watchdog.observe(Ok(()), at(50)) else {
+            panic!("no up event");
+        };
+        assert_eq!(up.duration_ms(), Some(30_000));
+        assert_eq!(watchdog.outages(), &[up]);
+        assert_eq!(watchdog.observe(Ok(()), at(60)), None);
+    }
+
+    /// Fails at the DNS stage for the first `n` checks
+    struct Scripted(Mutex<u32>);
+
+    impl ConnectivityCheck for Scripted {
+        fn check(&self, _token: &CancellationToken) -> Result<(), ConnectivityStage> {
+            let mut failures = self.0.lock().unwrap();
+            if *failures == 0 {
+                return Ok(());
+            }
+            *failures -= 1;
+            Err(ConnectivityStage::Dns)
+        }
+    }
+
+    #[test]
+    fn watch_emits_transitions() {
+        let token = CancellationToken::new();
+        let check = Scripted(Mutex::new(2));
+        let setting = WatchdogSetting {
+            interval_ms: 0,
+            min_outage_ms: 0,
+            ..Default::default()
+        };
+        let mut events = Vec::new();
+        let log = watch_connectivity(&check, &setting, &token, |e| {
+            if matches!(e, WatchdogEvent::Up(_)) {
+                token.cancel();
+            }
+            events.push(e.clone());
+        });
+        assert_eq!(events.len(), 2);
+        assert!(matches!(&events[0], WatchdogEvent::Down(o) if o.stage == ConnectivityStage::Dns));
+        assert!(matches!(&events[1], WatchdogEvent::Up(o) if o.ended_ms.is_some()));
+        assert_eq!(log.len(), 1);
+    }
+}
@@ -0,0 +1,409 @@
+//! Packet capture export in the classic libpcap format
+use crate::ping::icmp::checksum;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::net::IpAddr;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Magic number for microsecond-resolution pcap files
+pub const PCAP_MAGIC: u32 = 0xa1b2_c3d4;
+pub const PCAP_VERSION_MAJOR: u16 = 2;
+pub const PCAP_VERSION_MINOR: u16 = 4;
+/// Maximum number of bytes recorded per packet
+pub const DEFAULT_SNAPLEN: u32 = 65535;
+
+const ETHER_TYPE_IPV4: u16 = 0x0800;
+const ETHER_TYPE_IPV6: u16 = 0x86dd;
+const PROTO_ICMP: u8 = 1;
+const PROTO_ICMPV6: u8 = 58;
+/// TTL or hop limit written into synthesized IP headers
+const SYNTHETIC_TTL: u8 = 64;
+
+/// Link-layer header type of the records
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LinkType {
+    /// Ethernet II frames
+    Ethernet,
+    /// Raw IPv4/IPv6 packets without a link-layer header
+    Raw,
+}
+
+impl LinkType {
+    pub fn id(&self) -> u32 {
+        match self {
+            LinkType::Ethernet => 1,
+            LinkType::Raw => 101,
+        }
+    }
+}
+
+/// Writes probe packets and replies to a pcap stream
+pub struct PcapWriter<W: Write> {
+    inner: W,
+    link_type: LinkType,
+    snaplen: u32,
+}
+
+impl PcapWriter<BufWriter<File>> {
+    /// Create a pcap file at `path`, truncating any existing file
+    pub fn create<P: AsRef<Path>>(path: P, link_type: LinkType) -> io::Result<Self> {
+        let file = File::create(path)?;
+        PcapWriter::new(BufWriter::new(file), link_type)
+    }
+}
+
+impl<W: Write> PcapWriter<W> {
+    /// Wrap `inner` and write the global header
+    pub fn new(mut inner: W, link_type: LinkType) -> io::Result<Self> {
+        let mut header = Vec::with_capacity(24);
+        header.extend_from_slice(&PCAP_MAGIC.to_le_bytes());
+        header.extend_from_slice(&PCAP_VERSION_MAJOR.to_le_bytes());
+        header.extend_from_slice(&PCAP_VERSION_MINOR.to_le_bytes());
+        // thiszone, sigfigs
+        header.extend_from_slice(&0i32.to_le_bytes());
+        header.extend_from_slice(&0u32.to_le_bytes());
+        header.extend_from_slice(&DEFAULT_SNAPLEN.to_le_bytes());
+        header.extend_from_slice(&link_type.id().to_le_bytes());
+        inner.write_all(&header)?;
+        Ok(PcapWriter {
+            inner,
+            link_type,
+            snaplen: DEFAULT_SNAPLEN,
+        })
+    }
+    pub fn link_type(&self) -> LinkType {
+        self.link_type
+    }
+    /// Write one record. `data` must already start with the link-layer header.
+    pub fn write_packet(&mut self, timestamp: SystemTime, data: &[u8]) -> io::Result<()> {
+        let since_epoch = timestamp.duration_since(UNIX_EPOCH).unwrap_or_default();
+        let caplen = data.len().min(self.snaplen as usize);
+        let mut header = Vec::with_capacity(16);
+        header.extend_from_slice(&(since_epoch.as_secs() as u32).to_le_bytes());
+        header.extend_from_slice(&since_epoch.subsec_micros().to_le_bytes());
+        header.extend_from_slice(&(caplen as u32).to_le_bytes());
+        header.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        self.inner.write_all(&header)?;
+        self.inner.write_all(&data[..caplen])
+    }
+    /// Write an IP packet, prepending a synthetic Ethernet header if needed.
+    pub fn write_ip_packet(&mut self, timestamp: SystemTime, packet: &[u8]) -> io::Result<()> {
+        // Addresses are unknown at the IP layer; leave them zeroed.
+        self.write_frame(timestamp, [0; 6], [0; 6], packet)
+    }
+    /// Write an IP packet, framed between `src_mac` and `dst_mac` when the
+    /// link type is Ethernet. The addresses are ignored for raw records.
+    pub fn write_frame(
+        &mut self,
+        timestamp: SystemTime,
+        src_mac: [u8; 6],
+        dst_mac: [u8; 6],
+        packet: &[u8],
+    ) -> io::Result<()> {
+        match self.link_type {
+            LinkType::Raw => self.write_packet(timestamp, packet),
+            LinkType::Ethernet => {
+                let ether_type = match packet.first().map(|b| b >> 4) {
+                    Some(6) => ETHER_TYPE_IPV6,
+                    _ => ETHER_TYPE_IPV4,
+                };
+                let mut frame = Vec::with_capacity(14 + packet.len());
+                frame.extend_from_slice(&dst_mac);
+                frame.extend_from_slice(&src_mac);
+                frame.extend_from_slice(&ether_type.to_be_bytes());
+                frame.extend_from_slice(packet);
+                self.write_packet(timestamp, &frame)
+            }
+        }
+    }
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+    /// Flush and return the underlying writer
+    pub fn into_inner(mut self) -> io::Result<W> {
+        self.inner.flush()?;
+        Ok(self.inner)
+    }
+}
+
+/// IP packet carrying the ICMP or ICMPv6 `message` from `src` to `dst`, for
+/// sockets that send and receive without the IP header. An unknown side is
+/// written as the unspecified address of the other's family.
+///
+/// `None` for mixed families, or when `message` is too long for the
+/// header's length field.
+pub fn ip_packet_for_icmp(
+    src: Option<IpAddr>,
+    dst: Option<IpAddr>,
+    message: &[u8],
+) -> Option<Vec<u8>> {
+    let ipv6 = src.or(dst).is_some_and(|ip| ip.is_ipv6());
+    let unspecified = if ipv6 {
+        IpAddr::from([0u16; 8])
+    } else {
+        IpAddr::from([0u8; 4])
+    };
+    let (src, dst) = (src.unwrap_or(unspecified), dst.unwrap_or(unspecified));
+    let mut packet = Vec::with_capacity(40 + message.len());
+    match (src, dst) {
+        (IpAddr::V6(src), IpAddr::V6(dst)) => {
+            packet.extend_from_slice(&[0x60, 0, 0, 0]);
+            let payload_len = u16::try_from(message.len()).ok()?;
+            packet.extend_from_slice(&payload_len.to_be_bytes());
+            packet.extend_from_slice(&[PROTO_ICMPV6, SYNTHETIC_TTL]);
+            packet.extend_from_slice(&src.octets());
+            packet.extend_from_slice(&dst.octets());
+        }
+        (IpAddr::V4(src), IpAddr::V4(dst)) => {
+            packet.extend_from_slice(&[0x45, 0]);
+            let total_len = u16::try_from(20 + message.len()).ok()?;
+            packet.extend_from_slice(&total_len.to_be_bytes());
+            packet.extend_from_slice(&[0, 0, 0, 0, SYNTHETIC_TTL, PROTO_ICMP, 0, 0]);
+            packet.extend_from_slice(&src.octets());
+            packet.extend_from_slice(&dst.octets());
+            let sum = checksum(&packet);
+            packet[10..12].copy_from_slice(&sum.to_be_bytes());
+        }
+        // Mixed families cannot share a header
+        _ => return None,
+    }
+    packet.extend_from_slice(message);
+    Some(packet)
+}
+
+/// Which way a captured packet travelled
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Direction {
+    Sent,
+    Received,
+}
+
+/// Pcap stream shared by the probes of a session. Write errors are kept
+/// back until [`Capture::finish`], so a full disk does not fail the probes
+/// themselves.
+///
+/// Given the MAC address of the egress interface, packets are recorded as
+/// Ethernet frames to or from it; the peer's address is not known to the
+/// socket and is left zeroed. Without one they are recorded as raw IP.
+///
+/// Only ICMP probes are captured: UDP and TCP probes go through ordinary
+/// sockets, which never expose the headers of their packets.
+#[derive(Clone)]
+pub struct Capture {
+    inner: Arc<Mutex<CaptureState>>,
+}
+
+struct CaptureState {
+    writer: PcapWriter<Box<dyn Write + Send>>,
+    local_mac: [u8; 6],
+    error: Option<io::Error>,
+}
+
+impl fmt::Debug for Capture {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Capture").finish_non_exhaustive()
+    }
+}
+
+impl Capture {
+    /// Create a pcap file at `path`, truncating any existing file
+    pub fn create<P: AsRef<Path>>(path: P, local_mac: Option<[u8; 6]>) -> io::Result<Capture> {
+        Capture::new(BufWriter::new(File::create(path)?), local_mac)
+    }
+    pub fn new<W: Write + Send + 'static>(
+        writer: W,
+        local_mac: Option<[u8; 6]>,
+    ) -> io::Result<Capture> {
+        let link_type = match local_mac {
+            Some(_) => LinkType::Ethernet,
+            None => LinkType::Raw,
+        };
+        let writer = PcapWriter::new(Box::new(writer) as Box<dyn Write + Send>, link_type)?;
+        Ok(Capture {
+            inner: Arc::new(Mutex::new(CaptureState {
+                writer,
+                local_mac: local_mac.unwrap_or_default(),
+                error: None,
+            })),
+        })
+    }
+    pub fn link_type(&self) -> LinkType {
+        self.inner.lock().unwrap().writer.link_type()
+    }
+    /// Record an IP packet as seen on the wire
+    pub fn record_ip(&self, timestamp: SystemTime, direction: Direction, packet: &[u8]) {
+        if packet.is_empty() {
+            return;
+        }
+        let mut state = self.inner.lock().unwrap();
+        if state.error.is_some() {
+            return;
+        }
+        let (src_mac, dst_mac) = match direction {
+            Direction::Sent => (state.local_mac, [0; 6]),
+            Direction::Received => ([0; 6], state.local_mac),
+        };
+        if let Err(e) = state
+            .writer
+            .write_frame(timestamp, src_mac, dst_mac, packet)
+        {
+            state.error = Some(e);
+        }
+    }
+    /// Record an ICMP message under a synthesized IP header, see
+    /// [`ip_packet_for_icmp`]. Messages that cannot be wrapped are skipped.
+    pub fn record_icmp(
+        &self,
+        timestamp: SystemTime,
+        direction: Direction,
+        src: Option<IpAddr>,
+        dst: Option<IpAddr>,
+        message: &[u8],
+    ) {
+        if let Some(packet) = ip_packet_for_icmp(src, dst, message) {
+            self.record_ip(timestamp, direction, &packet);
+        }
+    }
+    /// Flush the stream and return the first write error, if any
+    pub fn finish(&self) -> io::Result<()> {
+        let mut state = self.inner.lock().unwrap();
+        if let Some(e) = state.error.take() {
+            return Err(e);
+        }
+        state.writer.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn u32_at(buf: &[u8], offset: usize) -> u32 {
+        u32::from_le_bytes(buf[offset..offset + 4].try_into().unwrap())
+    }
+
+    #[test]
+    fn write_and_read_back_records() {
+        let ts1 = UNIX_EPOCH + Duration::new(1_700_000_000, 123_456_000);
+        let ts2 = UNIX_EPOCH + Duration::new(1_700_000_001, 42_000);
+        let echo_request = [
+            0x45u8, 0, 0, 28, 0, 0, 0, 0, 64, 1, 0, 0, 10, 0, 0, 1, 10, 0, 0, 2,
+        ];
+        let echo_reply = [0x60u8, 0, 0, 0, 0, 8, 58, 64];
+
+        let mut writer = PcapWriter::new(Vec::new(), LinkType::Ethernet).unwrap();
+        writer.write_ip_packet(ts1, &echo_request).unwrap();
+        writer.write_ip_packet(ts2, &echo_reply).unwrap();
+        let buf = writer.into_inner().unwrap();
+
+        assert_eq!(u32_at(&buf, 0), PCAP_MAGIC);
+        assert_eq!(u32_at(&buf, 20), LinkType::Ethernet.id());
+
+        let rec1 = 24;
+        assert_eq!(u32_at(&buf, rec1), 1_700_000_000);
+        assert_eq!(u32_at(&buf, rec1 + 4), 123_456);
+        assert_eq!(u32_at(&buf, rec1 + 8), 14 + echo_request.len() as u32);
+        assert_eq!(u32_at(&buf, rec1 + 12), 14 + echo_request.len() as u32);
+        assert_eq!(&buf[rec1 + 16 + 12..rec1 + 16 + 14], &[0x08, 0x00]);
+
+        let rec2 = rec1 + 16 + 14 + echo_request.len();
+        assert_eq!(u32_at(&buf, rec2), 1_700_000_001);
+        assert_eq!(u32_at(&buf, rec2 + 4), 42);
+        assert_eq!(&buf[rec2 + 16 + 12..rec2 + 16 + 14], &[0x86, 0xdd]);
+        assert_eq!(buf.len(), rec2 + 16 + 14 + echo_reply.len());
+    }
+
+    #[test]
+    fn raw_link_type_has_no_frame_header() {
+        let mut writer = PcapWriter::new(Vec::new(), LinkType::Raw).unwrap();
+        writer
+            .write_ip_packet(UNIX_EPOCH, &[0x45, 0, 0, 20])
+            .unwrap();
+        let buf = writer.into_inner().unwrap();
+        assert_eq!(u32_at(&buf, 24 + 8), 4);
+        assert_eq!(&buf[24 + 16..], &[0x45, 0, 0, 20]);
+    }
+
+    #[test]
+    fn synthesized_headers_wrap_icmp_messages() {
+        let message = [8u8, 0, 0, 0, 0, 1, 0, 1];
+        let dst: IpAddr = "192.0.2.9".parse().unwrap();
+        let packet = ip_packet_for_icmp(None, Some(dst), &message).unwrap();
+        assert_eq!(packet.len(), 28);
+        assert_eq!((packet[0], packet[9]), (0x45, PROTO_ICMP));
+        assert_eq!(&packet[2..4], &28u16.to_be_bytes());
+        assert_eq!(&packet[12..16], &[0, 0, 0, 0]);
+        assert_eq!(&packet[16..20], &[192, 0, 2, 9]);
+        assert_eq!(checksum(&packet[..20]), 0);
+        assert_eq!(&packet[20..], &message);
+
+        let src: IpAddr = "2001:db8::1".parse().unwrap();
+        let packet = ip_packet_for_icmp(Some(src), None, &message).unwrap();
+        assert_eq!(packet.len(), 48);
+        assert_eq!((packet[0], packet[6]), (0x60, PROTO_ICMPV6));
+        assert_eq!(&packet[4..6], &8u16.to_be_bytes());
+        assert_eq!(&packet[24..40], &[0u8; 16]);
+        assert_eq!(ip_packet_for_icmp(Some(src), Some(dst), &message), None);
+    }
+
+    #[test]
+    fn oversized_messages_are_not_wrapped() {
+        let dst4: IpAddr = "192.0.2.9".parse().unwrap();
+        let dst6: IpAddr = "2001:db8::9".parse().unwrap();
+        assert!(ip_packet_for_icmp(None, Some(dst4), &[0; 65535 - 20]).is_some());
+        assert_eq!(ip_packet_for_icmp(None, Some(dst4), &[0; 65535 - 19]), None);
+        assert!(ip_packet_for_icmp(None, Some(dst6), &[0; 65535]).is_some());
+        assert_eq!(ip_packet_for_icmp(None, Some(dst6), &[0; 65536]), None);
+
+        let out = SharedBuf::default();
+        let capture = Capture::new(out.clone(), None).unwrap();
+        capture.record_icmp(UNIX_EPOCH, Direction::Sent, None, Some(dst4), &[0; 70000]);
+        capture.finish().unwrap();
+        assert_eq!(out.0.lock().unwrap().len(), 24);
+    }
+
+    #[test]
+    fn capture_frames_packets_with_the_local_mac() {
+        let mac = [2, 0, 0, 0, 0, 1];
+        let dst: IpAddr = "192.0.2.9".parse().unwrap();
+        let out = SharedBuf::default();
+        let capture = Capture::new(out.clone(), Some(mac)).unwrap();
+        assert_eq!(capture.link_type(), LinkType::Ethernet);
+        capture.record_icmp(UNIX_EPOCH, Direction::Sent, None, Some(dst), &[8, 0, 0, 0]);
+        capture.record_icmp(UNIX_EPOCH, Direction::Received, Some(dst), None, &[0; 4]);
+        capture.finish().unwrap();
+        let buf = out.0.lock().unwrap();
+        assert_eq!(u32_at(&buf, 20), LinkType::Ethernet.id());
+        let sent = &buf[24 + 16..24 + 16 + 14 + 24];
+        assert_eq!((&sent[..6], &sent[6..12]), (&[0u8; 6][..], &mac[..]));
+        assert_eq!(&sent[12..14], &[0x08, 0x00]);
+        assert_eq!(sent[14], 0x45);
+        let rec2 = 24 + 16 + 14 + 24;
+        let received = &buf[rec2 + 16..];
+        assert_eq!(
+            (&received[..6], &received[6..12]),
+            (&mac[..], &[0u8; 6][..])
+        );
+
+        let capture = Capture::new(SharedBuf::default(), None).unwrap();
+        assert_eq!(capture.link_type(), LinkType::Raw);
+    }
+
+    /// Writer whose bytes stay readable after the capture takes it
+    #[derive(Clone, Default)]
+    struct SharedBuf(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for SharedBuf {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+}
@@ -0,0 +1,178 @@
+use super::result::PingSample;
+use crate::stats::median;
+use std::collections::VecDeque;
+use std::net::IpAddr;
+use std::time::{Duration, Instant};
+use ts_rs::TS;
+
+/// Event name emitted when a continuous ping raises an alert
+pub const PING_ALERT_EVENT: &str = "ping:alert";
+
+/// Thresholds for alerts during a continuous ping
+#[derive(Clone, Debug, PartialEq, TS)]
+pub struct AlertSetting {
+    /// Alert when an RTT exceeds this multiple of the rolling median
+    pub spike_factor: f64,
+    /// Number of recent RTTs the median is taken over
+    pub window: usize,
+    /// RTTs needed before spikes are checked
+    pub min_samples: usize,
+    /// Alert after this many consecutive losses
+    pub loss_threshold: u32,
+    /// Minimum time between two alerts of the same kind
+    #[ts(type = "number")]
+    pub cooldown_ms: u64,
+}
+
+impl Default for AlertSetting {
+    fn default() -> Self {
+        AlertSetting {
+            spike_factor: 3.0,
+            window: 20,
+            min_samples: 5,
+            loss_threshold: 3,
+            cooldown_ms: 30_000,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, TS)]
+pub enum PingAlertKind {
+    LatencySpike { rtt_ms: f64, median_ms: f64 },
+    Loss { consecutive: u32 },
+}
+
+#[derive(Clone, Debug, PartialEq, TS)]
+pub struct PingAlertPayload {
+    pub dst_ip: IpAddr,
+    /// Sequence number of the probe that raised the alert
+    pub seq: u16,
+    pub kind: PingAlertKind,
+}
+
+/// Watches the samples of a ping session and raises alerts
+pub struct PingAlerter {
+    setting: AlertSetting,
+    dst_ip: IpAddr,
+    rtts: VecDeque<f64>,
+    losses: u32,
+    last_spike: Option<Instant>,
+    last_loss: Option<Instant>,
+}
+
+impl PingAlerter {
+    pub fn new(dst_ip: IpAddr, setting: AlertSetting) -> PingAlerter {
+        PingAlerter {
+            setting,
+            dst_ip,
+            rtts: VecDeque::new(),
+            losses: 0,
+            last_spike: None,
+            last_loss: None,
+        }
+    }
+
+    fn cooled_down(&self, last: Option<Instant>, now: Instant) -> bool {
+        let cooldown = Duration::from_millis(self.setting.cooldown_ms);
+        last.is_none_or(|at| now.saturating_duration_since(at) >= cooldown)
+    }
+
+    /// Feed the next sample, received at `now`
+    pub fn observe(&mut self, sample: &PingSample, now: Instant) -> Option<PingAlertPayload> {
+        let kind = match sample.rtt {
+            Some(rtt) => {
+                self.losses = 0;
+                let rtt_ms = rtt.as_secs_f64() * 1000.0;
+                let spike = if self.rtts.len() >= self.setting.min_samples {
+                    let window: Vec<f64> = self.rtts.iter().copied().collect();
+                    median(&window)
+                        .filter(|median_ms| rtt_ms > median_ms * self.setting.spike_factor)
+                        .map(|median_ms| PingAlertKind::LatencySpike { rtt_ms, median_ms })
+                } else {
+                    None
+                };
+                // Spikes are kept in the window so a lasting shift becomes the new baseline
+                self.rtts.push_back(rtt_ms);
+                while self.rtts.len() > self.setting.window.max(1) {
+                    self.rtts.pop_front();
+                }
+                let kind = spike.filter(|_| self.cooled_down(self.last_spike, now))?;
+                self.last_spike = Some(now);
+                kind
+            }
+            None => {
+                self.losses += 1;
+                if self.losses < self.setting.loss_threshold
+                    || !self.cooled_down(self.last_loss, now)
+                {
+                    return None;
+                }
+                self.last_loss = Some(now);
+                PingAlertKind::Loss {
+                    consecutive: self.losses,
+                }
+            }
+        };
+        Some(PingAlertPayload {
+            dst_ip: self.dst_ip,
+            seq: sample.seq,
+            kind,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample(seq: u16, rtt_ms: Option<u64>) -> PingSample {
+        PingSample {
+            seq,
+            rtt: rtt_ms.map(Duration::from_millis),
+            responder: None,
+            ttl: None,
+            ttl_changed: false,
+            unreachable: None,
+            duplicates: 0,
+        }
+    }
+
+    #[test]
+    fn spike_alerts_once_within_cooldown() {
+        let dst = "192.0.2.1".parse().unwrap();
+        let mut alerter = PingAlerter::new(dst, AlertSetting::default());
+        let start = Instant::now();
+        let rtts = [10, 11, 10, 12, 10, 80, 90, 10, 85];
+        let alerts: Vec<PingAlertPayload> = rtts
+            .iter()
+            .enumerate()
+            .filter_map(|(i, rtt)| {
+                let now = start + Duration::from_secs(i as u64);
+                alerter.observe(&sample(i as u16, Some(*rtt)), now)
+            })
+            .collect();
+        assert_eq!(alerts.len(), 1);
+        assert_eq!(alerts[0].seq, 5);
+        assert_eq!(
+            alerts[0].kind,
+            PingAlertKind::LatencySpike {
+                rtt_ms: 80.0,
+                median_ms: 10.0
+            }
+        );
+        let later = start + Duration::from_secs(60);
+        assert!(alerter.observe(&sample(9, Some(200)), later).is_some());
+    }
+
+    #[test]
+    fn consecutive_losses_alert() {
+        let dst = "192.0.2.1".parse().unwrap();
+        let mut alerter = PingAlerter::new(dst, AlertSetting::default());
+        let now = Instant::now();
+        assert!(alerter.observe(&sample(0, None), now).is_none());
+        assert!(alerter.observe(&sample(1, None), now).is_none());
+        let alert = alerter.observe(&sample(2, None), now).unwrap();
+        assert_eq!(alert.kind, PingAlertKind::Loss { consecutive: 3 });
+        assert!(alerter.observe(&sample(3, None), now).is_none());
+    }
+}
@@ -0,0 +1,179 @@
+//! Ping a list of targets concurrently, as for a watchlist
+use super::result::PingStat;
+use super::session::ping;
+use super::setting::{DEFAULT_INTERVAL_MS, DEFAULT_PING_COUNT, DEFAULT_TIMEOUT_MS};
+use super::{PingSetting, Prober};
+use crate::cancel::CancellationToken;
+use crate::pool::map_concurrent;
+use std::net::IpAddr;
+use ts_rs::TS;
+
+/// Event name emitted as each target finishes
+pub const BULK_PING_TARGET_DONE_EVENT: &str = "bulk_ping:target_done";
+/// Default number of targets pinged at the same time
+pub const DEFAULT_BULK_CONCURRENCY: usize = 16;
+
+#[derive(Clone, Debug, PartialEq, Eq, TS)]
+pub struct BulkPingSetting {
+    /// Identifies the run in events. The whole run shares one token.
+    pub run_id: String,
+    /// Pings per target
+    pub count: u32,
+    #[ts(type = "number")]
+    pub timeout_ms: u64,
+    #[ts(type = "number")]
+    pub interval_ms: u64,
+    /// Targets pinged at the same time
+    pub concurrency: usize,
+}
+
+impl BulkPingSetting {
+    pub fn new(run_id: &str) -> BulkPingSetting {
+        BulkPingSetting {
+            run_id: run_id.to_string(),
+            count: DEFAULT_PING_COUNT,
+            timeout_ms: DEFAULT_TIMEOUT_MS,
+            interval_ms: DEFAULT_INTERVAL_MS,
+            concurrency: DEFAULT_BULK_CONCURRENCY,
+        }
+    }
+    fn ping_setting(&self, dst_ip: IpAddr) -> PingSetting {
+        let mut setting = PingSetting::new(dst_ip);
+        setting.count = self.count;
+        setting.timeout_ms = self.timeout_ms;
+        setting.interval_ms = self.interval_ms;
+        setting
+    }
+}
+
+/// Summary line of one target
+#[derive(Clone, Debug, PartialEq, TS)]
+pub struct BulkPingRow {
+    pub dst_ip: IpAddr,
+    pub stat: PingStat,
+    /// At least one ping answered
+    pub reachable: bool,
+}
+
+/// Payload of [`BULK_PING_TARGET_DONE_EVENT`]
+#[derive(Clone, Debug, PartialEq, TS)]
+pub struct BulkPingTargetDone {
+    pub run_id: String,
+    pub row: BulkPingRow,
+}
+
+#[derive(Clone, Debug, PartialEq, TS)]
+pub struct BulkPingResult {
+    pub run_id: String,
+    /// Finished targets in input order
+    pub rows: Vec<BulkPingRow>,
+    pub cancelled: bool,
+}
+
+impl BulkPingResult {
+    pub fn reachable(&self) -> usize {
+        self.rows.iter().filter(|r| r.reachable).count()
+    }
+}
+
+/// Ping every target, at most `setting.concurrency` at a time, calling
+/// `on_done` from the worker threads as each one finishes
+pub fn bulk_ping<P, F>(
+    prober: &P,
+    targets: &[IpAddr],
+    setting: &BulkPingSetting,
+    token: &CancellationToken,
+    on_done: F,
+) -> BulkPingResult
+where
+    P: Prober,
+    F: Fn(&BulkPingTargetDone) + Sync,
+{
+    let rows = map_concurrent(targets, setting.concurrency, token, |dst| {
+        let done = ping(prober, &setting.ping_setting(*dst), token, |_| {});
+        let row = BulkPingRow {
+            dst_ip: *dst,
+            reachable: done.stat.received > 0,
+            stat: done.stat,
+        };
+        on_done(&BulkPingTargetDone {
+            run_id: setting.run_id.clone(),
+            row: row.clone(),
+        });
+        row
+    });
+    BulkPingResult {
+        run_id: setting.run_id.clone(),
+        rows: rows.into_iter().flatten().collect(),
+        cancelled: token.is_cancelled(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ping::{ProbeError, ProbeReply};
+    use std::sync::Mutex;
+    use std::time::Duration;
+
+    /// Targets with an odd last octet are down; the rest answer after
+    /// as many milliseconds as the sequence number plus one
+    struct Watchlist;
+
+    impl Prober for Watchlist {
+        fn probe(&self, dst: IpAddr, seq: u16, _t: Duration) -> Result<ProbeReply, ProbeError> {
+            let IpAddr::V4(v4) = dst else {
+                return Err(ProbeError::Timeout);
+            };
+            if v4.octets()[3] % 2 == 1 {
+                return Err(ProbeError::Timeout);
+            }
+            Ok(ProbeReply {
+                responder: dst,
+                rtt: Duration::from_millis(seq as u64 + 1),
+                ttl: Some(64),
+            })
+        }
+    }
+
+    #[test]
+    fn consolidated_summary_of_mixed_targets() {
+        let targets: Vec<IpAddr> = (1..=6).map(|n| IpAddr::from([192, 0, 2, n])).collect();
+        let setting = BulkPingSetting {
+            count: 3,
+            interval_ms: 0,
+            concurrency: 4,
+            ..BulkPingSetting::new("watchlist-1")
+        };
+        let events = Mutex::new(Vec::new());
+        let result = bulk_ping(
+            &Watchlist,
+            &targets,
+            &setting,
+            &CancellationToken::new(),
+            |e| events.lock().unwrap().push(e.clone()),
+        );
+        assert_eq!(result.rows.len(), 6);
+        assert_eq!(result.reachable(), 3);
+        let order: Vec<IpAddr> = result.rows.iter().map(|r| r.dst_ip).collect();
+        assert_eq!(order, targets);
+        let up = &result.rows[1].stat;
+        assert_eq!(
+            (up.min_ms, up.avg_ms, up.max_ms),
+            (Some(1.0), Some(2.0), Some(3.0))
+        );
+        assert_eq!(up.loss_percent, 0.0);
+        let down = &result.rows[0];
+        assert!(!down.reachable);
+        assert_eq!((down.stat.avg_ms, down.stat.loss_percent), (None, 100.0));
+
+        let events = events.into_inner().unwrap();
+        assert_eq!(events.len(), 6);
+        assert!(events.iter().all(|e| e.run_id == "watchlist-1"));
+
+        let token = CancellationToken::new();
+        token.cancel();
+        let result = bulk_ping(&Watchlist, &targets, &setting, &token, |_| {});
+        assert!(result.cancelled && result.rows.is_empty());
+    }
+}
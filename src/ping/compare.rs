@@ -0,0 +1,254 @@
+//! Run the same measurement over two interfaces
+use super::result::{PingSample, PingStat};
+use super::Prober;
+use crate::cancel::CancellationToken;
+use crate::net::interface::{classify_ipv6, Interface, Ipv6AddrKind};
+use std::net::IpAddr;
+use std::thread;
+use std::time::Duration;
+
+/// Measurement over one interface
+#[derive(Clone, Debug, PartialEq)]
+pub struct InterfaceMeasurement {
+    pub iface: String,
+    /// Source address the probes were bound to
+    pub src_ip: Option<IpAddr>,
+    pub stat: PingStat,
+    /// Set when the interface could not be used or nothing answered
+    pub error: Option<String>,
+}
+
+/// Side-by-side result of [`compare_interfaces`]
+#[derive(Clone, Debug, PartialEq)]
+pub struct InterfaceComparison {
+    pub dst_ip: IpAddr,
+    pub first: InterfaceMeasurement,
+    pub second: InterfaceMeasurement,
+}
+
+/// Source address on `iface` usable for reaching `dst`. IPv6 prefers
+/// stable over temporary addresses, and link-local for link-local targets.
+pub fn source_ip_for(iface: &Interface, dst: IpAddr) -> Option<IpAddr> {
+    if let IpAddr::V6(dst) = dst {
+        let addrs = iface.ipv6_addrs();
+        let link_local = addrs
+            .iter()
+            .find(|(_, kind)| *kind == Ipv6AddrKind::LinkLocal)
+            .filter(|_| classify_ipv6(&dst, Default::default()) == Ipv6AddrKind::LinkLocal);
+        return link_local.or(addrs.first()).map(|(a, _)| IpAddr::V6(*a));
+    }
+    iface
+        .addrs
+        .iter()
+        .map(|net| net.addr)
+        .find(|addr| addr.is_ipv4() && !addr.is_loopback())
+}
+
+fn measure<P, F>(
+    iface: &Interface,
+    dst: IpAddr,
+    count: u32,
+    timeout: Duration,
+    make_prober: &F,
+    token: &CancellationToken,
+) -> InterfaceMeasurement
+where
+    P: Prober,
+    F: Fn(IpAddr) -> P + Sync,
+{
+    let mut measurement = InterfaceMeasurement {
+        iface: iface.name.clone(),
+        src_ip: None,
+        stat: PingStat::default(),
+        error: None,
+    };
+    if !iface.is_up {
+        measurement.error = Some(format!("Interface {} is down", iface.name));
+        return measurement;
+    }
+    let Some(src_ip) = source_ip_for(iface, dst) else {
+        measurement.error = Some(format!("No usable address on {}", iface.name));
+        return measurement;
+    };
+    measurement.src_ip = Some(src_ip);
+    let prober = make_prober(src_ip);
+    let mut samples = Vec::new();
+    // Sequence numbers wrap as in a long ping; every one of `count` is sent
+    for seq in (0..=u16::MAX).cycle().take(count as usize) {
+        if token.is_cancelled() {
+            break;
+        }
+        let reply = prober.probe(dst, seq, timeout).ok();
+        samples.push(PingSample {
+            seq,
+            rtt: reply.as_ref().map(|r| r.rtt),
+            ttl: reply.as_ref().and_then(|r| r.ttl),
+            ttl_changed: false,
+            unreachable: None,
+            duplicates: 0,
+            responder: reply.map(|r| r.responder),
+        });
+    }
+    measurement.stat = PingStat::from_samples(&samples);
+    if measurement.stat.received == 0 {
+        measurement.error = Some(format!("No connectivity via {}", iface.name));
+    }
+    measurement
+}
+
+/// Ping `dst` through both interfaces concurrently.
+///
+/// `make_prober` builds a prober bound to the given source address.
+pub fn compare_interfaces<P, F>(
+    first: &Interface,
+    second: &Interface,
+    dst: IpAddr,
+    count: u32,
+    timeout: Duration,
+    make_prober: F,
+    token: &CancellationToken,
+) -> InterfaceComparison
+where
+    P: Prober,
+    F: Fn(IpAddr) -> P + Sync,
+{
+    let (first, second) = thread::scope(|s| {
+        let a = s.spawn(|| measure(first, dst, count, timeout, &make_prober, token));
+        let b = measure(second, dst, count, timeout, &make_prober, token);
+        (a.join().expect("measurement thread panicked"), b)
+    });
+    InterfaceComparison {
+        dst_ip: dst,
+        first,
+        second,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::net::ipnet::IpNet;
+    use crate::ping::{ProbeError, ProbeReply};
+    use std::sync::Mutex;
+
+    /// Records the source address it is bound to; only `10.0.0.2` has connectivity
+    struct BoundStub<'a> {
+        src: IpAddr,
+        log: &'a Mutex<Vec<IpAddr>>,
+    }
+
+    impl Prober for BoundStub<'_> {
+        fn probe(
+            &self,
+            dst: IpAddr,
+            _seq: u16,
+            _timeout: Duration,
+        ) -> Result<ProbeReply, ProbeError> {
+            self.log.lock().unwrap().push(self.src);
+            if self.src == "10.0.0.2".parse::<IpAddr>().unwrap() {
+                Ok(ProbeReply {
+                    responder: dst,
+                    rtt: Duration::from_millis(5),
+                    ttl: None,
+                })
+            } else {
+                Err(ProbeError::Timeout)
+            }
+        }
+    }
+
+    fn iface(index: u32, name: &str, addr: &str) -> Interface {
+        let mut iface = Interface::new(index, name);
+        iface.is_up = true;
+        iface.addrs.push(IpNet::new("fe80::1".parse().unwrap(), 64));
+        iface.addrs.push(IpNet::new(addr.parse().unwrap(), 24));
+        iface
+    }
+
+    #[test]
+    fn each_side_is_bound_to_its_interface() {
+        let log = Mutex::new(Vec::new());
+        let wifi = iface(2, "wlan0", "10.0.0.2");
+        let cell = iface(3, "wwan0", "100.64.1.9");
+        let dst: IpAddr = "192.0.2.1".parse().unwrap();
+        let result = compare_interfaces(
+            &wifi,
+            &cell,
+            dst,
+            3,
+            Duration::from_millis(10),
+            |src| BoundStub { src, log: &log },
+            &CancellationToken::new(),
+        );
+        assert_eq!(result.first.src_ip, Some("10.0.0.2".parse().unwrap()));
+        assert_eq!(result.first.stat.received, 3);
+        assert_eq!(result.first.error, None);
+        assert_eq!(result.second.src_ip, Some("100.64.1.9".parse().unwrap()));
+        assert_eq!(result.second.stat.received, 0);
+        assert!(result.second.error.is_some());
+        let log = log.into_inner().unwrap();
+        assert_eq!(log.len(), 6);
+        assert_eq!(
+            log.iter().filter(|ip| ip.to_string() == "10.0.0.2").count(),
+            3
+        );
+    }
+
+    #[test]
+    fn counts_past_the_sequence_space_are_sent_in_full() {
+        let log = Mutex::new(Vec::new());
+        let wifi = iface(2, "wlan0", "10.0.0.2");
+        let result = compare_interfaces(
+            &wifi,
+            &wifi,
+            "192.0.2.1".parse().unwrap(),
+            65_537,
+            Duration::ZERO,
+            |src| BoundStub { src, log: &log },
+            &CancellationToken::new(),
+        );
+        assert_eq!(result.first.stat.sent, 65_537);
+        assert_eq!(result.first.stat.received, 65_537);
+        assert_eq!(log.into_inner().unwrap().len(), 2 * 65_537);
+    }
+
+    #[test]
+    fn stable_ipv6_source_preferred() {
+        use crate::net::interface::Ipv6AddrFlags;
+        let mut eth0 = iface(2, "eth0", "10.0.0.2");
+        let temporary = "2001:db8::a1b2:c3d4".parse().unwrap();
+        eth0.addrs.push(IpNet::new(IpAddr::V6(temporary), 64));
+        eth0.addrs
+            .push(IpNet::new("2001:db8::1".parse().unwrap(), 64));
+        let flags = Ipv6AddrFlags {
+            temporary: true,
+            deprecated: false,
+        };
+        eth0.ipv6_flags.push((temporary, flags));
+        let src = |dst: &str| source_ip_for(&eth0, dst.parse().unwrap()).unwrap();
+        assert_eq!(src("2001:db8:ffff::1").to_string(), "2001:db8::1");
+        assert_eq!(src("fe80::99").to_string(), "fe80::1");
+        assert_eq!(src("192.0.2.1").to_string(), "10.0.0.2");
+    }
+
+    #[test]
+    fn interface_without_address_is_reported() {
+        let log = Mutex::new(Vec::new());
+        let mut v6_only = Interface::new(4, "tun0");
+        v6_only.is_up = true;
+        let result = compare_interfaces(
+            &iface(2, "wlan0", "10.0.0.2"),
+            &v6_only,
+            "192.0.2.1".parse().unwrap(),
+            1,
+            Duration::from_millis(10),
+            |src| BoundStub { src, log: &log },
+            &CancellationToken::new(),
+        );
+        assert_eq!(result.second.src_ip, None);
+        assert_eq!(
+            result.second.error.as_deref(),
+            Some("No usable address on tun0")
+        );
+    }
+}
@@ -0,0 +1,506 @@
+//! ICMP echo prober over one long-lived socket per address family
+use super::icmp::{self, build_echo, parse_echo, Echo, EchoKind};
+use super::matcher::ReplyMatcher;
+use super::{ProbeError, ProbeReply, Prober};
+use crate::net::scope::ScopedIp;
+use crate::pcap::{Capture, Direction};
+use crate::socket::icmp::{DropCounter, IcmpConfig, IcmpSocket, Received};
+use crate::trace::reply::{parse_ipv4_reply, parse_ipv6_reply, ReplyKind};
+use std::collections::{HashMap, HashSet};
+use std::io;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::{Condvar, Mutex, OnceLock};
+use std::time::{Duration, Instant, SystemTime};
+
+/// Default echo payload length, as sent by the classic ping
+pub const DEFAULT_PAYLOAD_LEN: usize = 56;
+/// Longest one thread waits before checking whether it should take over
+/// receiving for the others
+const WAIT_SLICE: Duration = Duration::from_millis(20);
+
+type Key = (IpAddr, u16);
+
+#[derive(Debug)]
+struct State {
+    matcher: ReplyMatcher,
+    /// Probes a thread is waiting on
+    waiting: HashSet<Key>,
+    /// Outcomes received for waiting probes, picked up by their thread
+    outcomes: HashMap<Key, Result<ProbeReply, ProbeError>>,
+    drops: DropCounter,
+    truncated: u64,
+}
+
+/// Socket of one family with the matcher of the probes sent over it.
+///
+/// Probes from many threads share the socket. Whichever waiting thread
+/// holds `buf` reads packets for everyone and hands each outcome to the
+/// thread waiting on it.
+#[derive(Debug)]
+struct Channel {
+    socket: IcmpSocket,
+    buf: Mutex<Vec<u8>>,
+    state: Mutex<State>,
+    ready: Condvar,
+    capture: Option<Capture>,
+}
+
+impl Channel {
+    fn open(
+        ipv6: bool,
+        id: u16,
+        pinned: bool,
+        config: &IcmpConfig,
+        capture: Option<Capture>,
+    ) -> io::Result<Channel> {
+        let socket = IcmpSocket::open_with_id(ipv6, config, id, pinned)?;
+        let state = State {
+            matcher: ReplyMatcher::for_socket(id, socket.kind()),
+            waiting: HashSet::new(),
+            outcomes: HashMap::new(),
+            drops: DropCounter::new(),
+            truncated: 0,
+        };
+        Ok(Channel {
+            buf: Mutex::new(vec![0u8; config.buffer_len]),
+            socket,
+            state: Mutex::new(state),
+            ready: Condvar::new(),
+            capture,
+        })
+    }
+
+    fn probe(
+        &self,
+        to: SocketAddr,
+        id: u16,
+        seq: u16,
+        payload: &Payload,
+        timeout: Duration,
+    ) -> Result<ProbeReply, ProbeError> {
+        let dst = to.ip();
+        let key = (dst, seq);
+        let sent_at = Instant::now();
+        let echo = Echo {
+            kind: EchoKind::Request,
+            id,
+            seq,
+            payload: if payload.nonce {
+                icmp::nonce_payload(sent_at, seq, icmp::random_nonce(), payload.len)
+            } else if payload.timestamp {
+                icmp::timestamp_payload(sent_at, payload.len)
+            } else {
+                vec![0; payload.len]
+            },
+        };
+        {
+            let mut state = self.state.lock().unwrap();
+            if payload.nonce {
+                let expected = echo.payload.clone();
+                state
+                    .matcher
+                    .register_with_payload(dst, seq, sent_at, expected);
+            } else {
+                state.matcher.register(dst, seq, sent_at);
+            }
+            state.outcomes.remove(&key);
+            state.waiting.insert(key);
+        }
+        let message = build_echo(&echo, dst.is_ipv6());
+        if let Err(e) = self.socket.send_to(&message, to) {
+            self.give_up(key);
+            return Err(ProbeError::Io(e));
+        }
+        if let Some(capture) = &self.capture {
+            // The kernel adds the IP header, so its source is not known here
+            capture.record_icmp(
+                SystemTime::now(),
+                Direction::Sent,
+                None,
+                Some(dst),
+                &message,
+            );
+        }
+        let deadline = sent_at + timeout;
+        let mut state = self.state.lock().unwrap();
+        loop {
+            if let Some(outcome) = state.outcomes.remove(&key) {
+                state.waiting.remove(&key);
+                return outcome;
+            }
+            let left = deadline.saturating_duration_since(Instant::now());
+            if left.is_zero() {
+                drop(state);
+                self.give_up(key);
+                return Err(ProbeError::Timeout);
+            }
+            // Checking and waiting under the state lock means an outcome
+            // handed over in between is never missed
+            match self.buf.try_lock() {
+                Ok(mut buf) => {
+                    drop(state);
+                    let received = self.receive(&mut buf, left.min(WAIT_SLICE));
+                    drop(buf);
+                    state = self.state.lock().unwrap();
+                    if let Some((received, packet, at)) = received {
+                        self.dispatch(&mut state, &received, &packet, at);
+                    }
+                    // Wakes the others to pick up outcomes or to take over
+                    self.ready.notify_all();
+                }
+                Err(_) => {
+                    state = self
+                        .ready
+                        .wait_timeout(state, left.min(WAIT_SLICE))
+                        .unwrap()
+                        .0;
+                }
+            }
+        }
+    }
+
+    /// Read one packet if it arrives within `wait`
+    fn receive(&self, buf: &mut [u8], wait: Duration) -> Option<(Received, Vec<u8>, Instant)> {
+        if !self.socket.wait_readable(wait).ok()? {
+            return None;
+        }
+        let received = self.socket.recv(buf).ok()?;
+        let at = Instant::now();
+        let packet = buf[..received.len].to_vec();
+        if let Some(capture) = &self.capture {
+            if self.socket.kind().has_ip_header(self.socket.is_ipv6()) {
+                capture.record_ip(SystemTime::now(), Direction::Received, &packet);
+            } else {
+                let from = received.from;
+                capture.record_icmp(SystemTime::now(), Direction::Received, from, None, &packet);
+            }
+        }
+        Some((received, packet, at))
+    }
+
+    /// Stop waiting on `key`. A late reply no longer has anyone to go to.
+    fn give_up(&self, key: Key) {
+        let mut state = self.state.lock().unwrap();
+        state.waiting.remove(&key);
+        state.outcomes.remove(&key);
+        state.matcher.expire(key.0, key.1);
+    }
+
+    /// Match a received packet and hand the outcome to its waiting thread
+    fn dispatch(&self, state: &mut State, received: &Received, packet: &[u8], at: Instant) {
+        if let Some(drops) = received.drops {
+            state.drops.observe(drops);
+        }
+        if received.truncated {
+            state.truncated += 1;
+        }
+        let Some((key, outcome)) = classify(&mut state.matcher, &self.socket, received, packet, at)
+        else {
+            return;
+        };
+        if state.waiting.contains(&key) {
+            state.outcomes.insert(key, outcome);
+        }
+    }
+}
+
+/// Outcome for the probe a packet answers, if it answers one of ours
+fn classify(
+    matcher: &mut ReplyMatcher,
+    socket: &IcmpSocket,
+    received: &Received,
+    packet: &[u8],
+    at: Instant,
+) -> Option<(Key, Result<ProbeReply, ProbeError>)> {
+    let ipv6 = socket.is_ipv6();
+    let kind = socket.kind();
+    let message = kind.icmp_message(packet, ipv6)?;
+    let header = if kind.has_ip_header(ipv6) {
+        parse_ipv4_reply(packet)
+    } else {
+        match received.from? {
+            IpAddr::V6(src) => parse_ipv6_reply(message, src, received.ttl),
+            // Datagram sockets strip the header, errors never arrive on them
+            IpAddr::V4(_) => None,
+        }
+    };
+    let src = header.as_ref().map(|h| h.src).or(received.from)?;
+    let ttl = header.as_ref().and_then(|h| h.ttl).or(received.ttl);
+    if let Some(echo) = parse_echo(message, ipv6) {
+        let matched = matcher.on_reply(src, &echo, at)?;
+        if matched.duplicate {
+            return None;
+        }
+        let reply = ProbeReply {
+            responder: src,
+            rtt: matched.rtt,
+            ttl,
+        };
+        return Some(((src, matched.seq), Ok(reply)));
+    }
+    let header = header?;
+    if !matches!(header.kind, ReplyKind::DestinationUnreachable { .. }) {
+        return None;
+    }
+    let dst = header.probe_dst?;
+    let (seq, reason) = matcher.on_error(&header)?;
+    let error = ProbeError::Unreachable {
+        responder: header.src,
+        reason,
+    };
+    Some(((dst, seq), Err(error)))
+}
+
+/// Echo payload of each probe
+#[derive(Clone, Copy, Debug)]
+struct Payload {
+    len: usize,
+    /// Embed the send time, see [`icmp::timestamp_payload`]
+    timestamp: bool,
+    /// Embed the send time and a random nonce the reply must echo, see
+    /// [`icmp::nonce_payload`]
+    nonce: bool,
+}
+
+/// Pings over ICMP echo. Opens the socket of each address family on first
+/// use, unprivileged where the OS allows and raw otherwise, and keeps it
+/// for every probe so duplicates, reordering and kernel drops are tracked
+/// across the session.
+#[derive(Debug)]
+pub struct IcmpEchoProber {
+    id: u16,
+    pinned_id: bool,
+    payload: Payload,
+    config: IcmpConfig,
+    scope_id: u32,
+    capture: Option<Capture>,
+    v4: OnceLock<Result<Channel, (io::ErrorKind, String)>>,
+    v6: OnceLock<Result<Channel, (io::ErrorKind, String)>>,
+}
+
+impl IcmpEchoProber {
+    /// Prober sending echo requests with identifier `id`
+    pub fn new(id: u16) -> IcmpEchoProber {
+        IcmpEchoProber::with_config(id, IcmpConfig::default())
+    }
+    pub fn with_config(id: u16, config: IcmpConfig) -> IcmpEchoProber {
+        IcmpEchoProber {
+            id,
+            pinned_id: false,
+            payload: Payload {
+                len: DEFAULT_PAYLOAD_LEN,
+                timestamp: true,
+                nonce: false,
+            },
+            config,
+            scope_id: 0,
+            capture: None,
+            v4: OnceLock::new(),
+            v6: OnceLock::new(),
+        }
+    }
+    /// Insist on sending `id`, as for an identifier the user chose. Where
+    /// the kernel would replace it on an unprivileged socket and the
+    /// identifier cannot be claimed there, a raw socket is used instead.
+    pub fn with_pinned_id(mut self, pinned: bool) -> IcmpEchoProber {
+        self.pinned_id = pinned;
+        self
+    }
+    /// Length of the echo payload, at least the embedded timestamp
+    pub fn with_payload_len(mut self, len: usize) -> IcmpEchoProber {
+        self.payload.len = len;
+        self
+    }
+    /// Embed the send time in the payload and take the RTT from it, which
+    /// keeps scheduling delays of the sending thread out of the RTT.
+    /// On by default.
+    pub fn with_timestamp_payload(mut self, timestamp: bool) -> IcmpEchoProber {
+        self.payload.timestamp = timestamp;
+        self
+    }
+    /// Give every probe a random nonce and accept only replies echoing it,
+    /// so stray or spoofed replies with our identifier and sequence number
+    /// are dropped. The payload also carries the send time.
+    pub fn with_nonce_payload(mut self, nonce: bool) -> IcmpEchoProber {
+        self.payload.nonce = nonce;
+        self
+    }
+    /// Interface index link-local IPv6 destinations are reached through.
+    /// Other destinations ignore it.
+    pub fn with_scope_id(mut self, scope_id: u32) -> IcmpEchoProber {
+        self.scope_id = scope_id;
+        self
+    }
+    /// Record every request sent and packet received to `capture`. Takes
+    /// effect for sockets opened after this, so set it before probing.
+    pub fn with_capture(mut self, capture: Capture) -> IcmpEchoProber {
+        self.capture = Some(capture);
+        self
+    }
+    /// Replies dropped because they did not echo the probe's nonce
+    pub fn rejected_payloads(&self) -> u64 {
+        self.opened()
+            .map(|c| c.state.lock().unwrap().matcher.rejected_payloads())
+            .sum()
+    }
+    pub fn id(&self) -> u16 {
+        self.id
+    }
+    /// Identifier requests of the family go out with, once its socket is
+    /// open. Differs from `id` where the kernel picked its own.
+    pub fn sent_id(&self, ipv6: bool) -> Option<u16> {
+        let cell = if ipv6 { &self.v6 } else { &self.v4 };
+        cell.get()?.as_ref().ok()?.socket.sent_id(self.id)
+    }
+    pub fn config(&self) -> &IcmpConfig {
+        &self.config
+    }
+    /// Address requests to `dst` are sent to, with the scope id where it
+    /// applies
+    pub fn dst_addr(&self, dst: IpAddr) -> SocketAddr {
+        ScopedIp::for_target(dst, self.scope_id).socket_addr(0)
+    }
+    fn channel(&self, ipv6: bool) -> io::Result<&Channel> {
+        let cell = if ipv6 { &self.v6 } else { &self.v4 };
+        cell.get_or_init(|| {
+            Channel::open(
+                ipv6,
+                self.id,
+                self.pinned_id,
+                &self.config,
+                self.capture.clone(),
+            )
+            .map_err(|e| (e.kind(), e.to_string()))
+        })
+        .as_ref()
+        .map_err(|(kind, message)| io::Error::new(*kind, message.clone()))
+    }
+    /// Channels opened so far
+    fn opened(&self) -> impl Iterator<Item = &Channel> {
+        [&self.v4, &self.v6]
+            .into_iter()
+            .filter_map(|cell| cell.get()?.as_ref().ok())
+    }
+    /// Packets cut short by the receive buffer, see `IcmpConfig::for_mtu`
+    pub fn truncated_packets(&self) -> u64 {
+        self.opened()
+            .map(|c| c.state.lock().unwrap().truncated)
+            .sum()
+    }
+    fn sum_matchers(&self, f: impl Fn(&ReplyMatcher) -> u64) -> Option<u64> {
+        let mut opened = self.opened().peekable();
+        opened.peek()?;
+        Some(opened.map(|c| f(&c.state.lock().unwrap().matcher)).sum())
+    }
+}
+
+impl Prober for IcmpEchoProber {
+    fn probe(&self, dst: IpAddr, seq: u16, timeout: Duration) -> Result<ProbeReply, ProbeError> {
+        self.channel(dst.is_ipv6())?
+            .probe(self.dst_addr(dst), self.id, seq, &self.payload, timeout)
+    }
+    fn dropped_packets(&self) -> Option<u64> {
+        let mut counting = self.opened().filter(|c| c.socket.counts_drops()).peekable();
+        counting.peek()?;
+        Some(
+            counting
+                .map(|c| c.state.lock().unwrap().drops.dropped())
+                .sum(),
+        )
+    }
+    fn duplicate_replies(&self) -> Option<u64> {
+        self.sum_matchers(ReplyMatcher::total_duplicates)
+    }
+    fn duplicates_of(&self, dst: IpAddr, seq: u16) -> Option<u32> {
+        let channel = self.channel(dst.is_ipv6()).ok()?;
+        let state = channel.state.lock().unwrap();
+        Some(state.matcher.duplicates(dst, seq))
+    }
+    fn reordered_replies(&self) -> Option<u64> {
+        self.sum_matchers(ReplyMatcher::reordered)
+    }
+    fn check_family(&self, ipv6: bool) -> io::Result<()> {
+        self.channel(ipv6).map(|_| ())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cancel::CancellationToken;
+    use crate::pool::map_concurrent;
+
+    /// Skips the test where this user may open neither kind of ICMP socket
+    fn prober() -> Option<IcmpEchoProber> {
+        let prober = IcmpEchoProber::new(icmp::random_id());
+        prober.check_family(false).ok()?;
+        Some(prober)
+    }
+
+    #[test]
+    fn loopback_answers_echo() {
+        let Some(prober) = prober() else {
+            return;
+        };
+        let dst: IpAddr = "127.0.0.1".parse().unwrap();
+        let reply = prober.probe(dst, 1, Duration::from_secs(2)).unwrap();
+        assert_eq!(reply.responder, dst);
+        assert!(reply.rtt < Duration::from_secs(2));
+        assert_eq!(prober.duplicate_replies(), Some(0));
+        if prober.check_family(true).is_ok() {
+            let dst: IpAddr = "::1".parse().unwrap();
+            let reply = prober.probe(dst, 2, Duration::from_secs(2)).unwrap();
+            assert_eq!(reply.responder, dst);
+        }
+    }
+
+    #[test]
+    fn concurrent_probes_get_their_own_replies() {
+        let Some(prober) = prober() else {
+            return;
+        };
+        // As in a host scan
+        let prober = prober.with_nonce_payload(true);
+        let targets: Vec<IpAddr> = (1..=16).map(|n| IpAddr::from([127, 0, 0, n])).collect();
+        let replies = map_concurrent(&targets, 16, &CancellationToken::new(), |dst| {
+            prober.probe(*dst, 7, Duration::from_secs(2))
+        });
+        for (dst, reply) in targets.iter().zip(replies) {
+            assert_eq!(reply.unwrap().unwrap().responder, *dst);
+        }
+        assert_eq!(prober.rejected_payloads(), 0);
+    }
+
+    #[test]
+    fn probes_and_replies_are_captured() {
+        if prober().is_none() {
+            return;
+        }
+        let file = std::env::temp_dir().join(format!("netdia-echo-{}.pcap", std::process::id()));
+        // The capture is handed to the socket when it opens on first use
+        let prober = IcmpEchoProber::new(icmp::random_id())
+            .with_capture(Capture::create(&file, None).unwrap());
+        let dst: IpAddr = "127.0.0.1".parse().unwrap();
+        prober.probe(dst, 3, Duration::from_secs(2)).unwrap();
+        prober.capture.as_ref().unwrap().finish().unwrap();
+        let bytes = std::fs::read(&file).unwrap();
+        let _ = std::fs::remove_file(&file);
+        // Walk the records after the 24-byte global header
+        let mut records = Vec::new();
+        let mut at = 24;
+        while at + 16 <= bytes.len() {
+            let len = u32::from_le_bytes(bytes[at + 8..at + 12].try_into().unwrap()) as usize;
+            records.push(&bytes[at + 16..at + 16 + len]);
+            at += 16 + len;
+        }
+        assert_eq!(at, bytes.len());
+        // Request to loopback, then at least the reply
+        assert!(records.len() >= 2, "{}", records.len());
+        assert_eq!(records[0][0] >> 4, 4);
+        assert_eq!(&records[0][16..20], &[127, 0, 0, 1]);
+        assert_eq!(records[0][20], 8);
+        assert!(records[1..]
+            .iter()
+            .any(|r| r[20] == 0 && r[12..16] == [127, 0, 0, 1]));
+    }
+}
@@ -0,0 +1,130 @@
+use super::Prober;
+use crate::cancel::CancellationToken;
+use crate::pool::map_concurrent;
+use crate::stats::median;
+use std::net::IpAddr;
+use std::time::Duration;
+use ts_rs::TS;
+
+/// Settings for the latency heatmap
+#[derive(Clone, Debug, PartialEq, Eq, TS)]
+pub struct HeatmapSetting {
+    /// Probes sent to each target
+    pub count: u16,
+    /// Timeout per probe
+    #[ts(type = "number")]
+    pub timeout_ms: u64,
+    /// Maximum number of targets probed at once
+    pub concurrency: usize,
+}
+
+impl Default for HeatmapSetting {
+    fn default() -> Self {
+        HeatmapSetting {
+            count: 3,
+            timeout_ms: 1000,
+            concurrency: 32,
+        }
+    }
+}
+
+/// Median RTT of one target. `median_rtt_ms` is `None` for unreachable targets.
+#[derive(Clone, Debug, PartialEq, TS)]
+pub struct HeatmapEntry {
+    pub target: IpAddr,
+    pub sent: u16,
+    pub received: u16,
+    pub median_rtt_ms: Option<f64>,
+}
+
+/// Ping every target `count` times and return entries sorted by median RTT.
+///
+/// Unreachable targets are kept as holes at the end of the list. Targets not
+/// probed before cancellation are omitted.
+pub fn latency_heatmap<P: Prober>(
+    prober: &P,
+    targets: &[IpAddr],
+    setting: &HeatmapSetting,
+    token: &CancellationToken,
+) -> Vec<HeatmapEntry> {
+    let timeout = Duration::from_millis(setting.timeout_ms);
+    let results = map_concurrent(targets, setting.concurrency, token, |target| {
+        let mut rtts: Vec<f64> = Vec::new();
+        let mut sent = 0;
+        for seq in 0..setting.count {
+            if token.is_cancelled() {
+                break;
+            }
+            sent += 1;
+            if let Ok(reply) = prober.probe(*target, seq, timeout) {
+                rtts.push(reply.rtt.as_secs_f64() * 1000.0);
+            }
+        }
+        HeatmapEntry {
+            target: *target,
+            sent,
+            received: rtts.len() as u16,
+            median_rtt_ms: median(&rtts),
+        }
+    });
+    let mut entries: Vec<HeatmapEntry> = results.into_iter().flatten().collect();
+    entries.sort_by(|a, b| match (a.median_rtt_ms, b.median_rtt_ms) {
+        (Some(x), Some(y)) => x.total_cmp(&y),
+        (Some(_), None) => std::cmp::Ordering::Less,
+        (None, Some(_)) => std::cmp::Ordering::Greater,
+        (None, None) => std::cmp::Ordering::Equal,
+    });
+    entries
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ping::{ProbeError, ProbeReply};
+    use std::net::Ipv4Addr;
+
+    /// Replies with the RTTs listed per host, keyed by sequence number
+    struct StubProber;
+
+    impl Prober for StubProber {
+        fn probe(
+            &self,
+            dst: IpAddr,
+            seq: u16,
+            _timeout: Duration,
+        ) -> Result<ProbeReply, ProbeError> {
+            let rtts: &[u64] = match dst {
+                IpAddr::V4(ip) if ip.octets()[3] == 1 => &[30, 10, 20],
+                IpAddr::V4(ip) if ip.octets()[3] == 2 => &[5, 7, 6],
+                _ => return Err(ProbeError::Timeout),
+            };
+            Ok(ProbeReply {
+                responder: dst,
+                rtt: Duration::from_millis(rtts[seq as usize]),
+                ttl: None,
+            })
+        }
+    }
+
+    #[test]
+    fn medians_sorted_with_unreachable_last() {
+        let a = IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1));
+        let b = IpAddr::V4(Ipv4Addr::new(192, 0, 2, 2));
+        let dead = IpAddr::V4(Ipv4Addr::new(192, 0, 2, 3));
+        let entries = latency_heatmap(
+            &StubProber,
+            &[dead, a, b],
+            &HeatmapSetting::default(),
+            &CancellationToken::new(),
+        );
+        assert_eq!(entries.len(), 3);
+        assert_eq!(entries[0].target, b);
+        assert_eq!(entries[0].median_rtt_ms, Some(6.0));
+        assert_eq!(entries[1].target, a);
+        assert_eq!(entries[1].median_rtt_ms, Some(20.0));
+        assert_eq!(entries[2].target, dead);
+        assert_eq!(entries[2].median_rtt_ms, None);
+        assert_eq!(entries[2].sent, 3);
+        assert_eq!(entries[2].received, 0);
+    }
+}
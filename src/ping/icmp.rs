@@ -0,0 +1,243 @@
+//! ICMP echo packet encoding and decoding
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::sync::OnceLock;
+use std::time::{Duration, Instant, SystemTime};
+
+pub const ICMPV4_ECHO_REPLY: u8 = 0;
+pub const ICMPV4_ECHO_REQUEST: u8 = 8;
+pub const ICMPV6_ECHO_REQUEST: u8 = 128;
+pub const ICMPV6_ECHO_REPLY: u8 = 129;
+/// Length of the ICMP echo header
+pub const ECHO_HEADER_LEN: usize = 8;
+/// Marks a payload that starts with a send timestamp
+pub const TIMESTAMP_MAGIC: [u8; 4] = *b"NDts";
+/// Length of the magic and timestamp at the start of a timestamp payload
+pub const TIMESTAMP_LEN: usize = 12;
+/// Length of a [`nonce_payload`] before padding: timestamp, sequence number
+/// and nonce
+pub const NONCE_PAYLOAD_LEN: usize = TIMESTAMP_LEN + 2 + 8;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EchoKind {
+    Request,
+    Reply,
+}
+
+/// ICMP or ICMPv6 echo message
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Echo {
+    pub kind: EchoKind,
+    pub id: u16,
+    pub seq: u16,
+    pub payload: Vec<u8>,
+}
+
+fn echo_type(kind: EchoKind, ipv6: bool) -> u8 {
+    match (kind, ipv6) {
+        (EchoKind::Request, false) => ICMPV4_ECHO_REQUEST,
+        (EchoKind::Reply, false) => ICMPV4_ECHO_REPLY,
+        (EchoKind::Request, true) => ICMPV6_ECHO_REQUEST,
+        (EchoKind::Reply, true) => ICMPV6_ECHO_REPLY,
+    }
+}
+
+/// Internet checksum (RFC 1071)
+pub fn checksum(data: &[u8]) -> u16 {
+    let mut sum: u32 = 0;
+    let mut chunks = data.chunks_exact(2);
+    for chunk in &mut chunks {
+        sum += u16::from_be_bytes([chunk[0], chunk[1]]) as u32;
+    }
+    if let [last] = chunks.remainder() {
+        sum += (*last as u32) << 8;
+    }
+    while sum >> 16 != 0 {
+        sum = (sum & 0xffff) + (sum >> 16);
+    }
+    !(sum as u16)
+}
+
+/// Encode an echo message.
+///
+/// The ICMPv6 checksum covers a pseudo header and is filled in by the kernel,
+/// so it is left zero here.
+pub fn build_echo(echo: &Echo, ipv6: bool) -> Vec<u8> {
+    let mut buf = Vec::with_capacity(ECHO_HEADER_LEN + echo.payload.len());
+    buf.push(echo_type(echo.kind, ipv6));
+    buf.push(0);
+    buf.extend_from_slice(&[0, 0]);
+    buf.extend_from_slice(&echo.id.to_be_bytes());
+    buf.extend_from_slice(&echo.seq.to_be_bytes());
+    buf.extend_from_slice(&echo.payload);
+    if !ipv6 {
+        let sum = checksum(&buf);
+        buf[2..4].copy_from_slice(&sum.to_be_bytes());
+    }
+    buf
+}
+
+/// Decode an echo message. `buf` starts at the ICMP header.
+pub fn parse_echo(buf: &[u8], ipv6: bool) -> Option<Echo> {
+    if buf.len() < ECHO_HEADER_LEN || buf[1] != 0 {
+        return None;
+    }
+    let kind = if buf[0] == echo_type(EchoKind::Request, ipv6) {
+        EchoKind::Request
+    } else if buf[0] == echo_type(EchoKind::Reply, ipv6) {
+        EchoKind::Reply
+    } else {
+        return None;
+    };
+    Some(Echo {
+        kind,
+        id: u16::from_be_bytes([buf[4], buf[5]]),
+        seq: u16::from_be_bytes([buf[6], buf[7]]),
+        payload: buf[ECHO_HEADER_LEN..].to_vec(),
+    })
+}
+
+/// TTL from the IPv4 header that raw IPv4 sockets deliver in front of ICMP
+pub fn ipv4_ttl(packet: &[u8]) -> Option<u8> {
+    if packet.first()? >> 4 != 4 || packet.len() < 20 {
+        return None;
+    }
+    Some(packet[8])
+}
+
+/// Skip the IPv4 header that raw IPv4 sockets deliver in front of ICMP.
+pub fn strip_ipv4_header(buf: &[u8]) -> Option<&[u8]> {
+    let first = *buf.first()?;
+    if first >> 4 != 4 {
+        return None;
+    }
+    let header_len = ((first & 0x0f) as usize) * 4;
+    buf.get(header_len..)
+}
+
+/// Process-wide reference point for payload timestamps
+fn clock_anchor() -> Instant {
+    static ANCHOR: OnceLock<Instant> = OnceLock::new();
+    *ANCHOR.get_or_init(Instant::now)
+}
+
+/// Payload carrying the send time `sent_at`, padded with zeros to `len` bytes.
+///
+/// The timestamp is monotonic and only meaningful within this process.
+pub fn timestamp_payload(sent_at: Instant, len: usize) -> Vec<u8> {
+    let anchor = clock_anchor();
+    let nanos: i64 = if sent_at >= anchor {
+        (sent_at - anchor).as_nanos() as i64
+    } else {
+        -((anchor - sent_at).as_nanos() as i64)
+    };
+    let mut payload = Vec::with_capacity(len.max(TIMESTAMP_LEN));
+    payload.extend_from_slice(&TIMESTAMP_MAGIC);
+    payload.extend_from_slice(&nanos.to_be_bytes());
+    payload.resize(len.max(TIMESTAMP_LEN), 0);
+    payload
+}
+
+/// Timestamp payload followed by the sequence number and a per-probe random
+/// nonce, so a reply can be checked to echo exactly this probe
+pub fn nonce_payload(sent_at: Instant, seq: u16, nonce: u64, len: usize) -> Vec<u8> {
+    let mut payload = timestamp_payload(sent_at, TIMESTAMP_LEN);
+    payload.extend_from_slice(&seq.to_be_bytes());
+    payload.extend_from_slice(&nonce.to_be_bytes());
+    payload.resize(len.max(NONCE_PAYLOAD_LEN), 0);
+    payload
+}
+
+/// Send time embedded by [`timestamp_payload`], if present
+pub fn payload_sent_at(payload: &[u8]) -> Option<Instant> {
+    if payload.len() < TIMESTAMP_LEN || payload[..4] != TIMESTAMP_MAGIC {
+        return None;
+    }
+    let nanos = i64::from_be_bytes(payload[4..TIMESTAMP_LEN].try_into().ok()?);
+    let offset = Duration::from_nanos(nanos.unsigned_abs());
+    if nanos >= 0 {
+        clock_anchor().checked_add(offset)
+    } else {
+        clock_anchor().checked_sub(offset)
+    }
+}
+
+/// Random ICMP identifier
+pub fn random_id() -> u16 {
+    random_nonce() as u16
+}
+
+/// Random value for [`nonce_payload`]
+pub fn random_nonce() -> u64 {
+    let mut hasher = RandomState::new().build_hasher();
+    if let Ok(d) = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH) {
+        hasher.write_u128(d.as_nanos());
+    }
+    hasher.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn echo_round_trip() {
+        let echo = Echo {
+            kind: EchoKind::Request,
+            id: 0x1234,
+            seq: 7,
+            payload: b"netdia".to_vec(),
+        };
+        let v4 = build_echo(&echo, false);
+        assert_eq!(v4[0], ICMPV4_ECHO_REQUEST);
+        assert_eq!(checksum(&v4), 0);
+        assert_eq!(parse_echo(&v4, false), Some(echo.clone()));
+        let v6 = build_echo(&echo, true);
+        assert_eq!(v6[0], ICMPV6_ECHO_REQUEST);
+        assert_eq!(parse_echo(&v6, true), Some(echo));
+        assert_eq!(parse_echo(&v6, false), None);
+    }
+
+    #[test]
+    fn timestamp_payload_round_trip() {
+        let sent_at = Instant::now();
+        let payload = timestamp_payload(sent_at, 56);
+        assert_eq!(payload.len(), 56);
+        assert_eq!(payload_sent_at(&payload), Some(sent_at));
+        assert_eq!(payload_sent_at(b"netdia-payload"), None);
+        let payload = nonce_payload(sent_at, 7, 0xfeed, 16);
+        assert_eq!(payload.len(), NONCE_PAYLOAD_LEN);
+        assert_eq!(payload_sent_at(&payload), Some(sent_at));
+        assert_ne!(payload, nonce_payload(sent_at, 7, 0xbeef, 16));
+    }
+
+    #[test]
+    fn ttl_from_ipv4_reply() {
+        let mut packet = vec![0x45u8, 0, 0, 28, 0, 0, 0, 0, 52, 1, 0, 0];
+        packet.extend_from_slice(&[192, 0, 2, 1, 10, 0, 0, 5]);
+        packet.extend_from_slice(&build_echo(
+            &Echo {
+                kind: EchoKind::Reply,
+                id: 1,
+                seq: 1,
+                payload: Vec::new(),
+            },
+            false,
+        ));
+        assert_eq!(ipv4_ttl(&packet), Some(52));
+        assert_eq!(crate::ping::result::inferred_hops(52), 12);
+        assert_eq!(ipv4_ttl(&packet[..10]), None);
+    }
+
+    #[test]
+    fn strip_ipv4_header_with_options() {
+        let mut packet = vec![0x46u8];
+        packet.extend_from_slice(&[0; 23]);
+        packet.extend_from_slice(&[ICMPV4_ECHO_REPLY, 0, 0, 0]);
+        assert_eq!(
+            strip_ipv4_header(&packet),
+            Some(&[ICMPV4_ECHO_REPLY, 0, 0, 0][..])
+        );
+        assert_eq!(strip_ipv4_header(&[0x60]), None);
+    }
+}
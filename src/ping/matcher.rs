@@ -0,0 +1,551 @@
+use super::icmp::{self, Echo, EchoKind};
+use super::UnreachableReason;
+use crate::socket::icmp::IcmpSocketKind;
+use crate::trace::reply::IcmpReply;
+use std::collections::{HashMap, VecDeque};
+use std::net::IpAddr;
+use std::time::{Duration, Instant};
+
+/// Default distance behind the highest sequence number within which a late
+/// reply counts as reordered rather than as a straggler from long ago
+pub const DEFAULT_REORDER_WINDOW: u16 = 64;
+/// Answered probes remembered for spotting duplicate replies. Duplicates
+/// of older probes are dropped as unsolicited.
+pub const MAX_ANSWERED: usize = 4096;
+/// How long before the recorded send time an embedded timestamp may be,
+/// as the probe is registered only after it was stamped and sent
+pub const TIMESTAMP_TOLERANCE: Duration = Duration::from_millis(100);
+
+/// Reply matched to an outstanding probe
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MatchedReply {
+    pub seq: u16,
+    /// RTT of the first reply to the probe, also for duplicates
+    pub rtt: Duration,
+    /// Set when the probe was already answered (`DUP!`)
+    pub duplicate: bool,
+    /// Arrived after the reply to a later probe
+    pub reordered: bool,
+}
+
+/// Probe waiting for its reply
+#[derive(Clone, Debug)]
+struct Pending {
+    sent_at: Instant,
+    /// Payload the reply must echo, for probes registered with one
+    payload: Option<Vec<u8>>,
+}
+
+/// Probe that got its first reply
+#[derive(Clone, Copy, Debug)]
+struct Answered {
+    rtt: Duration,
+    /// Duplicates that followed the first reply
+    dups: u32,
+    generation: u64,
+}
+
+/// Matches echo replies against the probes sent with one ICMP identifier
+#[derive(Debug)]
+pub struct ReplyMatcher {
+    id: u16,
+    /// The socket only delivers our own replies, under an identifier the
+    /// kernel picked, so the identifier is not checked
+    any_id: bool,
+    pending: HashMap<(IpAddr, u16), Pending>,
+    answered: HashMap<(IpAddr, u16), Answered>,
+    /// Answered probes oldest first, with the generation they were answered
+    /// in, to evict beyond [`MAX_ANSWERED`]
+    answered_order: VecDeque<((IpAddr, u16), u64)>,
+    generation: u64,
+    duplicates: u64,
+    rejected: u64,
+    reorder_window: u16,
+    /// Highest sequence number answered per source
+    highest_seq: HashMap<IpAddr, u16>,
+    reordered: u64,
+}
+
+impl ReplyMatcher {
+    pub fn new(id: u16) -> ReplyMatcher {
+        ReplyMatcher {
+            id,
+            any_id: false,
+            pending: HashMap::new(),
+            answered: HashMap::new(),
+            answered_order: VecDeque::new(),
+            generation: 0,
+            duplicates: 0,
+            rejected: 0,
+            reorder_window: DEFAULT_REORDER_WINDOW,
+            highest_seq: HashMap::new(),
+            reordered: 0,
+        }
+    }
+    /// Count replies up to `window` sequence numbers behind the highest
+    /// answered one as reordered
+    pub fn with_reorder_window(mut self, window: u16) -> ReplyMatcher {
+        self.reorder_window = window;
+        self
+    }
+    /// Matcher for probes sent over a socket of `kind`
+    pub fn for_socket(id: u16, kind: IcmpSocketKind) -> ReplyMatcher {
+        ReplyMatcher {
+            any_id: kind.rewrites_id(),
+            ..ReplyMatcher::new(id)
+        }
+    }
+    pub fn id(&self) -> u16 {
+        self.id
+    }
+    fn id_matches(&self, id: u16) -> bool {
+        self.any_id || id == self.id
+    }
+    /// Record a probe sent to `dst` with sequence number `seq`
+    pub fn register(&mut self, dst: IpAddr, seq: u16, sent_at: Instant) {
+        self.insert_pending((dst, seq), sent_at, None);
+    }
+    /// `register`, also requiring replies to echo `payload` byte for byte,
+    /// e.g. one made by [`icmp::nonce_payload`]. Replies that do not are
+    /// rejected as stray or spoofed.
+    pub fn register_with_payload(
+        &mut self,
+        dst: IpAddr,
+        seq: u16,
+        sent_at: Instant,
+        payload: Vec<u8>,
+    ) {
+        self.insert_pending((dst, seq), sent_at, Some(payload));
+    }
+    fn insert_pending(&mut self, key: (IpAddr, u16), sent_at: Instant, payload: Option<Vec<u8>>) {
+        // The sequence number wrapped; earlier replies belong to another probe
+        self.answered.remove(&key);
+        self.pending.insert(key, Pending { sent_at, payload });
+    }
+    /// Replies rejected because their payload did not match the probe
+    pub fn rejected_payloads(&self) -> u64 {
+        self.rejected
+    }
+    /// Match a received echo from `src`. Returns `None` for foreign or
+    /// unsolicited replies.
+    ///
+    /// The RTT is taken from a timestamp embedded in the payload when present,
+    /// which also matches replies whose pending entry was already evicted.
+    /// The payload comes from the network, so with a pending entry the
+    /// timestamp is only trusted between [`TIMESTAMP_TOLERANCE`] before the
+    /// recorded send time and `received_at`. Otherwise the recorded send
+    /// time is used.
+    ///
+    /// Further replies to an answered probe are counted and returned with
+    /// `duplicate` set and the RTT of the first reply.
+    pub fn on_reply(
+        &mut self,
+        src: IpAddr,
+        echo: &Echo,
+        received_at: Instant,
+    ) -> Option<MatchedReply> {
+        if echo.kind != EchoKind::Reply || !self.id_matches(echo.id) {
+            return None;
+        }
+        let key = (src, echo.seq);
+        let expected = self.pending.get(&key).and_then(|p| p.payload.as_ref());
+        if let Some(expected) = expected {
+            if *expected != echo.payload {
+                self.rejected += 1;
+                return None;
+            }
+        }
+        if let Some(answered) = self.answered.get_mut(&key) {
+            answered.dups = answered.dups.saturating_add(1);
+            self.duplicates += 1;
+            return Some(MatchedReply {
+                seq: echo.seq,
+                rtt: answered.rtt,
+                duplicate: true,
+                reordered: false,
+            });
+        }
+        let recorded = self.pending.remove(&key).map(|p| p.sent_at);
+        let earliest = recorded.map(|at| at.checked_sub(TIMESTAMP_TOLERANCE).unwrap_or(at));
+        let embedded = icmp::payload_sent_at(&echo.payload)
+            .filter(|at| *at <= received_at && earliest.is_none_or(|earliest| *at >= earliest));
+        let sent_at = embedded.or(recorded)?;
+        let rtt = received_at.saturating_duration_since(sent_at);
+        self.remember_answered(key, rtt);
+        let reordered = self.track_order(src, echo.seq);
+        Some(MatchedReply {
+            seq: echo.seq,
+            rtt,
+            duplicate: false,
+            reordered,
+        })
+    }
+    /// Keep `key` for spotting duplicates, forgetting the oldest answered
+    /// probe beyond [`MAX_ANSWERED`]
+    fn remember_answered(&mut self, key: (IpAddr, u16), rtt: Duration) {
+        self.generation += 1;
+        let generation = self.generation;
+        self.answered.insert(
+            key,
+            Answered {
+                rtt,
+                dups: 0,
+                generation,
+            },
+        );
+        self.answered_order.push_back((key, generation));
+        while self.answered_order.len() > MAX_ANSWERED {
+            let Some((old, old_generation)) = self.answered_order.pop_front() else {
+                break;
+            };
+            // Skip entries re-registered since, they are newer
+            if self.answered.get(&old).map(|a| a.generation) == Some(old_generation) {
+                self.answered.remove(&old);
+            }
+        }
+    }
+    /// Whether `seq` from `src` falls behind a later answered sequence
+    /// number, within the reorder window.
+    ///
+    /// Sequence numbers are compared as in RFC 1982 serial number
+    /// arithmetic, so `seq` is newer when less than half the space ahead
+    /// of the highest one, across a wrap too. The highest never moves
+    /// back, and replies further behind than the window are not counted.
+    fn track_order(&mut self, src: IpAddr, seq: u16) -> bool {
+        let highest = self.highest_seq.entry(src).or_insert(seq);
+        let ahead = seq.wrapping_sub(*highest);
+        if ahead < 0x8000 {
+            *highest = seq;
+            return false;
+        }
+        let behind = highest.wrapping_sub(seq);
+        if behind <= self.reorder_window {
+            self.reordered += 1;
+            return true;
+        }
+        false
+    }
+    /// Replies that arrived after the reply to a later probe
+    pub fn reordered(&self) -> u64 {
+        self.reordered
+    }
+    /// Duplicate replies received for the probe to `dst` with `seq`
+    pub fn duplicates(&self, dst: IpAddr, seq: u16) -> u32 {
+        self.answered.get(&(dst, seq)).map_or(0, |a| a.dups)
+    }
+    /// Duplicate replies received for all probes, also those forgotten since
+    pub fn total_duplicates(&self) -> u64 {
+        self.duplicates
+    }
+    /// Match an ICMP destination unreachable quoting one of our probes.
+    /// The probe is no longer pending afterwards.
+    pub fn on_error(&mut self, reply: &IcmpReply) -> Option<(u16, UnreachableReason)> {
+        let reason = reply.unreachable_reason()?;
+        if !self.id_matches(reply.id) {
+            return None;
+        }
+        self.pending.remove(&(reply.probe_dst?, reply.seq))?;
+        Some((reply.seq, reason))
+    }
+    /// Stop waiting for a reply to the probe to `dst` with `seq`, e.g. once
+    /// it timed out
+    pub fn expire(&mut self, dst: IpAddr, seq: u16) {
+        self.pending.remove(&(dst, seq));
+    }
+    /// Number of probes still waiting for a reply
+    pub fn pending(&self) -> usize {
+        self.pending.len()
+    }
+    /// Number of answered probes remembered for spotting duplicates
+    pub fn answered(&self) -> usize {
+        self.answered.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ping::icmp::timestamp_payload;
+    use std::thread;
+
+    fn reply(id: u16, seq: u16, payload: Vec<u8>) -> Echo {
+        Echo {
+            kind: EchoKind::Reply,
+            id,
+            seq,
+            payload,
+        }
+    }
+
+    #[test]
+    fn rtt_from_embedded_timestamp() {
+        let dst: IpAddr = "192.0.2.1".parse().unwrap();
+        let mut matcher = ReplyMatcher::new(1);
+        let sent_at = Instant::now();
+        let payload = timestamp_payload(sent_at, 32);
+        // Registered late, as if the sender was descheduled after sending
+        matcher.register(dst, 0, sent_at + Duration::from_millis(5));
+        thread::sleep(Duration::from_millis(20));
+        let received_at = Instant::now();
+        let matched = matcher
+            .on_reply(dst, &reply(1, 0, payload), received_at)
+            .unwrap();
+        let elapsed = received_at - sent_at;
+        assert_eq!(matched.rtt, elapsed);
+        assert!(matched.rtt >= Duration::from_millis(20));
+    }
+
+    #[test]
+    fn evicted_entry_matches_by_timestamp() {
+        let dst: IpAddr = "192.0.2.1".parse().unwrap();
+        let mut matcher = ReplyMatcher::new(1);
+        let sent_at = Instant::now();
+        let received_at = sent_at + Duration::from_millis(40);
+        let matched = matcher
+            .on_reply(
+                dst,
+                &reply(1, 3, timestamp_payload(sent_at, 16)),
+                received_at,
+            )
+            .unwrap();
+        assert_eq!(matched.rtt, Duration::from_millis(40));
+        // Without a timestamp there is nothing to match against
+        assert_eq!(
+            matcher.on_reply(dst, &reply(1, 4, vec![0; 16]), received_at),
+            None
+        );
+    }
+
+    #[test]
+    fn implausible_timestamps_are_ignored() {
+        let dst: IpAddr = "192.0.2.1".parse().unwrap();
+        let mut matcher = ReplyMatcher::new(1);
+        let sent_at = Instant::now() + Duration::from_secs(10);
+        let received_at = sent_at + Duration::from_millis(30);
+        // Stamped long before the probe was sent, e.g. forged or replayed
+        let stale = timestamp_payload(sent_at - Duration::from_secs(5), 32);
+        matcher.register(dst, 1, sent_at);
+        let matched = matcher
+            .on_reply(dst, &reply(1, 1, stale), received_at)
+            .unwrap();
+        assert_eq!(matched.rtt, Duration::from_millis(30));
+        // Stamped after the reply arrived
+        let future = timestamp_payload(received_at + Duration::from_secs(1), 32);
+        matcher.register(dst, 2, sent_at);
+        let matched = matcher
+            .on_reply(dst, &reply(1, 2, future), received_at)
+            .unwrap();
+        assert_eq!(matched.rtt, Duration::from_millis(30));
+        // Within the tolerance the embedded time wins
+        let early = timestamp_payload(sent_at - Duration::from_millis(50), 32);
+        matcher.register(dst, 3, sent_at);
+        let matched = matcher
+            .on_reply(dst, &reply(1, 3, early), received_at)
+            .unwrap();
+        assert_eq!(matched.rtt, Duration::from_millis(80));
+    }
+
+    #[test]
+    fn falls_back_to_recorded_send_time() {
+        let dst: IpAddr = "192.0.2.1".parse().unwrap();
+        let mut matcher = ReplyMatcher::new(1);
+        let sent_at = Instant::now();
+        matcher.register(dst, 9, sent_at);
+        let matched = matcher
+            .on_reply(
+                dst,
+                &reply(1, 9, b"plain".to_vec()),
+                sent_at + Duration::from_millis(7),
+            )
+            .unwrap();
+        assert_eq!(matched.rtt, Duration::from_millis(7));
+    }
+
+    #[test]
+    fn rewritten_id_matched_on_datagram_socket() {
+        let dst: IpAddr = "192.0.2.1".parse().unwrap();
+        let sent_at = Instant::now();
+        let at = sent_at + Duration::from_millis(3);
+        // Linux answers with the socket's local port as identifier
+        let rewritten = reply(40123, 5, vec![0; 8]);
+        let mut raw = ReplyMatcher::for_socket(1, IcmpSocketKind::Raw);
+        raw.register(dst, 5, sent_at);
+        assert_eq!(raw.on_reply(dst, &rewritten, at), None);
+        let mut dgram = ReplyMatcher::for_socket(1, IcmpSocketKind::Datagram);
+        dgram.register(dst, 5, sent_at);
+        let matched = dgram.on_reply(dst, &rewritten, at);
+        if IcmpSocketKind::Datagram.rewrites_id() {
+            assert_eq!(matched.unwrap().rtt, Duration::from_millis(3));
+        } else {
+            assert_eq!(matched, None);
+        }
+    }
+
+    #[test]
+    fn duplicate_reply_counted_not_matched_again() {
+        let dst: IpAddr = "192.0.2.1".parse().unwrap();
+        let mut matcher = ReplyMatcher::new(1);
+        let sent_at = Instant::now();
+        let payload = timestamp_payload(sent_at, 16);
+        matcher.register(dst, 2, sent_at);
+        let first = matcher
+            .on_reply(
+                dst,
+                &reply(1, 2, payload.clone()),
+                sent_at + Duration::from_millis(5),
+            )
+            .unwrap();
+        assert!(!first.duplicate);
+        let dup = matcher
+            .on_reply(
+                dst,
+                &reply(1, 2, payload),
+                sent_at + Duration::from_millis(9),
+            )
+            .unwrap();
+        assert!(dup.duplicate);
+        assert_eq!(dup.rtt, Duration::from_millis(5));
+        assert_eq!(matcher.duplicates(dst, 2), 1);
+        assert_eq!(matcher.total_duplicates(), 1);
+        // Reusing the sequence number after a wrap starts over
+        matcher.register(dst, 2, Instant::now());
+        assert_eq!(matcher.duplicates(dst, 2), 0);
+    }
+
+    #[test]
+    fn reply_with_mismatched_payload_rejected() {
+        let dst: IpAddr = "192.0.2.1".parse().unwrap();
+        let mut matcher = ReplyMatcher::new(1);
+        let sent_at = Instant::now();
+        let at = sent_at + Duration::from_millis(4);
+        let payload = icmp::nonce_payload(sent_at, 3, 0x1234_5678, 32);
+        matcher.register_with_payload(dst, 3, sent_at, payload.clone());
+        // Same id and sequence number, stale or forged nonce
+        let forged = icmp::nonce_payload(sent_at, 3, 0x8765_4321, 32);
+        assert_eq!(matcher.on_reply(dst, &reply(1, 3, forged), at), None);
+        assert_eq!(matcher.on_reply(dst, &reply(1, 3, Vec::new()), at), None);
+        assert_eq!(matcher.rejected_payloads(), 2);
+        assert_eq!(matcher.pending(), 1);
+        let matched = matcher.on_reply(dst, &reply(1, 3, payload), at).unwrap();
+        assert_eq!(matched.rtt, Duration::from_millis(4));
+        assert_eq!(matcher.pending(), 0);
+        // The expected payload went with the match and a timed out probe
+        matcher.register_with_payload(dst, 4, sent_at, vec![1; 32]);
+        matcher.expire(dst, 4);
+        assert_eq!(matcher.on_reply(dst, &reply(1, 4, Vec::new()), at), None);
+        assert_eq!(matcher.rejected_payloads(), 2);
+    }
+
+    #[test]
+    fn out_of_order_replies_counted() {
+        let dst: IpAddr = "192.0.2.1".parse().unwrap();
+        let mut matcher = ReplyMatcher::new(1).with_reorder_window(8);
+        let sent_at = Instant::now();
+        let mut order = Vec::new();
+        for seq in [0, 2, 1, 3, 6, 4, 5, 7, 40] {
+            matcher.register(dst, seq, sent_at);
+            let matched = matcher
+                .on_reply(dst, &reply(1, seq, vec![0; 8]), sent_at)
+                .unwrap();
+            order.push(matched.reordered);
+        }
+        assert_eq!(
+            order,
+            [false, false, true, false, false, true, true, false, false]
+        );
+        assert_eq!(matcher.reordered(), 3);
+        // Too far behind to count as a late reply
+        matcher.register(dst, 10, sent_at);
+        let matched = matcher.on_reply(dst, &reply(1, 10, vec![0; 8]), sent_at);
+        assert!(!matched.unwrap().reordered);
+        assert_eq!(matcher.reordered(), 3);
+    }
+
+    #[test]
+    fn answered_probes_are_bounded() {
+        let dst: IpAddr = "192.0.2.1".parse().unwrap();
+        let mut matcher = ReplyMatcher::new(1);
+        let sent_at = Instant::now();
+        let total = (MAX_ANSWERED + 100) as u16;
+        for seq in 0..total {
+            matcher.register(dst, seq, sent_at);
+            matcher.on_reply(dst, &reply(1, seq, vec![0; 8]), sent_at);
+        }
+        assert_eq!(matcher.answered(), MAX_ANSWERED);
+        // The oldest is forgotten, the newest still spots duplicates
+        assert_eq!(
+            matcher.on_reply(dst, &reply(1, 0, vec![0; 8]), sent_at),
+            None
+        );
+        let last = total - 1;
+        let dup = matcher.on_reply(dst, &reply(1, last, vec![0; 8]), sent_at);
+        assert!(dup.unwrap().duplicate);
+        assert_eq!(matcher.total_duplicates(), 1);
+    }
+
+    #[test]
+    fn highest_seq_never_moves_back() {
+        let dst: IpAddr = "192.0.2.1".parse().unwrap();
+        let mut matcher = ReplyMatcher::new(1).with_reorder_window(8);
+        let sent_at = Instant::now();
+        let answer = |matcher: &mut ReplyMatcher, seq| {
+            matcher.register(dst, seq, sent_at);
+            let matched = matcher.on_reply(dst, &reply(1, seq, vec![0; 8]), sent_at);
+            matched.unwrap().reordered
+        };
+        for seq in 0..=100 {
+            assert!(!answer(&mut matcher, seq));
+        }
+        // A straggler far behind leaves the highest at 100
+        assert!(!answer(&mut matcher, 20));
+        assert!(answer(&mut matcher, 95));
+        // Across the wrap, 1 is ahead of 65534 and 65533 is behind it
+        let mut matcher = ReplyMatcher::new(1).with_reorder_window(8);
+        for seq in [65534, 65535, 0, 1] {
+            assert!(!answer(&mut matcher, seq));
+        }
+        assert!(answer(&mut matcher, 65533));
+        assert_eq!(matcher.reordered(), 1);
+    }
+
+    /// Admin-prohibited error from `router` quoting an echo to `dst`
+    fn prohibited(router: [u8; 4], dst: [u8; 4], id: u16, seq: u16) -> Vec<u8> {
+        let header = |ttl: u8, src: [u8; 4], dst: [u8; 4]| {
+            let mut h = vec![0x45, 0, 0, 0, 0, 0, 0, 0, ttl, 1, 0, 0];
+            h.extend_from_slice(&src);
+            h.extend_from_slice(&dst);
+            h
+        };
+        let echo = icmp::build_echo(
+            &Echo {
+                kind: EchoKind::Request,
+                id,
+                seq,
+                payload: Vec::new(),
+            },
+            false,
+        );
+        let mut packet = header(60, router, [10, 0, 0, 5]);
+        packet.extend_from_slice(&[3, 13, 0, 0, 0, 0, 0, 0]);
+        packet.extend_from_slice(&header(63, [10, 0, 0, 5], dst));
+        packet.extend_from_slice(&echo[..8]);
+        packet
+    }
+
+    #[test]
+    fn admin_prohibited_error_matches_probe() {
+        use crate::trace::reply::parse_ipv4_reply;
+        let dst: IpAddr = "192.0.2.1".parse().unwrap();
+        let mut matcher = ReplyMatcher::new(7);
+        matcher.register(dst, 4, Instant::now());
+        let foreign =
+            parse_ipv4_reply(&prohibited([198, 51, 100, 1], [192, 0, 2, 1], 8, 4)).unwrap();
+        assert_eq!(matcher.on_error(&foreign), None);
+        let error = parse_ipv4_reply(&prohibited([198, 51, 100, 1], [192, 0, 2, 1], 7, 4)).unwrap();
+        assert_eq!(
+            matcher.on_error(&error),
+            Some((4, UnreachableReason::AdminProhibited))
+        );
+        assert_eq!(matcher.pending(), 0);
+    }
+}
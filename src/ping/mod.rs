@@ -0,0 +1,113 @@
+//! Ping
+pub mod alert;
+pub mod bulk;
+pub mod compare;
+pub mod echo;
+pub mod heatmap;
+pub mod icmp;
+pub mod matcher;
+pub mod quic;
+pub mod result;
+pub mod session;
+pub mod setting;
+pub mod sweep;
+pub mod tcp;
+pub mod template;
+pub mod udp;
+pub mod unreachable;
+
+pub use setting::{PingProtocol, PingSetting};
+pub use unreachable::UnreachableReason;
+
+use std::fmt;
+use std::io;
+use std::net::IpAddr;
+use std::time::Duration;
+
+/// Reply to a single probe
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ProbeReply {
+    /// Address the reply came from
+    pub responder: IpAddr,
+    /// Round trip time
+    pub rtt: Duration,
+    /// TTL or hop limit of the reply, where the OS exposes it
+    pub ttl: Option<u8>,
+}
+
+/// Reason a single probe did not get a reply
+#[derive(Debug)]
+pub enum ProbeError {
+    Timeout,
+    /// An ICMP error quoting the probe came back instead of a reply
+    Unreachable {
+        responder: IpAddr,
+        reason: UnreachableReason,
+    },
+    Io(io::Error),
+}
+
+impl fmt::Display for ProbeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ProbeError::Timeout => write!(f, "Request timed out"),
+            ProbeError::Unreachable { responder, reason } => {
+                write!(f, "{} from {}", reason, responder)
+            }
+            ProbeError::Io(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl ProbeError {
+    /// The local network or route is gone (`ENETDOWN`, `ENETUNREACH`),
+    /// e.g. after a Wi-Fi drop, rather than the target not answering
+    pub fn is_link_down(&self) -> bool {
+        let ProbeError::Io(e) = self else {
+            return false;
+        };
+        // std maps the OS error codes to these kinds on every platform
+        matches!(
+            e.kind(),
+            io::ErrorKind::NetworkDown | io::ErrorKind::NetworkUnreachable
+        )
+    }
+}
+
+impl std::error::Error for ProbeError {}
+
+impl From<io::Error> for ProbeError {
+    fn from(e: io::Error) -> ProbeError {
+        ProbeError::Io(e)
+    }
+}
+
+/// Sends one probe to a target and waits for its reply.
+///
+/// Implementations must be shareable between worker threads.
+pub trait Prober: Sync {
+    fn probe(&self, dst: IpAddr, seq: u16, timeout: Duration) -> Result<ProbeReply, ProbeError>;
+    /// Replies dropped by the kernel so far, if the receiver counts them
+    fn dropped_packets(&self) -> Option<u64> {
+        None
+    }
+    /// Duplicate replies received so far, if the receiver counts them
+    fn duplicate_replies(&self) -> Option<u64> {
+        None
+    }
+    /// Duplicate replies received so far for the probe to `dst` with `seq`,
+    /// if the receiver counts them
+    fn duplicates_of(&self, _dst: IpAddr, _seq: u16) -> Option<u32> {
+        None
+    }
+    /// Replies received after the reply to a later probe so far, if the
+    /// receiver tracks sequence numbers
+    fn reordered_replies(&self) -> Option<u64> {
+        None
+    }
+    /// Make sure probes to IPv6 (or IPv4) targets can be sent, e.g. by
+    /// opening the family's socket. Fails when the family is disabled.
+    fn check_family(&self, _ipv6: bool) -> io::Result<()> {
+        Ok(())
+    }
+}
@@ -0,0 +1,258 @@
+//! QUIC reachability ping with TCP fallback
+use crate::scan::port::{PortProber, PortState};
+use std::fmt;
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, UdpSocket};
+use std::time::{Duration, Instant};
+use ts_rs::TS;
+
+pub const QUIC_PORT: u16 = 443;
+/// Clients must pad the first datagram to at least this size
+const MIN_INITIAL_LEN: usize = 1200;
+/// Reserved version that forces a Version Negotiation reply (RFC 9000 15)
+const GREASE_VERSION: u32 = 0x1a2a_3a4a;
+
+/// Why the QUIC probe failed
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum QuicFailure {
+    /// The datagram was rejected or could not be sent
+    UdpBlocked(String),
+    /// No QUIC response within the timeout
+    HandshakeTimeout,
+}
+
+impl fmt::Display for QuicFailure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            QuicFailure::UdpBlocked(e) => write!(f, "UDP blocked: {}", e),
+            QuicFailure::HandshakeTimeout => write!(f, "QUIC handshake timed out"),
+        }
+    }
+}
+
+/// Measures the time to a QUIC response from a server
+pub trait QuicProber: Sync {
+    fn probe_quic(&self, dst: SocketAddr, timeout: Duration) -> Result<Duration, QuicFailure>;
+}
+
+/// Probes with a long-header packet carrying a reserved version.
+///
+/// Any QUIC server answers with Version Negotiation, so this measures a full
+/// round trip over UDP without a TLS stack.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct VersionNegotiationProber;
+
+/// Build the probe datagram with the given connection IDs
+pub fn version_probe_packet(dcid: &[u8; 8], scid: &[u8; 8]) -> Vec<u8> {
+    let mut packet = Vec::with_capacity(MIN_INITIAL_LEN);
+    // Long header, fixed bit set
+    packet.push(0xc0);
+    packet.extend_from_slice(&GREASE_VERSION.to_be_bytes());
+    packet.push(dcid.len() as u8);
+    packet.extend_from_slice(dcid);
+    packet.push(scid.len() as u8);
+    packet.extend_from_slice(scid);
+    packet.resize(MIN_INITIAL_LEN, 0);
+    packet
+}
+
+/// Whether `buf` is a Version Negotiation packet addressed to `scid`
+pub fn is_version_negotiation(buf: &[u8], scid: &[u8; 8]) -> bool {
+    if buf.len() < 7 || buf[0] & 0x80 == 0 || buf[1..5] != [0, 0, 0, 0] {
+        return false;
+    }
+    // Our source connection ID comes back as the destination connection ID
+    let dcid_len = buf[5] as usize;
+    buf.get(6..6 + dcid_len) == Some(&scid[..])
+}
+
+impl QuicProber for VersionNegotiationProber {
+    fn probe_quic(&self, dst: SocketAddr, timeout: Duration) -> Result<Duration, QuicFailure> {
+        let blocked = |e: io::Error| QuicFailure::UdpBlocked(e.to_string());
+        let bind: IpAddr = match dst {
+            SocketAddr::V4(_) => IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+            SocketAddr::V6(_) => IpAddr::V6(Ipv6Addr::UNSPECIFIED),
+        };
+        let socket = UdpSocket::bind(SocketAddr::new(bind, 0)).map_err(blocked)?;
+        socket.connect(dst).map_err(blocked)?;
+        let id = crate::ping::icmp::random_id().to_be_bytes();
+        let dcid = [id[0], id[1], 0x6e, 0x65, 0x74, 0x64, 0x69, 0x61];
+        let scid = [0x6e, 0x64, id[1], id[0], 0x71, 0x75, 0x69, 0x63];
+        let start = Instant::now();
+        socket
+            .send(&version_probe_packet(&dcid, &scid))
+            .map_err(blocked)?;
+        let mut buf = [0u8; 1500];
+        loop {
+            let remaining = timeout.saturating_sub(start.elapsed());
+            if remaining.is_zero() {
+                return Err(QuicFailure::HandshakeTimeout);
+            }
+            socket.set_read_timeout(Some(remaining)).map_err(blocked)?;
+            match socket.recv(&mut buf) {
+                Ok(n) if is_version_negotiation(&buf[..n], &scid) => return Ok(start.elapsed()),
+                Ok(_) => continue,
+                Err(e)
+                    if matches!(
+                        e.kind(),
+                        io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+                    ) =>
+                {
+                    return Err(QuicFailure::HandshakeTimeout)
+                }
+                Err(e) => return Err(blocked(e)),
+            }
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum QuicPingProtocol {
+    Quic,
+    /// TCP connect to the same host and port
+    TcpFallback,
+}
+
+/// Settings for QUIC ping
+#[derive(Clone, Debug, PartialEq, Eq, TS)]
+pub struct QuicPingSetting {
+    pub dst: SocketAddr,
+    #[ts(type = "number")]
+    pub timeout_ms: u64,
+    /// Probe over TCP when QUIC fails
+    pub tcp_fallback: bool,
+}
+
+/// Result of QUIC ping
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct QuicPingResult {
+    pub rtt: Option<Duration>,
+    /// Protocol that produced `rtt`, or that was last tried
+    pub protocol: QuicPingProtocol,
+    /// Why QUIC failed, set whenever a fallback happened or was needed
+    pub quic_failure: Option<QuicFailure>,
+    pub error: Option<String>,
+}
+
+/// Ping over QUIC, falling back to a TCP probe if UDP does not get through
+pub fn quic_ping<Q: QuicProber, T: PortProber>(
+    quic: &Q,
+    tcp: &T,
+    setting: &QuicPingSetting,
+) -> QuicPingResult {
+    let timeout = Duration::from_millis(setting.timeout_ms);
+    let failure = match quic.probe_quic(setting.dst, timeout) {
+        Ok(rtt) => {
+            return QuicPingResult {
+                rtt: Some(rtt),
+                protocol: QuicPingProtocol::Quic,
+                quic_failure: None,
+                error: None,
+            }
+        }
+        Err(failure) => failure,
+    };
+    if !setting.tcp_fallback {
+        return QuicPingResult {
+            rtt: None,
+            protocol: QuicPingProtocol::Quic,
+            error: Some(failure.to_string()),
+            quic_failure: Some(failure),
+        };
+    }
+    let probe = tcp.probe_port(setting.dst.ip(), setting.dst.port(), timeout);
+    let error = match probe.state {
+        PortState::Open => None,
+        PortState::Closed => Some("TCP fallback: connection refused".to_string()),
+        PortState::Filtered => Some("TCP fallback: no response".to_string()),
+        PortState::Error => probe.error.map(|e| format!("TCP fallback: {}", e)),
+    };
+    QuicPingResult {
+        rtt: probe.connect_time,
+        protocol: QuicPingProtocol::TcpFallback,
+        quic_failure: Some(failure),
+        error,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scan::port::PortProbe;
+    use std::thread;
+
+    struct FailingQuic(QuicFailure);
+
+    impl QuicProber for FailingQuic {
+        fn probe_quic(
+            &self,
+            _dst: SocketAddr,
+            _timeout: Duration,
+        ) -> Result<Duration, QuicFailure> {
+            Err(self.0.clone())
+        }
+    }
+
+    struct OpenTcp;
+
+    impl PortProber for OpenTcp {
+        fn probe_port(&self, _ip: IpAddr, port: u16, _timeout: Duration) -> PortProbe {
+            PortProbe {
+                port,
+                state: PortState::Open,
+                connect_time: Some(Duration::from_millis(12)),
+                error: None,
+            }
+        }
+    }
+
+    fn setting(tcp_fallback: bool) -> QuicPingSetting {
+        QuicPingSetting {
+            dst: "192.0.2.1:443".parse().unwrap(),
+            timeout_ms: 100,
+            tcp_fallback,
+        }
+    }
+
+    #[test]
+    fn udp_failure_falls_back_to_tcp() {
+        let result = quic_ping(
+            &FailingQuic(QuicFailure::HandshakeTimeout),
+            &OpenTcp,
+            &setting(true),
+        );
+        assert_eq!(result.protocol, QuicPingProtocol::TcpFallback);
+        assert_eq!(result.quic_failure, Some(QuicFailure::HandshakeTimeout));
+        assert_eq!(result.rtt, Some(Duration::from_millis(12)));
+        assert_eq!(result.error, None);
+
+        let blocked = QuicFailure::UdpBlocked("Connection refused".to_string());
+        let result = quic_ping(&FailingQuic(blocked.clone()), &OpenTcp, &setting(false));
+        assert_eq!(result.protocol, QuicPingProtocol::Quic);
+        assert_eq!(result.quic_failure, Some(blocked));
+        assert_eq!(result.rtt, None);
+    }
+
+    #[test]
+    fn version_negotiation_against_local_responder() {
+        let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let dst = server.local_addr().unwrap();
+        thread::spawn(move || {
+            let mut buf = [0u8; 1500];
+            let (n, peer) = server.recv_from(&mut buf).unwrap();
+            assert_eq!(n, MIN_INITIAL_LEN);
+            let dcid = &buf[6..14];
+            let scid = buf[15..23].to_vec();
+            let mut reply = vec![0x80, 0, 0, 0, 0, scid.len() as u8];
+            reply.extend_from_slice(&scid);
+            reply.push(dcid.len() as u8);
+            reply.extend_from_slice(dcid);
+            reply.extend_from_slice(&1u32.to_be_bytes());
+            server.send_to(&reply, peer).unwrap();
+        });
+        let rtt = VersionNegotiationProber
+            .probe_quic(dst, Duration::from_secs(1))
+            .unwrap();
+        assert!(rtt < Duration::from_secs(1));
+    }
+}
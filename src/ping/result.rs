@@ -0,0 +1,129 @@
+use super::UnreachableReason;
+use std::net::IpAddr;
+use std::time::Duration;
+use ts_rs::TS;
+
+/// Outcome of one probe in a ping session
+#[derive(Clone, Debug, PartialEq, Eq, TS)]
+pub struct PingSample {
+    pub seq: u16,
+    /// `None` if the probe timed out
+    #[ts(type = "Duration | null")]
+    pub rtt: Option<Duration>,
+    pub responder: Option<IpAddr>,
+    /// TTL or hop limit of the reply
+    pub ttl: Option<u8>,
+    /// Set when `ttl` differs from the previous reply, hinting at a path change
+    pub ttl_changed: bool,
+    /// Set when an ICMP error came back instead of a reply
+    pub unreachable: Option<UnreachableReason>,
+    /// Further replies to this probe (`DUP!`) seen so far, 0 when the
+    /// prober does not count them
+    pub duplicates: u32,
+}
+
+impl PingSample {
+    /// Hops to the responder inferred from the reply TTL
+    pub fn hops(&self) -> Option<u8> {
+        self.ttl.map(inferred_hops)
+    }
+}
+
+/// Initial TTLs commonly used by operating systems
+const INITIAL_TTLS: [u8; 4] = [32, 64, 128, 255];
+
+/// Hop count from a received TTL, assuming the nearest common initial TTL
+pub fn inferred_hops(ttl: u8) -> u8 {
+    let initial = INITIAL_TTLS
+        .iter()
+        .find(|t| **t >= ttl)
+        .copied()
+        .unwrap_or(255);
+    initial - ttl
+}
+
+/// Summary of a ping session
+#[derive(Clone, Debug, Default, PartialEq, TS)]
+pub struct PingStat {
+    pub sent: u32,
+    pub received: u32,
+    pub min_ms: Option<f64>,
+    pub avg_ms: Option<f64>,
+    pub max_ms: Option<f64>,
+    /// Mean absolute difference between consecutive RTTs
+    pub jitter_ms: Option<f64>,
+    pub loss_percent: f64,
+}
+
+impl PingStat {
+    pub fn from_samples(samples: &[PingSample]) -> PingStat {
+        let rtts: Vec<Option<Duration>> = samples.iter().map(|s| s.rtt).collect();
+        PingStat::from_rtts(&rtts)
+    }
+    /// Summary of probe RTTs in send order, `None` for losses
+    pub fn from_rtts(probes: &[Option<Duration>]) -> PingStat {
+        let rtts: Vec<f64> = probes
+            .iter()
+            .flatten()
+            .map(|rtt| rtt.as_secs_f64() * 1000.0)
+            .collect();
+        let sent = probes.len() as u32;
+        let received = rtts.len() as u32;
+        let loss_percent = if sent == 0 {
+            0.0
+        } else {
+            (sent - received) as f64 * 100.0 / sent as f64
+        };
+        let jitter_ms = (rtts.len() > 1).then(|| {
+            rtts.windows(2).map(|w| (w[1] - w[0]).abs()).sum::<f64>() / (rtts.len() - 1) as f64
+        });
+        PingStat {
+            sent,
+            received,
+            min_ms: rtts.iter().copied().reduce(f64::min),
+            avg_ms: (!rtts.is_empty()).then(|| rtts.iter().sum::<f64>() / rtts.len() as f64),
+            max_ms: rtts.iter().copied().reduce(f64::max),
+            jitter_ms,
+            loss_percent,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stat_from_samples() {
+        let samples: Vec<PingSample> = [Some(10), None, Some(20), Some(15)]
+            .iter()
+            .enumerate()
+            .map(|(i, rtt)| PingSample {
+                seq: i as u16,
+                rtt: rtt.map(Duration::from_millis),
+                responder: None,
+                ttl: None,
+                ttl_changed: false,
+                unreachable: None,
+                duplicates: 0,
+            })
+            .collect();
+        let stat = PingStat::from_samples(&samples);
+        assert_eq!((stat.sent, stat.received), (4, 3));
+        assert_eq!(stat.min_ms, Some(10.0));
+        assert_eq!(stat.avg_ms, Some(15.0));
+        assert_eq!(stat.max_ms, Some(20.0));
+        assert_eq!(stat.jitter_ms, Some(7.5));
+        assert_eq!(stat.loss_percent, 25.0);
+        assert_eq!(PingStat::from_samples(&[]).avg_ms, None);
+    }
+
+    #[test]
+    fn hops_from_ttl() {
+        assert_eq!(inferred_hops(64), 0);
+        assert_eq!(inferred_hops(57), 7);
+        assert_eq!(inferred_hops(116), 12);
+        assert_eq!(inferred_hops(245), 10);
+        assert_eq!(inferred_hops(30), 2);
+    }
+}
@@ -0,0 +1,718 @@
+use super::result::{PingSample, PingStat};
+use super::{PingSetting, ProbeError, Prober};
+use crate::cancel::{CancelReason, CancellationToken};
+use crate::event::EventQueue;
+use crate::grade::Grade;
+use crate::net::egress::system_egress_mac;
+use crate::net::monitor::LinkState;
+use crate::pcap::Capture;
+use crate::probe::cancellable_sleep_until;
+use std::collections::VecDeque;
+use std::io;
+use std::net::IpAddr;
+use std::time::{Duration, Instant};
+use ts_rs::TS;
+
+/// Summary emitted when a ping session ends
+#[derive(Clone, Debug, PartialEq, TS)]
+pub struct PingDonePayload {
+    pub dst_ip: IpAddr,
+    pub stat: PingStat,
+    /// Per-probe samples in send order, when requested
+    pub samples: Vec<PingSample>,
+    /// Set when older samples were dropped to stay within `max_samples`
+    pub samples_truncated: bool,
+    pub cancelled: bool,
+    /// Why the session was cancelled, when it was
+    pub cancel_reason: Option<CancelReason>,
+    /// Samples dropped or coalesced because the consumer fell behind
+    #[ts(type = "number")]
+    pub dropped_progress_events: u64,
+    /// Duplicate replies received during the session (`DUP!`), when the
+    /// prober counts them. Duplicates hint at loops or misconfiguration.
+    #[ts(type = "number | null")]
+    pub duplicate_replies: Option<u64>,
+    /// Replies that arrived after the reply to a later probe, when the
+    /// prober tracks them. Common on load-balanced or buffering paths.
+    #[ts(type = "number | null")]
+    pub reordered_replies: Option<u64>,
+    /// Latency, jitter and loss graded with `setting.grading`
+    pub grade: Option<Grade>,
+}
+
+/// Event name emitted when probes start failing because the interface is down
+pub const PING_INTERFACE_DOWN_EVENT: &str = "ping:interface_down";
+/// Event name emitted when probes go out again after an interface went down
+pub const PING_INTERFACE_UP_EVENT: &str = "ping:interface_up";
+/// Shortest time between probes checking whether a paused ping can resume
+const PAUSED_RECHECK_INTERVAL: Duration = Duration::from_millis(100);
+
+#[derive(Clone, Debug, PartialEq, Eq, TS)]
+pub struct PingInterfacePayload {
+    pub dst_ip: IpAddr,
+    /// Sequence number of the probe that failed or of the last one sent
+    /// before the link came back
+    pub seq: u16,
+    /// Send error that revealed the link was down
+    pub error: Option<String>,
+    /// Probing stopped until the interface recovers
+    pub paused: bool,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum PingLinkEvent {
+    /// [`PING_INTERFACE_DOWN_EVENT`]
+    Down(PingInterfacePayload),
+    /// [`PING_INTERFACE_UP_EVENT`]
+    Up(PingInterfacePayload),
+}
+
+/// Ping `setting.dst_ip` `count` times, calling `on_sample` after each probe.
+///
+/// With `setting.backpressure`, `on_sample` runs on its own thread behind a
+/// bounded queue, so a slow consumer never delays the probes.
+pub fn ping<P, F>(
+    prober: &P,
+    setting: &PingSetting,
+    token: &CancellationToken,
+    on_sample: F,
+) -> PingDonePayload
+where
+    P: Prober,
+    F: FnMut(&PingSample) + Send,
+{
+    ping_watching_link(prober, setting, None, token, on_sample, |_| {})
+}
+
+/// `ping` through the prober of `setting.protocol`: ICMP echo over the
+/// system's ICMP socket, or UDP echo. Fails only when the
+/// `setting.capture_to` file cannot be created or written.
+pub fn run_ping<F>(
+    setting: &PingSetting,
+    token: &CancellationToken,
+    on_sample: F,
+) -> io::Result<PingDonePayload>
+where
+    F: FnMut(&PingSample) + Send,
+{
+    if let Some(prober) = setting.udp_echo_prober() {
+        return Ok(ping(&prober, setting, token, on_sample));
+    }
+    let Some(path) = &setting.capture_to else {
+        return Ok(ping(&setting.icmp_prober(), setting, token, on_sample));
+    };
+    let capture = Capture::create(path, system_egress_mac(setting.dst_ip))?;
+    let prober = setting.icmp_prober().with_capture(capture.clone());
+    let done = ping(&prober, setting, token, on_sample);
+    capture.finish()?;
+    Ok(done)
+}
+
+/// `ping`, calling `on_link` once when probes start failing because the
+/// interface is down and once when they go out again, instead of reporting
+/// each failure alike.
+///
+/// With `setting.pause_on_link_down` and a `link`, probing stops after the
+/// first such failure until `link` is up again.
+pub fn ping_watching_link<P, F, G>(
+    prober: &P,
+    setting: &PingSetting,
+    link: Option<&LinkState>,
+    token: &CancellationToken,
+    mut on_sample: F,
+    mut on_link: G,
+) -> PingDonePayload
+where
+    P: Prober,
+    F: FnMut(&PingSample) + Send,
+    G: FnMut(&PingLinkEvent),
+{
+    let Some(backpressure) = setting.backpressure else {
+        return run(
+            prober,
+            setting,
+            link,
+            token,
+            |s| on_sample(&s),
+            &mut on_link,
+        );
+    };
+    let queue: EventQueue<PingSample, ()> = EventQueue::new(backpressure);
+    let done = std::thread::scope(|s| {
+        let on_sample = &mut on_sample;
+        let queue = &queue;
+        s.spawn(move || {
+            queue.drain(|event| {
+                if let crate::event::Event::Progress(sample) = event {
+                    on_sample(&sample);
+                }
+            })
+        });
+        let done = run(
+            prober,
+            setting,
+            link,
+            token,
+            |s| queue.progress(s),
+            &mut on_link,
+        );
+        queue.close();
+        done
+    });
+    PingDonePayload {
+        dropped_progress_events: queue.dropped(),
+        ..done
+    }
+}
+
+fn run<P, F, G>(
+    prober: &P,
+    setting: &PingSetting,
+    link: Option<&LinkState>,
+    token: &CancellationToken,
+    mut emit: F,
+    on_link: &mut G,
+) -> PingDonePayload
+where
+    P: Prober,
+    F: FnMut(PingSample),
+    G: FnMut(&PingLinkEvent),
+{
+    let timeout = Duration::from_millis(setting.timeout_ms);
+    let interval = Duration::from_millis(setting.interval_ms);
+    let mut kept: VecDeque<PingSample> = VecDeque::new();
+    let mut truncated = false;
+    let mut all_samples: Vec<PingSample> = Vec::new();
+    let mut last_ttl: Option<u8> = None;
+    let mut link_down = false;
+    let duplicates_before = prober.duplicate_replies();
+    let reordered_before = prober.reordered_replies();
+    for n in 0..setting.count {
+        if token.is_cancelled() {
+            break;
+        }
+        let started = Instant::now();
+        let seq = setting.seq_for(n);
+        let result = prober.probe(setting.dst_ip, seq, timeout);
+        let link_error = result
+            .as_ref()
+            .err()
+            .filter(|e| e.is_link_down())
+            .map(|e| e.to_string());
+        let unreachable = match &result {
+            Err(ProbeError::Unreachable { reason, .. }) => Some(*reason),
+            _ => None,
+        };
+        let reply = result.ok();
+        let ttl = reply.as_ref().and_then(|r| r.ttl);
+        let ttl_changed = matches!((last_ttl, ttl), (Some(prev), Some(cur)) if prev != cur);
+        if ttl.is_some() {
+            last_ttl = ttl;
+        }
+        let sample = PingSample {
+            seq,
+            rtt: reply.as_ref().map(|r| r.rtt),
+            responder: reply.map(|r| r.responder),
+            ttl,
+            ttl_changed,
+            unreachable,
+            duplicates: prober.duplicates_of(setting.dst_ip, seq).unwrap_or(0),
+        };
+        if setting.include_samples {
+            if kept.len() >= setting.max_samples {
+                kept.pop_front();
+                truncated = true;
+            }
+            if setting.max_samples > 0 {
+                kept.push_back(sample.clone());
+            } else {
+                truncated = true;
+            }
+        }
+        all_samples.push(sample.clone());
+        emit(sample);
+        let payload = |error, paused| PingInterfacePayload {
+            dst_ip: setting.dst_ip,
+            seq,
+            error,
+            paused,
+        };
+        match (link_error, link_down) {
+            (Some(error), false) => {
+                link_down = true;
+                let pause = link.filter(|_| setting.pause_on_link_down);
+                on_link(&PingLinkEvent::Down(payload(Some(error), pause.is_some())));
+                if let Some(link) = pause {
+                    link.set_up(false);
+                    let recheck = interval.max(PAUSED_RECHECK_INTERVAL);
+                    while !link.wait_until_up_for(token, recheck) && !token.is_cancelled() {
+                        // A lost route never shows up as an interface change, and a
+                        // delta applied before the pause began is already gone
+                        match prober.probe(setting.dst_ip, seq, timeout) {
+                            Err(e) if e.is_link_down() => {}
+                            _ => link.set_up(true),
+                        }
+                    }
+                    if token.is_cancelled() {
+                        break;
+                    }
+                    link_down = false;
+                    on_link(&PingLinkEvent::Up(payload(None, false)));
+                    continue;
+                }
+            }
+            (None, true) => {
+                link_down = false;
+                on_link(&PingLinkEvent::Up(payload(None, false)));
+            }
+            _ => {}
+        }
+        if n + 1 < setting.count {
+            cancellable_sleep_until(token, started + interval);
+        }
+    }
+    // Duplicates usually trail the first reply, after its sample went out
+    for sample in kept.iter_mut() {
+        if let Some(dups) = prober.duplicates_of(setting.dst_ip, sample.seq) {
+            sample.duplicates = dups;
+        }
+    }
+    let stat = PingStat::from_samples(&all_samples);
+    PingDonePayload {
+        dst_ip: setting.dst_ip,
+        grade: setting.grading.grade_ping(&stat),
+        stat,
+        samples: kept.into(),
+        samples_truncated: truncated,
+        cancelled: token.is_cancelled(),
+        cancel_reason: token.reason(),
+        dropped_progress_events: 0,
+        duplicate_replies: prober
+            .duplicate_replies()
+            .map(|n| n.saturating_sub(duplicates_before.unwrap_or(0))),
+        reordered_replies: prober
+            .reordered_replies()
+            .map(|n| n.saturating_sub(reordered_before.unwrap_or(0))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cancel::OpRegistry;
+    use crate::ping::{ProbeReply, UnreachableReason};
+
+    /// Every third probe times out
+    struct Lossy;
+
+    impl Prober for Lossy {
+        fn probe(
+            &self,
+            dst: IpAddr,
+            seq: u16,
+            _timeout: Duration,
+        ) -> Result<ProbeReply, ProbeError> {
+            if seq % 3 == 2 {
+                return Err(ProbeError::Timeout);
+            }
+            Ok(ProbeReply {
+                responder: dst,
+                rtt: Duration::from_millis(10 + seq as u64),
+                ttl: None,
+            })
+        }
+    }
+
+    fn setting(count: u32) -> PingSetting {
+        let mut setting = PingSetting::new("192.0.2.7".parse().unwrap());
+        setting.count = count;
+        setting.interval_ms = 0;
+        setting
+    }
+
+    #[test]
+    fn samples_match_probes_sent() {
+        let mut setting = setting(6);
+        setting.include_samples = true;
+        let done = ping(&Lossy, &setting, &CancellationToken::new(), |_| {});
+        assert_eq!(done.samples.len(), 6);
+        assert!(!done.samples_truncated);
+        let seqs: Vec<u16> = done.samples.iter().map(|s| s.seq).collect();
+        assert_eq!(seqs, vec![0, 1, 2, 3, 4, 5]);
+        assert_eq!(done.samples[1].rtt, Some(Duration::from_millis(11)));
+        assert_eq!(done.samples[1].responder, Some(setting.dst_ip));
+        assert_eq!(done.samples[2].rtt, None);
+        assert_eq!(done.samples[5].rtt, None);
+        assert_eq!((done.stat.sent, done.stat.received), (6, 4));
+    }
+
+    /// Replies with the TTL listed per sequence number
+    struct TtlSequence(Vec<u8>);
+
+    impl Prober for TtlSequence {
+        fn probe(
+            &self,
+            dst: IpAddr,
+            seq: u16,
+            _timeout: Duration,
+        ) -> Result<ProbeReply, ProbeError> {
+            Ok(ProbeReply {
+                responder: dst,
+                rtt: Duration::from_millis(1),
+                ttl: Some(self.0[seq as usize]),
+            })
+        }
+    }
+
+    #[test]
+    fn ttl_and_path_change_reported() {
+        let mut setting = setting(4);
+        setting.include_samples = true;
+        let done = ping(
+            &TtlSequence(vec![57, 57, 55, 55]),
+            &setting,
+            &CancellationToken::new(),
+            |_| {},
+        );
+        let ttls: Vec<Option<u8>> = done.samples.iter().map(|s| s.ttl).collect();
+        assert_eq!(ttls, vec![Some(57), Some(57), Some(55), Some(55)]);
+        assert_eq!(done.samples[0].hops(), Some(7));
+        let changed: Vec<bool> = done.samples.iter().map(|s| s.ttl_changed).collect();
+        assert_eq!(changed, vec![false, false, true, false]);
+    }
+
+    #[test]
+    fn samples_are_bounded_and_optional() {
+        let mut setting = setting(10);
+        setting.include_samples = true;
+        setting.max_samples = 4;
+        let done = ping(&Lossy, &setting, &CancellationToken::new(), |_| {});
+        let seqs: Vec<u16> = done.samples.iter().map(|s| s.seq).collect();
+        assert_eq!(seqs, vec![6, 7, 8, 9]);
+        assert!(done.samples_truncated);
+        assert_eq!(done.stat.sent, 10);
+
+        let done = ping(&Lossy, &self::setting(3), &CancellationToken::new(), |_| {});
+        assert!(done.samples.is_empty());
+    }
+
+    /// A firewall rejects every probe
+    struct Firewalled;
+
+    impl Prober for Firewalled {
+        fn probe(
+            &self,
+            _dst: IpAddr,
+            _seq: u16,
+            _timeout: Duration,
+        ) -> Result<ProbeReply, ProbeError> {
+            Err(ProbeError::Unreachable {
+                responder: "198.51.100.1".parse().unwrap(),
+                reason: UnreachableReason::AdminProhibited,
+            })
+        }
+    }
+
+    #[test]
+    fn unreachable_reason_reported_instead_of_timeout() {
+        let mut setting = setting(2);
+        setting.include_samples = true;
+        let done = ping(&Firewalled, &setting, &CancellationToken::new(), |_| {});
+        assert_eq!(done.stat.received, 0);
+        assert_eq!(
+            done.samples[0].unreachable,
+            Some(UnreachableReason::AdminProhibited)
+        );
+        let lossy = ping(&Lossy, &setting, &CancellationToken::new(), |_| {});
+        assert_eq!(lossy.samples[1].unreachable, None);
+    }
+
+    #[test]
+    fn slow_consumer_does_not_delay_probes() {
+        use crate::event::{BackpressureSetting, OverflowPolicy};
+        let mut setting = setting(20);
+        setting.backpressure = Some(BackpressureSetting {
+            capacity: 2,
+            policy: OverflowPolicy::Coalesce,
+        });
+        let mut seen = Vec::new();
+        let done = ping(&Lossy, &setting, &CancellationToken::new(), |s| {
+            std::thread::sleep(Duration::from_millis(20));
+            seen.push(s.seq);
+        });
+        assert_eq!(done.stat.sent, 20);
+        assert!(done.dropped_progress_events > 0);
+        assert_eq!(seen.len() as u64 + done.dropped_progress_events, 20);
+        // The latest sample is never coalesced away
+        assert_eq!(seen.last(), Some(&19));
+
+        setting.backpressure = None;
+        let done = ping(&Lossy, &setting, &CancellationToken::new(), |_| {});
+        assert_eq!(done.dropped_progress_events, 0);
+    }
+
+    /// Matches replies through a `ReplyMatcher` where every reply to an odd
+    /// probe overtakes the one before it
+    struct Overtaking(std::sync::Mutex<crate::ping::matcher::ReplyMatcher>);
+
+    impl Prober for Overtaking {
+        fn probe(
+            &self,
+            dst: IpAddr,
+            seq: u16,
+            _timeout: Duration,
+        ) -> Result<ProbeReply, ProbeError> {
+            use crate::ping::icmp::{Echo, EchoKind};
+            let mut matcher = self.0.lock().unwrap();
+            let now = Instant::now();
+            let echo = |seq| Echo {
+                kind: EchoKind::Reply,
+                id: 1,
+                seq,
+                payload: Vec::new(),
+            };
+            if seq % 2 == 1 {
+                matcher.register(dst, seq, now);
+                matcher.on_reply(dst, &echo(seq), now);
+                matcher.on_reply(dst, &echo(seq - 1), now);
+            } else {
+                matcher.register(dst, seq, now);
+            }
+            Ok(ProbeReply {
+                responder: dst,
+                rtt: Duration::from_millis(1),
+                ttl: None,
+            })
+        }
+        fn reordered_replies(&self) -> Option<u64> {
+            Some(self.0.lock().unwrap().reordered())
+        }
+    }
+
+    #[test]
+    fn reordered_replies_reported_in_summary() {
+        let prober = Overtaking(std::sync::Mutex::new(
+            crate::ping::matcher::ReplyMatcher::new(1),
+        ));
+        let done = ping(&prober, &setting(6), &CancellationToken::new(), |_| {});
+        assert_eq!(done.reordered_replies, Some(3));
+        let done = ping(&Lossy, &setting(2), &CancellationToken::new(), |_| {});
+        assert_eq!(done.reordered_replies, None);
+    }
+
+    /// Matches replies through a `ReplyMatcher` where each probe's reply
+    /// arrives along with a duplicate of the reply to the probe before it
+    struct Echoing(std::sync::Mutex<crate::ping::matcher::ReplyMatcher>);
+
+    impl Prober for Echoing {
+        fn probe(
+            &self,
+            dst: IpAddr,
+            seq: u16,
+            _timeout: Duration,
+        ) -> Result<ProbeReply, ProbeError> {
+            use crate::ping::icmp::{Echo, EchoKind};
+            let mut matcher = self.0.lock().unwrap();
+            let now = Instant::now();
+            let echo = |seq| Echo {
+                kind: EchoKind::Reply,
+                id: 1,
+                seq,
+                payload: Vec::new(),
+            };
+            matcher.register(dst, seq, now);
+            matcher.on_reply(dst, &echo(seq), now);
+            if seq > 0 {
+                matcher.on_reply(dst, &echo(seq - 1), now);
+            }
+            Ok(ProbeReply {
+                responder: dst,
+                rtt: Duration::from_millis(1),
+                ttl: None,
+            })
+        }
+        fn duplicate_replies(&self) -> Option<u64> {
+            Some(self.0.lock().unwrap().total_duplicates())
+        }
+        fn duplicates_of(&self, dst: IpAddr, seq: u16) -> Option<u32> {
+            Some(self.0.lock().unwrap().duplicates(dst, seq))
+        }
+    }
+
+    #[test]
+    fn trailing_duplicates_flag_their_samples() {
+        let prober = Echoing(std::sync::Mutex::new(
+            crate::ping::matcher::ReplyMatcher::new(1),
+        ));
+        let mut setting = setting(4);
+        setting.include_samples = true;
+        let mut emitted = Vec::new();
+        let done = ping(&prober, &setting, &CancellationToken::new(), |s| {
+            emitted.push(s.duplicates)
+        });
+        // Each duplicate trails its sample, the summary catches up
+        assert_eq!(emitted, [0, 0, 0, 0]);
+        let dups: Vec<u32> = done.samples.iter().map(|s| s.duplicates).collect();
+        assert_eq!(dups, [1, 1, 1, 0]);
+        assert_eq!(done.duplicate_replies, Some(3));
+    }
+
+    /// Sends fail with a network-down error for probes in the range
+    struct WifiDrop(std::ops::Range<u16>);
+
+    impl Prober for WifiDrop {
+        fn probe(
+            &self,
+            dst: IpAddr,
+            seq: u16,
+            _timeout: Duration,
+        ) -> Result<ProbeReply, ProbeError> {
+            if self.0.contains(&seq) {
+                return Err(ProbeError::Io(std::io::ErrorKind::NetworkDown.into()));
+            }
+            Ok(ProbeReply {
+                responder: dst,
+                rtt: Duration::from_millis(1),
+                ttl: None,
+            })
+        }
+    }
+
+    #[test]
+    fn interface_down_reported_once() {
+        let mut events = Vec::new();
+        let done = ping_watching_link(
+            &WifiDrop(2..5),
+            &setting(7),
+            None,
+            &CancellationToken::new(),
+            |_| {},
+            |e| events.push(e.clone()),
+        );
+        assert_eq!(done.stat.received, 4);
+        assert_eq!(events.len(), 2);
+        let PingLinkEvent::Down(down) = &events[0] else {
+            panic!("expected interface down first");
+        };
+        assert_eq!((down.seq, down.paused), (2, false));
+        assert!(down.error.is_some());
+        assert!(matches!(&events[1], PingLinkEvent::Up(up) if up.seq == 5));
+        assert!(!ProbeError::Timeout.is_link_down());
+        #[cfg(unix)]
+        assert!(
+            ProbeError::Io(std::io::Error::from_raw_os_error(libc::ENETUNREACH)).is_link_down()
+        );
+    }
+
+    #[test]
+    fn pauses_until_link_recovers() {
+        let mut setting = setting(4);
+        setting.pause_on_link_down = true;
+        let link = LinkState::new("wlan0");
+        let token = CancellationToken::new();
+        let mut events = Vec::new();
+        let started = Instant::now();
+        let done = std::thread::scope(|s| {
+            s.spawn(|| {
+                std::thread::sleep(Duration::from_millis(100));
+                link.set_up(true);
+            });
+            ping_watching_link(
+                &WifiDrop(1..2),
+                &setting,
+                Some(&link),
+                &token,
+                |_| {},
+                |e| events.push(e.clone()),
+            )
+        });
+        assert!(started.elapsed() >= Duration::from_millis(100));
+        assert_eq!((done.stat.sent, done.stat.received), (4, 3));
+        assert!(matches!(&events[0], PingLinkEvent::Down(d) if d.paused));
+        assert!(matches!(&events[1], PingLinkEvent::Up(_)));
+        assert_eq!(events.len(), 2);
+    }
+
+    /// Unroutable for the first `.0` probes, with no interface change to follow
+    struct RouteLoss(usize, std::sync::atomic::AtomicUsize);
+
+    impl Prober for RouteLoss {
+        fn probe(
+            &self,
+            dst: IpAddr,
+            _seq: u16,
+            _timeout: Duration,
+        ) -> Result<ProbeReply, ProbeError> {
+            let calls = self.1.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+            if calls < self.0 {
+                return Err(ProbeError::Io(
+                    std::io::ErrorKind::NetworkUnreachable.into(),
+                ));
+            }
+            Ok(ProbeReply {
+                responder: dst,
+                rtt: Duration::from_millis(1),
+                ttl: None,
+            })
+        }
+    }
+
+    #[test]
+    fn paused_ping_resumes_when_route_returns() {
+        let mut setting = setting(3);
+        setting.pause_on_link_down = true;
+        // The interface stays up, so the link is never reported back up
+        let link = LinkState::new("eth0");
+        let mut events = Vec::new();
+        let done = ping_watching_link(
+            &RouteLoss(3, Default::default()),
+            &setting,
+            Some(&link),
+            &CancellationToken::new(),
+            |_| {},
+            |e| events.push(e.clone()),
+        );
+        assert!(!done.cancelled);
+        assert_eq!((done.stat.sent, done.stat.received), (3, 2));
+        assert!(matches!(&events[0], PingLinkEvent::Down(d) if d.paused));
+        assert!(matches!(&events[1], PingLinkEvent::Up(up) if up.seq == 0));
+        assert_eq!(events.len(), 2);
+    }
+
+    #[test]
+    fn run_ping_uses_icmp_socket() {
+        let mut setting = setting(3);
+        setting.dst_ip = "127.0.0.1".parse().unwrap();
+        if setting.icmp_prober().check_family(false).is_err() {
+            // Neither kind of ICMP socket is allowed for this user
+            return;
+        }
+        let done = run_ping(&setting, &CancellationToken::new(), |_| {}).unwrap();
+        assert_eq!((done.stat.sent, done.stat.received), (3, 3));
+        assert_eq!(done.duplicate_replies, Some(0));
+
+        let file = std::env::temp_dir().join(format!("netdia-ping-{}.pcap", std::process::id()));
+        setting.capture_to = Some(file.to_string_lossy().into_owned());
+        run_ping(&setting, &CancellationToken::new(), |_| {}).unwrap();
+        let captured = std::fs::metadata(&file).unwrap().len();
+        let _ = std::fs::remove_file(&file);
+        // Global header plus at least the three requests of 16 + 28 + 56 bytes
+        assert!(captured >= 24 + 3 * 100, "{}", captured);
+        setting.capture_to = Some("/nonexistent/dir/ping.pcap".to_string());
+        assert!(run_ping(&setting, &CancellationToken::new(), |_| {}).is_err());
+    }
+
+    #[test]
+    fn second_ping_supersedes_first() {
+        let ops = OpRegistry::new();
+        let first = ops.start("ping");
+        let second = ops.start("ping");
+        let done = ping(&Lossy, &setting(3), &first, |_| {});
+        assert!(done.cancelled);
+        assert_eq!(done.cancel_reason, Some(CancelReason::Superseded));
+        let done = ping(&Lossy, &setting(3), &second, |_| {});
+        assert_eq!(done.cancel_reason, None);
+        assert!(ops.cancel_op("ping", CancelReason::User));
+        assert_eq!(second.reason(), Some(CancelReason::User));
+    }
+}
@@ -0,0 +1,261 @@
+use super::echo::IcmpEchoProber;
+use super::icmp;
+use super::template::UdpPayload;
+use super::udp::UdpEchoProber;
+use crate::event::BackpressureSetting;
+use crate::grade::GradeThresholds;
+use crate::net::mtu::system_egress_mtu;
+use crate::net::scope::ScopedIp;
+use crate::socket::IcmpConfig;
+use std::net::{IpAddr, SocketAddr};
+use ts_rs::TS;
+
+pub const DEFAULT_PING_COUNT: u32 = 4;
+pub const DEFAULT_TIMEOUT_MS: u64 = 1000;
+pub const DEFAULT_INTERVAL_MS: u64 = 1000;
+/// Upper bound of samples kept in the done payload by default
+pub const DEFAULT_MAX_SAMPLES: usize = 1000;
+
+/// How probes are sent
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, TS)]
+pub enum PingProtocol {
+    #[default]
+    Icmp,
+    /// Round trip to a UDP echo service on `port`
+    UdpEcho { port: u16 },
+}
+
+/// Settings for ping
+#[derive(Clone, Debug, PartialEq, TS)]
+pub struct PingSetting {
+    pub dst_ip: IpAddr,
+    /// Interface index for link-local IPv6 destinations, 0 otherwise
+    pub scope_id: u32,
+    pub protocol: PingProtocol,
+    pub count: u32,
+    #[ts(type = "number")]
+    pub timeout_ms: u64,
+    #[ts(type = "number")]
+    pub interval_ms: u64,
+    /// ICMP identifier. Randomized when `None`.
+    pub icmp_id: Option<u16>,
+    /// First ICMP sequence number. Starts at 0 when `None`.
+    pub icmp_seq: Option<u16>,
+    /// Embed the send time in the ICMP payload and take the RTT from it
+    pub timestamp_payload: bool,
+    /// Include every sample in the done payload
+    pub include_samples: bool,
+    /// Most recent samples kept when `include_samples` is set
+    pub max_samples: usize,
+    /// Source port of UDP probes. Chosen by the OS when `None`.
+    pub source_port: Option<u16>,
+    /// Payload of UDP probes
+    pub udp_payload: UdpPayload,
+    /// Queue samples for a slow consumer instead of blocking the probes.
+    /// Samples are passed on directly when `None`.
+    pub backpressure: Option<BackpressureSetting>,
+    /// Thresholds the done payload's `grade` is computed with
+    pub grading: GradeThresholds,
+    /// Stop probing while the interface is down instead of failing every
+    /// probe. Needs a link state followed by the interface monitor.
+    pub pause_on_link_down: bool,
+    /// Path of a pcap file every ICMP probe and reply is written to, for
+    /// inspection in Wireshark. Headers the socket hides, e.g. of sent
+    /// packets, are synthesized, inside Ethernet frames when the egress
+    /// interface has a MAC address. UDP probes are not captured. Not
+    /// captured when `None`.
+    pub capture_to: Option<String>,
+}
+
+impl PingSetting {
+    pub fn new(dst_ip: IpAddr) -> PingSetting {
+        PingSetting {
+            dst_ip,
+            scope_id: 0,
+            protocol: PingProtocol::Icmp,
+            count: DEFAULT_PING_COUNT,
+            timeout_ms: DEFAULT_TIMEOUT_MS,
+            interval_ms: DEFAULT_INTERVAL_MS,
+            icmp_id: None,
+            icmp_seq: None,
+            timestamp_payload: true,
+            include_samples: false,
+            max_samples: DEFAULT_MAX_SAMPLES,
+            source_port: None,
+            udp_payload: UdpPayload::Auto,
+            backpressure: None,
+            grading: GradeThresholds::default(),
+            pause_on_link_down: false,
+            capture_to: None,
+        }
+    }
+    /// Settings for a target parsed with [`crate::net::scope::parse_scoped_ip`]
+    pub fn with_scoped_ip(target: ScopedIp) -> PingSetting {
+        PingSetting {
+            scope_id: target.scope_id,
+            ..PingSetting::new(target.ip)
+        }
+    }
+    /// Destination socket address including the IPv6 scope id
+    pub fn dst_addr(&self, port: u16) -> SocketAddr {
+        ScopedIp {
+            ip: self.dst_ip,
+            scope_id: self.scope_id,
+        }
+        .socket_addr(port)
+    }
+    /// ICMP identifier for this session, pinned or random
+    pub fn resolve_icmp_id(&self) -> u16 {
+        self.icmp_id.unwrap_or_else(icmp::random_id)
+    }
+    /// ICMP echo prober with this session's identifier and payload. Its
+    /// receive buffer fits a full packet on the egress interface, so
+    /// replies on jumbo-frame links are not truncated.
+    pub fn icmp_prober(&self) -> IcmpEchoProber {
+        let config = IcmpConfig::for_mtu(system_egress_mtu(&[self.dst_ip]));
+        IcmpEchoProber::with_config(self.resolve_icmp_id(), config)
+            .with_pinned_id(self.icmp_id.is_some())
+            .with_timestamp_payload(self.timestamp_payload)
+            .with_scope_id(self.scope_id)
+    }
+    /// UDP echo prober for a `UdpEcho` setting
+    pub fn udp_echo_prober(&self) -> Option<UdpEchoProber> {
+        match self.protocol {
+            PingProtocol::UdpEcho { port } => Some(UdpEchoProber {
+                port,
+                src_ip: None,
+                source_port: self.source_port,
+                scope_id: self.scope_id,
+                payload: self.udp_payload.clone(),
+            }),
+            PingProtocol::Icmp => None,
+        }
+    }
+    /// Sequence number of the `n`th probe
+    pub fn seq_for(&self, n: u32) -> u16 {
+        self.icmp_seq.unwrap_or(0).wrapping_add(n as u16)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ping::icmp::{build_echo, parse_echo, Echo, EchoKind};
+    use crate::ping::matcher::ReplyMatcher;
+    use std::net::Ipv4Addr;
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn pinned_id_and_seq_are_sent_and_matched() {
+        let dst = IpAddr::V4(Ipv4Addr::new(192, 0, 2, 10));
+        let mut setting = PingSetting::new(dst);
+        setting.icmp_id = Some(0xbeef);
+        setting.icmp_seq = Some(100);
+
+        let id = setting.resolve_icmp_id();
+        let mut matcher = ReplyMatcher::new(id);
+        let sent_at = Instant::now();
+        let mut packets = Vec::new();
+        for n in 0..setting.count {
+            let request = Echo {
+                kind: EchoKind::Request,
+                id,
+                seq: setting.seq_for(n),
+                payload: Vec::new(),
+            };
+            matcher.register(dst, request.seq, sent_at);
+            packets.push(build_echo(&request, false));
+        }
+
+        let sent = parse_echo(&packets[1], false).unwrap();
+        assert_eq!(sent.id, 0xbeef);
+        assert_eq!(sent.seq, 101);
+
+        let reply = Echo {
+            kind: EchoKind::Reply,
+            ..sent
+        };
+        let matched = matcher
+            .on_reply(dst, &reply, sent_at + Duration::from_millis(12))
+            .unwrap();
+        assert_eq!(matched.seq, 101);
+        assert_eq!(matched.rtt, Duration::from_millis(12));
+
+        let foreign = Echo {
+            id: 0xbeee,
+            seq: 102,
+            ..reply
+        };
+        assert_eq!(matcher.on_reply(dst, &foreign, Instant::now()), None);
+        assert_eq!(matcher.pending(), 3);
+    }
+
+    #[test]
+    fn scoped_target_propagates_to_socket_addr() {
+        let target = crate::net::scope::parse_scoped_ip_with("fe80::2%eth0", |_| Some(4)).unwrap();
+        let mut setting = PingSetting::with_scoped_ip(target);
+        match setting.dst_addr(0) {
+            SocketAddr::V6(addr) => assert_eq!(addr.scope_id(), 4),
+            SocketAddr::V4(_) => panic!("expected IPv6"),
+        }
+        // The probers address the scoped destination
+        let expected: SocketAddr = "[fe80::2%4]:0".parse().unwrap();
+        assert_eq!(setting.icmp_prober().dst_addr(target.ip), expected);
+        setting.protocol = PingProtocol::UdpEcho { port: 7 };
+        let udp = setting.udp_echo_prober().unwrap();
+        assert_eq!(udp.dst_addr(target.ip), "[fe80::2%4]:7".parse().unwrap());
+        // Targets off the link get no scope
+        let global: IpAddr = "2001:db8::1".parse().unwrap();
+        assert_eq!(udp.dst_addr(global), "[2001:db8::1]:7".parse().unwrap());
+    }
+
+    #[test]
+    fn default_id_is_not_pinned() {
+        let setting = PingSetting::new(IpAddr::V4(Ipv4Addr::LOCALHOST));
+        assert_eq!(setting.icmp_id, None);
+        assert_eq!(setting.seq_for(0), 0);
+        assert_eq!(setting.seq_for(3), 3);
+    }
+
+    #[test]
+    fn udp_prober_carries_source_port() {
+        let mut setting = PingSetting::new(IpAddr::V4(Ipv4Addr::LOCALHOST));
+        assert_eq!(setting.udp_echo_prober(), None);
+        setting.protocol = PingProtocol::UdpEcho { port: 7 };
+        setting.source_port = Some(5353);
+        let prober = setting.udp_echo_prober().unwrap();
+        assert_eq!((prober.port, prober.source_port), (7, Some(5353)));
+    }
+
+    #[test]
+    fn icmp_prober_uses_pinned_id() {
+        use crate::ping::Prober;
+        let mut setting = PingSetting::new(IpAddr::V4(Ipv4Addr::LOCALHOST));
+        let id = icmp::random_id();
+        setting.icmp_id = Some(id);
+        let prober = setting.icmp_prober();
+        assert_eq!(prober.id(), id);
+        if prober.check_family(false).is_err() {
+            return;
+        }
+        // What goes on the wire, not just the field
+        prober
+            .probe(setting.dst_ip, 0, Duration::from_secs(2))
+            .unwrap();
+        assert_eq!(prober.sent_id(false), Some(id));
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn icmp_prober_buffer_fits_egress_mtu() {
+        use crate::socket::icmp::buffer_len_for_mtu;
+        let interfaces = crate::net::interface::get_interfaces().unwrap();
+        let lo_mtu = interfaces
+            .iter()
+            .find(|i| i.is_loopback)
+            .and_then(|i| i.mtu);
+        let setting = PingSetting::new(IpAddr::V4(Ipv4Addr::LOCALHOST));
+        let config = setting.icmp_prober().config().clone();
+        assert_eq!(config.buffer_len, buffer_len_for_mtu(lo_mtu));
+    }
+}
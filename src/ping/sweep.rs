@@ -0,0 +1,205 @@
+use super::{ProbeError, ProbeReply};
+use crate::cancel::CancellationToken;
+use std::net::IpAddr;
+use std::time::Duration;
+use ts_rs::TS;
+
+/// Sends one probe with a payload of a given size, without fragmentation
+pub trait SizedProber: Sync {
+    fn probe_sized(
+        &self,
+        dst: IpAddr,
+        seq: u16,
+        payload_len: usize,
+        timeout: Duration,
+    ) -> Result<ProbeReply, ProbeError>;
+}
+
+/// Settings for a payload-size sweep
+#[derive(Clone, Debug, PartialEq, Eq, TS)]
+pub struct PayloadSweepSetting {
+    pub dst_ip: IpAddr,
+    /// Smallest payload in bytes
+    pub start: usize,
+    /// Largest payload in bytes, inclusive
+    pub end: usize,
+    pub step: usize,
+    /// Probes per size. A size succeeds if any probe is answered.
+    pub count: u32,
+    #[ts(type = "number")]
+    pub timeout_ms: u64,
+}
+
+impl PayloadSweepSetting {
+    pub fn new(dst_ip: IpAddr) -> PayloadSweepSetting {
+        PayloadSweepSetting {
+            dst_ip,
+            start: 64,
+            end: 1500,
+            step: 64,
+            count: 2,
+            timeout_ms: 1000,
+        }
+    }
+    /// Sizes probed, always including `end`
+    pub fn sizes(&self) -> Vec<usize> {
+        let mut sizes: Vec<usize> = (self.start..=self.end).step_by(self.step.max(1)).collect();
+        if self.start <= self.end && sizes.last() != Some(&self.end) {
+            sizes.push(self.end);
+        }
+        sizes
+    }
+}
+
+/// Outcome of one payload size
+#[derive(Clone, Debug, PartialEq, Eq, TS)]
+pub struct SizeResult {
+    pub size: usize,
+    pub success: bool,
+    /// RTT of the first answered probe
+    #[ts(type = "Duration | null")]
+    pub rtt: Option<Duration>,
+    /// Last error, e.g. message too long when the local MTU is exceeded
+    pub error: Option<String>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, TS)]
+pub struct PayloadSweepReport {
+    pub dst_ip: IpAddr,
+    pub results: Vec<SizeResult>,
+    /// Largest size answered
+    pub largest_ok: Option<usize>,
+    /// Smallest failing size above `largest_ok`, where packets stop getting through
+    pub cliff: Option<usize>,
+    pub cancelled: bool,
+}
+
+/// Probe each size of `setting.sizes()` in order, calling `on_result` as
+/// each size completes
+pub fn payload_sweep<P, F>(
+    prober: &P,
+    setting: &PayloadSweepSetting,
+    token: &CancellationToken,
+    mut on_result: F,
+) -> PayloadSweepReport
+where
+    P: SizedProber,
+    F: FnMut(&SizeResult),
+{
+    let timeout = Duration::from_millis(setting.timeout_ms);
+    let mut results = Vec::new();
+    let mut seq: u16 = 0;
+    for size in setting.sizes() {
+        if token.is_cancelled() {
+            break;
+        }
+        let mut result = SizeResult {
+            size,
+            success: false,
+            rtt: None,
+            error: None,
+        };
+        for _ in 0..setting.count {
+            if token.is_cancelled() {
+                break;
+            }
+            match prober.probe_sized(setting.dst_ip, seq, size, timeout) {
+                Ok(reply) => {
+                    result.success = true;
+                    result.rtt = Some(reply.rtt);
+                    result.error = None;
+                }
+                Err(e) => result.error = Some(e.to_string()),
+            }
+            seq = seq.wrapping_add(1);
+            if result.success {
+                break;
+            }
+        }
+        on_result(&result);
+        results.push(result);
+    }
+    let largest_ok = results.iter().filter(|r| r.success).map(|r| r.size).max();
+    let cliff = results
+        .iter()
+        .filter(|r| !r.success && largest_ok.is_none_or(|ok| r.size > ok))
+        .map(|r| r.size)
+        .min();
+    PayloadSweepReport {
+        dst_ip: setting.dst_ip,
+        results,
+        largest_ok,
+        cliff,
+        cancelled: token.is_cancelled(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Answers payloads up to `max` bytes
+    struct PathMtu {
+        max: usize,
+    }
+
+    impl SizedProber for PathMtu {
+        fn probe_sized(
+            &self,
+            dst: IpAddr,
+            _seq: u16,
+            payload_len: usize,
+            _timeout: Duration,
+        ) -> Result<ProbeReply, ProbeError> {
+            if payload_len > self.max {
+                return Err(ProbeError::Timeout);
+            }
+            Ok(ProbeReply {
+                responder: dst,
+                rtt: Duration::from_millis(2),
+                ttl: None,
+            })
+        }
+    }
+
+    #[test]
+    fn cliff_above_threshold() {
+        let mut setting = PayloadSweepSetting::new("192.0.2.1".parse().unwrap());
+        setting.start = 1000;
+        setting.end = 1500;
+        setting.step = 100;
+        let mut seen = Vec::new();
+        let report = payload_sweep(
+            &PathMtu { max: 1372 },
+            &setting,
+            &CancellationToken::new(),
+            |r| seen.push((r.size, r.success)),
+        );
+        assert_eq!(
+            seen,
+            vec![
+                (1000, true),
+                (1100, true),
+                (1200, true),
+                (1300, true),
+                (1400, false),
+                (1500, false),
+            ]
+        );
+        assert_eq!(report.largest_ok, Some(1300));
+        assert_eq!(report.cliff, Some(1400));
+        assert_eq!(
+            report.results[4].error.as_deref(),
+            Some("Request timed out")
+        );
+    }
+
+    #[test]
+    fn sizes_include_end() {
+        let mut setting = PayloadSweepSetting::new("192.0.2.1".parse().unwrap());
+        setting.start = 64;
+        setting.end = 200;
+        setting.step = 64;
+        assert_eq!(setting.sizes(), vec![64, 128, 192, 200]);
+    }
+}
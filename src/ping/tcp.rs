@@ -0,0 +1,221 @@
+use super::result::{PingSample, PingStat};
+use crate::cancel::CancellationToken;
+use crate::net::socks::{Socks5Prober, Socks5Proxy};
+use crate::probe::cancellable_sleep_until;
+use crate::scan::port::{PortProber, PortState, TcpConnectProber};
+use std::net::SocketAddr;
+use std::time::{Duration, Instant};
+use ts_rs::TS;
+
+/// Most connects one run makes, so each gets its own sample sequence number
+pub const MAX_TCP_CONNECTS: u32 = u16::MAX as u32 + 1;
+
+/// Settings for repeated TCP connects to one service
+#[derive(Clone, Debug, PartialEq, Eq, TS)]
+pub struct TcpConnectSetting {
+    pub dst: SocketAddr,
+    /// Connects to make, clamped to [`MAX_TCP_CONNECTS`]
+    pub count: u32,
+    #[ts(type = "number")]
+    pub timeout_ms: u64,
+    /// Delay between the start of two connects
+    #[ts(type = "number")]
+    pub interval_ms: u64,
+    /// Local port to connect from. Chosen by the OS when `None`.
+    pub source_port: Option<u16>,
+    /// Close each connection with a reset instead of an orderly shutdown.
+    /// Avoids piling up TIME_WAIT ports over long runs, but the service
+    /// logs every connect as aborted.
+    pub reset_on_close: bool,
+}
+
+impl TcpConnectSetting {
+    pub fn new(dst: SocketAddr) -> TcpConnectSetting {
+        TcpConnectSetting {
+            dst,
+            count: 10,
+            timeout_ms: 1000,
+            interval_ms: 200,
+            source_port: None,
+            reset_on_close: false,
+        }
+    }
+    /// Direct connect prober for this setting
+    pub fn prober(&self) -> TcpConnectProber {
+        TcpConnectProber {
+            source_port: self.source_port,
+            reset_on_close: self.reset_on_close,
+        }
+    }
+}
+
+/// Connect time statistics of a service
+#[derive(Clone, Debug, PartialEq, TS)]
+pub struct TcpConnectReport {
+    pub dst: SocketAddr,
+    /// Connect time min/avg/max/jitter over the successful connects
+    pub stat: PingStat,
+    /// Connects answered with a reset
+    pub refused: u32,
+    /// Connects that got no answer in time
+    pub timeouts: u32,
+    /// Local error of the last connect that could not be sent, e.g. a
+    /// source port in use
+    pub error: Option<String>,
+    /// Proxy the connects went through. Connect times then measure the
+    /// proxy handshake plus the proxy-to-target path, not the direct path.
+    pub via_proxy: Option<SocketAddr>,
+    /// Set when the proxy itself failed, e.g. rejected the credentials
+    pub proxy_error: Option<String>,
+    pub cancelled: bool,
+}
+
+/// Open and close `count` connections to `setting.dst`, timing each handshake
+pub fn tcp_connect_jitter<P: PortProber>(
+    prober: &P,
+    setting: &TcpConnectSetting,
+    token: &CancellationToken,
+) -> TcpConnectReport {
+    let timeout = Duration::from_millis(setting.timeout_ms);
+    let interval = Duration::from_millis(setting.interval_ms);
+    let mut samples = Vec::new();
+    let mut refused = 0;
+    let mut timeouts = 0;
+    let mut error = None;
+    let count = setting.count.min(MAX_TCP_CONNECTS);
+    for (n, seq) in (0..count).zip(0..=u16::MAX) {
+        if token.is_cancelled() {
+            break;
+        }
+        let started = Instant::now();
+        let probe = prober.probe_port(setting.dst.ip(), setting.dst.port(), timeout);
+        match probe.state {
+            PortState::Open => {}
+            PortState::Closed => refused += 1,
+            PortState::Filtered => timeouts += 1,
+            PortState::Error => error = probe.error,
+        }
+        samples.push(PingSample {
+            seq,
+            rtt: probe.connect_time,
+            responder: None,
+            ttl: None,
+            ttl_changed: false,
+            unreachable: None,
+            duplicates: 0,
+        });
+        if n + 1 < count {
+            cancellable_sleep_until(token, started + interval);
+        }
+    }
+    TcpConnectReport {
+        dst: setting.dst,
+        stat: PingStat::from_samples(&samples),
+        refused,
+        timeouts,
+        error,
+        via_proxy: None,
+        proxy_error: None,
+        cancelled: token.is_cancelled(),
+    }
+}
+
+/// `tcp_connect_jitter` through a SOCKS5 proxy. The proxy is validated
+/// first so authentication problems are reported as such rather than as
+/// failed connects.
+pub fn tcp_connect_jitter_via_proxy(
+    proxy: &Socks5Proxy,
+    setting: &TcpConnectSetting,
+    token: &CancellationToken,
+) -> TcpConnectReport {
+    if let Err(e) = proxy.validate(Duration::from_millis(setting.timeout_ms)) {
+        return TcpConnectReport {
+            dst: setting.dst,
+            stat: PingStat::default(),
+            refused: 0,
+            timeouts: 0,
+            error: None,
+            via_proxy: Some(proxy.addr),
+            proxy_error: Some(e.to_string()),
+            cancelled: token.is_cancelled(),
+        };
+    }
+    let prober = Socks5Prober {
+        proxy: proxy.clone(),
+    };
+    TcpConnectReport {
+        via_proxy: Some(proxy.addr),
+        ..tcp_connect_jitter(&prober, setting, token)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+    use std::net::{Ipv4Addr, TcpListener};
+
+    #[test]
+    fn local_listener_stats_and_cleanup() {
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        let mut setting = TcpConnectSetting::new(listener.local_addr().unwrap());
+        setting.count = 5;
+        setting.interval_ms = 0;
+        let report = tcp_connect_jitter(&setting.prober(), &setting, &CancellationToken::new());
+        assert_eq!((report.stat.sent, report.stat.received), (5, 5));
+        assert_eq!((report.refused, report.timeouts), (0, 0));
+        assert!(report.stat.min_ms.unwrap() <= report.stat.max_ms.unwrap());
+        assert!(report.stat.jitter_ms.is_some());
+        // Every client socket is already closed, with an orderly shutdown
+        // unless resets were asked for
+        let closes = |listener: &TcpListener| {
+            (0..5)
+                .map(|_| {
+                    let (mut conn, _) = listener.accept().unwrap();
+                    conn.set_read_timeout(Some(Duration::from_secs(1))).unwrap();
+                    conn.read(&mut [0u8; 1]).map_err(|e| e.kind())
+                })
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(closes(&listener), vec![Ok(0); 5]);
+        setting.reset_on_close = true;
+        let report = tcp_connect_jitter(&setting.prober(), &setting, &CancellationToken::new());
+        assert_eq!(report.stat.received, 5);
+        assert_eq!(
+            closes(&listener),
+            vec![Err(std::io::ErrorKind::ConnectionReset); 5]
+        );
+
+        drop(listener);
+        let report = tcp_connect_jitter(&setting.prober(), &setting, &CancellationToken::new());
+        assert_eq!(report.refused, 5);
+        assert_eq!(report.stat.received, 0);
+    }
+
+    #[test]
+    fn connects_through_socks5_proxy() {
+        use crate::net::socks::tests::socks5_server;
+        use crate::net::socks::Socks5Auth;
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        let mut setting = TcpConnectSetting::new(listener.local_addr().unwrap());
+        setting.count = 3;
+        setting.interval_ms = 0;
+        let mut proxy = Socks5Proxy::new(socks5_server(Some(("jump", "pw"))));
+        proxy.auth = Some(Socks5Auth {
+            username: "jump".to_string(),
+            password: "pw".to_string(),
+        });
+        let report = tcp_connect_jitter_via_proxy(&proxy, &setting, &CancellationToken::new());
+        assert_eq!(report.stat.received, 3);
+        assert_eq!(report.via_proxy, Some(proxy.addr));
+        assert_eq!(report.proxy_error, None);
+
+        proxy.auth.as_mut().unwrap().password = "nope".to_string();
+        let report = tcp_connect_jitter_via_proxy(&proxy, &setting, &CancellationToken::new());
+        assert_eq!(report.stat.sent, 0);
+        assert_eq!(
+            report.proxy_error.as_deref(),
+            Some("SOCKS5 proxy authentication failed")
+        );
+    }
+}
@@ -0,0 +1,125 @@
+//! Service-aware payloads for UDP ping
+use ts_rs::TS;
+
+const NTP_MARK: &[u8; 6] = b"netdia";
+
+/// Request that elicits a reply from a UDP service, and the check that a
+/// datagram is the reply to it
+#[derive(Clone, Copy, Debug)]
+pub struct PayloadTemplate {
+    pub name: &'static str,
+    pub port: u16,
+    pub build: fn(u16) -> Vec<u8>,
+    pub is_reply: fn(&[u8], u16) -> bool,
+}
+
+/// Query for the root NS records, with the sequence as the DNS id
+fn dns_query(seq: u16) -> Vec<u8> {
+    let mut query = seq.to_be_bytes().to_vec();
+    // Recursion desired, one question
+    query.extend_from_slice(&[0x01, 0x00, 0, 1, 0, 0, 0, 0, 0, 0]);
+    // Root name, type NS, class IN
+    query.extend_from_slice(&[0, 0, 2, 0, 1]);
+    query
+}
+
+/// Any response with our id counts, including refusals
+fn is_dns_reply(reply: &[u8], seq: u16) -> bool {
+    reply.len() >= 12 && reply[..2] == seq.to_be_bytes() && reply[2] & 0x80 != 0
+}
+
+/// NTPv4 client request. The transmit timestamp carries the sequence and
+/// comes back as the originate timestamp of the reply.
+fn ntp_request(seq: u16) -> Vec<u8> {
+    let mut request = vec![0u8; 48];
+    request[0] = 0x23;
+    request[40..46].copy_from_slice(NTP_MARK);
+    request[46..48].copy_from_slice(&seq.to_be_bytes());
+    request
+}
+
+fn is_ntp_reply(reply: &[u8], seq: u16) -> bool {
+    reply.len() >= 48 && reply[0] & 0x07 == 4 && reply[24..32] == ntp_request(seq)[40..48]
+}
+
+/// Built-in templates, keyed by their well-known port
+pub const TEMPLATES: [PayloadTemplate; 2] = [
+    PayloadTemplate {
+        name: "dns",
+        port: 53,
+        build: dns_query,
+        is_reply: is_dns_reply,
+    },
+    PayloadTemplate {
+        name: "ntp",
+        port: 123,
+        build: ntp_request,
+        is_reply: is_ntp_reply,
+    },
+];
+
+pub fn template_for_port(port: u16) -> Option<&'static PayloadTemplate> {
+    TEMPLATES.iter().find(|t| t.port == port)
+}
+
+pub fn template_by_name(name: &str) -> Option<&'static PayloadTemplate> {
+    TEMPLATES.iter().find(|t| t.name.eq_ignore_ascii_case(name))
+}
+
+/// What UDP ping sends
+#[derive(Clone, Debug, Default, PartialEq, Eq, TS)]
+pub enum UdpPayload {
+    /// Template for the destination port if there is one, echo otherwise
+    #[default]
+    Auto,
+    /// Sequence-tagged payload that only an echo service returns
+    Echo,
+    /// Built-in template by name, whatever the port
+    Template(String),
+    /// Custom payload as hex. Any datagram back counts as a reply.
+    Hex(String),
+}
+
+/// Decode a hex payload, ignoring whitespace and an optional `0x` prefix
+pub fn parse_hex(hex: &str) -> Option<Vec<u8>> {
+    let digits: String = hex
+        .trim()
+        .trim_start_matches("0x")
+        .chars()
+        .filter(|c| !c.is_whitespace())
+        .collect();
+    if digits.is_empty() || !digits.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..digits.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&digits[i..i + 2], 16).ok())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn templates_recognize_their_replies() {
+        let dns = template_for_port(53).unwrap();
+        assert_eq!(dns.name, "dns");
+        let mut reply = (dns.build)(9);
+        reply[2] |= 0x80;
+        assert!((dns.is_reply)(&reply, 9));
+        assert!(!(dns.is_reply)(&reply, 10));
+        assert!(!(dns.is_reply)(&(dns.build)(9), 9));
+
+        let ntp = template_by_name("NTP").unwrap();
+        let mut reply = vec![0u8; 48];
+        reply[0] = 0x24;
+        reply[24..32].copy_from_slice(&(ntp.build)(3)[40..48]);
+        assert!((ntp.is_reply)(&reply, 3));
+        assert!(!(ntp.is_reply)(&reply, 4));
+
+        assert_eq!(parse_hex("0x de ad"), Some(vec![0xde, 0xad]));
+        assert_eq!(parse_hex("abc"), None);
+        assert_eq!(parse_hex("zz"), None);
+    }
+}
@@ -0,0 +1,340 @@
+use super::sweep::SizedProber;
+use super::template::{self, UdpPayload};
+use super::{ProbeError, ProbeReply, Prober};
+use crate::net::scope::ScopedIp;
+use crate::socket::{bind_udp, set_dont_fragment};
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::time::{Duration, Instant};
+
+/// Port of the UDP echo service (RFC 862)
+pub const ECHO_PORT: u16 = 7;
+const PAYLOAD_MAGIC: &[u8] = b"netdia";
+
+/// Measures RTT to a UDP service.
+///
+/// Each probe carries its sequence number, and only a reply to the same
+/// probe completes it, so late replies to earlier probes are ignored. This
+/// needs no ICMP capture and works without privileges on every platform.
+/// Services other than echo are probed with a request from `payload`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct UdpEchoProber {
+    pub port: u16,
+    /// Source address to bind to. Chosen by the OS when `None`.
+    pub src_ip: Option<IpAddr>,
+    /// Source port to bind to. Chosen by the OS when `None`.
+    pub source_port: Option<u16>,
+    /// Interface index for link-local IPv6 targets, ignored for others
+    pub scope_id: u32,
+    pub payload: UdpPayload,
+}
+
+impl Default for UdpEchoProber {
+    fn default() -> Self {
+        UdpEchoProber {
+            port: ECHO_PORT,
+            src_ip: None,
+            source_port: None,
+            scope_id: 0,
+            payload: UdpPayload::Auto,
+        }
+    }
+}
+
+/// How a reply to a probe is recognized
+enum ReplyCheck {
+    Echo,
+    Template(fn(&[u8], u16) -> bool),
+    Any,
+}
+
+fn echo_payload(seq: u16) -> Vec<u8> {
+    let mut payload = PAYLOAD_MAGIC.to_vec();
+    payload.extend_from_slice(&seq.to_be_bytes());
+    payload
+}
+
+impl UdpEchoProber {
+    /// Address to bind to for targets of the given family
+    fn bind_ip(&self, ipv6: bool) -> io::Result<IpAddr> {
+        match self.src_ip {
+            Some(src) if src.is_ipv6() != ipv6 => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "Source {} cannot reach {} targets",
+                    src,
+                    if ipv6 { "IPv6" } else { "IPv4" }
+                ),
+            )),
+            Some(src) => Ok(src),
+            None if ipv6 => Ok(IpAddr::V6(Ipv6Addr::UNSPECIFIED)),
+            None => Ok(IpAddr::V4(Ipv4Addr::UNSPECIFIED)),
+        }
+    }
+    /// Address probes to `dst` are sent to, with the scope id where it
+    /// applies
+    pub fn dst_addr(&self, dst: IpAddr) -> SocketAddr {
+        ScopedIp::for_target(dst, self.scope_id).socket_addr(self.port)
+    }
+    /// Request for probe `seq` and how to recognize its reply
+    fn request(&self, seq: u16) -> Result<(Vec<u8>, ReplyCheck), ProbeError> {
+        let template = match &self.payload {
+            UdpPayload::Auto => template::template_for_port(self.port),
+            UdpPayload::Echo => None,
+            UdpPayload::Template(name) => {
+                Some(template::template_by_name(name).ok_or_else(|| {
+                    io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        format!("Unknown payload template: {}", name),
+                    )
+                })?)
+            }
+            UdpPayload::Hex(hex) => {
+                let payload = template::parse_hex(hex).ok_or_else(|| {
+                    io::Error::new(io::ErrorKind::InvalidInput, "Invalid hex payload")
+                })?;
+                return Ok((payload, ReplyCheck::Any));
+            }
+        };
+        Ok(match template {
+            Some(t) => ((t.build)(seq), ReplyCheck::Template(t.is_reply)),
+            None => (echo_payload(seq), ReplyCheck::Echo),
+        })
+    }
+    fn send_echo(
+        &self,
+        dst: IpAddr,
+        seq: u16,
+        payload: &[u8],
+        check: ReplyCheck,
+        timeout: Duration,
+        dont_fragment: bool,
+    ) -> Result<ProbeReply, ProbeError> {
+        let bind_addr = self.bind_ip(dst.is_ipv6())?;
+        let socket = bind_udp(SocketAddr::new(bind_addr, self.source_port.unwrap_or(0)))?;
+        socket.connect(self.dst_addr(dst))?;
+        if dont_fragment {
+            set_dont_fragment(&socket, dst.is_ipv6())?;
+        }
+        let start = Instant::now();
+        socket.send(payload)?;
+        let mut buf = vec![0u8; payload.len().max(512) + 1];
+        loop {
+            let remaining = timeout.saturating_sub(start.elapsed());
+            if remaining.is_zero() {
+                return Err(ProbeError::Timeout);
+            }
+            socket.set_read_timeout(Some(remaining))?;
+            match socket.recv(&mut buf) {
+                Ok(n)
+                    if match check {
+                        ReplyCheck::Echo => buf[..n] == payload[..],
+                        ReplyCheck::Template(is_reply) => is_reply(&buf[..n], seq),
+                        ReplyCheck::Any => true,
+                    } =>
+                {
+                    return Ok(ProbeReply {
+                        responder: dst,
+                        rtt: start.elapsed(),
+                        ttl: None,
+                    })
+                }
+                Ok(_) => continue,
+                Err(e)
+                    if matches!(
+                        e.kind(),
+                        io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+                    ) =>
+                {
+                    return Err(ProbeError::Timeout)
+                }
+                Err(e) => return Err(ProbeError::Io(e)),
+            }
+        }
+    }
+}
+
+impl Prober for UdpEchoProber {
+    fn probe(&self, dst: IpAddr, seq: u16, timeout: Duration) -> Result<ProbeReply, ProbeError> {
+        let (payload, check) = self.request(seq)?;
+        self.send_echo(dst, seq, &payload, check, timeout, false)
+    }
+    /// Binds a socket of the family, without the configured source port
+    /// which a running probe may hold
+    fn check_family(&self, ipv6: bool) -> io::Result<()> {
+        bind_udp(SocketAddr::new(self.bind_ip(ipv6)?, 0)).map(|_| ())
+    }
+}
+
+impl SizedProber for UdpEchoProber {
+    /// Echo a `payload_len` byte datagram with the Don't Fragment bit set
+    fn probe_sized(
+        &self,
+        dst: IpAddr,
+        seq: u16,
+        payload_len: usize,
+        timeout: Duration,
+    ) -> Result<ProbeReply, ProbeError> {
+        let mut payload = echo_payload(seq);
+        payload.resize(payload_len.max(payload.len()), 0);
+        self.send_echo(dst, seq, &payload, ReplyCheck::Echo, timeout, true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::UdpSocket;
+    use std::thread;
+
+    fn echo_server() -> u16 {
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let port = socket.local_addr().unwrap().port();
+        thread::spawn(move || {
+            let mut buf = [0u8; 512];
+            while let Ok((n, peer)) = socket.recv_from(&mut buf) {
+                let _ = socket.send_to(&buf[..n], peer);
+            }
+        });
+        port
+    }
+
+    #[test]
+    fn echo_round_trip() {
+        let prober = UdpEchoProber {
+            port: echo_server(),
+            src_ip: Some(IpAddr::V4(Ipv4Addr::LOCALHOST)),
+            source_port: None,
+            scope_id: 0,
+            payload: UdpPayload::Echo,
+        };
+        let localhost = IpAddr::V4(Ipv4Addr::LOCALHOST);
+        for seq in 0..3 {
+            let reply = prober
+                .probe(localhost, seq, Duration::from_secs(1))
+                .unwrap();
+            assert_eq!(reply.responder, localhost);
+            assert!(reply.rtt < Duration::from_secs(1));
+        }
+    }
+
+    #[test]
+    fn sized_echo_round_trip() {
+        let prober = UdpEchoProber {
+            port: echo_server(),
+            ..Default::default()
+        };
+        let localhost = IpAddr::V4(Ipv4Addr::LOCALHOST);
+        let reply = prober
+            .probe_sized(localhost, 1, 300, Duration::from_secs(1))
+            .unwrap();
+        assert_eq!(reply.responder, localhost);
+    }
+
+    #[test]
+    fn probes_leave_from_source_port() {
+        let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let source_port = UdpSocket::bind("127.0.0.1:0")
+            .and_then(|s| s.local_addr())
+            .unwrap()
+            .port();
+        let prober = UdpEchoProber {
+            port: server.local_addr().unwrap().port(),
+            source_port: Some(source_port),
+            ..Default::default()
+        };
+        let handle = thread::spawn(move || {
+            let mut buf = [0u8; 64];
+            let (n, peer) = server.recv_from(&mut buf).unwrap();
+            server.send_to(&buf[..n], peer).unwrap();
+            peer.port()
+        });
+        let localhost = IpAddr::V4(Ipv4Addr::LOCALHOST);
+        prober.probe(localhost, 0, Duration::from_secs(1)).unwrap();
+        assert_eq!(handle.join().unwrap(), source_port);
+
+        // A port held by another socket fails with a clear error
+        let held = UdpSocket::bind("0.0.0.0:0").unwrap();
+        let prober = UdpEchoProber {
+            source_port: Some(held.local_addr().unwrap().port()),
+            ..prober
+        };
+        match prober.probe(localhost, 1, Duration::from_millis(100)) {
+            Err(ProbeError::Io(e)) => assert!(e.to_string().contains("already in use"), "{}", e),
+            other => panic!("expected bind error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn family_checked_against_source() {
+        let prober = UdpEchoProber {
+            src_ip: Some(IpAddr::V4(Ipv4Addr::LOCALHOST)),
+            ..Default::default()
+        };
+        prober.check_family(false).unwrap();
+        let e = prober.check_family(true).unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::InvalidInput);
+        UdpEchoProber::default().check_family(false).unwrap();
+    }
+
+    /// Answers DNS queries like a resolver refusing recursion
+    fn dns_server() -> u16 {
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let port = socket.local_addr().unwrap().port();
+        thread::spawn(move || {
+            let mut buf = [0u8; 512];
+            while let Ok((n, peer)) = socket.recv_from(&mut buf) {
+                let mut reply = buf[..n].to_vec();
+                // Response, REFUSED
+                reply[2] |= 0x80;
+                reply[3] = 0x05;
+                let _ = socket.send_to(&reply, peer);
+            }
+        });
+        port
+    }
+
+    #[test]
+    fn dns_template_elicits_reply() {
+        assert_eq!(template::template_for_port(53).unwrap().name, "dns");
+        let port = dns_server();
+        let localhost = IpAddr::V4(Ipv4Addr::LOCALHOST);
+        let prober = UdpEchoProber {
+            port,
+            payload: UdpPayload::Template("dns".to_string()),
+            ..Default::default()
+        };
+        let reply = prober.probe(localhost, 42, Duration::from_secs(1)).unwrap();
+        assert_eq!(reply.responder, localhost);
+
+        // The echo payload is not a DNS query, so the reply never matches
+        let prober = UdpEchoProber {
+            payload: UdpPayload::Echo,
+            ..prober
+        };
+        let result = prober.probe(localhost, 42, Duration::from_millis(100));
+        assert!(matches!(result, Err(ProbeError::Timeout)));
+
+        let prober = UdpEchoProber {
+            payload: UdpPayload::Hex("00 07 01 00 00 01 00 00 00 00 00 00 00 00 01 00 01".into()),
+            ..prober
+        };
+        assert!(prober.probe(localhost, 0, Duration::from_secs(1)).is_ok());
+    }
+
+    #[test]
+    fn silent_service_times_out() {
+        // Bound but never answers
+        let silent = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let prober = UdpEchoProber {
+            port: silent.local_addr().unwrap().port(),
+            ..Default::default()
+        };
+        let result = prober.probe(
+            IpAddr::V4(Ipv4Addr::LOCALHOST),
+            0,
+            Duration::from_millis(100),
+        );
+        assert!(matches!(result, Err(ProbeError::Timeout)));
+    }
+}
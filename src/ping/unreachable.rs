@@ -0,0 +1,82 @@
+use std::fmt;
+use ts_rs::TS;
+
+/// Why a router or the destination reported a probe undeliverable
+#[derive(Clone, Copy, Debug, PartialEq, Eq, TS)]
+pub enum UnreachableReason {
+    Network,
+    Host,
+    Protocol,
+    Port,
+    /// Packet too big for the next hop with Don't Fragment set
+    FragmentationNeeded,
+    /// A firewall rejected the probe
+    AdminProhibited,
+    /// Code without a more specific meaning here
+    Other {
+        code: u8,
+    },
+}
+
+impl UnreachableReason {
+    /// From the code of an ICMPv4 destination unreachable (type 3)
+    pub fn from_icmpv4(code: u8) -> UnreachableReason {
+        match code {
+            0 | 6 | 11 => UnreachableReason::Network,
+            1 | 7 | 12 => UnreachableReason::Host,
+            2 => UnreachableReason::Protocol,
+            3 => UnreachableReason::Port,
+            4 => UnreachableReason::FragmentationNeeded,
+            9 | 10 | 13 => UnreachableReason::AdminProhibited,
+            code => UnreachableReason::Other { code },
+        }
+    }
+    /// From the code of an ICMPv6 destination unreachable (type 1)
+    pub fn from_icmpv6(code: u8) -> UnreachableReason {
+        match code {
+            0 => UnreachableReason::Network,
+            1 | 5 | 6 => UnreachableReason::AdminProhibited,
+            3 => UnreachableReason::Host,
+            4 => UnreachableReason::Port,
+            code => UnreachableReason::Other { code },
+        }
+    }
+}
+
+impl fmt::Display for UnreachableReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            UnreachableReason::Network => write!(f, "Network unreachable"),
+            UnreachableReason::Host => write!(f, "Host unreachable"),
+            UnreachableReason::Protocol => write!(f, "Protocol unreachable"),
+            UnreachableReason::Port => write!(f, "Port unreachable"),
+            UnreachableReason::FragmentationNeeded => write!(f, "Fragmentation needed"),
+            UnreachableReason::AdminProhibited => write!(f, "Administratively prohibited"),
+            UnreachableReason::Other { code } => {
+                write!(f, "Destination unreachable (code {})", code)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn codes_map_to_reasons() {
+        assert_eq!(
+            UnreachableReason::from_icmpv4(13),
+            UnreachableReason::AdminProhibited
+        );
+        assert_eq!(UnreachableReason::from_icmpv4(3), UnreachableReason::Port);
+        assert_eq!(
+            UnreachableReason::from_icmpv6(1),
+            UnreachableReason::AdminProhibited
+        );
+        assert_eq!(
+            UnreachableReason::from_icmpv4(15).to_string(),
+            "Destination unreachable (code 15)"
+        );
+    }
+}
@@ -0,0 +1,61 @@
+use crate::cancel::CancellationToken;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::thread;
+
+/// Apply `f` to every item using at most `concurrency` worker threads.
+///
+/// Results are returned in input order. Items not started before the token
+/// was cancelled are `None`.
+pub fn map_concurrent<T, R, F>(
+    items: &[T],
+    concurrency: usize,
+    token: &CancellationToken,
+    f: F,
+) -> Vec<Option<R>>
+where
+    T: Sync,
+    R: Send,
+    F: Fn(&T) -> R + Sync,
+{
+    let results: Mutex<Vec<Option<R>>> = Mutex::new((0..items.len()).map(|_| None).collect());
+    let next = AtomicUsize::new(0);
+    let workers = concurrency.clamp(1, items.len().max(1));
+    thread::scope(|s| {
+        for _ in 0..workers {
+            s.spawn(|| loop {
+                if token.is_cancelled() {
+                    break;
+                }
+                let i = next.fetch_add(1, Ordering::SeqCst);
+                if i >= items.len() {
+                    break;
+                }
+                let r = f(&items[i]);
+                results.lock().unwrap()[i] = Some(r);
+            });
+        }
+    });
+    results.into_inner().unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn results_keep_input_order() {
+        let items: Vec<u32> = (0..50).collect();
+        let results = map_concurrent(&items, 8, &CancellationToken::new(), |n| n * 2);
+        let results: Vec<u32> = results.into_iter().map(|r| r.unwrap()).collect();
+        assert_eq!(results, items.iter().map(|n| n * 2).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn cancelled_items_are_skipped() {
+        let token = CancellationToken::new();
+        token.cancel();
+        let results = map_concurrent(&[1, 2, 3], 2, &token, |n| *n);
+        assert!(results.iter().all(|r| r.is_none()));
+    }
+}
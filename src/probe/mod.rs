@@ -0,0 +1,6 @@
+//! Helpers shared by the probe loops
+pub mod util;
+
+pub use util::{
+    cancellable_sleep, cancellable_sleep_until, cancellable_timeout, Clock, Outcome, SystemClock,
+};
@@ -0,0 +1,199 @@
+use crate::cancel::CancellationToken;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// How often waits check the cancellation token
+pub const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// Result of a wait that can time out or be cancelled
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Outcome<T> {
+    Completed(T),
+    TimedOut,
+    Cancelled,
+}
+
+impl<T> Outcome<T> {
+    pub fn completed(self) -> Option<T> {
+        match self {
+            Outcome::Completed(v) => Some(v),
+            _ => None,
+        }
+    }
+    pub fn is_cancelled(&self) -> bool {
+        matches!(self, Outcome::Cancelled)
+    }
+}
+
+/// Sleep until `deadline` unless cancelled first
+pub fn cancellable_sleep_until(token: &CancellationToken, deadline: Instant) -> Outcome<()> {
+    loop {
+        if token.is_cancelled() {
+            return Outcome::Cancelled;
+        }
+        let left = deadline.saturating_duration_since(Instant::now());
+        if left.is_zero() {
+            return Outcome::Completed(());
+        }
+        thread::sleep(left.min(POLL_INTERVAL));
+    }
+}
+
+/// Sleep for `duration` unless cancelled first
+pub fn cancellable_sleep(token: &CancellationToken, duration: Duration) -> Outcome<()> {
+    cancellable_sleep_until(token, Instant::now() + duration)
+}
+
+/// Time source of the probe loops that space or bound their probes, so
+/// tests can run them on virtual time
+pub trait Clock: Sync {
+    fn now(&self) -> Instant;
+    /// Wait until `deadline` unless cancelled first
+    fn sleep_until(&self, token: &CancellationToken, deadline: Instant) -> Outcome<()>;
+    /// Wait for `duration` unless cancelled first
+    fn sleep(&self, token: &CancellationToken, duration: Duration) -> Outcome<()> {
+        self.sleep_until(token, self.now() + duration)
+    }
+}
+
+/// The monotonic system clock
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+    fn sleep_until(&self, token: &CancellationToken, deadline: Instant) -> Outcome<()> {
+        cancellable_sleep_until(token, deadline)
+    }
+}
+
+/// Call `poll` until it yields a value, `timeout` passes or `token` is
+/// cancelled. `poll` must not block.
+pub fn cancellable_timeout<T, F>(
+    token: &CancellationToken,
+    timeout: Duration,
+    mut poll: F,
+) -> Outcome<T>
+where
+    F: FnMut() -> Option<T>,
+{
+    let deadline = Instant::now() + timeout;
+    loop {
+        if token.is_cancelled() {
+            return Outcome::Cancelled;
+        }
+        if let Some(v) = poll() {
+            return Outcome::Completed(v);
+        }
+        let left = deadline.saturating_duration_since(Instant::now());
+        if left.is_zero() {
+            return Outcome::TimedOut;
+        }
+        thread::sleep(left.min(POLL_INTERVAL));
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use std::sync::Mutex;
+
+    /// Virtual time that only moves when slept on or advanced
+    #[derive(Debug)]
+    pub(crate) struct ManualClock(Mutex<Instant>);
+
+    impl ManualClock {
+        pub(crate) fn new() -> ManualClock {
+            ManualClock(Mutex::new(Instant::now()))
+        }
+        pub(crate) fn advance(&self, by: Duration) {
+            *self.0.lock().unwrap() += by;
+        }
+    }
+
+    impl Clock for ManualClock {
+        fn now(&self) -> Instant {
+            *self.0.lock().unwrap()
+        }
+        fn sleep_until(&self, token: &CancellationToken, deadline: Instant) -> Outcome<()> {
+            if token.is_cancelled() {
+                return Outcome::Cancelled;
+            }
+            let mut now = self.0.lock().unwrap();
+            *now = (*now).max(deadline);
+            Outcome::Completed(())
+        }
+    }
+
+    #[test]
+    fn manual_clock_jumps_to_deadlines() {
+        let clock = ManualClock::new();
+        let t0 = clock.now();
+        let token = CancellationToken::new();
+        clock.sleep(&token, Duration::from_secs(60));
+        assert_eq!(clock.now() - t0, Duration::from_secs(60));
+        // A deadline in the past does not move time back
+        clock.sleep_until(&token, t0);
+        clock.advance(Duration::from_millis(5));
+        assert_eq!(clock.now() - t0, Duration::from_millis(60_005));
+        token.cancel();
+        assert!(clock.sleep(&token, Duration::from_secs(1)).is_cancelled());
+        assert_eq!(clock.now() - t0, Duration::from_millis(60_005));
+    }
+
+    #[test]
+    fn sleep_completes_or_is_cancelled() {
+        let token = CancellationToken::new();
+        let started = Instant::now();
+        assert_eq!(
+            cancellable_sleep(&token, Duration::from_millis(30)),
+            Outcome::Completed(())
+        );
+        assert!(started.elapsed() >= Duration::from_millis(30));
+
+        let canceller = token.clone();
+        let handle = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(20));
+            canceller.cancel();
+        });
+        let started = Instant::now();
+        assert!(cancellable_sleep(&token, Duration::from_secs(5)).is_cancelled());
+        assert!(started.elapsed() < Duration::from_secs(1));
+        handle.join().unwrap();
+    }
+
+    #[test]
+    fn timeout_completed() {
+        let mut polls = 0;
+        let outcome =
+            cancellable_timeout(&CancellationToken::new(), Duration::from_secs(1), || {
+                polls += 1;
+                (polls == 3).then_some("ready")
+            });
+        assert_eq!(outcome, Outcome::Completed("ready"));
+    }
+
+    #[test]
+    fn timeout_timed_out() {
+        let started = Instant::now();
+        let outcome: Outcome<()> =
+            cancellable_timeout(&CancellationToken::new(), Duration::from_millis(30), || {
+                None
+            });
+        assert_eq!(outcome, Outcome::TimedOut);
+        assert!(started.elapsed() >= Duration::from_millis(30));
+    }
+
+    #[test]
+    fn timeout_cancelled() {
+        let token = CancellationToken::new();
+        token.cancel();
+        // Cancellation wins even over a ready value
+        assert_eq!(
+            cancellable_timeout(&token, Duration::from_secs(1), || Some(1)),
+            Outcome::Cancelled
+        );
+    }
+}
@@ -0,0 +1,156 @@
+use std::time::{Duration, Instant};
+use ts_rs::TS;
+
+pub const DEFAULT_PROGRESS_INTERVAL_MS: u64 = 100;
+pub const DEFAULT_PROGRESS_STEP_PERCENT: f64 = 1.0;
+
+/// Progress update for an operation over `total` items
+#[derive(Clone, Copy, Debug, PartialEq, TS)]
+pub struct Progress {
+    #[ts(type = "number")]
+    pub done: u64,
+    #[ts(type = "number")]
+    pub total: u64,
+    pub percent: f64,
+}
+
+impl Progress {
+    pub fn new(done: u64, total: u64) -> Progress {
+        let percent = if total == 0 {
+            100.0
+        } else {
+            (done.min(total) as f64) * 100.0 / total as f64
+        };
+        Progress {
+            done,
+            total,
+            percent,
+        }
+    }
+    pub fn is_complete(&self) -> bool {
+        self.done >= self.total
+    }
+}
+
+/// Throttling thresholds for progress updates
+#[derive(Clone, Copy, Debug, PartialEq, TS)]
+pub struct ProgressSetting {
+    /// Minimum time between two updates
+    #[ts(type = "number")]
+    pub interval_ms: u64,
+    /// Minimum increase in percent between two updates
+    pub step_percent: f64,
+}
+
+impl Default for ProgressSetting {
+    fn default() -> Self {
+        ProgressSetting {
+            interval_ms: DEFAULT_PROGRESS_INTERVAL_MS,
+            step_percent: DEFAULT_PROGRESS_STEP_PERCENT,
+        }
+    }
+}
+
+/// Limits how often progress is reported.
+///
+/// An update passes only once both the interval has elapsed and the percentage
+/// has advanced by the step, so whichever threshold is less frequent wins. The
+/// final 100% update always passes.
+#[derive(Debug)]
+pub struct ThrottledProgress {
+    setting: ProgressSetting,
+    last_emit: Option<(Instant, f64)>,
+    finished: bool,
+}
+
+impl ThrottledProgress {
+    pub fn new(setting: ProgressSetting) -> ThrottledProgress {
+        ThrottledProgress {
+            setting,
+            last_emit: None,
+            finished: false,
+        }
+    }
+    /// Returns the update to emit, if any
+    pub fn update(&mut self, done: u64, total: u64) -> Option<Progress> {
+        self.update_at(done, total, Instant::now())
+    }
+    pub fn update_at(&mut self, done: u64, total: u64, now: Instant) -> Option<Progress> {
+        if self.finished {
+            return None;
+        }
+        let progress = Progress::new(done, total);
+        let emit = if progress.is_complete() {
+            self.finished = true;
+            true
+        } else {
+            match self.last_emit {
+                None => true,
+                Some((at, percent)) => {
+                    now.saturating_duration_since(at)
+                        >= Duration::from_millis(self.setting.interval_ms)
+                        && progress.percent - percent >= self.setting.step_percent
+                }
+            }
+        };
+        if emit {
+            self.last_emit = Some((now, progress.percent));
+            Some(progress)
+        } else {
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn honors_time_and_percent_thresholds() {
+        let mut throttle = ThrottledProgress::new(ProgressSetting {
+            interval_ms: 100,
+            step_percent: 10.0,
+        });
+        let t0 = Instant::now();
+        let ms = |n| t0 + Duration::from_millis(n);
+
+        assert!(throttle.update_at(1, 100, ms(0)).is_some());
+        // Enough time, not enough progress
+        assert!(throttle.update_at(5, 100, ms(500)).is_none());
+        // Enough progress, not enough time
+        assert!(throttle.update_at(50, 100, ms(50)).is_none());
+        // Both
+        assert_eq!(
+            throttle.update_at(50, 100, ms(150)).map(|p| p.done),
+            Some(50)
+        );
+        assert!(throttle.update_at(55, 100, ms(400)).is_none());
+    }
+
+    #[test]
+    fn final_update_is_always_emitted_once() {
+        let mut throttle = ThrottledProgress::new(ProgressSetting {
+            interval_ms: 60_000,
+            step_percent: 50.0,
+        });
+        let t0 = Instant::now();
+        assert!(throttle.update_at(1, 10, t0).is_some());
+        let last = throttle.update_at(10, 10, t0).unwrap();
+        assert_eq!(last.percent, 100.0);
+        assert!(throttle.update_at(10, 10, t0).is_none());
+    }
+
+    #[test]
+    fn emission_count_is_bounded() {
+        let mut throttle = ThrottledProgress::new(ProgressSetting {
+            interval_ms: 0,
+            step_percent: 5.0,
+        });
+        let t0 = Instant::now();
+        let emitted = (1..=10_000)
+            .filter(|done| throttle.update_at(*done, 10_000, t0).is_some())
+            .count();
+        assert!(emitted <= 21, "emitted {}", emitted);
+    }
+}
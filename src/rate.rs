@@ -0,0 +1,95 @@
+//! Probe pacing
+use crate::cancel::CancellationToken;
+use crate::ping::{ProbeError, ProbeReply, Prober};
+use crate::probe::cancellable_sleep_until;
+use std::net::IpAddr;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Spaces calls evenly so at most `per_sec` pass each second, shared by
+/// all worker threads
+#[derive(Debug)]
+pub struct RateLimiter {
+    interval: Duration,
+    next: Mutex<Option<Instant>>,
+}
+
+impl RateLimiter {
+    pub fn new(per_sec: u32) -> RateLimiter {
+        RateLimiter {
+            interval: Duration::from_secs(1) / per_sec.max(1),
+            next: Mutex::new(None),
+        }
+    }
+    /// Wait for the next free slot. Returns false if cancelled while waiting.
+    pub fn acquire(&self, token: &CancellationToken) -> bool {
+        let slot = {
+            let mut next = self.next.lock().unwrap();
+            let now = Instant::now();
+            let slot = next.map_or(now, |n| n.max(now));
+            *next = Some(slot + self.interval);
+            slot
+        };
+        !cancellable_sleep_until(token, slot).is_cancelled()
+    }
+}
+
+/// Prober that waits for a `RateLimiter` slot before each probe
+pub struct Paced<'a, P> {
+    inner: &'a P,
+    limiter: RateLimiter,
+    token: &'a CancellationToken,
+}
+
+impl<'a, P: Prober> Paced<'a, P> {
+    pub fn new(inner: &'a P, per_sec: u32, token: &'a CancellationToken) -> Paced<'a, P> {
+        Paced {
+            inner,
+            limiter: RateLimiter::new(per_sec),
+            token,
+        }
+    }
+}
+
+impl<P: Prober> Prober for Paced<'_, P> {
+    fn probe(&self, dst: IpAddr, seq: u16, timeout: Duration) -> Result<ProbeReply, ProbeError> {
+        if !self.limiter.acquire(self.token) {
+            return Err(ProbeError::Timeout);
+        }
+        self.inner.probe(dst, seq, timeout)
+    }
+    fn dropped_packets(&self) -> Option<u64> {
+        self.inner.dropped_packets()
+    }
+    fn duplicate_replies(&self) -> Option<u64> {
+        self.inner.duplicate_replies()
+    }
+    fn duplicates_of(&self, dst: IpAddr, seq: u16) -> Option<u32> {
+        self.inner.duplicates_of(dst, seq)
+    }
+    fn reordered_replies(&self) -> Option<u64> {
+        self.inner.reordered_replies()
+    }
+    fn check_family(&self, ipv6: bool) -> std::io::Result<()> {
+        self.inner.check_family(ipv6)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn calls_are_spaced() {
+        let limiter = RateLimiter::new(100);
+        let token = CancellationToken::new();
+        let started = Instant::now();
+        for _ in 0..6 {
+            assert!(limiter.acquire(&token));
+        }
+        // The first slot is immediate, the other five 10ms apart
+        assert!(started.elapsed() >= Duration::from_millis(50));
+        token.cancel();
+        assert!(!limiter.acquire(&token));
+    }
+}
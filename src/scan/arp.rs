@@ -0,0 +1,390 @@
+//! ARP-only discovery of hosts on the local IPv4 subnet
+use super::host::{Detection, Host, HostScanResult, HostState};
+use super::HostScanSetting;
+use crate::cancel::CancellationToken;
+use crate::net::interface::Interface;
+use crate::net::ipnet::IpNet;
+use crate::net::mac::MacAddr;
+use std::collections::HashMap;
+use std::fmt;
+use std::io;
+use std::net::{IpAddr, Ipv4Addr};
+use std::time::{Duration, Instant};
+
+const ETHERTYPE_ARP: u16 = 0x0806;
+const ARP_REQUEST: u16 = 1;
+const ARP_REPLY: u16 = 2;
+/// Ethernet header plus an IPv4-over-Ethernet ARP packet
+pub const ARP_FRAME_LEN: usize = 42;
+
+#[derive(Debug)]
+pub enum ArpError {
+    /// ARP resolves IPv4 addresses only
+    NotIpv4(IpAddr),
+    /// The target is not on the interface's subnet, so ARP cannot reach it
+    OffLink(IpAddr),
+    /// The interface has no MAC or IPv4 address to send from
+    NoSource(String),
+    /// Raw link-layer access needs root or CAP_NET_RAW
+    NoPrivilege,
+    Io(io::Error),
+}
+
+impl fmt::Display for ArpError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ArpError::NotIpv4(ip) => write!(f, "ARP cannot resolve {}", ip),
+            ArpError::OffLink(ip) => write!(f, "{} is not on the local subnet", ip),
+            ArpError::NoSource(iface) => {
+                write!(f, "{} has no MAC or IPv4 address to send ARP from", iface)
+            }
+            ArpError::NoPrivilege => write!(f, "ARP scan requires administrator privileges"),
+            ArpError::Io(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl std::error::Error for ArpError {}
+
+impl From<io::Error> for ArpError {
+    fn from(e: io::Error) -> ArpError {
+        match e.kind() {
+            io::ErrorKind::PermissionDenied => ArpError::NoPrivilege,
+            _ => ArpError::Io(e),
+        }
+    }
+}
+
+/// Sends and receives raw Ethernet frames on one interface
+pub trait L2Channel: Sync {
+    fn send(&self, frame: &[u8]) -> io::Result<()>;
+    /// Next frame, or `None` once `timeout` passes without one
+    fn recv(&self, buf: &mut [u8], timeout: Duration) -> io::Result<Option<usize>>;
+}
+
+/// Interface ARP requests are sent from
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ArpSource {
+    pub iface: String,
+    pub index: u32,
+    pub mac: MacAddr,
+    pub ip: Ipv4Addr,
+    /// Subnet of `ip`; only targets inside it are on-link
+    pub net: IpNet,
+}
+
+impl ArpSource {
+    pub fn from_interface(iface: &Interface) -> Result<ArpSource, ArpError> {
+        let no_source = || ArpError::NoSource(iface.name.clone());
+        let mac = iface.mac.ok_or_else(no_source)?;
+        let net = iface
+            .addrs
+            .iter()
+            .find(|net| net.addr.is_ipv4())
+            .ok_or_else(no_source)?;
+        let IpAddr::V4(ip) = net.addr else {
+            return Err(no_source());
+        };
+        Ok(ArpSource {
+            iface: iface.name.clone(),
+            index: iface.index,
+            mac: MacAddr(mac),
+            ip,
+            net: *net,
+        })
+    }
+}
+
+/// Broadcast ARP request asking for the MAC of `target`
+pub fn arp_request(src_mac: MacAddr, src_ip: Ipv4Addr, target: Ipv4Addr) -> [u8; ARP_FRAME_LEN] {
+    let mut frame = [0u8; ARP_FRAME_LEN];
+    frame[0..6].copy_from_slice(&[0xff; 6]);
+    frame[6..12].copy_from_slice(&src_mac.0);
+    frame[12..14].copy_from_slice(&ETHERTYPE_ARP.to_be_bytes());
+    // Ethernet, IPv4, 6-byte hardware and 4-byte protocol addresses
+    frame[14..22].copy_from_slice(&[0, 1, 0x08, 0, 6, 4, 0, ARP_REQUEST as u8]);
+    frame[22..28].copy_from_slice(&src_mac.0);
+    frame[28..32].copy_from_slice(&src_ip.octets());
+    // Target hardware address stays zero
+    frame[38..42].copy_from_slice(&target.octets());
+    frame
+}
+
+/// Sender of an ARP reply frame
+pub fn parse_arp_reply(frame: &[u8]) -> Option<(Ipv4Addr, MacAddr)> {
+    if frame.len() < ARP_FRAME_LEN
+        || u16::from_be_bytes([frame[12], frame[13]]) != ETHERTYPE_ARP
+        || u16::from_be_bytes([frame[20], frame[21]]) != ARP_REPLY
+    {
+        return None;
+    }
+    let mac: [u8; 6] = frame[22..28].try_into().ok()?;
+    let ip: [u8; 4] = frame[28..32].try_into().ok()?;
+    Some((Ipv4Addr::from(ip), MacAddr(mac)))
+}
+
+/// IPv4 targets of `targets`, failing on the first one ARP cannot reach
+pub fn on_link_targets(source: &ArpSource, targets: &[IpAddr]) -> Result<Vec<Ipv4Addr>, ArpError> {
+    targets
+        .iter()
+        .map(|ip| match ip {
+            IpAddr::V4(v4) if source.net.contains(ip) => Ok(*v4),
+            IpAddr::V4(_) => Err(ArpError::OffLink(*ip)),
+            IpAddr::V6(_) => Err(ArpError::NotIpv4(*ip)),
+        })
+        .collect()
+}
+
+/// Whether this process likely has the raw access ARP scanning needs.
+/// Capabilities are not inspected, so a non-root process with
+/// `CAP_NET_RAW` is reported as unprivileged.
+#[cfg(unix)]
+pub fn has_raw_access() -> bool {
+    unsafe { libc::geteuid() == 0 }
+}
+
+/// Whether this process likely has the raw access ARP scanning needs
+#[cfg(not(unix))]
+pub fn has_raw_access() -> bool {
+    false
+}
+
+/// Send an ARP request to every target in `setting` and mark responders
+/// alive with their MAC, without any ICMP. Waits `setting.timeout_ms`
+/// after the last request for replies. Off-link targets are rejected
+/// before anything is sent.
+pub fn arp_scan<C: L2Channel>(
+    channel: &C,
+    source: &ArpSource,
+    setting: &HostScanSetting,
+    token: &CancellationToken,
+) -> Result<HostScanResult, ArpError> {
+    let targets = on_link_targets(source, &setting.targets)?;
+    let mut sent_at: HashMap<Ipv4Addr, Instant> = HashMap::new();
+    for target in &targets {
+        if token.is_cancelled() {
+            break;
+        }
+        channel.send(&arp_request(source.mac, source.ip, *target))?;
+        sent_at.insert(*target, Instant::now());
+    }
+    let mut answered: HashMap<Ipv4Addr, (MacAddr, Duration)> = HashMap::new();
+    let deadline = Instant::now() + Duration::from_millis(setting.timeout_ms);
+    let mut buf = [0u8; 1514];
+    while answered.len() < sent_at.len() && !token.is_cancelled() {
+        let Some(left) = deadline.checked_duration_since(Instant::now()) else {
+            break;
+        };
+        let Some(n) = channel.recv(&mut buf, left)? else {
+            break;
+        };
+        if let Some((ip, mac)) = parse_arp_reply(&buf[..n]) {
+            if let Some(sent) = sent_at.get(&ip) {
+                answered.entry(ip).or_insert((mac, sent.elapsed()));
+            }
+        }
+    }
+    let hosts = targets
+        .iter()
+        .filter(|ip| sent_at.contains_key(ip))
+        .map(|ip| {
+            let reply = answered.get(ip);
+            Host {
+                ip: IpAddr::V4(*ip),
+                state: if reply.is_some() {
+                    HostState::Alive
+                } else {
+                    HostState::Unreachable
+                },
+                rtt: reply.map(|(_, rtt)| *rtt),
+                replies: reply.is_some() as u32,
+                open_ports: Vec::new(),
+                mac: reply.map(|(mac, _)| *mac),
+                detected_by: reply.map(|_| Detection::Arp),
+                hostname: None,
+            }
+        })
+        .collect();
+    Ok(HostScanResult {
+        hosts,
+        cancelled: token.is_cancelled(),
+        cancel_reason: token.reason(),
+        ..Default::default()
+    })
+}
+
+/// `AF_PACKET` socket bound to one interface
+#[cfg(target_os = "linux")]
+pub struct PacketSocket {
+    fd: std::os::fd::OwnedFd,
+    index: u32,
+}
+
+#[cfg(target_os = "linux")]
+impl PacketSocket {
+    /// Open a socket receiving ARP frames on interface `index`
+    pub fn open(index: u32) -> Result<PacketSocket, ArpError> {
+        use std::os::fd::FromRawFd;
+        let protocol = ETHERTYPE_ARP.to_be() as libc::c_int;
+        let fd = unsafe { libc::socket(libc::AF_PACKET, libc::SOCK_RAW, protocol) };
+        if fd < 0 {
+            return Err(io::Error::last_os_error().into());
+        }
+        let socket = PacketSocket {
+            fd: unsafe { std::os::fd::OwnedFd::from_raw_fd(fd) },
+            index,
+        };
+        let addr = socket.link_addr([0; 6]);
+        let ret = unsafe {
+            libc::bind(
+                fd,
+                &addr as *const libc::sockaddr_ll as *const libc::sockaddr,
+                std::mem::size_of::<libc::sockaddr_ll>() as libc::socklen_t,
+            )
+        };
+        if ret != 0 {
+            return Err(io::Error::last_os_error().into());
+        }
+        Ok(socket)
+    }
+    fn link_addr(&self, dst: [u8; 6]) -> libc::sockaddr_ll {
+        let mut addr: libc::sockaddr_ll = unsafe { std::mem::zeroed() };
+        addr.sll_family = libc::AF_PACKET as u16;
+        addr.sll_protocol = ETHERTYPE_ARP.to_be();
+        addr.sll_ifindex = self.index as i32;
+        addr.sll_halen = 6;
+        addr.sll_addr[..6].copy_from_slice(&dst);
+        addr
+    }
+}
+
+#[cfg(target_os = "linux")]
+impl L2Channel for PacketSocket {
+    fn send(&self, frame: &[u8]) -> io::Result<()> {
+        use std::os::fd::AsRawFd;
+        let mut dst = [0u8; 6];
+        dst.copy_from_slice(&frame[..6]);
+        let addr = self.link_addr(dst);
+        let ret = unsafe {
+            libc::sendto(
+                self.fd.as_raw_fd(),
+                frame.as_ptr() as *const libc::c_void,
+                frame.len(),
+                0,
+                &addr as *const libc::sockaddr_ll as *const libc::sockaddr,
+                std::mem::size_of::<libc::sockaddr_ll>() as libc::socklen_t,
+            )
+        };
+        if ret < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+    fn recv(&self, buf: &mut [u8], timeout: Duration) -> io::Result<Option<usize>> {
+        use std::os::fd::AsRawFd;
+        let mut pfd = libc::pollfd {
+            fd: self.fd.as_raw_fd(),
+            events: libc::POLLIN,
+            revents: 0,
+        };
+        let ms = timeout.as_millis().clamp(1, libc::c_int::MAX as u128) as libc::c_int;
+        match unsafe { libc::poll(&mut pfd, 1, ms) } {
+            0 => return Ok(None),
+            n if n < 0 => return Err(io::Error::last_os_error()),
+            _ => {}
+        }
+        let n = unsafe {
+            libc::recv(
+                self.fd.as_raw_fd(),
+                buf.as_mut_ptr() as *mut libc::c_void,
+                buf.len(),
+                0,
+            )
+        };
+        if n < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(Some(n as usize))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::VecDeque;
+    use std::sync::Mutex;
+
+    /// Answers requests for the listed addresses
+    struct Segment {
+        hosts: HashMap<Ipv4Addr, MacAddr>,
+        sent: Mutex<Vec<Vec<u8>>>,
+        replies: Mutex<VecDeque<Vec<u8>>>,
+    }
+
+    impl L2Channel for Segment {
+        fn send(&self, frame: &[u8]) -> io::Result<()> {
+            self.sent.lock().unwrap().push(frame.to_vec());
+            let target = Ipv4Addr::new(frame[38], frame[39], frame[40], frame[41]);
+            if let Some(mac) = self.hosts.get(&target) {
+                let mut reply = arp_request(*mac, target, Ipv4Addr::UNSPECIFIED);
+                reply[21] = ARP_REPLY as u8;
+                self.replies.lock().unwrap().push_back(reply.to_vec());
+            }
+            Ok(())
+        }
+        fn recv(&self, buf: &mut [u8], _timeout: Duration) -> io::Result<Option<usize>> {
+            Ok(self.replies.lock().unwrap().pop_front().map(|frame| {
+                buf[..frame.len()].copy_from_slice(&frame);
+                frame.len()
+            }))
+        }
+    }
+
+    fn source() -> ArpSource {
+        ArpSource {
+            iface: "eth0".to_string(),
+            index: 2,
+            mac: MacAddr([2, 0, 0, 0, 0, 1]),
+            ip: Ipv4Addr::new(192, 168, 1, 10),
+            net: IpNet::new("192.168.1.10".parse().unwrap(), 24),
+        }
+    }
+
+    #[test]
+    fn on_link_targets_get_requests_and_off_link_are_rejected() {
+        let printer = MacAddr([0, 0x11, 0x22, 0x33, 0x44, 0x55]);
+        let segment = Segment {
+            hosts: [(Ipv4Addr::new(192, 168, 1, 20), printer)]
+                .into_iter()
+                .collect(),
+            sent: Mutex::new(Vec::new()),
+            replies: Mutex::new(VecDeque::new()),
+        };
+        let setting = HostScanSetting {
+            targets: vec![
+                "192.168.1.20".parse().unwrap(),
+                "192.168.1.21".parse().unwrap(),
+            ],
+            timeout_ms: 50,
+            ..Default::default()
+        };
+        let result = arp_scan(&segment, &source(), &setting, &CancellationToken::new()).unwrap();
+        let sent = segment.sent.lock().unwrap().clone();
+        assert_eq!(sent.len(), 2);
+        assert_eq!(&sent[0][0..6], &[0xff; 6]);
+        assert_eq!(&sent[1][38..42], &[192, 168, 1, 21]);
+        assert_eq!(result.hosts[0].state, HostState::Alive);
+        assert_eq!(result.hosts[0].mac, Some(printer));
+        assert_eq!(result.hosts[1].state, HostState::Unreachable);
+        assert_eq!(result.hosts[1].mac, None);
+
+        let setting = HostScanSetting {
+            targets: vec!["192.168.1.20".parse().unwrap(), "8.8.8.8".parse().unwrap()],
+            ..Default::default()
+        };
+        segment.sent.lock().unwrap().clear();
+        let err = arp_scan(&segment, &source(), &setting, &CancellationToken::new()).unwrap_err();
+        assert_eq!(err.to_string(), "8.8.8.8 is not on the local subnet");
+        assert!(segment.sent.lock().unwrap().is_empty());
+    }
+}
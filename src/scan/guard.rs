@@ -0,0 +1,330 @@
+//! Checks that catch settings likely to flood the network
+use super::HostScanSetting;
+use crate::net::ipnet::IpNet;
+use crate::ping::PingSetting;
+use std::fmt;
+use std::net::IpAddr;
+use ts_rs::TS;
+
+/// Limits beyond which settings are flagged
+#[derive(Clone, Debug, PartialEq, Eq, TS)]
+pub struct GuardThresholds {
+    /// Concurrency above this is flagged
+    pub max_concurrency: usize,
+    /// Timeouts below this count as no timeout at all
+    #[ts(type = "number")]
+    pub min_timeout_ms: u64,
+    /// Unpaced scans of more targets than this are flagged when each target
+    /// gets more than `max_unpaced_count` probes
+    pub large_target_count: usize,
+    pub max_unpaced_count: u32,
+    /// Ping intervals below this are flagged for long sessions
+    #[ts(type = "number")]
+    pub min_ping_interval_ms: u64,
+    /// Ping sessions with more probes than this need `min_ping_interval_ms`
+    pub max_fast_ping_count: u32,
+    /// Expanding more targets than this needs confirmation
+    pub confirm_target_count: usize,
+    /// Expanding more targets than this is refused. Every target is held
+    /// in memory, twice while duplicates are removed, so this stays within
+    /// a small multiple of `confirm_target_count`.
+    pub max_target_count: usize,
+    /// IPv6 networks with a shorter prefix are never enumerated
+    pub min_ipv6_prefix_len: u8,
+}
+
+impl Default for GuardThresholds {
+    fn default() -> Self {
+        GuardThresholds {
+            max_concurrency: 1024,
+            min_timeout_ms: 50,
+            large_target_count: 65_536,
+            max_unpaced_count: 10,
+            min_ping_interval_ms: 10,
+            max_fast_ping_count: 100,
+            confirm_target_count: 65_536,
+            max_target_count: 131_072,
+            min_ipv6_prefix_len: 112,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, TS)]
+pub enum GuardIssue {
+    /// Many workers with next to no timeout, a tight send loop
+    ZeroTimeoutFlood {
+        concurrency: usize,
+    },
+    ExcessiveConcurrency {
+        concurrency: usize,
+    },
+    /// Many probes per target over a large range without a rate limit
+    UnpacedLargeSweep {
+        targets: usize,
+        count: u32,
+    },
+    /// Long ping session with almost no interval
+    PingFlood {
+        #[ts(type = "number")]
+        interval_ms: u64,
+        count: u32,
+    },
+}
+
+/// Issue found in a setting. Blocking ones are rejected; the others may run
+/// once the user confirms.
+#[derive(Clone, Debug, PartialEq, Eq, TS)]
+pub struct SettingWarning {
+    pub issue: GuardIssue,
+    pub blocking: bool,
+    pub message: String,
+}
+
+impl SettingWarning {
+    fn new(issue: GuardIssue, blocking: bool) -> SettingWarning {
+        let message = match &issue {
+            GuardIssue::ZeroTimeoutFlood { concurrency } => format!(
+                "{} concurrent probes with almost no timeout would flood the network",
+                concurrency
+            ),
+            GuardIssue::ExcessiveConcurrency { concurrency } => {
+                format!("{} concurrent probes may overload the network", concurrency)
+            }
+            GuardIssue::UnpacedLargeSweep { targets, count } => format!(
+                "{} probes to each of {} targets without a rate limit",
+                count, targets
+            ),
+            GuardIssue::PingFlood { interval_ms, count } => {
+                format!("{} pings {}ms apart", count, interval_ms)
+            }
+        };
+        SettingWarning {
+            issue,
+            blocking,
+            message,
+        }
+    }
+}
+
+/// Why a target list was not expanded
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum TargetGuardError {
+    /// Above `confirm_target_count`; expands once confirmed
+    NeedsConfirmation { count: u128, limit: usize },
+    /// Above `max_target_count`
+    TooManyTargets { count: u128, limit: usize },
+    /// IPv6 network too large to enumerate
+    Ipv6TooLarge { net: IpNet, count: u128 },
+}
+
+impl fmt::Display for TargetGuardError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TargetGuardError::NeedsConfirmation { count, limit } => write!(
+                f,
+                "{} targets exceed {} and need confirmation",
+                count, limit
+            ),
+            TargetGuardError::TooManyTargets { count, limit } => {
+                write!(f, "{} targets exceed the limit of {}", count, limit)
+            }
+            TargetGuardError::Ipv6TooLarge { net, count } => write!(
+                f,
+                "{} has {} addresses and cannot be scanned one by one; \
+                 discover hosts on the link with ICMPv6 multicast (ff02::1) instead",
+                net, count
+            ),
+        }
+    }
+}
+
+impl std::error::Error for TargetGuardError {}
+
+/// Every address of `nets`, without duplicates, after checking the total
+/// against the limits in `t`. The size is checked before anything is
+/// allocated.
+pub fn expand_targets(
+    nets: &[IpNet],
+    t: &GuardThresholds,
+    confirmed: bool,
+) -> Result<Vec<IpAddr>, TargetGuardError> {
+    let mut count: u128 = 0;
+    for net in nets {
+        if net.addr.is_ipv6() && net.prefix_len < t.min_ipv6_prefix_len {
+            let net = IpNet::new(net.network(), net.prefix_len);
+            return Err(TargetGuardError::Ipv6TooLarge {
+                net,
+                count: net.size(),
+            });
+        }
+        count = count.saturating_add(net.size());
+    }
+    if count > t.max_target_count as u128 {
+        return Err(TargetGuardError::TooManyTargets {
+            count,
+            limit: t.max_target_count,
+        });
+    }
+    if count > t.confirm_target_count as u128 && !confirmed {
+        return Err(TargetGuardError::NeedsConfirmation {
+            count,
+            limit: t.confirm_target_count,
+        });
+    }
+    let mut seen = std::collections::HashSet::new();
+    Ok(nets
+        .iter()
+        .flat_map(|net| net.addrs())
+        .filter(|ip| seen.insert(*ip))
+        .collect())
+}
+
+/// Issues with a host scan setting
+pub fn check_host_scan(setting: &HostScanSetting, t: &GuardThresholds) -> Vec<SettingWarning> {
+    let mut warnings = Vec::new();
+    let concurrency = setting.concurrency;
+    if concurrency > t.max_concurrency {
+        let issue = if setting.timeout_ms < t.min_timeout_ms {
+            SettingWarning::new(GuardIssue::ZeroTimeoutFlood { concurrency }, true)
+        } else {
+            SettingWarning::new(GuardIssue::ExcessiveConcurrency { concurrency }, false)
+        };
+        warnings.push(issue);
+    }
+    let targets = setting.targets.len();
+    if setting.rate_limit_pps.is_none()
+        && targets > t.large_target_count
+        && setting.count > t.max_unpaced_count
+    {
+        let issue = GuardIssue::UnpacedLargeSweep {
+            targets,
+            count: setting.count,
+        };
+        warnings.push(SettingWarning::new(issue, false));
+    }
+    warnings
+}
+
+/// Issues with a ping setting
+pub fn check_ping(setting: &PingSetting, t: &GuardThresholds) -> Vec<SettingWarning> {
+    if setting.interval_ms < t.min_ping_interval_ms && setting.count > t.max_fast_ping_count {
+        let issue = GuardIssue::PingFlood {
+            interval_ms: setting.interval_ms,
+            count: setting.count,
+        };
+        return vec![SettingWarning::new(issue, false)];
+    }
+    Vec::new()
+}
+
+/// Whether a setting with `warnings` may run. Blocking issues never pass;
+/// the rest pass once `confirmed`.
+pub fn may_run(warnings: &[SettingWarning], confirmed: bool) -> bool {
+    warnings.iter().all(|w| !w.blocking) && (confirmed || warnings.is_empty())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scan::ScanIntensity;
+
+    fn targets(cidr: &str) -> Vec<IpAddr> {
+        let net: IpNet = cidr.parse().unwrap();
+        (0..net.size())
+            .map(|i| match net.network() {
+                IpAddr::V4(ip) => IpAddr::V4((u32::from(ip) + i as u32).into()),
+                IpAddr::V6(_) => unreachable!(),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn abusive_settings_are_flagged() {
+        let t = GuardThresholds::default();
+        let flood = HostScanSetting {
+            concurrency: 5000,
+            timeout_ms: 0,
+            ..Default::default()
+        };
+        let warnings = check_host_scan(&flood, &t);
+        assert_eq!(
+            warnings[0].issue,
+            GuardIssue::ZeroTimeoutFlood { concurrency: 5000 }
+        );
+        assert!(!may_run(&warnings, true));
+
+        let sweep = HostScanSetting {
+            targets: targets("10.0.0.0/15"),
+            count: 20,
+            ..Default::default()
+        };
+        let warnings = check_host_scan(&sweep, &t);
+        assert!(matches!(
+            warnings[0].issue,
+            GuardIssue::UnpacedLargeSweep { count: 20, .. }
+        ));
+        assert!(!may_run(&warnings, false));
+        assert!(may_run(&warnings, true));
+
+        let mut ping = PingSetting::new("192.0.2.1".parse().unwrap());
+        ping.count = 10_000;
+        ping.interval_ms = 0;
+        assert_eq!(check_ping(&ping, &t).len(), 1);
+    }
+
+    #[test]
+    fn oversized_target_sets_are_refused() {
+        let t = GuardThresholds::default();
+        let nets =
+            |cidrs: &[&str]| -> Vec<IpNet> { cidrs.iter().map(|c| c.parse().unwrap()).collect() };
+        let err = expand_targets(&nets(&["2001:db8::5/64"]), &t, true).unwrap_err();
+        assert_eq!(
+            err,
+            TargetGuardError::Ipv6TooLarge {
+                net: "2001:db8::/64".parse().unwrap(),
+                count: 1 << 64,
+            }
+        );
+        let message = err.to_string();
+        assert!(message.contains("18446744073709551616"), "{}", message);
+        assert!(message.contains("ICMPv6 multicast"), "{}", message);
+
+        let large = nets(&["10.0.0.0/15"]);
+        assert_eq!(
+            expand_targets(&large, &t, false),
+            Err(TargetGuardError::NeedsConfirmation {
+                count: 131_072,
+                limit: 65_536
+            })
+        );
+        assert_eq!(expand_targets(&large, &t, true).unwrap().len(), 131_072);
+        assert_eq!(
+            expand_targets(&nets(&["10.0.0.0/14"]), &t, true),
+            Err(TargetGuardError::TooManyTargets {
+                count: 262_144,
+                limit: 131_072
+            })
+        );
+
+        let small = nets(&["192.0.2.0/30", "192.0.2.2/31", "2001:db8::/127"]);
+        let ips = expand_targets(&small, &t, false).unwrap();
+        assert_eq!(ips.len(), 6);
+        assert_eq!(ips[0], "192.0.2.0".parse::<IpAddr>().unwrap());
+    }
+
+    #[test]
+    fn reasonable_settings_pass() {
+        let t = GuardThresholds::default();
+        let paced = HostScanSetting {
+            targets: targets("10.0.0.0/15"),
+            count: 20,
+            rate_limit_pps: Some(1000),
+            ..Default::default()
+        };
+        assert!(check_host_scan(&paced, &t).is_empty());
+        let insane = HostScanSetting::with_intensity(ScanIntensity::Insane);
+        assert!(check_host_scan(&insane, &t).is_empty());
+        assert!(check_ping(&PingSetting::new("192.0.2.1".parse().unwrap()), &t).is_empty());
+        assert!(may_run(&[], false));
+    }
+}
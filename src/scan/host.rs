@@ -0,0 +1,1207 @@
+use super::port::{PortProber, PortState};
+use super::stream::NdjsonSink;
+use super::{DiscoveryMethod, DiscoveryOrder, HostScanSetting, RetrySetting};
+use crate::cancel::{CancelReason, CancellationToken};
+use crate::dns::ReverseLookup;
+use crate::net::mac::MacAddr;
+use crate::ping::Prober;
+use crate::pool::map_concurrent;
+use crate::probe::{Clock, SystemClock};
+use crate::rate::Paced;
+use std::net::IpAddr;
+use std::time::{Duration, Instant};
+use ts_rs::TS;
+
+/// Event name emitted for each alive host, and again with its hostname once
+/// reverse DNS resolved it
+pub const HOSTSCAN_ALIVE_EVENT: &str = "hostscan:alive";
+/// Reverse lookups in flight at once
+pub const REVERSE_DNS_CONCURRENCY: usize = 16;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, TS)]
+pub enum HostState {
+    Alive,
+    Unreachable,
+    /// Not probed because this host cannot send to the target's address
+    /// family, e.g. IPv6 is disabled. See `HostScanResult::family_errors`.
+    Unavailable,
+}
+
+/// Probe that found a host alive
+#[derive(Clone, Copy, Debug, PartialEq, Eq, TS)]
+pub enum Detection {
+    Icmp,
+    Tcp,
+    Arp,
+}
+
+/// Scan result of a single host
+#[derive(Clone, Debug, PartialEq, Eq, TS)]
+pub struct Host {
+    pub ip: IpAddr,
+    pub state: HostState,
+    /// RTT of the first reply
+    #[ts(type = "Duration | null")]
+    pub rtt: Option<Duration>,
+    /// Probes answered
+    pub replies: u32,
+    /// Ports of `quick_ports` that accepted a connection
+    pub open_ports: Vec<u16>,
+    /// Hardware address, when found by ARP
+    #[ts(type = "string | null")]
+    pub mac: Option<MacAddr>,
+    /// Method that detected the host, `None` unless alive
+    pub detected_by: Option<Detection>,
+    /// Name from reverse DNS, when `reverse_dns` is set and the host has one
+    pub hostname: Option<String>,
+}
+
+/// Result of a host scan
+#[derive(Clone, Debug, Default, PartialEq, Eq, TS)]
+pub struct HostScanResult {
+    pub hosts: Vec<Host>,
+    /// Set when the scan stopped before probing every target
+    pub cancelled: bool,
+    /// Why the scan was cancelled, when it was
+    pub cancel_reason: Option<CancelReason>,
+    /// Replies the kernel dropped on receive, when the receiver can tell.
+    /// Any non-zero value means `unreachable` may include live hosts.
+    #[ts(type = "number | null")]
+    pub dropped_packets: Option<u64>,
+    /// Hosts that only answered the retry pass
+    pub recovered_on_retry: usize,
+    /// Set when `stream_to` could not be written
+    pub stream_error: Option<String>,
+    /// Address families that could not be probed, such as "IPv6
+    /// unavailable on this host: ...". Their targets are `Unavailable`.
+    pub family_errors: Vec<String>,
+}
+
+impl HostScanResult {
+    pub fn alive(&self) -> impl Iterator<Item = &Host> {
+        self.hosts.iter().filter(|h| h.state == HostState::Alive)
+    }
+    pub fn unreachable(&self) -> impl Iterator<Item = &Host> {
+        self.hosts
+            .iter()
+            .filter(|h| h.state == HostState::Unreachable)
+    }
+    pub fn unavailable(&self) -> impl Iterator<Item = &Host> {
+        self.hosts
+            .iter()
+            .filter(|h| h.state == HostState::Unavailable)
+    }
+}
+
+/// Probe a single host up to `count` times, `probe_interval_ms` apart,
+/// stopping once `require_replies` replies arrived, once that many can no
+/// longer arrive, or when `host_budget_ms` runs out
+pub fn probe_host<P: Prober>(
+    prober: &P,
+    ip: IpAddr,
+    setting: &HostScanSetting,
+    token: &CancellationToken,
+) -> Host {
+    probe_host_on(prober, ip, setting, token, &SystemClock)
+}
+
+/// `probe_host` spacing and budgeting probes on `clock`
+fn probe_host_on<P: Prober, C: Clock>(
+    prober: &P,
+    ip: IpAddr,
+    setting: &HostScanSetting,
+    token: &CancellationToken,
+    clock: &C,
+) -> Host {
+    let timeout = Duration::from_millis(setting.timeout_ms);
+    let deadline = setting
+        .host_budget_ms
+        .map(|ms| clock.now() + Duration::from_millis(ms));
+    let required = setting.require_replies.max(1) as u32;
+    let interval = Duration::from_millis(setting.probe_interval_ms);
+    let mut replies = 0;
+    let mut rtt = None;
+    let mut last_send: Option<Instant> = None;
+    for n in 0..setting.count {
+        if let Some(sent) = last_send {
+            let next = sent + interval;
+            if deadline.is_some_and(|d| next >= d) {
+                break;
+            }
+            if clock.sleep_until(token, next).is_cancelled() {
+                break;
+            }
+        }
+        if token.is_cancelled() {
+            break;
+        }
+        // Shorten the last probe to fit the budget rather than skipping it,
+        // so a reply inside the budget is still timed normally
+        let timeout = match deadline {
+            Some(deadline) => match deadline.checked_duration_since(clock.now()) {
+                Some(left) if !left.is_zero() => timeout.min(left),
+                _ => break,
+            },
+            None => timeout,
+        };
+        last_send = Some(clock.now());
+        if let Ok(reply) = prober.probe(ip, setting.seq_for(n), timeout) {
+            replies += 1;
+            rtt.get_or_insert(reply.rtt);
+        }
+        if replies >= required || replies + (setting.count - n - 1) < required {
+            break;
+        }
+    }
+    let alive = replies >= required;
+    Host {
+        ip,
+        state: if alive {
+            HostState::Alive
+        } else {
+            HostState::Unreachable
+        },
+        rtt: rtt.filter(|_| alive),
+        replies,
+        open_ports: Vec::new(),
+        mac: None,
+        detected_by: alive.then_some(Detection::Icmp),
+        hostname: None,
+    }
+}
+
+/// Probe every target in `setting` with bounded concurrency and optional
+/// pacing, then re-probe the unreachable ones if a retry pass is configured.
+/// TCP connects go through [`HostScanSetting::tcp_prober`].
+pub fn host_scan<P: Prober>(
+    prober: &P,
+    setting: &HostScanSetting,
+    token: &CancellationToken,
+) -> HostScanResult {
+    host_scan_with_ports(prober, &setting.tcp_prober(), setting, token)
+}
+
+/// `host_scan` over ICMP echo, through the system's ICMP socket
+pub fn icmp_host_scan(setting: &HostScanSetting, token: &CancellationToken) -> HostScanResult {
+    host_scan(&setting.icmp_prober(), setting, token)
+}
+
+/// `host_scan` checking `quick_ports` through `ports`
+pub fn host_scan_with_ports<P: Prober, Q: PortProber>(
+    prober: &P,
+    ports: &Q,
+    setting: &HostScanSetting,
+    token: &CancellationToken,
+) -> HostScanResult {
+    match setting.rate_limit_pps {
+        Some(pps) => scan_with(&Paced::new(prober, pps, token), ports, setting, token),
+        None => scan_with(prober, ports, setting, token),
+    }
+}
+
+/// Connect to `discovery_ports` until one answers. A refused connection
+/// proves the host is up as much as an accepted one.
+fn tcp_discover<Q: PortProber>(
+    ports: &Q,
+    ip: IpAddr,
+    setting: &HostScanSetting,
+    token: &CancellationToken,
+) -> Host {
+    let timeout = Duration::from_millis(setting.timeout_ms);
+    let mut host = Host {
+        state: HostState::Unreachable,
+        ..unavailable_host(ip)
+    };
+    for port in &setting.discovery_ports {
+        if token.is_cancelled() {
+            break;
+        }
+        let started = Instant::now();
+        let probe = ports.probe_port(ip, *port, timeout);
+        if matches!(probe.state, PortState::Open | PortState::Closed) {
+            host.state = HostState::Alive;
+            host.rtt = Some(probe.connect_time.unwrap_or_else(|| started.elapsed()));
+            host.replies = 1;
+            host.detected_by = Some(Detection::Tcp);
+            break;
+        }
+    }
+    host
+}
+
+/// Liveness of one host by `setting.discovery`
+fn discover<P: Prober, Q: PortProber>(
+    prober: &P,
+    ports: &Q,
+    ip: IpAddr,
+    setting: &HostScanSetting,
+    token: &CancellationToken,
+) -> Host {
+    let icmp = || probe_host(prober, ip, setting, token);
+    let tcp = || tcp_discover(ports, ip, setting, token);
+    let alive = |h: &Host| h.state == HostState::Alive;
+    match (setting.discovery, setting.discovery_order) {
+        (DiscoveryMethod::Icmp, _) => icmp(),
+        (DiscoveryMethod::Tcp, _) => tcp(),
+        (DiscoveryMethod::Both, DiscoveryOrder::All) => {
+            let (icmp, tcp) = (icmp(), tcp());
+            if alive(&icmp) || !alive(&tcp) {
+                icmp
+            } else {
+                tcp
+            }
+        }
+        (DiscoveryMethod::Both, DiscoveryOrder::IcmpFirst) => {
+            let host = icmp();
+            if alive(&host) {
+                host
+            } else {
+                tcp()
+            }
+        }
+        (DiscoveryMethod::Both, DiscoveryOrder::TcpFirst) => {
+            let host = tcp();
+            if alive(&host) {
+                host
+            } else {
+                icmp()
+            }
+        }
+    }
+}
+
+/// Probe one host, then connect to `quick_ports` if it is alive. Runs in
+/// the host's worker so port checks share the scan's concurrency.
+fn scan_host<P: Prober, Q: PortProber>(
+    prober: &P,
+    ports: &Q,
+    ip: IpAddr,
+    setting: &HostScanSetting,
+    token: &CancellationToken,
+) -> Host {
+    let mut host = discover(prober, ports, ip, setting, token);
+    if host.state == HostState::Alive {
+        let timeout = Duration::from_millis(setting.timeout_ms);
+        for port in &setting.quick_ports {
+            if token.is_cancelled() {
+                break;
+            }
+            if ports.probe_port(ip, *port, timeout).state == PortState::Open {
+                host.open_ports.push(*port);
+            }
+        }
+    }
+    host
+}
+
+fn scan_with<P: Prober, Q: PortProber>(
+    prober: &P,
+    ports: &Q,
+    setting: &HostScanSetting,
+    token: &CancellationToken,
+) -> HostScanResult {
+    let (sink, mut stream_error) = match setting.stream_to.as_deref().map(NdjsonSink::create) {
+        Some(Ok(sink)) => (Some(sink), None),
+        Some(Err(e)) => (None, Some(e.to_string())),
+        None => (None, None),
+    };
+    // A family whose socket cannot be opened downgrades its targets rather
+    // than failing the scan for the other family
+    let mut family_errors = Vec::new();
+    let mut usable = [true, true];
+    for (ipv6, name) in [(false, "IPv4"), (true, "IPv6")] {
+        if !setting.targets.iter().any(|ip| ip.is_ipv6() == ipv6) {
+            continue;
+        }
+        if let Err(e) = prober.check_family(ipv6) {
+            family_errors.push(format!("{} unavailable on this host: {}", name, e));
+            usable[ipv6 as usize] = false;
+        }
+    }
+    let results = map_concurrent(&setting.targets, setting.concurrency, token, |ip| {
+        if !usable[ip.is_ipv6() as usize] {
+            let host = unavailable_host(*ip);
+            if let Some(sink) = &sink {
+                sink.write(&host);
+            }
+            return host;
+        }
+        let host = scan_host(prober, ports, *ip, setting, token);
+        // Unreachable hosts wait for the retry pass so each host is written once
+        if host.state == HostState::Alive || setting.retry.is_none() {
+            if let Some(sink) = &sink {
+                sink.write(&host);
+            }
+        }
+        host
+    });
+    let mut hosts: Vec<Host> = results.into_iter().flatten().collect();
+    let mut recovered_on_retry = 0;
+    if let Some(retry) = &setting.retry {
+        let retried: Vec<usize> = (0..hosts.len())
+            .filter(|i| hosts[*i].state == HostState::Unreachable)
+            .collect();
+        recovered_on_retry = retry_unreachable(prober, ports, &mut hosts, setting, retry, token);
+        if let Some(sink) = &sink {
+            retried.iter().for_each(|i| sink.write(&hosts[*i]));
+        }
+    }
+    if let Some(Err(e)) = sink.map(NdjsonSink::finish) {
+        stream_error = Some(e.to_string());
+    }
+    let cancelled = token.is_cancelled();
+    HostScanResult {
+        hosts,
+        cancelled,
+        cancel_reason: token.reason(),
+        dropped_packets: prober.dropped_packets(),
+        recovered_on_retry,
+        stream_error,
+        family_errors,
+    }
+}
+
+fn unavailable_host(ip: IpAddr) -> Host {
+    Host {
+        ip,
+        state: HostState::Unavailable,
+        rtt: None,
+        replies: 0,
+        open_ports: Vec::new(),
+        mac: None,
+        detected_by: None,
+        hostname: None,
+    }
+}
+
+/// Send a single probe to each of `ips` through the shared `prober` and
+/// report which answered. Meant for quick yes/no rechecks of a few hosts:
+/// there is no per-host budget, pacing, port check or streaming, and all
+/// probes are in flight at once up to `setting.concurrency`.
+pub fn quick_recheck<P: Prober>(
+    prober: &P,
+    ips: &[IpAddr],
+    setting: &HostScanSetting,
+    token: &CancellationToken,
+) -> Vec<Host> {
+    let timeout = Duration::from_millis(setting.timeout_ms);
+    // Past the sequence numbers of the scan and its retry pass, so a late
+    // reply to either is not mistaken for an answer to the recheck
+    let seq = setting.seq_for(setting.count.saturating_mul(2));
+    let results = map_concurrent(ips, setting.concurrency, token, |ip| {
+        let reply = prober.probe(*ip, seq, timeout).ok();
+        Host {
+            ip: *ip,
+            state: if reply.is_some() {
+                HostState::Alive
+            } else {
+                HostState::Unreachable
+            },
+            rtt: reply.as_ref().map(|r| r.rtt),
+            replies: reply.is_some() as u32,
+            open_ports: Vec::new(),
+            mac: None,
+            detected_by: reply.is_some().then_some(Detection::Icmp),
+            hostname: None,
+        }
+    });
+    ips.iter()
+        .zip(results)
+        .map(|(ip, host)| {
+            host.unwrap_or(Host {
+                ip: *ip,
+                state: HostState::Unreachable,
+                rtt: None,
+                replies: 0,
+                open_ports: Vec::new(),
+                mac: None,
+                detected_by: None,
+                hostname: None,
+            })
+        })
+        .collect()
+}
+
+/// With `setting.reverse_dns`, fill in the hostname of alive hosts from
+/// their PTR records, calling `on_resolved` for each host that got one.
+/// Meant as a follow-up to the scan. With `setting.stream_to`, the hosts
+/// that got a name are appended to the stream again. Returns the number of
+/// names found.
+pub fn resolve_hostnames<R, F>(
+    lookup: &R,
+    result: &mut HostScanResult,
+    setting: &HostScanSetting,
+    token: &CancellationToken,
+    on_resolved: F,
+) -> usize
+where
+    R: ReverseLookup,
+    F: Fn(&Host) + Sync,
+{
+    if !setting.reverse_dns {
+        return 0;
+    }
+    let alive: Vec<usize> = (0..result.hosts.len())
+        .filter(|i| result.hosts[*i].state == HostState::Alive)
+        .collect();
+    let concurrency = setting.concurrency.clamp(1, REVERSE_DNS_CONCURRENCY);
+    let sink = match setting.stream_to.as_deref().map(NdjsonSink::append) {
+        Some(Ok(sink)) => Some(sink),
+        Some(Err(e)) => {
+            result.stream_error.get_or_insert(e.to_string());
+            None
+        }
+        None => None,
+    };
+    let hosts = &result.hosts;
+    let names = map_concurrent(&alive, concurrency, token, |i| {
+        let name = lookup.reverse(hosts[*i].ip, token).ok().flatten()?;
+        let host = Host {
+            hostname: Some(name),
+            ..hosts[*i].clone()
+        };
+        if let Some(sink) = &sink {
+            sink.write(&host);
+        }
+        on_resolved(&host);
+        Some(host.hostname)
+    });
+    if let Some(Err(e)) = sink.map(NdjsonSink::finish) {
+        result.stream_error.get_or_insert(e.to_string());
+    }
+    let mut resolved = 0;
+    for (i, name) in alive.into_iter().zip(names) {
+        if let Some(Some(name)) = name {
+            result.hosts[i].hostname = name;
+            resolved += 1;
+        }
+    }
+    resolved
+}
+
+/// Re-probe unreachable hosts with the retry timeout and concurrency.
+/// Returns the number promoted to alive.
+fn retry_unreachable<P: Prober, Q: PortProber>(
+    prober: &P,
+    ports: &Q,
+    hosts: &mut [Host],
+    setting: &HostScanSetting,
+    retry: &RetrySetting,
+    token: &CancellationToken,
+) -> usize {
+    let pending: Vec<usize> = (0..hosts.len())
+        .filter(|i| hosts[*i].state == HostState::Unreachable)
+        .collect();
+    let retry_setting = HostScanSetting {
+        timeout_ms: retry.timeout_ms,
+        // Continue the sequence so late first-pass replies are not mistaken
+        // for retry replies
+        icmp_seq: Some(setting.seq_for(setting.count)),
+        ..setting.clone()
+    };
+    let results = if retry.quick {
+        // Only as many probes as `require_replies` needs, sent back to back,
+        // through the same discovery method
+        let quick_setting = HostScanSetting {
+            count: setting.require_replies.max(1) as u32,
+            probe_interval_ms: 0,
+            host_budget_ms: None,
+            ..retry_setting
+        };
+        map_concurrent(&pending, retry.concurrency, token, |i| {
+            discover(prober, ports, hosts[*i].ip, &quick_setting, token)
+        })
+    } else {
+        map_concurrent(&pending, retry.concurrency, token, |i| {
+            scan_host(prober, ports, hosts[*i].ip, &retry_setting, token)
+        })
+    };
+    let mut recovered = 0;
+    for (i, host) in pending.into_iter().zip(results) {
+        if let Some(host) = host.filter(|h| h.state == HostState::Alive) {
+            hosts[i] = host;
+            recovered += 1;
+        }
+    }
+    recovered
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::ping::{ProbeError, ProbeReply};
+    use crate::probe::util::tests::ManualClock;
+    use crate::scan::port::PortProbe;
+    use crate::scan::stream::host_json;
+    use std::collections::HashSet;
+    use std::net::Ipv4Addr;
+    use std::sync::Mutex;
+
+    /// Replies only from the listed hosts
+    pub(crate) struct AliveSet(pub HashSet<IpAddr>);
+
+    impl Prober for AliveSet {
+        fn probe(
+            &self,
+            dst: IpAddr,
+            _seq: u16,
+            _timeout: Duration,
+        ) -> Result<ProbeReply, ProbeError> {
+            if self.0.contains(&dst) {
+                Ok(ProbeReply {
+                    responder: dst,
+                    rtt: Duration::from_millis(1),
+                    ttl: None,
+                })
+            } else {
+                Err(ProbeError::Timeout)
+            }
+        }
+    }
+
+    pub(crate) fn v4(last: u8) -> IpAddr {
+        IpAddr::V4(Ipv4Addr::new(192, 0, 2, last))
+    }
+
+    /// PTR records of 192.0.2.1 and 192.0.2.2
+    struct Ptr;
+
+    impl ReverseLookup for Ptr {
+        fn reverse(
+            &self,
+            ip: IpAddr,
+            _token: &CancellationToken,
+        ) -> Result<Option<String>, crate::dns::DnsError> {
+            Ok(match ip {
+                ip if ip == v4(1) => Some("router.home.test".to_string()),
+                ip if ip == v4(2) => Some("nas.home.test".to_string()),
+                _ => None,
+            })
+        }
+    }
+
+    #[test]
+    fn alive_hosts_get_hostnames() {
+        let prober = AliveSet([v4(1), v4(3)].into_iter().collect());
+        let mut setting = HostScanSetting {
+            targets: (1..=4).map(v4).collect(),
+            ..Default::default()
+        };
+        let token = CancellationToken::new();
+        let mut result = host_scan(&prober, &setting, &token);
+        assert_eq!(
+            resolve_hostnames(&Ptr, &mut result, &setting, &token, |_| {}),
+            0
+        );
+
+        setting.reverse_dns = true;
+        let events = Mutex::new(Vec::new());
+        let resolved = resolve_hostnames(&Ptr, &mut result, &setting, &token, |h| {
+            events.lock().unwrap().push((h.ip, h.hostname.clone()))
+        });
+        assert_eq!(resolved, 1);
+        let names: Vec<Option<&str>> = result.hosts.iter().map(|h| h.hostname.as_deref()).collect();
+        // 192.0.2.2 has a PTR record but is not alive
+        assert_eq!(names, vec![Some("router.home.test"), None, None, None]);
+        assert_eq!(
+            events.into_inner().unwrap(),
+            vec![(v4(1), Some("router.home.test".to_string()))]
+        );
+    }
+
+    #[test]
+    fn streamed_hosts_get_a_hostname_record() {
+        let path = std::env::temp_dir().join(format!("netdia-ptr-{}.ndjson", std::process::id()));
+        let setting = HostScanSetting {
+            targets: (1..=2).map(v4).collect(),
+            stream_to: Some(path.to_string_lossy().into_owned()),
+            reverse_dns: true,
+            ..Default::default()
+        };
+        let prober = AliveSet([v4(1), v4(2)].into_iter().collect());
+        let token = CancellationToken::new();
+        let mut result = host_scan(&prober, &setting, &token);
+        let resolved = resolve_hostnames(&Ptr, &mut result, &setting, &token, |_| {});
+        let contents = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(resolved, 2);
+        assert_eq!(result.stream_error, None);
+        let lines: Vec<&str> = contents.lines().collect();
+        assert_eq!(lines.len(), 4);
+        assert!(lines[..2].iter().all(|l| l.ends_with("\"hostname\":null}")));
+        let mut followups: Vec<String> = lines[2..].iter().map(|l| l.to_string()).collect();
+        followups.sort();
+        let expected: Vec<String> = result.hosts.iter().map(host_json).collect();
+        assert_eq!(followups, expected);
+    }
+
+    /// Never answers, recording when each probe went out
+    #[derive(Default)]
+    struct SendTimes(Mutex<Vec<Instant>>);
+
+    impl Prober for SendTimes {
+        fn probe(
+            &self,
+            _dst: IpAddr,
+            _seq: u16,
+            _timeout: Duration,
+        ) -> Result<ProbeReply, ProbeError> {
+            self.0.lock().unwrap().push(Instant::now());
+            Err(ProbeError::Timeout)
+        }
+    }
+
+    #[test]
+    fn probes_to_a_host_are_spaced() {
+        let setting = HostScanSetting {
+            count: 3,
+            probe_interval_ms: 60,
+            ..Default::default()
+        };
+        let prober = SendTimes::default();
+        let host = probe_host(&prober, v4(1), &setting, &CancellationToken::new());
+        assert_eq!(host.state, HostState::Unreachable);
+        let sent = prober.0.into_inner().unwrap();
+        assert_eq!(sent.len(), 3);
+        for pair in sent.windows(2) {
+            assert!(pair[1] - pair[0] >= Duration::from_millis(60));
+        }
+
+        // Cancelling during the interval stops before the next probe
+        let prober = SendTimes::default();
+        let token = CancellationToken::new();
+        let started = Instant::now();
+        std::thread::scope(|s| {
+            s.spawn(|| {
+                std::thread::sleep(Duration::from_millis(20));
+                token.cancel();
+            });
+            let setting = HostScanSetting {
+                probe_interval_ms: 10_000,
+                ..setting.clone()
+            };
+            probe_host(&prober, v4(1), &setting, &token);
+        });
+        assert!(started.elapsed() < Duration::from_secs(2));
+        assert_eq!(prober.0.into_inner().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn scan_reports_alive_and_unreachable() {
+        let prober = AliveSet([v4(1), v4(3)].into_iter().collect());
+        let setting = HostScanSetting {
+            targets: (1..=4).map(v4).collect(),
+            ..Default::default()
+        };
+        let result = host_scan(&prober, &setting, &CancellationToken::new());
+        let alive: Vec<IpAddr> = result.alive().map(|h| h.ip).collect();
+        assert_eq!(alive, vec![v4(1), v4(3)]);
+        assert_eq!(result.unreachable().count(), 2);
+        assert!(!result.cancelled);
+        assert_eq!(result.dropped_packets, None);
+    }
+
+    #[test]
+    fn icmp_scan_finds_loopback_hosts() {
+        let setting = HostScanSetting {
+            targets: vec!["127.0.0.1".parse().unwrap(), "127.0.0.2".parse().unwrap()],
+            ..Default::default()
+        };
+        if setting.icmp_prober().check_family(false).is_err() {
+            // Neither kind of ICMP socket is allowed for this user
+            return;
+        }
+        let result = icmp_host_scan(&setting, &CancellationToken::new());
+        assert_eq!(result.alive().count(), 2);
+        assert!(result.family_errors.is_empty());
+    }
+
+    /// Drops the first probe to every host, as a rate-limiting router would
+    struct DropFirst(Mutex<HashSet<IpAddr>>);
+
+    impl Prober for DropFirst {
+        fn probe(
+            &self,
+            dst: IpAddr,
+            _seq: u16,
+            _timeout: Duration,
+        ) -> Result<ProbeReply, ProbeError> {
+            if self.0.lock().unwrap().insert(dst) {
+                return Err(ProbeError::Timeout);
+            }
+            Ok(ProbeReply {
+                responder: dst,
+                rtt: Duration::from_millis(1),
+                ttl: None,
+            })
+        }
+    }
+
+    #[test]
+    fn retry_pass_recovers_rate_limited_host() {
+        let setting = HostScanSetting {
+            targets: vec![v4(1), v4(2)],
+            ..Default::default()
+        };
+        let prober = DropFirst(Mutex::new(HashSet::new()));
+        let result = host_scan(&prober, &setting, &CancellationToken::new());
+        assert_eq!(result.alive().count(), 0);
+        assert_eq!(result.recovered_on_retry, 0);
+
+        let setting = HostScanSetting {
+            retry: Some(RetrySetting::default()),
+            ..setting
+        };
+        let prober = DropFirst(Mutex::new(HashSet::new()));
+        let result = host_scan(&prober, &setting, &CancellationToken::new());
+        assert_eq!(result.alive().count(), 2);
+        assert_eq!(result.recovered_on_retry, 2);
+        assert_eq!(result.hosts[0].ip, v4(1));
+    }
+
+    /// Holds every probe until `expected` are in flight at once, then
+    /// answers all but `silent`. Gives up waiting after 5s so a serial
+    /// caller fails instead of hanging.
+    struct Rendezvous {
+        expected: usize,
+        silent: IpAddr,
+        in_flight: Mutex<(usize, usize)>,
+        all_in: std::sync::Condvar,
+    }
+
+    impl Rendezvous {
+        fn new(expected: usize, silent: IpAddr) -> Rendezvous {
+            Rendezvous {
+                expected,
+                silent,
+                in_flight: Mutex::new((0, 0)),
+                all_in: std::sync::Condvar::new(),
+            }
+        }
+        /// Most probes seen in flight together
+        fn peak(&self) -> usize {
+            self.in_flight.lock().unwrap().1
+        }
+    }
+
+    impl Prober for Rendezvous {
+        fn probe(
+            &self,
+            dst: IpAddr,
+            _seq: u16,
+            _timeout: Duration,
+        ) -> Result<ProbeReply, ProbeError> {
+            let mut state = self.in_flight.lock().unwrap();
+            state.0 += 1;
+            state.1 = state.1.max(state.0);
+            self.all_in.notify_all();
+            let (mut state, _) = self
+                .all_in
+                .wait_timeout_while(state, Duration::from_secs(5), |s| s.1 < self.expected)
+                .unwrap();
+            state.0 -= 1;
+            if dst == self.silent {
+                return Err(ProbeError::Timeout);
+            }
+            Ok(ProbeReply {
+                responder: dst,
+                rtt: Duration::from_millis(5),
+                ttl: None,
+            })
+        }
+    }
+
+    /// Never answers, waiting out each probe's full timeout on `clock`
+    struct Unanswered<'a> {
+        clock: &'a ManualClock,
+        timeouts: Mutex<Vec<Duration>>,
+    }
+
+    impl Prober for Unanswered<'_> {
+        fn probe(
+            &self,
+            _dst: IpAddr,
+            _seq: u16,
+            timeout: Duration,
+        ) -> Result<ProbeReply, ProbeError> {
+            self.timeouts.lock().unwrap().push(timeout);
+            self.clock.advance(timeout);
+            Err(ProbeError::Timeout)
+        }
+    }
+
+    #[test]
+    fn slow_host_does_not_hold_slot_for_every_retry() {
+        let clock = ManualClock::new();
+        let prober = Unanswered {
+            clock: &clock,
+            timeouts: Mutex::new(Vec::new()),
+        };
+        let setting = HostScanSetting {
+            count: 5,
+            timeout_ms: 100,
+            host_budget_ms: Some(150),
+            ..Default::default()
+        };
+        let started = clock.now();
+        let host = probe_host_on(&prober, v4(1), &setting, &CancellationToken::new(), &clock);
+        assert_eq!(host.state, HostState::Unreachable);
+        // Unbounded, the host would take count * timeout = 500ms. The last
+        // probe is shortened to end with the budget.
+        assert_eq!(clock.now() - started, Duration::from_millis(150));
+        assert_eq!(
+            prober.timeouts.into_inner().unwrap(),
+            vec![Duration::from_millis(100), Duration::from_millis(50)]
+        );
+    }
+
+    #[test]
+    fn rate_limit_paces_probes() {
+        let prober = AliveSet((1..=6).map(v4).collect());
+        let setting = HostScanSetting {
+            targets: (1..=6).map(v4).collect(),
+            rate_limit_pps: Some(100),
+            ..Default::default()
+        };
+        let started = Instant::now();
+        let result = host_scan(&prober, &setting, &CancellationToken::new());
+        assert_eq!(result.alive().count(), 6);
+        assert!(started.elapsed() >= Duration::from_millis(50));
+    }
+
+    #[test]
+    fn quick_ports_reported_for_alive_hosts() {
+        let listener = std::net::TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        let open = listener.local_addr().unwrap().port();
+        let closed = {
+            let l = std::net::TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+            l.local_addr().unwrap().port()
+        };
+        let localhost = IpAddr::V4(Ipv4Addr::LOCALHOST);
+        let prober = AliveSet([localhost].into_iter().collect());
+        let setting = HostScanSetting {
+            targets: vec![localhost, v4(9)],
+            quick_ports: vec![closed, open],
+            ..Default::default()
+        };
+        let result = host_scan(&prober, &setting, &CancellationToken::new());
+        assert_eq!(result.hosts[0].open_ports, vec![open]);
+        // Unreachable hosts are not port-checked
+        assert!(result.hosts[1].open_ports.is_empty());
+    }
+
+    #[test]
+    fn quick_recheck_reports_small_set_promptly() {
+        let prober = Rendezvous::new(4, v4(3));
+        let hosts = quick_recheck(
+            &prober,
+            &[v4(1), v4(2), v4(3), v4(4)],
+            &HostScanSetting::default(),
+            &CancellationToken::new(),
+        );
+        // Probes are concurrent, so the wait is one timeout, not the sum
+        assert_eq!(prober.peak(), 4);
+        let states: Vec<HostState> = hosts.iter().map(|h| h.state).collect();
+        use HostState::*;
+        assert_eq!(states, vec![Alive, Alive, Unreachable, Alive]);
+        assert_eq!(hosts[0].rtt, Some(Duration::from_millis(5)));
+        assert_eq!(hosts[2].replies, 0);
+
+        let setting = HostScanSetting {
+            targets: vec![v4(1), v4(2)],
+            retry: Some(RetrySetting {
+                quick: true,
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        let prober = DropFirst(Mutex::new(HashSet::new()));
+        let result = host_scan(&prober, &setting, &CancellationToken::new());
+        assert_eq!(result.recovered_on_retry, 2);
+    }
+
+    /// Late replies to the probes of a scan with `count` probes per host
+    /// and its retry pass, still arriving for their sequence numbers
+    struct LateReplies {
+        count: u32,
+    }
+
+    impl Prober for LateReplies {
+        fn probe(
+            &self,
+            dst: IpAddr,
+            seq: u16,
+            _timeout: Duration,
+        ) -> Result<ProbeReply, ProbeError> {
+            if (seq as u32) >= 2 * self.count {
+                return Err(ProbeError::Timeout);
+            }
+            Ok(ProbeReply {
+                responder: dst,
+                rtt: Duration::from_millis(1),
+                ttl: None,
+            })
+        }
+    }
+
+    #[test]
+    fn quick_recheck_ignores_late_scan_replies() {
+        let setting = HostScanSetting {
+            count: 2,
+            ..Default::default()
+        };
+        let prober = LateReplies { count: 2 };
+        let hosts = quick_recheck(&prober, &[v4(1)], &setting, &CancellationToken::new());
+        assert_eq!(hosts[0].state, HostState::Unreachable);
+        assert_eq!(hosts[0].replies, 0);
+    }
+
+    /// Answers IPv4 targets; IPv6 is disabled
+    struct NoIpv6;
+
+    impl Prober for NoIpv6 {
+        fn probe(
+            &self,
+            dst: IpAddr,
+            _seq: u16,
+            _timeout: Duration,
+        ) -> Result<ProbeReply, ProbeError> {
+            assert!(dst.is_ipv4(), "probed {}", dst);
+            Ok(ProbeReply {
+                responder: dst,
+                rtt: Duration::from_millis(1),
+                ttl: None,
+            })
+        }
+        fn check_family(&self, ipv6: bool) -> std::io::Result<()> {
+            if ipv6 {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::Unsupported,
+                    "Address family not supported by protocol",
+                ));
+            }
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn disabled_ipv6_downgrades_only_v6_targets() {
+        let v6: IpAddr = "2001:db8::1".parse().unwrap();
+        let setting = HostScanSetting {
+            targets: vec![v4(1), v6, v4(2)],
+            retry: Some(RetrySetting::default()),
+            ..Default::default()
+        };
+        let result = host_scan(&NoIpv6, &setting, &CancellationToken::new());
+        let states: Vec<HostState> = result.hosts.iter().map(|h| h.state).collect();
+        use HostState::*;
+        assert_eq!(states, vec![Alive, Unavailable, Alive]);
+        assert_eq!(
+            result.family_errors,
+            vec!["IPv6 unavailable on this host: Address family not supported by protocol"]
+        );
+        assert_eq!(result.unavailable().count(), 1);
+
+        let result = host_scan(
+            &NoIpv6,
+            &HostScanSetting {
+                targets: vec![v4(1)],
+                ..Default::default()
+            },
+            &CancellationToken::new(),
+        );
+        assert!(result.family_errors.is_empty());
+    }
+
+    /// Port prober recording every connect; every port refuses
+    struct RefusingPorts(Mutex<Vec<IpAddr>>);
+
+    impl PortProber for RefusingPorts {
+        fn probe_port(&self, ip: IpAddr, port: u16, _timeout: Duration) -> PortProbe {
+            self.0.lock().unwrap().push(ip);
+            PortProbe {
+                port,
+                state: PortState::Closed,
+                connect_time: None,
+                error: None,
+            }
+        }
+    }
+
+    /// Port prober whose first connect to each host gets no answer; later
+    /// connects are refused
+    struct LateRefusal(Mutex<HashSet<IpAddr>>);
+
+    impl PortProber for LateRefusal {
+        fn probe_port(&self, ip: IpAddr, port: u16, _timeout: Duration) -> PortProbe {
+            let first = self.0.lock().unwrap().insert(ip);
+            PortProbe {
+                port,
+                state: if first {
+                    PortState::Filtered
+                } else {
+                    PortState::Closed
+                },
+                connect_time: None,
+                error: None,
+            }
+        }
+    }
+
+    #[test]
+    fn quick_retry_keeps_method_and_threshold() {
+        let quick = Some(RetrySetting {
+            quick: true,
+            ..Default::default()
+        });
+        let setting = HostScanSetting {
+            targets: vec![v4(1)],
+            discovery: DiscoveryMethod::Tcp,
+            discovery_ports: vec![443],
+            retry: quick.clone(),
+            ..Default::default()
+        };
+        let silent = AliveSet(HashSet::new());
+        let ports = LateRefusal(Mutex::new(HashSet::new()));
+        let result = host_scan_with_ports(&silent, &ports, &setting, &CancellationToken::new());
+        assert_eq!(result.recovered_on_retry, 1);
+        assert_eq!(result.hosts[0].detected_by, Some(Detection::Tcp));
+
+        // One reply in the first pass is not enough; the retry must get two
+        let setting = HostScanSetting {
+            targets: vec![v4(1)],
+            count: 2,
+            require_replies: 2,
+            retry: quick,
+            ..Default::default()
+        };
+        let prober = DropFirst(Mutex::new(HashSet::new()));
+        let result = host_scan(&prober, &setting, &CancellationToken::new());
+        assert_eq!(result.recovered_on_retry, 1);
+        assert_eq!(result.hosts[0].replies, 2);
+    }
+
+    #[test]
+    fn icmp_first_skips_tcp_for_icmp_responders() {
+        let prober = AliveSet([v4(1), v4(2)].into_iter().collect());
+        let ports = RefusingPorts(Mutex::new(Vec::new()));
+        let setting = HostScanSetting {
+            targets: (1..=3).map(v4).collect(),
+            discovery: DiscoveryMethod::Both,
+            discovery_order: DiscoveryOrder::IcmpFirst,
+            discovery_ports: vec![443],
+            ..Default::default()
+        };
+        let result = host_scan_with_ports(&prober, &ports, &setting, &CancellationToken::new());
+        assert_eq!(*ports.0.lock().unwrap(), vec![v4(3)]);
+        let detected: Vec<Option<Detection>> = result.hosts.iter().map(|h| h.detected_by).collect();
+        use Detection::*;
+        assert_eq!(detected, vec![Some(Icmp), Some(Icmp), Some(Tcp)]);
+        assert_eq!(result.alive().count(), 3);
+
+        // Running both probes every host
+        let ports = RefusingPorts(Mutex::new(Vec::new()));
+        let setting = HostScanSetting {
+            discovery_order: DiscoveryOrder::All,
+            ..setting
+        };
+        host_scan_with_ports(&prober, &ports, &setting, &CancellationToken::new());
+        assert_eq!(ports.0.lock().unwrap().len(), 3);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn repeated_tcp_scans_do_not_leak_descriptors() {
+        use crate::socket::open_fd_count;
+        let listener = std::net::TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        listener.set_nonblocking(true).unwrap();
+        let localhost = IpAddr::V4(Ipv4Addr::LOCALHOST);
+        let setting = HostScanSetting {
+            targets: vec![localhost],
+            discovery: DiscoveryMethod::Tcp,
+            discovery_ports: vec![listener.local_addr().unwrap().port()],
+            quick_ports: vec![listener.local_addr().unwrap().port()],
+            ..Default::default()
+        };
+        let token = CancellationToken::new();
+        let before = open_fd_count().unwrap();
+        for _ in 0..100 {
+            let result = host_scan(&AliveSet(HashSet::new()), &setting, &token);
+            assert_eq!(result.alive().count(), 1);
+            // Accept and drop what the scan connected, as a server would
+            while listener.accept().is_ok() {}
+        }
+        // Other tests open sockets concurrently, so allow some slack
+        let after = open_fd_count().unwrap();
+        assert!(after < before + 32, "{} -> {}", before, after);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn tcp_discovery_resets_its_connections() {
+        use std::io::Read;
+        let listener = std::net::TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        let setting = HostScanSetting {
+            targets: vec![IpAddr::V4(Ipv4Addr::LOCALHOST)],
+            discovery: DiscoveryMethod::Tcp,
+            discovery_ports: vec![listener.local_addr().unwrap().port()],
+            ..Default::default()
+        };
+        let result = host_scan(
+            &AliveSet(HashSet::new()),
+            &setting,
+            &CancellationToken::new(),
+        );
+        assert_eq!(result.alive().count(), 1);
+        // Closed with a reset, not left to TIME_WAIT by an orderly shutdown
+        let (mut conn, _) = listener.accept().unwrap();
+        conn.set_read_timeout(Some(Duration::from_secs(1))).unwrap();
+        let read = conn.read(&mut [0u8; 1]).map_err(|e| e.kind());
+        assert_eq!(read, Err(std::io::ErrorKind::ConnectionReset));
+    }
+
+    /// Answers only the probe with sequence number 1
+    struct OneStray;
+
+    impl Prober for OneStray {
+        fn probe(
+            &self,
+            dst: IpAddr,
+            seq: u16,
+            _timeout: Duration,
+        ) -> Result<ProbeReply, ProbeError> {
+            if seq != 1 {
+                return Err(ProbeError::Timeout);
+            }
+            Ok(ProbeReply {
+                responder: dst,
+                rtt: Duration::from_millis(1),
+                ttl: None,
+            })
+        }
+    }
+
+    #[test]
+    fn reply_threshold() {
+        let mut setting = HostScanSetting {
+            targets: vec![v4(1)],
+            count: 4,
+            ..Default::default()
+        };
+        let result = host_scan(&OneStray, &setting, &CancellationToken::new());
+        assert_eq!(result.hosts[0].state, HostState::Alive);
+        assert_eq!(result.hosts[0].replies, 1);
+
+        setting.require_replies = 2;
+        let result = host_scan(&OneStray, &setting, &CancellationToken::new());
+        assert_eq!(result.hosts[0].state, HostState::Unreachable);
+        assert_eq!(result.hosts[0].replies, 1);
+        assert_eq!(result.hosts[0].rtt, None);
+
+        let prober = AliveSet([v4(1)].into_iter().collect());
+        let result = host_scan(&prober, &setting, &CancellationToken::new());
+        assert_eq!(result.hosts[0].state, HostState::Alive);
+        // Stops as soon as the threshold is met
+        assert_eq!(result.hosts[0].replies, 2);
+    }
+}
@@ -0,0 +1,92 @@
+use super::host::{probe_host, HostState};
+use super::port::{PortProber, PortState};
+use super::HostScanSetting;
+use crate::cancel::CancellationToken;
+use crate::ping::Prober;
+use crate::pool::map_concurrent;
+use std::net::IpAddr;
+use std::time::Duration;
+
+/// Liveness and single-port reachability of one host
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct KnockResult {
+    pub ip: IpAddr,
+    pub alive: bool,
+    pub rtt: Option<Duration>,
+    pub port_state: PortState,
+}
+
+/// Ping sweep that also checks `port` on every target in one pass.
+///
+/// The port is checked on unreachable hosts too, since a host may drop ICMP
+/// while still serving the port.
+pub fn knock_sweep<P: Prober, Q: PortProber>(
+    prober: &P,
+    port_prober: &Q,
+    setting: &HostScanSetting,
+    port: u16,
+    token: &CancellationToken,
+) -> Vec<KnockResult> {
+    let timeout = Duration::from_millis(setting.timeout_ms);
+    map_concurrent(&setting.targets, setting.concurrency, token, |ip| {
+        let host = probe_host(prober, *ip, setting, token);
+        let port_state = port_prober.probe_port(*ip, port, timeout).state;
+        KnockResult {
+            ip: *ip,
+            alive: host.state == HostState::Alive,
+            rtt: host.rtt,
+            port_state,
+        }
+    })
+    .into_iter()
+    .flatten()
+    .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scan::host::tests::{v4, AliveSet};
+    use crate::scan::port::PortProbe;
+
+    struct StubPorts;
+
+    impl PortProber for StubPorts {
+        fn probe_port(&self, ip: IpAddr, port: u16, _timeout: Duration) -> PortProbe {
+            let state = match ip {
+                ip if ip == v4(1) || ip == v4(4) => PortState::Open,
+                ip if ip == v4(2) => PortState::Closed,
+                _ => PortState::Filtered,
+            };
+            PortProbe {
+                port,
+                state,
+                connect_time: None,
+                error: None,
+            }
+        }
+    }
+
+    #[test]
+    fn combines_liveness_and_port_state() {
+        let prober = AliveSet([v4(1), v4(2)].into_iter().collect());
+        let setting = HostScanSetting {
+            targets: (1..=4).map(v4).collect(),
+            ..Default::default()
+        };
+        let results = knock_sweep(&prober, &StubPorts, &setting, 22, &CancellationToken::new());
+        let summary: Vec<(IpAddr, bool, PortState)> = results
+            .iter()
+            .map(|r| (r.ip, r.alive, r.port_state))
+            .collect();
+        assert_eq!(
+            summary,
+            vec![
+                (v4(1), true, PortState::Open),
+                (v4(2), true, PortState::Closed),
+                (v4(3), false, PortState::Filtered),
+                (v4(4), false, PortState::Open),
+            ]
+        );
+    }
+}
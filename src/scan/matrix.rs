@@ -0,0 +1,104 @@
+//! Reachability of a chosen set of ports on one host
+use super::port::{PortProbe, PortProber, PortState};
+use crate::cancel::CancellationToken;
+use crate::pool::map_concurrent;
+use std::collections::HashSet;
+use std::net::IpAddr;
+use std::time::Duration;
+use ts_rs::TS;
+
+/// Ports of one matrix probed at once
+pub const MATRIX_CONCURRENCY: usize = 64;
+
+/// State and connect time of each requested port
+#[derive(Clone, Debug, PartialEq, Eq, TS)]
+pub struct PortMatrix {
+    pub ip: IpAddr,
+    /// Probed ports in request order, duplicates dropped
+    pub ports: Vec<PortProbe>,
+    /// Cancelled before every port was probed
+    pub cancelled: bool,
+}
+
+impl PortMatrix {
+    pub fn count(&self, state: PortState) -> usize {
+        self.ports.iter().filter(|p| p.state == state).count()
+    }
+    pub fn get(&self, port: u16) -> Option<&PortProbe> {
+        self.ports.iter().find(|p| p.port == port)
+    }
+}
+
+/// Probe the ports of `ports` on `ip`, up to [`MATRIX_CONCURRENCY`] at once
+/// and each with `timeout`, so a short list takes about one timeout
+pub fn port_matrix<Q: PortProber>(
+    prober: &Q,
+    ip: IpAddr,
+    ports: &[u16],
+    timeout: Duration,
+    token: &CancellationToken,
+) -> PortMatrix {
+    let mut seen = HashSet::new();
+    let unique: Vec<u16> = ports.iter().copied().filter(|p| seen.insert(*p)).collect();
+    let concurrency = unique.len().clamp(1, MATRIX_CONCURRENCY);
+    let probes = map_concurrent(&unique, concurrency, token, |port| {
+        prober.probe_port(ip, *port, timeout)
+    });
+    let cancelled = probes.iter().any(Option::is_none);
+    PortMatrix {
+        ip,
+        ports: probes.into_iter().flatten().collect(),
+        cancelled,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scan::port::TcpConnectProber;
+    use std::net::{Ipv4Addr, TcpListener};
+
+    fn closed_port() -> u16 {
+        let l = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        l.local_addr().unwrap().port()
+    }
+
+    #[test]
+    fn matrix_of_local_ports() {
+        let web = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        let ssh = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        let web = web.local_addr().unwrap().port();
+        let ssh = ssh.local_addr().unwrap().port();
+        let (closed_a, closed_b) = (closed_port(), closed_port());
+        let ip = IpAddr::V4(Ipv4Addr::LOCALHOST);
+        let matrix = port_matrix(
+            &TcpConnectProber::default(),
+            ip,
+            &[web, closed_a, ssh, closed_b, web],
+            Duration::from_secs(1),
+            &CancellationToken::new(),
+        );
+        assert!(!matrix.cancelled);
+        let order: Vec<u16> = matrix.ports.iter().map(|p| p.port).collect();
+        assert_eq!(order, vec![web, closed_a, ssh, closed_b]);
+        assert_eq!(matrix.count(PortState::Open), 2);
+        assert_eq!(matrix.count(PortState::Closed), 2);
+        assert!(matrix.get(web).unwrap().connect_time.is_some());
+        let closed = matrix.get(closed_b).unwrap();
+        assert_eq!(
+            (closed.state, closed.connect_time),
+            (PortState::Closed, None)
+        );
+
+        let token = CancellationToken::new();
+        token.cancel();
+        let cancelled = port_matrix(
+            &TcpConnectProber::default(),
+            ip,
+            &[web],
+            Duration::from_secs(1),
+            &token,
+        );
+        assert!(cancelled.cancelled && cancelled.ports.is_empty());
+    }
+}
@@ -0,0 +1,21 @@
+//! Host scan
+pub mod arp;
+pub mod guard;
+pub mod host;
+pub mod knock;
+pub mod matrix;
+pub mod neighbor;
+pub mod port;
+pub mod portspec;
+pub mod profile;
+pub mod router;
+pub mod service;
+pub mod setting;
+pub mod stream;
+pub mod utilization;
+
+pub use host::{
+    host_scan, host_scan_with_ports, icmp_host_scan, quick_recheck, resolve_hostnames, Detection,
+    Host, HostScanResult, HostState,
+};
+pub use setting::{DiscoveryMethod, DiscoveryOrder, HostScanSetting, RetrySetting, ScanIntensity};
@@ -0,0 +1,207 @@
+//! Scan of the hosts on an interface's own subnet
+use super::guard::{expand_targets, GuardThresholds, TargetGuardError};
+use super::{host_scan, HostScanResult, HostScanSetting};
+use crate::cancel::CancellationToken;
+use crate::net::interface::Interface;
+use crate::ping::Prober;
+use std::fmt;
+use std::net::IpAddr;
+use ts_rs::TS;
+
+pub const MAX_CONCURRENCY: usize = 1024;
+pub const MIN_TIMEOUT_MS: u64 = 10;
+pub const MAX_TIMEOUT_MS: u64 = 10_000;
+pub const MAX_COUNT: u32 = 10;
+
+/// User overrides of `HostScanSetting::neighbor_scan_default`. Fields left
+/// `None` keep the default.
+#[derive(Clone, Debug, Default, PartialEq, Eq, TS)]
+pub struct NeighborScanOverrides {
+    pub concurrency: Option<usize>,
+    #[ts(type = "number | null")]
+    pub timeout_ms: Option<u64>,
+    pub count: Option<u32>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum NeighborScanError {
+    Concurrency(usize),
+    Timeout(u64),
+    Count(u32),
+    /// The interface has no IPv4 subnet to sweep
+    NoSubnet(String),
+    /// The subnet is too large to sweep without confirmation, or at all
+    Guard(TargetGuardError),
+}
+
+impl fmt::Display for NeighborScanError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            NeighborScanError::Concurrency(n) => {
+                write!(f, "Concurrency must be 1 to {}, got {}", MAX_CONCURRENCY, n)
+            }
+            NeighborScanError::Timeout(ms) => write!(
+                f,
+                "Timeout must be {} to {} ms, got {}",
+                MIN_TIMEOUT_MS, MAX_TIMEOUT_MS, ms
+            ),
+            NeighborScanError::Count(n) => {
+                write!(f, "Probe count must be 1 to {}, got {}", MAX_COUNT, n)
+            }
+            NeighborScanError::NoSubnet(iface) => {
+                write!(f, "Interface {} has no IPv4 subnet", iface)
+            }
+            NeighborScanError::Guard(e) => e.fmt(f),
+        }
+    }
+}
+
+impl std::error::Error for NeighborScanError {}
+
+impl NeighborScanOverrides {
+    pub fn validate(&self) -> Result<(), NeighborScanError> {
+        if let Some(n) = self
+            .concurrency
+            .filter(|n| !(1..=MAX_CONCURRENCY).contains(n))
+        {
+            return Err(NeighborScanError::Concurrency(n));
+        }
+        if let Some(ms) = self
+            .timeout_ms
+            .filter(|ms| !(MIN_TIMEOUT_MS..=MAX_TIMEOUT_MS).contains(ms))
+        {
+            return Err(NeighborScanError::Timeout(ms));
+        }
+        if let Some(n) = self.count.filter(|n| !(1..=MAX_COUNT).contains(n)) {
+            return Err(NeighborScanError::Count(n));
+        }
+        Ok(())
+    }
+    /// Neighbor scan default for `targets` with these overrides applied
+    pub fn apply(&self, targets: Vec<IpAddr>) -> Result<HostScanSetting, NeighborScanError> {
+        self.validate()?;
+        let base = HostScanSetting::neighbor_scan_default(targets);
+        Ok(HostScanSetting {
+            concurrency: self.concurrency.unwrap_or(base.concurrency),
+            timeout_ms: self.timeout_ms.unwrap_or(base.timeout_ms),
+            count: self.count.unwrap_or(base.count),
+            ..base
+        })
+    }
+}
+
+/// Hosts of the first IPv4 subnet of `iface`, without the network and
+/// broadcast addresses and the interface's own address. The subnet is
+/// expanded through [`expand_targets`], so a large one needs `confirmed`.
+pub fn neighbor_targets(
+    iface: &Interface,
+    t: &GuardThresholds,
+    confirmed: bool,
+) -> Result<Vec<IpAddr>, NeighborScanError> {
+    let net = iface
+        .addrs
+        .iter()
+        .find(|n| n.addr.is_ipv4())
+        .ok_or_else(|| NeighborScanError::NoSubnet(iface.name.clone()))?;
+    let (network, last) = (net.network(), net.last());
+    let has_broadcast = net.prefix_len < 31;
+    let mut targets = expand_targets(std::slice::from_ref(net), t, confirmed)
+        .map_err(NeighborScanError::Guard)?;
+    targets.retain(|ip| *ip != net.addr && (!has_broadcast || (*ip != network && *ip != last)));
+    Ok(targets)
+}
+
+/// Scan the subnet of `iface` with the neighbor defaults and `overrides`
+pub fn neighbor_scan<P: Prober>(
+    prober: &P,
+    iface: &Interface,
+    overrides: &NeighborScanOverrides,
+    t: &GuardThresholds,
+    confirmed: bool,
+    token: &CancellationToken,
+) -> Result<HostScanResult, NeighborScanError> {
+    let targets = neighbor_targets(iface, t, confirmed)?;
+    let setting = overrides.apply(targets)?;
+    Ok(host_scan(prober, &setting, token))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::net::ipnet::IpNet;
+
+    #[test]
+    fn overrides_reach_the_setting() {
+        let targets = vec!["192.168.1.2".parse().unwrap()];
+        let defaults = NeighborScanOverrides::default()
+            .apply(targets.clone())
+            .unwrap();
+        assert_eq!(
+            defaults,
+            HostScanSetting::neighbor_scan_default(targets.clone())
+        );
+
+        let overrides = NeighborScanOverrides {
+            concurrency: Some(512),
+            timeout_ms: Some(2000),
+            count: Some(3),
+        };
+        let setting = overrides.apply(targets.clone()).unwrap();
+        assert_eq!(
+            (setting.concurrency, setting.timeout_ms, setting.count),
+            (512, 2000, 3)
+        );
+        assert_eq!(setting.targets, targets);
+
+        let bad = NeighborScanOverrides {
+            timeout_ms: Some(0),
+            ..Default::default()
+        };
+        assert_eq!(bad.apply(targets), Err(NeighborScanError::Timeout(0)));
+    }
+
+    #[test]
+    fn targets_skip_own_network_and_broadcast() {
+        let mut iface = Interface::new(2, "eth0");
+        iface.addrs = vec![IpNet::new("192.168.1.5".parse().unwrap(), 29)];
+        let t = GuardThresholds::default();
+        let targets: Vec<String> = neighbor_targets(&iface, &t, false)
+            .unwrap()
+            .iter()
+            .map(|ip| ip.to_string())
+            .collect();
+        assert_eq!(
+            targets,
+            [
+                "192.168.1.1",
+                "192.168.1.2",
+                "192.168.1.3",
+                "192.168.1.4",
+                "192.168.1.6"
+            ]
+        );
+        assert_eq!(
+            neighbor_targets(&Interface::new(3, "wg0"), &t, false),
+            Err(NeighborScanError::NoSubnet("wg0".to_string()))
+        );
+
+        // A /15 needs confirmation like any other large target list
+        iface.addrs = vec![IpNet::new("10.1.2.3".parse().unwrap(), 15)];
+        assert!(matches!(
+            neighbor_targets(&iface, &t, false),
+            Err(NeighborScanError::Guard(
+                TargetGuardError::NeedsConfirmation { .. }
+            ))
+        ));
+        let t = GuardThresholds {
+            max_target_count: 1024,
+            ..t
+        };
+        assert!(matches!(
+            neighbor_targets(&iface, &t, true),
+            Err(NeighborScanError::Guard(
+                TargetGuardError::TooManyTargets { .. }
+            ))
+        ));
+    }
+}
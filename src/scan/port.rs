@@ -0,0 +1,133 @@
+use crate::socket::{close_now, connect_from, unspecified_for};
+use std::io;
+use std::net::{IpAddr, SocketAddr, TcpStream};
+use std::time::{Duration, Instant};
+use ts_rs::TS;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, TS)]
+pub enum PortState {
+    Open,
+    Closed,
+    Filtered,
+    /// The probe never went out, e.g. its source port is taken. See
+    /// [`PortProbe::error`].
+    Error,
+}
+
+/// Result of probing a single port
+#[derive(Clone, Debug, PartialEq, Eq, TS)]
+pub struct PortProbe {
+    pub port: u16,
+    pub state: PortState,
+    /// Connect time for open ports
+    #[ts(type = "Duration | null")]
+    pub connect_time: Option<Duration>,
+    /// Local error of a probe in [`PortState::Error`]
+    pub error: Option<String>,
+}
+
+/// Checks whether a TCP port accepts connections
+pub trait PortProber: Sync {
+    fn probe_port(&self, ip: IpAddr, port: u16, timeout: Duration) -> PortProbe;
+}
+
+/// Probes ports with a full TCP connect, closing the connection once
+/// established
+#[derive(Clone, Copy, Debug, Default)]
+pub struct TcpConnectProber {
+    /// Local port to connect from. Chosen by the OS when `None`.
+    pub source_port: Option<u16>,
+    /// Close with a reset (`SO_LINGER` 0) instead of an orderly shutdown, so
+    /// many connects do not leave ports in TIME_WAIT. The peer sees an
+    /// aborted connection.
+    pub reset_on_close: bool,
+}
+
+impl PortProber for TcpConnectProber {
+    fn probe_port(&self, ip: IpAddr, port: u16, timeout: Duration) -> PortProbe {
+        let dst = SocketAddr::new(ip, port);
+        let start = Instant::now();
+        let result = match self.source_port {
+            Some(src_port) => connect_from(unspecified_for(dst, src_port), dst, timeout),
+            None => TcpStream::connect_timeout(&dst, timeout),
+        };
+        let elapsed = start.elapsed();
+        let (state, error) = match result {
+            Ok(stream) => {
+                if self.reset_on_close {
+                    close_now(stream);
+                }
+                (PortState::Open, None)
+            }
+            Err(e) => match state_from_error(e) {
+                Ok(state) => (state, None),
+                Err(e) => (PortState::Error, Some(e.to_string())),
+            },
+        };
+        PortProbe {
+            port,
+            state,
+            connect_time: (state == PortState::Open).then_some(elapsed),
+            error,
+        }
+    }
+}
+
+/// Map a connect error to a port state. Errors of the local socket, such as
+/// a source port in use, say nothing about the port and are returned.
+pub fn state_from_error(e: io::Error) -> io::Result<PortState> {
+    match e.kind() {
+        io::ErrorKind::ConnectionRefused | io::ErrorKind::ConnectionReset => Ok(PortState::Closed),
+        io::ErrorKind::AddrInUse
+        | io::ErrorKind::AddrNotAvailable
+        | io::ErrorKind::InvalidInput => Err(e),
+        _ => Ok(PortState::Filtered),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::{Ipv4Addr, TcpListener};
+
+    #[test]
+    fn local_open_and_closed_ports() {
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        let open = listener.local_addr().unwrap().port();
+        let closed = {
+            let l = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+            l.local_addr().unwrap().port()
+        };
+        let ip = IpAddr::V4(Ipv4Addr::LOCALHOST);
+        let timeout = Duration::from_secs(1);
+        let probe = TcpConnectProber::default().probe_port(ip, open, timeout);
+        assert_eq!(probe.state, PortState::Open);
+        assert!(probe.connect_time.is_some());
+        assert_eq!(
+            TcpConnectProber::default()
+                .probe_port(ip, closed, timeout)
+                .state,
+            PortState::Closed
+        );
+    }
+
+    #[test]
+    fn taken_source_port_is_an_error() {
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        let held = listener.local_addr().unwrap().port();
+        let prober = TcpConnectProber {
+            source_port: Some(held),
+            ..Default::default()
+        };
+        let probe = prober.probe_port(
+            IpAddr::V4(Ipv4Addr::LOCALHOST),
+            held,
+            Duration::from_secs(1),
+        );
+        assert_eq!(probe.state, PortState::Error);
+        assert_eq!(
+            probe.error.as_deref(),
+            Some(format!("Source port {} is already in use", held).as_str())
+        );
+    }
+}
@@ -0,0 +1,139 @@
+use super::service::{port_by_name, top_ports, Transport};
+use std::collections::BTreeSet;
+use std::fmt;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum PortSpecError {
+    Empty,
+    InvalidPort(String),
+    InvalidRange(String),
+    UnknownService(String),
+    /// `top-N` asked for more ports than the bundled list has
+    TopTooLarge {
+        requested: usize,
+        available: usize,
+    },
+}
+
+impl fmt::Display for PortSpecError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PortSpecError::Empty => write!(f, "No ports given"),
+            PortSpecError::InvalidPort(s) => write!(f, "Invalid port: {}", s),
+            PortSpecError::InvalidRange(s) => write!(f, "Invalid port range: {}", s),
+            PortSpecError::UnknownService(s) => write!(f, "Unknown service: {}", s),
+            PortSpecError::TopTooLarge {
+                requested,
+                available,
+            } => write!(
+                f,
+                "Only {} top ports are known, {} requested",
+                available, requested
+            ),
+        }
+    }
+}
+
+impl std::error::Error for PortSpecError {}
+
+fn parse_port(s: &str) -> Result<u16, PortSpecError> {
+    match s.parse::<u16>() {
+        Ok(port) if port > 0 => Ok(port),
+        _ => Err(PortSpecError::InvalidPort(s.to_string())),
+    }
+}
+
+/// Parse a comma separated port list into sorted, unique ports.
+///
+/// Each item is a port (`80`), an inclusive range (`1-1024`), a service name
+/// from the bundled table (`ssh`) or `top-N` for the N most common ports.
+pub fn parse_ports(spec: &str, transport: Transport) -> Result<Vec<u16>, PortSpecError> {
+    let mut ports = BTreeSet::new();
+    for item in spec.split(',').map(str::trim).filter(|s| !s.is_empty()) {
+        if let Some(n) = item.strip_prefix("top-") {
+            let requested: usize = n
+                .parse()
+                .map_err(|_| PortSpecError::InvalidPort(item.to_string()))?;
+            let top = top_ports(transport);
+            if requested > top.len() {
+                return Err(PortSpecError::TopTooLarge {
+                    requested,
+                    available: top.len(),
+                });
+            }
+            ports.extend(&top[..requested]);
+        } else if let Some((start, end)) = item.split_once('-') {
+            let invalid = || PortSpecError::InvalidRange(item.to_string());
+            let start = parse_port(start.trim()).map_err(|_| invalid())?;
+            let end = parse_port(end.trim()).map_err(|_| invalid())?;
+            if start > end {
+                return Err(invalid());
+            }
+            ports.extend(start..=end);
+        } else if item.bytes().all(|b| b.is_ascii_digit()) {
+            ports.insert(parse_port(item)?);
+        } else {
+            let port = port_by_name(item, transport)
+                .ok_or_else(|| PortSpecError::UnknownService(item.to_string()))?;
+            ports.insert(port);
+        }
+    }
+    if ports.is_empty() {
+        return Err(PortSpecError::Empty);
+    }
+    Ok(ports.into_iter().collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tcp(spec: &str) -> Result<Vec<u16>, PortSpecError> {
+        parse_ports(spec, Transport::Tcp)
+    }
+
+    #[test]
+    fn singles_ranges_and_names() {
+        assert_eq!(tcp("443, 22,80").unwrap(), vec![22, 80, 443]);
+        assert_eq!(tcp("20-23").unwrap(), vec![20, 21, 22, 23]);
+        assert_eq!(tcp("http,https,ssh").unwrap(), vec![22, 80, 443]);
+        assert_eq!(tcp("ssh,20-23,22").unwrap(), vec![20, 21, 22, 23]);
+        assert_eq!(parse_ports("snmp", Transport::Udp).unwrap(), vec![161]);
+    }
+
+    #[test]
+    fn top_n() {
+        assert_eq!(tcp("top-3").unwrap(), vec![23, 80, 443]);
+        assert_eq!(tcp("top-100").unwrap().len(), 100);
+        assert_eq!(tcp("top-2,22").unwrap(), vec![22, 23, 80]);
+    }
+
+    #[test]
+    fn invalid_input() {
+        assert_eq!(tcp(""), Err(PortSpecError::Empty));
+        assert_eq!(tcp("0"), Err(PortSpecError::InvalidPort("0".into())));
+        assert_eq!(
+            tcp("65536"),
+            Err(PortSpecError::InvalidPort("65536".into()))
+        );
+        assert_eq!(
+            tcp("90-80"),
+            Err(PortSpecError::InvalidRange("90-80".into()))
+        );
+        assert_eq!(
+            tcp("1-70000"),
+            Err(PortSpecError::InvalidRange("1-70000".into()))
+        );
+        assert_eq!(
+            tcp("gopherx"),
+            Err(PortSpecError::UnknownService("gopherx".into()))
+        );
+        assert!(matches!(
+            tcp("top-5000"),
+            Err(PortSpecError::TopTooLarge {
+                requested: 5000,
+                ..
+            })
+        ));
+    }
+}
@@ -0,0 +1,452 @@
+//! Named host scan settings saved for re-use.
+//!
+//! Profiles are `key = value` text files, one per profile, in a directory
+//! chosen by the caller (the app config dir). Missing keys take their
+//! default, so profiles written before a setting existed still load; keys
+//! this version does not know are skipped and reported.
+use super::setting::{DiscoveryMethod, DiscoveryOrder, HostScanSetting, RetrySetting};
+use crate::progress::ProgressSetting;
+use std::fmt::{self, Write as _};
+use std::io;
+use std::net::IpAddr;
+use std::path::PathBuf;
+use std::str::FromStr;
+
+/// Format version written to new profiles. Version 2 quotes `stream_to`.
+pub const PROFILE_VERSION: u32 = 2;
+const EXTENSION: &str = "profile";
+
+#[derive(Debug)]
+pub enum ProfileError {
+    /// Names may only use letters, digits, space, `-` and `_`
+    InvalidName(String),
+    NotFound(String),
+    /// Value that does not parse, with its 1-based line number
+    Parse {
+        line: usize,
+        message: String,
+    },
+    /// Written by a newer version with an incompatible format
+    Version(u32),
+    Io(io::Error),
+}
+
+impl fmt::Display for ProfileError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ProfileError::InvalidName(name) => write!(f, "Invalid profile name: {:?}", name),
+            ProfileError::NotFound(name) => write!(f, "No profile named {:?}", name),
+            ProfileError::Parse { line, message } => write!(f, "Line {}: {}", line, message),
+            ProfileError::Version(v) => write!(f, "Unsupported profile version {}", v),
+            ProfileError::Io(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl std::error::Error for ProfileError {}
+
+impl From<io::Error> for ProfileError {
+    fn from(e: io::Error) -> ProfileError {
+        ProfileError::Io(e)
+    }
+}
+
+/// Profile read back from disk
+#[derive(Clone, Debug, PartialEq)]
+pub struct LoadedProfile {
+    pub setting: HostScanSetting,
+    /// Keys that were skipped because this version does not know them
+    pub ignored_keys: Vec<String>,
+}
+
+/// Profiles kept as files in one directory
+#[derive(Clone, Debug)]
+pub struct ProfileStore {
+    dir: PathBuf,
+}
+
+impl ProfileStore {
+    pub fn new(dir: impl Into<PathBuf>) -> ProfileStore {
+        ProfileStore { dir: dir.into() }
+    }
+    fn path(&self, name: &str) -> Result<PathBuf, ProfileError> {
+        let valid = !name.trim().is_empty()
+            && name
+                .chars()
+                .all(|c| c.is_alphanumeric() || matches!(c, ' ' | '-' | '_'));
+        if !valid {
+            return Err(ProfileError::InvalidName(name.to_string()));
+        }
+        Ok(self.dir.join(format!("{}.{}", name, EXTENSION)))
+    }
+    /// Save `setting` as `name`, replacing any profile of that name
+    pub fn save(&self, name: &str, setting: &HostScanSetting) -> Result<(), ProfileError> {
+        let path = self.path(name)?;
+        std::fs::create_dir_all(&self.dir)?;
+        // Write then rename so a crash never leaves half a profile
+        let tmp = path.with_extension("tmp");
+        std::fs::write(&tmp, to_profile_text(setting))?;
+        std::fs::rename(&tmp, &path)?;
+        Ok(())
+    }
+    pub fn load(&self, name: &str) -> Result<LoadedProfile, ProfileError> {
+        let path = self.path(name)?;
+        match std::fs::read_to_string(&path) {
+            Ok(text) => from_profile_text(&text),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                Err(ProfileError::NotFound(name.to_string()))
+            }
+            Err(e) => Err(e.into()),
+        }
+    }
+    /// Names of the saved profiles, sorted
+    pub fn list(&self) -> Result<Vec<String>, ProfileError> {
+        let entries = match std::fs::read_dir(&self.dir) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e.into()),
+        };
+        let mut names = Vec::new();
+        for entry in entries {
+            let path = entry?.path();
+            if path.extension().and_then(|e| e.to_str()) == Some(EXTENSION) {
+                if let Some(stem) = path.file_stem().and_then(|s| s.to_str()) {
+                    names.push(stem.to_string());
+                }
+            }
+        }
+        names.sort();
+        Ok(names)
+    }
+    pub fn delete(&self, name: &str) -> Result<(), ProfileError> {
+        match std::fs::remove_file(self.path(name)?) {
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                Err(ProfileError::NotFound(name.to_string()))
+            }
+            result => Ok(result?),
+        }
+    }
+}
+
+fn list<T: fmt::Display>(items: &[T]) -> String {
+    items
+        .iter()
+        .map(|i| i.to_string())
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+fn opt<T: fmt::Display>(value: &Option<T>) -> String {
+    value
+        .as_ref()
+        .map_or_else(|| "none".to_string(), |v| v.to_string())
+}
+
+/// Quoted `s`, so a value of "none", surrounding spaces and line breaks
+/// survive the round trip
+fn quote(s: &str) -> String {
+    let mut out = String::with_capacity(s.len() + 2);
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+/// Text form of `setting` as saved in a profile
+pub fn to_profile_text(s: &HostScanSetting) -> String {
+    // Listing every field makes a new setting fail to compile until it is
+    // saved here and loaded in `from_profile_text`
+    let HostScanSetting {
+        targets,
+        scope_id,
+        count,
+        require_replies,
+        timeout_ms,
+        concurrency,
+        rate_limit_pps,
+        probe_interval_ms,
+        host_budget_ms,
+        icmp_id,
+        icmp_seq,
+        recv_buffer_size,
+        progress:
+            ProgressSetting {
+                interval_ms: progress_interval_ms,
+                step_percent,
+            },
+        quick_ports,
+        retry,
+        stream_to,
+        discovery,
+        discovery_order,
+        discovery_ports,
+        reverse_dns,
+    } = s;
+    let mut out = String::new();
+    let mut put = |key: &str, value: String| {
+        let _ = writeln!(out, "{} = {}", key, value);
+    };
+    put("version", PROFILE_VERSION.to_string());
+    put("targets", list(targets));
+    put("scope_id", scope_id.to_string());
+    put("count", count.to_string());
+    put("require_replies", require_replies.to_string());
+    put("timeout_ms", timeout_ms.to_string());
+    put("concurrency", concurrency.to_string());
+    put("rate_limit_pps", opt(rate_limit_pps));
+    put("probe_interval_ms", probe_interval_ms.to_string());
+    put("host_budget_ms", opt(host_budget_ms));
+    put("icmp_id", opt(icmp_id));
+    put("icmp_seq", opt(icmp_seq));
+    put("recv_buffer_size", opt(recv_buffer_size));
+    put("progress.interval_ms", progress_interval_ms.to_string());
+    put("progress.step_percent", step_percent.to_string());
+    put("quick_ports", list(quick_ports));
+    put("retry", retry.is_some().to_string());
+    if let Some(RetrySetting {
+        timeout_ms,
+        concurrency,
+        quick,
+    }) = retry
+    {
+        put("retry.timeout_ms", timeout_ms.to_string());
+        put("retry.concurrency", concurrency.to_string());
+        put("retry.quick", quick.to_string());
+    }
+    put(
+        "stream_to",
+        stream_to
+            .as_deref()
+            .map_or_else(|| "none".to_string(), quote),
+    );
+    put("discovery", format!("{:?}", discovery));
+    put("discovery_order", format!("{:?}", discovery_order));
+    put("discovery_ports", list(discovery_ports));
+    put("reverse_dns", reverse_dns.to_string());
+    out
+}
+
+fn value<T: FromStr>(v: &str) -> Result<T, String> {
+    v.parse().map_err(|_| format!("Invalid value {:?}", v))
+}
+
+fn opt_value<T: FromStr>(v: &str) -> Result<Option<T>, String> {
+    match v {
+        "none" => Ok(None),
+        v => value(v).map(Some),
+    }
+}
+
+/// Value written by [`quote`]. Version 1 profiles wrote it bare.
+fn string_value(v: &str) -> Result<Option<String>, String> {
+    let Some(inner) = v.strip_prefix('"') else {
+        return Ok((v != "none").then(|| v.to_string()));
+    };
+    let inner = inner
+        .strip_suffix('"')
+        .ok_or_else(|| format!("Unterminated string {:?}", v))?;
+    let mut out = String::with_capacity(inner.len());
+    let mut chars = inner.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            out.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('"') => out.push('"'),
+            Some('\\') => out.push('\\'),
+            Some('n') => out.push('\n'),
+            Some('r') => out.push('\r'),
+            _ => return Err(format!("Invalid escape in {:?}", v)),
+        }
+    }
+    Ok(Some(out))
+}
+
+fn list_value<T: FromStr>(v: &str) -> Result<Vec<T>, String> {
+    v.split(',')
+        .map(str::trim)
+        .filter(|i| !i.is_empty())
+        .map(value)
+        .collect()
+}
+
+/// Parse a profile. Missing keys keep their `HostScanSetting::default()`.
+pub fn from_profile_text(text: &str) -> Result<LoadedProfile, ProfileError> {
+    let mut s = HostScanSetting::default();
+    let mut retry: Option<RetrySetting> = None;
+    let mut ignored_keys = Vec::new();
+    for (i, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let parse_error = |message: String| ProfileError::Parse {
+            line: i + 1,
+            message,
+        };
+        let (key, v) = line
+            .split_once('=')
+            .map(|(k, v)| (k.trim(), v.trim()))
+            .ok_or_else(|| parse_error("Expected key = value".to_string()))?;
+        let result: Result<(), String> = match key {
+            "version" => {
+                let version: u32 = value(v).map_err(parse_error)?;
+                if version > PROFILE_VERSION {
+                    return Err(ProfileError::Version(version));
+                }
+                Ok(())
+            }
+            "targets" => list_value::<IpAddr>(v).map(|t| s.targets = t),
+            "scope_id" => value(v).map(|x| s.scope_id = x),
+            "count" => value(v).map(|x| s.count = x),
+            "require_replies" => value(v).map(|x| s.require_replies = x),
+            "timeout_ms" => value(v).map(|x| s.timeout_ms = x),
+            "concurrency" => value(v).map(|x| s.concurrency = x),
+            "rate_limit_pps" => opt_value(v).map(|x| s.rate_limit_pps = x),
+            "probe_interval_ms" => value(v).map(|x| s.probe_interval_ms = x),
+            "host_budget_ms" => opt_value(v).map(|x| s.host_budget_ms = x),
+            "icmp_id" => opt_value(v).map(|x| s.icmp_id = x),
+            "icmp_seq" => opt_value(v).map(|x| s.icmp_seq = x),
+            "recv_buffer_size" => opt_value(v).map(|x| s.recv_buffer_size = x),
+            "progress.interval_ms" => value(v).map(|x| s.progress.interval_ms = x),
+            "progress.step_percent" => value(v).map(|x| s.progress.step_percent = x),
+            "quick_ports" => list_value(v).map(|x| s.quick_ports = x),
+            "retry" => value::<bool>(v).map(|on| {
+                if on {
+                    retry.get_or_insert_with(RetrySetting::default);
+                }
+            }),
+            "retry.timeout_ms" => {
+                value(v).map(|x| retry.get_or_insert_with(RetrySetting::default).timeout_ms = x)
+            }
+            "retry.concurrency" => {
+                value(v).map(|x| retry.get_or_insert_with(RetrySetting::default).concurrency = x)
+            }
+            "retry.quick" => {
+                value(v).map(|x| retry.get_or_insert_with(RetrySetting::default).quick = x)
+            }
+            "stream_to" => string_value(v).map(|x| s.stream_to = x),
+            "discovery" => match v {
+                "Icmp" => Ok(DiscoveryMethod::Icmp),
+                "Tcp" => Ok(DiscoveryMethod::Tcp),
+                "Both" => Ok(DiscoveryMethod::Both),
+                _ => Err(format!("Invalid value {:?}", v)),
+            }
+            .map(|x| s.discovery = x),
+            "discovery_order" => match v {
+                "All" => Ok(DiscoveryOrder::All),
+                "IcmpFirst" => Ok(DiscoveryOrder::IcmpFirst),
+                "TcpFirst" => Ok(DiscoveryOrder::TcpFirst),
+                _ => Err(format!("Invalid value {:?}", v)),
+            }
+            .map(|x| s.discovery_order = x),
+            "discovery_ports" => list_value(v).map(|x| s.discovery_ports = x),
+            "reverse_dns" => value(v).map(|x| s.reverse_dns = x),
+            _ => {
+                ignored_keys.push(key.to_string());
+                Ok(())
+            }
+        };
+        result.map_err(|m| parse_error(format!("{}: {}", key, m)))?;
+    }
+    s.retry = retry;
+    Ok(LoadedProfile {
+        setting: s,
+        ignored_keys,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_store(name: &str) -> ProfileStore {
+        let dir = std::env::temp_dir().join(format!("netdia-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        ProfileStore::new(dir)
+    }
+
+    #[test]
+    fn save_load_list_delete_round_trip() {
+        let store = temp_store("profiles");
+        let setting = HostScanSetting {
+            targets: vec!["192.0.2.1".parse().unwrap(), "2001:db8::5".parse().unwrap()],
+            rate_limit_pps: Some(50),
+            quick_ports: vec![22, 443],
+            retry: Some(RetrySetting {
+                quick: true,
+                ..Default::default()
+            }),
+            probe_interval_ms: 250,
+            recv_buffer_size: Some(1 << 20),
+            stream_to: Some("/tmp/scan.ndjson".to_string()),
+            discovery: DiscoveryMethod::Both,
+            discovery_order: DiscoveryOrder::TcpFirst,
+            reverse_dns: true,
+            ..Default::default()
+        };
+        assert_eq!(store.list().unwrap(), Vec::<String>::new());
+        store.save("office lan", &setting).unwrap();
+        store.save("home", &HostScanSetting::default()).unwrap();
+        let loaded = store.load("office lan").unwrap();
+        assert_eq!(loaded.setting, setting);
+        assert!(loaded.ignored_keys.is_empty());
+        assert_eq!(store.list().unwrap(), vec!["home", "office lan"]);
+        store.delete("home").unwrap();
+        assert!(matches!(store.load("home"), Err(ProfileError::NotFound(_))));
+        assert!(matches!(
+            store.save("../escape", &setting),
+            Err(ProfileError::InvalidName(_))
+        ));
+        let _ = std::fs::remove_dir_all(&store.dir);
+    }
+
+    #[test]
+    fn stream_to_survives_awkward_paths() {
+        for path in ["none", " spaced ", "C:\\scans\\\"q\".ndjson", "a\nb"] {
+            let setting = HostScanSetting {
+                stream_to: Some(path.to_string()),
+                ..Default::default()
+            };
+            let loaded = from_profile_text(&to_profile_text(&setting)).unwrap();
+            assert_eq!(loaded.setting.stream_to.as_deref(), Some(path));
+        }
+        let default = HostScanSetting::default();
+        let loaded = from_profile_text(&to_profile_text(&default)).unwrap();
+        assert_eq!(loaded.setting, default);
+        // Written bare before version 2
+        let loaded = from_profile_text("version = 1\nstream_to = /tmp/a.ndjson\n").unwrap();
+        assert_eq!(loaded.setting.stream_to.as_deref(), Some("/tmp/a.ndjson"));
+        assert!(from_profile_text("stream_to = \"open\n").is_err());
+    }
+
+    #[test]
+    fn missing_and_unknown_keys_load() {
+        let loaded =
+            from_profile_text("version = 1\ntargets = 192.0.2.9\ncount = 3\nfuture_option = yes\n")
+                .unwrap();
+        assert_eq!(loaded.setting.count, 3);
+        assert_eq!(loaded.setting.rate_limit_pps, None);
+        assert_eq!(
+            loaded.setting.discovery_ports,
+            HostScanSetting::default().discovery_ports
+        );
+        assert_eq!(loaded.ignored_keys, vec!["future_option"]);
+
+        let err = from_profile_text("count = many\n").unwrap_err();
+        assert_eq!(err.to_string(), "Line 1: count: Invalid value \"many\"");
+        assert!(matches!(
+            from_profile_text("version = 9\n"),
+            Err(ProfileError::Version(9))
+        ));
+    }
+}
@@ -0,0 +1,277 @@
+//! Gateway and router discovery
+use crate::cancel::CancellationToken;
+use crate::net::interface::Interface;
+use crate::net::route::{self, Route};
+use crate::ping::Prober;
+use crate::pool::map_concurrent;
+use crate::trace::HopProber;
+use std::net::{IpAddr, Ipv4Addr};
+use std::time::Duration;
+use ts_rs::TS;
+
+/// /24 networks enumerated per interface address at most
+pub const MAX_SUBNETS_PER_ADDR: u32 = 256;
+
+/// Router candidate that answered
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RouterInfo {
+    pub ip: IpAddr,
+    pub rtt: Duration,
+    pub is_default_gateway: bool,
+    /// Next hop of some route in the routing table
+    pub is_next_hop: bool,
+    /// Whether the router answered a TTL-limited probe towards a destination
+    /// routed through it: `forward_probe_dst` for a default gateway, an
+    /// address of the route's network otherwise. `None` if the check was
+    /// not run, as for routers no route goes through, which probes cannot
+    /// be steered to.
+    pub forwards: Option<bool>,
+}
+
+/// Settings for the router sweep
+#[derive(Clone, Debug, PartialEq, Eq, TS)]
+pub struct RouterScanSetting {
+    #[ts(type = "number")]
+    pub timeout_ms: u64,
+    pub concurrency: usize,
+    /// Off-link address used to check forwarding. Skipped when `None`.
+    pub forward_probe_dst: Option<IpAddr>,
+}
+
+impl Default for RouterScanSetting {
+    fn default() -> Self {
+        RouterScanSetting {
+            timeout_ms: 1000,
+            concurrency: 64,
+            forward_probe_dst: Some(IpAddr::V4(Ipv4Addr::new(1, 1, 1, 1))),
+        }
+    }
+}
+
+/// Likely router addresses: the given gateways, then .1 and .254 of every
+/// /24 in the IPv4 networks of up, non-loopback interfaces.
+///
+/// Pass [`route::next_hops`] to include the routers of every route, not
+/// only the default gateway.
+pub fn gateway_candidates(interfaces: &[Interface], gateways: &[IpAddr]) -> Vec<IpAddr> {
+    let mut candidates: Vec<IpAddr> = gateways.to_vec();
+    for iface in interfaces.iter().filter(|i| i.is_up && !i.is_loopback) {
+        for net in &iface.addrs {
+            let IpAddr::V4(addr) = net.addr else {
+                continue;
+            };
+            if net.prefix_len > 30 {
+                continue;
+            }
+            let prefix = net.prefix_len.min(24);
+            let mask = if prefix == 0 {
+                0
+            } else {
+                u32::MAX << (32 - prefix)
+            };
+            let network = u32::from(addr) & mask;
+            let subnets = (1u32 << (24 - prefix)).min(MAX_SUBNETS_PER_ADDR);
+            for i in 0..subnets {
+                let base = network + (i << 8);
+                for host in [1, 254] {
+                    let ip = base | host;
+                    // Stay inside networks smaller than /24
+                    if net.prefix_len > 24 && ip & !(u32::MAX << (32 - net.prefix_len)) != host {
+                        continue;
+                    }
+                    let ip = IpAddr::V4(Ipv4Addr::from(ip));
+                    if ip != IpAddr::V4(addr) {
+                        candidates.push(ip);
+                    }
+                }
+            }
+        }
+    }
+    let mut seen = std::collections::HashSet::new();
+    candidates.retain(|ip| seen.insert(*ip));
+    candidates
+}
+
+/// Destination whose first hop is `router`, to check it forwards
+fn forward_check_dst(
+    routes: &[Route],
+    router: IpAddr,
+    setting: &RouterScanSetting,
+) -> Option<IpAddr> {
+    let mut via = routes.iter().filter(|r| r.gateway == Some(router));
+    let route = via
+        .clone()
+        .find(|r| r.is_default())
+        .or_else(|| via.next())?;
+    if route.is_default() {
+        return setting.forward_probe_dst;
+    }
+    let net = &route.destination;
+    Some(net.addrs().nth(1).unwrap_or_else(|| net.network()))
+}
+
+/// Ping every candidate and report the ones that answer. Routers that are
+/// a next hop in `routes` are also checked for forwarding.
+pub fn router_sweep<P: Prober, H: HopProber>(
+    prober: &P,
+    hop_prober: &H,
+    candidates: &[IpAddr],
+    routes: &[Route],
+    setting: &RouterScanSetting,
+    token: &CancellationToken,
+) -> Vec<RouterInfo> {
+    let timeout = Duration::from_millis(setting.timeout_ms);
+    let gateways = route::default_gateways(routes);
+    map_concurrent(candidates, setting.concurrency, token, |ip| {
+        let reply = prober.probe(*ip, 0, timeout).ok()?;
+        let forwards = forward_check_dst(routes, *ip, setting).map(|dst| {
+            hop_prober
+                .probe_hop(dst, 1, timeout)
+                .map(|hop| hop.responder == *ip)
+                .unwrap_or(false)
+        });
+        Some(RouterInfo {
+            ip: *ip,
+            rtt: reply.rtt,
+            is_default_gateway: gateways.contains(ip),
+            is_next_hop: routes.iter().any(|r| r.gateway == Some(*ip)),
+            forwards,
+        })
+    })
+    .into_iter()
+    .flatten()
+    .flatten()
+    .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::net::ipnet::IpNet;
+    use crate::ping::{ProbeError, ProbeReply};
+    use crate::trace::HopReply;
+
+    fn iface(addrs: &[(&str, u8)]) -> Interface {
+        let mut iface = Interface::new(2, "eth0");
+        iface.is_up = true;
+        iface.addrs = addrs
+            .iter()
+            .map(|(a, p)| IpNet::new(a.parse().unwrap(), *p))
+            .collect();
+        iface
+    }
+
+    fn ips(list: &[&str]) -> Vec<IpAddr> {
+        list.iter().map(|s| s.parse().unwrap()).collect()
+    }
+
+    #[test]
+    fn candidates_for_slash_24_and_gateway() {
+        let candidates = gateway_candidates(
+            &[iface(&[("192.168.1.20", 24), ("fe80::1", 64)])],
+            &ips(&["192.168.1.1"]),
+        );
+        assert_eq!(candidates, ips(&["192.168.1.1", "192.168.1.254"]));
+    }
+
+    #[test]
+    fn candidates_cover_each_slash_24() {
+        let candidates = gateway_candidates(&[iface(&[("10.0.2.15", 23)])], &[]);
+        assert_eq!(
+            candidates,
+            ips(&["10.0.2.1", "10.0.2.254", "10.0.3.1", "10.0.3.254"])
+        );
+    }
+
+    fn route(dst: &str, prefix_len: u8, gateway: &str) -> Route {
+        Route {
+            destination: IpNet::new(dst.parse().unwrap(), prefix_len),
+            gateway: Some(gateway.parse().unwrap()),
+            iface: "eth0".to_string(),
+            metric: 100,
+        }
+    }
+
+    /// Answers pings from the listed hosts
+    struct Hosts(Vec<IpAddr>);
+
+    impl Prober for Hosts {
+        fn probe(
+            &self,
+            dst: IpAddr,
+            _seq: u16,
+            _timeout: Duration,
+        ) -> Result<ProbeReply, ProbeError> {
+            if !self.0.contains(&dst) {
+                return Err(ProbeError::Timeout);
+            }
+            Ok(ProbeReply {
+                responder: dst,
+                rtt: Duration::from_millis(1),
+                ttl: Some(64),
+            })
+        }
+    }
+
+    /// First hop as the routing table decides it
+    struct FirstHop(Vec<Route>);
+
+    impl HopProber for FirstHop {
+        fn probe_hop(
+            &self,
+            dst: IpAddr,
+            _ttl: u8,
+            _timeout: Duration,
+        ) -> Result<HopReply, ProbeError> {
+            let gateway = route::lookup(&self.0, dst).and_then(|r| r.gateway);
+            Ok(HopReply {
+                responder: gateway.ok_or(ProbeError::Timeout)?,
+                rtt: Duration::from_millis(1),
+                reached: false,
+                reply_ttl: Some(64),
+            })
+        }
+    }
+
+    #[test]
+    fn every_next_hop_checked_for_forwarding() {
+        let routes = vec![
+            route("0.0.0.0", 0, "192.168.1.1"),
+            route("10.0.0.0", 8, "192.168.1.254"),
+        ];
+        let interfaces = [iface(&[("192.168.1.20", 24)])];
+        let mut candidates = gateway_candidates(&interfaces, &route::next_hops(&routes));
+        // Answers, but no route goes through it
+        candidates.push("192.168.1.50".parse().unwrap());
+        let hosts = Hosts(ips(&["192.168.1.1", "192.168.1.254", "192.168.1.50"]));
+        let routers = router_sweep(
+            &hosts,
+            &FirstHop(routes.clone()),
+            &candidates,
+            &routes,
+            &RouterScanSetting::default(),
+            &CancellationToken::new(),
+        );
+        let summary: Vec<(IpAddr, bool, bool, Option<bool>)> = routers
+            .iter()
+            .map(|r| (r.ip, r.is_default_gateway, r.is_next_hop, r.forwards))
+            .collect();
+        assert_eq!(
+            summary,
+            [
+                ("192.168.1.1".parse().unwrap(), true, true, Some(true)),
+                ("192.168.1.254".parse().unwrap(), false, true, Some(true)),
+                ("192.168.1.50".parse().unwrap(), false, false, None),
+            ]
+        );
+    }
+
+    #[test]
+    fn candidates_stay_inside_small_networks() {
+        let candidates = gateway_candidates(&[iface(&[("172.16.5.2", 28)])], &[]);
+        assert_eq!(candidates, ips(&["172.16.5.1"]));
+        let mut down = iface(&[("10.9.9.9", 24)]);
+        down.is_up = false;
+        assert!(gateway_candidates(&[down], &[]).is_empty());
+    }
+}
@@ -0,0 +1,210 @@
+/// Transport protocol of a service
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Transport {
+    Tcp,
+    Udp,
+}
+
+/// Well-known service and its default port
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Service {
+    pub name: &'static str,
+    pub port: u16,
+    pub transport: Transport,
+}
+
+const fn tcp(name: &'static str, port: u16) -> Service {
+    Service {
+        name,
+        port,
+        transport: Transport::Tcp,
+    }
+}
+
+const fn udp(name: &'static str, port: u16) -> Service {
+    Service {
+        name,
+        port,
+        transport: Transport::Udp,
+    }
+}
+
+/// Bundled service names, after the IANA registry
+pub const SERVICES: &[Service] = &[
+    tcp("ftp-data", 20),
+    tcp("ftp", 21),
+    tcp("ssh", 22),
+    tcp("telnet", 23),
+    tcp("smtp", 25),
+    tcp("domain", 53),
+    udp("domain", 53),
+    udp("dhcps", 67),
+    udp("dhcpc", 68),
+    udp("tftp", 69),
+    tcp("http", 80),
+    tcp("kerberos", 88),
+    udp("kerberos", 88),
+    tcp("pop3", 110),
+    tcp("rpcbind", 111),
+    udp("rpcbind", 111),
+    tcp("ident", 113),
+    tcp("ntp", 123),
+    udp("ntp", 123),
+    tcp("msrpc", 135),
+    udp("netbios-ns", 137),
+    udp("netbios-dgm", 138),
+    tcp("netbios-ssn", 139),
+    tcp("imap", 143),
+    udp("snmp", 161),
+    udp("snmptrap", 162),
+    tcp("bgp", 179),
+    tcp("ldap", 389),
+    tcp("https", 443),
+    udp("https", 443),
+    tcp("microsoft-ds", 445),
+    tcp("smtps", 465),
+    udp("isakmp", 500),
+    udp("syslog", 514),
+    tcp("submission", 587),
+    tcp("ipp", 631),
+    tcp("ldaps", 636),
+    tcp("rsync", 873),
+    tcp("imaps", 993),
+    tcp("pop3s", 995),
+    tcp("mssql", 1433),
+    tcp("pptp", 1723),
+    udp("ssdp", 1900),
+    tcp("nfs", 2049),
+    tcp("mysql", 3306),
+    tcp("rdp", 3389),
+    udp("ipsec-nat-t", 4500),
+    tcp("sip", 5060),
+    udp("sip", 5060),
+    udp("mdns", 5353),
+    tcp("postgresql", 5432),
+    tcp("vnc", 5900),
+    tcp("redis", 6379),
+    tcp("http-alt", 8080),
+    tcp("https-alt", 8443),
+];
+
+/// Most frequently open TCP ports, most common first, after nmap's
+/// frequency data
+pub const TOP_TCP_PORTS: &[u16] = &[
+    80, 23, 443, 21, 22, 25, 3389, 110, 445, 139, 143, 53, 135, 3306, 8080, 1723, 111, 995, 993,
+    5900, 1025, 587, 8888, 199, 1720, 465, 548, 113, 81, 6001, 10000, 514, 5060, 179, 1026, 2000,
+    8443, 8000, 32768, 554, 26, 1433, 49152, 2001, 515, 8008, 49154, 1027, 5666, 646, 5000, 5631,
+    631, 49153, 8081, 2049, 88, 79, 5800, 106, 2121, 1110, 49155, 6000, 513, 990, 5357, 427, 49156,
+    543, 544, 5101, 144, 7, 389, 8009, 3128, 444, 9999, 5009, 7070, 5190, 3000, 5432, 1900, 3986,
+    13, 1029, 9, 5051, 6646, 49157, 1028, 873, 1755, 2717, 4899, 9100, 119, 37,
+];
+
+/// Most frequently open UDP ports, most common first
+pub const TOP_UDP_PORTS: &[u16] = &[
+    631, 161, 137, 123, 138, 1434, 445, 135, 67, 53, 139, 500, 68, 520, 1900, 4500, 514, 49152,
+    162, 69,
+];
+
+/// Most common ports for `transport`
+pub fn top_ports(transport: Transport) -> &'static [u16] {
+    match transport {
+        Transport::Tcp => TOP_TCP_PORTS,
+        Transport::Udp => TOP_UDP_PORTS,
+    }
+}
+
+/// Default port of the service called `name`, case-insensitive
+pub fn port_by_name(name: &str, transport: Transport) -> Option<u16> {
+    SERVICES
+        .iter()
+        .find(|s| s.transport == transport && s.name.eq_ignore_ascii_case(name))
+        .map(|s| s.port)
+}
+
+/// Name of the service usually found on `port`
+pub fn name_by_port(port: u16, transport: Transport) -> Option<&'static str> {
+    SERVICES
+        .iter()
+        .find(|s| s.transport == transport && s.port == port)
+        .map(|s| s.name)
+}
+
+/// Every bundled service on `port`, for looking up what a port is for
+/// without scanning. Empty for unknown ports.
+pub fn lookup_service_by_port(port: u16, transport: Transport) -> Vec<Service> {
+    SERVICES
+        .iter()
+        .filter(|s| s.transport == transport && s.port == port)
+        .copied()
+        .collect()
+}
+
+/// Bundled services whose name contains `query`, case-insensitive, or whose
+/// port is `query` when it is a number. Sorted by port, TCP first.
+pub fn search_services(query: &str) -> Vec<Service> {
+    let query = query.trim();
+    if query.is_empty() {
+        return Vec::new();
+    }
+    let port: Option<u16> = query.parse().ok();
+    let needle = query.to_ascii_lowercase();
+    let mut found: Vec<Service> = SERVICES
+        .iter()
+        .filter(|s| match port {
+            Some(port) => s.port == port,
+            None => s.name.contains(&needle),
+        })
+        .copied()
+        .collect();
+    found.sort_by_key(|s| (s.port, s.transport == Transport::Udp));
+    found
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashSet;
+
+    #[test]
+    fn lookup_is_per_transport() {
+        assert_eq!(port_by_name("HTTPS", Transport::Tcp), Some(443));
+        assert_eq!(port_by_name("snmp", Transport::Tcp), None);
+        assert_eq!(port_by_name("snmp", Transport::Udp), Some(161));
+        assert_eq!(name_by_port(22, Transport::Tcp), Some("ssh"));
+    }
+
+    #[test]
+    fn top_lists_have_no_duplicates() {
+        for transport in [Transport::Tcp, Transport::Udp] {
+            let ports = top_ports(transport);
+            assert_eq!(ports.iter().collect::<HashSet<_>>().len(), ports.len());
+        }
+        assert_eq!(TOP_TCP_PORTS.len(), 100);
+    }
+
+    #[test]
+    fn browse_bundled_services() {
+        let names =
+            |services: Vec<Service>| -> Vec<&str> { services.iter().map(|s| s.name).collect() };
+        assert_eq!(
+            names(lookup_service_by_port(22, Transport::Tcp)),
+            vec!["ssh"]
+        );
+        assert_eq!(
+            names(lookup_service_by_port(161, Transport::Udp)),
+            vec!["snmp"]
+        );
+        assert!(lookup_service_by_port(1, Transport::Tcp).is_empty());
+        assert!(lookup_service_by_port(22, Transport::Udp).is_empty());
+
+        let https = search_services("HTTPS");
+        assert_eq!(names(https), vec!["https", "https", "https-alt"]);
+        let dns = search_services("53");
+        assert_eq!(
+            dns.iter().map(|s| s.transport).collect::<Vec<_>>(),
+            vec![Transport::Tcp, Transport::Udp]
+        );
+        assert!(search_services("  ").is_empty());
+        assert!(search_services("no-such-service").is_empty());
+    }
+}
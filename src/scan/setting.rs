@@ -0,0 +1,296 @@
+use crate::net::mtu::system_egress_mtu;
+use crate::ping::echo::IcmpEchoProber;
+use crate::ping::icmp;
+use crate::progress::ProgressSetting;
+use crate::scan::port::TcpConnectProber;
+use crate::socket::IcmpConfig;
+use std::net::IpAddr;
+use ts_rs::TS;
+
+/// SSH, HTTP, HTTPS, SMB and RDP
+pub const DEFAULT_QUICK_PORTS: [u16; 5] = [22, 80, 443, 445, 3389];
+
+/// How hosts are probed for liveness
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, TS)]
+pub enum DiscoveryMethod {
+    #[default]
+    Icmp,
+    /// TCP connect to `discovery_ports`. A refused connection counts as alive.
+    Tcp,
+    /// ICMP and TCP, sequenced by `discovery_order`
+    Both,
+}
+
+/// Sequencing of the probes of `DiscoveryMethod::Both`
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, TS)]
+pub enum DiscoveryOrder {
+    /// Run both methods against every host
+    #[default]
+    All,
+    /// TCP only for hosts that did not answer ICMP
+    IcmpFirst,
+    /// ICMP only for hosts that did not answer TCP
+    TcpFirst,
+}
+
+/// HTTP and HTTPS, open or firewalled with a reset on most hosts
+pub const DEFAULT_DISCOVERY_PORTS: [u16; 2] = [80, 443];
+
+/// Settings for host scan
+#[derive(Clone, Debug, PartialEq, TS)]
+pub struct HostScanSetting {
+    pub targets: Vec<IpAddr>,
+    /// Interface index used for link-local IPv6 targets
+    pub scope_id: u32,
+    /// Probes sent to each host
+    pub count: u32,
+    /// Replies needed, out of `count`, to consider a host alive
+    pub require_replies: u8,
+    #[ts(type = "number")]
+    pub timeout_ms: u64,
+    /// Maximum number of hosts probed at once
+    pub concurrency: usize,
+    /// Probes sent per second across all workers. Unpaced when `None`.
+    pub rate_limit_pps: Option<u32>,
+    /// Time between the sends of successive probes to one host, like the
+    /// interval of ping. Probes follow each other right away when 0.
+    #[ts(type = "number")]
+    pub probe_interval_ms: u64,
+    /// Cap on the total time spent on one host across its `count` probes,
+    /// so unresponsive hosts give their slot back early. Unlimited when `None`.
+    #[ts(type = "number | null")]
+    pub host_budget_ms: Option<u64>,
+    /// ICMP identifier. Randomized when `None`.
+    pub icmp_id: Option<u16>,
+    /// First ICMP sequence number. Starts at 0 when `None`.
+    pub icmp_seq: Option<u16>,
+    /// `SO_RCVBUF` of the ICMP socket. Raise it for large, fast scans when
+    /// `HostScanResult::dropped_packets` reports kernel drops. The OS
+    /// default is kept when `None`.
+    pub recv_buffer_size: Option<usize>,
+    /// Throttling of progress updates
+    pub progress: ProgressSetting,
+    /// TCP ports connected to on alive hosts as a quick service hint.
+    /// Skipped when empty; see `DEFAULT_QUICK_PORTS`.
+    pub quick_ports: Vec<u16>,
+    /// Second pass over unreachable hosts. Disabled when `None`.
+    pub retry: Option<RetrySetting>,
+    /// Path of an NDJSON file each result is appended to as it is found
+    pub stream_to: Option<String>,
+    pub discovery: DiscoveryMethod,
+    pub discovery_order: DiscoveryOrder,
+    /// Ports connected to by TCP discovery
+    pub discovery_ports: Vec<u16>,
+    /// Look up the hostnames of alive hosts after the scan, see
+    /// `resolve_hostnames`
+    pub reverse_dns: bool,
+}
+
+/// Re-probe of hosts that did not answer the first pass
+#[derive(Clone, Debug, PartialEq, Eq, TS)]
+pub struct RetrySetting {
+    #[ts(type = "number")]
+    pub timeout_ms: u64,
+    pub concurrency: usize,
+    /// Send only as many probes per host as `require_replies` needs, back to
+    /// back and through the same discovery method, instead of repeating the
+    /// full per-host scan. Quick ports are not checked.
+    pub quick: bool,
+}
+
+impl Default for RetrySetting {
+    fn default() -> Self {
+        RetrySetting {
+            timeout_ms: 3000,
+            concurrency: 8,
+            quick: false,
+        }
+    }
+}
+
+impl Default for HostScanSetting {
+    fn default() -> Self {
+        HostScanSetting {
+            targets: Vec::new(),
+            scope_id: 0,
+            count: 1,
+            require_replies: 1,
+            timeout_ms: 1000,
+            concurrency: 64,
+            rate_limit_pps: None,
+            probe_interval_ms: 0,
+            host_budget_ms: None,
+            icmp_id: None,
+            icmp_seq: None,
+            recv_buffer_size: None,
+            progress: ProgressSetting::default(),
+            quick_ports: Vec::new(),
+            retry: None,
+            stream_to: None,
+            discovery: DiscoveryMethod::Icmp,
+            discovery_order: DiscoveryOrder::All,
+            discovery_ports: DEFAULT_DISCOVERY_PORTS.to_vec(),
+            reverse_dns: false,
+        }
+    }
+}
+
+/// Named combinations of scan parameters, after nmap's timing templates
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, TS)]
+pub enum ScanIntensity {
+    /// 8 hosts at once, 50 probes/s, 2s timeout, 2 probes per host and a
+    /// retry pass. Stays under typical router ICMP rate limits.
+    Polite,
+    /// 64 hosts at once, unpaced, 1s timeout, 1 probe per host.
+    /// Same as `HostScanSetting::default()`.
+    #[default]
+    Normal,
+    /// 256 hosts at once, unpaced, 500ms timeout, 1 probe per host.
+    Aggressive,
+    /// 1024 hosts at once, unpaced, 250ms timeout, 1 probe per host.
+    /// Only suited to fast local networks.
+    Insane,
+}
+
+impl HostScanSetting {
+    /// Setting with the parameters of `intensity`. Override individual
+    /// fields with struct update syntax:
+    /// `HostScanSetting { timeout_ms: 300, ..HostScanSetting::with_intensity(ScanIntensity::Polite) }`
+    pub fn with_intensity(intensity: ScanIntensity) -> HostScanSetting {
+        let base = HostScanSetting::default();
+        match intensity {
+            ScanIntensity::Polite => HostScanSetting {
+                concurrency: 8,
+                rate_limit_pps: Some(50),
+                timeout_ms: 2000,
+                count: 2,
+                retry: Some(RetrySetting::default()),
+                ..base
+            },
+            ScanIntensity::Normal => base,
+            ScanIntensity::Aggressive => HostScanSetting {
+                concurrency: 256,
+                timeout_ms: 500,
+                ..base
+            },
+            ScanIntensity::Insane => HostScanSetting {
+                concurrency: 1024,
+                timeout_ms: 250,
+                ..base
+            },
+        }
+    }
+    /// Setting for a sweep of the local segment: neighbors answer fast, so
+    /// many hosts at once with a short timeout and a second probe
+    pub fn neighbor_scan_default(targets: Vec<IpAddr>) -> HostScanSetting {
+        HostScanSetting {
+            targets,
+            concurrency: 128,
+            timeout_ms: 500,
+            count: 2,
+            ..HostScanSetting::default()
+        }
+    }
+    /// ICMP identifier for this scan, pinned or random
+    pub fn resolve_icmp_id(&self) -> u16 {
+        self.icmp_id.unwrap_or_else(icmp::random_id)
+    }
+    /// ICMP echo prober for this scan, shared by all hosts. Replies must
+    /// echo a per-probe nonce, since a sweep draws stray replies from hosts
+    /// answering someone else's probes. Link-local targets are reached
+    /// through `scope_id`. The receive buffer fits a full packet on the
+    /// egress interface with the largest MTU among the targets.
+    pub fn icmp_prober(&self) -> IcmpEchoProber {
+        let config = IcmpConfig {
+            recv_buffer_size: self.recv_buffer_size,
+            ..IcmpConfig::for_mtu(system_egress_mtu(&self.targets))
+        };
+        IcmpEchoProber::with_config(self.resolve_icmp_id(), config)
+            .with_pinned_id(self.icmp_id.is_some())
+            .with_nonce_payload(true)
+            .with_scope_id(self.scope_id)
+    }
+    /// TCP connect prober for discovery and quick ports. Connections are
+    /// reset on close, so a sweep does not leave a TIME_WAIT port behind
+    /// for every host that accepted.
+    pub fn tcp_prober(&self) -> TcpConnectProber {
+        TcpConnectProber {
+            reset_on_close: true,
+            ..TcpConnectProber::default()
+        }
+    }
+    /// Sequence number of the `n`th probe to a host
+    pub fn seq_for(&self, n: u32) -> u16 {
+        self.icmp_seq.unwrap_or(0).wrapping_add(n as u16)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn params(s: &HostScanSetting) -> (usize, Option<u32>, u64, u32, bool) {
+        (
+            s.concurrency,
+            s.rate_limit_pps,
+            s.timeout_ms,
+            s.count,
+            s.retry.is_some(),
+        )
+    }
+
+    #[test]
+    fn presets_match_documentation() {
+        let polite = HostScanSetting::with_intensity(ScanIntensity::Polite);
+        assert_eq!(params(&polite), (8, Some(50), 2000, 2, true));
+        assert_eq!(
+            HostScanSetting::with_intensity(ScanIntensity::Normal),
+            HostScanSetting::default()
+        );
+        let aggressive = HostScanSetting::with_intensity(ScanIntensity::Aggressive);
+        assert_eq!(params(&aggressive), (256, None, 500, 1, false));
+        let insane = HostScanSetting::with_intensity(ScanIntensity::Insane);
+        assert_eq!(params(&insane), (1024, None, 250, 1, false));
+    }
+
+    #[test]
+    fn explicit_fields_override_preset() {
+        let setting = HostScanSetting {
+            timeout_ms: 300,
+            rate_limit_pps: None,
+            ..HostScanSetting::with_intensity(ScanIntensity::Polite)
+        };
+        assert_eq!(params(&setting), (8, None, 300, 2, true));
+    }
+
+    #[test]
+    fn icmp_prober_requests_receive_buffer() {
+        let setting = HostScanSetting {
+            recv_buffer_size: Some(1 << 20),
+            ..Default::default()
+        };
+        let prober = setting.icmp_prober();
+        assert_eq!(prober.config().recv_buffer_size, Some(1 << 20));
+        assert!(prober.config().count_drops);
+        assert_eq!(
+            HostScanSetting::default().icmp_prober().config(),
+            &IcmpConfig::default()
+        );
+    }
+
+    #[test]
+    fn icmp_prober_scopes_link_local_targets() {
+        let setting = HostScanSetting {
+            scope_id: 3,
+            ..Default::default()
+        };
+        let prober = setting.icmp_prober();
+        let link_local: IpAddr = "fe80::1".parse().unwrap();
+        assert_eq!(
+            prober.dst_addr(link_local),
+            "[fe80::1%3]:0".parse().unwrap()
+        );
+        let v4: IpAddr = "192.0.2.1".parse().unwrap();
+        assert_eq!(prober.dst_addr(v4), "192.0.2.1:0".parse().unwrap());
+    }
+}
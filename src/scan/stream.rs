@@ -0,0 +1,346 @@
+use super::{Host, HostState};
+use std::fmt::Write as _;
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufWriter, Write};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+/// Longest time a written result may sit in the buffer
+pub const FLUSH_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Append `s` to `out` as a JSON string literal. Every string in a line
+/// goes through here.
+fn push_json_str(out: &mut String, s: &str) {
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if c < ' ' => {
+                let _ = write!(out, "\\u{:04x}", c as u32);
+            }
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+}
+
+/// JSON object written one field at a time
+struct JsonObject(String);
+
+impl JsonObject {
+    fn new() -> JsonObject {
+        JsonObject(String::from("{"))
+    }
+    fn key(&mut self, key: &str) -> &mut String {
+        if self.0.len() > 1 {
+            self.0.push(',');
+        }
+        push_json_str(&mut self.0, key);
+        self.0.push(':');
+        &mut self.0
+    }
+    /// Field whose value is already JSON
+    fn raw(&mut self, key: &str, value: &str) {
+        self.key(key).push_str(value);
+    }
+    fn str(&mut self, key: &str, value: &str) {
+        push_json_str(self.key(key), value);
+    }
+    fn opt_str(&mut self, key: &str, value: Option<&str>) {
+        match value {
+            Some(value) => self.str(key, value),
+            None => self.raw(key, "null"),
+        }
+    }
+    fn finish(mut self) -> String {
+        self.0.push('}');
+        self.0
+    }
+}
+
+/// One JSON object per line in the shape of the `Host` binding
+pub fn host_json(host: &Host) -> String {
+    let mut obj = JsonObject::new();
+    obj.str("ip", &host.ip.to_string());
+    obj.str(
+        "state",
+        match host.state {
+            HostState::Alive => "Alive",
+            HostState::Unreachable => "Unreachable",
+            HostState::Unavailable => "Unavailable",
+        },
+    );
+    let rtt = host.rtt.map(|rtt| {
+        format!(
+            "{{\"secs\":{},\"nanos\":{}}}",
+            rtt.as_secs(),
+            rtt.subsec_nanos()
+        )
+    });
+    obj.raw("rtt", rtt.as_deref().unwrap_or("null"));
+    obj.raw("replies", &host.replies.to_string());
+    let ports: Vec<String> = host.open_ports.iter().map(|p| p.to_string()).collect();
+    obj.raw("open_ports", &format!("[{}]", ports.join(",")));
+    obj.opt_str("mac", host.mac.map(|mac| mac.to_string()).as_deref());
+    let detected_by = host.detected_by.map(|by| format!("{:?}", by));
+    obj.opt_str("detected_by", detected_by.as_deref());
+    obj.opt_str("hostname", host.hostname.as_deref());
+    obj.finish()
+}
+
+struct Inner {
+    writer: BufWriter<File>,
+    /// Lines written since the last flush
+    dirty: bool,
+    stopped: bool,
+    error: Option<io::Error>,
+}
+
+struct Shared {
+    inner: Mutex<Inner>,
+    stop: Condvar,
+}
+
+/// Appends scan results to an NDJSON file as they are found.
+///
+/// Every line is written whole, so the file stays valid when the scan stops
+/// early. A background thread flushes buffered lines every
+/// `FLUSH_INTERVAL`, also when no further results come in.
+///
+/// Hosts are written as soon as they are scanned, before reverse DNS, so
+/// their `hostname` is `null`. `resolve_hostnames` appends a further line
+/// for each host that got a name; a later line for an `ip` replaces the
+/// earlier one.
+pub struct NdjsonSink {
+    shared: Arc<Shared>,
+    flusher: Option<JoinHandle<()>>,
+}
+
+impl NdjsonSink {
+    /// Create or truncate `path`
+    pub fn create(path: &str) -> io::Result<NdjsonSink> {
+        Ok(NdjsonSink::new(File::create(path)?, FLUSH_INTERVAL))
+    }
+
+    /// Append to `path`, creating it if needed
+    pub fn append(path: &str) -> io::Result<NdjsonSink> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(NdjsonSink::new(file, FLUSH_INTERVAL))
+    }
+
+    fn new(file: File, flush_interval: Duration) -> NdjsonSink {
+        let shared = Arc::new(Shared {
+            inner: Mutex::new(Inner {
+                writer: BufWriter::new(file),
+                dirty: false,
+                stopped: false,
+                error: None,
+            }),
+            stop: Condvar::new(),
+        });
+        let flusher = {
+            let shared = shared.clone();
+            thread::spawn(move || flush_every(&shared, flush_interval))
+        };
+        NdjsonSink {
+            shared,
+            flusher: Some(flusher),
+        }
+    }
+
+    /// Write `host`. The first error is kept and later writes are skipped.
+    pub fn write(&self, host: &Host) {
+        let mut inner = self.shared.inner.lock().unwrap();
+        if inner.error.is_some() {
+            return;
+        }
+        let mut line = host_json(host);
+        line.push('\n');
+        match inner.writer.write_all(line.as_bytes()) {
+            Ok(()) => inner.dirty = true,
+            Err(e) => inner.error = Some(e),
+        }
+    }
+
+    fn stop_flusher(&mut self) {
+        let Some(flusher) = self.flusher.take() else {
+            return;
+        };
+        self.shared.inner.lock().unwrap().stopped = true;
+        self.shared.stop.notify_all();
+        let _ = flusher.join();
+    }
+
+    /// Flush remaining lines and return the first error, if any
+    pub fn finish(mut self) -> io::Result<()> {
+        self.stop_flusher();
+        let mut inner = self.shared.inner.lock().unwrap();
+        match inner.error.take() {
+            Some(e) => Err(e),
+            None => inner.writer.flush(),
+        }
+    }
+}
+
+impl Drop for NdjsonSink {
+    fn drop(&mut self) {
+        self.stop_flusher();
+    }
+}
+
+/// Flush `shared` every `interval` while lines are waiting, until stopped
+fn flush_every(shared: &Shared, interval: Duration) {
+    let mut inner = shared.inner.lock().unwrap();
+    loop {
+        inner = shared.stop.wait_timeout(inner, interval).unwrap().0;
+        if inner.stopped {
+            return;
+        }
+        if inner.dirty && inner.error.is_none() {
+            inner.dirty = false;
+            if let Err(e) = inner.writer.flush() {
+                inner.error = Some(e);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cancel::CancellationToken;
+    use crate::ping::{ProbeError, ProbeReply, Prober};
+    use crate::scan::host::tests::{v4, AliveSet};
+    use crate::scan::Detection;
+    use crate::scan::{host_scan, HostScanSetting};
+    use std::net::IpAddr;
+
+    #[test]
+    fn host_line_matches_binding_shape() {
+        let host = Host {
+            ip: v4(1),
+            state: HostState::Alive,
+            rtt: Some(Duration::from_micros(1500)),
+            replies: 1,
+            open_ports: vec![22, 443],
+            mac: None,
+            detected_by: Some(Detection::Tcp),
+            hostname: None,
+        };
+        assert_eq!(
+            host_json(&host),
+            "{\"ip\":\"192.0.2.1\",\"state\":\"Alive\",\"rtt\":{\"secs\":0,\"nanos\":1500000},\
+             \"replies\":1,\"open_ports\":[22,443],\"mac\":null,\"detected_by\":\"Tcp\",\
+             \"hostname\":null}"
+        );
+    }
+
+    #[test]
+    fn hostname_escaped_as_json() {
+        let host = Host {
+            ip: v4(1),
+            state: HostState::Alive,
+            rtt: None,
+            replies: 1,
+            open_ports: Vec::new(),
+            mac: None,
+            detected_by: None,
+            hostname: Some("o'brien\"s\\pc\u{1}é".to_string()),
+        };
+        let line = host_json(&host);
+        assert!(
+            line.ends_with(",\"hostname\":\"o'brien\\\"s\\\\pc\\u0001é\"}"),
+            "{}",
+            line
+        );
+    }
+
+    #[test]
+    fn quiet_sink_still_flushes() {
+        let path = std::env::temp_dir().join(format!("netdia-quiet-{}.ndjson", std::process::id()));
+        let file = File::create(&path).unwrap();
+        let sink = NdjsonSink::new(file, Duration::from_millis(10));
+        let host = Host {
+            ip: v4(7),
+            state: HostState::Alive,
+            rtt: None,
+            replies: 1,
+            open_ports: Vec::new(),
+            mac: None,
+            detected_by: None,
+            hostname: None,
+        };
+        sink.write(&host);
+        // No further writes; the line must show up on its own
+        let mut contents = String::new();
+        for _ in 0..500 {
+            contents = std::fs::read_to_string(&path).unwrap();
+            if !contents.is_empty() {
+                break;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+        assert_eq!(contents, host_json(&host) + "\n");
+        sink.finish().unwrap();
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    /// Cancels the scan once `after` hosts were probed
+    struct CancelAfter {
+        inner: AliveSet,
+        after: usize,
+        probed: Mutex<usize>,
+        token: CancellationToken,
+    }
+
+    impl Prober for CancelAfter {
+        fn probe(
+            &self,
+            dst: IpAddr,
+            seq: u16,
+            timeout: Duration,
+        ) -> Result<ProbeReply, ProbeError> {
+            let mut probed = self.probed.lock().unwrap();
+            *probed += 1;
+            if *probed == self.after {
+                self.token.cancel();
+            }
+            self.inner.probe(dst, seq, timeout)
+        }
+    }
+
+    #[test]
+    fn cancelled_scan_leaves_valid_file() {
+        let path =
+            std::env::temp_dir().join(format!("netdia-stream-{}.ndjson", std::process::id()));
+        let token = CancellationToken::new();
+        let prober = CancelAfter {
+            inner: AliveSet([v4(1), v4(3)].into_iter().collect()),
+            after: 3,
+            probed: Mutex::new(0),
+            token: token.clone(),
+        };
+        let setting = HostScanSetting {
+            targets: (1..=20).map(v4).collect(),
+            concurrency: 1,
+            stream_to: Some(path.to_string_lossy().into_owned()),
+            ..Default::default()
+        };
+        let result = host_scan(&prober, &setting, &token);
+        assert!(result.cancelled);
+        assert_eq!(result.stream_error, None);
+        let contents = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert!(contents.ends_with('\n'));
+        let lines: Vec<&str> = contents.lines().collect();
+        assert_eq!(lines.len(), 3);
+        let expected: Vec<String> = result.hosts.iter().map(host_json).collect();
+        assert_eq!(lines, expected);
+        assert!(lines[1].contains("\"state\":\"Unreachable\""));
+    }
+}
@@ -0,0 +1,161 @@
+//! Address usage of a subnet after a host scan
+use super::HostScanResult;
+use crate::net::ipnet::{subnet_info, IpNet, IpNetError, SubnetInfo};
+use std::net::{IpAddr, Ipv4Addr};
+use ts_rs::TS;
+
+/// Free ranges suggested for static assignment, largest first
+pub const MAX_FREE_RANGES: usize = 5;
+
+/// Run of consecutive unused addresses
+#[derive(Clone, Debug, PartialEq, Eq, TS)]
+pub struct FreeRange {
+    pub first: IpAddr,
+    pub last: IpAddr,
+    #[ts(type = "number")]
+    pub size: u64,
+}
+
+#[derive(Clone, Debug, PartialEq, TS)]
+pub struct SubnetUtilization {
+    pub cidr: String,
+    /// Alive hosts inside the subnet, in address order
+    pub in_use: Vec<IpAddr>,
+    /// Usable host addresses. `None` for IPv6, where only presence is
+    /// meaningful.
+    #[ts(type = "number | null")]
+    pub usable: Option<u64>,
+    #[ts(type = "number | null")]
+    pub free: Option<u64>,
+    /// Share of usable addresses in use, in percent
+    pub density_percent: Option<f64>,
+    /// Largest unused runs, at most [`MAX_FREE_RANGES`]. Empty for IPv6.
+    pub free_ranges: Vec<FreeRange>,
+}
+
+/// Count the alive hosts of `report` in `cidr` and find the free ranges
+pub fn subnet_utilization(
+    report: &HostScanResult,
+    cidr: &str,
+) -> Result<SubnetUtilization, IpNetError> {
+    let net: IpNet = cidr.parse()?;
+    let mut in_use: Vec<IpAddr> = report
+        .alive()
+        .map(|h| h.ip)
+        .filter(|ip| net.contains(ip))
+        .collect();
+    in_use.sort();
+    in_use.dedup();
+    let mut utilization = SubnetUtilization {
+        cidr: IpNet::new(net.network(), net.prefix_len).to_string(),
+        in_use,
+        usable: None,
+        free: None,
+        density_percent: None,
+        free_ranges: Vec::new(),
+    };
+    let SubnetInfo::V4(info) = subnet_info(cidr)? else {
+        return Ok(utilization);
+    };
+    let (first, last) = (u32::from(info.first_usable), u32::from(info.last_usable));
+    let used: Vec<u32> = utilization
+        .in_use
+        .iter()
+        .filter_map(|ip| match ip {
+            IpAddr::V4(v4) => Some(u32::from(*v4)),
+            IpAddr::V6(_) => None,
+        })
+        .filter(|n| (first..=last).contains(n))
+        .collect();
+    let usable = info.usable_hosts;
+    utilization.usable = Some(usable);
+    utilization.free = Some(usable - used.len() as u64);
+    utilization.density_percent = Some(used.len() as f64 * 100.0 / usable as f64);
+
+    let mut ranges = Vec::new();
+    let mut start = first as u64;
+    for n in used.iter().map(|n| *n as u64).chain([last as u64 + 1]) {
+        if n > start {
+            ranges.push(FreeRange {
+                first: IpAddr::V4(Ipv4Addr::from(start as u32)),
+                last: IpAddr::V4(Ipv4Addr::from((n - 1) as u32)),
+                size: n - start,
+            });
+        }
+        start = n + 1;
+    }
+    ranges.sort_by(|a, b| b.size.cmp(&a.size).then(a.first.cmp(&b.first)));
+    ranges.truncate(MAX_FREE_RANGES);
+    utilization.free_ranges = ranges;
+    Ok(utilization)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scan::{Host, HostState};
+
+    fn host(ip: &str, state: HostState) -> Host {
+        Host {
+            ip: ip.parse().unwrap(),
+            state,
+            rtt: None,
+            replies: 0,
+            open_ports: Vec::new(),
+            mac: None,
+            detected_by: None,
+            hostname: None,
+        }
+    }
+
+    fn report(alive: &[&str]) -> HostScanResult {
+        let mut hosts: Vec<Host> = alive.iter().map(|ip| host(ip, HostState::Alive)).collect();
+        hosts.push(host("192.168.10.5", HostState::Unreachable));
+        HostScanResult {
+            hosts,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn utilization_of_slash_27() {
+        let report = report(&[
+            "192.168.10.30",
+            "192.168.10.1",
+            "192.168.10.2",
+            "192.168.10.10",
+            "192.168.10.20",
+            // Outside the subnet
+            "192.168.10.40",
+        ]);
+        let u = subnet_utilization(&report, "192.168.10.7/27").unwrap();
+        assert_eq!(u.cidr, "192.168.10.0/27");
+        assert_eq!(u.in_use.len(), 5);
+        assert_eq!((u.usable, u.free), (Some(30), Some(25)));
+        let density = u.density_percent.unwrap();
+        assert!((density - 16.67).abs() < 0.01, "{}", density);
+        let ranges: Vec<(String, u64)> = u
+            .free_ranges
+            .iter()
+            .map(|r| (format!("{}-{}", r.first, r.last), r.size))
+            .collect();
+        assert_eq!(
+            ranges,
+            vec![
+                ("192.168.10.11-192.168.10.19".to_string(), 9),
+                ("192.168.10.21-192.168.10.29".to_string(), 9),
+                ("192.168.10.3-192.168.10.9".to_string(), 7),
+            ]
+        );
+    }
+
+    #[test]
+    fn ipv6_reports_presence_only() {
+        let report = report(&["2001:db8::1", "2001:db8::ff"]);
+        let u = subnet_utilization(&report, "2001:db8::/64").unwrap();
+        assert_eq!(u.in_use.len(), 2);
+        assert_eq!((u.usable, u.free, u.density_percent), (None, None, None));
+        assert!(u.free_ranges.is_empty());
+        assert!(subnet_utilization(&report, "2001:db8::/129").is_err());
+    }
+}
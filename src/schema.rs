@@ -0,0 +1,237 @@
+//! TypeScript definitions of the setting and payload types shared with the UI
+use ts_rs::TS;
+
+/// Path of the checked-in definitions, relative to the crate root
+pub const BINDINGS_PATH: &str = "bindings/netdia.d.ts";
+
+/// Shape given to `std::time::Duration` fields, which ts-rs does not
+/// declare on its own
+const DURATION_DECL: &str = "type Duration = { secs: number, nanos: number };";
+
+macro_rules! declarations {
+    ($($ty:ty),* $(,)?) => {
+        vec![$(<$ty as TS>::decl()),*]
+    };
+}
+
+/// Declarations of every exported type, in a stable order
+pub fn declarations() -> Vec<String> {
+    use crate::cancel::CancelReason;
+    use crate::dns::cachebench::ResolverCacheReport;
+    use crate::dns::health::{DnsHealthReport, DnsServerHealth};
+    use crate::event::{BackpressureSetting, OverflowPolicy};
+    use crate::grade::{Grade, GradeThresholds, Thresholds};
+    use crate::http::latency::{LatencyDonePayload, LatencySetting};
+    use crate::http::ping::{HttpPingResult, HttpPingSetting};
+    use crate::net::geo::GeoInfo;
+    use crate::net::infra::InfraSetting;
+    use crate::net::multicast::{MulticastResult, MulticastSetting, MulticastStatus};
+    use crate::net::presence::PresenceSetting;
+    use crate::net::snapshot::SnapshotSetting;
+    use crate::net::watchdog::{ConnectivityStage, Outage, WatchdogSetting};
+    use crate::ping::alert::{AlertSetting, PingAlertKind, PingAlertPayload};
+    use crate::ping::bulk::{BulkPingResult, BulkPingRow, BulkPingSetting, BulkPingTargetDone};
+    use crate::ping::heatmap::{HeatmapEntry, HeatmapSetting};
+    use crate::ping::quic::QuicPingSetting;
+    use crate::ping::result::{PingSample, PingStat};
+    use crate::ping::session::{PingDonePayload, PingInterfacePayload};
+    use crate::ping::sweep::{PayloadSweepReport, PayloadSweepSetting, SizeResult};
+    use crate::ping::tcp::{TcpConnectReport, TcpConnectSetting};
+    use crate::ping::template::UdpPayload;
+    use crate::ping::{PingProtocol, PingSetting, UnreachableReason};
+    use crate::progress::{Progress, ProgressSetting};
+    use crate::scan::guard::{GuardIssue, GuardThresholds, SettingWarning};
+    use crate::scan::matrix::PortMatrix;
+    use crate::scan::neighbor::NeighborScanOverrides;
+    use crate::scan::port::{PortProbe, PortState};
+    use crate::scan::router::RouterScanSetting;
+    use crate::scan::utilization::{FreeRange, SubnetUtilization};
+    use crate::scan::{
+        Detection, DiscoveryMethod, DiscoveryOrder, Host, HostScanResult, HostScanSetting,
+        HostState, RetrySetting, ScanIntensity,
+    };
+    use crate::socket::tcp_info::{TcpInfoReport, TcpInfoSetting, TcpStats};
+    use crate::speedtest::series::{SeriesResult, SeriesSetting};
+    use crate::speedtest::server::{ServerComparison, ServerMeasurement, SpeedtestServer};
+    use crate::speedtest::{
+        Direction, DuplexBaseline, FullDuplexDonePayload, SpeedtestDonePayload, SpeedtestOutcome,
+        SpeedtestPhase, SpeedtestSetting, SpeedtestUpdatePayload,
+    };
+    use crate::trace::anomaly::{Anomaly, AnomalyKind};
+    use crate::trace::aspath::{AsHop, AsPathPayload};
+    use crate::trace::session::{Hop, TraceResult};
+    use crate::trace::{FlowPolicy, TraceSetting};
+    use crate::update::DownloadEvent;
+
+    let mut decls = vec![DURATION_DECL.to_string()];
+    decls.extend(declarations![
+        CancelReason,
+        Progress,
+        ProgressSetting,
+        OverflowPolicy,
+        BackpressureSetting,
+        Grade,
+        Thresholds,
+        GradeThresholds,
+        PingProtocol,
+        UdpPayload,
+        PingSetting,
+        UnreachableReason,
+        PingSample,
+        PingStat,
+        PingDonePayload,
+        PingInterfacePayload,
+        TcpConnectSetting,
+        TcpConnectReport,
+        QuicPingSetting,
+        PayloadSweepSetting,
+        SizeResult,
+        PayloadSweepReport,
+        BulkPingSetting,
+        BulkPingRow,
+        BulkPingTargetDone,
+        BulkPingResult,
+        AlertSetting,
+        PingAlertKind,
+        PingAlertPayload,
+        HeatmapSetting,
+        HeatmapEntry,
+        ConnectivityStage,
+        WatchdogSetting,
+        GeoInfo,
+        MulticastSetting,
+        MulticastStatus,
+        MulticastResult,
+        Outage,
+        PresenceSetting,
+        InfraSetting,
+        SnapshotSetting,
+        TcpInfoSetting,
+        TcpStats,
+        TcpInfoReport,
+        DnsServerHealth,
+        DnsHealthReport,
+        ResolverCacheReport,
+        HostState,
+        Detection,
+        Host,
+        RetrySetting,
+        ScanIntensity,
+        DiscoveryMethod,
+        DiscoveryOrder,
+        HostScanSetting,
+        HostScanResult,
+        GuardThresholds,
+        GuardIssue,
+        SettingWarning,
+        NeighborScanOverrides,
+        RouterScanSetting,
+        FreeRange,
+        SubnetUtilization,
+        PortState,
+        PortProbe,
+        PortMatrix,
+        FlowPolicy,
+        TraceSetting,
+        AnomalyKind,
+        Anomaly,
+        Hop,
+        TraceResult,
+        AsHop,
+        AsPathPayload,
+        Direction,
+        SpeedtestOutcome,
+        SpeedtestPhase,
+        SpeedtestSetting,
+        SeriesSetting,
+        SpeedtestUpdatePayload,
+        SpeedtestDonePayload,
+        DuplexBaseline,
+        FullDuplexDonePayload,
+        SeriesResult,
+        SpeedtestServer,
+        ServerMeasurement,
+        ServerComparison,
+        HttpPingSetting,
+        HttpPingResult,
+        LatencySetting,
+        LatencyDonePayload,
+        DownloadEvent,
+    ]);
+    decls
+}
+
+/// Contents of the `.d.ts` file
+pub fn typescript_definitions() -> String {
+    let mut out = String::from("// Generated by `cargo run --example ts_bindings`. Do not edit.\n");
+    for decl in declarations() {
+        out.push_str("\nexport ");
+        out.push_str(&decl);
+        out.push('\n');
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn declaration(name: &str) -> String {
+        let prefix = format!("type {} =", name);
+        declarations()
+            .into_iter()
+            .find(|d| d.contains(&prefix))
+            .unwrap_or_else(|| panic!("{} not declared", name))
+    }
+
+    #[test]
+    fn representative_payloads() {
+        let done = declaration("PingDonePayload");
+        assert!(done.contains("dst_ip: string"));
+        assert!(done.contains("samples: Array<PingSample>"));
+        assert!(done.contains("cancel_reason: CancelReason | null"));
+        let sample = declaration("PingSample");
+        assert!(sample.contains("rtt: Duration | null"));
+        let update = declaration("SpeedtestUpdatePayload");
+        assert!(update.contains("bytes: number"));
+        assert!(!typescript_definitions().contains("bigint"));
+        assert!(declaration("PingProtocol").contains("{ \"UdpEcho\": { port: number, } }"));
+    }
+
+    #[test]
+    fn every_referenced_type_is_declared() {
+        let defs = declarations().join("\n");
+        // Drop doc comments, which are free text
+        let mut code = String::new();
+        let mut rest = defs.as_str();
+        while let Some((before, after)) = rest.split_once("/**") {
+            code.push_str(before);
+            rest = after.split_once("*/").map_or("", |(_, after)| after);
+        }
+        code.push_str(rest);
+        let declared: Vec<&str> = code
+            .lines()
+            .filter_map(|l| l.strip_prefix("type "))
+            .filter_map(|l| l.split(' ').next())
+            .collect();
+        let builtin = ["Array", "Record", "Partial"];
+        for word in code.split(|c: char| !c.is_ascii_alphanumeric() && c != '_') {
+            let capitalized = word.starts_with(|c: char| c.is_ascii_uppercase());
+            let quoted = code.contains(&format!("\"{}\"", word));
+            if capitalized && !quoted && !builtin.contains(&word) {
+                assert!(declared.contains(&word), "{} is not declared", word);
+            }
+        }
+    }
+
+    #[test]
+    fn checked_in_bindings_are_current() {
+        let path = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join(BINDINGS_PATH);
+        let current = std::fs::read_to_string(&path).unwrap_or_default();
+        assert!(
+            current == typescript_definitions(),
+            "{} is stale, run `cargo run --example ts_bindings`",
+            BINDINGS_PATH
+        );
+    }
+}
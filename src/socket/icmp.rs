@@ -0,0 +1,673 @@
+use std::io;
+use std::net::{IpAddr, SocketAddr};
+use std::time::Duration;
+
+/// Default size of the receive loop's packet buffer
+pub const DEFAULT_BUFFER_LEN: usize = 2048;
+/// Largest buffer picked from an interface MTU
+pub const MAX_BUFFER_LEN: usize = 65535;
+/// Room for the IP and ICMP headers on top of a full-MTU packet
+const HEADER_ROOM: usize = 128;
+
+/// Buffer length that holds a full packet on a link with `mtu`, e.g. 9128
+/// for 9000-byte jumbo frames. Never below `DEFAULT_BUFFER_LEN`.
+pub fn buffer_len_for_mtu(mtu: Option<u32>) -> usize {
+    mtu.map_or(DEFAULT_BUFFER_LEN, |mtu| {
+        (mtu as usize + HEADER_ROOM).clamp(DEFAULT_BUFFER_LEN, MAX_BUFFER_LEN)
+    })
+}
+
+/// One packet read by the receive loop
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Received {
+    pub len: usize,
+    /// Kernel drop counter attached to the packet
+    pub drops: Option<u32>,
+    /// The packet did not fit the buffer and was cut to `len` bytes
+    pub truncated: bool,
+    /// Sender of the packet
+    pub from: Option<IpAddr>,
+    /// TTL or hop limit the packet arrived with, where the OS reports it
+    pub ttl: Option<u8>,
+}
+
+/// Socket options for the ICMP receiver
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct IcmpConfig {
+    /// `SO_RCVBUF` to request. The OS default is kept when `None`.
+    pub recv_buffer_size: Option<usize>,
+    /// Size of the buffer each packet is read into
+    pub buffer_len: usize,
+    /// Count packets dropped by the kernel, where supported
+    pub count_drops: bool,
+}
+
+impl Default for IcmpConfig {
+    fn default() -> Self {
+        IcmpConfig {
+            recv_buffer_size: None,
+            buffer_len: DEFAULT_BUFFER_LEN,
+            count_drops: true,
+        }
+    }
+}
+
+impl IcmpConfig {
+    /// Default config with a buffer sized for the interface MTU, so replies
+    /// on jumbo-frame links are not truncated
+    pub fn for_mtu(mtu: Option<u32>) -> IcmpConfig {
+        IcmpConfig {
+            buffer_len: buffer_len_for_mtu(mtu),
+            ..IcmpConfig::default()
+        }
+    }
+}
+
+/// Kind of ICMP socket opened by [`open_icmp`]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum IcmpSocketKind {
+    /// `SOCK_DGRAM`, usable without root where the OS allows it (Linux
+    /// within `net.ipv4.ping_group_range`, macOS)
+    Datagram,
+    /// `SOCK_RAW`, needs root or `CAP_NET_RAW`
+    Raw,
+}
+
+impl IcmpSocketKind {
+    /// Whether received IPv4 packets start with the IP header. Linux strips
+    /// it on datagram sockets; macOS keeps it.
+    pub fn has_ip_header(self, ipv6: bool) -> bool {
+        !ipv6 && (self == IcmpSocketKind::Raw || cfg!(target_os = "macos"))
+    }
+    /// Whether the kernel replaces the echo identifier with the socket's
+    /// local port, as Linux does on datagram sockets
+    pub fn rewrites_id(self) -> bool {
+        self == IcmpSocketKind::Datagram && cfg!(any(target_os = "linux", target_os = "android"))
+    }
+    /// ICMP message of a packet received on this kind of socket
+    pub fn icmp_message(self, packet: &[u8], ipv6: bool) -> Option<&[u8]> {
+        if self.has_ip_header(ipv6) {
+            crate::ping::icmp::strip_ipv4_header(packet)
+        } else {
+            Some(packet)
+        }
+    }
+}
+
+/// Open with `open`, preferring the unprivileged datagram socket and
+/// falling back to a raw one. Returns the raw socket's error if neither
+/// can be opened.
+pub fn open_preferring_datagram<S, F>(mut open: F) -> io::Result<(S, IcmpSocketKind)>
+where
+    F: FnMut(IcmpSocketKind) -> io::Result<S>,
+{
+    match open(IcmpSocketKind::Datagram) {
+        Ok(socket) => Ok((socket, IcmpSocketKind::Datagram)),
+        Err(_) => open(IcmpSocketKind::Raw).map(|socket| (socket, IcmpSocketKind::Raw)),
+    }
+}
+
+/// Tracks the kernel's cumulative drop counter across received packets
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct DropCounter {
+    last: Option<u32>,
+    dropped: u64,
+}
+
+impl DropCounter {
+    pub fn new() -> DropCounter {
+        DropCounter::default()
+    }
+    /// Feed the counter value reported with a packet
+    pub fn observe(&mut self, counter: u32) {
+        let prev = self.last.unwrap_or(0);
+        self.dropped += counter.wrapping_sub(prev) as u64;
+        self.last = Some(counter);
+    }
+    /// Packets dropped since the counter was created
+    pub fn dropped(&self) -> u64 {
+        self.dropped
+    }
+}
+
+/// ICMP or ICMPv6 socket opened by [`open_icmp`] and set up with an
+/// [`IcmpConfig`]
+#[derive(Debug)]
+pub struct IcmpSocket {
+    #[cfg(unix)]
+    fd: std::os::fd::OwnedFd,
+    #[cfg(not(unix))]
+    never: std::convert::Infallible,
+    kind: IcmpSocketKind,
+    ipv6: bool,
+    counts_drops: bool,
+}
+
+impl IcmpSocket {
+    /// Open a socket for `ipv6` or IPv4, preferring the unprivileged kind
+    #[cfg(unix)]
+    pub fn open(ipv6: bool, config: &IcmpConfig) -> io::Result<IcmpSocket> {
+        use std::os::fd::AsRawFd;
+        let (fd, kind) = open_icmp(ipv6)?;
+        let counts_drops = apply(fd.as_raw_fd(), config)?;
+        // Without it the TTL is only known where the IP header is delivered
+        let _ = sys::enable_ttl(fd.as_raw_fd(), ipv6);
+        Ok(IcmpSocket {
+            fd,
+            kind,
+            ipv6,
+            counts_drops,
+        })
+    }
+    #[cfg(not(unix))]
+    pub fn open(_ipv6: bool, _config: &IcmpConfig) -> io::Result<IcmpSocket> {
+        Err(io::Error::from(io::ErrorKind::Unsupported))
+    }
+    /// Open a socket whose echo requests go out with identifier `id`.
+    /// A datagram socket whose kernel rewrites the identifier is bound to
+    /// it. If the identifier is taken, a `pinned` one falls back to a raw
+    /// socket, and otherwise the kernel's choice is kept.
+    #[cfg(unix)]
+    pub fn open_with_id(
+        ipv6: bool,
+        config: &IcmpConfig,
+        id: u16,
+        pinned: bool,
+    ) -> io::Result<IcmpSocket> {
+        use std::os::fd::AsRawFd;
+        let mut socket = IcmpSocket::open(ipv6, config)?;
+        if !socket.kind.rewrites_id() {
+            return Ok(socket);
+        }
+        match sys::bind_id(socket.fd.as_raw_fd(), ipv6, id) {
+            Ok(()) => Ok(socket),
+            Err(e) if pinned => {
+                let fd = open_icmp_kind(ipv6, IcmpSocketKind::Raw).map_err(|_| e)?;
+                socket.counts_drops = apply(fd.as_raw_fd(), config)?;
+                let _ = sys::enable_ttl(fd.as_raw_fd(), ipv6);
+                socket.fd = fd;
+                socket.kind = IcmpSocketKind::Raw;
+                Ok(socket)
+            }
+            Err(_) => Ok(socket),
+        }
+    }
+    #[cfg(not(unix))]
+    pub fn open_with_id(
+        _ipv6: bool,
+        _config: &IcmpConfig,
+        _id: u16,
+        _pinned: bool,
+    ) -> io::Result<IcmpSocket> {
+        Err(io::Error::from(io::ErrorKind::Unsupported))
+    }
+    pub fn kind(&self) -> IcmpSocketKind {
+        self.kind
+    }
+    pub fn is_ipv6(&self) -> bool {
+        self.ipv6
+    }
+    /// Whether received packets carry the kernel drop counter
+    pub fn counts_drops(&self) -> bool {
+        self.counts_drops
+    }
+    /// Identifier echo requests carrying `id` leave with: the socket's
+    /// local port where the kernel rewrites it, `id` otherwise. `None` until
+    /// the kernel has picked a port for an unbound socket.
+    #[cfg(unix)]
+    pub fn sent_id(&self, id: u16) -> Option<u16> {
+        use std::os::fd::AsRawFd;
+        if !self.kind.rewrites_id() {
+            return Some(id);
+        }
+        sys::local_port(self.fd.as_raw_fd()).filter(|port| *port != 0)
+    }
+    #[cfg(not(unix))]
+    pub fn sent_id(&self, _id: u16) -> Option<u16> {
+        match self.never {}
+    }
+    /// Send an ICMP message to `dst`. The port is ignored; the scope id of
+    /// an IPv6 `dst` picks the interface of a link-local destination.
+    #[cfg(unix)]
+    pub fn send_to(&self, message: &[u8], dst: SocketAddr) -> io::Result<()> {
+        use std::os::fd::AsRawFd;
+        let (_, raw, len) = crate::socket::sockaddr(dst);
+        let n = unsafe {
+            libc::sendto(
+                self.fd.as_raw_fd(),
+                message.as_ptr() as *const libc::c_void,
+                message.len(),
+                0,
+                &raw as *const _ as *const libc::sockaddr,
+                len,
+            )
+        };
+        if n < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+    #[cfg(not(unix))]
+    pub fn send_to(&self, _message: &[u8], _dst: SocketAddr) -> io::Result<()> {
+        match self.never {}
+    }
+    /// Wait up to `timeout` for a packet to read. False on timeout.
+    #[cfg(unix)]
+    pub fn wait_readable(&self, timeout: Duration) -> io::Result<bool> {
+        use std::os::fd::AsRawFd;
+        let mut pfd = libc::pollfd {
+            fd: self.fd.as_raw_fd(),
+            events: libc::POLLIN,
+            revents: 0,
+        };
+        let ms = timeout.as_millis().min(libc::c_int::MAX as u128) as libc::c_int;
+        match unsafe { libc::poll(&mut pfd, 1, ms) } {
+            n if n < 0 => {
+                let e = io::Error::last_os_error();
+                if e.kind() == io::ErrorKind::Interrupted {
+                    Ok(false)
+                } else {
+                    Err(e)
+                }
+            }
+            n => Ok(n > 0),
+        }
+    }
+    #[cfg(not(unix))]
+    pub fn wait_readable(&self, _timeout: Duration) -> io::Result<bool> {
+        match self.never {}
+    }
+    /// Read one packet, see [`recv_with_drops`]
+    #[cfg(unix)]
+    pub fn recv(&self, buf: &mut [u8]) -> io::Result<Received> {
+        use std::os::fd::AsRawFd;
+        recv_with_drops(self.fd.as_raw_fd(), buf)
+    }
+    #[cfg(not(unix))]
+    pub fn recv(&self, _buf: &mut [u8]) -> io::Result<Received> {
+        match self.never {}
+    }
+}
+
+#[cfg(unix)]
+mod sys {
+    use super::{open_preferring_datagram, IcmpConfig, IcmpSocketKind, Received};
+    use std::io;
+    use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+    use std::os::fd::{FromRawFd, OwnedFd, RawFd};
+
+    /// ICMP or ICMPv6 socket of `kind`
+    pub fn open_icmp_kind(ipv6: bool, kind: IcmpSocketKind) -> io::Result<OwnedFd> {
+        let (domain, protocol) = if ipv6 {
+            (libc::AF_INET6, libc::IPPROTO_ICMPV6)
+        } else {
+            (libc::AF_INET, libc::IPPROTO_ICMP)
+        };
+        let ty = match kind {
+            IcmpSocketKind::Datagram => libc::SOCK_DGRAM,
+            IcmpSocketKind::Raw => libc::SOCK_RAW,
+        };
+        let fd = unsafe { libc::socket(domain, ty, protocol) };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(unsafe { OwnedFd::from_raw_fd(fd) })
+    }
+
+    /// ICMP socket, unprivileged where the OS allows and raw otherwise
+    pub fn open_icmp(ipv6: bool) -> io::Result<(OwnedFd, IcmpSocketKind)> {
+        open_preferring_datagram(|kind| open_icmp_kind(ipv6, kind))
+    }
+
+    fn set_int(fd: RawFd, level: i32, name: i32, value: i32) -> io::Result<()> {
+        let ret = unsafe {
+            libc::setsockopt(
+                fd,
+                level,
+                name,
+                &value as *const i32 as *const libc::c_void,
+                std::mem::size_of::<i32>() as libc::socklen_t,
+            )
+        };
+        if ret != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+
+    /// Current `SO_RCVBUF`. Linux reports double the requested size to
+    /// account for bookkeeping overhead.
+    pub fn recv_buffer_size(fd: RawFd) -> io::Result<usize> {
+        let mut value: i32 = 0;
+        let mut len = std::mem::size_of::<i32>() as libc::socklen_t;
+        let ret = unsafe {
+            libc::getsockopt(
+                fd,
+                libc::SOL_SOCKET,
+                libc::SO_RCVBUF,
+                &mut value as *mut i32 as *mut libc::c_void,
+                &mut len,
+            )
+        };
+        if ret != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(value as usize)
+    }
+
+    pub fn set_recv_buffer_size(fd: RawFd, size: usize) -> io::Result<()> {
+        let size = i32::try_from(size).unwrap_or(i32::MAX);
+        set_int(fd, libc::SOL_SOCKET, libc::SO_RCVBUF, size)
+    }
+
+    /// Bind a datagram socket to echo identifier `id`, which Linux sends
+    /// in place of the one in each request
+    pub fn bind_id(fd: RawFd, ipv6: bool, id: u16) -> io::Result<()> {
+        let any = if ipv6 {
+            IpAddr::V6(Ipv6Addr::UNSPECIFIED)
+        } else {
+            IpAddr::V4(Ipv4Addr::UNSPECIFIED)
+        };
+        let (_, raw, len) = crate::socket::sockaddr(SocketAddr::new(any, id));
+        if unsafe { libc::bind(fd, &raw as *const _ as *const libc::sockaddr, len) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+
+    /// Port the socket is bound to
+    pub fn local_port(fd: RawFd) -> Option<u16> {
+        let mut storage: libc::sockaddr_storage = unsafe { std::mem::zeroed() };
+        let mut len = std::mem::size_of_val(&storage) as libc::socklen_t;
+        let ret = unsafe {
+            libc::getsockname(fd, &mut storage as *mut _ as *mut libc::sockaddr, &mut len)
+        };
+        if ret != 0 {
+            return None;
+        }
+        crate::socket::from_sockaddr(&storage).map(|addr| addr.port())
+    }
+
+    /// Ask the kernel to attach its drop counter to received packets
+    #[cfg(target_os = "linux")]
+    pub fn enable_drop_counter(fd: RawFd) -> io::Result<()> {
+        set_int(fd, libc::SOL_SOCKET, libc::SO_RXQ_OVFL, 1)
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    pub fn enable_drop_counter(_fd: RawFd) -> io::Result<()> {
+        Err(io::Error::from(io::ErrorKind::Unsupported))
+    }
+
+    /// Ask the kernel to attach the TTL or hop limit to received packets
+    #[cfg(target_os = "linux")]
+    pub fn enable_ttl(fd: RawFd, ipv6: bool) -> io::Result<()> {
+        if ipv6 {
+            set_int(fd, libc::IPPROTO_IPV6, libc::IPV6_RECVHOPLIMIT, 1)
+        } else {
+            set_int(fd, libc::IPPROTO_IP, libc::IP_RECVTTL, 1)
+        }
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    pub fn enable_ttl(_fd: RawFd, _ipv6: bool) -> io::Result<()> {
+        Err(io::Error::from(io::ErrorKind::Unsupported))
+    }
+
+    /// Apply `config` to the socket. Returns whether drops are counted.
+    pub fn apply(fd: RawFd, config: &IcmpConfig) -> io::Result<bool> {
+        if let Some(size) = config.recv_buffer_size {
+            set_recv_buffer_size(fd, size)?;
+        }
+        Ok(config.count_drops && enable_drop_counter(fd).is_ok())
+    }
+
+    /// Receive one packet along with its sender and the kernel drop counter
+    /// and TTL, if attached
+    pub fn recv_with_drops(fd: RawFd, buf: &mut [u8]) -> io::Result<Received> {
+        let mut iov = libc::iovec {
+            iov_base: buf.as_mut_ptr() as *mut libc::c_void,
+            iov_len: buf.len(),
+        };
+        // Room for a few int-sized control messages, aligned for cmsghdr
+        let mut control = [0u64; 16];
+        let mut from: libc::sockaddr_storage = unsafe { std::mem::zeroed() };
+        let mut msg: libc::msghdr = unsafe { std::mem::zeroed() };
+        msg.msg_name = &mut from as *mut _ as *mut libc::c_void;
+        msg.msg_namelen = std::mem::size_of_val(&from) as libc::socklen_t;
+        msg.msg_iov = &mut iov;
+        msg.msg_iovlen = 1;
+        msg.msg_control = control.as_mut_ptr() as *mut libc::c_void;
+        msg.msg_controllen = std::mem::size_of_val(&control) as _;
+        let n = unsafe { libc::recvmsg(fd, &mut msg, 0) };
+        if n < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let from = (msg.msg_namelen > 0)
+            .then(|| crate::socket::from_sockaddr(&from))
+            .flatten()
+            .map(|addr| addr.ip());
+        let (drops, ttl) = control_values(&msg);
+        Ok(Received {
+            len: n as usize,
+            drops,
+            truncated: msg.msg_flags & libc::MSG_TRUNC != 0,
+            from,
+            ttl,
+        })
+    }
+
+    /// Drop counter and TTL from the control messages of `msg`
+    #[cfg(target_os = "linux")]
+    fn control_values(msg: &libc::msghdr) -> (Option<u32>, Option<u8>) {
+        let (mut drops, mut ttl) = (None, None);
+        let mut cmsg = unsafe { libc::CMSG_FIRSTHDR(msg) };
+        while !cmsg.is_null() {
+            let hdr = unsafe { &*cmsg };
+            let data = unsafe { libc::CMSG_DATA(cmsg) };
+            match (hdr.cmsg_level, hdr.cmsg_type) {
+                (libc::SOL_SOCKET, libc::SO_RXQ_OVFL) => {
+                    drops = Some(unsafe { (data as *const u32).read_unaligned() });
+                }
+                (libc::IPPROTO_IP, libc::IP_TTL) | (libc::IPPROTO_IPV6, libc::IPV6_HOPLIMIT) => {
+                    let value = unsafe { (data as *const libc::c_int).read_unaligned() };
+                    ttl = u8::try_from(value).ok();
+                }
+                _ => {}
+            }
+            cmsg = unsafe { libc::CMSG_NXTHDR(msg, cmsg) };
+        }
+        (drops, ttl)
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    fn control_values(_msg: &libc::msghdr) -> (Option<u32>, Option<u8>) {
+        (None, None)
+    }
+}
+
+#[cfg(unix)]
+pub use sys::{
+    apply, enable_drop_counter, open_icmp, open_icmp_kind, recv_buffer_size, recv_with_drops,
+    set_recv_buffer_size,
+};
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn drop_counter_accumulates_deltas() {
+        let mut counter = DropCounter::new();
+        counter.observe(0);
+        counter.observe(3);
+        counter.observe(3);
+        counter.observe(10);
+        assert_eq!(counter.dropped(), 10);
+        // The kernel counter is a wrapping u32
+        let mut counter = DropCounter::new();
+        counter.observe(u32::MAX - 1);
+        counter.observe(1);
+        assert_eq!(counter.dropped(), u32::MAX as u64 + 2);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn recv_buffer_set_to_requested_size() {
+        use std::os::fd::AsRawFd;
+        let socket = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+        let config = IcmpConfig {
+            recv_buffer_size: Some(64 * 1024),
+            ..IcmpConfig::default()
+        };
+        apply(socket.as_raw_fd(), &config).unwrap();
+        let size = recv_buffer_size(socket.as_raw_fd()).unwrap();
+        assert!(size >= 64 * 1024, "SO_RCVBUF is {}", size);
+        assert!(size <= 2 * 64 * 1024, "SO_RCVBUF is {}", size);
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn overflow_reports_drops() {
+        use std::os::fd::AsRawFd;
+        let rx = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+        let config = IcmpConfig {
+            recv_buffer_size: Some(4096),
+            ..IcmpConfig::default()
+        };
+        assert!(apply(rx.as_raw_fd(), &config).unwrap());
+        let tx = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+        for _ in 0..64 {
+            tx.send_to(&[0u8; 1024], rx.local_addr().unwrap()).unwrap();
+        }
+        // The counter is attached to packets queued after the overflow, so
+        // drain the queue and send one more
+        rx.set_nonblocking(true).unwrap();
+        let mut buf = [0u8; DEFAULT_BUFFER_LEN];
+        let mut counter = DropCounter::new();
+        while recv_with_drops(rx.as_raw_fd(), &mut buf).is_ok() {}
+        tx.send_to(&[0u8; 8], rx.local_addr().unwrap()).unwrap();
+        rx.set_nonblocking(false).unwrap();
+        let received = recv_with_drops(rx.as_raw_fd(), &mut buf).unwrap();
+        assert_eq!(received.len, 8);
+        counter.observe(received.drops.unwrap());
+        assert!(counter.dropped() > 0);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn jumbo_reply_fits_enlarged_buffer() {
+        use crate::ping::icmp::{build_echo, parse_echo, Echo, EchoKind};
+        use std::os::fd::AsRawFd;
+        let reply = Echo {
+            kind: EchoKind::Reply,
+            id: 7,
+            seq: 1,
+            payload: vec![0xab; 8000],
+        };
+        let packet = build_echo(&reply, false);
+        let rx = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+        let tx = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+        let config = IcmpConfig::for_mtu(Some(9000));
+        assert_eq!(config.buffer_len, 9128);
+        assert_eq!(buffer_len_for_mtu(Some(1500)), DEFAULT_BUFFER_LEN);
+
+        let mut small = vec![0u8; DEFAULT_BUFFER_LEN];
+        tx.send_to(&packet, rx.local_addr().unwrap()).unwrap();
+        let cut = recv_with_drops(rx.as_raw_fd(), &mut small).unwrap();
+        assert!(cut.truncated);
+
+        let mut buf = vec![0u8; config.buffer_len];
+        tx.send_to(&packet, rx.local_addr().unwrap()).unwrap();
+        let received = recv_with_drops(rx.as_raw_fd(), &mut buf).unwrap();
+        assert!(!received.truncated);
+        assert_eq!(parse_echo(&buf[..received.len], false), Some(reply));
+    }
+
+    #[test]
+    fn datagram_preferred_and_raw_as_fallback() {
+        let denied = || io::Error::from(io::ErrorKind::PermissionDenied);
+        let chosen = open_preferring_datagram(Ok).unwrap();
+        assert_eq!(chosen.1, IcmpSocketKind::Datagram);
+        let chosen = open_preferring_datagram(|kind| match kind {
+            IcmpSocketKind::Datagram => Err(denied()),
+            IcmpSocketKind::Raw => Ok(()),
+        })
+        .unwrap();
+        assert_eq!(chosen.1, IcmpSocketKind::Raw);
+        let err = open_preferring_datagram::<(), _>(|_| Err(denied())).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::PermissionDenied);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn datagram_socket_used_when_permitted() {
+        let datagram_allowed = open_icmp_kind(false, IcmpSocketKind::Datagram).is_ok();
+        match open_icmp(false) {
+            Ok((_, kind)) if datagram_allowed => assert_eq!(kind, IcmpSocketKind::Datagram),
+            Ok((_, kind)) => assert_eq!(kind, IcmpSocketKind::Raw),
+            // Neither allowed for this user
+            Err(_) => assert!(!datagram_allowed),
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn pinned_id_is_sent() {
+        use crate::ping::icmp::{build_echo, parse_echo, Echo, EchoKind};
+        let id = crate::ping::icmp::random_id();
+        let Ok(socket) = IcmpSocket::open_with_id(false, &IcmpConfig::default(), id, true) else {
+            // This user may open neither kind of ICMP socket
+            return;
+        };
+        let request = Echo {
+            kind: EchoKind::Request,
+            id,
+            seq: 9,
+            payload: b"pinned id".to_vec(),
+        };
+        let dst = SocketAddr::from(([127, 0, 0, 1], 0));
+        socket.send_to(&build_echo(&request, false), dst).unwrap();
+        assert_eq!(socket.sent_id(id), Some(id));
+        // The reply echoes the identifier the request went out with
+        let mut buf = [0u8; DEFAULT_BUFFER_LEN];
+        let reply = loop {
+            assert!(socket.wait_readable(Duration::from_secs(2)).unwrap());
+            let received = socket.recv(&mut buf).unwrap();
+            let message = socket.kind().icmp_message(&buf[..received.len], false);
+            match message.and_then(|m| parse_echo(m, false)) {
+                Some(echo) if echo.kind == EchoKind::Reply && echo.payload == request.payload => {
+                    break echo
+                }
+                _ => continue,
+            }
+        };
+        assert_eq!(reply.id, id);
+    }
+
+    #[test]
+    fn icmp_message_follows_socket_kind() {
+        use crate::ping::icmp::{build_echo, Echo, EchoKind};
+        let echo = build_echo(
+            &Echo {
+                kind: EchoKind::Reply,
+                id: 1,
+                seq: 2,
+                payload: vec![0; 8],
+            },
+            false,
+        );
+        let mut packet = vec![
+            0x45, 0, 0, 0, 0, 0, 0, 0, 64, 1, 0, 0, 127, 0, 0, 1, 127, 0, 0, 1,
+        ];
+        packet.extend_from_slice(&echo);
+        let raw = IcmpSocketKind::Raw;
+        assert_eq!(raw.icmp_message(&packet, false), Some(&echo[..]));
+        assert_eq!(raw.icmp_message(&echo, true), Some(&echo[..]));
+        if cfg!(target_os = "linux") {
+            let dgram = IcmpSocketKind::Datagram;
+            assert_eq!(dgram.icmp_message(&echo, false), Some(&echo[..]));
+            assert!(dgram.rewrites_id() && !raw.rewrites_id());
+        }
+    }
+}
@@ -0,0 +1,342 @@
+pub mod icmp;
+pub mod tcp_info;
+
+pub use icmp::IcmpConfig;
+
+use std::io;
+use std::net::{SocketAddr, TcpStream, UdpSocket};
+use std::time::Duration;
+
+/// Replace the OS error for a source port that is taken with one naming it
+fn source_port_error(e: io::Error, src: SocketAddr) -> io::Error {
+    if e.kind() == io::ErrorKind::AddrInUse {
+        io::Error::new(
+            io::ErrorKind::AddrInUse,
+            format!("Source port {} is already in use", src.port()),
+        )
+    } else {
+        e
+    }
+}
+
+/// Bind a UDP socket to `src`, with a clear error if its port is taken
+pub fn bind_udp(src: SocketAddr) -> io::Result<UdpSocket> {
+    UdpSocket::bind(src).map_err(|e| source_port_error(e, src))
+}
+
+/// Bind a UDP socket to `src` with `SO_REUSEADDR`, so it can share a port
+/// such as mDNS 5353 with a daemon already listening on it
+#[cfg(unix)]
+pub fn bind_udp_shared(src: SocketAddr) -> io::Result<UdpSocket> {
+    use std::os::fd::{FromRawFd, OwnedFd};
+
+    let (family, raw, len) = sockaddr(src);
+    let fd = unsafe { libc::socket(family, libc::SOCK_DGRAM, 0) };
+    if fd < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    let owned = unsafe { OwnedFd::from_raw_fd(fd) };
+    let one: libc::c_int = 1;
+    let ret = unsafe {
+        libc::setsockopt(
+            fd,
+            libc::SOL_SOCKET,
+            libc::SO_REUSEADDR,
+            &one as *const libc::c_int as *const libc::c_void,
+            std::mem::size_of::<libc::c_int>() as libc::socklen_t,
+        )
+    };
+    if ret < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    if unsafe { libc::bind(fd, &raw as *const _ as *const libc::sockaddr, len) } < 0 {
+        return Err(source_port_error(io::Error::last_os_error(), src));
+    }
+    Ok(UdpSocket::from(owned))
+}
+
+/// Bind a UDP socket to `src`; the port is not shared on this platform
+#[cfg(not(unix))]
+pub fn bind_udp_shared(src: SocketAddr) -> io::Result<UdpSocket> {
+    bind_udp(src)
+}
+
+/// Connect to `dst` from the local address `src`.
+///
+/// `SO_REUSEADDR` is set so a port whose last connection was reset can be
+/// bound again right away.
+#[cfg(unix)]
+pub fn connect_from(src: SocketAddr, dst: SocketAddr, timeout: Duration) -> io::Result<TcpStream> {
+    use std::os::fd::{FromRawFd, OwnedFd};
+    use std::time::Instant;
+
+    fn check(ret: libc::c_int) -> io::Result<libc::c_int> {
+        if ret < 0 {
+            Err(io::Error::last_os_error())
+        } else {
+            Ok(ret)
+        }
+    }
+
+    let (family, src_raw, src_len) = sockaddr(src);
+    let (_, dst_raw, dst_len) = sockaddr(dst);
+    let fd = check(unsafe { libc::socket(family, libc::SOCK_STREAM, 0) })?;
+    // Closes the socket on every early return
+    let owned = unsafe { OwnedFd::from_raw_fd(fd) };
+    let one: libc::c_int = 1;
+    check(unsafe {
+        libc::setsockopt(
+            fd,
+            libc::SOL_SOCKET,
+            libc::SO_REUSEADDR,
+            &one as *const libc::c_int as *const libc::c_void,
+            std::mem::size_of::<libc::c_int>() as libc::socklen_t,
+        )
+    })?;
+    check(unsafe { libc::bind(fd, &src_raw as *const _ as *const libc::sockaddr, src_len) })
+        .map_err(|e| source_port_error(e, src))?;
+    let flags = check(unsafe { libc::fcntl(fd, libc::F_GETFL) })?;
+    check(unsafe { libc::fcntl(fd, libc::F_SETFL, flags | libc::O_NONBLOCK) })?;
+    let ret = unsafe { libc::connect(fd, &dst_raw as *const _ as *const libc::sockaddr, dst_len) };
+    if ret < 0 {
+        let e = io::Error::last_os_error();
+        if e.raw_os_error() != Some(libc::EINPROGRESS) {
+            return Err(e);
+        }
+        let deadline = Instant::now() + timeout;
+        loop {
+            let mut pfd = libc::pollfd {
+                fd,
+                events: libc::POLLOUT,
+                revents: 0,
+            };
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            let ms = remaining.as_millis().clamp(1, libc::c_int::MAX as u128) as libc::c_int;
+            match check(unsafe { libc::poll(&mut pfd, 1, ms) }) {
+                Ok(0) => return Err(io::Error::from(io::ErrorKind::TimedOut)),
+                Ok(_) => break,
+                // A signal cut the wait short; wait out the rest
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(e),
+            }
+        }
+        let mut err: libc::c_int = 0;
+        let mut len = std::mem::size_of::<libc::c_int>() as libc::socklen_t;
+        check(unsafe {
+            libc::getsockopt(
+                fd,
+                libc::SOL_SOCKET,
+                libc::SO_ERROR,
+                &mut err as *mut libc::c_int as *mut libc::c_void,
+                &mut len,
+            )
+        })?;
+        if err != 0 {
+            return Err(io::Error::from_raw_os_error(err));
+        }
+    }
+    check(unsafe { libc::fcntl(fd, libc::F_SETFL, flags) })?;
+    Ok(TcpStream::from(owned))
+}
+
+#[cfg(unix)]
+pub(crate) fn sockaddr(addr: SocketAddr) -> (libc::c_int, libc::sockaddr_storage, libc::socklen_t) {
+    let mut storage: libc::sockaddr_storage = unsafe { std::mem::zeroed() };
+    let len = match addr {
+        SocketAddr::V4(a) => {
+            let sin = unsafe { &mut *(&mut storage as *mut _ as *mut libc::sockaddr_in) };
+            sin.sin_family = libc::AF_INET as libc::sa_family_t;
+            sin.sin_port = a.port().to_be();
+            sin.sin_addr.s_addr = u32::from(*a.ip()).to_be();
+            std::mem::size_of::<libc::sockaddr_in>()
+        }
+        SocketAddr::V6(a) => {
+            let sin6 = unsafe { &mut *(&mut storage as *mut _ as *mut libc::sockaddr_in6) };
+            sin6.sin6_family = libc::AF_INET6 as libc::sa_family_t;
+            sin6.sin6_port = a.port().to_be();
+            sin6.sin6_addr.s6_addr = a.ip().octets();
+            sin6.sin6_scope_id = a.scope_id();
+            std::mem::size_of::<libc::sockaddr_in6>()
+        }
+    };
+    let family = match addr {
+        SocketAddr::V4(_) => libc::AF_INET,
+        SocketAddr::V6(_) => libc::AF_INET6,
+    };
+    (family, storage, len as libc::socklen_t)
+}
+
+/// Address stored by the kernel in `storage`, e.g. the sender of a packet
+#[cfg(unix)]
+pub(crate) fn from_sockaddr(storage: &libc::sockaddr_storage) -> Option<SocketAddr> {
+    match storage.ss_family as libc::c_int {
+        libc::AF_INET => {
+            let sin = unsafe { &*(storage as *const _ as *const libc::sockaddr_in) };
+            let ip = std::net::Ipv4Addr::from(u32::from_be(sin.sin_addr.s_addr));
+            Some(SocketAddr::from((ip, u16::from_be(sin.sin_port))))
+        }
+        libc::AF_INET6 => {
+            let sin6 = unsafe { &*(storage as *const _ as *const libc::sockaddr_in6) };
+            let ip = std::net::Ipv6Addr::from(sin6.sin6_addr.s6_addr);
+            Some(SocketAddr::V6(std::net::SocketAddrV6::new(
+                ip,
+                u16::from_be(sin6.sin6_port),
+                0,
+                sin6.sin6_scope_id,
+            )))
+        }
+        _ => None,
+    }
+}
+
+/// Connect to `dst` from the local address `src`
+#[cfg(not(unix))]
+pub fn connect_from(
+    _src: SocketAddr,
+    _dst: SocketAddr,
+    _timeout: Duration,
+) -> io::Result<TcpStream> {
+    Err(io::Error::from(io::ErrorKind::Unsupported))
+}
+
+/// Wildcard address of the family of `dst` with the given port
+pub fn unspecified_for(dst: SocketAddr, port: u16) -> SocketAddr {
+    match dst {
+        SocketAddr::V4(_) => SocketAddr::from((std::net::Ipv4Addr::UNSPECIFIED, port)),
+        SocketAddr::V6(_) => SocketAddr::from((std::net::Ipv6Addr::UNSPECIFIED, port)),
+    }
+}
+
+/// Make closing `stream` send a reset instead of entering TIME_WAIT, so
+/// repeated connects do not pile up ephemeral ports
+#[cfg(unix)]
+pub fn reset_on_close(stream: &TcpStream) -> io::Result<()> {
+    use std::os::fd::AsRawFd;
+    let linger = libc::linger {
+        l_onoff: 1,
+        l_linger: 0,
+    };
+    let ret = unsafe {
+        libc::setsockopt(
+            stream.as_raw_fd(),
+            libc::SOL_SOCKET,
+            libc::SO_LINGER,
+            &linger as *const libc::linger as *const libc::c_void,
+            std::mem::size_of::<libc::linger>() as libc::socklen_t,
+        )
+    };
+    if ret != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// Make closing `stream` send a reset instead of entering TIME_WAIT
+#[cfg(not(unix))]
+pub fn reset_on_close(_stream: &TcpStream) -> io::Result<()> {
+    Err(io::Error::from(io::ErrorKind::Unsupported))
+}
+
+/// Close `stream` right away. Resets the connection where possible and
+/// falls back to an orderly shutdown, so the descriptor and port are
+/// released even when `SO_LINGER` cannot be set.
+pub fn close_now(stream: TcpStream) {
+    if reset_on_close(&stream).is_err() {
+        let _ = stream.shutdown(std::net::Shutdown::Both);
+    }
+    drop(stream);
+}
+
+/// Number of file descriptors this process has open
+#[cfg(target_os = "linux")]
+pub fn open_fd_count() -> io::Result<usize> {
+    // The directory handle used for listing is counted too
+    Ok(std::fs::read_dir("/proc/self/fd")?
+        .count()
+        .saturating_sub(1))
+}
+
+/// Number of file descriptors this process has open
+#[cfg(all(unix, not(target_os = "linux")))]
+pub fn open_fd_count() -> io::Result<usize> {
+    Ok(std::fs::read_dir("/dev/fd")?.count().saturating_sub(1))
+}
+
+/// Number of file descriptors this process has open
+#[cfg(not(unix))]
+pub fn open_fd_count() -> io::Result<usize> {
+    Err(io::Error::from(io::ErrorKind::Unsupported))
+}
+
+/// Set the Don't Fragment bit on outgoing packets so oversized ones fail
+/// with `EMSGSIZE` instead of being fragmented
+#[cfg(any(target_os = "linux", target_os = "android"))]
+pub fn set_dont_fragment<S: std::os::fd::AsRawFd>(socket: &S, ipv6: bool) -> io::Result<()> {
+    let (level, name, value) = if ipv6 {
+        (
+            libc::IPPROTO_IPV6,
+            libc::IPV6_MTU_DISCOVER,
+            libc::IPV6_PMTUDISC_DO,
+        )
+    } else {
+        (
+            libc::IPPROTO_IP,
+            libc::IP_MTU_DISCOVER,
+            libc::IP_PMTUDISC_DO,
+        )
+    };
+    let ret = unsafe {
+        libc::setsockopt(
+            socket.as_raw_fd(),
+            level,
+            name,
+            &value as *const i32 as *const libc::c_void,
+            std::mem::size_of::<i32>() as libc::socklen_t,
+        )
+    };
+    if ret != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// Set the Don't Fragment bit on outgoing packets
+#[cfg(not(any(target_os = "linux", target_os = "android")))]
+pub fn set_dont_fragment<S>(_socket: &S, _ipv6: bool) -> io::Result<()> {
+    Err(io::Error::from(io::ErrorKind::Unsupported))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::{Ipv4Addr, TcpListener};
+
+    #[cfg(unix)]
+    #[test]
+    fn connects_from_requested_port() {
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        let dst = listener.local_addr().unwrap();
+        // Find a free port, then release it for the connect to take
+        let port = TcpListener::bind((Ipv4Addr::LOCALHOST, 0))
+            .and_then(|l| l.local_addr())
+            .unwrap()
+            .port();
+        let src = SocketAddr::from((Ipv4Addr::LOCALHOST, port));
+        let stream = connect_from(src, dst, Duration::from_secs(1)).unwrap();
+        assert_eq!(stream.local_addr().unwrap(), src);
+        let (_, peer) = listener.accept().unwrap();
+        assert_eq!(peer.port(), port);
+    }
+
+    #[test]
+    fn taken_source_port_is_named() {
+        let taken = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        let src = taken.local_addr().unwrap();
+        let e = bind_udp(src).unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::AddrInUse);
+        assert_eq!(
+            e.to_string(),
+            format!("Source port {} is already in use", src.port())
+        );
+    }
+}
@@ -0,0 +1,201 @@
+//! Per-connection TCP statistics from `TCP_INFO`
+use std::io::{self, Write};
+use std::net::{SocketAddr, TcpStream};
+use std::time::{Duration, Instant};
+use ts_rs::TS;
+
+/// Fields of the kernel's `tcp_info` of interest for diagnosing a path
+#[derive(Clone, Debug, Default, PartialEq, TS)]
+pub struct TcpStats {
+    /// Smoothed RTT
+    pub rtt_ms: f64,
+    /// RTT variance
+    pub rtt_var_ms: f64,
+    /// Retransmission timeout
+    pub rto_ms: f64,
+    /// Segments retransmitted over the connection's lifetime
+    pub total_retrans: u32,
+    /// Segments currently considered lost
+    pub lost: u32,
+    /// Congestion window, in segments
+    pub snd_cwnd: u32,
+    /// Slow start threshold. `None` while still in initial slow start.
+    pub snd_ssthresh: Option<u32>,
+    pub snd_mss: u32,
+    /// Path MTU
+    pub pmtu: u32,
+    /// Segments sent. `None` on kernels that do not report it.
+    pub segs_out: Option<u32>,
+}
+
+impl TcpStats {
+    /// Share of sent segments that were retransmitted
+    pub fn retransmit_rate(&self) -> Option<f64> {
+        self.segs_out
+            .filter(|n| *n > 0)
+            .map(|n| self.total_retrans as f64 / n as f64)
+    }
+
+    #[cfg(target_os = "linux")]
+    pub fn from_raw(info: &libc::tcp_info) -> TcpStats {
+        let ms = |us: u32| us as f64 / 1000.0;
+        TcpStats {
+            rtt_ms: ms(info.tcpi_rtt),
+            rtt_var_ms: ms(info.tcpi_rttvar),
+            rto_ms: ms(info.tcpi_rto),
+            total_retrans: info.tcpi_total_retrans,
+            lost: info.tcpi_lost,
+            snd_cwnd: info.tcpi_snd_cwnd,
+            // The kernel reports "infinite" until the first loss
+            snd_ssthresh: (info.tcpi_snd_ssthresh < 0x7fff_ffff).then_some(info.tcpi_snd_ssthresh),
+            snd_mss: info.tcpi_snd_mss,
+            pmtu: info.tcpi_pmtu,
+            // Older kernels return a shorter struct, leaving it zeroed
+            segs_out: (info.tcpi_segs_out > 0).then_some(info.tcpi_segs_out),
+        }
+    }
+}
+
+/// Statistics of a connected socket
+#[cfg(target_os = "linux")]
+pub fn tcp_stats(stream: &TcpStream) -> io::Result<TcpStats> {
+    use std::os::fd::AsRawFd;
+    let mut info: libc::tcp_info = unsafe { std::mem::zeroed() };
+    let mut len = std::mem::size_of::<libc::tcp_info>() as libc::socklen_t;
+    let ret = unsafe {
+        libc::getsockopt(
+            stream.as_raw_fd(),
+            libc::IPPROTO_TCP,
+            libc::TCP_INFO,
+            &mut info as *mut libc::tcp_info as *mut libc::c_void,
+            &mut len,
+        )
+    };
+    if ret != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(TcpStats::from_raw(&info))
+}
+
+/// Statistics of a connected socket
+#[cfg(not(target_os = "linux"))]
+pub fn tcp_stats(_stream: &TcpStream) -> io::Result<TcpStats> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "TCP_INFO is not available on this platform",
+    ))
+}
+
+/// Settings for [`tcp_info_probe`]
+#[derive(Clone, Debug, PartialEq, Eq, TS)]
+pub struct TcpInfoSetting {
+    pub dst: SocketAddr,
+    /// Filler bytes sent before reading the statistics, so the congestion
+    /// window and retransmissions reflect some actual transfer
+    pub transfer_bytes: usize,
+    #[ts(type = "number")]
+    pub timeout_ms: u64,
+}
+
+impl TcpInfoSetting {
+    pub fn new(dst: SocketAddr) -> TcpInfoSetting {
+        TcpInfoSetting {
+            dst,
+            transfer_bytes: 0,
+            timeout_ms: 3000,
+        }
+    }
+}
+
+/// Result of [`tcp_info_probe`]
+#[derive(Clone, Debug, PartialEq, TS)]
+pub struct TcpInfoReport {
+    pub dst: SocketAddr,
+    pub connect_ms: Option<f64>,
+    #[ts(type = "number")]
+    pub bytes_sent: u64,
+    /// `None` when the connect failed or the platform lacks `TCP_INFO`
+    pub stats: Option<TcpStats>,
+    pub error: Option<String>,
+}
+
+/// Connect to `setting.dst`, optionally send some data, and report the
+/// kernel's view of the connection
+pub fn tcp_info_probe(setting: &TcpInfoSetting) -> TcpInfoReport {
+    let timeout = Duration::from_millis(setting.timeout_ms);
+    let mut report = TcpInfoReport {
+        dst: setting.dst,
+        connect_ms: None,
+        bytes_sent: 0,
+        stats: None,
+        error: None,
+    };
+    let started = Instant::now();
+    let mut stream = match TcpStream::connect_timeout(&setting.dst, timeout) {
+        Ok(stream) => stream,
+        Err(e) => {
+            report.error = Some(e.to_string());
+            return report;
+        }
+    };
+    report.connect_ms = Some(started.elapsed().as_secs_f64() * 1000.0);
+    if setting.transfer_bytes > 0 {
+        let sent = stream
+            .set_write_timeout(Some(timeout))
+            .and_then(|_| stream.write_all(&vec![0x5a; setting.transfer_bytes]))
+            .and_then(|_| stream.flush());
+        match sent {
+            Ok(()) => report.bytes_sent = setting.transfer_bytes as u64,
+            Err(e) => report.error = Some(e.to_string()),
+        }
+    }
+    match tcp_stats(&stream) {
+        Ok(stats) => report.stats = Some(stats),
+        Err(e) => {
+            report.error.get_or_insert(e.to_string());
+        }
+    }
+    report
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn raw_tcp_info_maps_to_stats() {
+        let mut info: libc::tcp_info = unsafe { std::mem::zeroed() };
+        info.tcpi_rtt = 12_500;
+        info.tcpi_rttvar = 3_000;
+        info.tcpi_rto = 204_000;
+        info.tcpi_total_retrans = 5;
+        info.tcpi_snd_cwnd = 10;
+        info.tcpi_snd_ssthresh = 0x7fff_ffff;
+        info.tcpi_snd_mss = 1448;
+        info.tcpi_segs_out = 200;
+        let stats = TcpStats::from_raw(&info);
+        assert_eq!((stats.rtt_ms, stats.rtt_var_ms), (12.5, 3.0));
+        assert_eq!(stats.rto_ms, 204.0);
+        assert_eq!((stats.snd_cwnd, stats.snd_ssthresh), (10, None));
+        assert_eq!(stats.retransmit_rate(), Some(0.025));
+
+        info.tcpi_segs_out = 0;
+        assert_eq!(TcpStats::from_raw(&info).retransmit_rate(), None);
+    }
+
+    #[test]
+    fn local_connection_reports_or_explains() {
+        let listener = std::net::TcpListener::bind(("127.0.0.1", 0)).unwrap();
+        let mut setting = TcpInfoSetting::new(listener.local_addr().unwrap());
+        setting.transfer_bytes = 16 * 1024;
+        let report = tcp_info_probe(&setting);
+        assert!(report.connect_ms.is_some());
+        assert_eq!(report.bytes_sent, 16 * 1024);
+        if cfg!(target_os = "linux") {
+            assert!(report.stats.unwrap().snd_mss > 0);
+        } else {
+            assert!(report.error.unwrap().contains("not available"));
+        }
+    }
+}
@@ -0,0 +1,704 @@
+//! Speedtest
+pub mod series;
+pub mod server;
+
+use crate::cancel::CancellationToken;
+use crate::grade::{Grade, GradeThresholds};
+use crate::probe::cancellable_timeout;
+use crate::progress::{ProgressSetting, ThrottledProgress};
+use std::io::{self, Read, Write};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+use ts_rs::TS;
+
+/// Default minimum interval between progress updates
+pub const TICK: Duration = Duration::from_millis(250);
+pub const DEFAULT_DURATION_MS: u64 = 10_000;
+pub const DEFAULT_WARMUP_MS: u64 = 1_000;
+/// Time given to a stopped test to report what it measured
+pub const DEFAULT_STOP_GRACE: Duration = Duration::from_millis(500);
+const CHUNK_SIZE: usize = 64 * 1024;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, TS)]
+pub enum Direction {
+    Download,
+    Upload,
+}
+
+/// Stage of a running test, in the order they occur
+#[derive(Clone, Copy, Debug, PartialEq, Eq, TS)]
+pub enum SpeedtestPhase {
+    /// Request sent, nothing transferred yet
+    Connecting,
+    /// First `warmup_ms` of the transfer, while the connection ramps up
+    Warmup,
+    Running,
+    /// Transfer over, waiting for the server to acknowledge the upload
+    /// and for the result
+    Finalizing,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, TS)]
+pub enum SpeedtestOutcome {
+    Completed,
+    Canceled,
+    Failed,
+}
+
+/// Settings for a single-direction test
+#[derive(Clone, Debug, PartialEq, TS)]
+pub struct SpeedtestSetting {
+    /// Maximum test duration
+    #[ts(type = "number")]
+    pub duration_ms: u64,
+    /// Stop after this many bytes. Unlimited when `None`.
+    #[ts(type = "number | null")]
+    pub max_bytes: Option<u64>,
+    /// Length of the warmup phase from the first transferred byte. The
+    /// phase is reported only; its bytes still count.
+    #[ts(type = "number")]
+    pub warmup_ms: u64,
+    /// Throttling of progress updates. Progress is the share of the
+    /// duration or of `max_bytes` used, whichever is further along.
+    pub progress: ProgressSetting,
+    /// Thresholds the done payload's `grade` is computed with
+    pub grading: GradeThresholds,
+}
+
+impl Default for SpeedtestSetting {
+    fn default() -> Self {
+        SpeedtestSetting {
+            duration_ms: DEFAULT_DURATION_MS,
+            max_bytes: None,
+            warmup_ms: DEFAULT_WARMUP_MS,
+            progress: ProgressSetting {
+                interval_ms: TICK.as_millis() as u64,
+                step_percent: 0.0,
+            },
+            grading: GradeThresholds::default(),
+        }
+    }
+}
+
+/// Periodic progress of a running test
+#[derive(Clone, Debug, PartialEq, TS)]
+pub struct SpeedtestUpdatePayload {
+    pub direction: Direction,
+    pub phase: SpeedtestPhase,
+    #[ts(type = "number")]
+    pub bytes: u64,
+    #[ts(type = "number")]
+    pub elapsed_ms: u64,
+    pub mbps: f64,
+    /// How far the test is towards its duration or byte limit
+    pub percent: f64,
+}
+
+/// Final result of a test
+#[derive(Clone, Debug, PartialEq, TS)]
+pub struct SpeedtestDonePayload {
+    pub direction: Direction,
+    pub result: SpeedtestOutcome,
+    #[ts(type = "number")]
+    pub bytes: u64,
+    #[ts(type = "number")]
+    pub elapsed_ms: u64,
+    /// Throughput over the transfer, excluding the time to first byte
+    pub mbps: f64,
+    /// Time from the request to the first received chunk, covering
+    /// connection setup and server think time. Downloads only.
+    pub ttfb_ms: Option<f64>,
+    pub error: Option<String>,
+    /// Throughput graded with `setting.grading`. `None` if nothing was
+    /// transferred.
+    pub grade: Option<Grade>,
+}
+
+/// Throughput in megabits per second
+pub fn mbps(bytes: u64, elapsed: Duration) -> f64 {
+    let secs = elapsed.as_secs_f64();
+    if secs <= 0.0 {
+        0.0
+    } else {
+        bytes as f64 * 8.0 / secs / 1_000_000.0
+    }
+}
+
+/// Resolution of the progress passed to the throttle
+const PROGRESS_STEPS: u64 = 10_000;
+
+struct Meter {
+    direction: Direction,
+    start: Instant,
+    /// Arrival of the first downloaded chunk
+    first_byte: Option<Instant>,
+    /// First chunk received or accepted, in either direction
+    first_transfer: Option<Instant>,
+    bytes: u64,
+    /// Phase of the last update, `None` before the first
+    phase: Option<SpeedtestPhase>,
+    throttle: ThrottledProgress,
+    grading: GradeThresholds,
+}
+
+impl Meter {
+    fn new(direction: Direction, setting: &SpeedtestSetting) -> Meter {
+        Meter {
+            direction,
+            start: Instant::now(),
+            first_byte: None,
+            first_transfer: None,
+            bytes: 0,
+            phase: None,
+            throttle: ThrottledProgress::new(setting.progress),
+            grading: setting.grading,
+        }
+    }
+    fn received(&mut self, n: usize) {
+        self.first_byte.get_or_insert_with(Instant::now);
+        self.sent(n);
+    }
+    fn sent(&mut self, n: usize) {
+        self.first_transfer.get_or_insert_with(Instant::now);
+        self.bytes += n as u64;
+    }
+    /// Phase of the transfer so far
+    fn transfer_phase(&self, setting: &SpeedtestSetting) -> SpeedtestPhase {
+        match self.first_transfer {
+            None => SpeedtestPhase::Connecting,
+            Some(t) if t.elapsed() < Duration::from_millis(setting.warmup_ms) => {
+                SpeedtestPhase::Warmup
+            }
+            Some(_) => SpeedtestPhase::Running,
+        }
+    }
+    /// Update announcing `phase`, unless the last one was already in it
+    fn enter(
+        &mut self,
+        phase: SpeedtestPhase,
+        setting: &SpeedtestSetting,
+    ) -> Option<SpeedtestUpdatePayload> {
+        if self.phase == Some(phase) {
+            return None;
+        }
+        self.phase = Some(phase);
+        Some(self.update(self.percent(setting)))
+    }
+    /// Throughput since the first byte, so server latency does not count
+    /// against the bandwidth
+    fn mbps(&self) -> f64 {
+        mbps(self.bytes, self.first_byte.unwrap_or(self.start).elapsed())
+    }
+    fn update(&self, percent: f64) -> SpeedtestUpdatePayload {
+        SpeedtestUpdatePayload {
+            direction: self.direction,
+            phase: self.phase.unwrap_or(SpeedtestPhase::Connecting),
+            bytes: self.bytes,
+            elapsed_ms: self.start.elapsed().as_millis() as u64,
+            mbps: self.mbps(),
+            percent,
+        }
+    }
+    fn percent(&self, setting: &SpeedtestSetting) -> f64 {
+        let elapsed_ms = self.start.elapsed().as_secs_f64() * 1000.0;
+        let by_time = elapsed_ms / setting.duration_ms.max(1) as f64;
+        let by_bytes = match setting.max_bytes {
+            Some(max) => self.bytes as f64 / max.max(1) as f64,
+            None => 0.0,
+        };
+        by_time.max(by_bytes).min(1.0) * 100.0
+    }
+    /// Update to emit now: always on a phase change, otherwise if the
+    /// throttle lets one through
+    fn tick(&mut self, setting: &SpeedtestSetting) -> Option<SpeedtestUpdatePayload> {
+        if let Some(update) = self.enter(self.transfer_phase(setting), setting) {
+            return Some(update);
+        }
+        let done = (self.percent(setting) / 100.0 * PROGRESS_STEPS as f64) as u64;
+        let progress = self.throttle.update(done, PROGRESS_STEPS)?;
+        Some(self.update(progress.percent))
+    }
+    /// Report the final update, which bypasses the throttle, and the result
+    fn finish<F>(
+        &mut self,
+        setting: &SpeedtestSetting,
+        done: SpeedtestDonePayload,
+        on_update: &mut F,
+    ) -> SpeedtestDonePayload
+    where
+        F: FnMut(SpeedtestUpdatePayload),
+    {
+        let percent = match done.result {
+            SpeedtestOutcome::Completed => 100.0,
+            _ => self.percent(setting),
+        };
+        self.phase = Some(SpeedtestPhase::Finalizing);
+        on_update(self.update(percent));
+        done
+    }
+    fn done(&self, result: SpeedtestOutcome, error: Option<String>) -> SpeedtestDonePayload {
+        let mbps = self.mbps();
+        SpeedtestDonePayload {
+            direction: self.direction,
+            result,
+            bytes: self.bytes,
+            elapsed_ms: self.start.elapsed().as_millis() as u64,
+            mbps,
+            grade: (self.bytes > 0).then(|| self.grading.grade_throughput(mbps)),
+            ttfb_ms: self
+                .first_byte
+                .map(|t| t.duration_since(self.start).as_secs_f64() * 1000.0),
+            error,
+        }
+    }
+}
+
+fn finished(meter: &Meter, setting: &SpeedtestSetting) -> bool {
+    meter.start.elapsed() >= Duration::from_millis(setting.duration_ms)
+        || setting.max_bytes.is_some_and(|max| meter.bytes >= max)
+}
+
+/// Read from `body` until the duration or byte limit is hit, or the token is cancelled.
+///
+/// Call right after sending the request: time until the first chunk is
+/// reported as `ttfb_ms` and left out of the throughput. A cancelled test
+/// still reports the bytes received so far.
+pub fn download_test<R, F>(
+    body: &mut R,
+    setting: &SpeedtestSetting,
+    token: &CancellationToken,
+    mut on_update: F,
+) -> SpeedtestDonePayload
+where
+    R: Read,
+    F: FnMut(SpeedtestUpdatePayload),
+{
+    let mut meter = Meter::new(Direction::Download, setting);
+    let mut buf = vec![0u8; CHUNK_SIZE];
+    let done = loop {
+        if token.is_cancelled() {
+            break meter.done(SpeedtestOutcome::Canceled, None);
+        }
+        if finished(&meter, setting) {
+            break meter.done(SpeedtestOutcome::Completed, None);
+        }
+        if let Some(update) = meter.enter(meter.transfer_phase(setting), setting) {
+            on_update(update);
+        }
+        match body.read(&mut buf) {
+            Ok(0) => break meter.done(SpeedtestOutcome::Completed, None),
+            Ok(n) => meter.received(n),
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => break meter.done(SpeedtestOutcome::Failed, Some(e.to_string())),
+        }
+        if let Some(update) = meter.tick(setting) {
+            on_update(update);
+        }
+    };
+    meter.finish(setting, done, &mut on_update)
+}
+
+/// Write filler data to `sink` until the duration or byte limit is hit, or the
+/// token is cancelled.
+pub fn upload_test<W, F>(
+    sink: &mut W,
+    setting: &SpeedtestSetting,
+    token: &CancellationToken,
+    mut on_update: F,
+) -> SpeedtestDonePayload
+where
+    W: Write,
+    F: FnMut(SpeedtestUpdatePayload),
+{
+    let mut meter = Meter::new(Direction::Upload, setting);
+    let buf = vec![0x5au8; CHUNK_SIZE];
+    let done = loop {
+        if token.is_cancelled() {
+            break meter.done(SpeedtestOutcome::Canceled, None);
+        }
+        if finished(&meter, setting) {
+            // The server may only answer once the whole body is in
+            if let Some(update) = meter.enter(SpeedtestPhase::Finalizing, setting) {
+                on_update(update);
+            }
+            break match sink.flush() {
+                Ok(()) => meter.done(SpeedtestOutcome::Completed, None),
+                Err(e) => meter.done(SpeedtestOutcome::Failed, Some(e.to_string())),
+            };
+        }
+        if let Some(update) = meter.enter(meter.transfer_phase(setting), setting) {
+            on_update(update);
+        }
+        let len = match setting.max_bytes {
+            Some(max) => (max - meter.bytes).min(CHUNK_SIZE as u64) as usize,
+            None => CHUNK_SIZE,
+        };
+        match sink.write(&buf[..len]) {
+            Ok(0) => {
+                break meter.done(SpeedtestOutcome::Completed, None);
+            }
+            Ok(n) => meter.sent(n),
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => break meter.done(SpeedtestOutcome::Failed, Some(e.to_string())),
+        }
+        if let Some(update) = meter.tick(setting) {
+            on_update(update);
+        }
+    };
+    meter.finish(setting, done, &mut on_update)
+}
+
+/// Share of its solo throughput below which a direction is considered
+/// starved in a full-duplex test. Fair sharing of a half-duplex medium
+/// leaves each direction about half.
+pub const STARVED_SHARE: f64 = 0.25;
+
+/// Throughput of each direction tested on its own
+#[derive(Clone, Copy, Debug, PartialEq, TS)]
+pub struct DuplexBaseline {
+    pub download_mbps: f64,
+    pub upload_mbps: f64,
+}
+
+/// Result of a download and an upload run at the same time
+#[derive(Clone, Debug, PartialEq, TS)]
+pub struct FullDuplexDonePayload {
+    pub download: SpeedtestDonePayload,
+    pub upload: SpeedtestDonePayload,
+    /// Sum of both directions
+    pub combined_mbps: f64,
+    /// Solo throughput the directions were compared against
+    pub baseline: Option<DuplexBaseline>,
+    /// Direction that kept less than `STARVED_SHARE` of its solo
+    /// throughput. `None` without a baseline.
+    pub starved: Option<Direction>,
+}
+
+/// Direction starved by the other under simultaneous load, if any. Each
+/// direction is compared with its own solo run, so an asymmetric link is
+/// not mistaken for starvation. When both fell short, the one that kept
+/// the smaller share is reported.
+pub fn starved_direction(
+    download_mbps: f64,
+    upload_mbps: f64,
+    baseline: &DuplexBaseline,
+) -> Option<Direction> {
+    let kept = |mbps: f64, solo: f64| (solo > 0.0).then(|| mbps / solo);
+    let download = kept(download_mbps, baseline.download_mbps).filter(|s| *s < STARVED_SHARE);
+    let upload = kept(upload_mbps, baseline.upload_mbps).filter(|s| *s < STARVED_SHARE);
+    match (download, upload) {
+        (Some(d), Some(u)) if u < d => Some(Direction::Upload),
+        (Some(_), _) => Some(Direction::Download),
+        (None, Some(_)) => Some(Direction::Upload),
+        (None, None) => None,
+    }
+}
+
+/// Run `download_test` on `body` and `upload_test` on `sink` concurrently,
+/// under the same duration budget and token. Updates of both directions go
+/// to `on_update`. Starvation is judged against `baseline`, the results of
+/// solo runs of each direction.
+pub fn full_duplex_test<R, W, F>(
+    body: &mut R,
+    sink: &mut W,
+    setting: &SpeedtestSetting,
+    baseline: Option<DuplexBaseline>,
+    token: &CancellationToken,
+    on_update: F,
+) -> FullDuplexDonePayload
+where
+    R: Read + Send,
+    W: Write + Send,
+    F: FnMut(SpeedtestUpdatePayload) + Send,
+{
+    let on_update = std::sync::Mutex::new(on_update);
+    let emit = |update| (on_update.lock().unwrap())(update);
+    let (download, upload) = thread::scope(|s| {
+        let upload = s.spawn(|| upload_test(sink, setting, token, emit));
+        let download = download_test(body, setting, token, emit);
+        (download, upload.join().expect("upload thread panicked"))
+    });
+    FullDuplexDonePayload {
+        combined_mbps: download.mbps + upload.mbps,
+        baseline,
+        starved: baseline.and_then(|b| starved_direction(download.mbps, upload.mbps, &b)),
+        download,
+        upload,
+    }
+}
+
+/// Speedtest running on its own thread
+pub struct SpeedtestHandle {
+    token: CancellationToken,
+    thread: JoinHandle<SpeedtestDonePayload>,
+}
+
+impl SpeedtestHandle {
+    /// Run `test` on a new thread with a fresh cancellation token
+    pub fn spawn<F>(test: F) -> SpeedtestHandle
+    where
+        F: FnOnce(CancellationToken) -> SpeedtestDonePayload + Send + 'static,
+    {
+        let token = CancellationToken::new();
+        let thread_token = token.clone();
+        SpeedtestHandle {
+            token,
+            thread: thread::spawn(move || test(thread_token)),
+        }
+    }
+    pub fn is_finished(&self) -> bool {
+        self.thread.is_finished()
+    }
+    /// Wait for the test to finish on its own
+    pub fn join(self) -> Option<SpeedtestDonePayload> {
+        self.thread.join().ok()
+    }
+    /// Cancel the test and give it `grace` to report a final partial result.
+    ///
+    /// Returns `None` if the test did not wind down in time; it is then left
+    /// to finish in the background and its result is discarded.
+    pub fn stop(self, grace: Duration) -> Option<SpeedtestDonePayload> {
+        self.token.cancel();
+        let finished = cancellable_timeout(&CancellationToken::new(), grace, || {
+            self.thread.is_finished().then_some(())
+        });
+        finished.completed()?;
+        self.thread.join().ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Endless body that yields a small chunk every few milliseconds
+    struct SlowBody;
+
+    impl Read for SlowBody {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            thread::sleep(Duration::from_millis(2));
+            let n = buf.len().min(1024);
+            buf[..n].fill(1);
+            Ok(n)
+        }
+    }
+
+    #[test]
+    fn stop_mid_download_reports_partial_bytes() {
+        let handle = SpeedtestHandle::spawn(|token| {
+            download_test(&mut SlowBody, &SpeedtestSetting::default(), &token, |_| {})
+        });
+        thread::sleep(Duration::from_millis(100));
+        let done = handle
+            .stop(DEFAULT_STOP_GRACE)
+            .expect("stopped within grace");
+        assert_eq!(done.result, SpeedtestOutcome::Canceled);
+        assert!(done.bytes > 0);
+        assert!(done.mbps > 0.0);
+    }
+
+    /// Holds back the first byte, then streams at full speed
+    struct SlowStart {
+        delay: Option<Duration>,
+    }
+
+    impl Read for SlowStart {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            if let Some(delay) = self.delay.take() {
+                thread::sleep(delay);
+            }
+            thread::sleep(Duration::from_millis(1));
+            buf.fill(1);
+            Ok(buf.len())
+        }
+    }
+
+    #[test]
+    fn ttfb_is_reported_apart_from_throughput() {
+        let setting = SpeedtestSetting {
+            max_bytes: Some(20 * CHUNK_SIZE as u64),
+            ..Default::default()
+        };
+        let token = CancellationToken::new();
+        let fast = download_test(&mut SlowStart { delay: None }, &setting, &token, |_| {});
+        let mut body = SlowStart {
+            delay: Some(Duration::from_millis(300)),
+        };
+        let slow = download_test(&mut body, &setting, &token, |_| {});
+        assert!(slow.ttfb_ms.unwrap() >= 300.0);
+        assert!(fast.ttfb_ms.unwrap() < 300.0);
+        assert!(slow.elapsed_ms >= 300);
+        // Same transfer rate once bytes flow, despite the slow first byte.
+        // Over the whole test the slow start would cost well over half.
+        let whole = mbps(slow.bytes, Duration::from_millis(slow.elapsed_ms));
+        assert!(slow.mbps > whole * 2.0, "{} vs {}", slow.mbps, whole);
+        assert!(
+            slow.mbps > fast.mbps / 4.0,
+            "{} vs {}",
+            slow.mbps,
+            fast.mbps
+        );
+
+        let upload = upload_test(&mut io::sink(), &setting, &token, |_| {});
+        assert_eq!(upload.ttfb_ms, None);
+    }
+
+    #[test]
+    fn download_stops_at_byte_limit() {
+        let setting = SpeedtestSetting {
+            max_bytes: Some(4096),
+            ..Default::default()
+        };
+        let done = download_test(&mut SlowBody, &setting, &CancellationToken::new(), |_| {});
+        assert_eq!(done.result, SpeedtestOutcome::Completed);
+        assert_eq!(done.bytes, 4096);
+    }
+
+    #[test]
+    fn upload_writes_up_to_limit() {
+        let setting = SpeedtestSetting {
+            max_bytes: Some(100_000),
+            ..Default::default()
+        };
+        let mut sink = Vec::new();
+        let done = upload_test(&mut sink, &setting, &CancellationToken::new(), |_| {});
+        assert_eq!(done.result, SpeedtestOutcome::Completed);
+        assert_eq!(done.bytes, 100_000);
+        assert_eq!(sink.len(), 100_000);
+    }
+
+    #[test]
+    fn updates_are_throttled_by_percent() {
+        let setting = SpeedtestSetting {
+            max_bytes: Some(200 * CHUNK_SIZE as u64),
+            warmup_ms: 0,
+            progress: ProgressSetting {
+                interval_ms: 0,
+                step_percent: 10.0,
+            },
+            ..Default::default()
+        };
+        let mut updates = Vec::new();
+        let done = upload_test(&mut io::sink(), &setting, &CancellationToken::new(), |u| {
+            updates.push(u)
+        });
+        assert_eq!(done.result, SpeedtestOutcome::Completed);
+        // One per 10% step at most while running, plus the phase changes
+        let running = updates
+            .iter()
+            .filter(|u| u.phase == SpeedtestPhase::Running)
+            .count();
+        assert!(running <= 12, "{} updates", running);
+        assert!(running >= 5, "{} updates", running);
+        let last = updates.last().unwrap();
+        assert_eq!((last.percent, last.bytes), (100.0, done.bytes));
+    }
+
+    #[test]
+    fn cancelled_test_still_sends_final_update() {
+        let token = CancellationToken::new();
+        token.cancel();
+        let mut updates = Vec::new();
+        download_test(&mut SlowBody, &SpeedtestSetting::default(), &token, |u| {
+            updates.push(u)
+        });
+        assert_eq!(updates.len(), 1);
+        assert!(updates[0].percent < 100.0);
+        assert_eq!(updates[0].phase, SpeedtestPhase::Finalizing);
+    }
+
+    #[test]
+    fn download_goes_through_every_phase() {
+        let setting = SpeedtestSetting {
+            duration_ms: 400,
+            warmup_ms: 100,
+            ..Default::default()
+        };
+        let mut body = SlowStart {
+            delay: Some(Duration::from_millis(50)),
+        };
+        let mut phases: Vec<SpeedtestPhase> = Vec::new();
+        let done = download_test(&mut body, &setting, &CancellationToken::new(), |u| {
+            if phases.last() != Some(&u.phase) {
+                phases.push(u.phase);
+            }
+        });
+        assert_eq!(done.result, SpeedtestOutcome::Completed);
+        assert_eq!(
+            phases,
+            vec![
+                SpeedtestPhase::Connecting,
+                SpeedtestPhase::Warmup,
+                SpeedtestPhase::Running,
+                SpeedtestPhase::Finalizing,
+            ]
+        );
+    }
+
+    /// Accepts writes at a bounded pace
+    struct SlowSink;
+
+    impl Write for SlowSink {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            thread::sleep(Duration::from_millis(2));
+            Ok(buf.len().min(4096))
+        }
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn full_duplex_measures_both_directions() {
+        let setting = SpeedtestSetting {
+            duration_ms: 200,
+            ..Default::default()
+        };
+        let mut directions = Vec::new();
+        let started = Instant::now();
+        let done = full_duplex_test(
+            &mut SlowBody,
+            &mut SlowSink,
+            &setting,
+            None,
+            &CancellationToken::new(),
+            |u| directions.push(u.direction),
+        );
+        // Concurrent, so the budget is not spent twice
+        assert!(started.elapsed() < Duration::from_millis(390));
+        assert!(done.download.mbps > 0.0 && done.upload.mbps > 0.0);
+        assert_eq!(done.combined_mbps, done.download.mbps + done.upload.mbps);
+        assert_eq!(done.starved, None);
+        assert!(directions.contains(&Direction::Download));
+        assert!(directions.contains(&Direction::Upload));
+    }
+
+    #[test]
+    fn starvation_judged_against_solo_runs() {
+        // A 100/5 link is asymmetric, not starved
+        let adsl = DuplexBaseline {
+            download_mbps: 100.0,
+            upload_mbps: 5.0,
+        };
+        assert_eq!(starved_direction(90.0, 4.0, &adsl), None);
+        assert_eq!(starved_direction(95.0, 0.5, &adsl), Some(Direction::Upload));
+        let symmetric = DuplexBaseline {
+            download_mbps: 100.0,
+            upload_mbps: 100.0,
+        };
+        assert_eq!(starved_direction(50.0, 45.0, &symmetric), None);
+        assert_eq!(
+            starved_direction(10.0, 90.0, &symmetric),
+            Some(Direction::Download)
+        );
+        assert_eq!(
+            starved_direction(20.0, 5.0, &symmetric),
+            Some(Direction::Upload)
+        );
+        let silent = DuplexBaseline {
+            download_mbps: 0.0,
+            upload_mbps: 0.0,
+        };
+        assert_eq!(starved_direction(0.0, 0.0, &silent), None);
+    }
+}
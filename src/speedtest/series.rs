@@ -0,0 +1,138 @@
+//! Repeated speedtest runs reported as one figure
+use super::{SpeedtestDonePayload, SpeedtestOutcome};
+use crate::cancel::CancellationToken;
+use crate::probe::cancellable_sleep;
+use crate::stats::{median, variance};
+use std::time::Duration;
+use ts_rs::TS;
+
+#[derive(Clone, Debug, PartialEq, Eq, TS)]
+pub struct SeriesSetting {
+    /// Number of runs
+    pub runs: u32,
+    /// Pause between runs so the link settles
+    #[ts(type = "number")]
+    pub pause_ms: u64,
+}
+
+impl Default for SeriesSetting {
+    fn default() -> Self {
+        SeriesSetting {
+            runs: 3,
+            pause_ms: 2_000,
+        }
+    }
+}
+
+/// Runs of a series and their aggregate over the completed ones
+#[derive(Clone, Debug, PartialEq, TS)]
+pub struct SeriesResult {
+    /// Every run started, in order
+    pub runs: Vec<SpeedtestDonePayload>,
+    pub median_mbps: Option<f64>,
+    /// Variance of the throughput, in Mbps squared
+    pub variance: Option<f64>,
+    /// Index into `runs` of the fastest completed run
+    pub best_run: Option<usize>,
+    pub cancelled: bool,
+}
+
+impl SeriesResult {
+    pub fn from_runs(runs: Vec<SpeedtestDonePayload>, cancelled: bool) -> SeriesResult {
+        let completed: Vec<(usize, f64)> = runs
+            .iter()
+            .enumerate()
+            .filter(|(_, r)| r.result == SpeedtestOutcome::Completed)
+            .map(|(i, r)| (i, r.mbps))
+            .collect();
+        let mbps: Vec<f64> = completed.iter().map(|(_, m)| *m).collect();
+        SeriesResult {
+            median_mbps: median(&mbps),
+            variance: variance(&mbps),
+            best_run: completed
+                .iter()
+                .max_by(|a, b| a.1.total_cmp(&b.1))
+                .map(|(i, _)| *i),
+            runs,
+            cancelled,
+        }
+    }
+}
+
+/// Call `run` with the run index `setting.runs` times, pausing in between.
+/// Cancelling `token` stops the series; a run cut short is kept but left
+/// out of the aggregate.
+pub fn run_series<F>(setting: &SeriesSetting, token: &CancellationToken, mut run: F) -> SeriesResult
+where
+    F: FnMut(u32, &CancellationToken) -> SpeedtestDonePayload,
+{
+    let mut runs = Vec::new();
+    for i in 0..setting.runs {
+        if i > 0 && cancellable_sleep(token, Duration::from_millis(setting.pause_ms)).is_cancelled()
+        {
+            break;
+        }
+        if token.is_cancelled() {
+            break;
+        }
+        runs.push(run(i, token));
+    }
+    SeriesResult::from_runs(runs, token.is_cancelled())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::speedtest::Direction;
+
+    fn done(mbps: f64, result: SpeedtestOutcome) -> SpeedtestDonePayload {
+        SpeedtestDonePayload {
+            direction: Direction::Download,
+            result,
+            bytes: 1,
+            elapsed_ms: 1000,
+            mbps,
+            ttfb_ms: None,
+            error: None,
+            grade: None,
+        }
+    }
+
+    #[test]
+    fn aggregate_of_varying_runs() {
+        let speeds = [90.0, 110.0, 80.0, 120.0, 100.0];
+        let setting = SeriesSetting {
+            runs: 5,
+            pause_ms: 0,
+        };
+        let result = run_series(&setting, &CancellationToken::new(), |i, _| {
+            done(speeds[i as usize], SpeedtestOutcome::Completed)
+        });
+        assert_eq!(result.runs.len(), 5);
+        assert_eq!(result.median_mbps, Some(100.0));
+        assert_eq!(result.variance, Some(200.0));
+        assert_eq!(result.best_run, Some(3));
+        assert!(!result.cancelled);
+    }
+
+    #[test]
+    fn cancellation_stops_the_series() {
+        let setting = SeriesSetting {
+            runs: 5,
+            pause_ms: 0,
+        };
+        let token = CancellationToken::new();
+        let result = run_series(&setting, &token, |i, token| {
+            if i == 1 {
+                token.cancel();
+                return done(10.0, SpeedtestOutcome::Canceled);
+            }
+            done(50.0, SpeedtestOutcome::Completed)
+        });
+        assert!(result.cancelled);
+        assert_eq!(result.runs.len(), 2);
+        // The cut short run is not part of the figure
+        assert_eq!(result.median_mbps, Some(50.0));
+        assert_eq!(result.best_run, Some(0));
+    }
+}
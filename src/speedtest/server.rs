@@ -0,0 +1,348 @@
+//! Speedtest server selection
+use super::{SpeedtestDonePayload, SpeedtestOutcome};
+use crate::cancel::CancellationToken;
+use crate::http::{HttpTransport, Url};
+use crate::net::geo::{GeoCache, GeoInfo, GeoLookup};
+use crate::pool::map_concurrent;
+use std::net::ToSocketAddrs;
+use std::time::Duration;
+use ts_rs::TS;
+
+/// Candidate speedtest server
+#[derive(Clone, Debug, PartialEq, Eq, TS)]
+pub struct SpeedtestServer {
+    pub name: String,
+    /// Endpoint used for latency probes and downloads
+    pub url: String,
+}
+
+impl SpeedtestServer {
+    pub fn new(name: &str, url: &str) -> SpeedtestServer {
+        SpeedtestServer {
+            name: name.to_string(),
+            url: url.to_string(),
+        }
+    }
+}
+
+/// Server chosen for the test and its measured latency
+#[derive(Clone, Debug, PartialEq)]
+pub struct SelectedServer {
+    pub server: SpeedtestServer,
+    /// Best latency probe. `None` when chosen by manual override.
+    pub ping_ms: Option<f64>,
+    /// Whether the server was picked by the user rather than by latency
+    pub manual: bool,
+}
+
+/// Location of the address `server.url` resolves to, through the
+/// process-wide geo cache
+pub fn server_geo<L: GeoLookup>(server: &SpeedtestServer, backend: &L) -> Result<GeoInfo, String> {
+    let url = Url::parse(&server.url).map_err(|e| e.to_string())?;
+    let addr = (url.host.as_str(), url.port)
+        .to_socket_addrs()
+        .map_err(|e| e.to_string())?
+        .next()
+        .ok_or_else(|| format!("{} has no address", url.host))?;
+    GeoCache::shared(backend).lookup(addr.ip())
+}
+
+/// Lowest time to first byte over `samples` HEAD requests, or `None` if all
+/// failed. HEAD keeps a server URL that points at a test file from sending
+/// the whole file per sample. Stops sending once `token` is cancelled.
+pub fn probe_latency<T: HttpTransport>(
+    transport: &T,
+    server: &SpeedtestServer,
+    samples: u32,
+    timeout: Duration,
+    token: &CancellationToken,
+) -> Option<Duration> {
+    let url = Url::parse(&server.url).ok()?;
+    (0..samples.max(1))
+        .take_while(|_| !token.is_cancelled())
+        .filter_map(|_| transport.head(&url, timeout).ok())
+        .filter(|r| r.status < 400)
+        .map(|r| r.ttfb)
+        .min()
+}
+
+/// Choose the server to test against.
+///
+/// With `manual` set, the server with that name is used as is. Otherwise every
+/// candidate is probed and the lowest-latency one wins.
+pub fn select_server<T: HttpTransport>(
+    transport: &T,
+    servers: &[SpeedtestServer],
+    manual: Option<&str>,
+    samples: u32,
+    timeout: Duration,
+    token: &CancellationToken,
+) -> Result<SelectedServer, String> {
+    if let Some(name) = manual {
+        return servers
+            .iter()
+            .find(|s| s.name == name)
+            .map(|server| SelectedServer {
+                server: server.clone(),
+                ping_ms: None,
+                manual: true,
+            })
+            .ok_or_else(|| format!("Unknown speedtest server: {}", name));
+    }
+    servers
+        .iter()
+        .filter_map(|server| {
+            probe_latency(transport, server, samples, timeout, token).map(|rtt| (server, rtt))
+        })
+        .min_by_key(|(_, rtt)| *rtt)
+        .map(|(server, rtt)| SelectedServer {
+            server: server.clone(),
+            ping_ms: Some(rtt.as_secs_f64() * 1000.0),
+            manual: false,
+        })
+        .ok_or_else(|| "No speedtest server responded".to_string())
+}
+
+/// One server's line in a comparison
+#[derive(Clone, Debug, PartialEq, TS)]
+pub struct ServerMeasurement {
+    pub server: SpeedtestServer,
+    pub ping_ms: Option<f64>,
+    /// Throughput of a completed test, when requested
+    pub mbps: Option<f64>,
+    /// 1 for the lowest latency. `None` for servers that did not answer.
+    pub rank: Option<usize>,
+    pub error: Option<String>,
+}
+
+/// Servers of a comparison, ranked by latency
+#[derive(Clone, Debug, PartialEq, TS)]
+pub struct ServerComparison {
+    pub servers: Vec<ServerMeasurement>,
+    /// Set when latency probes or throughput tests were cut short
+    pub cancelled: bool,
+}
+
+/// Runs a throughput test against one server
+pub type ThroughputTest<'a> =
+    &'a (dyn Fn(&SpeedtestServer, &CancellationToken) -> SpeedtestDonePayload + Sync);
+
+/// Measure latency to every server at once and, with `throughput`, run the
+/// throughput test against each reachable one in turn so the tests do not
+/// compete for the link. An unreachable server only fails its own entry.
+/// Entries come back ranked by latency, failed ones last.
+pub fn compare_servers<T: HttpTransport>(
+    transport: &T,
+    servers: &[SpeedtestServer],
+    samples: u32,
+    timeout: Duration,
+    throughput: Option<ThroughputTest>,
+    token: &CancellationToken,
+) -> ServerComparison {
+    let pings = map_concurrent(servers, servers.len().max(1), token, |server| {
+        probe_latency(transport, server, samples, timeout, token)
+    });
+    let mut entries: Vec<ServerMeasurement> = servers
+        .iter()
+        .zip(pings)
+        .map(|(server, ping)| {
+            let ping_ms = ping.flatten().map(|rtt| rtt.as_secs_f64() * 1000.0);
+            let error = match ping {
+                None => Some("Not probed: cancelled".to_string()),
+                Some(None) if token.is_cancelled() => Some("Cancelled".to_string()),
+                Some(None) => Some("No response".to_string()),
+                Some(Some(_)) => None,
+            };
+            ServerMeasurement {
+                server: server.clone(),
+                ping_ms,
+                mbps: None,
+                rank: None,
+                error,
+            }
+        })
+        .collect();
+    if let Some(test) = throughput {
+        for entry in entries.iter_mut().filter(|e| e.ping_ms.is_some()) {
+            if token.is_cancelled() {
+                break;
+            }
+            let done = test(&entry.server, token);
+            match done.result {
+                SpeedtestOutcome::Completed => entry.mbps = Some(done.mbps),
+                SpeedtestOutcome::Canceled => {
+                    entry.error = Some(done.error.unwrap_or_else(|| "Cancelled".to_string()))
+                }
+                SpeedtestOutcome::Failed => {
+                    entry.error = Some(done.error.unwrap_or_else(|| "Failed".to_string()))
+                }
+            }
+        }
+    }
+    entries.sort_by(|a, b| match (a.ping_ms, b.ping_ms) {
+        (Some(a), Some(b)) => a.total_cmp(&b),
+        (a, b) => b.is_some().cmp(&a.is_some()),
+    });
+    for (i, entry) in entries.iter_mut().enumerate() {
+        entry.rank = entry.ping_ms.map(|_| i + 1);
+    }
+    ServerComparison {
+        servers: entries,
+        cancelled: token.is_cancelled(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::http::{HttpError, HttpResponse};
+
+    /// Latency depends on the host name
+    struct StubServers;
+
+    impl HttpTransport for StubServers {
+        fn get(&self, _url: &Url, _timeout: Duration) -> Result<HttpResponse, HttpError> {
+            unreachable!("servers are probed with HEAD")
+        }
+        fn head(&self, url: &Url, _timeout: Duration) -> Result<HttpResponse, HttpError> {
+            let ms = match url.host.as_str() {
+                "tokyo.example" => 80,
+                "osaka.example" => 15,
+                "seoul.example" => 40,
+                _ => return Err(HttpError::Timeout),
+            };
+            Ok(HttpResponse {
+                status: 200,
+                headers: Vec::new(),
+                body: Vec::new(),
+                ttfb: Duration::from_millis(ms),
+                elapsed: Duration::from_millis(ms),
+                reused_connection: false,
+            })
+        }
+    }
+
+    fn servers() -> Vec<SpeedtestServer> {
+        vec![
+            SpeedtestServer::new("tokyo", "http://tokyo.example/"),
+            SpeedtestServer::new("down", "http://down.example/"),
+            SpeedtestServer::new("osaka", "http://osaka.example/"),
+            SpeedtestServer::new("seoul", "http://seoul.example/"),
+        ]
+    }
+
+    #[test]
+    fn nearest_server_is_chosen() {
+        let timeout = Duration::from_secs(1);
+        let token = CancellationToken::new();
+        let selected = select_server(&StubServers, &servers(), None, 2, timeout, &token).unwrap();
+        assert_eq!(selected.server.name, "osaka");
+        assert_eq!(selected.ping_ms, Some(15.0));
+        assert!(!selected.manual);
+    }
+
+    #[test]
+    fn servers_compared_side_by_side() {
+        use crate::speedtest::Direction;
+        let throughput = |server: &SpeedtestServer, _: &CancellationToken| SpeedtestDonePayload {
+            direction: Direction::Download,
+            result: SpeedtestOutcome::Completed,
+            bytes: 1,
+            elapsed_ms: 1000,
+            mbps: if server.name == "seoul" { 300.0 } else { 100.0 },
+            ttfb_ms: None,
+            error: None,
+            grade: None,
+        };
+        let comparison = compare_servers(
+            &StubServers,
+            &servers(),
+            2,
+            Duration::from_secs(1),
+            Some(&throughput),
+            &CancellationToken::new(),
+        );
+        assert!(!comparison.cancelled);
+        let entries = comparison.servers;
+        let ranked: Vec<(&str, Option<usize>, Option<f64>)> = entries
+            .iter()
+            .map(|e| (e.server.name.as_str(), e.rank, e.mbps))
+            .collect();
+        assert_eq!(
+            ranked,
+            vec![
+                ("osaka", Some(1), Some(100.0)),
+                ("seoul", Some(2), Some(300.0)),
+                ("tokyo", Some(3), Some(100.0)),
+                ("down", None, None),
+            ]
+        );
+        assert_eq!(entries[0].ping_ms, Some(15.0));
+        assert!(entries[3].error.is_some());
+        assert!(entries[..3].iter().all(|e| e.error.is_none()));
+    }
+
+    #[test]
+    fn cancelled_throughput_is_marked() {
+        use crate::speedtest::Direction;
+        let token = CancellationToken::new();
+        // The first test is stopped by the user midway
+        let throughput = |_: &SpeedtestServer, token: &CancellationToken| {
+            token.cancel();
+            SpeedtestDonePayload {
+                direction: Direction::Download,
+                result: SpeedtestOutcome::Canceled,
+                bytes: 0,
+                elapsed_ms: 0,
+                mbps: 0.0,
+                ttfb_ms: None,
+                error: None,
+                grade: None,
+            }
+        };
+        let comparison = compare_servers(
+            &StubServers,
+            &servers(),
+            2,
+            Duration::from_secs(1),
+            Some(&throughput),
+            &token,
+        );
+        assert!(comparison.cancelled);
+        let tokyo = &comparison.servers[2];
+        assert_eq!(tokyo.server.name, "tokyo");
+        assert_eq!(
+            (tokyo.mbps, tokyo.error.as_deref()),
+            (None, Some("Cancelled"))
+        );
+        // Tests after the cancellation never ran
+        assert!(comparison.servers[..2]
+            .iter()
+            .all(|e| e.mbps.is_none() && e.error.is_none()));
+
+        let comparison = compare_servers(
+            &StubServers,
+            &servers(),
+            2,
+            Duration::from_secs(1),
+            None,
+            &token,
+        );
+        assert!(comparison.cancelled);
+        assert!(comparison.servers.iter().all(|e| e.ping_ms.is_none()));
+    }
+
+    #[test]
+    fn manual_override_wins() {
+        let timeout = Duration::from_secs(1);
+        let token = CancellationToken::new();
+        let select = |servers: &[SpeedtestServer], manual| {
+            select_server(&StubServers, servers, manual, 2, timeout, &token)
+        };
+        let selected = select(&servers(), Some("tokyo")).unwrap();
+        assert_eq!(selected.server.name, "tokyo");
+        assert!(selected.manual);
+        assert!(select(&servers(), Some("paris")).is_err());
+        assert!(select(&servers()[1..2], None).is_err());
+    }
+}
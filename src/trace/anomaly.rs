@@ -0,0 +1,110 @@
+use super::session::Hop;
+use std::net::IpAddr;
+use ts_rs::TS;
+
+/// Event name emitted when a hop looks anomalous
+pub const TRACEROUTE_ANOMALY_EVENT: &str = "traceroute:anomaly";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, TS)]
+pub enum AnomalyKind {
+    /// Same responder at non-adjacent hops, as in a routing loop
+    Loop,
+    /// Same responder at consecutive hops, as behind NAT or anycast or on a
+    /// router that does not decrement TTL
+    Stall,
+}
+
+/// Responder seen at more than one hop
+#[derive(Clone, Debug, PartialEq, Eq, TS)]
+pub struct Anomaly {
+    pub kind: AnomalyKind,
+    pub responder: IpAddr,
+    /// TTLs at which `responder` answered so far, ascending
+    pub hops: Vec<u8>,
+}
+
+/// Check `hop` against the hops before it
+pub fn check_hop(previous: &[Hop], hop: &Hop) -> Option<Anomaly> {
+    let responder = hop.responder?;
+    // The destination answering every probe past its distance is expected
+    if hop.reached {
+        return None;
+    }
+    let mut hops: Vec<u8> = previous
+        .iter()
+        .filter(|h| h.responder == Some(responder))
+        .map(|h| h.ttl)
+        .collect();
+    if hops.is_empty() {
+        return None;
+    }
+    let adjacent = previous
+        .last()
+        .is_some_and(|h| h.ttl + 1 == hop.ttl && h.responder == Some(responder));
+    hops.push(hop.ttl);
+    Some(Anomaly {
+        kind: if adjacent {
+            AnomalyKind::Stall
+        } else {
+            AnomalyKind::Loop
+        },
+        responder,
+        hops,
+    })
+}
+
+/// Anomalies of a finished trace, one per affected hop
+pub fn detect_anomalies(hops: &[Hop]) -> Vec<Anomaly> {
+    (0..hops.len())
+        .filter_map(|i| check_hop(&hops[..i], &hops[i]))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::Ipv4Addr;
+
+    fn hop(ttl: u8, last: Option<u8>) -> Hop {
+        Hop {
+            ttl,
+            responder: last.map(|l| IpAddr::V4(Ipv4Addr::new(10, 0, 0, l))),
+            rtts: Vec::new(),
+            reached: false,
+            anomaly: None,
+            reply_ttl: None,
+            answered_on_flow: None,
+        }
+    }
+
+    #[test]
+    fn loop_and_stall_reported_with_hop_indices() {
+        let hops = vec![
+            hop(1, Some(1)),
+            hop(2, Some(2)),
+            hop(3, Some(3)),
+            hop(4, None),
+            hop(5, Some(2)),
+            hop(6, Some(3)),
+            hop(7, Some(3)),
+        ];
+        let anomalies = detect_anomalies(&hops);
+        let summary: Vec<(AnomalyKind, u8, Vec<u8>)> = anomalies
+            .iter()
+            .map(|a| {
+                let IpAddr::V4(ip) = a.responder else {
+                    unreachable!()
+                };
+                (a.kind, ip.octets()[3], a.hops.clone())
+            })
+            .collect();
+        assert_eq!(
+            summary,
+            vec![
+                (AnomalyKind::Loop, 2, vec![2, 5]),
+                (AnomalyKind::Loop, 3, vec![3, 6]),
+                (AnomalyKind::Stall, 3, vec![3, 6, 7]),
+            ]
+        );
+    }
+}
@@ -0,0 +1,157 @@
+//! Collapse a traceroute into the autonomous systems it crosses
+use super::session::{Hop, TraceResult};
+use crate::net::geo::{GeoCache, GeoLookup};
+use crate::net::ipnet::IpNet;
+use std::net::IpAddr;
+use std::time::Duration;
+use ts_rs::TS;
+
+/// Event name of the AS path summary emitted when a trace completes
+pub const TRACEROUTE_AS_PATH_EVENT: &str = "traceroute:as_path";
+
+/// Maps a hop address to the AS announcing it
+pub trait AsnLookup: Sync {
+    fn asn(&self, ip: IpAddr) -> Option<u32>;
+}
+
+/// Prefix-to-origin table, matched by longest prefix
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct PrefixAsnTable(pub Vec<(IpNet, u32)>);
+
+impl AsnLookup for PrefixAsnTable {
+    fn asn(&self, ip: IpAddr) -> Option<u32> {
+        self.0
+            .iter()
+            .filter(|(net, _)| net.contains(&ip))
+            .max_by_key(|(net, _)| net.prefix_len)
+            .map(|(_, asn)| *asn)
+    }
+}
+
+/// Consecutive hops inside one AS
+#[derive(Clone, Debug, PartialEq, TS)]
+pub struct AsHop {
+    /// `None` for hops without a known origin, e.g. private addresses
+    pub asn: Option<u32>,
+    /// TTLs of the first and last hop answering from this AS
+    pub first_ttl: u8,
+    pub last_ttl: u8,
+    /// Routers where the path enters and leaves the AS
+    pub entry: IpAddr,
+    pub exit: IpAddr,
+    /// Lowest RTT at the entry and exit router
+    pub entry_rtt_ms: Option<f64>,
+    pub exit_rtt_ms: Option<f64>,
+}
+
+/// Summary emitted as [`TRACEROUTE_AS_PATH_EVENT`]
+#[derive(Clone, Debug, PartialEq, TS)]
+pub struct AsPathPayload {
+    pub dst_ip: IpAddr,
+    pub hops: Vec<AsHop>,
+    pub reached: bool,
+}
+
+impl AsPathPayload {
+    /// Known ASNs in path order, e.g. for comparison with a BGP AS path
+    pub fn asns(&self) -> Vec<u32> {
+        self.hops.iter().filter_map(|h| h.asn).collect()
+    }
+}
+
+fn min_rtt_ms(hop: &Hop) -> Option<f64> {
+    hop.rtts
+        .iter()
+        .flatten()
+        .min()
+        .map(|rtt: &Duration| rtt.as_secs_f64() * 1000.0)
+}
+
+/// Group consecutive hops by AS. Hops where every probe timed out are
+/// skipped, so a silent router does not split an AS in two.
+pub fn as_path<L: AsnLookup + ?Sized>(hops: &[Hop], lookup: &L) -> Vec<AsHop> {
+    let mut path: Vec<AsHop> = Vec::new();
+    for hop in hops {
+        let Some(responder) = hop.responder else {
+            continue;
+        };
+        let asn = lookup.asn(responder);
+        let rtt = min_rtt_ms(hop);
+        match path.last_mut() {
+            Some(last) if last.asn == asn => {
+                last.last_ttl = hop.ttl;
+                last.exit = responder;
+                last.exit_rtt_ms = rtt;
+            }
+            _ => path.push(AsHop {
+                asn,
+                first_ttl: hop.ttl,
+                last_ttl: hop.ttl,
+                entry: responder,
+                exit: responder,
+                entry_rtt_ms: rtt,
+                exit_rtt_ms: rtt,
+            }),
+        }
+    }
+    path
+}
+
+/// AS path summary of a finished trace
+pub fn as_path_summary<L: AsnLookup + ?Sized>(result: &TraceResult, lookup: &L) -> AsPathPayload {
+    AsPathPayload {
+        dst_ip: result.dst_ip,
+        hops: as_path(&result.hops, lookup),
+        reached: result.reached,
+    }
+}
+
+/// [`as_path_summary`] with origins from `backend`, through the
+/// process-wide geo cache
+pub fn as_path_summary_geo<L: GeoLookup>(result: &TraceResult, backend: &L) -> AsPathPayload {
+    as_path_summary(result, &GeoCache::shared(backend))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::Ipv4Addr;
+
+    fn hop(ttl: u8, responder: Option<[u8; 4]>) -> Hop {
+        Hop {
+            ttl,
+            responder: responder.map(|a| IpAddr::V4(Ipv4Addr::from(a))),
+            rtts: vec![None, Some(Duration::from_millis(ttl as u64 * 10))],
+            reached: false,
+            anomaly: None,
+            reply_ttl: None,
+            answered_on_flow: None,
+        }
+    }
+
+    #[test]
+    fn three_ases_collapse_into_three_hops() {
+        let table = PrefixAsnTable(vec![
+            (IpNet::new("198.51.100.0".parse().unwrap(), 24), 64500),
+            (IpNet::new("203.0.113.0".parse().unwrap(), 24), 64501),
+            (IpNet::new("192.0.2.0".parse().unwrap(), 24), 64502),
+        ]);
+        let hops = vec![
+            hop(1, Some([198, 51, 100, 1])),
+            hop(2, Some([198, 51, 100, 9])),
+            hop(3, Some([203, 0, 113, 1])),
+            hop(4, None),
+            hop(5, Some([203, 0, 113, 7])),
+            hop(6, Some([192, 0, 2, 1])),
+        ];
+        let path = as_path(&hops, &table);
+        let asns: Vec<Option<u32>> = path.iter().map(|h| h.asn).collect();
+        assert_eq!(asns, vec![Some(64500), Some(64501), Some(64502)]);
+        let bounds: Vec<(u8, u8)> = path.iter().map(|h| (h.first_ttl, h.last_ttl)).collect();
+        assert_eq!(bounds, vec![(1, 2), (3, 5), (6, 6)]);
+        assert_eq!(path[1].entry, "203.0.113.1".parse::<IpAddr>().unwrap());
+        assert_eq!(path[1].exit, "203.0.113.7".parse::<IpAddr>().unwrap());
+        assert_eq!(path[1].entry_rtt_ms, Some(30.0));
+        assert_eq!(path[1].exit_rtt_ms, Some(50.0));
+    }
+}
@@ -0,0 +1,13 @@
+//! Traceroute
+pub mod anomaly;
+pub mod aspath;
+pub mod probe;
+pub mod reply;
+pub mod session;
+pub mod setting;
+pub mod udp;
+
+pub use probe::{HopProber, HopReply};
+pub use session::{traceroute, Hop, TraceEvent, TraceResult};
+pub use setting::{FlowPolicy, TraceSetting};
+pub use udp::UdpHopProber;
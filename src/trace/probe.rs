@@ -0,0 +1,37 @@
+use crate::ping::ProbeError;
+use std::net::IpAddr;
+use std::time::Duration;
+
+/// Reply to a probe sent with a limited TTL
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct HopReply {
+    /// Router that sent time-exceeded, or the destination itself
+    pub responder: IpAddr,
+    pub rtt: Duration,
+    /// Whether the reply came from the destination
+    pub reached: bool,
+    /// TTL or hop limit the reply arrived with
+    pub reply_ttl: Option<u8>,
+}
+
+/// Sends one TTL-limited probe towards a destination
+pub trait HopProber: Sync {
+    fn probe_hop(&self, dst: IpAddr, ttl: u8, timeout: Duration) -> Result<HopReply, ProbeError>;
+    /// Whether [`HopProber::probe_hop_flow`] can send on other flows, i.e.
+    /// with a different 5-tuple that load balancers and flow filters see
+    fn supports_flows(&self) -> bool {
+        false
+    }
+    /// Probe on flow `flow`, 0 being the flow of `probe_hop`. Probers
+    /// without a notion of flows send the usual probe.
+    fn probe_hop_flow(
+        &self,
+        dst: IpAddr,
+        ttl: u8,
+        flow: u16,
+        timeout: Duration,
+    ) -> Result<HopReply, ProbeError> {
+        let _ = flow;
+        self.probe_hop(dst, ttl, timeout)
+    }
+}
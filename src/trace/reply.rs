@@ -0,0 +1,161 @@
+use crate::ping::icmp::{ipv4_ttl, strip_ipv4_header};
+use crate::ping::UnreachableReason;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+const ICMP_ECHO_REPLY: u8 = 0;
+const ICMP_DEST_UNREACHABLE: u8 = 3;
+const ICMP_TIME_EXCEEDED: u8 = 11;
+const ICMPV6_DEST_UNREACHABLE: u8 = 1;
+const ICMPV6_TIME_EXCEEDED: u8 = 3;
+const ICMPV6_ECHO_REPLY: u8 = 129;
+const IPV6_HEADER_LEN: usize = 40;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ReplyKind {
+    EchoReply,
+    TimeExceeded,
+    DestinationUnreachable { code: u8 },
+}
+
+/// ICMP message received in answer to an echo probe
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct IcmpReply {
+    pub src: IpAddr,
+    /// IPv4 TTL or IPv6 hop limit the reply arrived with
+    pub ttl: Option<u8>,
+    pub kind: ReplyKind,
+    /// Identifier and sequence of the probe, quoted by errors
+    pub id: u16,
+    pub seq: u16,
+    /// Destination of the probe an error quotes
+    pub probe_dst: Option<IpAddr>,
+}
+
+impl IcmpReply {
+    /// Reason of a destination unreachable error
+    pub fn unreachable_reason(&self) -> Option<UnreachableReason> {
+        match self.kind {
+            ReplyKind::DestinationUnreachable { code } => Some(match self.src {
+                IpAddr::V4(_) => UnreachableReason::from_icmpv4(code),
+                IpAddr::V6(_) => UnreachableReason::from_icmpv6(code),
+            }),
+            _ => None,
+        }
+    }
+}
+
+fn echo_ids(icmp: &[u8]) -> Option<(u16, u16)> {
+    let b = icmp.get(4..8)?;
+    Some((
+        u16::from_be_bytes([b[0], b[1]]),
+        u16::from_be_bytes([b[2], b[3]]),
+    ))
+}
+
+/// Parse a packet from a raw IPv4 ICMP socket, IP header included
+pub fn parse_ipv4_reply(packet: &[u8]) -> Option<IcmpReply> {
+    let ttl = ipv4_ttl(packet)?;
+    let src = IpAddr::V4(Ipv4Addr::new(
+        packet[12], packet[13], packet[14], packet[15],
+    ));
+    let icmp = strip_ipv4_header(packet)?;
+    let kind = match (*icmp.first()?, *icmp.get(1)?) {
+        (ICMP_ECHO_REPLY, _) => ReplyKind::EchoReply,
+        (ICMP_TIME_EXCEEDED, _) => ReplyKind::TimeExceeded,
+        (ICMP_DEST_UNREACHABLE, code) => ReplyKind::DestinationUnreachable { code },
+        _ => return None,
+    };
+    let ((id, seq), probe_dst) = match kind {
+        ReplyKind::EchoReply => (echo_ids(icmp)?, None),
+        // Errors quote the original IP header and the first 8 bytes after it
+        _ => {
+            let quoted = icmp.get(8..)?;
+            let dst = quoted.get(16..20)?;
+            let dst = IpAddr::V4(Ipv4Addr::new(dst[0], dst[1], dst[2], dst[3]));
+            (echo_ids(strip_ipv4_header(quoted)?)?, Some(dst))
+        }
+    };
+    Some(IcmpReply {
+        src,
+        ttl: Some(ttl),
+        kind,
+        id,
+        seq,
+        probe_dst,
+    })
+}
+
+/// Parse an ICMPv6 message. Raw ICMPv6 sockets strip the IPv6 header, so
+/// the hop limit comes from `IPV6_RECVHOPLIMIT` ancillary data if enabled.
+pub fn parse_ipv6_reply(icmp: &[u8], src: Ipv6Addr, hop_limit: Option<u8>) -> Option<IcmpReply> {
+    let kind = match (*icmp.first()?, *icmp.get(1)?) {
+        (ICMPV6_ECHO_REPLY, _) => ReplyKind::EchoReply,
+        (ICMPV6_TIME_EXCEEDED, _) => ReplyKind::TimeExceeded,
+        (ICMPV6_DEST_UNREACHABLE, code) => ReplyKind::DestinationUnreachable { code },
+        _ => return None,
+    };
+    let ((id, seq), probe_dst) = match kind {
+        ReplyKind::EchoReply => (echo_ids(icmp)?, None),
+        _ => {
+            let dst: [u8; 16] = icmp.get(8 + 24..8 + IPV6_HEADER_LEN)?.try_into().ok()?;
+            let ids = echo_ids(icmp.get(8 + IPV6_HEADER_LEN..)?)?;
+            (ids, Some(IpAddr::V6(Ipv6Addr::from(dst))))
+        }
+    };
+    Some(IcmpReply {
+        src: IpAddr::V6(src),
+        ttl: hop_limit,
+        kind,
+        id,
+        seq,
+        probe_dst,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ping::icmp::{build_echo, Echo, EchoKind};
+    use crate::ping::result::inferred_hops;
+
+    fn ipv4_header(ttl: u8, src: [u8; 4], dst: [u8; 4]) -> Vec<u8> {
+        let mut h = vec![0x45, 0, 0, 0, 0, 0, 0, 0, ttl, 1, 0, 0];
+        h.extend_from_slice(&src);
+        h.extend_from_slice(&dst);
+        h
+    }
+
+    #[test]
+    fn time_exceeded_with_ttl() {
+        let probe = build_echo(
+            &Echo {
+                kind: EchoKind::Request,
+                id: 0x1234,
+                seq: 7,
+                payload: vec![0; 16],
+            },
+            false,
+        );
+        let mut packet = ipv4_header(250, [198, 51, 100, 1], [10, 0, 0, 5]);
+        packet.extend_from_slice(&[ICMP_TIME_EXCEEDED, 0, 0, 0, 0, 0, 0, 0]);
+        packet.extend_from_slice(&ipv4_header(1, [10, 0, 0, 5], [192, 0, 2, 1]));
+        packet.extend_from_slice(&probe[..8]);
+        let reply = parse_ipv4_reply(&packet).unwrap();
+        assert_eq!(reply.kind, ReplyKind::TimeExceeded);
+        assert_eq!(reply.src, "198.51.100.1".parse::<IpAddr>().unwrap());
+        assert_eq!(reply.ttl, Some(250));
+        assert_eq!((reply.id, reply.seq), (0x1234, 7));
+        // Sent with 255, so five routers on the way back
+        assert_eq!(inferred_hops(250), 5);
+    }
+
+    #[test]
+    fn ipv6_time_exceeded() {
+        let mut icmp = vec![ICMPV6_TIME_EXCEEDED, 0, 0, 0, 0, 0, 0, 0];
+        icmp.extend_from_slice(&[0; IPV6_HEADER_LEN]);
+        icmp.extend_from_slice(&[128, 0, 0, 0, 0, 9, 0, 3]);
+        let reply = parse_ipv6_reply(&icmp, "2001:db8::1".parse().unwrap(), Some(60)).unwrap();
+        assert_eq!((reply.kind, reply.ttl), (ReplyKind::TimeExceeded, Some(60)));
+        assert_eq!((reply.id, reply.seq), (9, 3));
+    }
+}
@@ -0,0 +1,457 @@
+use super::anomaly::{check_hop, Anomaly, AnomalyKind};
+use super::{FlowPolicy, HopProber, TraceSetting};
+use crate::cancel::CancellationToken;
+use crate::ping::result::{inferred_hops, PingStat};
+use crate::probe::{Clock, SystemClock};
+use std::net::IpAddr;
+use std::time::{Duration, Instant};
+use ts_rs::TS;
+
+/// Result of probing one TTL
+#[derive(Clone, Debug, PartialEq, Eq, TS)]
+pub struct Hop {
+    pub ttl: u8,
+    /// First router that answered at this TTL. `None` if every probe timed out.
+    pub responder: Option<IpAddr>,
+    /// RTT of each probe, `None` for timeouts
+    #[ts(type = "Array<Duration | null>")]
+    pub rtts: Vec<Option<Duration>>,
+    /// Whether the destination answered
+    pub reached: bool,
+    /// Set when the responder was already seen at an earlier hop
+    pub anomaly: Option<AnomalyKind>,
+    /// TTL of the first reply as received
+    pub reply_ttl: Option<u8>,
+    /// Flow that answered after the usual flow stayed silent, a sign of
+    /// per-flow filtering
+    pub answered_on_flow: Option<u16>,
+}
+
+impl Hop {
+    /// Estimated hops on the way back from the responder, assuming it sent
+    /// with a common initial TTL
+    pub fn return_hops(&self) -> Option<u8> {
+        self.reply_ttl
+            .map(|ttl| inferred_hops(ttl).saturating_add(1))
+    }
+    /// Return hops minus forward hops. Non-zero suggests asymmetric routing.
+    pub fn asymmetry(&self) -> Option<i16> {
+        self.return_hops().map(|back| back as i16 - self.ttl as i16)
+    }
+    fn last_rtt(&self) -> Option<Duration> {
+        self.rtts.iter().rev().find_map(|r| *r)
+    }
+}
+
+/// Events reported while a trace runs
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum TraceEvent {
+    Hop(Hop),
+    Anomaly(Anomaly),
+}
+
+#[derive(Clone, Debug, PartialEq, TS)]
+pub struct TraceResult {
+    pub dst_ip: IpAddr,
+    pub hops: Vec<Hop>,
+    pub reached: bool,
+    pub anomalies: Vec<Anomaly>,
+    /// RTTs of the `confirm_probes` sent after reaching the destination
+    #[ts(type = "Array<Duration | null>")]
+    pub destination_rtts: Vec<Option<Duration>>,
+    /// Summary of `destination_rtts`, `None` when none were sent
+    pub destination: Option<PingStat>,
+    pub cancelled: bool,
+}
+
+/// Probe each TTL up to `max_hop` until the destination answers
+pub fn traceroute<P, F>(
+    prober: &P,
+    setting: &TraceSetting,
+    token: &CancellationToken,
+    on_event: F,
+) -> TraceResult
+where
+    P: HopProber,
+    F: FnMut(&TraceEvent),
+{
+    traceroute_on(prober, setting, token, &SystemClock, on_event)
+}
+
+/// `traceroute` spacing its tries on `clock`
+fn traceroute_on<P, C, F>(
+    prober: &P,
+    setting: &TraceSetting,
+    token: &CancellationToken,
+    clock: &C,
+    mut on_event: F,
+) -> TraceResult
+where
+    P: HopProber,
+    C: Clock,
+    F: FnMut(&TraceEvent),
+{
+    let mut hops: Vec<Hop> = Vec::new();
+    let mut anomalies = Vec::new();
+    let mut last_rtt = None;
+    for ttl in 1..=setting.max_hop {
+        if token.is_cancelled() {
+            break;
+        }
+        let timeout = setting.hop_timeout(ttl, last_rtt);
+        let mut hop = Hop {
+            ttl,
+            responder: None,
+            rtts: Vec::new(),
+            reached: false,
+            anomaly: None,
+            reply_ttl: None,
+            answered_on_flow: None,
+        };
+        let mut last_sent: Option<Instant> = None;
+        for _ in 0..setting.tries_per_hop {
+            if !spaced(setting, token, clock, &mut last_sent) {
+                break;
+            }
+            probe_try(prober, setting, ttl, timeout, &mut hop);
+        }
+        if hop.reached {
+            let tries = setting.final_hop_tries.unwrap_or(setting.tries_per_hop);
+            while hop.rtts.len() < tries as usize && spaced(setting, token, clock, &mut last_sent) {
+                probe_try(prober, setting, ttl, timeout, &mut hop);
+            }
+        }
+        if let Some(ms) = setting.silent_retry_ms {
+            if hop.responder.is_none()
+                && !hop.rtts.is_empty()
+                && !clock.sleep(token, Duration::from_millis(ms)).is_cancelled()
+            {
+                probe_try(prober, setting, ttl, timeout, &mut hop);
+            }
+        }
+        match setting.flow_policy {
+            FlowPolicy::RetryOnSilence { flows } if prober.supports_flows() => {
+                for flow in 1..=flows as u16 {
+                    if hop.responder.is_some() || hop.rtts.is_empty() || token.is_cancelled() {
+                        break;
+                    }
+                    let result = prober.probe_hop_flow(setting.dst_ip, ttl, flow, timeout);
+                    if result.is_ok() {
+                        hop.answered_on_flow = Some(flow);
+                    }
+                    record(&mut hop, result);
+                }
+            }
+            _ => {}
+        }
+        last_rtt = hop.last_rtt().or(last_rtt);
+        // Annotate and keep going; a loop may still resolve
+        let anomaly = check_hop(&hops, &hop);
+        hop.anomaly = anomaly.as_ref().map(|a| a.kind);
+        on_event(&TraceEvent::Hop(hop.clone()));
+        if let Some(anomaly) = anomaly {
+            on_event(&TraceEvent::Anomaly(anomaly.clone()));
+            anomalies.push(anomaly);
+        }
+        let reached = hop.reached;
+        hops.push(hop);
+        if reached {
+            break;
+        }
+    }
+    let reached = hops.last().is_some_and(|h| h.reached);
+    let mut destination_rtts = Vec::new();
+    if reached {
+        // Sent with the full TTL so a path that grew still gets there
+        let timeout = setting.hop_timeout(hops.len() as u8, last_rtt);
+        let mut last_sent = None;
+        for _ in 0..setting.confirm_probes {
+            if !spaced(setting, token, clock, &mut last_sent) {
+                break;
+            }
+            let rtt = match prober.probe_hop(setting.dst_ip, setting.max_hop, timeout) {
+                Ok(reply) if reply.reached => Some(reply.rtt),
+                _ => None,
+            };
+            destination_rtts.push(rtt);
+        }
+    }
+    TraceResult {
+        dst_ip: setting.dst_ip,
+        reached,
+        hops,
+        anomalies,
+        destination: (!destination_rtts.is_empty()).then(|| PingStat::from_rtts(&destination_rtts)),
+        destination_rtts,
+        cancelled: token.is_cancelled(),
+    }
+}
+
+/// Wait out `try_interval_ms` since the previous probe and mark the next
+/// one sent. False when cancelled.
+fn spaced<C: Clock>(
+    setting: &TraceSetting,
+    token: &CancellationToken,
+    clock: &C,
+    last_sent: &mut Option<Instant>,
+) -> bool {
+    if let Some(sent) = *last_sent {
+        clock.sleep_until(token, sent + Duration::from_millis(setting.try_interval_ms));
+    }
+    if token.is_cancelled() {
+        return false;
+    }
+    *last_sent = Some(clock.now());
+    true
+}
+
+fn probe_try<P: HopProber>(
+    prober: &P,
+    setting: &TraceSetting,
+    ttl: u8,
+    timeout: Duration,
+    hop: &mut Hop,
+) {
+    record(hop, prober.probe_hop(setting.dst_ip, ttl, timeout));
+}
+
+fn record(hop: &mut Hop, result: Result<super::HopReply, crate::ping::ProbeError>) {
+    match result {
+        Ok(reply) => {
+            if hop.responder.is_none() {
+                hop.responder = Some(reply.responder);
+                hop.reply_ttl = reply.reply_ttl;
+            }
+            hop.reached |= reply.reached;
+            hop.rtts.push(Some(reply.rtt));
+        }
+        Err(_) => hop.rtts.push(None),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ping::ProbeError;
+    use crate::probe::util::tests::ManualClock;
+    use crate::trace::HopReply;
+    use std::net::Ipv4Addr;
+
+    /// Routers at 10.0.0.x listed per TTL, destination after the last
+    struct Path(Vec<u8>);
+
+    impl HopProber for Path {
+        fn probe_hop(
+            &self,
+            dst: IpAddr,
+            ttl: u8,
+            _timeout: Duration,
+        ) -> Result<HopReply, ProbeError> {
+            let rtt = Duration::from_millis(ttl as u64);
+            match self.0.get(ttl as usize - 1) {
+                Some(0) => Err(ProbeError::Timeout),
+                Some(last) => Ok(HopReply {
+                    responder: IpAddr::V4(Ipv4Addr::new(10, 0, 0, *last)),
+                    rtt,
+                    reached: false,
+                    // Symmetric path from routers sending with 255
+                    reply_ttl: Some(255 - (ttl - 1)),
+                }),
+                None => Ok(HopReply {
+                    responder: dst,
+                    rtt,
+                    reached: true,
+                    reply_ttl: None,
+                }),
+            }
+        }
+    }
+
+    #[test]
+    fn loop_annotated_and_trace_continues() {
+        let mut setting = TraceSetting::new("192.0.2.1".parse().unwrap());
+        setting.tries_per_hop = 2;
+        let mut events = Vec::new();
+        let result = traceroute(
+            &Path(vec![1, 2, 3, 2, 4]),
+            &setting,
+            &CancellationToken::new(),
+            |e| events.push(e.clone()),
+        );
+        assert!(result.reached);
+        assert_eq!(result.hops.len(), 6);
+        assert_eq!(result.hops[3].anomaly, Some(AnomalyKind::Loop));
+        assert_eq!(result.anomalies.len(), 1);
+        assert_eq!(result.anomalies[0].hops, vec![2, 4]);
+        let anomaly_events = events
+            .iter()
+            .filter(|e| matches!(e, TraceEvent::Anomaly(_)))
+            .count();
+        assert_eq!(anomaly_events, 1);
+        assert_eq!(result.hops[0].rtts.len(), 2);
+        assert_eq!(result.hops[2].reply_ttl, Some(253));
+        assert_eq!(result.hops[2].return_hops(), Some(3));
+        assert_eq!(result.hops[2].asymmetry(), Some(0));
+    }
+
+    /// Routers share a limiter that drops any probe arriving within 30ms of
+    /// the previous one, as read on `clock`; the destination is at TTL 3
+    struct RateLimited<'a> {
+        clock: &'a ManualClock,
+        previous: std::sync::Mutex<Option<Instant>>,
+    }
+
+    impl<'a> RateLimited<'a> {
+        fn new(clock: &'a ManualClock) -> RateLimited<'a> {
+            RateLimited {
+                clock,
+                previous: Default::default(),
+            }
+        }
+    }
+
+    impl HopProber for RateLimited<'_> {
+        fn probe_hop(
+            &self,
+            dst: IpAddr,
+            ttl: u8,
+            _timeout: Duration,
+        ) -> Result<HopReply, ProbeError> {
+            let now = self.clock.now();
+            let previous = self.previous.lock().unwrap().replace(now);
+            if ttl < 3 && previous.is_some_and(|p| now - p < Duration::from_millis(30)) {
+                return Err(ProbeError::Timeout);
+            }
+            Ok(HopReply {
+                responder: if ttl < 3 {
+                    IpAddr::V4(Ipv4Addr::new(10, 0, 0, ttl))
+                } else {
+                    dst
+                },
+                rtt: Duration::from_millis(1),
+                reached: ttl == 3,
+                reply_ttl: None,
+            })
+        }
+    }
+
+    #[test]
+    fn spaced_tries_get_past_icmp_rate_limit() {
+        let mut setting = TraceSetting::new("192.0.2.1".parse().unwrap());
+        let token = CancellationToken::new();
+        // Probes take no time, so only the spacing separates them
+        let clock = ManualClock::new();
+        let trace = |setting: &TraceSetting| {
+            traceroute_on(&RateLimited::new(&clock), setting, &token, &clock, |_| {})
+        };
+        let responders = |setting: &TraceSetting| -> Vec<Option<IpAddr>> {
+            trace(setting).hops.iter().map(|h| h.responder).collect()
+        };
+        // Hop 2 follows hop 1's burst and never gets a reply
+        assert_eq!(responders(&setting)[1], None);
+
+        setting.try_interval_ms = 40;
+        let hops = responders(&setting);
+        assert!(hops.iter().all(|r| r.is_some()), "{:?}", hops);
+
+        setting.try_interval_ms = 0;
+        setting.silent_retry_ms = Some(40);
+        let result = trace(&setting);
+        assert_eq!(result.hops[1].responder, Some("10.0.0.2".parse().unwrap()));
+        assert_eq!(result.hops[1].rtts.len(), 4);
+    }
+
+    /// Hop 2 drops flow 0 and flow 1 but answers flow 2
+    struct FlowFiltered;
+
+    impl HopProber for FlowFiltered {
+        fn probe_hop(&self, dst: IpAddr, ttl: u8, t: Duration) -> Result<HopReply, ProbeError> {
+            self.probe_hop_flow(dst, ttl, 0, t)
+        }
+        fn supports_flows(&self) -> bool {
+            true
+        }
+        fn probe_hop_flow(
+            &self,
+            dst: IpAddr,
+            ttl: u8,
+            flow: u16,
+            _timeout: Duration,
+        ) -> Result<HopReply, ProbeError> {
+            if ttl == 2 && flow < 2 {
+                return Err(ProbeError::Timeout);
+            }
+            Ok(HopReply {
+                responder: if ttl < 3 {
+                    IpAddr::V4(Ipv4Addr::new(10, 0, 0, ttl))
+                } else {
+                    dst
+                },
+                rtt: Duration::from_millis(1),
+                reached: ttl == 3,
+                reply_ttl: None,
+            })
+        }
+    }
+
+    #[test]
+    fn silent_hop_answers_on_another_flow() {
+        let mut setting = TraceSetting::new("192.0.2.1".parse().unwrap());
+        setting.tries_per_hop = 2;
+        let token = CancellationToken::new();
+        let paris = traceroute(&FlowFiltered, &setting, &token, |_| {});
+        assert_eq!(paris.hops[1].responder, None);
+        assert_eq!(paris.hops[1].answered_on_flow, None);
+
+        setting.flow_policy = FlowPolicy::RetryOnSilence { flows: 3 };
+        let result = traceroute(&FlowFiltered, &setting, &token, |_| {});
+        let hop = &result.hops[1];
+        assert_eq!(hop.responder, Some("10.0.0.2".parse().unwrap()));
+        assert_eq!(hop.answered_on_flow, Some(2));
+        // Two silent tries, one silent retry on flow 1, one answer on flow 2
+        assert_eq!(
+            hop.rtts,
+            vec![None, None, None, Some(Duration::from_millis(1))]
+        );
+        assert!(result
+            .hops
+            .iter()
+            .filter(|h| h.ttl != 2)
+            .all(|h| h.answered_on_flow.is_none()));
+
+        // A prober without flows is not retried on them
+        let path = Path(vec![1, 0]);
+        let result = traceroute(&path, &setting, &token, |_| {});
+        assert_eq!(result.hops[1].rtts.len(), 2);
+        assert_eq!(result.hops[1].answered_on_flow, None);
+    }
+
+    #[test]
+    fn destination_confirmed_with_extra_probes() {
+        let mut setting = TraceSetting::new("192.0.2.1".parse().unwrap());
+        setting.tries_per_hop = 1;
+        setting.final_hop_tries = Some(3);
+        setting.confirm_probes = 5;
+        let result = traceroute(
+            &Path(vec![1, 2]),
+            &setting,
+            &CancellationToken::new(),
+            |_| {},
+        );
+        assert!(result.reached);
+        assert_eq!(result.hops[0].rtts.len(), 1);
+        assert_eq!(result.hops[2].rtts.len(), 3);
+        // Path answers with the TTL as RTT in ms; confirmations go at max_hop
+        assert_eq!(
+            result.destination_rtts,
+            vec![Some(Duration::from_millis(setting.max_hop as u64)); 5]
+        );
+        let stat = result.destination.unwrap();
+        assert_eq!((stat.sent, stat.received), (5, 5));
+        assert_eq!(stat.avg_ms, Some(setting.max_hop as f64));
+
+        setting.confirm_probes = 0;
+        let result = traceroute(&Path(vec![1]), &setting, &CancellationToken::new(), |_| {});
+        assert_eq!(result.destination, None);
+    }
+}
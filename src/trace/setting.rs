@@ -0,0 +1,138 @@
+use super::UdpHopProber;
+use std::net::IpAddr;
+use std::time::Duration;
+use ts_rs::TS;
+
+/// Default timeout for the first hop in milliseconds
+pub const DEFAULT_TIMEOUT_BASE_MS: u64 = 200;
+/// Default upper bound of the per-hop timeout in milliseconds
+pub const DEFAULT_TIMEOUT_MAX_MS: u64 = 3000;
+/// Default maximum number of hops
+pub const DEFAULT_MAX_HOP: u8 = 30;
+/// Default number of probes sent per hop
+pub const DEFAULT_TRIES_PER_HOP: u8 = 3;
+/// Multiplier applied to the RTT observed on the previous hop
+const RTT_TIMEOUT_FACTOR: u64 = 3;
+
+/// Whether probes of a trace keep one flow
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, TS)]
+pub enum FlowPolicy {
+    /// Every probe on the same flow (Paris traceroute), so load balancers
+    /// keep the path stable
+    #[default]
+    Fixed,
+    /// When a hop stays silent, retry on up to this many other flows to
+    /// get past per-flow drops. Needs a prober with flows, such as
+    /// [`crate::trace::UdpHopProber`].
+    RetryOnSilence { flows: u8 },
+}
+
+/// Settings for traceroute
+#[derive(Clone, Debug, PartialEq, Eq, TS)]
+pub struct TraceSetting {
+    /// Destination IP address
+    pub dst_ip: IpAddr,
+    /// Maximum number of hops
+    pub max_hop: u8,
+    /// Number of probes sent per hop
+    pub tries_per_hop: u8,
+    /// Timeout for the first hop. Grows with TTL.
+    #[ts(type = "number")]
+    pub timeout_base_ms: u64,
+    /// Upper bound of the per-hop timeout
+    #[ts(type = "number")]
+    pub timeout_max_ms: u64,
+    /// Source port of UDP probes. Picked by the OS when `None`.
+    pub source_port: Option<u16>,
+    /// Pause between the tries of one hop. Routers rate-limit time-exceeded
+    /// messages, so back-to-back tries can all go unanswered.
+    #[ts(type = "number")]
+    pub try_interval_ms: u64,
+    /// When every try at a hop went unanswered, wait this long and send one
+    /// more before declaring the hop silent. Disabled when `None`.
+    #[ts(type = "number | null")]
+    pub silent_retry_ms: Option<u64>,
+    /// Probes sent at the hop where the destination answered, topping up
+    /// `tries_per_hop`. Uses `tries_per_hop` when `None`.
+    pub final_hop_tries: Option<u8>,
+    /// Extra probes sent to the destination once reached, reported
+    /// separately as a destination RTT distribution
+    pub confirm_probes: u8,
+    pub flow_policy: FlowPolicy,
+}
+
+impl TraceSetting {
+    pub fn new(dst_ip: IpAddr) -> TraceSetting {
+        TraceSetting {
+            dst_ip,
+            max_hop: DEFAULT_MAX_HOP,
+            tries_per_hop: DEFAULT_TRIES_PER_HOP,
+            timeout_base_ms: DEFAULT_TIMEOUT_BASE_MS,
+            timeout_max_ms: DEFAULT_TIMEOUT_MAX_MS,
+            source_port: None,
+            try_interval_ms: 0,
+            silent_retry_ms: None,
+            final_hop_tries: None,
+            confirm_probes: 0,
+            flow_policy: FlowPolicy::Fixed,
+        }
+    }
+    /// UDP hop prober sending from `source_port`
+    pub fn udp_prober(&self) -> UdpHopProber {
+        UdpHopProber::new().with_source_port(self.source_port)
+    }
+    /// Effective timeout for the hop at `ttl`.
+    ///
+    /// Scales linearly with TTL from `timeout_base_ms`, or with the RTT observed
+    /// on the previous hop if that is larger, bounded by `timeout_max_ms`.
+    pub fn hop_timeout(&self, ttl: u8, last_rtt: Option<Duration>) -> Duration {
+        let base = self.timeout_base_ms;
+        let max = self.timeout_max_ms.max(base);
+        let by_ttl = base.saturating_mul(ttl.max(1) as u64);
+        let by_rtt = match last_rtt {
+            Some(rtt) => (rtt.as_millis() as u64).saturating_mul(RTT_TIMEOUT_FACTOR),
+            None => 0,
+        };
+        Duration::from_millis(by_ttl.max(by_rtt).min(max))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::Ipv4Addr;
+
+    #[test]
+    fn hop_timeout_grows_with_ttl() {
+        let setting = TraceSetting::new(IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1)));
+        let mut prev = Duration::ZERO;
+        for ttl in 1..=15 {
+            let timeout = setting.hop_timeout(ttl, None);
+            assert!(
+                timeout > prev,
+                "ttl {} timeout {:?} <= {:?}",
+                ttl,
+                timeout,
+                prev
+            );
+            prev = timeout;
+        }
+        assert_eq!(
+            setting.hop_timeout(1, None),
+            Duration::from_millis(DEFAULT_TIMEOUT_BASE_MS)
+        );
+        assert_eq!(
+            setting.hop_timeout(30, None),
+            Duration::from_millis(DEFAULT_TIMEOUT_MAX_MS)
+        );
+    }
+
+    #[test]
+    fn hop_timeout_follows_observed_rtt() {
+        let setting = TraceSetting::new(IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1)));
+        let timeout = setting.hop_timeout(2, Some(Duration::from_millis(300)));
+        assert_eq!(timeout, Duration::from_millis(900));
+        let timeout = setting.hop_timeout(2, Some(Duration::from_secs(5)));
+        assert_eq!(timeout, Duration::from_millis(DEFAULT_TIMEOUT_MAX_MS));
+    }
+}
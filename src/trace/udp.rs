@@ -0,0 +1,491 @@
+//! UDP traceroute probes, answered by the ICMP errors they draw
+use super::{HopProber, HopReply};
+use crate::ping::{ProbeError, UnreachableReason};
+use crate::socket::bind_udp_shared;
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, UdpSocket};
+use std::sync::OnceLock;
+use std::time::Duration;
+
+/// First of the ports classic traceroute sends to, which nothing listens on
+pub const DEFAULT_BASE_PORT: u16 = 33434;
+const PAYLOAD: &[u8] = b"netdia traceroute";
+
+const ICMP_DEST_UNREACHABLE: u8 = 3;
+const ICMP_TIME_EXCEEDED: u8 = 11;
+const ICMP_PORT_UNREACHABLE: u8 = 3;
+const ICMPV6_DEST_UNREACHABLE: u8 = 1;
+const ICMPV6_TIME_EXCEEDED: u8 = 3;
+const ICMPV6_PORT_UNREACHABLE: u8 = 4;
+
+/// ICMP error the kernel attached to a probe
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct IcmpError {
+    ty: u8,
+    code: u8,
+    /// Router or host that sent the error
+    offender: IpAddr,
+    /// TTL or hop limit the error arrived with
+    ttl: Option<u8>,
+}
+
+impl IcmpError {
+    /// Responder and whether it is the destination, or why the probe
+    /// cannot get through
+    fn classify(&self, ipv6: bool) -> Option<Result<(IpAddr, bool), ProbeError>> {
+        let (unreachable, exceeded, port) = if ipv6 {
+            (
+                ICMPV6_DEST_UNREACHABLE,
+                ICMPV6_TIME_EXCEEDED,
+                ICMPV6_PORT_UNREACHABLE,
+            )
+        } else {
+            (
+                ICMP_DEST_UNREACHABLE,
+                ICMP_TIME_EXCEEDED,
+                ICMP_PORT_UNREACHABLE,
+            )
+        };
+        match (self.ty, self.code) {
+            (ty, _) if ty == exceeded => Some(Ok((self.offender, false))),
+            // Nothing listens on the port, so the destination refuses it
+            (ty, code) if ty == unreachable && code == port => Some(Ok((self.offender, true))),
+            (ty, code) if ty == unreachable => {
+                let reason = if ipv6 {
+                    UnreachableReason::from_icmpv6(code)
+                } else {
+                    UnreachableReason::from_icmpv4(code)
+                };
+                Some(Err(ProbeError::Unreachable {
+                    responder: self.offender,
+                    reason,
+                }))
+            }
+            _ => None,
+        }
+    }
+}
+
+/// Traceroute over UDP without privileges. Each probe is a datagram with a
+/// limited TTL to `base_port + flow`; the time-exceeded or port-unreachable
+/// error it draws is read from the socket's error queue (`IP_RECVERR`),
+/// so this works on Linux only.
+///
+/// Every probe leaves from one source port per address family, so all
+/// probes of a flow share a 5-tuple and load balancers keep them on one
+/// path (Paris traceroute). Other flows differ in the destination port.
+#[derive(Debug)]
+pub struct UdpHopProber {
+    base_port: u16,
+    source_port: Option<u16>,
+    /// Sockets holding the source port picked for each family when none
+    /// was given
+    v4: OnceLock<Result<UdpSocket, (io::ErrorKind, String)>>,
+    v6: OnceLock<Result<UdpSocket, (io::ErrorKind, String)>>,
+}
+
+impl Default for UdpHopProber {
+    fn default() -> Self {
+        UdpHopProber::new()
+    }
+}
+
+impl UdpHopProber {
+    pub fn new() -> UdpHopProber {
+        UdpHopProber {
+            base_port: DEFAULT_BASE_PORT,
+            source_port: None,
+            v4: OnceLock::new(),
+            v6: OnceLock::new(),
+        }
+    }
+    /// Destination port of flow 0
+    pub fn with_base_port(mut self, port: u16) -> UdpHopProber {
+        self.base_port = port;
+        self
+    }
+    /// Source port of every probe. Picked by the OS once per address
+    /// family when `None`.
+    pub fn with_source_port(mut self, port: Option<u16>) -> UdpHopProber {
+        self.source_port = port;
+        self
+    }
+    pub fn source_port(&self) -> Option<u16> {
+        self.source_port
+    }
+    /// Port probes to `dst` leave from
+    fn local_port(&self, ipv6: bool) -> io::Result<u16> {
+        if let Some(port) = self.source_port {
+            return Ok(port);
+        }
+        let cell = if ipv6 { &self.v6 } else { &self.v4 };
+        cell.get_or_init(|| {
+            // Shared, so each probe's own socket can bind the port too
+            bind_udp_shared(SocketAddr::new(unspecified(ipv6), 0))
+                .map_err(|e| (e.kind(), e.to_string()))
+        })
+        .as_ref()
+        .map_err(|(kind, message)| io::Error::new(*kind, message.clone()))?
+        .local_addr()
+        .map(|addr| addr.port())
+    }
+    fn send(
+        &self,
+        dst: IpAddr,
+        ttl: u8,
+        flow: u16,
+        timeout: Duration,
+    ) -> Result<HopReply, ProbeError> {
+        let ipv6 = dst.is_ipv6();
+        let src = SocketAddr::new(unspecified(ipv6), self.local_port(ipv6)?);
+        let socket = bind_udp_shared(src)?;
+        sys::prepare(&socket, ipv6, ttl)?;
+        // Connected, so the kernel hands errors for this 5-tuple to this
+        // socket rather than to another one on the port
+        socket.connect(SocketAddr::new(dst, self.base_port.wrapping_add(flow)))?;
+        let start = std::time::Instant::now();
+        socket.send(PAYLOAD)?;
+        loop {
+            let remaining = timeout.saturating_sub(start.elapsed());
+            if remaining.is_zero() {
+                return Err(ProbeError::Timeout);
+            }
+            match sys::wait(&socket, ipv6, remaining)? {
+                sys::Event::Nothing => {}
+                // A service on the port answered
+                sys::Event::Datagram => {
+                    return Ok(HopReply {
+                        responder: dst,
+                        rtt: start.elapsed(),
+                        reached: true,
+                        reply_ttl: None,
+                    })
+                }
+                sys::Event::Error(error) => {
+                    let rtt = start.elapsed();
+                    match error.classify(ipv6) {
+                        Some(Ok((responder, reached))) => {
+                            return Ok(HopReply {
+                                responder,
+                                rtt,
+                                reached,
+                                reply_ttl: error.ttl,
+                            })
+                        }
+                        Some(Err(e)) => return Err(e),
+                        None => {}
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl HopProber for UdpHopProber {
+    fn probe_hop(&self, dst: IpAddr, ttl: u8, timeout: Duration) -> Result<HopReply, ProbeError> {
+        self.send(dst, ttl, 0, timeout)
+    }
+    fn supports_flows(&self) -> bool {
+        true
+    }
+    fn probe_hop_flow(
+        &self,
+        dst: IpAddr,
+        ttl: u8,
+        flow: u16,
+        timeout: Duration,
+    ) -> Result<HopReply, ProbeError> {
+        self.send(dst, ttl, flow, timeout)
+    }
+}
+
+fn unspecified(ipv6: bool) -> IpAddr {
+    if ipv6 {
+        IpAddr::V6(Ipv6Addr::UNSPECIFIED)
+    } else {
+        IpAddr::V4(Ipv4Addr::UNSPECIFIED)
+    }
+}
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+mod sys {
+    use super::IcmpError;
+    use std::io;
+    use std::net::UdpSocket;
+    use std::os::fd::{AsRawFd, RawFd};
+    use std::time::Duration;
+
+    pub enum Event {
+        Nothing,
+        Datagram,
+        Error(IcmpError),
+    }
+
+    fn set_int(fd: RawFd, level: i32, name: i32, value: i32) -> io::Result<()> {
+        let ret = unsafe {
+            libc::setsockopt(
+                fd,
+                level,
+                name,
+                &value as *const i32 as *const libc::c_void,
+                std::mem::size_of::<i32>() as libc::socklen_t,
+            )
+        };
+        if ret != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+
+    /// Limit the TTL and queue ICMP errors along with the TTL they came with
+    pub fn prepare(socket: &UdpSocket, ipv6: bool, ttl: u8) -> io::Result<()> {
+        let fd = socket.as_raw_fd();
+        if ipv6 {
+            set_int(fd, libc::IPPROTO_IPV6, libc::IPV6_UNICAST_HOPS, ttl as i32)?;
+            set_int(fd, libc::IPPROTO_IPV6, libc::IPV6_RECVERR, 1)?;
+            let _ = set_int(fd, libc::IPPROTO_IPV6, libc::IPV6_RECVHOPLIMIT, 1);
+        } else {
+            set_int(fd, libc::IPPROTO_IP, libc::IP_TTL, ttl as i32)?;
+            set_int(fd, libc::IPPROTO_IP, libc::IP_RECVERR, 1)?;
+            let _ = set_int(fd, libc::IPPROTO_IP, libc::IP_RECVTTL, 1);
+        }
+        Ok(())
+    }
+
+    /// Wait up to `timeout` for a datagram or a queued error
+    pub fn wait(socket: &UdpSocket, ipv6: bool, timeout: Duration) -> io::Result<Event> {
+        let fd = socket.as_raw_fd();
+        let mut pfd = libc::pollfd {
+            fd,
+            events: libc::POLLIN,
+            revents: 0,
+        };
+        let ms = timeout.as_millis().clamp(1, libc::c_int::MAX as u128) as libc::c_int;
+        match unsafe { libc::poll(&mut pfd, 1, ms) } {
+            n if n < 0 => {
+                let e = io::Error::last_os_error();
+                if e.kind() == io::ErrorKind::Interrupted {
+                    return Ok(Event::Nothing);
+                }
+                Err(e)
+            }
+            0 => Ok(Event::Nothing),
+            _ if pfd.revents & libc::POLLERR != 0 => {
+                Ok(read_error(fd, ipv6)?.map_or(Event::Nothing, Event::Error))
+            }
+            _ => {
+                let mut buf = [0u8; 512];
+                match socket.recv(&mut buf) {
+                    Ok(_) => Ok(Event::Datagram),
+                    // The error is read from the queue on the next wait
+                    Err(_) => Ok(Event::Nothing),
+                }
+            }
+        }
+    }
+
+    /// Dequeue one error and the ICMP message behind it, if it was one
+    fn read_error(fd: RawFd, ipv6: bool) -> io::Result<Option<IcmpError>> {
+        let mut buf = [0u8; 512];
+        let mut iov = libc::iovec {
+            iov_base: buf.as_mut_ptr() as *mut libc::c_void,
+            iov_len: buf.len(),
+        };
+        // Room for the extended error, the offender and the TTL, aligned
+        // for cmsghdr
+        let mut control = [0u64; 32];
+        let mut msg: libc::msghdr = unsafe { std::mem::zeroed() };
+        msg.msg_iov = &mut iov;
+        msg.msg_iovlen = 1;
+        msg.msg_control = control.as_mut_ptr() as *mut libc::c_void;
+        msg.msg_controllen = std::mem::size_of_val(&control) as _;
+        if unsafe { libc::recvmsg(fd, &mut msg, libc::MSG_ERRQUEUE) } < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let (mut error, mut ttl) = (None, None);
+        let mut cmsg = unsafe { libc::CMSG_FIRSTHDR(&msg) };
+        while !cmsg.is_null() {
+            let hdr = unsafe { &*cmsg };
+            let data = unsafe { libc::CMSG_DATA(cmsg) };
+            match (hdr.cmsg_level, hdr.cmsg_type) {
+                (libc::IPPROTO_IP, libc::IP_RECVERR) | (libc::IPPROTO_IPV6, libc::IPV6_RECVERR) => {
+                    let ee = data as *const libc::sock_extended_err;
+                    let err = unsafe { ee.read_unaligned() };
+                    let origin = if ipv6 {
+                        libc::SO_EE_ORIGIN_ICMP6
+                    } else {
+                        libc::SO_EE_ORIGIN_ICMP
+                    };
+                    if err.ee_origin == origin {
+                        let offender = unsafe { offender(ee) };
+                        error = offender.map(|offender| (err.ee_type, err.ee_code, offender));
+                    } else if err.ee_errno != 0 {
+                        return Err(io::Error::from_raw_os_error(err.ee_errno as i32));
+                    }
+                }
+                (libc::IPPROTO_IP, libc::IP_TTL) | (libc::IPPROTO_IPV6, libc::IPV6_HOPLIMIT) => {
+                    let value = unsafe { (data as *const libc::c_int).read_unaligned() };
+                    ttl = u8::try_from(value).ok();
+                }
+                _ => {}
+            }
+            cmsg = unsafe { libc::CMSG_NXTHDR(&msg, cmsg) };
+        }
+        Ok(error.map(|(ty, code, offender)| IcmpError {
+            ty,
+            code,
+            offender,
+            ttl,
+        }))
+    }
+
+    /// Sender of the ICMP error, stored by the kernel after `ee`
+    unsafe fn offender(ee: *const libc::sock_extended_err) -> Option<std::net::IpAddr> {
+        let addr = libc::SO_EE_OFFENDER(ee) as *const libc::sockaddr;
+        let len = match (*addr).sa_family as libc::c_int {
+            libc::AF_INET => std::mem::size_of::<libc::sockaddr_in>(),
+            libc::AF_INET6 => std::mem::size_of::<libc::sockaddr_in6>(),
+            _ => return None,
+        };
+        let mut storage: libc::sockaddr_storage = std::mem::zeroed();
+        std::ptr::copy_nonoverlapping(addr as *const u8, &mut storage as *mut _ as *mut u8, len);
+        crate::socket::from_sockaddr(&storage).map(|addr| addr.ip())
+    }
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "android")))]
+mod sys {
+    use super::IcmpError;
+    use std::io;
+    use std::net::UdpSocket;
+    use std::time::Duration;
+
+    pub enum Event {
+        Nothing,
+        Datagram,
+        Error(IcmpError),
+    }
+
+    /// The error queue is Linux only
+    pub fn prepare(_socket: &UdpSocket, _ipv6: bool, _ttl: u8) -> io::Result<()> {
+        Err(io::Error::from(io::ErrorKind::Unsupported))
+    }
+
+    pub fn wait(_socket: &UdpSocket, _ipv6: bool, _timeout: Duration) -> io::Result<Event> {
+        Err(io::Error::from(io::ErrorKind::Unsupported))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn errors_classified_by_type() {
+        let router: IpAddr = "192.0.2.1".parse().unwrap();
+        let error = |ty, code| IcmpError {
+            ty,
+            code,
+            offender: router,
+            ttl: Some(254),
+        };
+        let hop = |e: IcmpError| e.classify(false).unwrap().ok();
+        assert_eq!(hop(error(11, 0)), Some((router, false)));
+        assert_eq!(hop(error(3, 3)), Some((router, true)));
+        assert!(matches!(
+            error(3, 13).classify(false),
+            Some(Err(ProbeError::Unreachable {
+                reason: UnreachableReason::AdminProhibited,
+                ..
+            }))
+        ));
+        assert!(error(0, 0).classify(false).is_none());
+        assert_eq!(
+            error(3, 0).classify(true).unwrap().ok(),
+            Some((router, false))
+        );
+        assert_eq!(
+            error(1, 4).classify(true).unwrap().ok(),
+            Some((router, true))
+        );
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn closed_port_reports_the_destination_reached() {
+        let closed = UdpSocket::bind("127.0.0.1:0")
+            .and_then(|s| s.local_addr())
+            .unwrap()
+            .port();
+        let prober = UdpHopProber::new().with_base_port(closed);
+        let dst = IpAddr::V4(Ipv4Addr::LOCALHOST);
+        let reply = prober.probe_hop(dst, 1, Duration::from_secs(1)).unwrap();
+        assert_eq!(reply.responder, dst);
+        assert!(reply.reached);
+        assert!(reply.rtt < Duration::from_secs(1));
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn flows_vary_the_port_from_one_source() {
+        let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let port = server.local_addr().unwrap().port();
+        let handle = std::thread::spawn(move || {
+            let mut peers = Vec::new();
+            let mut buf = [0u8; 64];
+            for _ in 0..2 {
+                let (n, peer) = server.recv_from(&mut buf).unwrap();
+                server.send_to(&buf[..n], peer).unwrap();
+                peers.push(peer.port());
+            }
+            peers
+        });
+        // Flow 1 goes to the listening port, one above the base
+        let prober = UdpHopProber::new().with_base_port(port - 1);
+        assert!(prober.supports_flows());
+        let dst = IpAddr::V4(Ipv4Addr::LOCALHOST);
+        for _ in 0..2 {
+            let reply = prober
+                .probe_hop_flow(dst, 64, 1, Duration::from_secs(1))
+                .unwrap();
+            assert!(reply.reached);
+        }
+        let peers = handle.join().unwrap();
+        // Every probe of the flow leaves from the same source port
+        assert_eq!(peers[0], peers[1]);
+        assert_eq!(peers[0], prober.local_port(false).unwrap());
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn probes_leave_from_the_configured_source_port() {
+        let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let source_port = UdpSocket::bind("0.0.0.0:0")
+            .and_then(|s| s.local_addr())
+            .unwrap()
+            .port();
+        let dst = IpAddr::V4(Ipv4Addr::LOCALHOST);
+        let mut setting = crate::trace::TraceSetting::new(dst);
+        setting.source_port = Some(source_port);
+        let prober = setting
+            .udp_prober()
+            .with_base_port(server.local_addr().unwrap().port());
+        let handle = std::thread::spawn(move || {
+            let mut buf = [0u8; 64];
+            let (n, peer) = server.recv_from(&mut buf).unwrap();
+            server.send_to(&buf[..n], peer).unwrap();
+            peer.port()
+        });
+        let reply = prober.probe_hop(dst, 64, Duration::from_secs(1)).unwrap();
+        assert!(reply.reached);
+        assert_eq!(handle.join().unwrap(), source_port);
+
+        // A port held by another socket fails with a clear error
+        let held = UdpSocket::bind("0.0.0.0:0").unwrap();
+        let prober = UdpHopProber::new().with_source_port(Some(held.local_addr().unwrap().port()));
+        match prober.probe_hop(dst, 1, Duration::from_millis(100)) {
+            Err(ProbeError::Io(e)) => assert!(e.to_string().contains("already in use"), "{}", e),
+            other => panic!("expected bind error, got {:?}", other),
+        }
+    }
+}
@@ -0,0 +1,305 @@
+//! Download progress for updates
+use std::collections::VecDeque;
+use std::io::{self, Read, Write};
+use std::time::{Duration, Instant};
+use ts_rs::TS;
+
+/// Width of the window used for the speed estimate
+pub const DEFAULT_SPEED_WINDOW: Duration = Duration::from_secs(5);
+/// Progress is re-emitted at least this often, even without new chunks
+pub const DEFAULT_HEARTBEAT: Duration = Duration::from_secs(1);
+/// A download that receives nothing for this long is abandoned
+pub const DEFAULT_STALL_TIMEOUT: Duration = Duration::from_secs(30);
+
+#[derive(Clone, Debug, PartialEq, TS)]
+pub enum DownloadEvent {
+    Started {
+        #[ts(type = "number | null")]
+        content_length: Option<u64>,
+    },
+    Progress {
+        #[ts(type = "number")]
+        downloaded: u64,
+        #[ts(type = "number | null")]
+        content_length: Option<u64>,
+        /// Average speed over the rolling window
+        bytes_per_sec: f64,
+        /// Estimated time remaining. `None` if the length or speed is unknown.
+        eta_secs: Option<f64>,
+    },
+    Finished,
+}
+
+/// Tracks received chunks and derives speed and ETA from a rolling window
+#[derive(Debug)]
+pub struct DownloadMeter {
+    content_length: Option<u64>,
+    downloaded: u64,
+    window: Duration,
+    heartbeat: Duration,
+    chunks: VecDeque<(Instant, u64)>,
+    started_at: Instant,
+    last_emit: Option<Instant>,
+}
+
+impl DownloadMeter {
+    pub fn new(content_length: Option<u64>, started_at: Instant) -> DownloadMeter {
+        DownloadMeter {
+            content_length,
+            downloaded: 0,
+            window: DEFAULT_SPEED_WINDOW,
+            heartbeat: DEFAULT_HEARTBEAT,
+            chunks: VecDeque::new(),
+            started_at,
+            last_emit: None,
+        }
+    }
+    pub fn with_window(mut self, window: Duration) -> DownloadMeter {
+        self.window = window;
+        self
+    }
+    pub fn with_heartbeat(mut self, heartbeat: Duration) -> DownloadMeter {
+        self.heartbeat = heartbeat;
+        self
+    }
+    /// Record a chunk of `len` bytes received at `at` and return the progress event
+    pub fn on_chunk(&mut self, len: u64, at: Instant) -> DownloadEvent {
+        self.downloaded += len;
+        self.chunks.push_back((at, len));
+        self.progress(at)
+    }
+    /// Progress event if nothing was emitted for a heartbeat interval
+    pub fn heartbeat(&mut self, now: Instant) -> Option<DownloadEvent> {
+        let last = self.last_emit.unwrap_or(self.started_at);
+        if now.saturating_duration_since(last) >= self.heartbeat {
+            Some(self.progress(now))
+        } else {
+            None
+        }
+    }
+    /// Average speed over the window ending at `now`
+    pub fn bytes_per_sec(&mut self, now: Instant) -> f64 {
+        while let Some((at, _)) = self.chunks.front() {
+            if now.saturating_duration_since(*at) > self.window {
+                self.chunks.pop_front();
+            } else {
+                break;
+            }
+        }
+        let window_start = self
+            .started_at
+            .max(now.checked_sub(self.window).unwrap_or(now));
+        let span = now.saturating_duration_since(window_start).as_secs_f64();
+        if span <= 0.0 {
+            return 0.0;
+        }
+        let bytes: u64 = self.chunks.iter().map(|(_, len)| len).sum();
+        bytes as f64 / span
+    }
+    fn progress(&mut self, now: Instant) -> DownloadEvent {
+        self.last_emit = Some(now);
+        let bytes_per_sec = self.bytes_per_sec(now);
+        let eta_secs = match self.content_length {
+            Some(total) if bytes_per_sec > 0.0 => {
+                Some(total.saturating_sub(self.downloaded) as f64 / bytes_per_sec)
+            }
+            _ => None,
+        };
+        DownloadEvent::Progress {
+            downloaded: self.downloaded,
+            content_length: self.content_length,
+            bytes_per_sec,
+            eta_secs,
+        }
+    }
+}
+
+/// Copy `reader` into `writer`, reporting progress to `on_event`.
+///
+/// Give `reader` a read timeout (e.g. a socket's) shorter than
+/// [`DEFAULT_HEARTBEAT`]: each timed-out read re-emits progress so a stalled
+/// download still shows its falling speed, and the copy fails with
+/// `TimedOut` once nothing arrived for [`DEFAULT_STALL_TIMEOUT`].
+pub fn copy_with_progress<R, W, F>(
+    reader: &mut R,
+    writer: &mut W,
+    content_length: Option<u64>,
+    on_event: F,
+) -> io::Result<u64>
+where
+    R: Read,
+    W: Write,
+    F: FnMut(DownloadEvent),
+{
+    copy_with_clock(reader, writer, content_length, Instant::now, on_event)
+}
+
+fn copy_with_clock<R, W, C, F>(
+    reader: &mut R,
+    writer: &mut W,
+    content_length: Option<u64>,
+    mut now: C,
+    mut on_event: F,
+) -> io::Result<u64>
+where
+    R: Read,
+    W: Write,
+    C: FnMut() -> Instant,
+    F: FnMut(DownloadEvent),
+{
+    let mut last_data = now();
+    let mut meter = DownloadMeter::new(content_length, last_data);
+    on_event(DownloadEvent::Started { content_length });
+    let mut buf = [0u8; 16 * 1024];
+    loop {
+        let n = match reader.read(&mut buf) {
+            Ok(0) => break,
+            Ok(n) => n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e)
+                if matches!(
+                    e.kind(),
+                    io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+                ) =>
+            {
+                let at = now();
+                if at.saturating_duration_since(last_data) >= DEFAULT_STALL_TIMEOUT {
+                    return Err(io::Error::new(io::ErrorKind::TimedOut, "Download stalled"));
+                }
+                if let Some(event) = meter.heartbeat(at) {
+                    on_event(event);
+                }
+                continue;
+            }
+            Err(e) => return Err(e),
+        };
+        writer.write_all(&buf[..n])?;
+        last_data = now();
+        on_event(meter.on_chunk(n as u64, last_data));
+    }
+    writer.flush()?;
+    on_event(DownloadEvent::Finished);
+    Ok(meter.downloaded)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn speed_and_eta(event: &DownloadEvent) -> (f64, Option<f64>) {
+        match event {
+            DownloadEvent::Progress {
+                bytes_per_sec,
+                eta_secs,
+                ..
+            } => (*bytes_per_sec, *eta_secs),
+            _ => panic!("expected progress"),
+        }
+    }
+
+    #[test]
+    fn speed_and_eta_from_chunk_timeline() {
+        let t0 = Instant::now();
+        let s = |secs| t0 + Duration::from_secs(secs);
+        let mut meter = DownloadMeter::new(Some(10_000), t0).with_window(Duration::from_secs(4));
+
+        meter.on_chunk(1000, s(1));
+        let (speed, eta) = speed_and_eta(&meter.on_chunk(1000, s(2)));
+        assert_eq!(speed, 1000.0);
+        assert_eq!(eta, Some(8.0));
+
+        // The first two chunks fall out of the 4s window
+        let (speed, eta) = speed_and_eta(&meter.on_chunk(4000, s(7)));
+        assert_eq!(speed, 1000.0);
+        assert_eq!(eta, Some(4.0));
+    }
+
+    #[test]
+    fn heartbeat_when_chunks_are_sparse() {
+        let t0 = Instant::now();
+        let mut meter = DownloadMeter::new(None, t0).with_heartbeat(Duration::from_secs(1));
+        meter.on_chunk(500, t0 + Duration::from_millis(100));
+        assert!(meter.heartbeat(t0 + Duration::from_millis(600)).is_none());
+        let event = meter.heartbeat(t0 + Duration::from_millis(1200)).unwrap();
+        assert_eq!(speed_and_eta(&event).1, None);
+        assert!(meter.heartbeat(t0 + Duration::from_millis(1500)).is_none());
+    }
+
+    #[test]
+    fn copy_reports_start_progress_and_finish() {
+        let data = vec![7u8; 40_000];
+        let mut out = Vec::new();
+        let mut events = Vec::new();
+        let copied = copy_with_progress(&mut data.as_slice(), &mut out, Some(40_000), |e| {
+            events.push(e)
+        })
+        .unwrap();
+        assert_eq!(copied, 40_000);
+        assert_eq!(out, data);
+        assert_eq!(
+            events.first(),
+            Some(&DownloadEvent::Started {
+                content_length: Some(40_000)
+            })
+        );
+        assert_eq!(events.last(), Some(&DownloadEvent::Finished));
+        assert!(events.len() >= 3);
+    }
+
+    /// Hands out scripted reads: `Some(n)` bytes or `None` for a timeout
+    struct Scripted(Vec<Option<usize>>);
+
+    impl Read for Scripted {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            if self.0.is_empty() {
+                return Ok(0);
+            }
+            match self.0.remove(0) {
+                Some(n) => Ok(n.min(buf.len())),
+                None => Err(io::ErrorKind::TimedOut.into()),
+            }
+        }
+    }
+
+    #[test]
+    fn timed_out_reads_emit_heartbeats_until_stalled() {
+        let t0 = Instant::now();
+        // Every clock reading is 400ms after the previous one
+        let clock = |step: &mut u32| {
+            *step += 1;
+            t0 + Duration::from_millis(400) * *step
+        };
+        let mut step = 0;
+        let mut events = Vec::new();
+        let mut reader = Scripted(vec![Some(100), None, None, None, Some(100)]);
+        let copied = copy_with_clock(
+            &mut reader,
+            &mut Vec::new(),
+            None,
+            || clock(&mut step),
+            |e| events.push(e),
+        )
+        .unwrap();
+        assert_eq!(copied, 200);
+        // Started, first chunk, one heartbeat 1.2s after it, second chunk
+        let speeds: Vec<f64> = events[1..events.len() - 1]
+            .iter()
+            .map(|e| speed_and_eta(e).0)
+            .collect();
+        assert_eq!(speeds.len(), 3);
+        assert!(speeds[1] < speeds[0]);
+
+        let mut step = 0;
+        let mut reader = Scripted(vec![None; 100]);
+        let err = copy_with_clock(
+            &mut reader,
+            &mut Vec::new(),
+            None,
+            || clock(&mut step),
+            |_| {},
+        )
+        .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+        assert!(reader.0.len() > 20);
+    }
+}